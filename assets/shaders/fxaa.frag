@@ -0,0 +1,52 @@
+#version 450
+
+layout(location = 0) in vec2 i_uv;
+
+layout(location = 0) out vec4 o_color;
+
+layout(set = 0, binding = 0) uniform sampler u_sampler;
+layout(set = 0, binding = 1) uniform texture2D u_scene;
+
+const float SPAN_MAX = 8.0;
+const float REDUCE_MUL = 1.0 / 8.0;
+const float REDUCE_MIN = 1.0 / 128.0;
+
+float luma(vec3 color) {
+    return dot(color, vec3(0.299, 0.587, 0.114));
+}
+
+vec3 sample_scene(vec2 uv) {
+    return texture(sampler2D(u_scene, u_sampler), uv).rgb;
+}
+
+void main() {
+    vec2 texel = 1.0 / vec2(textureSize(sampler2D(u_scene, u_sampler), 0));
+
+    /* EDGE DETECTION */
+    vec3 color_m = sample_scene(i_uv);
+    float luma_nw = luma(sample_scene(i_uv + vec2(-1.0, -1.0) * texel));
+    float luma_ne = luma(sample_scene(i_uv + vec2(1.0, -1.0) * texel));
+    float luma_sw = luma(sample_scene(i_uv + vec2(-1.0, 1.0) * texel));
+    float luma_se = luma(sample_scene(i_uv + vec2(1.0, 1.0) * texel));
+    float luma_m = luma(color_m);
+    float luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    float luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    /* BLUR ALONG THE EDGE */
+    vec2 dir = vec2(-((luma_nw + luma_ne) - (luma_sw + luma_se)), (luma_nw + luma_sw) - (luma_ne + luma_se));
+    float dir_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * REDUCE_MUL, REDUCE_MIN);
+    float inverse_dir_min = 1.0 / (min(abs(dir.x), abs(dir.y)) + dir_reduce);
+    dir = clamp(dir * inverse_dir_min, vec2(-SPAN_MAX), vec2(SPAN_MAX)) * texel;
+
+    vec3 color_a = 0.5 * (sample_scene(i_uv + dir * (1.0 / 3.0 - 0.5)) + sample_scene(i_uv + dir * (2.0 / 3.0 - 0.5)));
+    vec3 color_b = 0.5 * color_a + 0.25 * (sample_scene(i_uv - dir * 0.5) + sample_scene(i_uv + dir * 0.5));
+    float luma_b = luma(color_b);
+
+    /* OUTPUT */
+    // the wider blur is only used if it doesn't go past the contrast of the neighbourhood
+    if (luma_b < luma_min || luma_b > luma_max) {
+        o_color = vec4(color_a, 1.0);
+    } else {
+        o_color = vec4(color_b, 1.0);
+    }
+}
@@ -6,13 +6,20 @@ layout(location = 2) in vec3 v_Rgb;
 
 layout(location = 0) out vec4 ColorBuffer;
 
+layout(set = 0, binding = 1) uniform Temp2 {
+    mat4 u_Model;
+    // light level of the block containing the model, same light curve as world.frag
+    float u_light_level;
+};
+
 const vec3 SUN_DIRECTION = normalize(vec3(0, 1, 0.5));
 const float SUN_FRACTION = 0.1;
 
 void main() {
     /* VARIOUS BRIGHTNESS FACTORS */
     float normal_factor = 1.0 - SUN_FRACTION + SUN_FRACTION * dot(v_Norm, SUN_DIRECTION);
-    float total_factor = occl * normal_factor;
+    float light_factor = pow(0.8, 15.0 - u_light_level);
+    float total_factor = light_factor * occl * normal_factor;
 
     /* OUTPUT */
     ColorBuffer = vec4(v_Rgb * total_factor, 1.0);
@@ -0,0 +1,15 @@
+#version 450
+
+// Only run the shader for the fragments that pass the depth test
+layout(early_fragment_tests) in;
+
+layout(location = 0) flat in uint v_Index;
+
+layout(set = 0, binding = 1) buffer Visibility { uint visible[]; };
+
+layout(location = 0) out vec4 ColorBuffer;
+
+void main() {
+    visible[v_Index] = 1;
+    ColorBuffer = vec4(0.0);
+}
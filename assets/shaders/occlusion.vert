@@ -0,0 +1,24 @@
+#version 450
+
+layout(location = 0) in vec3 a_Min;
+layout(location = 1) in float a_Size;
+layout(location = 2) in uint a_Index;
+
+layout(set = 0, binding = 0) uniform Temp1 { mat4 u_ViewProj; };
+
+layout(location = 0) flat out uint v_Index;
+
+// The 12 triangles of the unit cube
+const vec3 CUBE[36] = vec3[36](
+    vec3(0, 0, 0), vec3(1, 0, 0), vec3(1, 1, 0), vec3(0, 0, 0), vec3(1, 1, 0), vec3(0, 1, 0),
+    vec3(0, 0, 1), vec3(1, 1, 1), vec3(1, 0, 1), vec3(0, 0, 1), vec3(0, 1, 1), vec3(1, 1, 1),
+    vec3(0, 0, 0), vec3(0, 1, 0), vec3(0, 1, 1), vec3(0, 0, 0), vec3(0, 1, 1), vec3(0, 0, 1),
+    vec3(1, 0, 0), vec3(1, 1, 1), vec3(1, 1, 0), vec3(1, 0, 0), vec3(1, 0, 1), vec3(1, 1, 1),
+    vec3(0, 0, 0), vec3(1, 0, 1), vec3(1, 0, 0), vec3(0, 0, 0), vec3(0, 0, 1), vec3(1, 0, 1),
+    vec3(0, 1, 0), vec3(1, 1, 0), vec3(1, 1, 1), vec3(0, 1, 0), vec3(1, 1, 1), vec3(0, 1, 1)
+);
+
+void main() {
+    v_Index = a_Index;
+    gl_Position = u_ViewProj * vec4(a_Min + CUBE[gl_VertexIndex] * a_Size, 1.0);
+}
@@ -0,0 +1,10 @@
+#version 450
+
+layout(location = 0) out vec2 o_uv;
+
+void main() {
+    // a single triangle covering the whole screen
+    vec2 pos = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    o_uv = vec2(pos.x, 1.0 - pos.y);
+    gl_Position = vec4(pos * 2.0 - 1.0, 0.0, 1.0);
+}
@@ -0,0 +1,12 @@
+#version 450
+
+// only the position of the chunk vertices is used
+layout(location = 0) in vec3 i_position;
+
+layout(set = 0, binding = 0) uniform Cascade {
+    mat4 u_view_proj;
+};
+
+void main() {
+    gl_Position = u_view_proj * vec4(i_position, 1.0);
+}
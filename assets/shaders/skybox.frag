@@ -4,6 +4,12 @@
 layout(location = 0) in vec3 pos;
 layout(location = 0) out vec4 ColorBuffer;
 
+layout(set = 0, binding = 2) uniform Sky {
+    vec4 u_SunDirection;
+    // rgb: color of the sky, a: 1 if the dimension has a sun, 0 otherwise
+    vec4 u_SkyColor;
+};
+
 
 float dist_sphere(vec3 v1, vec3 v2){
     float cos_angle = dot(v1, v2);
@@ -16,7 +22,7 @@ vec3 getSky(vec3 pos, vec3 sun_pos)
 {
     float y_lim = clamp(pos.y, 0.0, 1.0) - 5*clamp(pos.y, -0.2, 0.0);
     float atmosphere = pow(1.0-y_lim, 1.4);
-    vec3 skyColor = vec3(0.2,0.4,0.8);
+    vec3 skyColor = u_SkyColor.rgb;
 
     float scatter = pow(1.0 - dist_sphere(pos, sun_pos)/(3.1415926535), 1.0 / 30.0);
     scatter = 1.0 - clamp(scatter,0.8,1.0);
@@ -56,11 +62,19 @@ vec3 getSun(vec3 pos, vec3 sun_pos){
 
 void main() {
     vec3 pos_norm = normalize(pos);
-    vec3 sun_pos = normalize(vec3(1.0, 0.75, 1.0));
+    vec3 sun_pos = normalize(u_SunDirection.xyz);
+    if (u_SkyColor.a < 0.5) {
+        // No sun: a plain sky that doesn't follow the time of day
+        ColorBuffer = vec4(u_SkyColor.rgb, 1.0);
+        return;
+    }
     vec3 sky = getSky(pos_norm, sun_pos);
     vec3 sun = getSun(pos_norm, sun_pos);
 
-    ColorBuffer = vec4(sky + sun,1.0);
+    // Darken the sky when the sun is below the horizon
+    float daylight = clamp(sun_pos.y * 4.0 + 0.5, 0.05, 1.0);
+
+    ColorBuffer = vec4(sky * daylight + sun,1.0);
 
 
 }
\ No newline at end of file
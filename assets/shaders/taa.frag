@@ -0,0 +1,56 @@
+#version 450
+
+layout(location = 0) in vec2 i_uv;
+
+layout(location = 0) out vec4 o_color;
+layout(location = 1) out vec4 o_history;
+
+layout(set = 0, binding = 0) uniform sampler u_sampler;
+layout(set = 0, binding = 1) uniform texture2D u_scene;
+layout(set = 0, binding = 2) uniform texture2D u_depth;
+layout(set = 0, binding = 3) uniform texture2D u_history;
+layout(set = 0, binding = 4) uniform Reprojection {
+    // from the clip space of this frame to the clip space of the previous frame
+    mat4 u_reprojection;
+    // x: 1 if the history is valid, y: weight of the history
+    vec4 u_params;
+};
+
+void main() {
+    ivec2 size = textureSize(sampler2D(u_scene, u_sampler), 0);
+    ivec2 pixel = ivec2(gl_FragCoord.xy);
+    vec3 current = texelFetch(sampler2D(u_scene, u_sampler), pixel, 0).rgb;
+
+    /* NEIGHBOURHOOD OF THE PIXEL */
+    // the history is clamped to the colors around the pixel to limit ghosting
+    vec3 neighbourhood_min = current;
+    vec3 neighbourhood_max = current;
+    for (int dx = -1; dx <= 1; ++dx) {
+        for (int dy = -1; dy <= 1; ++dy) {
+            ivec2 neighbour = clamp(pixel + ivec2(dx, dy), ivec2(0), size - 1);
+            vec3 color = texelFetch(sampler2D(u_scene, u_sampler), neighbour, 0).rgb;
+            neighbourhood_min = min(neighbourhood_min, color);
+            neighbourhood_max = max(neighbourhood_max, color);
+        }
+    }
+
+    /* REPROJECTION */
+    float depth = texelFetch(sampler2D(u_depth, u_sampler), pixel, 0).r;
+    vec2 ndc = vec2(i_uv.x * 2.0 - 1.0, 1.0 - i_uv.y * 2.0);
+    vec4 previous_clip = u_reprojection * vec4(ndc, depth, 1.0);
+    vec2 previous_ndc = previous_clip.xy / previous_clip.w;
+    vec2 previous_uv = vec2(previous_ndc.x * 0.5 + 0.5, 0.5 - previous_ndc.y * 0.5);
+
+    /* ACCUMULATION */
+    vec3 result = current;
+    bool on_screen = all(greaterThanEqual(previous_uv, vec2(0.0))) && all(lessThanEqual(previous_uv, vec2(1.0)));
+    if (u_params.x > 0.5 && on_screen) {
+        vec3 history = texture(sampler2D(u_history, u_sampler), previous_uv).rgb;
+        history = clamp(history, neighbourhood_min, neighbourhood_max);
+        result = mix(current, history, u_params.y);
+    }
+
+    /* OUTPUT */
+    o_color = vec4(result, 1.0);
+    o_history = vec4(result, 1.0);
+}
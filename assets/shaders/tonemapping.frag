@@ -0,0 +1,37 @@
+#version 450
+
+layout(location = 0) in vec2 i_uv;
+
+layout(location = 0) out vec4 o_color;
+
+layout(set = 0, binding = 0) uniform sampler u_sampler;
+layout(set = 0, binding = 1) uniform texture2D u_scene;
+layout(set = 0, binding = 2) uniform texture3D u_lut;
+layout(set = 0, binding = 3) uniform ColorGrading {
+    float u_exposure;
+    float u_contrast;
+    float u_lut_size;
+    float u_padding;
+    vec3 u_tint;
+};
+
+// the colors below this value are not changed by the tonemapping
+const float SHOULDER_START = 0.8;
+const vec3 MIDDLE_GRAY = vec3(0.5);
+
+// compress the colors above SHOULDER_START smoothly into [SHOULDER_START, 1)
+vec3 tonemap(vec3 color) {
+    vec3 shoulder = SHOULDER_START + (1.0 - SHOULDER_START) * (1.0 - exp(-(color - SHOULDER_START) / (1.0 - SHOULDER_START)));
+    return mix(color, shoulder, step(SHOULDER_START, color));
+}
+
+void main() {
+    vec3 color = texture(sampler2D(u_scene, u_sampler), i_uv).rgb * u_exposure;
+    color = tonemap(max(color, vec3(0.0)));
+    color = clamp((color - MIDDLE_GRAY) * u_contrast + MIDDLE_GRAY, 0.0, 1.0);
+    color *= u_tint;
+    // sample the centers of the first and last entries of the lookup table for 0 and 1
+    vec3 lut_coords = color * (u_lut_size - 1.0) / u_lut_size + 0.5 / u_lut_size;
+    color = texture(sampler3D(u_lut, u_sampler), lut_coords).rgb;
+    o_color = vec4(color, 1.0);
+}
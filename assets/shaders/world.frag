@@ -7,16 +7,134 @@ layout(location = 3) flat in vec2 i_texture_size;
 layout(location = 4) flat in vec2 i_texture_max_uv;
 layout(location = 5) in vec2 i_texture_uv;
 layout(location = 6) flat in float i_light_level;
+layout(location = 7) in vec3 i_world_position;
+layout(location = 8) flat in vec3 i_tint;
 
 layout(location = 0) out vec4 o_color;
 
+// must match texture_streaming.rs
+const int ATLAS_TILES = 8;
+const int CACHE_TILES = 4;
+const float ATLAS_SIZE = 2048.0;
+const float RESIDENT_LEVEL = 2.0;
+
 layout(set = 0, binding = 1) uniform sampler u_sampler;
+// the low resolution mipmaps of the atlas, always on the GPU
 layout(set = 0, binding = 2) uniform texture2D u_texture_atlas;
+// the high resolution mipmaps of the streamed tiles of the atlas
+layout(set = 0, binding = 4) uniform texture2D u_tile_cache;
+// for every tile of the atlas, 0 if it's not in the cache or 1 + its slot in the cache
+layout(set = 0, binding = 5) uniform PageTable {
+    uvec4 u_page_table[ATLAS_TILES * ATLAS_TILES / 4];
+};
+
+// must match dynamic_lights.rs
+const int MAX_DYNAMIC_LIGHTS = 8;
+
+struct PointLight {
+    // xyz: position, w: radius
+    vec4 position_radius;
+    // rgb: color, a: flicker
+    vec4 color_flicker;
+};
+layout(set = 0, binding = 6) uniform DynamicLights {
+    // x: number of lights, y: time
+    vec4 u_dynamic_lights_header;
+    PointLight u_dynamic_lights[MAX_DYNAMIC_LIGHTS];
+};
+
+layout(set = 0, binding = 7) uniform Weather {
+    // how wet the surfaces exposed to the rain are, between 0 and 1
+    float u_wetness;
+    // minimum light level of the dimension
+    float u_ambient_light;
+    // distances where the distance fog starts and where it hides everything, the end is 0 if it's disabled
+    float u_distance_fog_start;
+    float u_distance_fog_end;
+    vec4 u_camera_position;
+    // rgb: color of the fog, a: density of the fog
+    vec4 u_fog;
+    // rgb: color of the horizon, that the distance fog blends to
+    vec4 u_horizon_color;
+};
+
+// must match shadows.rs
+const int MAX_SHADOW_CASCADES = 3;
+
+layout(set = 0, binding = 9) uniform texture2DArray u_shadow_maps;
+layout(set = 0, binding = 10) uniform samplerShadow u_shadow_sampler;
+layout(set = 0, binding = 11) uniform Shadows {
+    mat4 u_shadow_cascades[MAX_SHADOW_CASCADES];
+    // xyz: direction of the sun, w: number of cascades, 0 if there are no shadows
+    vec4 u_sun;
+    // x: size of a texel of the shadow maps in uv units
+    vec4 u_shadow_params;
+};
+
+// how much darker the wet surfaces are
+const float WETNESS_DARKENING = 0.3;
+// how much darker the surfaces in the shadow of the sun are
+const float SHADOW_DARKENING = 0.35;
+// offset of the shadow lookups along the normal, in blocks, to avoid shadow acne
+const float SHADOW_NORMAL_OFFSET = 0.05;
 
 const vec3 SUN_DIRECTION = normalize(vec3(0, 1, 0.5));
 const float SUN_FRACTION = 0.1;
 const vec2 EPSILON = vec2(1e-7, 1e-7);
 
+// a few unrelated sines, offset for every light so that they don't flicker together
+float flicker_noise(float time, float seed) {
+    return 0.5 * sin(time * 7.3 + seed * 1.7) + 0.3 * sin(time * 13.1 + seed * 4.1) + 0.2 * sin(time * 23.7 + seed * 2.3);
+}
+
+vec3 dynamic_light() {
+    vec3 total = vec3(0.0);
+    int count = int(u_dynamic_lights_header.x);
+    float time = u_dynamic_lights_header.y;
+    for (int i = 0; i < count; ++i) {
+        PointLight light = u_dynamic_lights[i];
+        vec3 to_light = light.position_radius.xyz - i_world_position;
+        float dist = length(to_light);
+        float attenuation = clamp(1.0 - dist / light.position_radius.w, 0.0, 1.0);
+        attenuation *= attenuation;
+        // light the faces that are orthogonal to the light a bit, like the voxel light does
+        float facing = 0.25 + 0.75 * max(dot(i_norm, to_light / max(dist, 1e-4)), 0.0);
+        float flicker = 1.0 - light.color_flicker.a * (0.5 + 0.5 * flicker_noise(time, float(i)));
+        total += light.color_flicker.rgb * attenuation * facing * flicker;
+    }
+    return total;
+}
+
+// 1 if the fragment is lit by the sun, 0 if it's in the shadow
+float sun_visibility() {
+    int cascades = int(u_sun.w);
+    if (cascades == 0) {
+        return 1.0;
+    }
+    // the faces that look away from the sun are in their own shadow
+    if (dot(i_norm, u_sun.xyz) <= 0.0) {
+        return 0.0;
+    }
+    vec4 position = vec4(i_world_position + i_norm * SHADOW_NORMAL_OFFSET, 1.0);
+    // use the first, sharpest cascade that contains the fragment
+    for (int i = 0; i < cascades; ++i) {
+        vec3 coords = (u_shadow_cascades[i] * position).xyz;
+        vec2 uv = coords.xy * vec2(0.5, -0.5) + 0.5;
+        if (all(greaterThan(uv, vec2(0.0))) && all(lessThan(uv, vec2(1.0))) && coords.z < 1.0) {
+            // average 4 filtered lookups to soften the edges
+            float lit = 0.0;
+            for (int dx = 0; dx < 2; ++dx) {
+                for (int dy = 0; dy < 2; ++dy) {
+                    vec2 offset = (vec2(dx, dy) - 0.5) * u_shadow_params.x;
+                    lit += texture(sampler2DArrayShadow(u_shadow_maps, u_shadow_sampler), vec4(uv + offset, float(i), coords.z));
+                }
+            }
+            return lit / 4.0;
+        }
+    }
+    return 1.0;
+}
+
 void main() {
     /* TEXTURE ACCESS */
     // avoid going out of bounds when multisampling is enabled
@@ -26,14 +144,41 @@ void main() {
     vec2 y_derivative = dFdy(corrected_uv);
     // wrap texture
     vec2 actual_uv = i_texture_top_left + mod(corrected_uv, i_texture_size);
-    // get texture value
-    vec4 tex_color = textureGrad(sampler2D(u_texture_atlas, u_sampler), actual_uv, x_derivative, y_derivative);
+    // get texture value, from the tile cache if the high resolution mipmaps are needed and available
+    float lod = log2(max(length(x_derivative), length(y_derivative)) * ATLAS_SIZE);
+    ivec2 tile = clamp(ivec2(actual_uv * float(ATLAS_TILES)), ivec2(0), ivec2(ATLAS_TILES - 1));
+    int tile_index = tile.y * ATLAS_TILES + tile.x;
+    uint slot = u_page_table[tile_index / 4][tile_index % 4];
+    vec4 tex_color;
+    if (lod < RESIDENT_LEVEL && slot != 0u) {
+        vec2 slot_pos = vec2((slot - 1u) % uint(CACHE_TILES), (slot - 1u) / uint(CACHE_TILES));
+        vec2 uv_in_tile = clamp(actual_uv * float(ATLAS_TILES) - vec2(tile), vec2(0.0), vec2(1.0 - 1e-6));
+        vec2 cache_uv = (slot_pos + uv_in_tile) / float(CACHE_TILES);
+        float scale = float(ATLAS_TILES) / float(CACHE_TILES);
+        tex_color = textureGrad(sampler2D(u_tile_cache, u_sampler), cache_uv, x_derivative * scale, y_derivative * scale);
+    } else {
+        tex_color = textureGrad(sampler2D(u_texture_atlas, u_sampler), actual_uv, x_derivative, y_derivative);
+    }
 
     /* VARIOUS BRIGHTNESS FACTORS */
-    float light_factor = pow(0.8, 15.0 - i_light_level);
+    float light_factor = pow(0.8, 15.0 - max(i_light_level, u_ambient_light));
     float normal_factor = 1.0 - SUN_FRACTION + SUN_FRACTION * dot(i_norm, SUN_DIRECTION);
-    float total_factor = light_factor * i_occl * normal_factor;
+    vec3 total_factor = (vec3(light_factor) + dynamic_light()) * i_occl * normal_factor;
+    // only the top faces under the open sky get wet, they are the only ones with the maximum sky light
+    float exposed_to_sky = step(14.5, i_light_level) * max(i_norm.y, 0.0);
+    total_factor *= 1.0 - WETNESS_DARKENING * u_wetness * exposed_to_sky;
+    // only the fragments that the sky light reaches are darkened by the shadows, the caves are dark enough
+    float sky_lit = clamp((i_light_level - 10.0) / 5.0, 0.0, 1.0);
+    total_factor *= 1.0 - SHADOW_DARKENING * sky_lit * (1.0 - sun_visibility());
 
     /* OUTPUT */
-    o_color = vec4(total_factor, total_factor, total_factor, 1.0) * tex_color;
+    o_color = vec4(total_factor * i_tint, 1.0) * tex_color;
+    float camera_distance = distance(i_world_position, u_camera_position.xyz);
+    float fog = 1.0 - exp(-u_fog.a * camera_distance);
+    o_color.rgb = mix(o_color.rgb, u_fog.rgb, fog);
+    // blend the chunks close to the render distance into the sky, so that they don't pop in
+    if (u_distance_fog_end > 0.0) {
+        float distance_fog = smoothstep(u_distance_fog_start, u_distance_fog_end, camera_distance);
+        o_color.rgb = mix(o_color.rgb, u_horizon_color.rgb, distance_fog);
+    }
 }
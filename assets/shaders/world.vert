@@ -7,6 +7,9 @@ layout(location = 3) in vec2 i_texture_max_uv;
 layout(location = 4) in vec2 i_texture_uv;
 // occl at end, then face then light
 layout(location = 5) in uint i_occl_and_face;
+// number of frames and frame time
+layout(location = 6) in vec2 i_texture_animation;
+// seasonal: 1 bit
 // light: 4 bits
 // occl: 2 bits
 // face: 3 bits
@@ -15,6 +18,15 @@ layout(set = 0, binding = 0) uniform Transform {
     mat4 u_view_proj;
 };
 
+layout(set = 0, binding = 3) uniform Animation {
+    float u_animation_time;
+};
+
+layout(set = 0, binding = 8) uniform Season {
+    // rgb: tint of the seasonal blocks, a: frame of the seasonal textures
+    vec4 u_season;
+};
+
 layout(location = 0) flat out vec3 o_norm;
 layout(location = 1) out float o_occl;
 layout(location = 2) flat out vec2 o_texture_top_left;
@@ -22,6 +34,8 @@ layout(location = 3) flat out vec2 o_texture_size;
 layout(location = 4) flat out vec2 o_texture_max_uv;
 layout(location = 5) out vec2 o_texture_uv;
 layout(location = 6) flat out float o_light_level;
+layout(location = 7) out vec3 o_world_position;
+layout(location = 8) flat out vec3 o_tint;
 
 vec3 get_normal(uint id) {
     if(id == 0u) {
@@ -56,14 +70,25 @@ void main() {
     uint light_level = (i_occl_and_face & 0x000001E0u) >> 5;
     uint occl_code = (i_occl_and_face & 0x00000018u) >> 3;
     uint face_index = (i_occl_and_face & 0x00000007u) >> 0;
+    bool seasonal = (i_occl_and_face & 0x00000200u) != 0u;
 
     o_norm = get_normal(face_index);
     o_occl = get_occl(occl_code);
+    // animated textures are vertical strips of frames
     o_texture_top_left = i_texture_top_left;
+    if (i_texture_animation.x > 1.0 && i_texture_animation.y > 0.0) {
+        float frame = mod(floor(u_animation_time / i_texture_animation.y), i_texture_animation.x);
+        o_texture_top_left.y += frame * i_texture_size.y;
+    } else if (seasonal && i_texture_animation.x > 1.0) {
+        // seasonal textures are vertical strips of one frame per season
+        o_texture_top_left.y += min(u_season.a, i_texture_animation.x - 1.0) * i_texture_size.y;
+    }
+    o_tint = seasonal ? u_season.rgb : vec3(1.0);
     o_texture_size = i_texture_size;
     o_texture_max_uv = i_texture_max_uv;
     o_texture_uv = i_texture_uv;
     o_light_level = float(light_level);
+    o_world_position = i_position;
 
     gl_Position = u_view_proj * vec4(i_position, 1.0);
 }
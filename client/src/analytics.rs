@@ -0,0 +1,139 @@
+//! Opt-in session analytics.
+//!
+//! Nothing is ever uploaded: the statistics are appended to a local file that the user
+//! can inspect and share when reporting performance problems.
+use anyhow::{Context, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Number of sessions kept in the stats file, the oldest ones are dropped
+const MAX_SESSIONS: usize = 100;
+
+/// Statistics of all the recorded sessions
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct SessionStats {
+    pub crash_count: u64,
+    pub sessions: Vec<SessionRecord>,
+}
+
+/// Statistics of one session
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SessionRecord {
+    /// Start of the session, in seconds since the unix epoch
+    pub start_time: u64,
+    pub length_seconds: f64,
+    pub average_fps: f64,
+    /// The render distance at the end of the session
+    pub render_distance: (u64, u64, u64, u64, u64, u64),
+    pub crashed: bool,
+}
+
+struct SessionData {
+    path: PathBuf,
+    start: Instant,
+    start_time: u64,
+    /// The render distance of the last frame, it can change during the session
+    render_distance: Mutex<(u64, u64, u64, u64, u64, u64)>,
+    frames: AtomicU64,
+    finished: AtomicBool,
+}
+
+/// Tracks the current session. Cloning it yields a handle to the same session.
+#[derive(Clone)]
+pub struct SessionAnalytics {
+    data: Arc<SessionData>,
+}
+
+impl SessionAnalytics {
+    /// Start tracking a new session, and install a panic hook to record crashes
+    pub fn start(path: PathBuf, render_distance: (u64, u64, u64, u64, u64, u64)) -> Self {
+        info!("Session analytics enabled, writing to {}", path.display());
+        let start_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let analytics = Self {
+            data: Arc::new(SessionData {
+                path,
+                start: Instant::now(),
+                start_time,
+                render_distance: Mutex::new(render_distance),
+                frames: AtomicU64::new(0),
+                finished: AtomicBool::new(false),
+            }),
+        };
+
+        let hook_analytics = analytics.clone();
+        let previous_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |panic_info| {
+            previous_hook(panic_info);
+            hook_analytics.finish(true);
+        }));
+
+        analytics
+    }
+
+    /// Count one rendered frame, drawn with `render_distance`
+    pub fn add_frame(&self, render_distance: (u64, u64, u64, u64, u64, u64)) {
+        self.data.frames.fetch_add(1, Ordering::Relaxed);
+        *self.data.render_distance.lock().unwrap_or_else(|e| e.into_inner()) = render_distance;
+    }
+
+    /// Record the session in the stats file. Only the first call has an effect.
+    pub fn finish(&self, crashed: bool) {
+        if self.data.finished.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let length_seconds = self.data.start.elapsed().as_secs_f64();
+        let frames = self.data.frames.load(Ordering::Relaxed);
+        let record = SessionRecord {
+            start_time: self.data.start_time,
+            length_seconds,
+            average_fps: if length_seconds > 0.0 {
+                frames as f64 / length_seconds
+            } else {
+                0.0
+            },
+            render_distance: *self.data.render_distance.lock().unwrap_or_else(|e| e.into_inner()),
+            crashed,
+        };
+        if let Err(e) = append_record(&self.data.path, record) {
+            warn!("Failed to save session analytics ({:?})", e);
+        }
+    }
+}
+
+/// Load the stats file, or default stats if it doesn't exist
+pub fn load_stats(path: &Path) -> Result<SessionStats> {
+    if !path.is_file() {
+        return Ok(SessionStats::default());
+    }
+    let buf = std::fs::read_to_string(path)
+        .context(format!("Failed to read session stats file {}", path.display()))?;
+    toml::de::from_str(&buf)
+        .context(format!("Failed to parse session stats file {}", path.display()))
+}
+
+fn append_record(path: &Path, record: SessionRecord) -> Result<()> {
+    let mut stats = load_stats(path).unwrap_or_else(|e| {
+        warn!("Resetting session stats ({:?})", e);
+        SessionStats::default()
+    });
+    if record.crashed {
+        stats.crash_count += 1;
+    }
+    stats.sessions.push(record);
+    if stats.sessions.len() > MAX_SESSIONS {
+        let dropped = stats.sessions.len() - MAX_SESSIONS;
+        stats.sessions.drain(..dropped);
+    }
+    let string = toml::ser::to_string(&stats).context("Failed to serialize session stats")?;
+    std::fs::write(path, string)
+        .context(format!("Failed to write session stats file {}", path.display()))?;
+    Ok(())
+}
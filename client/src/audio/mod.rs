@@ -0,0 +1,170 @@
+//! Audio: sound events played at some position in the world or directly in the ears of the player
+use anyhow::{Context, Result};
+use log::warn;
+use nalgebra::Vector3;
+use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink, SpatialSink};
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::Arc;
+use voxel_rs_common::{
+    registry::Registry,
+    sound::{SoundEvent, SoundId},
+};
+
+/// Sounds further away from the listener can't be heard
+const MAX_SOUND_DISTANCE: f64 = 32.0;
+/// Distance between the center of the head and each ear
+const EAR_DISTANCE: f32 = 0.1;
+
+/// The files of a sound event
+struct LoadedSound {
+    /// Content of every file, they are decoded when they are played
+    variants: Vec<Arc<[u8]>>,
+    volume: f32,
+}
+
+pub struct Audio {
+    /// `None` if there is no audio device. The stream must be kept alive for the sounds to play.
+    output: Option<(OutputStream, OutputStreamHandle)>,
+    sounds: Vec<LoadedSound>,
+    /// Used to alternate between the variants of a sound
+    next_variant: usize,
+    listener_position: Vector3<f64>,
+    listener_yaw: f64,
+    /// Global volume between 0 and 1
+    volume: f32,
+}
+
+impl Audio {
+    /// Load the files of the sound events from `sounds_directory`.
+    /// Missing files are skipped, and no sound is played if there is no audio device.
+    pub fn new(sounds: &Registry<SoundEvent>, sounds_directory: &Path) -> Self {
+        let output = match OutputStream::try_default() {
+            Ok(output) => Some(output),
+            Err(e) => {
+                warn!("Failed to open audio device, sounds are disabled ({:?})", e);
+                None
+            }
+        };
+        let mut audio = Self {
+            output,
+            sounds: Vec::new(),
+            next_variant: 0,
+            listener_position: Vector3::zeros(),
+            listener_yaw: 0.0,
+            volume: 1.0,
+        };
+        audio.load_sounds(sounds, sounds_directory);
+        audio
+    }
+
+    /// Replace the loaded sounds, for example after the data was reloaded
+    pub fn load_sounds(&mut self, sounds: &Registry<SoundEvent>, sounds_directory: &Path) {
+        self.sounds = (0..sounds.get_number_of_ids())
+            .map(|id| {
+                let sound = sounds.get_value_by_id(id).unwrap();
+                let variants = sound
+                    .files
+                    .iter()
+                    .filter_map(|file| match load_file(&sounds_directory.join(file)) {
+                        Ok(content) => Some(content),
+                        Err(e) => {
+                            warn!("Failed to load sound file ({:?})", e);
+                            None
+                        }
+                    })
+                    .collect();
+                LoadedSound {
+                    variants,
+                    volume: sound.volume,
+                }
+            })
+            .collect();
+    }
+
+    /// Set the position and the yaw (in degrees) of the ears of the player
+    pub fn set_listener(&mut self, position: Vector3<f64>, yaw: f64) {
+        self.listener_position = position;
+        self.listener_yaw = yaw;
+    }
+
+    /// Set the global volume, between 0 and 1
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume;
+    }
+
+    /// Play a sound that doesn't come from the world, for example a UI click
+    pub fn play(&mut self, sound: SoundId) {
+        if let Some((handle, source, volume)) = self.prepare(sound) {
+            match Sink::try_new(handle) {
+                Ok(sink) => {
+                    sink.set_volume(volume);
+                    sink.append(source);
+                    sink.detach();
+                }
+                Err(e) => warn!("Failed to play sound ({:?})", e),
+            }
+        }
+    }
+
+    /// Play a sound at some position in the world
+    pub fn play_at(&mut self, sound: SoundId, position: Vector3<f64>) {
+        let offset = position - self.listener_position;
+        let distance = offset.norm();
+        if distance > MAX_SOUND_DISTANCE {
+            return;
+        }
+        // Linear attenuation: the sink only handles the direction of the sound
+        let attenuation = (1.0 - distance / MAX_SOUND_DISTANCE) as f32;
+        let direction = if distance > 1e-3 {
+            offset / distance
+        } else {
+            Vector3::zeros()
+        };
+        let y = self.listener_yaw.to_radians();
+        let right = Vector3::new(y.cos(), 0.0, -y.sin()).map(|x| x as f32) * EAR_DISTANCE;
+        if let Some((handle, source, volume)) = self.prepare(sound) {
+            // Positions are relative to the listener
+            match SpatialSink::try_new(
+                handle,
+                [direction.x as f32, direction.y as f32, direction.z as f32],
+                [-right.x, -right.y, -right.z],
+                [right.x, right.y, right.z],
+            ) {
+                Ok(sink) => {
+                    sink.set_volume(volume * attenuation);
+                    sink.append(source);
+                    sink.detach();
+                }
+                Err(e) => warn!("Failed to play sound ({:?})", e),
+            }
+        }
+    }
+
+    /// Pick the next variant of a sound and decode it
+    fn prepare(
+        &mut self,
+        sound: SoundId,
+    ) -> Option<(&OutputStreamHandle, Decoder<Cursor<Arc<[u8]>>>, f32)> {
+        let (_, handle) = self.output.as_ref()?;
+        let loaded_sound = self.sounds.get(sound as usize)?;
+        if loaded_sound.variants.is_empty() {
+            return None;
+        }
+        self.next_variant = self.next_variant.wrapping_add(1);
+        let content = loaded_sound.variants[self.next_variant % loaded_sound.variants.len()].clone();
+        match Decoder::new(Cursor::new(content)) {
+            Ok(source) => Some((handle, source, loaded_sound.volume * self.volume)),
+            Err(e) => {
+                warn!("Failed to decode sound ({:?})", e);
+                None
+            }
+        }
+    }
+}
+
+fn load_file(path: &Path) -> Result<Arc<[u8]>> {
+    let content =
+        std::fs::read(path).context(format!("Failed to read sound file {}", path.display()))?;
+    Ok(content.into())
+}
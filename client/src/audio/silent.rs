@@ -0,0 +1,28 @@
+//! Audio of the clients built without the `audio` feature: the sounds are never played.
+//! It has the same interface as the real audio in `mod.rs`.
+use log::info;
+use nalgebra::Vector3;
+use std::path::Path;
+use voxel_rs_common::{
+    registry::Registry,
+    sound::{SoundEvent, SoundId},
+};
+
+pub struct Audio;
+
+impl Audio {
+    pub fn new(_sounds: &Registry<SoundEvent>, _sounds_directory: &Path) -> Self {
+        info!("The game was built without the audio feature, sounds are disabled");
+        Self
+    }
+
+    pub fn load_sounds(&mut self, _sounds: &Registry<SoundEvent>, _sounds_directory: &Path) {}
+
+    pub fn set_listener(&mut self, _position: Vector3<f64>, _yaw: f64) {}
+
+    pub fn set_volume(&mut self, _volume: f32) {}
+
+    pub fn play(&mut self, _sound: SoundId) {}
+
+    pub fn play_at(&mut self, _sound: SoundId, _position: Vector3<f64>) {}
+}
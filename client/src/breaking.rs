@@ -0,0 +1,113 @@
+//! Client-side effects of the players breaking blocks: cracks on the blocks and particles when they break
+
+use crate::particles::{add_particle_lines, update_particles, Particle};
+use crate::render::DebugLines;
+use nalgebra::Vector3;
+use std::collections::HashMap;
+use voxel_rs_common::player::PlayerId;
+use voxel_rs_common::world::BlockPos;
+
+/// Number of cracks starting from the center of every face
+const CRACKS_PER_FACE: usize = 4;
+/// Distance between the faces of the block and the cracks, so that they are not hidden by the block
+const CRACK_OFFSET: f64 = 0.002;
+const CRACK_COLOR: [f32; 3] = [0.1, 0.1, 0.1];
+/// Number of particles spawned when a block breaks
+const BREAK_PARTICLES: usize = 16;
+const BREAK_PARTICLE_SPEED: f64 = 2.0;
+const BREAK_PARTICLE_LIFETIME: f64 = 0.6;
+
+pub struct BlockBreakingEffects {
+    /// The block that every player is breaking, and the progress between 0 and 1
+    cracks: HashMap<PlayerId, (BlockPos, f32)>,
+    particles: Vec<Particle>,
+}
+
+impl BlockBreakingEffects {
+    pub fn new() -> Self {
+        Self {
+            cracks: HashMap::new(),
+            particles: Vec::new(),
+        }
+    }
+
+    /// Update the progress of `player` breaking `block`, as sent by the server
+    pub fn set_progress(&mut self, player: PlayerId, block: BlockPos, progress: Option<f32>) {
+        match progress {
+            Some(progress) if progress < 1.0 => {
+                self.cracks.insert(player, (block, progress));
+            }
+            Some(_) => {
+                self.cracks.remove(&player);
+                self.spawn_break_particles(block);
+            }
+            None => {
+                self.cracks.remove(&player);
+            }
+        }
+    }
+
+    /// Forget the blocks broken by a player that is not visible anymore
+    pub fn retain_players(&mut self, mut is_visible: impl FnMut(PlayerId) -> bool) {
+        self.cracks.retain(|&player, _| is_visible(player));
+    }
+
+    pub fn update(&mut self, seconds_delta: f64) {
+        update_particles(&mut self.particles, seconds_delta);
+    }
+
+    fn spawn_break_particles(&mut self, block: BlockPos) {
+        let center = Vector3::new(block.px as f64, block.py as f64, block.pz as f64) + Vector3::new(0.5, 0.5, 0.5);
+        for _ in 0..BREAK_PARTICLES {
+            let direction = Vector3::new(
+                rand::random::<f64>() - 0.5,
+                rand::random::<f64>(),
+                rand::random::<f64>() - 0.5,
+            );
+            let gray = 0.4 + 0.3 * rand::random::<f32>();
+            self.particles.push(Particle {
+                position: center + direction * 0.5,
+                velocity: direction * BREAK_PARTICLE_SPEED,
+                remaining_time: BREAK_PARTICLE_LIFETIME * (0.5 + 0.5 * rand::random::<f64>()),
+                color: [gray, gray, gray],
+            });
+        }
+    }
+
+    /// Add the lines of the cracks and of the particles to `lines`
+    pub fn add_lines(&self, lines: &mut DebugLines) {
+        for &(block, progress) in self.cracks.values() {
+            add_crack_lines(lines, block, progress);
+        }
+        add_particle_lines(&self.particles, lines);
+    }
+}
+
+/// Draw cracks on every face of `block`, growing from the center of the face with the progress
+fn add_crack_lines(lines: &mut DebugLines, block: BlockPos, progress: f32) {
+    let min = Vector3::new(block.px as f64, block.py as f64, block.pz as f64);
+    // The cracks depend on the position of the block, so that they don't change every frame
+    let seed = ((block.px.wrapping_mul(73_856_093) ^ block.py.wrapping_mul(19_349_663) ^ block.pz.wrapping_mul(83_492_791)) % 1000) as f64;
+    let length = 0.5 * progress as f64;
+    for axis in 0..3 {
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        for &side in [-CRACK_OFFSET, 1.0 + CRACK_OFFSET].iter() {
+            let mut center = min + Vector3::new(0.5, 0.5, 0.5);
+            center[axis] = min[axis] + side;
+            let point = |angle: f64, distance: f64| {
+                let mut point = Vector3::zeros();
+                point[u] = angle.cos() * distance;
+                point[v] = angle.sin() * distance;
+                point
+            };
+            for i in 0..CRACKS_PER_FACE {
+                let angle = seed + (axis as f64 + side) * 1.3 + i as f64 * std::f64::consts::FRAC_PI_2;
+                // Every crack has a kink in the middle
+                let middle = center + point(angle, length * 0.5);
+                let end = middle + point(angle + 0.6, length * 0.5);
+                lines.add_line(center, middle, CRACK_COLOR);
+                lines.add_line(middle, end, CRACK_COLOR);
+            }
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! Wireframe visualizers to debug the physics and the meshing: the chunk borders and the hitboxes of the players.
+//!
+//! They are toggled by pressing a key while `DEBUG_MODIFIER` is held, and drawn with the debug lines of the world.
+//! The same modifier also asks for a remesh of every chunk, with `RELOAD_CHUNKS`.
+use nalgebra::Vector3;
+use voxel_rs_common::{
+    physics::player::PhysicsPlayer,
+    world::{BlockPos, ChunkPos, CHUNK_SIZE},
+};
+use winit::event::ElementState;
+
+use crate::input::{DEBUG_MODIFIER, RELOAD_CHUNKS, TOGGLE_CHUNK_BORDERS, TOGGLE_HITBOXES};
+use crate::render::DebugLines;
+
+const CHUNK_COLOR: [f32; 3] = [1.0, 1.0, 0.0];
+/// Spacing of the grid drawn on the faces of the chunk of the player
+const GRID_SPACING: u32 = 4;
+const GRID_COLOR: [f32; 3] = [0.5, 0.5, 0.0];
+const NEIGHBOR_CHUNK_COLOR: [f32; 3] = [0.2, 0.4, 1.0];
+/// Number of neighboring chunks whose vertical edges are drawn, along x and z
+const NEIGHBOR_CHUNKS: i64 = 2;
+const HITBOX_COLOR: [f32; 3] = [1.0, 1.0, 1.0];
+const LOOK_COLOR: [f32; 3] = [0.2, 0.2, 1.0];
+/// Length of the line that shows where a player looks
+const LOOK_LENGTH: f64 = 2.0;
+
+/// The enabled debug visualizers
+#[derive(Debug, Default)]
+pub struct DebugVisualizers {
+    modifier_held: bool,
+    chunk_borders: bool,
+    hitboxes: bool,
+    /// The chunks must be remeshed, see `take_reload_chunks`
+    reload_chunks: bool,
+}
+
+impl DebugVisualizers {
+    /// Toggle the visualizers whose key was pressed while the modifier is held
+    pub fn handle_key_state_changes(&mut self, changes: &[(u32, ElementState)]) {
+        for &(key, state) in changes.iter() {
+            if key == DEBUG_MODIFIER {
+                self.modifier_held = state == ElementState::Pressed;
+            } else if self.modifier_held && state == ElementState::Pressed {
+                match key {
+                    TOGGLE_CHUNK_BORDERS => self.chunk_borders = !self.chunk_borders,
+                    TOGGLE_HITBOXES => self.hitboxes = !self.hitboxes,
+                    RELOAD_CHUNKS => self.reload_chunks = true,
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    /// Return `true` once after `RELOAD_CHUNKS` was pressed with the modifier
+    pub fn take_reload_chunks(&mut self) -> bool {
+        std::mem::replace(&mut self.reload_chunks, false)
+    }
+
+    /// Add the lines of the enabled visualizers.
+    /// `look_dir` is the direction the player looks at, and `other_players` contains the other players with their yaw.
+    pub fn add_lines(
+        &self,
+        lines: &mut DebugLines,
+        player: &PhysicsPlayer,
+        look_dir: Vector3<f64>,
+        other_players: &[(&PhysicsPlayer, f64)],
+    ) {
+        if self.chunk_borders {
+            add_chunk_borders(lines, BlockPos::from(player.aabb.pos).containing_chunk_pos());
+        }
+        if self.hitboxes {
+            add_hitbox(lines, player, look_dir);
+            for &(other, yaw) in other_players.iter() {
+                // Only the yaw of the other players is known
+                let yaw = yaw.to_radians();
+                add_hitbox(lines, other, Vector3::new(-yaw.sin(), 0.0, -yaw.cos()));
+            }
+        }
+    }
+}
+
+/// Draw the chunk of the player with a grid on its faces, and the vertical edges of the neighboring chunks
+fn add_chunk_borders(lines: &mut DebugLines, chunk: ChunkPos) {
+    let size = CHUNK_SIZE as f64;
+    let min = Vector3::new(chunk.px as f64, chunk.py as f64, chunk.pz as f64) * size;
+    lines.add_box(min, Vector3::new(size, size, size), CHUNK_COLOR);
+
+    // Grid on the vertical faces of the chunk
+    for offset in (GRID_SPACING..CHUNK_SIZE).step_by(GRID_SPACING as usize) {
+        let d = offset as f64;
+        for &a in [0.0, size].iter() {
+            // Horizontal lines
+            lines.add_line(min + Vector3::new(0.0, d, a), min + Vector3::new(size, d, a), GRID_COLOR);
+            lines.add_line(min + Vector3::new(a, d, 0.0), min + Vector3::new(a, d, size), GRID_COLOR);
+            // Vertical lines
+            lines.add_line(min + Vector3::new(d, 0.0, a), min + Vector3::new(d, size, a), GRID_COLOR);
+            lines.add_line(min + Vector3::new(a, 0.0, d), min + Vector3::new(a, size, d), GRID_COLOR);
+        }
+    }
+
+    // Vertical edges of the neighboring chunks, spanning the same height
+    let bottom = min.y - NEIGHBOR_CHUNKS as f64 * size;
+    let top = min.y + (NEIGHBOR_CHUNKS + 1) as f64 * size;
+    for i in -NEIGHBOR_CHUNKS..=NEIGHBOR_CHUNKS + 1 {
+        for k in -NEIGHBOR_CHUNKS..=NEIGHBOR_CHUNKS + 1 {
+            let (x, z) = (min.x + i as f64 * size, min.z + k as f64 * size);
+            lines.add_line(Vector3::new(x, bottom, z), Vector3::new(x, top, z), NEIGHBOR_CHUNK_COLOR);
+        }
+    }
+}
+
+/// Draw the hitbox of a player, and a line from its eyes in the direction it looks at
+fn add_hitbox(lines: &mut DebugLines, player: &PhysicsPlayer, look_dir: Vector3<f64>) {
+    let aabb = &player.aabb;
+    lines.add_box(aabb.pos, Vector3::new(aabb.size_x, aabb.size_y, aabb.size_z), HITBOX_COLOR);
+    let eye = player.get_camera_position();
+    lines.add_line(eye, eye + look_dir * LOOK_LENGTH, LOOK_COLOR);
+}
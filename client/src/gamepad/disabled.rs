@@ -0,0 +1,30 @@
+//! Gamepad controls of the clients built without the `gamepad` feature: the gamepads are ignored.
+//! It has the same interface as the real controls in `mod.rs`.
+use crate::input::{GamepadFrame, InputState};
+use crate::settings::Settings;
+use log::info;
+use winit::dpi::{LogicalPosition, LogicalSize};
+use winit::event::{ElementState, MouseButton};
+
+pub struct GamepadControls;
+
+impl GamepadControls {
+    pub fn new() -> Option<Self> {
+        info!("The game was built without the gamepad feature, gamepads are disabled");
+        None
+    }
+
+    pub fn set_cursor_position(&mut self, _position: LogicalPosition<f64>) {}
+
+    pub fn update(
+        &mut self,
+        _settings: &Settings,
+        _in_menu: bool,
+        _window_size: LogicalSize<f64>,
+        _input_state: &mut InputState,
+        _mouse_state_changes: &mut Vec<(MouseButton, ElementState)>,
+        _key_state_changes: &mut Vec<(u32, ElementState)>,
+    ) -> GamepadFrame {
+        GamepadFrame::default()
+    }
+}
@@ -0,0 +1,240 @@
+//! Gamepad controls, that press the keys and the mouse buttons bound to the gamepad buttons.
+//! Only built with the `gamepad` feature, see `disabled.rs` for the clients built without it.
+use crate::input::{
+    GamepadAction, GamepadButton, GamepadFrame, InputState, MOVE_DOWN, MOVE_UP, OPEN_MENU, TAKE_SCREENSHOT,
+    TOGGLE_CAMERA_MODE, TOGGLE_CRAFTING, TOGGLE_FLIGHT,
+};
+use crate::settings::Settings;
+use gilrs::{Axis, EventType, Gilrs};
+use log::{info, warn};
+use std::collections::HashMap;
+use std::time::Instant;
+use winit::dpi::{LogicalPosition, LogicalSize};
+use winit::event::{ElementState, MouseButton};
+
+/// The gamepad button that a gilrs button is bound with, if it can be bound
+fn button_from_gilrs(button: gilrs::Button) -> Option<GamepadButton> {
+    use gilrs::Button::*;
+    Some(match button {
+        South => GamepadButton::South,
+        East => GamepadButton::East,
+        North => GamepadButton::North,
+        West => GamepadButton::West,
+        LeftTrigger => GamepadButton::LeftBumper,
+        RightTrigger => GamepadButton::RightBumper,
+        LeftTrigger2 => GamepadButton::LeftTrigger,
+        RightTrigger2 => GamepadButton::RightTrigger,
+        Select => GamepadButton::Select,
+        Start => GamepadButton::Start,
+        LeftThumb => GamepadButton::LeftStick,
+        RightThumb => GamepadButton::RightStick,
+        DPadUp => GamepadButton::DPadUp,
+        DPadDown => GamepadButton::DPadDown,
+        DPadLeft => GamepadButton::DPadLeft,
+        DPadRight => GamepadButton::DPadRight,
+        C | Z | Mode | Unknown => return None,
+    })
+}
+
+/// The key or the mouse button pressed by an action
+fn action_input(action: GamepadAction) -> VirtualInput {
+    match action {
+        GamepadAction::MoveUp => VirtualInput::Key(MOVE_UP),
+        GamepadAction::MoveDown => VirtualInput::Key(MOVE_DOWN),
+        GamepadAction::Break => VirtualInput::Mouse(MouseButton::Left),
+        GamepadAction::Place => VirtualInput::Mouse(MouseButton::Right),
+        GamepadAction::OpenMenu => VirtualInput::Key(OPEN_MENU),
+        GamepadAction::ToggleCrafting => VirtualInput::Key(TOGGLE_CRAFTING),
+        GamepadAction::ToggleFlight => VirtualInput::Key(TOGGLE_FLIGHT),
+        GamepadAction::ToggleCameraMode => VirtualInput::Key(TOGGLE_CAMERA_MODE),
+        GamepadAction::TakeScreenshot => VirtualInput::Key(TAKE_SCREENSHOT),
+    }
+}
+
+/// A key or a mouse button pressed with the gamepad
+#[derive(Debug, Clone, Copy)]
+enum VirtualInput {
+    Key(u32),
+    Mouse(MouseButton),
+}
+
+impl VirtualInput {
+    /// Change the state of the key or the mouse button, like the window does for real inputs
+    fn process(
+        self,
+        state: ElementState,
+        input_state: &mut InputState,
+        mouse_state_changes: &mut Vec<(MouseButton, ElementState)>,
+        key_state_changes: &mut Vec<(u32, ElementState)>,
+    ) {
+        match self {
+            VirtualInput::Key(scancode) => {
+                if input_state.process_key(scancode, state) {
+                    key_state_changes.push((scancode, state));
+                }
+            }
+            VirtualInput::Mouse(button) => {
+                if input_state.process_mouse_input(state, button) {
+                    mouse_state_changes.push((button, state));
+                }
+            }
+        }
+    }
+}
+
+/// Maximum duration of a frame for the sticks, so that the camera doesn't jump after a long frame
+const MAX_GAMEPAD_FRAME: f64 = 0.1;
+
+/// Gamepad controls, using the first connected gamepad for the sticks.
+///
+/// In game, the buttons do their bound actions, the left stick moves and the right stick looks around.
+/// In the menus, the left stick moves the cursor, the right stick scrolls, `South` clicks and `East` goes back.
+pub struct GamepadControls {
+    gilrs: Gilrs,
+    /// The key or the mouse button pressed by every held gamepad button
+    pressed: HashMap<GamepadButton, VirtualInput>,
+    /// Position of the cursor in the menus, moved by the mouse and by the left stick
+    cursor: LogicalPosition<f64>,
+    last_update: Instant,
+}
+
+impl GamepadControls {
+    /// Start listening to the gamepads, returning `None` if they are not supported
+    pub fn new() -> Option<Self> {
+        match Gilrs::new() {
+            Ok(gilrs) => {
+                for (_, gamepad) in gilrs.gamepads() {
+                    info!("Found gamepad {}", gamepad.name());
+                }
+                Some(Self {
+                    gilrs,
+                    pressed: HashMap::new(),
+                    cursor: LogicalPosition::new(0.0, 0.0),
+                    last_update: Instant::now(),
+                })
+            }
+            Err(e) => {
+                warn!("Gamepads are not supported ({})", e);
+                None
+            }
+        }
+    }
+
+    /// Update the position of the cursor after it was moved by the mouse
+    pub fn set_cursor_position(&mut self, position: LogicalPosition<f64>) {
+        self.cursor = position;
+    }
+
+    /// Process the gamepad events of the frame, pressing the bound keys and mouse buttons
+    pub fn update(
+        &mut self,
+        settings: &Settings,
+        in_menu: bool,
+        window_size: LogicalSize<f64>,
+        input_state: &mut InputState,
+        mouse_state_changes: &mut Vec<(MouseButton, ElementState)>,
+        key_state_changes: &mut Vec<(u32, ElementState)>,
+    ) -> GamepadFrame {
+        let now = Instant::now();
+        let dt = (now - self.last_update).as_secs_f64().min(MAX_GAMEPAD_FRAME);
+        self.last_update = now;
+        let gamepad_settings = &settings.gamepad;
+
+        while let Some(gilrs::Event { event, .. }) = self.gilrs.next_event() {
+            match event {
+                EventType::ButtonPressed(button, _) => {
+                    let button = match button_from_gilrs(button) {
+                        Some(button) => button,
+                        None => continue,
+                    };
+                    let input = match (in_menu, button) {
+                        (true, GamepadButton::South) => Some(VirtualInput::Mouse(MouseButton::Left)),
+                        (true, GamepadButton::East) => Some(VirtualInput::Key(OPEN_MENU)),
+                        _ => gamepad_settings
+                            .bindings
+                            .iter()
+                            .find(|binding| binding.button == button)
+                            .map(|binding| action_input(binding.action)),
+                    };
+                    if let Some(input) = input {
+                        self.pressed.insert(button, input);
+                        input.process(ElementState::Pressed, input_state, mouse_state_changes, key_state_changes);
+                    }
+                }
+                // Release what the button pressed, even if the bindings changed since
+                EventType::ButtonReleased(button, _) => {
+                    if let Some(input) = button_from_gilrs(button).and_then(|b| self.pressed.remove(&b)) {
+                        input.process(ElementState::Released, input_state, mouse_state_changes, key_state_changes);
+                    }
+                }
+                EventType::Connected => info!("Gamepad connected"),
+                EventType::Disconnected => info!("Gamepad disconnected"),
+                _ => (),
+            }
+        }
+
+        // The y axis of the sticks goes up, unlike the y axis of the mouse
+        let (left, right) = match self.gilrs.gamepads().next() {
+            Some((_, gamepad)) => {
+                let stick = |x: Axis, y: Axis, deadzone: f64| {
+                    apply_deadzone((gamepad.value(x) as f64, -gamepad.value(y) as f64), deadzone)
+                };
+                (
+                    stick(Axis::LeftStickX, Axis::LeftStickY, gamepad_settings.move_deadzone),
+                    stick(Axis::RightStickX, Axis::RightStickY, gamepad_settings.look_deadzone),
+                )
+            }
+            None => ((0.0, 0.0), (0.0, 0.0)),
+        };
+
+        let mut frame = GamepadFrame::default();
+        if in_menu {
+            input_state.set_gamepad_movement((0.0, 0.0));
+            if left != (0.0, 0.0) {
+                let speed = gamepad_settings.cursor_speed * dt;
+                self.cursor.x = (self.cursor.x + left.0 * speed).max(0.0).min(window_size.width);
+                self.cursor.y = (self.cursor.y + left.1 * speed).max(0.0).min(window_size.height);
+                frame.cursor = Some(self.cursor);
+            }
+            if right.1 != 0.0 {
+                frame.scroll = Some((-right.1 * gamepad_settings.scroll_speed * dt) as f32);
+            }
+        } else {
+            input_state.set_gamepad_movement(left);
+            if right != (0.0, 0.0) {
+                // The mouse settings are applied to the look movement, so it's converted to mouse units
+                let speed = gamepad_settings.look_speed * dt / settings.mouse_sensitivity.max(0.001);
+                frame.look = Some((right.0 * speed, right.1 * speed));
+            }
+        }
+        frame
+    }
+}
+
+/// Ignore the stick movements that are shorter than `deadzone`, and scale the others
+/// so that they go from 0 at the edge of the deadzone to 1 when the stick is fully tilted
+fn apply_deadzone((x, y): (f64, f64), deadzone: f64) -> (f64, f64) {
+    let length = (x * x + y * y).sqrt();
+    if length <= deadzone || length == 0.0 {
+        return (0.0, 0.0);
+    }
+    let scaled_length = ((length - deadzone) / (1.0 - deadzone)).min(1.0);
+    (x / length * scaled_length, y / length * scaled_length)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gamepad_deadzone() {
+        assert_eq!(apply_deadzone((0.1, -0.1), 0.2), (0.0, 0.0));
+        assert_eq!(apply_deadzone((0.0, 0.0), 0.0), (0.0, 0.0));
+        let (x, y) = apply_deadzone((0.0, -0.6), 0.2);
+        assert_eq!(x, 0.0);
+        assert!((y + 0.5).abs() < 1e-9);
+        // Diagonals can't go further than the edge of the stick
+        let (x, y) = apply_deadzone((1.0, 1.0), 0.2);
+        assert!((x * x + y * y - 1.0).abs() < 1e-9);
+    }
+}
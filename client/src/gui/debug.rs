@@ -0,0 +1,158 @@
+use std::time::Instant;
+use voxel_rs_common::debug::{DebugInfo, DebugInfoPart, PERF_GRAPH_DURATION};
+
+const ELEMENT_HEIGHT: i32 = 20;
+const ELEMENT_OFFSET: i32 = 25;
+
+/// The perf graphs that can be toggled, with their debug info section and id
+pub const PERF_GRAPHS: [(&str, &str); 3] = [
+    ("Server", "mainloop_graph"),
+    ("Client performance", "mainloop_graph"),
+    ("Client performance", "gpu_graph"),
+];
+/// Index of the graph of the GPU time of the render passes, which are only measured while it is shown
+pub const GPU_PERF_GRAPH: usize = 2;
+const GRAPH_WIDTH: f32 = 500.0;
+const GRAPH_HEIGHT: f32 = 150.0;
+const GRAPH_MARGIN: f32 = 10.0;
+/// Width of the legend, on the left of the graph
+const LEGEND_WIDTH: f32 = 250.0;
+const GRAPH_COLORS: [[f32; 4]; 8] = [
+    [0.9, 0.2, 0.2, 1.0],
+    [0.2, 0.8, 0.2, 1.0],
+    [0.3, 0.4, 1.0, 1.0],
+    [0.9, 0.9, 0.2, 1.0],
+    [0.9, 0.2, 0.9, 1.0],
+    [0.2, 0.9, 0.9, 1.0],
+    [1.0, 0.6, 0.2, 1.0],
+    [0.7, 0.7, 0.7, 1.0],
+];
+
+pub fn render_debug_info(gui: &mut super::Gui, debug_info: &mut DebugInfo) {
+    let debug_info = debug_info.get_debug_info();
+    let x = 4;
+    let mut y = 4;
+    for (section, (displayed, id, messages)) in debug_info {
+        let section_text = format!("{} debug info", section.to_uppercase());
+        if gui.button(*id, x, y, 400, ELEMENT_HEIGHT).text(section_text, [1.0, 1.0, 1.0, 1.0]).build() {
+            *displayed = !*displayed;
+        }
+        y += ELEMENT_OFFSET;
+        if *displayed {
+            for (_, part) in messages {
+                match part {
+                    DebugInfoPart::Message(message) => {
+                        for line in message.lines() {
+                            gui.text(x + 10, y, ELEMENT_HEIGHT, line.to_owned(), [1.0, 1.0, 1.0, 1.0]);
+                            y += ELEMENT_HEIGHT;
+                        }
+                    },
+                    DebugInfoPart::WorkerPerf(perf) => {
+                        let text = format!(
+                            "{:22} | {:6.1} ms/iter | {:5.0} iter/s | {:3.0}% efficiency | {:7} pending",
+                            perf.name,
+                            perf.micros_per_iter/1000.0,
+                            perf.iter_per_sec,
+                            perf.efficiency * 100.0,
+                            perf.pending,
+                        );
+                        gui.text(x + 10, y, ELEMENT_HEIGHT, text, [1.0, 1.0, 1.0, 1.0]);
+                        y += ELEMENT_HEIGHT;
+                    },
+                    // The graphs are drawn by `render_perf_graphs`
+                    DebugInfoPart::PerfGraph(_) => {}
+                    DebugInfoPart::PerfBreakdown(name, breakdown) => {
+                        gui.text(x + 10, y, ELEMENT_HEIGHT, format!("{} performance breakdown", name), [1.0, 1.0, 1.0, 1.0]);
+                        y += ELEMENT_HEIGHT;
+                        for (text, percents) in breakdown {
+                            let text = format!("{:3.0}% of time: {}", *percents * 100.0, text);
+                            gui.text(x + 20, y, ELEMENT_HEIGHT, text, [1.0, 1.0, 1.0, 1.0]);
+                            y += ELEMENT_HEIGHT;
+                        }
+                    },
+                }
+            }
+        }
+    }
+}
+/// Draw the graphs of `PERF_GRAPHS` that are shown, stacked from the bottom right corner of the window.
+/// Every part of the loop is stacked on top of the previous parts.
+pub fn render_perf_graphs(
+    gui: &mut super::Gui,
+    debug_info: &mut DebugInfo,
+    shown: &[bool],
+    window_width: f32,
+    window_height: f32,
+) {
+    let now = Instant::now();
+    let debug_info = debug_info.get_debug_info();
+    let x = window_width - GRAPH_WIDTH - GRAPH_MARGIN;
+    let mut y = window_height - GRAPH_MARGIN;
+    for (&(section, id), _) in PERF_GRAPHS.iter().zip(shown.iter()).filter(|(_, shown)| **shown) {
+        let graph = match debug_info
+            .get(section)
+            .and_then(|(_, _, parts)| parts.get(id))
+        {
+            Some(DebugInfoPart::PerfGraph(graph)) => graph,
+            _ => continue,
+        };
+        y -= GRAPH_HEIGHT;
+
+        let max_total = graph
+            .samples
+            .iter()
+            .map(|(_, millis)| millis.iter().sum::<f64>())
+            .fold(1.0, f64::max);
+        let sample_x = |time: Instant| {
+            let age = (now - time).as_secs_f32() / PERF_GRAPH_DURATION.as_secs_f32();
+            x + GRAPH_WIDTH * (1.0 - age.min(1.0))
+        };
+        let sample_y = |millis: f64| y + GRAPH_HEIGHT * (1.0 - (millis / max_total) as f32);
+
+        // Background and legend
+        gui.primitives.draw_rect(
+            (x - LEGEND_WIDTH) as i32,
+            y as i32,
+            (GRAPH_WIDTH + LEGEND_WIDTH) as i32,
+            GRAPH_HEIGHT as i32,
+            [0.0, 0.0, 0.0, 0.6],
+        );
+        let mut legend_y = y as i32;
+        let title = format!("{} (max {:.1} ms)", graph.name, max_total);
+        gui.text((x - LEGEND_WIDTH) as i32 + 4, legend_y, ELEMENT_HEIGHT, title, [1.0, 1.0, 1.0, 1.0]);
+        for (i, part_name) in graph.part_names.iter().enumerate() {
+            legend_y += ELEMENT_HEIGHT;
+            let color = GRAPH_COLORS[i % GRAPH_COLORS.len()];
+            gui.text((x - LEGEND_WIDTH) as i32 + 4, legend_y, ELEMENT_HEIGHT, part_name.clone(), color);
+        }
+
+        // One area per part
+        for part in 0..graph.part_names.len() {
+            let mut vertices = Vec::new();
+            let mut indices = Vec::new();
+            let bounds = graph
+                .samples
+                .iter()
+                .map(|(time, millis)| {
+                    let bottom = millis[..part].iter().sum::<f64>();
+                    (sample_x(*time), sample_y(bottom), sample_y(bottom + millis[part]))
+                })
+                .collect::<Vec<_>>();
+            for w in bounds.windows(2) {
+                let (x1, bottom1, top1) = w[0];
+                let (x2, bottom2, top2) = w[1];
+                let a = vertices.len() as u32;
+                vertices.extend_from_slice(&[
+                    [x1, top1],
+                    [x2, top2],
+                    [x1, bottom1],
+                    [x2, bottom2],
+                ]);
+                indices.extend_from_slice(&[a + 1, a, a + 2, a + 1, a + 2, a + 3]);
+            }
+            gui.primitives
+                .draw_triangles(vertices, indices, GRAPH_COLORS[part % GRAPH_COLORS.len()]);
+        }
+        y -= GRAPH_MARGIN;
+    }
+}
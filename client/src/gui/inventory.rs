@@ -0,0 +1,48 @@
+//! The inventory screen: the slots of the player inventory, whose stacks are moved with drag-and-drop.
+//!
+//! The other container screens, like a chest or a crafting table, add one slot grid per container to their panel.
+use super::panel::{panel_height, PANEL_PADDING};
+use super::slots::{grid_size, SlotId, SlotInteraction};
+use super::Gui;
+use voxel_rs_common::inventory::{Inventory, InventoryItem, SlotAction, PLAYER_INVENTORY_ROW_SIZE};
+
+/// Id of the slot grid of the player inventory
+pub const PLAYER_INVENTORY_GRID: u32 = 0;
+
+pub fn render_inventory(
+    gui: &mut Gui,
+    inventory: &Inventory,
+    name: &dyn Fn(InventoryItem) -> String,
+    window_width: i32,
+    window_height: i32,
+) {
+    let (grid_width, grid_height) = grid_size(inventory.slots().len(), PLAYER_INVENTORY_ROW_SIZE);
+    let mut panel = gui.panel(
+        "Inventory",
+        grid_width + 2 * PANEL_PADDING,
+        panel_height(&[grid_height]),
+        window_width,
+        window_height,
+    );
+    let y = panel.row(grid_height);
+    gui.slot_grid(PLAYER_INVENTORY_GRID, panel.content_x(), y, PLAYER_INVENTORY_ROW_SIZE, inventory.slots(), name);
+}
+
+/// The action on the slots of the player inventory, `None` if the interaction is with other grids
+pub fn player_slot_action(interaction: SlotInteraction) -> Option<SlotAction> {
+    let in_inventory = |slot: &SlotId| slot.grid == PLAYER_INVENTORY_GRID;
+    match interaction {
+        SlotInteraction::Drop { from, to } if in_inventory(&from) && in_inventory(&to) => Some(SlotAction::Click {
+            from: from.index,
+            to: to.index,
+        }),
+        SlotInteraction::ShiftClick(slot) if in_inventory(&slot) => Some(SlotAction::ShiftClick(slot.index)),
+        SlotInteraction::Split { from, to } if in_inventory(&from) && to.iter().all(in_inventory) => {
+            Some(SlotAction::DragSplit {
+                from: from.index,
+                to: to.iter().map(|slot| slot.index).collect(),
+            })
+        }
+        _ => None,
+    }
+}
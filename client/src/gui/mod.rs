@@ -1,12 +1,23 @@
-use crate::ui::PrimitiveBuffer;
+use crate::ui::{PrimitiveBuffer, TextPart, UiLayer};
+use self::slots::{DraggedStack, SlotInteraction};
 
-pub mod experiments;
+pub mod debug;
+pub mod inventory;
+pub mod panel;
+pub mod slots;
+
+/// Z-index of the elements that follow the mouse, above everything else but the tooltips
+const MOUSE_Z_INDEX: i32 = 1_000_000;
 
 /// Immediate-mode GUI
 pub struct Gui {
     pub(self) mouse_x: i32,
     pub(self) mouse_y: i32,
     pub(self) mouse_down: bool,
+    pub(self) right_mouse_down: bool,
+    /// `true` if the right mouse button was pressed since the last frame
+    pub(self) right_mouse_clicked: bool,
+    pub(self) shift_down: bool,
 
     pub(self) hot_item: u32,
     /// Active item. Ids 0 and 1 are reserved.
@@ -15,6 +26,11 @@ pub struct Gui {
     pub(self) active_item: u32,
 
     pub(self) primitives: PrimitiveBuffer,
+
+    /// The stack dragged from a slot, while the mouse button is down
+    pub(self) dragged: Option<DraggedStack>,
+    /// The interactions with the slots, until they are taken
+    pub(self) slot_interactions: Vec<SlotInteraction>,
 }
 
 impl Gui {
@@ -24,9 +40,14 @@ impl Gui {
             mouse_x: 0,
             mouse_y: 0,
             mouse_down: false,
+            right_mouse_down: false,
+            right_mouse_clicked: false,
+            shift_down: false,
             hot_item: 0,
             active_item: 0,
             primitives: Default::default(),
+            dragged: None,
+            slot_interactions: Vec::new(),
         }
     }
 
@@ -41,6 +62,17 @@ impl Gui {
         self.mouse_down = is_down;
     }
 
+    /// Update the state of the right mouse button
+    pub fn update_right_mouse_button(&mut self, is_down: bool) {
+        self.right_mouse_clicked |= is_down && !self.right_mouse_down;
+        self.right_mouse_down = is_down;
+    }
+
+    /// Update the state of the shift key
+    pub fn update_shift(&mut self, is_down: bool) {
+        self.shift_down = is_down;
+    }
+
     /// Drain stores primitives
     pub fn drain_primitives(&mut self) -> PrimitiveBuffer {
         std::mem::replace(&mut self.primitives, PrimitiveBuffer::default())
@@ -49,10 +81,19 @@ impl Gui {
     /// Prepare for frame drawing
     pub fn prepare(&mut self) {
         self.hot_item = 0;
+        self.primitives.set_layer(UiLayer::Hud);
+    }
+
+    /// Draw the next elements in `layer`. Within a layer, the elements are drawn in order.
+    pub fn set_layer(&mut self, layer: UiLayer) {
+        self.primitives.set_layer(layer);
     }
 
     /// Finish the frame
     pub fn finish(&mut self) {
+        self.draw_mouse_elements();
+        self.end_slot_drag();
+        self.right_mouse_clicked = false;
         if !self.mouse_down {
             // If the mouse button is not down, then we allow an item to become active
             // when the mouse button will be pressed.
@@ -66,6 +107,18 @@ impl Gui {
         }
     }
 
+    /// Draw the dragged stack above everything else
+    fn draw_mouse_elements(&mut self) {
+        self.primitives.set_layer(UiLayer::Menu);
+        quint::Backend::set_z_index(&mut self.primitives, MOUSE_Z_INDEX);
+        self.draw_dragged_stack();
+    }
+
+    /// Describe the hovered element with a tooltip, shown once the mouse stays on it
+    pub fn tooltip(&mut self, text: String) {
+        self.primitives.draw_tooltip(self.mouse_x as f32, self.mouse_y as f32, text);
+    }
+
     /// Is the mouse inside the rectangle
     pub fn is_mouse_inside(&self, x: i32, y: i32, w: i32, h: i32) -> bool {
         x <= self.mouse_x && self.mouse_x < x + w && y <= self.mouse_y && self.mouse_y < y + h
@@ -85,12 +138,26 @@ impl Gui {
     }
 
     /// Draw text, aligned to the left but centered vertically
-    pub fn text(&mut self, x: i32, y: i32, h: i32, text: String, color: [f32; 4], z: f32) {
-        self.primitives.draw_text_simple(x, y, h, text, color, z);
+    pub fn text(&mut self, x: i32, y: i32, h: i32, text: String, color: [f32; 4]) {
+        self.primitives.draw_text_simple(x, y, h, text, color);
+    }
+
+    /// Draw a filled rectangle
+    pub fn rect(&mut self, x: i32, y: i32, w: i32, h: i32, color: [f32; 4]) {
+        self.primitives.draw_rect(x, y, w, h, color);
     }
-}
 
-// TODO: fix depth
+    /// Draw text, centered in the rectangle
+    pub fn centered_text(&mut self, x: f32, y: f32, w: f32, h: f32, text: TextPart) {
+        let layout = quint::Layout {
+            x,
+            y,
+            width: w,
+            height: h,
+        };
+        self.primitives.draw_text(vec![text], layout, true);
+    }
+}
 
 /// Builder for a button
 #[must_use]
@@ -118,7 +185,7 @@ impl<'a> ButtonBuilder<'a> {
             }
         }
         // Draw the shadow
-        gui.primitives.draw_rect(x + 3, y + 3, w, h, [0.0, 0.0, 0.0, 1.0], 0.02);
+        gui.primitives.draw_rect(x + 3, y + 3, w, h, [0.0, 0.0, 0.0, 1.0]);
         // Draw the button
         let draw_pos;
         let button_color;
@@ -137,9 +204,9 @@ impl<'a> ButtonBuilder<'a> {
             draw_pos = (x, y);
             button_color = [0.8, 0.8, 0.8, 1.0];
         }
-        gui.primitives.draw_rect(draw_pos.0, draw_pos.1, w, h, button_color, 0.01);
+        gui.primitives.draw_rect(draw_pos.0, draw_pos.1, w, h, button_color);
         if let Some((text, color)) = text {
-            gui.text(draw_pos.0, draw_pos.1, h, text, color, 0.005);
+            gui.text(draw_pos.0, draw_pos.1, h, text, color);
         }
         // If the mouse button is not down but this button is both hot and active, it must have been clicked
         if !gui.mouse_down && gui.active_item == id && gui.hot_item == id {
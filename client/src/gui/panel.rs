@@ -0,0 +1,65 @@
+//! The windows of the Gui, like the inventory: a background with a title, that contains other elements.
+use super::Gui;
+use crate::ui::TextPart;
+use wgpu_glyph::ab_glyph::PxScale;
+
+/// Height of the title of a panel
+pub const TITLE_HEIGHT: i32 = 30;
+/// Space between the border of a panel and its content
+pub const PANEL_PADDING: i32 = 10;
+
+const BACKGROUND_COLOR: [f32; 4] = [0.1, 0.1, 0.1, 0.9];
+const TITLE_COLOR: [f32; 4] = [0.2, 0.2, 0.2, 1.0];
+
+/// A panel that was drawn, to place its content. The content is laid out from top to bottom.
+#[derive(Debug, Clone, Copy)]
+pub struct Panel {
+    x: i32,
+    /// Top of the next row of content
+    next_y: i32,
+}
+
+impl Panel {
+    /// Left of the content
+    pub fn content_x(&self) -> i32 {
+        self.x + PANEL_PADDING
+    }
+
+    /// Reserve a row of content of height `h`, returning its top
+    pub fn row(&mut self, h: i32) -> i32 {
+        let y = self.next_y;
+        self.next_y += h + PANEL_PADDING;
+        y
+    }
+}
+
+impl Gui {
+    /// Draw a panel of size `w` x `h`, content included, at the center of the window
+    pub fn panel(&mut self, title: &str, w: i32, h: i32, window_width: i32, window_height: i32) -> Panel {
+        let x = (window_width - w) / 2;
+        let y = (window_height - h) / 2;
+        self.rect(x, y, w, h, BACKGROUND_COLOR);
+        self.rect(x, y, w, TITLE_HEIGHT, TITLE_COLOR);
+        self.centered_text(
+            x as f32,
+            y as f32,
+            w as f32,
+            TITLE_HEIGHT as f32,
+            TextPart {
+                text: title.to_owned(),
+                font_size: PxScale::from(24.0),
+                color: [1.0, 1.0, 1.0, 1.0],
+                font: None,
+            },
+        );
+        Panel {
+            x,
+            next_y: y + TITLE_HEIGHT + PANEL_PADDING,
+        }
+    }
+}
+
+/// The height of a panel containing rows of content of the given heights
+pub fn panel_height(rows: &[i32]) -> i32 {
+    TITLE_HEIGHT + PANEL_PADDING + rows.iter().map(|h| h + PANEL_PADDING).sum::<i32>()
+}
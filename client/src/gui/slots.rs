@@ -0,0 +1,270 @@
+//! Grids of item slots, with drag-and-drop between the slots of every grid of the frame.
+//!
+//! A stack is dragged while the mouse button is down, and dropping it on another slot records a drop.
+//! Clicking a slot while shift is down records a shift-click, and a stack dragged with the right mouse button
+//! is split among the slots it went over when the button is released.
+//! The Gui doesn't modify the inventories: the screens read the interactions with `Gui::take_slot_interactions`.
+use super::Gui;
+use crate::ui::TextPart;
+use voxel_rs_common::inventory::{InventoryItem, ItemStack};
+use wgpu_glyph::ab_glyph::PxScale;
+
+/// Size of a slot
+pub const SLOT_SIZE: i32 = 40;
+/// Space between two slots
+pub const SLOT_SPACING: i32 = 4;
+
+const SLOT_COLOR: [f32; 4] = [0.3, 0.3, 0.3, 1.0];
+const HOVERED_SLOT_COLOR: [f32; 4] = [0.45, 0.45, 0.45, 1.0];
+const DRAGGED_SLOT_COLOR: [f32; 4] = [0.2, 0.2, 0.2, 1.0];
+
+/// A slot of a grid: the id of the grid and the index of the slot in the grid
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlotId {
+    pub grid: u32,
+    pub index: usize,
+}
+
+/// The stack that the mouse is dragging
+#[derive(Debug, Clone)]
+pub(super) struct DraggedStack {
+    from: SlotId,
+    stack: ItemStack,
+    label: String,
+    /// The slots that the stack went over if it's dragged with the right mouse button, to split it among them
+    split: Option<Vec<SlotId>>,
+}
+
+/// What the player did with the slots
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SlotInteraction {
+    /// A stack was dragged from a slot and dropped on another slot
+    Drop { from: SlotId, to: SlotId },
+    /// A slot was clicked while shift was down
+    ShiftClick(SlotId),
+    /// A stack was dragged with the right mouse button over other slots
+    Split { from: SlotId, to: Vec<SlotId> },
+}
+
+/// Size of a grid of `slots` slots, `columns` slots wide
+pub fn grid_size(slots: usize, columns: usize) -> (i32, i32) {
+    let rows = (slots + columns - 1) / columns;
+    let size = |n: usize| n as i32 * (SLOT_SIZE + SLOT_SPACING) - SLOT_SPACING;
+    (size(columns.min(slots)), size(rows))
+}
+
+impl Gui {
+    /// Draw a grid of slots with the top left corner at (`x`, `y`). `name` gives the name of an item, for its
+    /// label and for the tooltip. The ids of the grids must be different in a frame.
+    pub fn slot_grid(
+        &mut self,
+        grid: u32,
+        x: i32,
+        y: i32,
+        columns: usize,
+        slots: &[Option<ItemStack>],
+        name: &dyn Fn(InventoryItem) -> String,
+    ) {
+        for (index, stack) in slots.iter().enumerate() {
+            let id = SlotId { grid, index };
+            let slot_x = x + (index % columns) as i32 * (SLOT_SIZE + SLOT_SPACING);
+            let slot_y = y + (index / columns) as i32 * (SLOT_SIZE + SLOT_SPACING);
+            let hovered = self.is_mouse_inside(slot_x, slot_y, SLOT_SIZE, SLOT_SIZE);
+            if hovered {
+                self.update_slot_drag(id, stack, name);
+                if let (Some(stack), None) = (stack, &self.dragged) {
+                    self.tooltip(stack_tooltip(stack, name));
+                }
+            }
+            let dragged_from_here = self.dragged.as_ref().map(|dragged| dragged.from) == Some(id);
+            let split_here =
+                matches!(&self.dragged, Some(DraggedStack { split: Some(split), .. }) if split.contains(&id));
+            let color = if dragged_from_here {
+                DRAGGED_SLOT_COLOR
+            } else if hovered || split_here {
+                HOVERED_SLOT_COLOR
+            } else {
+                SLOT_COLOR
+            };
+            self.rect(slot_x, slot_y, SLOT_SIZE, SLOT_SIZE, color);
+            if let (Some(stack), false) = (stack, dragged_from_here) {
+                self.draw_stack(slot_x, slot_y, &stack_label(stack, name), stack.count);
+            }
+        }
+    }
+
+    /// Start dragging the stack of a hovered slot, drop the dragged stack on it or split the stack there
+    fn update_slot_drag(&mut self, id: SlotId, stack: &Option<ItemStack>, name: &dyn Fn(InventoryItem) -> String) {
+        if let Some(dragged) = &mut self.dragged {
+            match &mut dragged.split {
+                Some(split) => {
+                    // The stack is only split among the slots that can receive it, at least one item per slot
+                    let fits = match stack {
+                        Some(stack) => stack.item == dragged.stack.item,
+                        None => true,
+                    };
+                    let new_slot = dragged.from != id && !split.contains(&id);
+                    if fits && new_slot && (split.len() as u32) < dragged.stack.count {
+                        split.push(id);
+                    }
+                }
+                None => {
+                    if !self.mouse_down && dragged.from != id {
+                        self.slot_interactions.push(SlotInteraction::Drop { from: dragged.from, to: id });
+                    }
+                }
+            }
+        } else if let Some(stack) = stack {
+            let dragged = |split| DraggedStack {
+                from: id,
+                stack: *stack,
+                label: stack_label(stack, name),
+                split,
+            };
+            if self.mouse_down && self.active_item == 0 {
+                if self.shift_down {
+                    self.slot_interactions.push(SlotInteraction::ShiftClick(id));
+                } else {
+                    self.dragged = Some(dragged(None));
+                }
+                // The other elements can't become active while the mouse button is down
+                self.active_item = 1;
+            } else if self.right_mouse_clicked && !self.mouse_down {
+                self.dragged = Some(dragged(Some(Vec::new())));
+            }
+        }
+    }
+
+    /// Stop dragging the stack once its mouse button is released, on a slot or not
+    pub(super) fn end_slot_drag(&mut self) {
+        let released = match &self.dragged {
+            Some(DraggedStack { split: Some(_), .. }) => !self.right_mouse_down,
+            Some(_) => !self.mouse_down,
+            None => false,
+        };
+        if released {
+            if let Some(DraggedStack { from, split: Some(to), .. }) = self.dragged.take() {
+                if !to.is_empty() {
+                    self.slot_interactions.push(SlotInteraction::Split { from, to });
+                }
+            }
+        }
+    }
+
+    /// Draw the dragged stack under the mouse
+    pub(super) fn draw_dragged_stack(&mut self) {
+        if let Some(DraggedStack { stack, label, .. }) = self.dragged.clone() {
+            let (x, y) = (self.mouse_x - SLOT_SIZE / 2, self.mouse_y - SLOT_SIZE / 2);
+            self.rect(x, y, SLOT_SIZE, SLOT_SIZE, HOVERED_SLOT_COLOR);
+            self.draw_stack(x, y, &label, stack.count);
+        }
+    }
+
+    /// Draw the label and the size of a stack in a slot
+    fn draw_stack(&mut self, x: i32, y: i32, label: &str, count: u32) {
+        let text = |text: String, size: f32| TextPart {
+            text,
+            font_size: PxScale::from(size),
+            color: [1.0, 1.0, 1.0, 1.0],
+            font: None,
+        };
+        let (x, y, size) = (x as f32, y as f32, SLOT_SIZE as f32);
+        self.centered_text(x, y, size, size * 0.6, text(label.to_owned(), 14.0));
+        if count > 1 {
+            self.centered_text(x + size * 0.4, y + size * 0.55, size * 0.6, size * 0.45, text(count.to_string(), 14.0));
+        }
+    }
+
+    /// The interactions with the slots since the last call
+    pub fn take_slot_interactions(&mut self) -> Vec<SlotInteraction> {
+        std::mem::take(&mut self.slot_interactions)
+    }
+}
+
+/// The name of the item of a stack, its kind and the size of the stack.
+// TODO: show the durability once the items have one
+fn stack_tooltip(stack: &ItemStack, name: &dyn Fn(InventoryItem) -> String) -> String {
+    let kind = match stack.item {
+        InventoryItem::Block(_) => "Block",
+        InventoryItem::Item(_) => "Item",
+    };
+    format!("{}\n{} - {} in the stack", name(stack.item), kind, stack.count)
+}
+
+/// There are no item icons yet, so the slots show the beginning of the item names
+fn stack_label(stack: &ItemStack, name: &dyn Fn(InventoryItem) -> String) -> String {
+    name(stack.item).chars().take(4).collect::<String>().to_uppercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(gui: &mut Gui, slots: &[Option<ItemStack>]) {
+        gui.prepare();
+        gui.slot_grid(0, 0, 0, 9, slots, &|_| "stone".to_owned());
+        gui.finish();
+        gui.drain_primitives();
+    }
+
+    #[test]
+    fn test_drag_and_drop() {
+        let stack = ItemStack { item: InventoryItem::Block(1), count: 5 };
+        let slots = [Some(stack), None];
+        let mut gui = Gui::new();
+        let next_slot = SLOT_SIZE + SLOT_SPACING;
+
+        // Drag the stack to the next slot
+        gui.update_mouse_position(5, 5);
+        gui.update_mouse_button(true);
+        frame(&mut gui, &slots);
+        gui.update_mouse_position(next_slot + 5, 5);
+        frame(&mut gui, &slots);
+        gui.update_mouse_button(false);
+        frame(&mut gui, &slots);
+        let from = SlotId { grid: 0, index: 0 };
+        let to = SlotId { grid: 0, index: 1 };
+        assert_eq!(gui.take_slot_interactions(), vec![SlotInteraction::Drop { from, to }]);
+
+        // Dropping the stack outside of the grid does nothing
+        gui.update_mouse_position(5, 5);
+        gui.update_mouse_button(true);
+        frame(&mut gui, &slots);
+        gui.update_mouse_position(5, next_slot + 5);
+        gui.update_mouse_button(false);
+        frame(&mut gui, &slots);
+        assert!(gui.take_slot_interactions().is_empty());
+    }
+
+    #[test]
+    fn test_shift_click_and_split() {
+        let stack = ItemStack { item: InventoryItem::Block(1), count: 5 };
+        let other = ItemStack { item: InventoryItem::Block(2), count: 1 };
+        let slots = [Some(stack), None, Some(other), None];
+        let mut gui = Gui::new();
+        let slot = |index: usize| SlotId { grid: 0, index };
+        let slot_x = |index: i32| index * (SLOT_SIZE + SLOT_SPACING) + 5;
+
+        gui.update_shift(true);
+        gui.update_mouse_position(slot_x(0), 5);
+        gui.update_mouse_button(true);
+        frame(&mut gui, &slots);
+        gui.update_mouse_button(false);
+        frame(&mut gui, &slots);
+        gui.update_shift(false);
+        assert_eq!(gui.take_slot_interactions(), vec![SlotInteraction::ShiftClick(slot(0))]);
+
+        // The slot with another item is skipped
+        gui.update_right_mouse_button(true);
+        frame(&mut gui, &slots);
+        for index in 1..4 {
+            gui.update_mouse_position(slot_x(index), 5);
+            frame(&mut gui, &slots);
+        }
+        assert!(gui.take_slot_interactions().is_empty());
+        gui.update_right_mouse_button(false);
+        frame(&mut gui, &slots);
+        let split = SlotInteraction::Split { from: slot(0), to: vec![slot(1), slot(3)] };
+        assert_eq!(gui.take_slot_interactions(), vec![split]);
+    }
+}
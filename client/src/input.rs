@@ -1,6 +1,11 @@
+use crate::settings::Settings;
+use crate::touch::{TouchAction, TouchControls, TouchInput};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::time::{Duration, Instant};
 use voxel_rs_common::debug::send_debug_info;
 use voxel_rs_common::player::PlayerInput;
+use winit::dpi::LogicalPosition;
 use winit::event::{ElementState, KeyboardInput, ModifiersState, MouseButton};
 
 /// A helper struct to keep track of the yaw and pitch of a player
@@ -11,12 +16,10 @@ pub struct YawPitch {
 }
 
 impl YawPitch {
-    // TODO: Allow mouse inverting
+    /// Rotate the camera by the given yaw and pitch deltas, in degrees
     pub fn update_cursor(&mut self, dx: f64, dy: f64) {
-        // TODO: don't hardcode this
-        let mouse_speed: f64 = 0.2;
-        self.yaw -= mouse_speed * (dx as f64);
-        self.pitch -= mouse_speed * (dy as f64);
+        self.yaw -= dx;
+        self.pitch -= dy;
 
         // Ensure the yaw stays within [-180; 180]
         if self.yaw < -180.0 {
@@ -45,13 +48,49 @@ impl Default for YawPitch {
     }
 }
 
+/// A helper struct to apply the mouse settings to the mouse movement of every frame
+#[derive(Debug, Default)]
+pub struct MouseFilter {
+    pending: (f64, f64),
+    smoothed: (f64, f64),
+}
+
+impl MouseFilter {
+    /// Add some mouse movement to the current frame
+    pub fn add_motion(&mut self, delta: (f64, f64)) {
+        self.pending.0 += delta.0;
+        self.pending.1 += delta.1;
+    }
+
+    /// Get the camera rotation of the current frame in degrees, and start a new frame
+    pub fn extract_frame_rotation(&mut self, settings: &Settings) -> (f64, f64) {
+        let (dx, dy) = std::mem::replace(&mut self.pending, (0.0, 0.0));
+        let dy = if settings.invert_mouse { -dy } else { dy };
+        let smoothing = settings.mouse_smoothing.max(0.0).min(0.95);
+        self.smoothed = (
+            self.smoothed.0 * smoothing + dx * (1.0 - smoothing),
+            self.smoothed.1 * smoothing + dy * (1.0 - smoothing),
+        );
+        (
+            self.smoothed.0 * settings.mouse_sensitivity,
+            self.smoothed.1 * settings.mouse_sensitivity,
+        )
+    }
+}
+
 /// The state of the keyboard and mouse buttons.
 pub struct InputState {
     keys: HashMap<u32, ElementState>,
     mouse_buttons: HashMap<MouseButton, ElementState>,
     modifiers_state: ModifiersState,
+    touch_controls: TouchControls,
+    /// Direction of the left stick of the gamepad, with y going down
+    gamepad_movement: (f64, f64),
     flying: bool,             // TODO: reset this on game start
+    /// When `MOVE_UP` was last pressed, to toggle flight with a double jump
+    last_jump_press: Option<Instant>,
     pub enable_culling: bool, // TODO: don't put this here
+    pub enable_debug_camera: bool, // TODO: don't put this here
 }
 
 impl InputState {
@@ -60,20 +99,39 @@ impl InputState {
             keys: HashMap::new(),
             mouse_buttons: HashMap::new(),
             modifiers_state: ModifiersState::default(),
+            touch_controls: TouchControls::default(),
+            gamepad_movement: (0.0, 0.0),
             flying: true,
+            last_jump_press: None,
             enable_culling: true,
+            enable_debug_camera: false,
         }
     }
 
     /// Process a keyboard input, returning whether the state of the key changed or not
     pub fn process_keyboard_input(&mut self, input: KeyboardInput) -> bool {
-        let previous_state = self.keys.get(&input.scancode).cloned();
-        self.keys.insert(input.scancode, input.state);
+        self.process_key(input.scancode, input.state)
+    }
+
+    /// Process a change in the state of the key with the given scancode, returning whether the state of the key changed or not
+    pub fn process_key(&mut self, scancode: u32, state: ElementState) -> bool {
+        let previous_state = self.keys.get(&scancode).cloned();
+        self.keys.insert(scancode, state);
+        if scancode == MOVE_UP && state == ElementState::Pressed && previous_state != Some(ElementState::Pressed) {
+            let now = Instant::now();
+            match self.last_jump_press {
+                Some(last) if now - last < DOUBLE_JUMP_DELAY => {
+                    self.flying = !self.flying;
+                    self.last_jump_press = None;
+                }
+                _ => self.last_jump_press = Some(now),
+            }
+        }
         if let &Some(ElementState::Pressed) = &previous_state {
-            if input.scancode == TOGGLE_FLIGHT {
+            if scancode == TOGGLE_FLIGHT {
                 self.flying = !self.flying;
             }
-            if input.scancode == TOGGLE_CULLING {
+            if scancode == TOGGLE_CULLING {
                 self.enable_culling = !self.enable_culling;
                 send_debug_info(
                     "Render",
@@ -84,8 +142,11 @@ impl InputState {
                     ),
                 );
             }
+            if scancode == TOGGLE_DEBUG_CAMERA {
+                self.enable_debug_camera = !self.enable_debug_camera;
+            }
         }
-        previous_state != Some(input.state)
+        previous_state != Some(state)
     }
 
     /// Process a mouse input, returning whether the state of the button changed or not
@@ -99,12 +160,23 @@ impl InputState {
         previous_state != Some(state)
     }
 
+    /// Process a touch event of the touch controls, returning what it does, if anything
+    pub fn process_touch(&mut self, touch: TouchInput, window_width: u32) -> Option<TouchAction> {
+        self.touch_controls.process_touch(touch, window_width)
+    }
+
+    /// Set the direction of the left stick of the gamepad, with y going down
+    #[cfg_attr(not(feature = "gamepad"), allow(dead_code))]
+    pub fn set_gamepad_movement(&mut self, movement: (f64, f64)) {
+        self.gamepad_movement = movement;
+    }
+
     /// Update the modifiers
     pub fn set_modifiers_state(&mut self, modifiers_state: ModifiersState) {
         self.modifiers_state = modifiers_state;
     }
 
-    pub fn _get_modifiers_state(&self) -> ModifiersState {
+    pub fn get_modifiers_state(&self) -> ModifiersState {
         self.modifiers_state
     }
 
@@ -119,6 +191,8 @@ impl InputState {
         self.keys.clear();
         self.mouse_buttons.clear();
         self.modifiers_state = ModifiersState::default();
+        self.touch_controls.clear();
+        self.gamepad_movement = (0.0, 0.0);
     }
 
     fn is_key_pressed(&self, scancode: u32) -> bool {
@@ -130,20 +204,89 @@ impl InputState {
 
     // TODO: add configuration for this
     pub fn get_physics_input(&self, yaw_pitch: YawPitch, allow_movement: bool) -> PlayerInput {
+        // The virtual joystick and the gamepad stick move in 8 directions
+        let (jx, jy) = self.touch_controls.get_joystick_offset();
+        let joystick_length = (jx * jx + jy * jy).sqrt().max(1.0);
+        let (jx, jy) = (jx / joystick_length, jy / joystick_length);
+        let (gx, gy) = self.gamepad_movement;
+        const THRESHOLD: f64 = 0.38; // sin(22.5°)
         PlayerInput {
-            key_move_forward: allow_movement && self.is_key_pressed(MOVE_FORWARD),
-            key_move_left: allow_movement && self.is_key_pressed(MOVE_LEFT),
-            key_move_backward: allow_movement && self.is_key_pressed(MOVE_BACKWARD),
-            key_move_right: allow_movement && self.is_key_pressed(MOVE_RIGHT),
+            key_move_forward: allow_movement
+                && (self.is_key_pressed(MOVE_FORWARD) || jy < -THRESHOLD || gy < -THRESHOLD),
+            key_move_left: allow_movement && (self.is_key_pressed(MOVE_LEFT) || jx < -THRESHOLD || gx < -THRESHOLD),
+            key_move_backward: allow_movement
+                && (self.is_key_pressed(MOVE_BACKWARD) || jy > THRESHOLD || gy > THRESHOLD),
+            key_move_right: allow_movement && (self.is_key_pressed(MOVE_RIGHT) || jx > THRESHOLD || gx > THRESHOLD),
             key_move_up: allow_movement && self.is_key_pressed(MOVE_UP),
             key_move_down: allow_movement && self.is_key_pressed(MOVE_DOWN),
             yaw: yaw_pitch.yaw,
             pitch: yaw_pitch.pitch,
             flying: self.flying,
+            noclip: false,
         }
     }
 }
 
+/// Maximum delay between the two presses of `MOVE_UP` that toggle flight
+const DOUBLE_JUMP_DELAY: Duration = Duration::from_millis(300);
+
+/// A gamepad button that can be bound to an action, named after the positions on a standard gamepad
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GamepadButton {
+    South,
+    East,
+    North,
+    West,
+    LeftBumper,
+    RightBumper,
+    LeftTrigger,
+    RightTrigger,
+    Select,
+    Start,
+    LeftStick,
+    RightStick,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+/// What a gamepad button does.
+/// Every action presses the key or the mouse button that does the same thing,
+/// so that the states handle the gamepad like the keyboard and the mouse.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GamepadAction {
+    MoveUp,
+    MoveDown,
+    Break,
+    Place,
+    OpenMenu,
+    ToggleCrafting,
+    ToggleFlight,
+    ToggleCameraMode,
+    TakeScreenshot,
+}
+
+/// A gamepad button and the action it does
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct GamepadBinding {
+    pub button: GamepadButton,
+    pub action: GamepadAction,
+}
+
+/// What the gamepad did during a frame, besides pressing keys and mouse buttons
+#[derive(Debug, Default)]
+pub struct GamepadFrame {
+    /// Camera rotation, in mouse movement units
+    pub look: Option<(f64, f64)>,
+    /// New position of the cursor in the menus
+    pub cursor: Option<LogicalPosition<f64>>,
+    /// Scrolling in the menus in logical pixels, positive when scrolling up
+    pub scroll: Option<f32>,
+}
+
+/// Open or close the menu (escape)
+pub const OPEN_MENU: u32 = 1;
 pub const MOVE_FORWARD: u32 = 17;
 pub const MOVE_LEFT: u32 = 30;
 pub const MOVE_BACKWARD: u32 = 31;
@@ -152,3 +295,26 @@ pub const MOVE_UP: u32 = 57;
 pub const MOVE_DOWN: u32 = 42;
 pub const TOGGLE_FLIGHT: u32 = 33;
 pub const TOGGLE_CULLING: u32 = 46;
+pub const TOGGLE_DEBUG_CAMERA: u32 = 47;
+pub const TOGGLE_CAMERA_MODE: u32 = 63;
+/// Toggle the graphs of `crate::gui::debug::PERF_GRAPHS`, in order (F6, F7 and F4)
+pub const TOGGLE_PERF_GRAPHS: [u32; 3] = [64, 65, 62];
+pub const TOGGLE_COLLISION_DEBUG: u32 = 66;
+pub const TOGGLE_HELD_LIGHT: u32 = 67;
+/// Cycle the weather locally, until the server sends a new one
+pub const CYCLE_WEATHER: u32 = 68;
+/// Ask the server to switch between the creative and survival gamemodes
+pub const TOGGLE_GAMEMODE: u32 = 69;
+/// Open or close the crafting window
+pub const TOGGLE_CRAFTING: u32 = 18;
+/// Open or close the inventory (I)
+pub const TOGGLE_INVENTORY: u32 = 23;
+/// Hold this key (F3) and press one of the keys below to toggle a debug visualizer
+pub const DEBUG_MODIFIER: u32 = 61;
+pub const TOGGLE_CHUNK_BORDERS: u32 = 34;
+pub const TOGGLE_HITBOXES: u32 = 48;
+/// Throw away the chunk meshes and mesh every chunk again, to get rid of stale geometry
+pub const RELOAD_CHUNKS: u32 = 30;
+/// Save the next frame to the screenshots folder (F2)
+pub const TAKE_SCREENSHOT: u32 = 60;
+
@@ -1,18 +1,35 @@
-use anyhow::Result;
-use log::{error, info};
+use anyhow::{Context, Result};
+use log::info;
 use std::path::Path;
-use voxel_rs_common::network::dummy;
-use voxel_rs_server::launch_server;
 
+mod analytics;
+#[cfg(feature = "audio")]
+mod audio;
+#[cfg(not(feature = "audio"))]
+#[path = "audio/silent.rs"]
+mod audio;
+mod breaking;
+mod debug_render;
 mod fps;
+#[cfg(feature = "gamepad")]
+mod gamepad;
+#[cfg(not(feature = "gamepad"))]
+#[path = "gamepad/disabled.rs"]
+mod gamepad;
 mod gui;
 mod input;
-//mod mainmenu; TODO: fix this
+mod mainmenu;
+mod panorama;
+mod particles;
 mod render;
+mod replay;
 mod settings;
 mod singleplayer;
+mod swing;
 mod texture;
+mod touch;
 mod ui;
+mod weather;
 mod window;
 mod world;
 
@@ -20,30 +37,31 @@ fn main() -> Result<()> {
     env_logger::init();
 
     info!("Starting up...");
-    let config_folder = Path::new("config");
-    let config_file = Path::new("config/settings.toml");
+    let config_folder = Path::new(settings::SETTINGS_FOLDER);
+    let config_file = Path::new(settings::SETTINGS_FILE);
     let settings = settings::load_settings(&config_folder, &config_file)?;
     info!("Current settings: {:?}", settings);
 
-    let (client, server) = dummy::new();
+    let (input_recorder, input_player) = replay::from_env()?;
+    if replay::headless() {
+        let input_player = input_player.context("Running headless needs an input script")?;
+        return window::run_headless(settings, mainmenu::MainMenu::new_factory(), input_player);
+    }
 
-    std::thread::spawn(move || {
-        if let Err(e) = launch_server(Box::new(server)) {
-            // TODO: rewrite this error reporting
-            error!(
-                "Error happened in the server code: {}\nPrinting chain:\n{}",
-                e,
-                e.chain()
-                    .enumerate()
-                    .map(|(i, e)| format!("{}: {}", i, e))
-                    .collect::<Vec<_>>()
-                    .join("\n")
-            );
-        }
-    });
+    let analytics = if settings.session_analytics {
+        Some(analytics::SessionAnalytics::start(
+            config_folder.join("session_stats.toml"),
+            settings.render_distance,
+        ))
+    } else {
+        None
+    };
 
     window::open_window(
         settings,
-        Box::new(singleplayer::SinglePlayer::new_factory(Box::new(client))),
+        analytics,
+        input_recorder,
+        input_player,
+        mainmenu::MainMenu::new_factory(),
     )
 }
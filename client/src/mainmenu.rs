@@ -1,182 +1,404 @@
 use anyhow::Result;
-use log::info;
+use log::{info, warn};
+use quint::{wt, ScrollView, Size, Style, TextInputEvent, TextInputState, WidgetTree};
+use voxel_rs_common::network::{
+    remote::{resolve_address, RemoteClient},
+    DisconnectReason,
+};
+use voxel_rs_server::save::{self, WorldMetadata};
+use wgpu_glyph::ab_glyph::PxScale;
 
 use crate::{
-    fps::FpsCounter,
+    gui::Gui,
     input::InputState,
+    panorama::Panorama,
+    render::UiRenderer,
     settings::Settings,
     singleplayer::SinglePlayer,
     ui::{
-        renderer::{self, UiRenderer},
-        widgets,
+        quint_element_state, quint_mouse_button,
+        widgets::{Button, Label, TextInput, WithStyle},
+        PrimitiveBuffer, TextPart, SCROLLBAR_SPACE,
     },
-    window::{Gfx, State, StateTransition, WindowData, WindowFlags},
+    window::{State, StateFactory, StateTransition, WindowBuffers, WindowData, WindowFlags},
 };
 
+/// The screens of the main menu
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Screen {
+    Main,
+    WorldSelection,
+    Multiplayer,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Message {
+    OpenWorldSelection,
+    OpenMultiplayer,
+    Back,
+    PlayWorld(usize),
+    ScrollWorlds(f32),
+    CreateWorld,
+    EditWorldName(TextInputEvent),
+    EditWorldSeed(TextInputEvent),
+    Connect,
+    EditServerAddress(TextInputEvent),
+    ExitGame,
+}
+
 /// State of the main menu
 pub struct MainMenu {
-    fps_counter: FpsCounter,
-    ui: self::Ui,
+    ui: quint::Ui<PrimitiveBuffer, Message>,
     ui_renderer: UiRenderer,
+    panorama: Panorama,
+    gui: Gui,
+    messages: Vec<Message>,
+    screen: Screen,
+    worlds: Vec<WorldMetadata>,
+    /// Scroll offset of the list of worlds
+    worlds_scroll: f32,
+    /// Name of the next created world, a default name is used if it's empty
+    world_name: TextInputState,
+    /// Seed of the next created world, a random seed is used if it's empty
+    world_seed: TextInputState,
+    server_address: TextInputState,
+    status: Option<String>,
+    next_state: Option<StateFactory>,
+    should_exit: bool,
 }
 
 impl MainMenu {
-    pub fn new(_settings: &mut Settings, gfx: &mut Gfx) -> Result<Box<dyn State>> {
+    pub fn new_factory() -> StateFactory {
+        Box::new(move |settings, device| Self::new(settings, device))
+    }
+
+    /// Go back to the main menu after being disconnected from the server, showing the reason
+    pub fn new_disconnected_factory(reason: DisconnectReason) -> StateFactory {
+        Box::new(move |_settings, device| Self::new_disconnected(device, &reason))
+    }
+
+    pub fn new(
+        _settings: &mut Settings,
+        device: &mut wgpu::Device,
+    ) -> Result<(Box<dyn State>, wgpu::CommandBuffer)> {
+        Self::with_status(device, None)
+    }
+
+    pub fn new_disconnected(
+        device: &mut wgpu::Device,
+        reason: &DisconnectReason,
+    ) -> Result<(Box<dyn State>, wgpu::CommandBuffer)> {
+        Self::with_status(device, Some(format!("Disconnected from the server ({})", reason)))
+    }
+
+    fn with_status(
+        device: &mut wgpu::Device,
+        status: Option<String>,
+    ) -> Result<(Box<dyn State>, wgpu::CommandBuffer)> {
         info!("Creating main menu");
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        let panorama = Panorama::new(device, &mut encoder);
+
+        Ok((
+            Box::new(Self {
+                ui: quint::Ui::new(),
+                ui_renderer: UiRenderer::new(device),
+                panorama,
+                gui: Gui::new(),
+                messages: Vec::new(),
+                screen: Screen::Main,
+                worlds: Vec::new(),
+                worlds_scroll: 0.0,
+                world_name: TextInputState::default(),
+                world_seed: TextInputState::default(),
+                server_address: TextInputState::default(),
+                status,
+                next_state: None,
+                should_exit: false,
+            }),
+            encoder.finish(),
+        ))
+    }
 
-        Ok(Box::new(Self {
-            fps_counter: FpsCounter::new(),
-            ui: self::Ui::new(),
-            ui_renderer: UiRenderer::new(gfx)?,
-        }))
+    fn process_messages(&mut self, settings: &mut Settings) {
+        for message in std::mem::replace(&mut self.messages, Vec::new()) {
+            match message {
+                Message::OpenWorldSelection => {
+                    self.screen = Screen::WorldSelection;
+                    self.status = None;
+                    self.worlds_scroll = 0.0;
+                    self.worlds = save::list_worlds().unwrap_or_else(|e| {
+                        warn!("Failed to list saved worlds ({:?})", e);
+                        Vec::new()
+                    });
+                }
+                Message::OpenMultiplayer => {
+                    self.screen = Screen::Multiplayer;
+                    self.status = None;
+                    self.server_address = TextInputState::new(settings.server_address.clone());
+                }
+                Message::Back => {
+                    self.screen = Screen::Main;
+                    self.status = None;
+                }
+                Message::PlayWorld(index) => {
+                    let world = self.worlds[index].clone();
+                    self.next_state = Some(SinglePlayer::new_local_factory(world));
+                }
+                Message::ScrollWorlds(offset) => self.worlds_scroll = offset,
+                Message::CreateWorld => match self.create_world() {
+                    Ok(world) => self.next_state = Some(SinglePlayer::new_local_factory(world)),
+                    Err(e) => self.status = Some(format!("Failed to create world: {}", e)),
+                },
+                Message::EditWorldName(event) => {
+                    if self.world_name.apply(event) {
+                        self.messages.push(Message::CreateWorld);
+                    }
+                }
+                Message::EditWorldSeed(event) => {
+                    if self.world_seed.apply(event) {
+                        self.messages.push(Message::CreateWorld);
+                    }
+                }
+                Message::Connect => {
+                    settings.server_address = self.server_address.text().trim().to_owned();
+                    match resolve_address(&settings.server_address).and_then(RemoteClient::connect) {
+                        Ok(client) => {
+                            info!("Connecting to {}", settings.server_address);
+                            self.next_state = Some(SinglePlayer::new_factory(Box::new(client)));
+                        }
+                        Err(e) => self.status = Some(format!("Can't connect to {}: {}", settings.server_address, e)),
+                    }
+                }
+                Message::EditServerAddress(event) => {
+                    if self.server_address.apply(event) {
+                        self.messages.push(Message::Connect);
+                    }
+                }
+                Message::ExitGame => self.should_exit = true,
+            }
+        }
+    }
+
+    /// Create a new world with the typed seed or a random seed, and the typed name or a unique default name
+    fn create_world(&self) -> Result<WorldMetadata> {
+        let mut index = self.worlds.len() + 1;
+        let typed_name = self.world_name.text().trim();
+        let name = if !typed_name.is_empty() {
+            typed_name.to_owned()
+        } else {
+            loop {
+                let name = format!("World {}", index);
+                if self.worlds.iter().all(|world| world.name != name) {
+                    break name;
+                }
+                index += 1;
+            }
+        };
+        let seed = save::seed_from_text(self.world_seed.text()).unwrap_or_else(rand::random);
+        // The name becomes the name of the world folder, `create_world` refuses separators, dots and absolute paths
+        save::create_world(name, seed)
+    }
+
+    fn rebuild_ui(&mut self, settings: &Settings, data: &WindowData) {
+        let scale = settings.get_ui_scale();
+        let text = |text: String, size: f32| {
+            vec![TextPart {
+                text,
+                font_size: PxScale::from(size * scale),
+                color: [1.0, 1.0, 1.0, 1.0],
+                font: Some("arcade".to_owned()),
+            }]
+        };
+        let button_height = 70.0 * scale;
+        let button = |label: String, message: Message| {
+            wt! {
+                Button {
+                    text: text(label, 40.0),
+                    message,
+                    style: Style::default().absolute_size(600.0 * scale, button_height).no_shrink(),
+                },
+            }
+        };
+        let text_input = |state: &TextInputState, placeholder: &str, on_edit: fn(TextInputEvent) -> Message| {
+            wt! {
+                TextInput {
+                    state: state.clone(),
+                    placeholder: placeholder.to_owned(),
+                    font_size: 20.0 * scale,
+                    on_edit,
+                    style: Style::default().absolute_size(600.0 * scale, 50.0 * scale),
+                },
+            }
+        };
+        let label = |label: String| {
+            wt! {
+                Label {
+                    text: text(label, 25.0),
+                    style: Style::default().absolute_size(1000.0 * scale, 40.0 * scale),
+                },
+            }
+        };
+
+        let mut widgets = Vec::new();
+        match self.screen {
+            Screen::Main => {
+                widgets.push(label("VOXEL-RS".to_owned()));
+                widgets.push(button("SINGLEPLAYER".to_owned(), Message::OpenWorldSelection));
+                widgets.push(button("MULTIPLAYER".to_owned(), Message::OpenMultiplayer));
+                widgets.push(button("EXIT".to_owned(), Message::ExitGame));
+            }
+            Screen::WorldSelection => {
+                widgets.push(label("SELECT A WORLD".to_owned()));
+                let world_buttons: Vec<_> = self
+                    .worlds
+                    .iter()
+                    .enumerate()
+                    .map(|(i, world)| button(world.name.to_uppercase(), Message::PlayWorld(i)))
+                    .collect();
+                if !world_buttons.is_empty() {
+                    // The list is scrolled if the worlds don't fit above the other widgets
+                    let content_height = world_buttons.len() as f32 * button_height;
+                    let window_height = data.logical_window_size.height as f32;
+                    let list_height = content_height.min(window_height - 7.0 * button_height).max(button_height);
+                    widgets.push(WidgetTree::new(
+                        Box::new(ScrollView {
+                            offset: self.worlds_scroll,
+                            content_height,
+                            on_scroll: Message::ScrollWorlds,
+                            style: Style::default()
+                                .absolute_size(600.0 * scale + SCROLLBAR_SPACE, list_height)
+                                .vertical(),
+                            scrollbar_color: [1.0, 1.0, 1.0, 0.8],
+                        }),
+                        world_buttons,
+                    ));
+                }
+                widgets.push(text_input(&self.world_name, "World name", Message::EditWorldName));
+                widgets.push(text_input(&self.world_seed, "Seed", Message::EditWorldSeed));
+                widgets.push(button("NEW WORLD".to_owned(), Message::CreateWorld));
+                widgets.push(button("BACK".to_owned(), Message::Back));
+            }
+            Screen::Multiplayer => {
+                widgets.push(label("SERVER ADDRESS".to_owned()));
+                widgets.push(text_input(&self.server_address, "Address", Message::EditServerAddress));
+                widgets.push(button("CONNECT".to_owned(), Message::Connect));
+                widgets.push(button("BACK".to_owned(), Message::Back));
+            }
+        }
+        if let Some(status) = self.status.clone() {
+            widgets.push(label(status));
+        }
+
+        let layer = WidgetTree::new(
+            Box::new(WithStyle {
+                style: Style::default()
+                    .percent_size(1.0, 1.0)
+                    .center_cross()
+                    .center_main()
+                    .vertical(),
+            }),
+            widgets,
+        );
+        self.ui.rebuild(
+            vec![layer],
+            Size {
+                width: data.logical_window_size.width as f32,
+                height: data.logical_window_size.height as f32,
+            },
+        );
     }
 }
 
 impl State for MainMenu {
-    fn update(&mut self, _: &mut Settings, _: &InputState, _: &WindowData, flags: &mut WindowFlags, _: f64, _: &mut Gfx) -> Result<StateTransition> {
-        flags.hide_and_center_cursor = false;
+    fn update(
+        &mut self,
+        settings: &mut Settings,
+        _input_state: &InputState,
+        _data: &WindowData,
+        flags: &mut WindowFlags,
+        _seconds_delta: f64,
+        _device: &mut wgpu::Device,
+    ) -> Result<StateTransition> {
+        flags.grab_cursor = false;
+        self.process_messages(settings);
 
-        if self.ui.should_exit {
+        if self.should_exit {
             Ok(StateTransition::CloseWindow)
-        } else if self.ui.should_start_single_player {
-            Ok(StateTransition::ReplaceCurrent(Box::new(SinglePlayer::new)))
+        } else if let Some(next_state) = self.next_state.take() {
+            Ok(StateTransition::ReplaceCurrent(next_state))
         } else {
             Ok(StateTransition::KeepCurrent)
         }
     }
 
-    fn render(&mut self, _: &Settings, gfx: &mut Gfx, data: &WindowData) -> Result<StateTransition> {
-        use gfx::traits::Device;
-
-        self.fps_counter.add_frame();
-
-        // Clear buffers
-        gfx.encoder
-            .clear(&gfx.color_buffer, crate::window::CLEAR_COLOR);
-        gfx.encoder
-            .clear_depth(&gfx.depth_buffer, crate::window::CLEAR_DEPTH);
-        // Rebuild ui
-        self.ui.rebuild(self.fps_counter.fps(), data);
-        self.ui_renderer.render(gfx, data, &self.ui.ui)?;
-        // Flush and swap buffers
-        gfx.encoder.flush(&mut gfx.device);
-        gfx.context.swap_buffers()?;
-        gfx.device.cleanup();
-
-        Ok(StateTransition::KeepCurrent)
-    }
+    fn render<'a>(
+        &mut self,
+        settings: &Settings,
+        buffers: WindowBuffers<'a>,
+        device: &mut wgpu::Device,
+        queue: &wgpu::Queue,
+        data: &WindowData,
+        _input_state: &InputState,
+    ) -> Result<(StateTransition, wgpu::CommandBuffer)> {
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
 
-    fn handle_mouse_motion(&mut self, _: &Settings, _: (f64, f64)) {}
+        // Draw the panorama behind the UI, or a flat color until it is ready
+        if self.panorama.render(settings, device, queue, &mut encoder, buffers, data) {
+            crate::render::clear_depth(&mut encoder, buffers);
+        } else {
+            crate::render::clear_color_and_depth(&mut encoder, buffers);
+        }
 
-    fn handle_cursor_movement(&mut self, logical_position: glutin::dpi::LogicalPosition) {
-        self.ui.cursor_moved(logical_position);
-    }
+        self.rebuild_ui(settings, data);
+        self.gui.prepare();
+        self.gui.finish();
+        self.ui_renderer.render(
+            buffers,
+            device,
+            &mut encoder,
+            data,
+            &self.ui,
+            &mut self.gui,
+            false,
+        );
 
-    fn handle_mouse_state_changes(&mut self, changes: Vec<(glutin::MouseButton, glutin::ElementState)>) {
-        self.ui.handle_mouse_state_changes(changes);
+        Ok((StateTransition::KeepCurrent, encoder.finish()))
     }
 
-    fn handle_key_state_changes(&mut self, _: Vec<(u32, glutin::ElementState)>) {}
-}
-
-#[derive(Debug, Clone, Copy)]
-enum UiMessage {
-    StartSinglePlayer,
-    ExitGame,
-}
-
-struct Ui {
-    pub(self) ui: quint::Ui<renderer::PrimitiveBuffer, UiMessage>,
-    messages: Vec<UiMessage>,
-    pub(self) should_exit: bool,
-    pub(self) should_start_single_player: bool,
-}
+    fn handle_mouse_motion(&mut self, _settings: &Settings, _delta: (f64, f64)) {}
 
-impl Ui {
-    pub fn new() -> Self {
-        Self {
-            ui: quint::Ui::new(),
-            messages: Vec::new(),
-            should_exit: false,
-            should_start_single_player: false,
-        }
+    fn handle_cursor_movement(&mut self, logical_position: winit::dpi::LogicalPosition<f64>) {
+        let position = quint::Position {
+            x: logical_position.x as f32,
+            y: logical_position.y as f32,
+        };
+        self.messages.extend(self.ui.update(vec![quint::Event::CursorMoved(position)]));
     }
 
-    pub fn cursor_moved(&mut self, p: glutin::dpi::LogicalPosition) {
-        self.ui.set_cursor_position(quint::Position {
-            x: p.x as f32,
-            y: p.y as f32,
-        });
+    fn handle_mouse_wheel(&mut self, delta: f32) {
+        self.messages.extend(self.ui.update(vec![quint::Event::MouseWheel { delta }]));
     }
 
-    pub fn handle_mouse_state_changes(
+    fn handle_mouse_state_changes(
         &mut self,
-        changes: Vec<(glutin::MouseButton, glutin::ElementState)>,
+        changes: Vec<(winit::event::MouseButton, winit::event::ElementState)>,
     ) {
         let changes = changes
             .into_iter()
             .map(|(button, state)| quint::Event::MouseInput {
-                button: crate::ui::quint_mouse_button(button),
-                state: crate::ui::quint_element_state(state),
+                button: quint_mouse_button(button),
+                state: quint_element_state(state),
             })
             .collect();
         self.messages.extend(self.ui.update(changes));
     }
 
-    pub fn rebuild(&mut self, _fps: usize, data: &WindowData) {
-        use quint::WidgetTree;
-
-        self.update();
+    fn handle_key_state_changes(&mut self, _changes: Vec<(u32, winit::event::ElementState)>) {}
 
-        let mut menu_button_count = 0;
-        let mut menu_button = |text: &'static str, message| {
-
-            menu_button_count += 1;
-            quint::wt! {
-                widgets::Button {
-                    text: text.to_owned(),
-                    font_size: gfx_glyph::Scale::uniform(40.0),
-                    message,
-                    style: quint::Style::default().absolute_size(400.0, 100.0),
-                },
-            }
-        };
-
-        let buttons = vec![
-            menu_button("Start Singleplayer Game", UiMessage::StartSinglePlayer),
-            menu_button("Exit Game", UiMessage::ExitGame),
-        ];
-
-        let menu_layer = WidgetTree::new(
-            Box::new(widgets::WithStyle {
-                style: quint::Style::default()
-                    .percent_size(1.0, 1.0)
-                    .center_cross()
-                    .center_main()
-                    .vertical(),
-            }),
-            buttons,
-        );
-
-        let (win_w, win_h) = (
-            data.logical_window_size.width,
-            data.logical_window_size.height,
-        );
-        self.ui.rebuild(
-            vec![menu_layer],
-            quint::Size {
-                width: win_w as f32,
-                height: win_h as f32,
-            },
-        );
+    fn handle_text_input(&mut self, event: quint::Event) {
+        self.messages.extend(self.ui.update(vec![event]));
     }
-
-    pub fn update(&mut self) {
-        for message in self.messages.drain(..) {
-            match message {
-                UiMessage::StartSinglePlayer => self.should_start_single_player = true,
-                UiMessage::ExitGame => self.should_exit = true,
-            }
-        }
-    }
-}
\ No newline at end of file
+}
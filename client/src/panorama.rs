@@ -0,0 +1,183 @@
+//! The background of the main menu: a slowly rotating camera over a fixed set of generated chunks.
+//!
+//! The chunks are generated with a fixed seed in a background thread, so the menu is usable right away and the
+//! panorama fills in as the chunks arrive. The world is rendered with the same renderer as the game, without a server.
+use log::warn;
+use nalgebra::Vector3;
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Arc;
+use std::time::Instant;
+use voxel_rs_common::{
+    data::{load_data, Data},
+    player::RenderDistance,
+    time::TimeOfDay,
+    world::{BlockPos, Chunk, ChunkPos, LightChunk, WorldGenerator},
+    worldgen::DefaultWorldGenerator,
+};
+
+use crate::input::YawPitch;
+use crate::render::{ColorGrading, Frustum, GpuTimer, Tonemapping, Viewport, WorldRenderer};
+use crate::settings::Settings;
+use crate::window::{WindowBuffers, WindowData};
+use crate::world::World;
+
+const DATA_FOLDER: &str = "data";
+/// Seed of the generated chunks, chosen for the view
+const PANORAMA_SEED: i32 = 1061;
+/// Number of chunks generated around the center along x and z
+const HORIZONTAL_RADIUS: i64 = 3;
+/// Number of chunks generated below and above the center
+const VERTICAL_RADIUS: i64 = 1;
+/// Height of the camera above the spawn point, in blocks
+const CAMERA_HEIGHT: f64 = 20.0;
+const CAMERA_PITCH: f64 = -15.0;
+/// Rotation speed of the camera, in degrees per second
+const ROTATION_SPEED: f64 = 3.0;
+
+/// What the generation thread sends to the panorama
+enum PanoramaUpdate {
+    /// The game data, and the spawn point of the generated world
+    Data(Box<Data>, BlockPos),
+    Chunk(Chunk),
+}
+
+/// The rotating world behind the main menu
+pub struct Panorama {
+    updates: Receiver<PanoramaUpdate>,
+    /// `None` until the game data is loaded
+    world: Option<World>,
+    tonemapping: Tonemapping,
+    /// Position the camera rotates around
+    center: Vector3<f64>,
+    center_chunk: ChunkPos,
+    start_time: Instant,
+}
+
+impl Panorama {
+    pub fn new(device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) -> Self {
+        let (sender, updates) = channel();
+        std::thread::spawn(move || {
+            let data = match load_data(DATA_FOLDER.into(), &[]) {
+                Ok(data) => data,
+                Err(e) => {
+                    warn!("Failed to load the game data of the main menu panorama ({:?})", e);
+                    return;
+                }
+            };
+            let blocks = data.blocks.clone();
+            let world_generator = DefaultWorldGenerator::new(&data.blocks, &data.structures, PANORAMA_SEED);
+            let spawn = world_generator.spawn_point();
+            if sender.send(PanoramaUpdate::Data(Box::new(data), spawn)).is_err() {
+                return;
+            }
+
+            // Generate the closest chunks first
+            let center_chunk = spawn.containing_chunk_pos();
+            let mut positions = Vec::new();
+            for i in -HORIZONTAL_RADIUS..=HORIZONTAL_RADIUS {
+                for j in -VERTICAL_RADIUS..=VERTICAL_RADIUS {
+                    for k in -HORIZONTAL_RADIUS..=HORIZONTAL_RADIUS {
+                        positions.push(center_chunk.offset(i, j, k));
+                    }
+                }
+            }
+            positions.sort_by_key(|pos| pos.squared_euclidian_distance(center_chunk));
+            for pos in positions {
+                // Stop when the main menu is closed
+                if sender.send(PanoramaUpdate::Chunk(world_generator.generate_chunk(pos, &blocks))).is_err() {
+                    return;
+                }
+            }
+        });
+
+        Self {
+            updates,
+            world: None,
+            tonemapping: Tonemapping::new(device, encoder, None),
+            center: Vector3::zeros(),
+            center_chunk: ChunkPos::from((0, 0, 0)),
+            start_time: Instant::now(),
+        }
+    }
+
+    /// Add the data and the chunks sent by the generation thread
+    fn receive_updates(&mut self, settings: &Settings, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
+        while let Ok(update) = self.updates.try_recv() {
+            match update {
+                PanoramaUpdate::Data(data, spawn) => {
+                    let data = *data;
+                    let mut world = World::new(
+                        data.meshes,
+                        WorldRenderer::new(
+                            device,
+                            encoder,
+                            data.texture_atlas,
+                            &data.models,
+                            settings.shadows.get_resolution(),
+                            &settings.tuning,
+                        ),
+                    );
+                    world.set_sun_direction(TimeOfDay::default().sun_direction());
+                    self.world = Some(world);
+                    self.center = Vector3::new(spawn.px as f64, spawn.py as f64 + CAMERA_HEIGHT, spawn.pz as f64);
+                    self.center_chunk = spawn.containing_chunk_pos();
+                }
+                PanoramaUpdate::Chunk(chunk) => {
+                    if let Some(world) = self.world.as_mut() {
+                        // There is no light computation: every block is fully lit
+                        let light_chunk = LightChunk::new(chunk.pos);
+                        world.add_chunk(Arc::new(chunk), Arc::new(light_chunk), Arc::new(HashMap::new()));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Render the panorama to `buffers`. Return `false` if the panorama is not ready, in which case nothing is drawn.
+    pub fn render(
+        &mut self,
+        settings: &Settings,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        buffers: WindowBuffers,
+        data: &WindowData,
+    ) -> bool {
+        self.receive_updates(settings, device, encoder);
+        let world = match self.world.as_mut() {
+            Some(world) => world,
+            None => return false,
+        };
+        world.set_shadow_cascades(settings.shadows.get_cascades());
+
+        let render_distance = RenderDistance {
+            x_max: HORIZONTAL_RADIUS as u64,
+            x_min: HORIZONTAL_RADIUS as u64,
+            y_max: VERTICAL_RADIUS as u64 + 1,
+            y_min: VERTICAL_RADIUS as u64 + 1,
+            z_max: HORIZONTAL_RADIUS as u64,
+            z_min: HORIZONTAL_RADIUS as u64,
+        };
+        world.enqueue_chunks_for_meshing(self.center_chunk, &render_distance);
+        world.set_distance_fog(settings.fog.distance_fog_range(&render_distance));
+
+        let yaw = (self.start_time.elapsed().as_secs_f64() * ROTATION_SPEED) % 360.0 - 180.0;
+        let frustum = Frustum::new(self.center, YawPitch { yaw, pitch: CAMERA_PITCH }, settings.fov);
+
+        self.tonemapping.prepare(device, data);
+        let world_buffers = self.tonemapping.scene_buffers(buffers);
+        crate::render::clear_color_and_depth(encoder, world_buffers);
+        // The render passes of the panorama are not timed
+        let cameras = [(Viewport::full(data), frustum)];
+        world.render_chunks(device, queue, &mut GpuTimer::default(), encoder, world_buffers, &cameras, true, None, &[]);
+        self.tonemapping.apply(
+            device,
+            encoder,
+            buffers,
+            buffers.multisampled_texture_buffer,
+            &ColorGrading::default(),
+        );
+        true
+    }
+}
@@ -0,0 +1,32 @@
+//! Particles shared by the client-side effects, drawn as short lines along their velocity
+
+use crate::render::DebugLines;
+use nalgebra::Vector3;
+
+const GRAVITY: f64 = 9.81;
+
+pub struct Particle {
+    pub position: Vector3<f64>,
+    pub velocity: Vector3<f64>,
+    /// Remaining time before the particle disappears, in seconds
+    pub remaining_time: f64,
+    pub color: [f32; 3],
+}
+
+/// Move the particles, and remove the ones that disappeared
+pub fn update_particles(particles: &mut Vec<Particle>, seconds_delta: f64) {
+    for particle in particles.iter_mut() {
+        particle.velocity.y -= GRAVITY * seconds_delta;
+        particle.position += particle.velocity * seconds_delta;
+        particle.remaining_time -= seconds_delta;
+    }
+    particles.retain(|particle| particle.remaining_time > 0.0);
+}
+
+/// Add the lines of the particles to `lines`
+pub fn add_particle_lines(particles: &[Particle], lines: &mut DebugLines) {
+    for particle in particles.iter() {
+        let tail = particle.position - particle.velocity.normalize() * 0.05;
+        lines.add_line(tail, particle.position, particle.color);
+    }
+}
@@ -0,0 +1,340 @@
+//! Post-process antialiasing, used instead of MSAA.
+//!
+//! The world is rendered without multisampling, and tonemapped to an intermediate texture which is then filtered
+//! into the window frame buffer. The UI is drawn afterwards so that it stays sharp.
+//! The temporal antialiasing offsets the camera by a different subpixel amount every frame,
+//! and blends every frame with the previous ones, reprojected using the depth buffer.
+//! Only the camera moves between two frames, so the reprojection ignores the moving models.
+
+use super::frustum::{opengl_to_wgpu_matrix, Frustum};
+use super::init::{load_glsl_shader, ShaderStage, RASTERIZER_NO_CULLING};
+use super::{buffer_from_slice, to_u8_slice};
+use crate::settings::Antialiasing;
+use crate::window::{WindowBuffers, WindowData};
+use nalgebra::Matrix4;
+
+/// Weight of the previous frames in the output of the temporal antialiasing
+const HISTORY_WEIGHT: f32 = 0.9;
+/// Number of different camera offsets used by the temporal antialiasing
+const JITTER_SEQUENCE_LENGTH: u32 = 8;
+
+const POST_PROCESS_BIND_GROUP_LAYOUT: wgpu::BindGroupLayoutDescriptor<'static> =
+    wgpu::BindGroupLayoutDescriptor {
+        label: None,
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::Sampler { comparison: false },
+                count: None
+            },
+            // The rendered frame
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::SampledTexture {
+                    component_type: wgpu::TextureComponentType::Float,
+                    multisampled: false,
+                    dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None
+            },
+            // The depth buffer
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::SampledTexture {
+                    component_type: wgpu::TextureComponentType::Float,
+                    multisampled: false,
+                    dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None
+            },
+            // The accumulated previous frames
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::SampledTexture {
+                    component_type: wgpu::TextureComponentType::Float,
+                    multisampled: false,
+                    dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None
+            },
+            // Reprojection matrix and parameters
+            wgpu::BindGroupLayoutEntry {
+                binding: 4,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::UniformBuffer { dynamic: false, min_binding_size: None },
+                count: None
+            },
+        ],
+    };
+
+/// A texture that can be rendered to and sampled
+struct RenderTexture {
+    _texture: wgpu::Texture,
+    view: wgpu::TextureView,
+}
+
+impl RenderTexture {
+    fn new(device: &wgpu::Device, (width, height): (u32, u32)) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: crate::window::COLOR_FORMAT,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        Self {
+            _texture: texture,
+            view,
+        }
+    }
+}
+
+pub struct PostAntialiasing {
+    mode: Antialiasing,
+    size: (u32, u32),
+    /// The tonemapped world is written to this texture
+    scene: RenderTexture,
+    /// The output of the last two frames of the temporal antialiasing. One is read while the other is written.
+    history: [RenderTexture; 2],
+    /// Index of the history texture written during the next frame
+    current_history: usize,
+    /// The view-projection matrix of the previous frame, if the history is valid
+    previous_view_proj: Option<Matrix4<f64>>,
+    frame_index: u32,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    uniform_reprojection: wgpu::Buffer,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl PostAntialiasing {
+    /// Create the post-process antialiasing, or `None` if the world is rendered with MSAA
+    pub fn new(device: &wgpu::Device, mode: Antialiasing) -> Option<Self> {
+        let fragment_shader_path = match mode {
+            Antialiasing::Msaa => return None,
+            Antialiasing::Fxaa => "assets/shaders/fxaa.frag",
+            Antialiasing::Taa => "assets/shaders/taa.frag",
+        };
+        let bind_group_layout = device.create_bind_group_layout(&POST_PROCESS_BIND_GROUP_LAYOUT);
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: None,
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            lod_min_clamp: 0.0,
+            lod_max_clamp: 0.0,
+            compare: None,
+            anisotropy_clamp: None
+        });
+        let uniform_reprojection = device.create_buffer(&wgpu::BufferDescriptor {
+            mapped_at_creation: false,
+            label: None,
+            size: 80,
+            usage: (wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST),
+        });
+
+        // The temporal antialiasing also writes its output to the history
+        let color_state = wgpu::ColorStateDescriptor {
+            format: crate::window::COLOR_FORMAT,
+            color_blend: wgpu::BlendDescriptor::REPLACE,
+            alpha_blend: wgpu::BlendDescriptor::REPLACE,
+            write_mask: wgpu::ColorWrite::ALL,
+        };
+        let color_states = [color_state.clone(), color_state];
+        let num_outputs = if mode == Antialiasing::Taa { 2 } else { 1 };
+
+        let vertex_shader_bytes = load_glsl_shader(ShaderStage::Vertex, "assets/shaders/postprocess.vert");
+        let vertex_shader = device.create_shader_module(wgpu::util::make_spirv(&vertex_shader_bytes));
+        let fragment_shader_bytes = load_glsl_shader(ShaderStage::Fragment, fragment_shader_path);
+        let fragment_shader = device.create_shader_module(wgpu::util::make_spirv(&fragment_shader_bytes));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[]
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: None,
+            layout: Some(&pipeline_layout),
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vertex_shader,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &fragment_shader,
+                entry_point: "main",
+            }),
+            vertex_state: wgpu::VertexStateDescriptor {
+                index_format: wgpu::IndexFormat::Uint32,
+                vertex_buffers: &[],
+            },
+            rasterization_state: Some(RASTERIZER_NO_CULLING),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            color_states: &color_states[..num_outputs],
+            depth_stencil_state: None,
+            sample_count: 1,
+            sample_mask: 0xFFFFFFFF,
+            alpha_to_coverage_enabled: false,
+        });
+
+        let size = (1, 1);
+        Some(Self {
+            mode,
+            size,
+            scene: RenderTexture::new(device, size),
+            history: [RenderTexture::new(device, size), RenderTexture::new(device, size)],
+            current_history: 0,
+            previous_view_proj: None,
+            frame_index: 0,
+            bind_group_layout,
+            sampler,
+            uniform_reprojection,
+            pipeline,
+        })
+    }
+
+    /// Resize the textures to the window if needed. Must be called once per frame, before the world is rendered.
+    pub fn prepare(&mut self, device: &wgpu::Device, data: &WindowData) {
+        let size = (
+            data.physical_window_size.width.max(1),
+            data.physical_window_size.height.max(1),
+        );
+        if size != self.size {
+            self.size = size;
+            self.scene = RenderTexture::new(device, size);
+            self.history = [RenderTexture::new(device, size), RenderTexture::new(device, size)];
+            self.previous_view_proj = None;
+        }
+        self.frame_index = (self.frame_index + 1) % JITTER_SEQUENCE_LENGTH;
+    }
+
+    /// The texture the tonemapped world should be written to
+    pub fn scene_view(&self) -> &wgpu::TextureView {
+        &self.scene.view
+    }
+
+    /// The offset of the camera for this frame, in normalized device coordinates
+    pub fn jitter(&self) -> (f64, f64) {
+        if self.mode != Antialiasing::Taa {
+            return (0.0, 0.0);
+        }
+        // Halton sequence, between -0.5 and 0.5 pixel
+        let index = self.frame_index + 1;
+        (
+            (halton(index, 2) - 0.5) * 2.0 / self.size.0 as f64,
+            (halton(index, 3) - 0.5) * 2.0 / self.size.1 as f64,
+        )
+    }
+
+    /// Filter the rendered world into the window frame buffer.
+    /// `frustum` is the camera of the frame, without jitter.
+    pub fn apply(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        buffers: WindowBuffers,
+        frustum: &Frustum,
+    ) {
+        // Update the reprojection from the current frame to the previous one
+        let aspect_ratio = self.size.0 as f64 / self.size.1 as f64;
+        let view_proj = opengl_to_wgpu_matrix() * frustum.get_view_projection(aspect_ratio);
+        let (reprojection, history_valid) = match (self.previous_view_proj, view_proj.try_inverse()) {
+            (Some(previous_view_proj), Some(inverse_view_proj)) => (previous_view_proj * inverse_view_proj, 1.0),
+            _ => (Matrix4::identity(), 0.0),
+        };
+        self.previous_view_proj = Some(view_proj);
+        let reprojection: [[f32; 4]; 4] =
+            nalgebra::convert::<Matrix4<f64>, Matrix4<f32>>(reprojection).into();
+        let src_buffer = buffer_from_slice(
+            device,
+            wgpu::BufferUsage::COPY_SRC,
+            to_u8_slice(&[reprojection]),
+        );
+        encoder.copy_buffer_to_buffer(&src_buffer, 0, &self.uniform_reprojection, 0, 64);
+        let src_buffer = buffer_from_slice(
+            device,
+            wgpu::BufferUsage::COPY_SRC,
+            to_u8_slice(&[history_valid, HISTORY_WEIGHT, 0.0f32, 0.0f32]),
+        );
+        encoder.copy_buffer_to_buffer(&src_buffer, 0, &self.uniform_reprojection, 64, 16);
+
+        let previous_history = &self.history[1 - self.current_history];
+        let current_history = &self.history[self.current_history];
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&self.scene.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(buffers.depth_buffer),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&previous_history.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Buffer(self.uniform_reprojection.slice(..)),
+                },
+            ],
+        });
+
+        let output = wgpu::RenderPassColorAttachmentDescriptor {
+            attachment: buffers.multisampled_texture_buffer,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Load,
+                store: true
+            },
+        };
+        let history_output = wgpu::RenderPassColorAttachmentDescriptor {
+            attachment: &current_history.view,
+            ..output.clone()
+        };
+        let color_attachments = [output, history_output];
+        let num_outputs = if self.mode == Antialiasing::Taa { 2 } else { 1 };
+        {
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &color_attachments[..num_outputs],
+                depth_stencil_attachment: None,
+            });
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_bind_group(0, &bind_group, &[]);
+            rpass.draw(0..3, 0..1);
+        }
+        self.current_history = 1 - self.current_history;
+    }
+}
+
+/// The `index`-th element of the Halton sequence of base `base`, between 0 and 1
+fn halton(mut index: u32, base: u32) -> f64 {
+    let mut fraction = 1.0;
+    let mut result = 0.0;
+    while index > 0 {
+        fraction /= base as f64;
+        result += fraction * (index % base) as f64;
+        index /= base;
+    }
+    result
+}
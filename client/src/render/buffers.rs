@@ -5,6 +5,11 @@ use std::hash::Hash;
 
 use super::{ buffer_from_slice, to_u8_slice };
 
+/// Fraction of a `MultiBuffer` that can be lost to fragmentation before it is compacted
+const MAX_FRAGMENTATION: f32 = 0.25;
+/// A `MultiBuffer` is shrunk when less than this fraction of it is used
+const MIN_USAGE: f32 = 0.25;
+
 /// A buffer that will automatically resize itself when necessary
 pub struct DynamicBuffer<T: Copy> {
     buffer: wgpu::Buffer,
@@ -162,7 +167,6 @@ impl<K: Hash + Eq + Clone + std::fmt::Debug, T: Copy + std::fmt::Debug + 'static
     ///
     /// # Panics
     /// Will panic if `data` is empty.
-    // TODO: handle memory fragmentation
     pub fn update(
         &mut self,
         device: &wgpu::Device,
@@ -264,6 +268,96 @@ impl<K: Hash + Eq + Clone + std::fmt::Debug, T: Copy + std::fmt::Debug + 'static
         self.len = new_len;
     }
 
+    /// Number of elements used by the objects
+    pub fn used_len(&self) -> usize {
+        self.segments.iter().filter(|seg| !seg.free).map(|seg| seg.len).sum()
+    }
+
+    /// Number of elements the buffer can hold
+    pub fn capacity(&self) -> usize {
+        self.len
+    }
+
+    /// Fraction of the buffer that is free but located between objects.
+    /// The free space at the end of the buffer doesn't count because it can hold objects of any size.
+    pub fn fragmentation(&self) -> f32 {
+        let free_end = match self.segments.last() {
+            Some(seg) if seg.free => seg.len,
+            _ => 0,
+        };
+        let free_len = self.len - self.used_len();
+        (free_len - free_end) as f32 / self.len as f32
+    }
+
+    /// Compact the buffer if it is too fragmented or mostly empty. Return `true` if the objects moved.
+    pub fn compact_if_needed(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> bool {
+        let usage = self.used_len() as f32 / self.len as f32;
+        let needed = self.fragmentation() > MAX_FRAGMENTATION || usage < MIN_USAGE;
+        if needed {
+            self.compact(device, encoder);
+        }
+        needed
+    }
+
+    /// Move all the objects to the beginning of a new buffer, leaving as much free space as used space at the end
+    pub fn compact(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        let used_len = self.used_len();
+        // We crash on Vulkan if buffer capacity is 0
+        let new_len = (2 * used_len).min(self.len).max(1);
+        log::debug!(
+            "Compacting MultiBuffer<{}, {}> from length {} to length {}, {} used",
+            std::any::type_name::<K>(),
+            std::any::type_name::<T>(),
+            self.len,
+            new_len,
+            used_len,
+        );
+        let new_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            mapped_at_creation: false,
+            size: (new_len * std::mem::size_of::<T>()) as u64,
+            usage: self.usage,
+        });
+        let mut objects_by_pos: HashMap<usize, K> = self.objects.drain().map(|(object, pos)| (pos, object)).collect();
+        let mut segments = Vec::with_capacity(objects_by_pos.len() + 1);
+        let mut new_pos = 0;
+        for seg in self.segments.iter().filter(|seg| !seg.free) {
+            encoder.copy_buffer_to_buffer(
+                &self.buffer,
+                (seg.pos * std::mem::size_of::<T>()) as u64,
+                &new_buffer,
+                (new_pos * std::mem::size_of::<T>()) as u64,
+                (seg.len * std::mem::size_of::<T>()) as u64,
+            );
+            let object = objects_by_pos.remove(&seg.pos).expect("logic error!");
+            self.objects.insert(object, new_pos);
+            segments.push(MultiBufferSegment {
+                free: false,
+                pos: new_pos,
+                len: seg.len,
+            });
+            new_pos += seg.len;
+        }
+        if new_pos < new_len {
+            segments.push(MultiBufferSegment {
+                free: true,
+                pos: new_pos,
+                len: new_len - new_pos,
+            });
+        }
+        self.buffer = new_buffer;
+        self.segments = segments;
+        self.len = new_len;
+    }
+
     fn _assert_invariants(&self) {
         assert_eq!(self.segments.first().unwrap().pos, 0);
         assert_eq!(
@@ -385,5 +479,17 @@ mod tests {
         // Reallocate
         multi_buffer.update(&device, &mut encoder, 3u16, &seg2);
         assert_eq!(multi_buffer.get_pos_len(&3), Some((8, 4)));
+        assert_eq!(multi_buffer.capacity(), 20);
+
+        // Compact
+        multi_buffer.remove(&0u16);
+        multi_buffer.remove(&2u16);
+        assert_eq!(multi_buffer.fragmentation(), 7.0 / 20.0);
+        assert!(multi_buffer.compact_if_needed(&device, &mut encoder));
+        multi_buffer._assert_invariants();
+        assert_eq!(multi_buffer.get_pos_len(&1), Some((0, 1)));
+        assert_eq!(multi_buffer.get_pos_len(&3), Some((1, 4)));
+        assert_eq!(multi_buffer.capacity(), 10);
+        assert_eq!(multi_buffer.fragmentation(), 0.0);
     }
 }
@@ -17,7 +17,15 @@ impl Plane {
     }
 }
 
-const FOV: f64 = 90.0f64 * 2.0 * std::f64::consts::PI / 360.0;
+/// Convert the OpenGL clip space, where z is between -1 and 1, to the wgpu clip space, where z is between 0 and 1
+pub fn opengl_to_wgpu_matrix() -> Matrix4<f64> {
+    Matrix4::from([
+        [1.0, 0.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0, 0.0],
+        [0.0, 0.0, 0.5, 0.0],
+        [0.0, 0.0, 0.5, 1.0],
+    ])
+}
 
 /// The player's frustum
 #[derive(Debug, Clone, Copy)]
@@ -28,22 +36,34 @@ pub struct Frustum {
     pub yaw: f64,
     /// Yaw in degrees
     pub pitch: f64,
+    /// Vertical field of view in degrees
+    pub fov: f64,
+    /// Subpixel offset of the projection in normalized device coordinates, used by the temporal antialiasing
+    pub jitter: (f64, f64),
 }
 
 impl Frustum {
     /// Create a new frustum. This function should be called each frame.
-    pub fn new(position: Vector3<f64>, yaw_pitch: YawPitch) -> Frustum {
+    pub fn new(position: Vector3<f64>, yaw_pitch: YawPitch, fov: f64) -> Frustum {
         Self {
             position,
             yaw: yaw_pitch.yaw,
             pitch: yaw_pitch.pitch,
+            fov,
+            jitter: (0.0, 0.0),
         }
     }
 
+    /// The same frustum, with the projection offset by `jitter` in normalized device coordinates
+    pub fn with_jitter(self, jitter: (f64, f64)) -> Frustum {
+        Self { jitter, ..self }
+    }
+
     /// Get the view/projection matrix associated with this frustum
     pub fn get_view_projection(&self, aspect_ratio: f64) -> Matrix4<f64> {
-        let proj = Perspective3::new(aspect_ratio, FOV, 0.1, 3000.0);
-        proj.as_matrix() * self.get_view_matrix()
+        let proj = Perspective3::new(aspect_ratio, self.fov.to_radians(), 0.1, 3000.0);
+        let jitter = Matrix4::new_translation(&Vector3::new(self.jitter.0, self.jitter.1, 0.0));
+        jitter * proj.as_matrix() * self.get_view_matrix()
     }
 
     pub fn get_view_matrix(&self) -> Matrix4<f64> {
@@ -54,7 +74,7 @@ impl Frustum {
     }
 
     pub fn get_planes(&self, aspect_ratio: f64) -> [[Plane; 2]; 3] {
-        let (fovy, znear, zfar) = (FOV, 0.1, 3000.0);
+        let (fovy, znear, zfar) = (self.fov.to_radians(), 0.1, 3000.0);
         let t = (fovy / 2.0).tan();
         let h_near = t * 2.0 * znear;
         let w_near = h_near * aspect_ratio;
@@ -0,0 +1,55 @@
+//! GPU time of the render passes
+use std::time::Instant;
+
+/// Measures how long the GPU takes to execute the render passes of a frame.
+/// wgpu 0.6 doesn't have timestamp queries, so the commands of every pass are submitted separately and the CPU
+/// waits until the GPU executed them. This stalls the rendering, so the timer is only enabled while it is shown.
+/// The measured times also include the submission and the wake-up of the CPU, they are only approximate.
+#[derive(Default)]
+pub struct GpuTimer {
+    enabled: bool,
+    /// GPU time of every pass of the current frame in milliseconds, in the order they were first recorded
+    passes: Vec<(String, f64)>,
+}
+
+impl GpuTimer {
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Submit the commands recorded in `encoder` since the end of the previous pass, and add their GPU time to `pass`.
+    /// `encoder` is replaced by an empty encoder. Does nothing if the timer is disabled.
+    pub fn end_pass(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        pass: &str,
+    ) {
+        if !self.enabled {
+            return;
+        }
+        let new_encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        let commands = std::mem::replace(encoder, new_encoder).finish();
+        // Don't measure the commands that were submitted before, for example the end of the previous frame
+        device.poll(wgpu::Maintain::Wait);
+        let start = Instant::now();
+        queue.submit(vec![commands]);
+        device.poll(wgpu::Maintain::Wait);
+        let millis = start.elapsed().as_secs_f64() * 1000.0;
+        // Passes that are rendered once per camera are added together
+        match self.passes.iter_mut().find(|(name, _)| name == pass) {
+            Some((_, total)) => *total += millis,
+            None => self.passes.push((pass.to_owned(), millis)),
+        }
+    }
+
+    /// Take the GPU time of every pass of the frame in milliseconds
+    pub fn take_passes(&mut self) -> Vec<(String, f64)> {
+        std::mem::take(&mut self.passes)
+    }
+}
@@ -42,10 +42,10 @@ pub const RASTERIZER_WITH_CULLING: wgpu::RasterizationStateDescriptor =
         ..RASTERIZER_NO_CULLING
     };
 
-/// Default `ColorStateDescriptor`
-pub const DEFAULT_COLOR_STATE_DESCRIPTOR: [wgpu::ColorStateDescriptor; 1] =
-    [wgpu::ColorStateDescriptor {
-        format: crate::window::COLOR_FORMAT,
+/// Default `ColorStateDescriptor` for a color buffer of the given format
+pub fn default_color_state_descriptor(format: wgpu::TextureFormat) -> wgpu::ColorStateDescriptor {
+    wgpu::ColorStateDescriptor {
+        format,
         color_blend: wgpu::BlendDescriptor {
             src_factor: wgpu::BlendFactor::SrcAlpha,
             dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
@@ -57,7 +57,8 @@ pub const DEFAULT_COLOR_STATE_DESCRIPTOR: [wgpu::ColorStateDescriptor; 1] =
             operation: wgpu::BlendOperation::Add,
         },
         write_mask: wgpu::ColorWrite::ALL,
-    }];
+    }
+}
 
 /// Default `DepthStencilStateDescriptor`
 pub const DEFAULT_DEPTH_STENCIL_STATE_DESCRIPTOR: wgpu::DepthStencilStateDescriptor =
@@ -73,9 +74,10 @@ pub const DEFAULT_DEPTH_STENCIL_STATE_DESCRIPTOR: wgpu::DepthStencilStateDescrip
         }
     };
 
-/// Create a default pipeline
+/// Create a default pipeline that renders to a color buffer of the given format
 pub fn create_default_pipeline(
     device: &wgpu::Device,
+    color_format: wgpu::TextureFormat,
     uniform_layout: &wgpu::BindGroupLayout,
     vertex_shader: wgpu::ShaderModuleSource,
     fragment_shader: wgpu::ShaderModuleSource,
@@ -117,9 +119,9 @@ pub fn create_default_pipeline(
             RASTERIZER_NO_CULLING
         }),
         primitive_topology,
-        color_states: &DEFAULT_COLOR_STATE_DESCRIPTOR,
+        color_states: &[default_color_state_descriptor(color_format)],
         depth_stencil_state: Some(DEFAULT_DEPTH_STENCIL_STATE_DESCRIPTOR),
-        sample_count: crate::window::SAMPLE_COUNT,
+        sample_count: crate::window::sample_count(),
         sample_mask: 0xFFFFFFFF,
         alpha_to_coverage_enabled: false,
     })
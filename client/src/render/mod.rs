@@ -2,17 +2,25 @@
 
 /* WebGPU HELPER MODULES */
 mod buffers;
+mod gpu_timer;
 mod init;
 mod render;
 pub use self::buffers::MultiBuffer;
-pub use self::render::{clear_color_and_depth, clear_depth, encode_resolve_render_pass, to_u8_slice, buffer_from_slice};
+pub use self::gpu_timer::GpuTimer;
+pub use self::render::{clear_color_and_depth, clear_depth, encode_resolve_render_pass, to_u8_slice, buffer_from_slice, Viewport};
 
 /* OTHER HELPER MODULES */
 mod frustum;
 pub use self::frustum::Frustum;
 
 /* RENDERING-RESPONSIBLE MODULES */
+mod antialiasing;
+pub use self::antialiasing::PostAntialiasing;
+mod screenshot;
+pub use self::screenshot::ScreenshotCapture;
+mod tonemapping;
+pub use self::tonemapping::{ColorGrading, Tonemapping};
 mod ui;
 pub mod world;
 pub use self::ui::UiRenderer;
-pub use self::world::{Model, WorldRenderer, ChunkVertex};
+pub use self::world::{DebugLines, Model, PointLight, WorldRenderer, ChunkVertex};
@@ -1,7 +1,7 @@
 //! Helpers for renderer passes
 
 use wgpu::util::{BufferInitDescriptor, DeviceExt};
-use crate::window::WindowBuffers;
+use crate::window::{WindowBuffers, WindowData};
 
 /// Create an attachment for the depth buffer that doesn't clear it.
 pub fn create_default_depth_stencil_attachment(
@@ -40,8 +40,65 @@ pub fn create_default_render_pass<'a>(
     })
 }
 
+/// A rectangle of the window that is rendered to, in physical pixels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewport {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Viewport {
+    /// The whole window
+    pub fn full(data: &WindowData) -> Self {
+        Self {
+            x: 0.0,
+            y: 0.0,
+            width: data.physical_window_size.width as f32,
+            height: data.physical_window_size.height as f32,
+        }
+    }
+
+    /// Split the viewport into a left and a right half
+    pub fn split_vertically(self) -> (Self, Self) {
+        let half_width = (self.width / 2.0).floor();
+        (
+            Self {
+                width: half_width,
+                ..self
+            },
+            Self {
+                x: self.x + half_width,
+                width: self.width - half_width,
+                ..self
+            },
+        )
+    }
+
+    // TODO: what if the height is 0 ?
+    pub fn aspect_ratio(&self) -> f64 {
+        self.width as f64 / self.height as f64
+    }
+
+    /// Restrict the render pass to the viewport
+    pub fn apply(&self, rpass: &mut wgpu::RenderPass) {
+        rpass.set_viewport(self.x, self.y, self.width, self.height, 0.0, 1.0);
+        rpass.set_scissor_rect(
+            self.x as u32,
+            self.y as u32,
+            self.width as u32,
+            self.height as u32,
+        );
+    }
+}
+
 /// Encode a render pass to resolve the multisampled frame buffer to the window frame buffer
 pub fn encode_resolve_render_pass<'a>(encoder: &mut wgpu::CommandEncoder, buffers: WindowBuffers) {
+    // Without multisampling, the frame was rendered directly to the window frame buffer
+    if crate::window::sample_count() == 1 {
+        return;
+    }
     let _rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
         color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
             attachment: buffers.multisampled_texture_buffer,
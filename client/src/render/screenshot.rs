@@ -0,0 +1,391 @@
+//! Screenshots of the window.
+//!
+//! The swap chain texture can't be copied, so the frame of a screenshot is rendered to an intermediate texture
+//! which is then drawn to the swap chain. The texture is copied to a buffer that is mapped asynchronously
+//! during the next frames, and the PNG is encoded and written by a separate thread: the render loop never waits.
+
+use super::init::{load_glsl_shader, ShaderStage, RASTERIZER_NO_CULLING};
+use crate::window::WindowData;
+use futures::FutureExt;
+use log::{info, warn};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::mpsc::{channel, Sender};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SCREENSHOTS_FOLDER: &str = "screenshots";
+
+type MapFuture = Pin<Box<dyn Future<Output = Result<(), wgpu::BufferAsyncError>> + Send>>;
+
+const BLIT_BIND_GROUP_LAYOUT: wgpu::BindGroupLayoutDescriptor<'static> =
+    wgpu::BindGroupLayoutDescriptor {
+        label: None,
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::Sampler { comparison: false },
+                count: None
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::SampledTexture {
+                    component_type: wgpu::TextureComponentType::Float,
+                    multisampled: false,
+                    dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None
+            },
+        ],
+    };
+
+/// A captured frame that is being read back from the GPU
+struct Readback {
+    buffer: wgpu::Buffer,
+    width: u32,
+    height: u32,
+    /// Rows are padded to `wgpu::COPY_BYTES_PER_ROW_ALIGNMENT` in the buffer
+    padded_bytes_per_row: u32,
+}
+
+/// Where the current screenshot is
+enum CaptureState {
+    /// No screenshot is being taken
+    Idle,
+    /// A screenshot was requested, the next frame will be captured
+    Requested,
+    /// The copy was encoded, the buffer can be mapped once the commands are submitted
+    Encoded(Readback),
+    /// Waiting for the buffer to be mapped
+    Mapping(Readback, MapFuture),
+}
+
+/// The pixels of a screenshot, sent to the thread that saves it
+struct Screenshot {
+    width: u32,
+    height: u32,
+    /// Pixels in `COLOR_FORMAT`, without padding
+    bgra: Vec<u8>,
+}
+
+/// The captured frame, rendered to instead of the swap chain
+struct FrameTexture {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    bind_group: wgpu::BindGroup,
+    size: (u32, u32),
+}
+
+pub struct ScreenshotCapture {
+    frame: Option<FrameTexture>,
+    state: CaptureState,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    pipeline: wgpu::RenderPipeline,
+    saver: Sender<Screenshot>,
+}
+
+impl ScreenshotCapture {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let (saver, screenshots) = channel::<Screenshot>();
+        std::thread::spawn(move || {
+            for screenshot in screenshots.iter() {
+                match save_screenshot(screenshot) {
+                    Ok(path) => info!("Saved screenshot to {}", path.display()),
+                    Err(e) => warn!("Failed to save screenshot ({:?})", e),
+                }
+            }
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&BLIT_BIND_GROUP_LAYOUT);
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: None,
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            lod_min_clamp: 0.0,
+            lod_max_clamp: 0.0,
+            compare: None,
+            anisotropy_clamp: None
+        });
+
+        let vertex_shader_bytes = load_glsl_shader(ShaderStage::Vertex, "assets/shaders/postprocess.vert");
+        let vertex_shader = device.create_shader_module(wgpu::util::make_spirv(&vertex_shader_bytes));
+        let fragment_shader_bytes = load_glsl_shader(ShaderStage::Fragment, "assets/shaders/blit.frag");
+        let fragment_shader = device.create_shader_module(wgpu::util::make_spirv(&fragment_shader_bytes));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[]
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: None,
+            layout: Some(&pipeline_layout),
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vertex_shader,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &fragment_shader,
+                entry_point: "main",
+            }),
+            vertex_state: wgpu::VertexStateDescriptor {
+                index_format: wgpu::IndexFormat::Uint32,
+                vertex_buffers: &[],
+            },
+            rasterization_state: Some(RASTERIZER_NO_CULLING),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            color_states: &[wgpu::ColorStateDescriptor {
+                format: crate::window::COLOR_FORMAT,
+                color_blend: wgpu::BlendDescriptor::REPLACE,
+                alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                write_mask: wgpu::ColorWrite::ALL,
+            }],
+            depth_stencil_state: None,
+            sample_count: 1,
+            sample_mask: 0xFFFFFFFF,
+            alpha_to_coverage_enabled: false,
+        });
+
+        Self {
+            frame: None,
+            state: CaptureState::Idle,
+            bind_group_layout,
+            sampler,
+            pipeline,
+            saver,
+        }
+    }
+
+    /// Capture one of the next frames. Ignored if the previous screenshot is still being read back.
+    pub fn request(&mut self) {
+        match self.state {
+            CaptureState::Idle => self.state = CaptureState::Requested,
+            _ => warn!("Can't take a screenshot while the previous one is being captured"),
+        }
+    }
+
+    /// Read back the last captured frame if it is ready. Must be called once per frame, before `prepare`.
+    pub fn update(&mut self, device: &wgpu::Device) {
+        self.state = match std::mem::replace(&mut self.state, CaptureState::Idle) {
+            // The commands of the previous frame were submitted, the buffer can be mapped
+            CaptureState::Encoded(readback) => {
+                let future = readback.buffer.slice(..).map_async(wgpu::MapMode::Read);
+                CaptureState::Mapping(readback, Box::pin(future))
+            }
+            CaptureState::Mapping(readback, mut future) => {
+                device.poll(wgpu::Maintain::Poll);
+                match (&mut future).now_or_never() {
+                    None => CaptureState::Mapping(readback, future),
+                    Some(Ok(())) => {
+                        let bytes_per_row = (readback.width * 4) as usize;
+                        let mut bgra = Vec::with_capacity(bytes_per_row * readback.height as usize);
+                        {
+                            let data = readback.buffer.slice(..).get_mapped_range();
+                            for row in data.chunks(readback.padded_bytes_per_row as usize) {
+                                bgra.extend_from_slice(&row[..bytes_per_row]);
+                            }
+                        }
+                        readback.buffer.unmap();
+                        let screenshot = Screenshot {
+                            width: readback.width,
+                            height: readback.height,
+                            bgra,
+                        };
+                        if self.saver.send(screenshot).is_err() {
+                            warn!("Failed to save screenshot: the saving thread stopped");
+                        }
+                        CaptureState::Idle
+                    }
+                    Some(Err(e)) => {
+                        warn!("Failed to read back the screenshot: {:?}", e);
+                        CaptureState::Idle
+                    }
+                }
+            }
+            state => state,
+        };
+    }
+
+    /// Return `true` if this frame should be rendered to `frame_view` to be captured.
+    /// The texture is resized to the window if needed.
+    pub fn prepare(&mut self, device: &wgpu::Device, data: &WindowData) -> bool {
+        if !matches!(self.state, CaptureState::Requested) {
+            return false;
+        }
+        let size = (
+            data.physical_window_size.width.max(1),
+            data.physical_window_size.height.max(1),
+        );
+        if self.frame.as_ref().map(|frame| frame.size) != Some(size) {
+            self.frame = Some(self.create_frame_texture(device, size));
+        }
+        true
+    }
+
+    /// The texture the captured frame should be rendered to, instead of the swap chain
+    pub fn frame_view(&self) -> &wgpu::TextureView {
+        &self.frame.as_ref().expect("No screenshot was prepared").view
+    }
+
+    /// Copy the captured frame to the readback buffer, and draw it to the swap chain `output`.
+    /// The returned commands must be submitted after the ones that rendered the frame.
+    pub fn capture(&mut self, device: &wgpu::Device, output: &wgpu::TextureView) -> wgpu::CommandBuffer {
+        let frame = self.frame.as_ref().expect("No screenshot was prepared");
+        let (width, height) = frame.size;
+        let alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = (width * 4 + alignment - 1) / alignment * alignment;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            mapped_at_creation: false,
+            label: None,
+            size: (padded_bytes_per_row * height) as u64,
+            usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_texture_to_buffer(
+            wgpu::TextureCopyView {
+                texture: &frame.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::BufferCopyView {
+                buffer: &buffer,
+                layout: wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: padded_bytes_per_row,
+                    rows_per_image: height,
+                },
+            },
+            wgpu::Extent3d { width, height, depth: 1 },
+        );
+        {
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                    attachment: output,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: None,
+            });
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_bind_group(0, &frame.bind_group, &[]);
+            rpass.draw(0..3, 0..1);
+        }
+
+        self.state = CaptureState::Encoded(Readback {
+            buffer,
+            width,
+            height,
+            padded_bytes_per_row,
+        });
+        encoder.finish()
+    }
+
+    fn create_frame_texture(&self, device: &wgpu::Device, (width, height): (u32, u32)) -> FrameTexture {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: crate::window::COLOR_FORMAT,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT
+                | wgpu::TextureUsage::SAMPLED
+                | wgpu::TextureUsage::COPY_SRC,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+            ],
+        });
+        FrameTexture {
+            texture,
+            view,
+            bind_group,
+            size: (width, height),
+        }
+    }
+}
+
+/// Write the screenshot to a new PNG file named after the current UTC time, and return its path
+fn save_screenshot(screenshot: Screenshot) -> anyhow::Result<PathBuf> {
+    let Screenshot { width, height, mut bgra } = screenshot;
+    for pixel in bgra.chunks_mut(4) {
+        pixel.swap(0, 2);
+    }
+
+    std::fs::create_dir_all(SCREENSHOTS_FOLDER)?;
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let name = format_timestamp(seconds);
+    let mut path = Path::new(SCREENSHOTS_FOLDER).join(format!("{}.png", name));
+    // Several screenshots can be taken during the same second
+    let mut index = 2;
+    while path.exists() {
+        path = Path::new(SCREENSHOTS_FOLDER).join(format!("{}_{}.png", name, index));
+        index += 1;
+    }
+    image::save_buffer(&path, &bgra, width, height, image::ColorType::Rgba8)?;
+    Ok(path)
+}
+
+/// Format a unix timestamp as `YYYY-MM-DD_HH.MM.SS`, in UTC
+fn format_timestamp(seconds: u64) -> String {
+    let (days, seconds) = ((seconds / 86400) as i64, seconds % 86400);
+    // Convert the days since the epoch to a date (cf: http://howardhinnant.github.io/date_algorithms.html#civil_from_days)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}_{:02}.{:02}.{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_timestamp;
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01_00.00.00");
+        assert_eq!(format_timestamp(951_786_061), "2000-02-29_01.01.01");
+        assert_eq!(format_timestamp(1_792_152_000), "2026-10-16_12.00.00");
+    }
+}
@@ -0,0 +1,403 @@
+//! HDR rendering, with a tonemapping and color grading pass.
+//!
+//! The world is rendered to a floating point texture, so that the colors brighter than white are kept.
+//! The tonemapping pass then multiplies the colors by the exposure, compresses the bright colors
+//! into the displayable range, and applies the contrast, the tint and an optional color lookup table.
+
+use super::init::{load_glsl_shader, ShaderStage, RASTERIZER_NO_CULLING};
+use super::{buffer_from_slice, encode_resolve_render_pass, to_u8_slice};
+use crate::window::{WindowBuffers, WindowData};
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::path::Path;
+
+/// Size of the uniform containing the `ColorGrading`
+const COLOR_GRADING_SIZE: u64 = 32;
+
+const TONEMAPPING_BIND_GROUP_LAYOUT: wgpu::BindGroupLayoutDescriptor<'static> =
+    wgpu::BindGroupLayoutDescriptor {
+        label: None,
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::Sampler { comparison: false },
+                count: None
+            },
+            // The HDR frame
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::SampledTexture {
+                    component_type: wgpu::TextureComponentType::Float,
+                    multisampled: false,
+                    dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None
+            },
+            // The color lookup table
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::SampledTexture {
+                    component_type: wgpu::TextureComponentType::Float,
+                    multisampled: false,
+                    dimension: wgpu::TextureViewDimension::D3,
+                },
+                count: None
+            },
+            // The color grading parameters
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::UniformBuffer { dynamic: false, min_binding_size: None },
+                count: None
+            },
+        ],
+    };
+
+/// Parameters of the tonemapping and color grading
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColorGrading {
+    /// The colors are multiplied by the exposure before they are tonemapped
+    pub exposure: f32,
+    /// 1 keeps the tonemapped colors, higher values increase the contrast around middle gray
+    pub contrast: f32,
+    /// The tonemapped colors are multiplied by the tint, for example to make the water blue
+    pub tint: [f32; 3],
+}
+
+impl Default for ColorGrading {
+    fn default() -> Self {
+        Self {
+            exposure: 1.0,
+            contrast: 1.0,
+            tint: [1.0, 1.0, 1.0],
+        }
+    }
+}
+
+/// A floating point texture the world can be rendered to
+struct HdrTexture {
+    _texture: wgpu::Texture,
+    view: wgpu::TextureView,
+}
+
+impl HdrTexture {
+    fn new(device: &wgpu::Device, (width, height): (u32, u32), sample_count: u32) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format: crate::window::HDR_COLOR_FORMAT,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        Self {
+            _texture: texture,
+            view,
+        }
+    }
+}
+
+pub struct Tonemapping {
+    size: (u32, u32),
+    /// The world is rendered to this texture, or to `multisampled_scene` with MSAA
+    scene: HdrTexture,
+    multisampled_scene: Option<HdrTexture>,
+    _lut_texture: wgpu::Texture,
+    lut_view: wgpu::TextureView,
+    /// Number of entries of the lookup table on every side
+    lut_size: u32,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    uniform_color_grading: wgpu::Buffer,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl Tonemapping {
+    /// Create the tonemapping pass. If `lut_path` is `None` or if the lookup table can't be loaded,
+    /// the colors are not changed by the lookup table.
+    pub fn new(device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, lut_path: Option<&Path>) -> Self {
+        let (lut_size, lut_data) = match lut_path.map(load_lut) {
+            Some(Ok(lut)) => {
+                info!("Loaded the color lookup table {}", lut_path.unwrap().display());
+                lut
+            }
+            Some(Err(e)) => {
+                warn!("Failed to load the color lookup table, using the identity: {:?}", e);
+                identity_lut()
+            }
+            None => identity_lut(),
+        };
+        let lut_texture = load_lut_texture(device, encoder, lut_size, &lut_data);
+        let lut_view = lut_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let bind_group_layout = device.create_bind_group_layout(&TONEMAPPING_BIND_GROUP_LAYOUT);
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: None,
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            lod_min_clamp: 0.0,
+            lod_max_clamp: 0.0,
+            compare: None,
+            anisotropy_clamp: None
+        });
+        let uniform_color_grading = device.create_buffer(&wgpu::BufferDescriptor {
+            mapped_at_creation: false,
+            label: None,
+            size: COLOR_GRADING_SIZE,
+            usage: (wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST),
+        });
+
+        let vertex_shader_bytes = load_glsl_shader(ShaderStage::Vertex, "assets/shaders/postprocess.vert");
+        let vertex_shader = device.create_shader_module(wgpu::util::make_spirv(&vertex_shader_bytes));
+        let fragment_shader_bytes = load_glsl_shader(ShaderStage::Fragment, "assets/shaders/tonemapping.frag");
+        let fragment_shader = device.create_shader_module(wgpu::util::make_spirv(&fragment_shader_bytes));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[]
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: None,
+            layout: Some(&pipeline_layout),
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vertex_shader,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &fragment_shader,
+                entry_point: "main",
+            }),
+            vertex_state: wgpu::VertexStateDescriptor {
+                index_format: wgpu::IndexFormat::Uint32,
+                vertex_buffers: &[],
+            },
+            rasterization_state: Some(RASTERIZER_NO_CULLING),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            color_states: &[wgpu::ColorStateDescriptor {
+                format: crate::window::COLOR_FORMAT,
+                color_blend: wgpu::BlendDescriptor::REPLACE,
+                alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                write_mask: wgpu::ColorWrite::ALL,
+            }],
+            depth_stencil_state: None,
+            // The output is the multisampled frame buffer of the window, which is resolved after the UI is drawn
+            sample_count: crate::window::sample_count(),
+            sample_mask: 0xFFFFFFFF,
+            alpha_to_coverage_enabled: false,
+        });
+
+        let size = (1, 1);
+        Self {
+            size,
+            scene: HdrTexture::new(device, size, 1),
+            multisampled_scene: None,
+            _lut_texture: lut_texture,
+            lut_view,
+            lut_size,
+            bind_group_layout,
+            sampler,
+            uniform_color_grading,
+            pipeline,
+        }
+    }
+
+    /// Resize the textures to the window if needed. Must be called once per frame, before the world is rendered.
+    pub fn prepare(&mut self, device: &wgpu::Device, data: &WindowData) {
+        let size = (
+            data.physical_window_size.width.max(1),
+            data.physical_window_size.height.max(1),
+        );
+        let sample_count = crate::window::sample_count();
+        if size != self.size || (sample_count > 1) != self.multisampled_scene.is_some() {
+            self.size = size;
+            self.scene = HdrTexture::new(device, size, 1);
+            self.multisampled_scene = if sample_count > 1 {
+                Some(HdrTexture::new(device, size, sample_count))
+            } else {
+                None
+            };
+        }
+    }
+
+    /// The buffers the world should be rendered to
+    pub fn scene_buffers<'a>(&'a self, buffers: WindowBuffers<'a>) -> WindowBuffers<'a> {
+        WindowBuffers {
+            texture_buffer: &self.scene.view,
+            multisampled_texture_buffer: self
+                .multisampled_scene
+                .as_ref()
+                .map(|scene| &scene.view)
+                .unwrap_or(&self.scene.view),
+            depth_buffer: buffers.depth_buffer,
+        }
+    }
+
+    /// Tonemap the rendered world into `output`, which is usually the multisampled frame buffer of the window
+    pub fn apply(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        buffers: WindowBuffers,
+        output: &wgpu::TextureView,
+        color_grading: &ColorGrading,
+    ) {
+        encode_resolve_render_pass(encoder, self.scene_buffers(buffers));
+
+        let [r, g, b] = color_grading.tint;
+        let src_buffer = buffer_from_slice(
+            device,
+            wgpu::BufferUsage::COPY_SRC,
+            to_u8_slice(&[
+                color_grading.exposure,
+                color_grading.contrast,
+                self.lut_size as f32,
+                0.0f32,
+                r,
+                g,
+                b,
+                0.0f32,
+            ]),
+        );
+        encoder.copy_buffer_to_buffer(&src_buffer, 0, &self.uniform_color_grading, 0, COLOR_GRADING_SIZE);
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&self.scene.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&self.lut_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Buffer(self.uniform_color_grading.slice(..)),
+                },
+            ],
+        });
+
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, &bind_group, &[]);
+        rpass.draw(0..3, 0..1);
+    }
+}
+
+/// A 2x2x2 lookup table that doesn't change the colors, thanks to the linear filtering
+fn identity_lut() -> (u32, Vec<u8>) {
+    let mut data = Vec::with_capacity(2 * 2 * 2 * 4);
+    for b in 0..2 {
+        for g in 0..2 {
+            for r in 0..2 {
+                data.extend_from_slice(&[r * 255, g * 255, b * 255, 255]);
+            }
+        }
+    }
+    (2, data)
+}
+
+/// Load a lookup table from an image containing N slices of NxN pixels side by side.
+/// The red component increases from left to right in every slice, the green component from top to bottom,
+/// and the blue component from one slice to the next.
+fn load_lut(path: &Path) -> Result<(u32, Vec<u8>)> {
+    let image = image::open(path)
+        .context(format!("Failed to open color lookup table {}", path.display()))?
+        .to_rgba8();
+    let size = image.height();
+    if size < 2 || image.width() != size * size {
+        anyhow::bail!(
+            "Invalid color lookup table size {}x{}, expected {}x{}",
+            image.width(),
+            image.height(),
+            size * size,
+            size
+        );
+    }
+    let mut data = Vec::with_capacity((size * size * size * 4) as usize);
+    for b in 0..size {
+        for g in 0..size {
+            for r in 0..size {
+                data.extend_from_slice(&image.get_pixel(b * size + r, g).0);
+            }
+        }
+    }
+    Ok((size, data))
+}
+
+/// Copy a lookup table to a 3D texture
+fn load_lut_texture(device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, size: u32, data: &[u8]) -> wgpu::Texture {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: None,
+        size: wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth: size,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D3,
+        format: wgpu::TextureFormat::Rgba8Unorm,
+        usage: wgpu::TextureUsage::COPY_DST | wgpu::TextureUsage::SAMPLED,
+    });
+    // The rows of the copy must be aligned
+    let row_size = (4 * size) as usize;
+    let aligned_row_size = (row_size + wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as usize - 1)
+        / wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as usize
+        * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as usize;
+    let mut aligned_data = vec![0; aligned_row_size * (size * size) as usize];
+    for (row, pixels) in data.chunks(row_size).enumerate() {
+        aligned_data[row * aligned_row_size..row * aligned_row_size + row_size].copy_from_slice(pixels);
+    }
+    let src_buffer = buffer_from_slice(device, wgpu::BufferUsage::COPY_SRC, &aligned_data);
+    encoder.copy_buffer_to_texture(
+        wgpu::BufferCopyView {
+            layout: wgpu::TextureDataLayout {
+                offset: 0,
+                rows_per_image: size,
+                bytes_per_row: aligned_row_size as u32,
+            },
+            buffer: &src_buffer,
+        },
+        wgpu::TextureCopyView {
+            texture: &texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d { x: 0, y: 0, z: 0 },
+        },
+        wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth: size,
+        },
+    );
+    texture
+}
@@ -2,11 +2,17 @@
 
 use super::{ buffer_from_slice, to_u8_slice };
 use super::buffers::DynamicBuffer;
-use super::init::{load_glsl_shader, ShaderStage};
-use crate::ui::PrimitiveBuffer;
+use super::init::{default_color_state_descriptor, load_glsl_shader, ShaderStage, RASTERIZER_NO_CULLING};
+use crate::ui::tooltip::{
+    tooltip_position, TooltipDelay, TooltipPrimitive, TOOLTIP_COLOR, TOOLTIP_FONT_SIZE, TOOLTIP_PADDING,
+    TOOLTIP_TEXT_COLOR, TOOLTIP_Z_INDEX,
+};
+use crate::ui::{PrimitiveBuffer, RectanglePrimitive, TextPart, TextPrimitive, TrianglesPrimitive, UiLayer, ZIndex};
 use crate::window::{WindowBuffers, WindowData};
-use std::collections::{BTreeMap, HashMap};
-use wgpu_glyph::{FontId, ab_glyph::FontVec};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Range;
+use std::time::Instant;
+use wgpu_glyph::{FontId, GlyphCruncher, ab_glyph::{FontVec, PxScale}};
 
 pub struct UiRenderer {
     // Glyph rendering
@@ -18,6 +24,7 @@ pub struct UiRenderer {
     pipeline: wgpu::RenderPipeline,
     vertex_buffer: DynamicBuffer<UiVertex>,
     index_buffer: DynamicBuffer<u32>,
+    tooltip_delay: TooltipDelay,
 }
 
 impl<'a> UiRenderer {
@@ -87,19 +94,41 @@ impl<'a> UiRenderer {
 
         log::trace!("Creating pipeline.");
 
-        let pipeline = super::init::create_default_pipeline(
-            device,
-            &uniform_layout,
-            vertex_shader,
-            fragment_shader,
-            wgpu::PrimitiveTopology::TriangleList,
-            wgpu::VertexBufferDescriptor {
-                stride: std::mem::size_of::<UiVertex>() as u64,
-                step_mode: wgpu::InputStepMode::Vertex,
-                attributes: &UI_VERTEX_ATTRIBUTES,
+        // The Ui is drawn on the resolved frame in draw order, without depth test
+        let vertex_shader_module = device.create_shader_module(vertex_shader);
+        let fragment_shader_module = device.create_shader_module(fragment_shader);
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&uniform_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("ui_pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vertex_shader_module,
+                entry_point: "main",
             },
-            false,
-        );
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &fragment_shader_module,
+                entry_point: "main",
+            }),
+            vertex_state: wgpu::VertexStateDescriptor {
+                index_format: wgpu::IndexFormat::Uint32,
+                vertex_buffers: &[wgpu::VertexBufferDescriptor {
+                    stride: std::mem::size_of::<UiVertex>() as u64,
+                    step_mode: wgpu::InputStepMode::Vertex,
+                    attributes: &UI_VERTEX_ATTRIBUTES,
+                }],
+            },
+            rasterization_state: Some(RASTERIZER_NO_CULLING),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            color_states: &[default_color_state_descriptor(crate::window::COLOR_FORMAT)],
+            depth_stencil_state: None,
+            sample_count: 1,
+            sample_mask: 0xFFFFFFFF,
+            alpha_to_coverage_enabled: false,
+        });
 
         log::trace!("Created pipeline.");
 
@@ -111,6 +140,7 @@ impl<'a> UiRenderer {
             pipeline,
             vertex_buffer: DynamicBuffer::with_capacity(device, 64, wgpu::BufferUsage::VERTEX),
             index_buffer: DynamicBuffer::with_capacity(device, 64, wgpu::BufferUsage::INDEX),
+            tooltip_delay: TooltipDelay::default(),
         }
     }
 
@@ -124,134 +154,11 @@ impl<'a> UiRenderer {
         gui: &mut crate::gui::Gui,
         draw_crosshair: bool,
     ) {
-        // Render test dropdown
         let mut primitive_buffer = gui.drain_primitives();
 
-        //ui.render(&mut primitive_buffer);
-
-        // Render primitives
-        let mut rect_vertices: Vec<UiVertex> = Vec::new();
-        let mut rect_indices: Vec<u32> = Vec::new();
-
-        use crate::ui::{RectanglePrimitive, TextPrimitive, TrianglesPrimitive};
-
-        // Rectangles
-        for RectanglePrimitive {
-            layout: l,
-            color,
-            z,
-        } in primitive_buffer.rectangle.into_iter()
-        {
-            let a = UiVertex {
-                position: [l.x, l.y, z],
-                color: color.clone(),
-            };
-            let b = UiVertex {
-                position: [l.x + l.width, l.y, z],
-                color: color.clone(),
-            };
-            let c = UiVertex {
-                position: [l.x, l.y + l.height, z],
-                color: color.clone(),
-            };
-            let d = UiVertex {
-                position: [l.x + l.width, l.y + l.height, z],
-                color: color.clone(),
-            };
-            let a_index = rect_vertices.len() as u32;
-            let b_index = a_index + 1;
-            let c_index = b_index + 1;
-            let d_index = c_index + 1;
-            rect_vertices.extend([a, b, c, d].iter());
-            rect_indices.extend([b_index, a_index, c_index, b_index, c_index, d_index].iter());
-        }
-        // Triangles
-        for TrianglesPrimitive {
-            vertices,
-            indices,
-            color,
-        } in primitive_buffer.triangles.into_iter()
-        {
-            let index_offset = rect_vertices.len() as u32;
-            rect_vertices.extend(
-                vertices
-                    .into_iter()
-                    .map(|v| UiVertex { position: v, color }),
-            );
-            rect_indices.extend(indices.into_iter().map(|id| id + index_offset));
-        }
-        // Text
-        for TextPrimitive {
-            x, y, w, h,
-            mut parts,
-            z,
-            center_horizontally, center_vertically,
-        } in primitive_buffer.text.into_iter()
-        {
-            let dpi = data.hidpi_factor as f32;
+        primitive_buffer.set_layer(UiLayer::Menu);
+        ui.render(&mut primitive_buffer);
 
-            // Apply DPI to font size
-            for p in parts.iter_mut() {
-                p.font_size.x *= dpi;
-                p.font_size.y *= dpi;
-            }
-            // Get font IDs
-            let Self { ref fonts, .. } = &self;
-            let parts: Vec<wgpu_glyph::Text> = parts
-                .iter()
-                .map(|part| wgpu_glyph::Text::new(&part.text)
-                    .with_scale(part.font_size)
-                    .with_color(part.color)
-                    .with_font_id(part
-                        .font
-                        .clone()
-                        .and_then(|f| fonts.get(&f).cloned())
-                        .unwrap_or_default())
-                )
-                .collect();
-            // Calculate positions
-            let mut x = x as f32;
-            let mut y = y as f32;
-            let mut w = match w {
-                Some(w) => w as f32,
-                None => std::f32::INFINITY,
-            };
-            let mut h = match h {
-                Some(h) => h as f32,
-                None => std::f32::INFINITY,
-            };
-            if center_horizontally {
-                x += w/2.0;
-            }
-            if center_vertically {
-                y += h/2.0;
-            }
-            // Apply DPI to positions
-            x *= dpi;
-            y *= dpi;
-            w *= dpi;
-            h *= dpi;
-            let v_align = if center_vertically {
-                wgpu_glyph::VerticalAlign::Center
-            } else {
-                wgpu_glyph::VerticalAlign::Top
-            };
-            let h_align = if center_horizontally {
-                wgpu_glyph::HorizontalAlign::Center
-            } else {
-                wgpu_glyph::HorizontalAlign::Left
-            };
-            let section = wgpu_glyph::Section::default()
-                .with_screen_position((x, y))
-                .with_bounds((w, h))
-                .with_layout(wgpu_glyph::Layout::Wrap {
-                    line_breaker: Default::default(),
-                    v_align,
-                    h_align,
-                })
-                .with_text(parts);
-            self.glyph_brush.queue(section);
-        }
         // Crosshair
         if draw_crosshair {
             let (cx, cy) = (
@@ -261,128 +168,334 @@ impl<'a> UiRenderer {
             const HALF_HEIGHT: f32 = 15.0;
             const HALF_WIDTH: f32 = 2.0;
             const COLOR: [f32; 4] = [1.0, 1.0, 1.0, 0.5];
-            let v1 = UiVertex {
-                position: [cx - HALF_WIDTH, cy - HALF_HEIGHT, -1.0],
-                color: COLOR,
-            };
-            let v2 = UiVertex {
-                position: [cx + HALF_WIDTH, cy - HALF_HEIGHT, -1.0],
-                color: COLOR,
-            };
-            let v3 = UiVertex {
-                position: [cx - HALF_WIDTH, cy + HALF_HEIGHT, -1.0],
-                color: COLOR,
-            };
-            let v4 = UiVertex {
-                position: [cx + HALF_WIDTH, cy + HALF_HEIGHT, -1.0],
-                color: COLOR,
-            };
-            let v5 = UiVertex {
-                position: [cx - HALF_HEIGHT, cy - HALF_WIDTH, -1.0],
-                color: COLOR,
-            };
-            let v6 = UiVertex {
-                position: [cx + HALF_HEIGHT, cy - HALF_WIDTH, -1.0],
-                color: COLOR,
-            };
-            let v7 = UiVertex {
-                position: [cx - HALF_HEIGHT, cy + HALF_WIDTH, -1.0],
-                color: COLOR,
-            };
-            let v8 = UiVertex {
-                position: [cx + HALF_HEIGHT, cy + HALF_WIDTH, -1.0],
-                color: COLOR,
-            };
-            let voffset = rect_vertices.len() as u32;
-            rect_vertices.extend([v1, v2, v3, v4, v5, v6, v7, v8].iter());
-            rect_indices.extend(
-                [0, 1, 2, 1, 2, 3, 4, 5, 6, 5, 6, 7]
-                    .iter()
-                    .map(|id| id + voffset),
+            primitive_buffer.set_layer(UiLayer::Hud);
+            primitive_buffer.draw_triangles(
+                vec![
+                    [cx - HALF_WIDTH, cy - HALF_HEIGHT],
+                    [cx + HALF_WIDTH, cy - HALF_HEIGHT],
+                    [cx - HALF_WIDTH, cy + HALF_HEIGHT],
+                    [cx + HALF_WIDTH, cy + HALF_HEIGHT],
+                    [cx - HALF_HEIGHT, cy - HALF_WIDTH],
+                    [cx + HALF_HEIGHT, cy - HALF_WIDTH],
+                    [cx - HALF_HEIGHT, cy + HALF_WIDTH],
+                    [cx + HALF_HEIGHT, cy + HALF_WIDTH],
+                ],
+                vec![0, 1, 2, 1, 2, 3, 4, 5, 6, 5, 6, 7],
+                COLOR,
             );
         }
 
-        // Draw rectangles
-        {
-            let (win_w, win_h) = (
-                data.logical_window_size.width,
-                data.logical_window_size.height,
-            );
-            // Update the uniform buffer to map (w, h) coordinates to [-1, 1]
-            let transformation_matrix = [
-                2.0 / win_w as f32,
-                0.0,
-                0.0,
-                0.0,
-                0.0,
-                -2.0 / win_h as f32,
-                0.0,
-                0.0,
-                0.0,
-                0.0,
-                0.5,
-                0.0,
-                -1.0,
-                1.0,
-                0.5,
-                1.0,
-            ];
-            let src_buffer = buffer_from_slice(
-                device,
-                wgpu::BufferUsage::COPY_SRC,
-                to_u8_slice(&transformation_matrix[..])
-            );
-            encoder.copy_buffer_to_buffer(&src_buffer, 0, &self.transform_buffer, 0, 16 * 4);
-            // Update vertex buffer
-            self.vertex_buffer.upload(device, encoder, &rect_vertices);
-            // Update index buffer
-            self.index_buffer.upload(device, encoder, &rect_indices);
-            // Draw
-            {
-                let mut rpass = super::render::create_default_render_pass(encoder, buffers);
+        // Tooltip of the hovered element
+        let tooltip = primitive_buffer.tooltip.take();
+        if let Some(tooltip) = self.tooltip_delay.update(tooltip, Instant::now()) {
+            self.draw_tooltip(&mut primitive_buffer, tooltip, data);
+        }
+
+        // Render primitives, grouped by z-index in draw order
+        primitive_buffer.sort();
+        let z_indices: BTreeSet<ZIndex> = primitive_buffer.rectangle.iter().map(|r| r.z)
+            .chain(primitive_buffer.triangles.iter().map(|t| t.z))
+            .chain(primitive_buffer.text.iter().map(|t| t.z))
+            .collect();
+        let mut rect_vertices: Vec<UiVertex> = Vec::new();
+        let mut rect_indices: Vec<u32> = Vec::new();
+        // The range of indices, the text and the clipping area of every z-index
+        let mut batches: Vec<(Range<u32>, Vec<TextPrimitive>, Option<ScissorRect>)> = Vec::new();
+        let clips = primitive_buffer.clips;
+        let mut rectangles = primitive_buffer.rectangle.into_iter().peekable();
+        let mut triangles = primitive_buffer.triangles.into_iter().peekable();
+        let mut text = primitive_buffer.text.into_iter().peekable();
+
+        for z in z_indices {
+            let first_index = rect_indices.len() as u32;
+            // Rectangles
+            while rectangles.peek().map(|r| r.z) == Some(z) {
+                let RectanglePrimitive { layout: l, color, .. } = rectangles.next().unwrap();
+                let a = UiVertex {
+                    position: [l.x, l.y],
+                    color,
+                };
+                let b = UiVertex {
+                    position: [l.x + l.width, l.y],
+                    color,
+                };
+                let c = UiVertex {
+                    position: [l.x, l.y + l.height],
+                    color,
+                };
+                let d = UiVertex {
+                    position: [l.x + l.width, l.y + l.height],
+                    color,
+                };
+                let a_index = rect_vertices.len() as u32;
+                let b_index = a_index + 1;
+                let c_index = b_index + 1;
+                let d_index = c_index + 1;
+                rect_vertices.extend([a, b, c, d].iter());
+                rect_indices.extend([b_index, a_index, c_index, b_index, c_index, d_index].iter());
+            }
+            // Triangles
+            while triangles.peek().map(|t| t.z) == Some(z) {
+                let TrianglesPrimitive { vertices, indices, color, .. } = triangles.next().unwrap();
+                let index_offset = rect_vertices.len() as u32;
+                rect_vertices.extend(
+                    vertices
+                        .into_iter()
+                        .map(|v| UiVertex { position: v, color }),
+                );
+                rect_indices.extend(indices.into_iter().map(|id| id + index_offset));
+            }
+            // Text is drawn over the rectangles and the triangles of the same z-index
+            let mut batch_text = Vec::new();
+            while text.peek().map(|t| t.z) == Some(z) {
+                batch_text.push(text.next().unwrap());
+            }
+            let scissor = clips[z.clip].map(|clip| scissor_region(clip, data));
+            batches.push((first_index..rect_indices.len() as u32, batch_text, scissor));
+        }
+
+        // Upload the rectangles
+        let (win_w, win_h) = (
+            data.logical_window_size.width,
+            data.logical_window_size.height,
+        );
+        // Update the uniform buffer to map (w, h) coordinates to [-1, 1]
+        let transformation_matrix = [
+            2.0 / win_w as f32,
+            0.0,
+            0.0,
+            0.0,
+            0.0,
+            -2.0 / win_h as f32,
+            0.0,
+            0.0,
+            0.0,
+            0.0,
+            0.5,
+            0.0,
+            -1.0,
+            1.0,
+            0.5,
+            1.0,
+        ];
+        let src_buffer = buffer_from_slice(
+            device,
+            wgpu::BufferUsage::COPY_SRC,
+            to_u8_slice(&transformation_matrix[..])
+        );
+        encoder.copy_buffer_to_buffer(&src_buffer, 0, &self.transform_buffer, 0, 16 * 4);
+        // Update vertex buffer
+        self.vertex_buffer.upload(device, encoder, &rect_vertices);
+        // Update index buffer
+        self.index_buffer.upload(device, encoder, &rect_indices);
+
+        // Resolve !
+        // The Ui is drawn on the resolved frame, so that the text can be drawn between the rectangles
+        super::render::encode_resolve_render_pass(encoder, buffers);
+
+        let mut staging_belt = wgpu::util::StagingBelt::new(128);
+        for (indices, batch_text, scissor) in batches.into_iter() {
+            // Nothing is visible if the clipping area is outside of the window
+            if scissor.map_or(false, |(_, _, width, height)| width == 0 || height == 0) {
+                continue;
+            }
+            // Draw rectangles
+            if !indices.is_empty() {
+                let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                        attachment: buffers.texture_buffer,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true
+                        },
+                    }],
+                    depth_stencil_attachment: None,
+                });
                 rpass.set_pipeline(&self.pipeline);
                 rpass.set_bind_group(0, &self.uniforms_bind_group, &[]);
                 rpass.set_vertex_buffer(0, self.vertex_buffer.get_buffer().slice(..));
                 rpass.set_index_buffer(self.index_buffer.get_buffer().slice(..));
-                rpass.draw_indexed(0..(self.index_buffer.len() as u32), 0, 0..1);
+                if let Some((x, y, width, height)) = scissor {
+                    rpass.set_scissor_rect(x, y, width, height);
+                }
+                debug_assert!(indices.end as usize <= self.index_buffer.len(), "UI batch past the end of the index buffer");
+                rpass.draw_indexed(indices, 0, 0..1);
             }
+
+            // Draw text
+            if batch_text.is_empty() {
+                continue;
+            }
+            for text in batch_text.into_iter() {
+                self.queue_text(text, data);
+            }
+            let (width, height) = (data.physical_window_size.width, data.physical_window_size.height);
+            match scissor {
+                Some((x, y, scissor_width, scissor_height)) => self.glyph_brush.draw_queued_with_transform_and_scissoring(
+                    device,
+                    &mut staging_belt,
+                    encoder,
+                    buffers.texture_buffer,
+                    wgpu_glyph::orthographic_projection(width, height),
+                    wgpu_glyph::Region { x, y, width: scissor_width, height: scissor_height },
+                ),
+                None => self.glyph_brush.draw_queued(
+                    device,
+                    &mut staging_belt,
+                    encoder,
+                    buffers.texture_buffer,
+                    width,
+                    height,
+                ),
+            }
+            .expect("couldn't draw queued glyphs");
         }
+        staging_belt.finish();
+    }
 
-        // Resolve !
-        super::render::encode_resolve_render_pass(encoder, buffers);
+    /// Draw a tooltip over everything else, in a box sized to its text
+    fn draw_tooltip(&mut self, buffer: &mut PrimitiveBuffer, tooltip: TooltipPrimitive, data: &WindowData) {
+        let dpi = data.hidpi_factor as f32;
+        let section = wgpu_glyph::Section::default()
+            .with_text(vec![wgpu_glyph::Text::new(&tooltip.text).with_scale(TOOLTIP_FONT_SIZE * dpi)]);
+        let bounds = match self.glyph_brush.glyph_bounds(section) {
+            Some(bounds) => bounds,
+            None => return,
+        };
+        let (text_width, text_height) = (bounds.width() / dpi, bounds.height() / dpi);
+        let width = text_width + 2.0 * TOOLTIP_PADDING;
+        let height = text_height + 2.0 * TOOLTIP_PADDING;
+        let (x, y) = tooltip_position(
+            tooltip.x,
+            tooltip.y,
+            width,
+            height,
+            data.logical_window_size.width as f32,
+            data.logical_window_size.height as f32,
+        );
+        buffer.set_layer(UiLayer::Menu);
+        quint::Backend::set_z_index(buffer, TOOLTIP_Z_INDEX);
+        buffer.draw_rectangle(TOOLTIP_COLOR, quint::Layout { x, y, width, height });
+        let text = TextPart {
+            text: tooltip.text,
+            font_size: PxScale::from(TOOLTIP_FONT_SIZE),
+            color: TOOLTIP_TEXT_COLOR,
+            font: None,
+        };
+        // The text layout is rounded to whole pixels, so it gets the padding too to never wrap the lines
+        let layout = quint::Layout {
+            x: x + TOOLTIP_PADDING,
+            y: y + TOOLTIP_PADDING,
+            width: text_width + TOOLTIP_PADDING,
+            height: text_height + TOOLTIP_PADDING,
+        };
+        buffer.draw_text(vec![text], layout, false);
+    }
 
-        // Draw text
-        // TODO: use depth buffer
-        let mut staging_belt = wgpu::util::StagingBelt::new(128);
-        self.glyph_brush
-            .draw_queued(
-                device,
-                &mut staging_belt,
-                encoder,
-                buffers.texture_buffer,
-                //create_default_depth_stencil_attachment(buffers.depth_buffer),
-                data.physical_window_size.width,
-                data.physical_window_size.height,
+    /// Queue the text to be drawn by the next `draw_queued`
+    fn queue_text(&mut self, text: TextPrimitive, data: &WindowData) {
+        let TextPrimitive {
+            x, y, w, h,
+            mut parts,
+            center_horizontally, center_vertically,
+            ..
+        } = text;
+        let dpi = data.hidpi_factor as f32;
+
+        // Apply DPI to font size
+        for p in parts.iter_mut() {
+            p.font_size.x *= dpi;
+            p.font_size.y *= dpi;
+        }
+        // Get font IDs
+        let Self { ref fonts, .. } = &self;
+        let parts: Vec<wgpu_glyph::Text> = parts
+            .iter()
+            .map(|part| wgpu_glyph::Text::new(&part.text)
+                .with_scale(part.font_size)
+                .with_color(part.color)
+                .with_font_id(part
+                    .font
+                    .clone()
+                    .and_then(|f| fonts.get(&f).cloned())
+                    .unwrap_or_default())
             )
-            .expect("couldn't draw queued glyphs");
+            .collect();
+        // Calculate positions
+        let mut x = x as f32;
+        let mut y = y as f32;
+        let mut w = match w {
+            Some(w) => w as f32,
+            None => std::f32::INFINITY,
+        };
+        let mut h = match h {
+            Some(h) => h as f32,
+            None => std::f32::INFINITY,
+        };
+        if center_horizontally {
+            x += w/2.0;
+        }
+        if center_vertically {
+            y += h/2.0;
+        }
+        // Apply DPI to positions
+        x *= dpi;
+        y *= dpi;
+        w *= dpi;
+        h *= dpi;
+        let v_align = if center_vertically {
+            wgpu_glyph::VerticalAlign::Center
+        } else {
+            wgpu_glyph::VerticalAlign::Top
+        };
+        let h_align = if center_horizontally {
+            wgpu_glyph::HorizontalAlign::Center
+        } else {
+            wgpu_glyph::HorizontalAlign::Left
+        };
+        let section = wgpu_glyph::Section::default()
+            .with_screen_position((x, y))
+            .with_bounds((w, h))
+            .with_layout(wgpu_glyph::Layout::Wrap {
+                line_breaker: Default::default(),
+                v_align,
+                h_align,
+            })
+            .with_text(parts);
+        self.glyph_brush.queue(section);
     }
 }
 
+/// A rectangle of physical pixels: x, y, width and height
+type ScissorRect = (u32, u32, u32, u32);
+
+/// The physical pixels of the window that are inside the logical `clip` area
+fn scissor_region(clip: quint::Layout, data: &WindowData) -> ScissorRect {
+    let dpi = data.hidpi_factor as f32;
+    let (width, height) = (data.physical_window_size.width as f32, data.physical_window_size.height as f32);
+    let x0 = (clip.x * dpi).max(0.0).min(width);
+    let y0 = (clip.y * dpi).max(0.0).min(height);
+    let x1 = ((clip.x + clip.width) * dpi).max(x0).min(width);
+    let y1 = ((clip.y + clip.height) * dpi).max(y0).min(height);
+    (x0 as u32, y0 as u32, (x1 - x0) as u32, (y1 - y0) as u32)
+}
+
 #[derive(Debug, Clone, Copy)]
 struct UiVertex {
-    position: [f32; 3],
+    position: [f32; 2],
     color: [f32; 4],
 }
 
 const UI_VERTEX_ATTRIBUTES: [wgpu::VertexAttributeDescriptor; 2] = [
     wgpu::VertexAttributeDescriptor {
         shader_location: 0,
-        format: wgpu::VertexFormat::Float3,
+        format: wgpu::VertexFormat::Float2,
         offset: 0,
     },
     wgpu::VertexAttributeDescriptor {
         shader_location: 1,
         format: wgpu::VertexFormat::Float4,
-        offset: 12,
+        offset: 8,
     },
 ];
@@ -0,0 +1,177 @@
+//! CPU cave culling of the chunks (cf: https://tomcc.github.io/2014/08/31/visibility-1.html).
+//!
+//! When a chunk is meshed, a flood fill of its non-opaque blocks finds which pairs of faces of the chunk
+//! are connected. Every frame, the chunks are traversed from the camera chunk, only leaving a chunk through
+//! a face that is connected to the face it was entered from, and never going back towards the camera.
+//! The chunks that are not reached can't be seen, for example the caves below the ground.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use voxel_rs_common::block::BlockMesh;
+use voxel_rs_common::world::{Chunk, ChunkPos, Direction, CHUNK_SIZE};
+
+/// Which pairs of faces of a chunk are connected through non-opaque blocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkVisibility(u64);
+
+impl ChunkVisibility {
+    /// Every face is connected to every other face
+    pub fn all() -> Self {
+        Self(!0)
+    }
+
+    /// No face is connected to another face
+    pub fn none() -> Self {
+        Self(0)
+    }
+
+    /// Whether the chunk can be seen through from face `a` to face `b`
+    pub fn connects(self, a: usize, b: usize) -> bool {
+        self.0 & (1 << (a * 6 + b)) != 0
+    }
+
+    fn connect(&mut self, a: usize, b: usize) {
+        self.0 |= (1 << (a * 6 + b)) | (1 << (b * 6 + a));
+    }
+
+    /// Flood fill the non-opaque blocks of the chunk to find the connected faces
+    pub fn compute(chunk: &Chunk, meshes: &[BlockMesh]) -> Self {
+        let is_opaque = |block: u16| meshes[block as usize].is_opaque();
+        // Most chunks are only air or only stone
+        if chunk.palette().iter().all(|&block| !is_opaque(block)) {
+            return Self::all();
+        }
+        if chunk.palette().iter().all(|&block| is_opaque(block)) {
+            return Self::none();
+        }
+
+        const SIZE: usize = CHUNK_SIZE as usize;
+        let index = |x: usize, y: usize, z: usize| x * SIZE * SIZE + y * SIZE + z;
+        // Opaque blocks are marked as visited from the start
+        let mut visited = vec![false; SIZE * SIZE * SIZE];
+        for x in 0..SIZE {
+            for y in 0..SIZE {
+                for z in 0..SIZE {
+                    visited[index(x, y, z)] = is_opaque(chunk.get_block_at((x as u32, y as u32, z as u32)));
+                }
+            }
+        }
+
+        let mut visibility = Self::none();
+        let mut stack = Vec::new();
+        for start in 0..visited.len() {
+            if visited[start] {
+                continue;
+            }
+            visited[start] = true;
+            stack.push((start / (SIZE * SIZE), (start / SIZE) % SIZE, start % SIZE));
+            let mut touched_faces = [false; 6];
+            while let Some((x, y, z)) = stack.pop() {
+                let pos = [x, y, z];
+                for dir in Direction::iter() {
+                    let (face, axis) = (dir.face(), dir.face() / 2);
+                    let (dx, dy, dz) = dir.offset();
+                    let coord = pos[axis] as i64 + [dx, dy, dz][axis];
+                    if coord < 0 || coord >= SIZE as i64 {
+                        touched_faces[face] = true;
+                        continue;
+                    }
+                    let mut next = pos;
+                    next[axis] = coord as usize;
+                    let next_index = index(next[0], next[1], next[2]);
+                    if !visited[next_index] {
+                        visited[next_index] = true;
+                        stack.push((next[0], next[1], next[2]));
+                    }
+                }
+            }
+            for a in 0..6 {
+                for b in 0..6 {
+                    if a != b && touched_faces[a] && touched_faces[b] {
+                        visibility.connect(a, b);
+                    }
+                }
+            }
+        }
+        visibility
+    }
+}
+
+/// Find the chunks that may be visible from the camera chunk.
+/// Only the chunks in `visibilities` and accepted by `in_frustum` are traversed.
+pub fn find_visible_chunks(
+    camera_chunk: ChunkPos,
+    visibilities: &HashMap<ChunkPos, ChunkVisibility>,
+    mut in_frustum: impl FnMut(ChunkPos) -> bool,
+) -> HashSet<ChunkPos> {
+    let mut visible = HashSet::new();
+    visible.insert(camera_chunk);
+    // The chunk, the face it was entered from, and the directions taken so far as a bitset of faces
+    let mut queue = VecDeque::new();
+    for dir in Direction::iter() {
+        queue.push_back((camera_chunk.neighbor(dir), dir.opposite().face(), 1u8 << dir.face()));
+    }
+    while let Some((pos, entry_face, directions)) = queue.pop_front() {
+        let visibility = match visibilities.get(&pos) {
+            Some(&visibility) => visibility,
+            None => continue,
+        };
+        if visible.contains(&pos) || !in_frustum(pos) {
+            continue;
+        }
+        visible.insert(pos);
+        for dir in Direction::iter() {
+            // Never go back towards the camera
+            if directions & (1 << dir.opposite().face()) != 0 || !visibility.connects(entry_face, dir.face()) {
+                continue;
+            }
+            let next = pos.neighbor(dir);
+            if !visible.contains(&next) {
+                queue.push_back((next, dir.opposite().face(), directions | (1 << dir.face())));
+            }
+        }
+    }
+    visible
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use voxel_rs_common::data::TextureRect;
+
+    #[test]
+    fn test_cave_culling() {
+        let texture = TextureRect { x: 0.0, y: 0.0, width: 0.0, height: 0.0, frames: 1 };
+        let meshes = vec![
+            BlockMesh::Empty,
+            BlockMesh::FullCube { textures: [texture; 6], frame_time: 0.0, seasonal: false },
+        ];
+
+        // A stone chunk with a tunnel along the x axis
+        let mut tunnel = Chunk::new((0, 0, 0).into());
+        tunnel.fill(1);
+        for x in 0..CHUNK_SIZE {
+            tunnel.set_block_at((x, 5, 5), 0);
+        }
+        let tunnel_visibility = ChunkVisibility::compute(&tunnel, &meshes);
+        assert!(tunnel_visibility.connects(0, 1));
+        assert!(tunnel_visibility.connects(1, 0));
+        assert!(!tunnel_visibility.connects(0, 2));
+        assert!(!tunnel_visibility.connects(2, 3));
+        assert_eq!(ChunkVisibility::compute(&Chunk::new((0, 0, 0).into()), &meshes), ChunkVisibility::all());
+
+        // The camera is in an air chunk, the chunks along +x are tunnels and the chunk above the tunnels is air
+        let mut visibilities = HashMap::new();
+        visibilities.insert(ChunkPos::from((0, 0, 0)), ChunkVisibility::all());
+        for x in 1..4 {
+            visibilities.insert(ChunkPos::from((x, 0, 0)), tunnel_visibility);
+        }
+        visibilities.insert(ChunkPos::from((2, 1, 0)), ChunkVisibility::all());
+        let visible = find_visible_chunks((0, 0, 0).into(), &visibilities, |_| true);
+        assert!(visible.contains(&(3, 0, 0).into()));
+        assert!(!visible.contains(&(2, 1, 0).into()));
+        // The chunks outside the frustum are not traversed
+        let visible = find_visible_chunks((0, 0, 0).into(), &visibilities, |pos| pos.px < 2);
+        assert!(visible.contains(&(1, 0, 0).into()));
+        assert!(!visible.contains(&(3, 0, 0).into()));
+    }
+}
@@ -0,0 +1,137 @@
+//! Indirect drawing of the chunks.
+//!
+//! The draw commands of the visible chunks are stored in a GPU buffer, and all the chunks are drawn with a
+//! single `multi_draw_indexed_indirect` call if the device supports it. The commands are only rebuilt and
+//! uploaded when the set of drawn chunks or the chunk buffers change.
+
+use crate::render::buffers::DynamicBuffer;
+use voxel_rs_common::world::ChunkPos;
+
+/// The arguments of an indexed draw, in the layout expected by the indirect draw calls
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DrawIndexedIndirect {
+    pub index_count: u32,
+    pub instance_count: u32,
+    pub base_index: u32,
+    pub vertex_offset: i32,
+    pub base_instance: u32,
+}
+
+/// The draw commands of the chunks drawn during the previous frame
+#[derive(Debug, Default)]
+struct DrawCommandList {
+    chunks: Vec<ChunkPos>,
+    commands: Vec<DrawIndexedIndirect>,
+    /// `true` if the chunk buffers changed, so the commands must be rebuilt even if the same chunks are drawn
+    dirty: bool,
+}
+
+impl DrawCommandList {
+    /// Rebuild the commands if the drawn chunks changed.
+    /// `locate` returns the index range and the vertex offset of a chunk in the chunk buffers.
+    /// Return `true` if the commands were rebuilt.
+    fn update(&mut self, chunks: &[ChunkPos], mut locate: impl FnMut(ChunkPos) -> (usize, usize, usize)) -> bool {
+        if !self.dirty && self.chunks == chunks {
+            return false;
+        }
+        self.dirty = false;
+        self.chunks.clear();
+        self.chunks.extend_from_slice(chunks);
+        self.commands.clear();
+        self.commands.extend(chunks.iter().map(|&chunk_pos| {
+            let (index_pos, index_len, vertex_pos) = locate(chunk_pos);
+            DrawIndexedIndirect {
+                index_count: index_len as u32,
+                instance_count: 1,
+                base_index: index_pos as u32,
+                vertex_offset: vertex_pos as i32,
+                base_instance: 0,
+            }
+        }));
+        true
+    }
+}
+
+/// The draw commands of the chunks, on the CPU and in an indirect buffer
+pub struct ChunkDrawCommands {
+    list: DrawCommandList,
+    indirect_buffer: DynamicBuffer<DrawIndexedIndirect>,
+    /// `true` if the device can draw all the chunks with a single call
+    multi_draw: bool,
+}
+
+impl ChunkDrawCommands {
+    pub fn new(device: &wgpu::Device) -> Self {
+        Self {
+            list: DrawCommandList::default(),
+            indirect_buffer: DynamicBuffer::with_capacity(device, 256, wgpu::BufferUsage::INDIRECT),
+            multi_draw: device.features().contains(wgpu::Features::MULTI_DRAW_INDIRECT),
+        }
+    }
+
+    /// Rebuild the commands during the next update, because the chunk buffers changed
+    pub fn invalidate(&mut self) {
+        self.list.dirty = true;
+    }
+
+    /// Set the chunks to draw, uploading the new commands if they changed
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        chunks: &[ChunkPos],
+        locate: impl FnMut(ChunkPos) -> (usize, usize, usize),
+    ) {
+        if self.list.update(chunks, locate) && self.multi_draw {
+            self.indirect_buffer.upload(device, encoder, &self.list.commands);
+        }
+    }
+
+    /// Draw the chunks. The chunk pipeline and buffers must already be bound.
+    pub fn draw<'a>(&'a self, rpass: &mut wgpu::RenderPass<'a>) {
+        if self.multi_draw {
+            if !self.list.commands.is_empty() {
+                rpass.multi_draw_indexed_indirect(
+                    self.indirect_buffer.get_buffer(),
+                    0,
+                    self.list.commands.len() as u32,
+                );
+            }
+        } else {
+            // Without multi draw, the commands that were generated once are still reused every frame
+            for command in self.list.commands.iter() {
+                rpass.draw_indexed(
+                    command.base_index..(command.base_index + command.index_count),
+                    command.vertex_offset,
+                    0..1,
+                );
+            }
+        }
+    }
+
+    pub fn num_drawn_chunks(&self) -> usize {
+        self.list.commands.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_draw_commands_rebuild() {
+        let mut list = DrawCommandList::default();
+        let chunks = [ChunkPos::from((0, 0, 0)), ChunkPos::from((1, 0, 0))];
+        let locate = |chunk_pos: ChunkPos| (chunk_pos.px as usize * 100, 36, chunk_pos.px as usize * 24);
+        assert!(list.update(&chunks, locate));
+        assert_eq!(list.commands[1].base_index, 100);
+        assert_eq!(list.commands[1].vertex_offset, 24);
+        // The same chunks don't rebuild the commands, unless the buffers changed
+        assert!(!list.update(&chunks, locate));
+        list.dirty = true;
+        assert!(list.update(&chunks, locate));
+        assert!(list.update(&chunks[..1], locate));
+        assert_eq!(list.commands.len(), 1);
+    }
+}
@@ -0,0 +1,65 @@
+//! Lines drawn on top of the world to debug the physics and the block picking
+
+use nalgebra::Vector3;
+
+/// Vertex of a debug line
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct DebugLineVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 3],
+}
+
+/// Debug line vertex attributes
+pub(super) const DEBUG_LINE_VERTEX_ATTRIBUTES: [wgpu::VertexAttributeDescriptor; 2] = [
+    wgpu::VertexAttributeDescriptor {
+        shader_location: 0,
+        format: wgpu::VertexFormat::Float3,
+        offset: 0,
+    },
+    wgpu::VertexAttributeDescriptor {
+        shader_location: 1,
+        format: wgpu::VertexFormat::Float3,
+        offset: 4 * 3,
+    },
+];
+
+/// A list of lines, in world coordinates
+#[derive(Debug, Clone, Default)]
+pub struct DebugLines {
+    pub(super) vertices: Vec<DebugLineVertex>,
+}
+
+impl DebugLines {
+    pub fn add_line(&mut self, from: Vector3<f64>, to: Vector3<f64>, color: [f32; 3]) {
+        for pos in [from, to].iter() {
+            self.vertices.push(DebugLineVertex {
+                position: [pos.x as f32, pos.y as f32, pos.z as f32],
+                color,
+            });
+        }
+    }
+
+    /// Add the 12 edges of a box
+    pub fn add_box(&mut self, min: Vector3<f64>, size: Vector3<f64>, color: [f32; 3]) {
+        let corner = |i: usize| {
+            min + Vector3::new(
+                if i & 1 != 0 { size.x } else { 0.0 },
+                if i & 2 != 0 { size.y } else { 0.0 },
+                if i & 4 != 0 { size.z } else { 0.0 },
+            )
+        };
+        for i in 0..8 {
+            for &axis in [1, 2, 4].iter() {
+                // Every edge goes from a corner without the axis bit to the corner with it
+                if i & axis == 0 {
+                    self.add_line(corner(i), corner(i | axis), color);
+                }
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+}
@@ -0,0 +1,65 @@
+//! Dynamic point lights, rendered on top of the voxel light
+
+use nalgebra::Vector3;
+
+/// Maximum number of dynamic lights rendered at the same time, must match world.frag
+pub const MAX_DYNAMIC_LIGHTS: usize = 8;
+/// Size of the dynamic lights uniform: a header with the number of lights and the time, then 2 vec4 per light
+pub(super) const DYNAMIC_LIGHTS_UNIFORM_SIZE: u64 = 16 + 32 * MAX_DYNAMIC_LIGHTS as u64;
+
+/// A light attached to an entity, for example a held torch or an explosion
+#[derive(Debug, Clone, Copy)]
+pub struct PointLight {
+    pub position: Vector3<f64>,
+    /// Distance at which the light fades out completely
+    pub radius: f32,
+    /// Color of the light, it can be brighter than 1 since the world is rendered in HDR
+    pub color: [f32; 3],
+    /// How much the intensity of the light flickers, 0 for a steady light
+    pub flicker: f32,
+}
+
+impl PointLight {
+    /// The light of a torch held by a player
+    pub fn held_torch(position: Vector3<f64>) -> Self {
+        Self {
+            position,
+            radius: 10.0,
+            color: [1.5, 1.1, 0.6],
+            flicker: 0.15,
+        }
+    }
+}
+
+/// Encode the lights closest to `camera_position` in the layout of the dynamic lights uniform
+pub(super) fn encode_dynamic_lights(
+    lights: &[PointLight],
+    camera_position: Vector3<f64>,
+    time: f32,
+) -> Vec<f32> {
+    let mut lights = lights.to_vec();
+    lights.sort_by(|a, b| {
+        let da = (a.position - camera_position).norm_squared();
+        let db = (b.position - camera_position).norm_squared();
+        da.partial_cmp(&db).unwrap()
+    });
+    lights.truncate(MAX_DYNAMIC_LIGHTS);
+
+    let mut data = vec![0.0; DYNAMIC_LIGHTS_UNIFORM_SIZE as usize / 4];
+    data[0] = lights.len() as f32;
+    data[1] = time;
+    for (i, light) in lights.iter().enumerate() {
+        let offset = 4 + 8 * i;
+        data[offset..offset + 8].copy_from_slice(&[
+            light.position.x as f32,
+            light.position.y as f32,
+            light.position.z as f32,
+            light.radius,
+            light.color[0],
+            light.color[1],
+            light.color[2],
+            light.flicker,
+        ]);
+    }
+    data
+}
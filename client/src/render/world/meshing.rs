@@ -3,9 +3,10 @@ use super::ChunkVertex;
 use std::sync::Arc;
 use voxel_rs_common::world::LightChunk;
 use voxel_rs_common::{
-    block::BlockMesh,
+    block::{BlockId, BlockMesh},
     collections::zero_initialized_vec,
-    world::{Chunk, CHUNK_SIZE},
+    config::MAX_LIGHT_LEVEL,
+    world::{Chunk, Direction, Neighborhood27, CHUNK_SIZE},
 };
 
 #[derive(Clone, Copy, Default)]
@@ -27,15 +28,6 @@ impl Quad {
     }
 }
 
-const D: [[i32; 3]; 6] = [
-    [1, 0, 0],
-    [-1, 0, 0],
-    [0, 1, 0],
-    [0, -1, 0],
-    [0, 0, 1],
-    [0, 0, -1],
-];
-
 /// Ambient occlusion code (cf : https://0fps.net/2013/07/03/ambient-occlusion-for-minecraft-like-worlds/)
 fn ambiant_occl(corners: u32, edge: u32) -> u32 {
     if edge == 2 {
@@ -49,16 +41,39 @@ fn ambiant_occl(corners: u32, edge: u32) -> u32 {
     }
 }
 
+/// Texture coordinates of the 4 vertices of the quads of every face
+const FACE_UVS: [[[f32; 2]; 4]; 6] = [
+    [[1.0, 1.0], [0.0, 1.0], [1.0, 0.0], [0.0, 0.0]],
+    [[0.0, 1.0], [1.0, 1.0], [0.0, 0.0], [1.0, 0.0]],
+    [[0.0, 0.0], [0.0, 1.0], [1.0, 0.0], [1.0, 1.0]],
+    [[1.0, 0.0], [1.0, 1.0], [0.0, 0.0], [0.0, 1.0]],
+    [[0.0, 1.0], [0.0, 0.0], [1.0, 1.0], [1.0, 0.0]],
+    [[1.0, 1.0], [1.0, 0.0], [0.0, 1.0], [0.0, 0.0]],
+];
+
+/// Indices of the vertices of the 2 triangles of the quads of every face
+const QUAD_INDICES: [[usize; 6]; 6] = [
+    [0, 2, 3, 0, 3, 1],
+    [0, 3, 2, 0, 1, 3],
+    [0, 3, 2, 0, 1, 3],
+    [0, 2, 3, 0, 3, 1],
+    [1, 0, 3, 2, 3, 0],
+    [1, 3, 0, 2, 0, 3],
+];
+
 /// The chunk-specific data that is needed to mesh it.
 pub struct ChunkMeshData {
     /// The chunk to mesh
     pub chunk: Arc<Chunk>,
-    /// The chunks that are adjacent to the current chunk (the value at position 9+3+1, i.e. the current chunk, doesn't matter)
+    /// The chunks that are adjacent to the current chunk (the value at `Neighborhood27::CENTER`, i.e. the current chunk, doesn't matter)
     pub all_chunks: [Option<Arc<Chunk>>; 27],
     /// The light chunk of the current chunk
     pub light_chunk: Arc<LightChunk>,
     /// The light chunks that are adjacent to the current light chunk
     pub all_light_chunks: [Option<Arc<LightChunk>>; 27],
+    /// Side of the cubes of blocks that are merged into one for the level of detail, 1 for full detail.
+    /// See `lod_meshing`.
+    pub lod_scale: u32,
 }
 
 /// Greedy meshing : compressed adjacent quads, return the number of uncompressed and compressed quads
@@ -84,7 +99,7 @@ pub fn greedy_meshing(
 
     const N_SIZE: usize = (CHUNK_SIZE + 2) as usize;
     let mut chunk_mask = [false; N_SIZE * N_SIZE * N_SIZE];
-    let mut light_levels = [15; N_SIZE * N_SIZE * N_SIZE];
+    let mut light_levels = [MAX_LIGHT_LEVEL; N_SIZE * N_SIZE * N_SIZE];
 
     #[inline(always)]
     fn ind(x: i32, y: i32, z: i32) -> usize {
@@ -109,7 +124,7 @@ pub fn greedy_meshing(
                 1
             }
         }
-        9 * f(x) + 3 * f(y) + f(z)
+        Neighborhood27::index(f(x), f(y), f(z))
     }
 
     #[inline(always)]
@@ -135,7 +150,7 @@ pub fn greedy_meshing(
         for j in 0..N_SIZE {
             for k in 0..N_SIZE {
                 let ci = chunk_index(i, j, k);
-                if ci == 13 {
+                if ci == Neighborhood27::CENTER {
                     unsafe {
                         let u_ind = uind(i, j, k);
 
@@ -145,7 +160,6 @@ pub fn greedy_meshing(
                             k as u32 - 1,
                         )) as usize))
                             .is_opaque();
-                        // 13 = 9 + 3 + 1 is the current chunk
                         *chunk_mask.get_unchecked_mut(u_ind) = masked;
 
                         if masked {
@@ -174,31 +188,6 @@ pub fn greedy_meshing(
     }
 
 
-    const D_DELTA0: [[i32; 3]; 6] = [
-        [1, 0, 0],
-        [1, 0, 0],
-        [0, 1, 0],
-        [0, 1, 0],
-        [0, 0, 1],
-        [0, 0, 1],
-    ];
-    const D_DELTA1: [[i32; 3]; 6] = [
-        [0, 1, 0],
-        [0, 1, 0],
-        [1, 0, 0],
-        [1, 0, 0],
-        [1, 0, 0],
-        [1, 0, 0],
-    ];
-    const D_DELTA2: [[i32; 3]; 6] = [
-        [0, 0, 1],
-        [0, 0, 1],
-        [0, 0, 1],
-        [0, 0, 1],
-        [0, 1, 0],
-        [0, 1, 0],
-    ];
-
     quads.resize(
         6 * (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize,
         Quad::default(),
@@ -217,6 +206,8 @@ pub fn greedy_meshing(
     for s in 0..6 {
         let mut opaque_blocks_count_pass = opaque_blocks_count;
         // each direction
+        let (dx, dy, dz) = Direction::ALL[s].offset();
+        let d = [dx as i32, dy as i32, dz as i32];
         'faces: for j in 0..(CHUNK_SIZE as i32) {
             for i in 0..(CHUNK_SIZE as i32) {
                 for k in 0..(CHUNK_SIZE as i32) {
@@ -225,18 +216,19 @@ pub fn greedy_meshing(
                             opaque_blocks_count_pass -= 1;
                             *to_mesh_faces.get_unchecked_mut(s) += 1;
                             //checking if not void
-                            if !*chunk_mask.get_unchecked(ind(i + 1 + D[s][0], j + 1 + D[s][1], k + 1 + D[s][2])) {
+                            if !*chunk_mask.get_unchecked(ind(i + 1 + d[0], j + 1 + d[1], k + 1 + d[2])) {
                                 let mut coins = [0; 4];
                                 let mut edge = [0; 4];
+                                let [t1, t2] = Direction::TANGENTS[s];
 
                                 for i2 in -1..=1 {
                                     for j2 in -1..=1 {
                                         let dx =
-                                            1 + D[s][0] + D_DELTA1[s][0] * i2 + D_DELTA2[s][0] * j2;
+                                            1 + d[0] + t1[0] * i2 + t2[0] * j2;
                                         let dy =
-                                            1 + D[s][1] + D_DELTA1[s][1] * i2 + D_DELTA2[s][1] * j2;
+                                            1 + d[1] + t1[1] * i2 + t2[1] * j2;
                                         let dz =
-                                            1 + D[s][2] + D_DELTA1[s][2] * i2 + D_DELTA2[s][2] * j2;
+                                            1 + d[2] + t1[2] * i2 + t2[2] * j2;
 
                                         if *chunk_mask.get_unchecked(ind(i + dx, j + dy, k + dz)) {
                                             match (i2, j2) {
@@ -275,7 +267,7 @@ pub fn greedy_meshing(
                                 }
 
                                 let light_level = *light_levels
-                                    .get_unchecked(ind(i + 1 + D[s][0], j + 1 + D[s][1], k + 1 + D[s][2]));
+                                    .get_unchecked(ind(i + 1 + d[0], j + 1 + d[1], k + 1 + d[2]));
                                 let quad = Quad {
                                     v1: (s as u32)
                                         + (ambiant_occl(coins[0], edge[0]) << 3)
@@ -315,23 +307,9 @@ pub fn greedy_meshing(
         [3, 2, 1, 2, 0, 1],
     ];
 
-    let order2 = [
-        [0, 2, 3, 0, 3, 1],
-        [0, 3, 2, 0, 1, 3],
-        [0, 3, 2, 0, 1, 3],
-        [0, 2, 3, 0, 3, 1],
-        [1, 0, 3, 2, 3, 0],
-        [1, 3, 0, 2, 0, 3],
-    ];
+    let order2 = QUAD_INDICES;
 
-    let uvs = [
-        [[1.0, 1.0], [0.0, 1.0], [1.0, 0.0], [0.0, 0.0]],
-        [[0.0, 1.0], [1.0, 1.0], [0.0, 0.0], [1.0, 0.0]],
-        [[0.0, 0.0], [0.0, 1.0], [1.0, 0.0], [1.0, 1.0]],
-        [[1.0, 0.0], [1.0, 1.0], [0.0, 0.0], [0.0, 1.0]],
-        [[0.0, 1.0], [0.0, 0.0], [1.0, 1.0], [1.0, 0.0]],
-        [[1.0, 1.0], [1.0, 0.0], [0.0, 1.0], [0.0, 0.0]],
-    ];
+    let uvs = FACE_UVS;
     let uv_directions = [[1, 0], [1, 0], [0, 1], [0, 1], [0, 1], [0, 1]];
 
     for s in 0..6 {
@@ -339,9 +317,8 @@ pub fn greedy_meshing(
 
         #[inline(always)]
         unsafe fn ijk_to_pos(s: usize, i: i32, j: i32, k: i32) -> (i32, i32, i32) {
-            let delta0 = D_DELTA0.get_unchecked(s);
-            let delta1 = D_DELTA1.get_unchecked(s);
-            let delta2 = D_DELTA2.get_unchecked(s);
+            let delta0 = Direction::AXES.get_unchecked(s);
+            let [delta1, delta2] = Direction::TANGENTS.get_unchecked(s);
             let x = i * *delta0.get_unchecked(0) + j * *delta1.get_unchecked(0) + k * *delta2.get_unchecked(0);
             let y = i * *delta0.get_unchecked(1) + j * *delta1.get_unchecked(1) + k * *delta2.get_unchecked(1);
             let z = i * *delta0.get_unchecked(2) + j * *delta1.get_unchecked(2) + k * *delta2.get_unchecked(2);
@@ -349,9 +326,8 @@ pub fn greedy_meshing(
         };
 
 
-        let delta0 = D_DELTA0[s];
-        let delta1 = D_DELTA1[s];
-        let delta2 = D_DELTA2[s];
+        let delta0 = Direction::AXES[s];
+        let [delta1, delta2] = Direction::TANGENTS[s];
         let dix = delta0[0];
         let diy = delta0[1];
         let diz = delta0[2];
@@ -491,13 +467,16 @@ pub fn greedy_meshing(
                                 }
                             }
 
-                            let uv = match meshes[current_quad.block_id as usize] {
+                            let (uv, frame_time, seasonal) = match meshes[current_quad.block_id as usize] {
                                 BlockMesh::Empty => continue,
-                                BlockMesh::FullCube { textures } => textures[s],
+                                BlockMesh::FullCube { textures, frame_time, seasonal } => (textures[s], frame_time, seasonal),
                             };
+                            // The seasonal flag is stored after the light level
+                            let seasonal_flag = if seasonal { 1 << 9 } else { 0 };
 
                             let texture_top_left = [uv.x, uv.y];
                             let texture_size = [uv.width, uv.height];
+                            let texture_animation = [uv.frames as f32, frame_time];
                             let uv_factors = [(j_end - j) as f32, (k_end - k) as f32];
                             let uv_factors = [
                                 uv_factors[uv_directions[s][0]],
@@ -530,7 +509,8 @@ pub fn greedy_meshing(
                                     texture_uv: uvs[kk],
                                     texture_max_uv,
                                     texture_size,
-                                    occl_and_face: v[kk],
+                                    occl_and_face: v[kk] | seasonal_flag,
+                                    texture_animation,
                                 });
                             }
 
@@ -560,3 +540,163 @@ pub fn greedy_meshing(
     let res_index: Vec<u32> = res_index.iter().map(|x| *x as u32).collect();
     (res_vertex, res_index, tot_quad, act_quad)
 }
+
+
+/// Block at a position relative to the chunk, that may be in an adjacent chunk. The blocks of missing chunks are air.
+fn block_around(chunk_data: &ChunkMeshData, x: i32, y: i32, z: i32) -> BlockId {
+    let size = CHUNK_SIZE as i32;
+    let (cx, cy, cz) = (x.div_euclid(size) + 1, y.div_euclid(size) + 1, z.div_euclid(size) + 1);
+    let pos_in_chunk = (x.rem_euclid(size) as u32, y.rem_euclid(size) as u32, z.rem_euclid(size) as u32);
+    match Neighborhood27::index(cx as usize, cy as usize, cz as usize) {
+        Neighborhood27::CENTER => chunk_data.chunk.get_block_at(pos_in_chunk),
+        index => chunk_data.all_chunks[index].as_ref().map_or(0, |c| c.get_block_at(pos_in_chunk)),
+    }
+}
+
+/// Light at a position relative to the chunk, that may be in an adjacent chunk. Missing chunks are fully lit.
+fn light_around(chunk_data: &ChunkMeshData, x: i32, y: i32, z: i32) -> u8 {
+    let size = CHUNK_SIZE as i32;
+    let (cx, cy, cz) = (x.div_euclid(size) + 1, y.div_euclid(size) + 1, z.div_euclid(size) + 1);
+    let pos_in_chunk = (x.rem_euclid(size) as u32, y.rem_euclid(size) as u32, z.rem_euclid(size) as u32);
+    match Neighborhood27::index(cx as usize, cy as usize, cz as usize) {
+        Neighborhood27::CENTER => chunk_data.light_chunk.get_light_at(pos_in_chunk),
+        index => chunk_data.all_light_chunks[index].as_ref().map_or(MAX_LIGHT_LEVEL, |c| c.get_light_at(pos_in_chunk)),
+    }
+}
+
+/// The block of a cube of `scale`³ blocks starting at `(x, y, z)`: its most common opaque block,
+/// or air if less than half of its blocks are opaque
+fn downsampled_block(chunk_data: &ChunkMeshData, meshes: &[BlockMesh], (x, y, z): (i32, i32, i32), scale: i32) -> BlockId {
+    let mut counts: Vec<(BlockId, u32)> = Vec::new();
+    for i in x..x + scale {
+        for j in y..y + scale {
+            for k in z..z + scale {
+                let block = block_around(chunk_data, i, j, k);
+                if meshes[block as usize].is_opaque() {
+                    match counts.iter_mut().find(|(id, _)| *id == block) {
+                        Some((_, count)) => *count += 1,
+                        None => counts.push((block, 1)),
+                    }
+                }
+            }
+        }
+    }
+    let opaque_count: u32 = counts.iter().map(|(_, count)| count).sum();
+    if 2 * opaque_count < (scale * scale * scale) as u32 {
+        return 0;
+    }
+    counts.iter().max_by_key(|(_, count)| *count).map_or(0, |(id, _)| *id)
+}
+
+/// Mesh a chunk at a lower level of detail: every cube of `lod_scale`³ blocks is meshed as a single block.
+/// The faces are not merged and have no ambient occlusion, the chunks meshed this way are far from the player.
+///
+/// The chunks next to this one may be meshed at another level of detail, so a face on the border of the chunk
+/// is kept unless all the blocks of the adjacent chunk that it touches are opaque. This closes the gaps.
+pub fn lod_meshing(chunk_data: ChunkMeshData, meshes: &[BlockMesh]) -> (Vec<ChunkVertex>, Vec<u32>) {
+    let scale = chunk_data.lod_scale as i32;
+    let chunk_pos = chunk_data.chunk.pos;
+    let offset = [
+        chunk_pos.px as f32 * CHUNK_SIZE as f32,
+        chunk_pos.py as f32 * CHUNK_SIZE as f32,
+        chunk_pos.pz as f32 * CHUNK_SIZE as f32,
+    ];
+    // The cells of the chunk, with one more cell on every side from the adjacent chunks
+    let n = CHUNK_SIZE as i32 / scale;
+    let n_size = (n + 2) as usize;
+    let cell_index = |x: i32, y: i32, z: i32| ((x + 1) as usize * n_size + (y + 1) as usize) * n_size + (z + 1) as usize;
+    let mut cells = vec![0; n_size * n_size * n_size];
+    for x in -1..=n {
+        for y in -1..=n {
+            for z in -1..=n {
+                cells[cell_index(x, y, z)] = downsampled_block(&chunk_data, meshes, (x * scale, y * scale, z * scale), scale);
+            }
+        }
+    }
+
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    for x in 0..n {
+        for y in 0..n {
+            for z in 0..n {
+                let block = cells[cell_index(x, y, z)];
+                let (textures, frame_time, seasonal) = match meshes[block as usize] {
+                    BlockMesh::FullCube { textures, frame_time, seasonal } => (textures, frame_time, seasonal),
+                    BlockMesh::Empty => continue,
+                };
+                let origin = [x * scale, y * scale, z * scale];
+                for s in 0..6 {
+                    let (dx, dy, dz) = Direction::ALL[s].offset();
+                    let d = [dx as i32, dy as i32, dz as i32];
+                    let (nx, ny, nz) = (x + d[0], y + d[1], z + d[2]);
+                    if meshes[cells[cell_index(nx, ny, nz)] as usize].is_opaque() {
+                        let inside = (0..n).contains(&nx) && (0..n).contains(&ny) && (0..n).contains(&nz);
+                        if inside || touches_only_opaque_blocks(&chunk_data, meshes, origin, d, scale) {
+                            continue;
+                        }
+                    }
+
+                    // The light of the block in front of the center of the face
+                    let mut light_pos = [0; 3];
+                    for a in 0..3 {
+                        light_pos[a] = match d[a] {
+                            1 => origin[a] + scale,
+                            -1 => origin[a] - 1,
+                            _ => origin[a] + scale / 2,
+                        };
+                    }
+                    let light_level = light_around(&chunk_data, light_pos[0], light_pos[1], light_pos[2]);
+                    let seasonal_flag = if seasonal { 1 << 9 } else { 0 };
+                    let occl_and_face = (s as u32) + (3 << 3) + ((light_level as u32) << 5) + seasonal_flag;
+
+                    let uv = textures[s];
+                    let size = scale as f32;
+                    let first_vertex = vertices.len();
+                    for (kk, &(j, k)) in [(0, 0), (0, 1), (1, 0), (1, 1)].iter().enumerate() {
+                        let mut pos = [0.0; 3];
+                        for a in 0..3 {
+                            // The faces in the positive directions are on the far side of the cube
+                            let normal_offset = if s % 2 == 0 { Direction::AXES[s][a] } else { 0 };
+                            let [t1, t2] = Direction::TANGENTS[s];
+                            let block_offset = normal_offset + j * t1[a] + k * t2[a];
+                            pos[a] = offset[a] + (origin[a] + block_offset * scale) as f32;
+                        }
+                        vertices.push(ChunkVertex {
+                            pos,
+                            texture_top_left: [uv.x, uv.y],
+                            texture_uv: [FACE_UVS[s][kk][0] * uv.width * size, FACE_UVS[s][kk][1] * uv.height * size],
+                            texture_max_uv: [uv.width * size, uv.height * size],
+                            texture_size: [uv.width, uv.height],
+                            occl_and_face,
+                            texture_animation: [uv.frames as f32, frame_time],
+                        });
+                    }
+                    indices.extend(QUAD_INDICES[s].iter().map(|&index| (first_vertex + index) as u32));
+                }
+            }
+        }
+    }
+    (vertices, indices)
+}
+
+/// Whether the blocks of the adjacent chunk touching the face of the cube at `origin` in direction `d` are all opaque
+fn touches_only_opaque_blocks(chunk_data: &ChunkMeshData, meshes: &[BlockMesh], origin: [i32; 3], d: [i32; 3], scale: i32) -> bool {
+    let mut range = [(0, 0); 3];
+    for a in 0..3 {
+        range[a] = match d[a] {
+            1 => (origin[a] + scale, origin[a] + scale + 1),
+            -1 => (origin[a] - 1, origin[a]),
+            _ => (origin[a], origin[a] + scale),
+        };
+    }
+    for x in range[0].0..range[0].1 {
+        for y in range[1].0..range[1].1 {
+            for z in range[2].0..range[2].1 {
+                if !meshes[block_around(chunk_data, x, y, z) as usize].is_opaque() {
+                    return false;
+                }
+            }
+        }
+    }
+    true
+}
@@ -1,11 +1,12 @@
 //! Meshing worker, allowing meshing to be performed in a separate thread
-use super::meshing::{greedy_meshing, ChunkMeshData};
+use super::cave_culling::ChunkVisibility;
+use super::meshing::{greedy_meshing, lod_meshing, ChunkMeshData};
 use crate::render::world::ChunkVertex;
 use voxel_rs_common::block::BlockMesh;
 use voxel_rs_common::world::ChunkPos;
 use voxel_rs_common::worker::{WorkerState, Worker};
 
-pub type ChunkMesh = (ChunkPos, Vec<ChunkVertex>, Vec<u32>);
+pub type ChunkMesh = (ChunkPos, Vec<ChunkVertex>, Vec<u32>, ChunkVisibility);
 pub type MeshingWorker = Worker<ChunkMeshData, ChunkMesh, MeshingState>;
 
 pub fn start_meshing_worker(block_meshes: Vec<BlockMesh>) -> MeshingWorker {
@@ -33,8 +34,14 @@ impl MeshingState {
 impl WorkerState<ChunkMeshData, ChunkMesh> for MeshingState {
     fn compute(&mut self, input: ChunkMeshData) -> ChunkMesh {
         let pos = input.chunk.pos;
-        let (vertices, indices, _, _) = greedy_meshing(input, &self.block_meshes, &mut self.quads_reuse);
-        (pos, vertices, indices)
+        let visibility = ChunkVisibility::compute(&input.chunk, &self.block_meshes);
+        let (vertices, indices) = if input.lod_scale > 1 {
+            lod_meshing(input, &self.block_meshes)
+        } else {
+            let (vertices, indices, _, _) = greedy_meshing(input, &self.block_meshes, &mut self.quads_reuse);
+            (vertices, indices)
+        };
+        (pos, vertices, indices, visibility)
     }
 }
 
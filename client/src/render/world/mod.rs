@@ -1,24 +1,46 @@
 //! World rendering
 
 use super::buffers::MultiBuffer;
-use super::frustum::Frustum;
+use super::frustum::{opengl_to_wgpu_matrix, Frustum};
 use super::init::{create_default_pipeline, load_glsl_shader, ShaderStage};
-use super::{ to_u8_slice, buffer_from_slice };
-use crate::texture::load_image;
+use super::{ to_u8_slice, buffer_from_slice, Viewport };
 use crate::window::WindowBuffers;
 use image::{ImageBuffer, Rgba};
 use nalgebra::{Matrix4, Similarity3, Translation3, UnitQuaternion, Vector3};
 use voxel_rs_common::data::vox::VoxelModel;
+use voxel_rs_common::config::ClientTuning;
 use voxel_rs_common::debug::send_debug_info;
+use voxel_rs_common::dimension::{Dimension, FogProfile};
 use voxel_rs_common::registry::Registry;
+use voxel_rs_common::season::SeasonState;
 use voxel_rs_common::world::{BlockPos, ChunkPos};
+use std::collections::HashMap;
+use std::time::Instant;
 
+mod cave_culling;
+mod chunk_draws;
+mod debug_lines;
+mod dynamic_lights;
 mod meshing;
 mod meshing_worker;
 mod model;
+mod occlusion;
+mod shadows;
 mod skybox;
+mod texture_streaming;
+pub use self::debug_lines::DebugLines;
+use self::cave_culling::{find_visible_chunks, ChunkVisibility};
+use self::chunk_draws::ChunkDrawCommands;
+use self::debug_lines::{DebugLineVertex, DEBUG_LINE_VERTEX_ATTRIBUTES};
+pub use self::dynamic_lights::{PointLight, MAX_DYNAMIC_LIGHTS};
+use self::dynamic_lights::{encode_dynamic_lights, DYNAMIC_LIGHTS_UNIFORM_SIZE};
 pub use self::model::Model;
+use self::occlusion::OcclusionCuller;
+use self::shadows::{ShadowMaps, SHADOWS_UNIFORM_SIZE};
+pub use self::shadows::MAX_SHADOW_CASCADES;
 pub use self::meshing::ChunkMeshData;
+pub use self::texture_streaming::tiles_of_mesh;
+use self::texture_streaming::TextureStreamer;
 pub use self::meshing_worker::{ChunkMesh, MeshingWorker, start_meshing_worker};
 
 /// All the state necessary to render the world.
@@ -27,11 +49,38 @@ pub struct WorldRenderer {
     uniform_view_proj: wgpu::Buffer,
     // Model matrix
     uniform_model: wgpu::Buffer,
+    // Time used to animate the textures
+    uniform_animation_time: wgpu::Buffer,
+    animation_start: Instant,
+    // Direction of the sun and color of the sky
+    uniform_sky: wgpu::Buffer,
+    sun_direction: [f32; 3],
+    // Dynamic point lights
+    uniform_dynamic_lights: wgpu::Buffer,
+    dynamic_lights: Vec<PointLight>,
+    // How wet the surfaces exposed to the rain are, and the lighting and fog of the dimension
+    uniform_weather: wgpu::Buffer,
+    wetness: f32,
+    dimension: Dimension,
+    /// Distances where the distance fog starts and where it hides everything, in blocks
+    distance_fog: Option<(f32, f32)>,
+    /// Fog of the environment of the camera, replacing the fog of the dimension, for example in the water
+    environment_fog: Option<FogProfile>,
+    // Tint and texture variant of the seasonal blocks
+    uniform_season: wgpu::Buffer,
+    season: SeasonState,
+    // Shadows of the sun
+    shadow_maps: ShadowMaps,
     // Chunk rendering
     chunk_index_buffers: MultiBuffer<ChunkPos, u32>,
     chunk_vertex_buffers: MultiBuffer<ChunkPos, ChunkVertex>,
     chunk_pipeline: wgpu::RenderPipeline,
     chunk_bind_group: wgpu::BindGroup,
+    chunk_draws: ChunkDrawCommands,
+    occlusion_culler: OcclusionCuller,
+    /// Which faces of every meshed chunk are connected, for cave culling
+    chunk_visibilities: HashMap<ChunkPos, ChunkVisibility>,
+    texture_streamer: TextureStreamer,
     // Skybox rendering
     skybox_index_buffer: wgpu::Buffer,
     skybox_vertex_buffer: wgpu::Buffer,
@@ -45,6 +94,10 @@ pub struct WorldRenderer {
     model_index_buffers: MultiBuffer<u32, u32>,
     model_vertex_buffers: MultiBuffer<u32, RgbVertex>,
     model_pipeline: wgpu::RenderPipeline,
+    // Debug lines rendering, also used for the particles
+    debug_lines: DebugLines,
+    particles: DebugLines,
+    debug_lines_pipeline: wgpu::RenderPipeline,
 }
 
 impl WorldRenderer {
@@ -53,10 +106,17 @@ impl WorldRenderer {
         encoder: &mut wgpu::CommandEncoder,
         texture_atlas: ImageBuffer<Rgba<u8>, Vec<u8>>,
         models: &Registry<VoxelModel>,
+        shadow_resolution: u32,
+        tuning: &ClientTuning,
     ) -> Self {
         // Load texture atlas
-        let texture_atlas = load_image(device, encoder, texture_atlas);
-        let texture_atlas_view = texture_atlas.create_view(&wgpu::TextureViewDescriptor::default());
+        let texture_streamer = TextureStreamer::new(device, encoder, texture_atlas, tuning.texture_uploads_per_frame);
+        let texture_atlas_view = texture_streamer
+            .resident_texture()
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let tile_cache_view = texture_streamer
+            .cache_texture()
+            .create_view(&wgpu::TextureViewDescriptor::default());
 
         // Create uniform buffers
         let uniform_view_proj = device.create_buffer(&wgpu::BufferDescriptor {
@@ -68,9 +128,42 @@ impl WorldRenderer {
         let uniform_model = device.create_buffer(&wgpu::BufferDescriptor {
             mapped_at_creation: false,
             label: None,
-            size: 64,
+            size: MODEL_UNIFORM_SIZE,
+            usage: (wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST),
+        });
+        let uniform_animation_time = device.create_buffer(&wgpu::BufferDescriptor {
+            mapped_at_creation: false,
+            label: None,
+            size: 16,
             usage: (wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST),
         });
+        let uniform_sky = device.create_buffer(&wgpu::BufferDescriptor {
+            mapped_at_creation: false,
+            label: None,
+            size: 32,
+            usage: (wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST),
+        });
+        let uniform_dynamic_lights = device.create_buffer(&wgpu::BufferDescriptor {
+            mapped_at_creation: false,
+            label: None,
+            size: DYNAMIC_LIGHTS_UNIFORM_SIZE,
+            usage: (wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST),
+        });
+        let uniform_weather = device.create_buffer(&wgpu::BufferDescriptor {
+            mapped_at_creation: false,
+            label: None,
+            size: WEATHER_UNIFORM_SIZE,
+            usage: (wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST),
+        });
+        let uniform_season = device.create_buffer(&wgpu::BufferDescriptor {
+            mapped_at_creation: false,
+            label: None,
+            size: 16,
+            usage: (wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST),
+        });
+
+        // Create shadow maps
+        let shadow_maps = ShadowMaps::new(device, shadow_resolution);
 
         // Create uniform bind group
         let chunk_bind_group_layout = device.create_bind_group_layout(&CHUNK_BIND_GROUP_LAYOUT);
@@ -78,7 +171,14 @@ impl WorldRenderer {
             device,
             &chunk_bind_group_layout,
             &texture_atlas_view,
+            &tile_cache_view,
             &uniform_view_proj,
+            &uniform_animation_time,
+            texture_streamer.uniform_page_table(),
+            &uniform_dynamic_lights,
+            &uniform_weather,
+            &uniform_season,
+            &shadow_maps,
         );
 
         // Create chunk pipeline
@@ -90,6 +190,7 @@ impl WorldRenderer {
 
             create_default_pipeline(
                 device,
+                crate::window::HDR_COLOR_FORMAT,
                 &chunk_bind_group_layout,
                 vertex_shader,
                 fragment_shader,
@@ -103,6 +204,9 @@ impl WorldRenderer {
             )
         };
 
+        // Create occlusion culler
+        let occlusion_culler = OcclusionCuller::new(device, &uniform_view_proj);
+
         // Create skybox vertex and index buffers
         let (skybox_vertex_buffer, skybox_index_buffer) = self::skybox::create_skybox(device);
 
@@ -113,6 +217,7 @@ impl WorldRenderer {
             &vpm_bind_group_layout,
             &uniform_view_proj,
             &uniform_model,
+            &uniform_sky,
         );
 
         // Create skybox pipeline
@@ -124,6 +229,7 @@ impl WorldRenderer {
 
             create_default_pipeline(
                 device,
+                crate::window::HDR_COLOR_FORMAT,
                 &vpm_bind_group_layout,
                 vertex_shader,
                 fragment_shader,
@@ -152,6 +258,7 @@ impl WorldRenderer {
 
             create_default_pipeline(
                 device,
+                crate::window::HDR_COLOR_FORMAT,
                 &vpm_bind_group_layout,
                 vertex_shader,
                 fragment_shader,
@@ -174,6 +281,7 @@ impl WorldRenderer {
 
             create_default_pipeline(
                 device,
+                crate::window::HDR_COLOR_FORMAT,
                 &vpm_bind_group_layout,
                 vertex_shader,
                 fragment_shader,
@@ -187,6 +295,29 @@ impl WorldRenderer {
             )
         };
 
+        // Create debug lines pipeline
+        let debug_lines_pipeline = {
+            let vertex_shader_bytes = load_glsl_shader(ShaderStage::Vertex, "assets/shaders/debug_lines.vert");
+            let vertex_shader = wgpu::util::make_spirv(&vertex_shader_bytes);
+            let fragment_shader_bytes = load_glsl_shader(ShaderStage::Fragment, "assets/shaders/debug_lines.frag");
+            let fragment_shader = wgpu::util::make_spirv(&fragment_shader_bytes);
+
+            create_default_pipeline(
+                device,
+                crate::window::HDR_COLOR_FORMAT,
+                &vpm_bind_group_layout,
+                vertex_shader,
+                fragment_shader,
+                wgpu::PrimitiveTopology::LineList,
+                wgpu::VertexBufferDescriptor {
+                    stride: std::mem::size_of::<DebugLineVertex>() as u64,
+                    step_mode: wgpu::InputStepMode::Vertex,
+                    attributes: &DEBUG_LINE_VERTEX_ATTRIBUTES,
+                },
+                false,
+            )
+        };
+
         // Mesh models
         let mut model_index_buffers =
             MultiBuffer::with_capacity(device, 1, wgpu::BufferUsage::INDEX);
@@ -202,14 +333,36 @@ impl WorldRenderer {
         Self {
             uniform_view_proj,
             uniform_model,
-            chunk_index_buffers: MultiBuffer::with_capacity(device, 1000, wgpu::BufferUsage::INDEX),
+            uniform_animation_time,
+            animation_start: Instant::now(),
+            uniform_sky,
+            sun_direction: [0.0, 1.0, 0.0],
+            uniform_dynamic_lights,
+            dynamic_lights: Vec::new(),
+            uniform_weather,
+            wetness: 0.0,
+            dimension: Dimension::default(),
+            distance_fog: None,
+            environment_fog: None,
+            uniform_season,
+            season: SeasonState::default(),
+            shadow_maps,
+            chunk_index_buffers: MultiBuffer::with_capacity(
+                device,
+                tuning.chunk_buffer_capacity,
+                wgpu::BufferUsage::INDEX,
+            ),
             chunk_vertex_buffers: MultiBuffer::with_capacity(
                 device,
-                1000,
+                tuning.chunk_buffer_capacity,
                 wgpu::BufferUsage::VERTEX,
             ),
             chunk_pipeline,
             chunk_bind_group,
+            chunk_draws: ChunkDrawCommands::new(device),
+            occlusion_culler,
+            chunk_visibilities: HashMap::new(),
+            texture_streamer,
             skybox_vertex_buffer,
             skybox_index_buffer,
             skybox_pipeline,
@@ -219,39 +372,110 @@ impl WorldRenderer {
             model_pipeline,
             model_index_buffers,
             model_vertex_buffers,
+            debug_lines: DebugLines::default(),
+            particles: DebugLines::default(),
+            debug_lines_pipeline,
+        }
+    }
+
+    /// Set the debug lines drawn on top of the world
+    pub fn set_debug_lines(&mut self, debug_lines: DebugLines) {
+        self.debug_lines = debug_lines;
+    }
+
+    /// Set the tiles of the texture atlas whose high resolution mipmaps should be streamed, most important first
+    pub fn request_texture_tiles(&mut self, tiles: Vec<u32>) {
+        self.texture_streamer.request_tiles(tiles);
+    }
+
+    /// Set the direction of the sun in the sky
+    pub fn set_sun_direction(&mut self, sun_direction: Vector3<f64>) {
+        self.sun_direction = [
+            sun_direction.x as f32,
+            sun_direction.y as f32,
+            sun_direction.z as f32,
+        ];
+    }
+
+    /// Set how wet the surfaces exposed to the rain are, between 0 and 1
+    pub fn set_wetness(&mut self, wetness: f32) {
+        self.wetness = wetness;
+    }
+
+    /// Set the sky, ambient light and fog of the dimension
+    pub fn set_dimension(&mut self, dimension: &Dimension) {
+        self.dimension = *dimension;
+    }
+
+    /// Set the distances where the distance fog starts and where it hides everything, in blocks.
+    /// `None` disables the distance fog.
+    pub fn set_distance_fog(&mut self, distance_fog: Option<(f32, f32)>) {
+        self.distance_fog = distance_fog;
+    }
+
+    /// Set the fog of the environment of the camera, that replaces the fog of the dimension.
+    /// `None` uses the fog of the dimension.
+    pub fn set_environment_fog(&mut self, environment_fog: Option<FogProfile>) {
+        self.environment_fog = environment_fog;
+    }
+
+    /// Approximate color of the sky at the horizon, where the distance fog blends the chunks, see skybox.frag
+    fn horizon_color(&self) -> [f32; 3] {
+        if let Some(fog) = self.environment_fog {
+            return fog.color;
+        }
+        let sky_color = self.dimension.sky_color;
+        if !self.dimension.sunlight {
+            return sky_color;
         }
+        let daylight = (self.sun_direction[1] * 4.0 + 0.5).max(0.05).min(1.0);
+        let atmosphere = 1.0 / 1.3;
+        let horizon = |c: f32| (c * (1.0 - atmosphere) + atmosphere) * daylight;
+        [horizon(sky_color[0]), horizon(sky_color[1]), horizon(sky_color[2])]
+    }
+
+    /// Set the tint and the texture variant of the seasonal blocks
+    pub fn set_season(&mut self, season: SeasonState) {
+        self.season = season;
+    }
+
+    /// Set the particles, drawn as lines hidden by the world
+    pub fn set_particles(&mut self, particles: DebugLines) {
+        self.particles = particles;
+    }
+
+    /// Set the number of shadow cascades, 0 to disable the shadows
+    pub fn set_shadow_cascades(&mut self, cascades: usize) {
+        self.shadow_maps.set_cascades(cascades);
+    }
+
+    /// Set the dynamic lights, only the `MAX_DYNAMIC_LIGHTS` closest to the camera are rendered
+    pub fn set_dynamic_lights(&mut self, dynamic_lights: Vec<PointLight>) {
+        self.dynamic_lights = dynamic_lights;
     }
 
     pub fn render(
         &mut self,
         device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        gpu_timer: &mut super::GpuTimer,
         encoder: &mut wgpu::CommandEncoder,
         buffers: WindowBuffers,
-        data: &crate::window::WindowData,
+        viewport: Viewport,
         frustum: &Frustum,
         enable_culling: bool,
+        occlusion_culling: bool,
         pointed_block: Option<(BlockPos, usize)>,
         models: &[model::Model],
+        model_light_levels: &[u8],
     ) {
         //============= RENDER =============//
-        // TODO: what if win_h is 0 ?
-        let aspect_ratio = {
-            let winit::dpi::PhysicalSize {
-                width: win_w,
-                height: win_h,
-            } = data.physical_window_size;
-            win_w as f64 / win_h as f64
-        };
+        let aspect_ratio = viewport.aspect_ratio();
 
         let view_mat = frustum.get_view_matrix();
         let planes = frustum.get_planes(aspect_ratio);
         let view_proj_mat = frustum.get_view_projection(aspect_ratio);
-        let opengl_to_wgpu = nalgebra::Matrix4::from([
-            [1.0, 0.0, 0.0, 0.0],
-            [0.0, 1.0, 0.0, 0.0],
-            [0.0, 0.0, 0.5, 0.0],
-            [0.0, 0.0, 0.5, 1.0],
-        ]);
+        let opengl_to_wgpu = opengl_to_wgpu_matrix();
         let view_proj: [[f32; 4]; 4] = nalgebra::convert::<
             nalgebra::Matrix4<f64>,
             nalgebra::Matrix4<f32>,
@@ -266,34 +490,143 @@ impl WorldRenderer {
         );
         encoder.copy_buffer_to_buffer(&src_buffer, 0, &self.uniform_view_proj, 0, 64);
 
+        // Update animation time, wrapping every hour to keep enough f32 precision
+        let animation_time = (self.animation_start.elapsed().as_secs_f64() % 3600.0) as f32;
+        let src_buffer = buffer_from_slice(
+            device,
+            wgpu::BufferUsage::COPY_SRC,
+            to_u8_slice(&[animation_time, 0.0, 0.0, 0.0])
+        );
+        encoder.copy_buffer_to_buffer(&src_buffer, 0, &self.uniform_animation_time, 0, 16);
+
+        // Update dynamic lights
+        let src_buffer = buffer_from_slice(
+            device,
+            wgpu::BufferUsage::COPY_SRC,
+            to_u8_slice(&encode_dynamic_lights(&self.dynamic_lights, frustum.position, animation_time))
+        );
+        encoder.copy_buffer_to_buffer(&src_buffer, 0, &self.uniform_dynamic_lights, 0, DYNAMIC_LIGHTS_UNIFORM_SIZE);
+
+        // Update wetness, ambient light and fog
+        let fog = self.environment_fog.unwrap_or(self.dimension.fog);
+        let [fog_r, fog_g, fog_b] = fog.color;
+        let (fog_start, fog_end) = self.distance_fog.unwrap_or((0.0, 0.0));
+        let [horizon_r, horizon_g, horizon_b] = self.horizon_color();
+        let src_buffer = buffer_from_slice(
+            device,
+            wgpu::BufferUsage::COPY_SRC,
+            to_u8_slice(&[
+                self.wetness,
+                self.dimension.ambient_light as f32,
+                fog_start,
+                fog_end,
+                frustum.position.x as f32,
+                frustum.position.y as f32,
+                frustum.position.z as f32,
+                0.0,
+                fog_r,
+                fog_g,
+                fog_b,
+                fog.density,
+                horizon_r,
+                horizon_g,
+                horizon_b,
+                0.0,
+            ])
+        );
+        encoder.copy_buffer_to_buffer(&src_buffer, 0, &self.uniform_weather, 0, WEATHER_UNIFORM_SIZE);
+
+        // Update season
+        let [tint_r, tint_g, tint_b] = self.season.tint;
+        let src_buffer = buffer_from_slice(
+            device,
+            wgpu::BufferUsage::COPY_SRC,
+            to_u8_slice(&[tint_r, tint_g, tint_b, self.season.variant as f32])
+        );
+        encoder.copy_buffer_to_buffer(&src_buffer, 0, &self.uniform_season, 0, 16);
+
+        // Upload the requested texture tiles
+        self.texture_streamer.update(device, encoder);
+
+        // Render the shadows of the sun
+        let [sun_x, sun_y, sun_z] = self.sun_direction;
+        let sun_direction = if self.dimension.sunlight {
+            Some(Vector3::new(sun_x as f64, sun_y as f64, sun_z as f64))
+        } else {
+            None
+        };
+        self.shadow_maps.render(
+            device,
+            encoder,
+            sun_direction,
+            frustum.position,
+            &self.chunk_index_buffers,
+            &self.chunk_vertex_buffers,
+        );
+        gpu_timer.end_pass(device, queue, encoder, "Shadows");
+
         // Draw all the chunks
+        if occlusion_culling {
+            self.occlusion_culler.update(device);
+        }
+        let camera_chunk = BlockPos::from(frustum.position).containing_chunk_pos();
+        let cave_visible_chunks = if enable_culling {
+            Some(find_visible_chunks(camera_chunk, &self.chunk_visibilities, |chunk_pos| {
+                Frustum::contains_chunk(&planes, &view_mat, chunk_pos)
+            }))
+        } else {
+            None
+        };
+        let mut chunks_in_frustum = Vec::new();
+        let mut drawn_chunks = Vec::new();
+        for chunk_pos in self.chunk_index_buffers.keys() {
+            if !enable_culling || Frustum::contains_chunk(&planes, &view_mat, chunk_pos) {
+                if cave_visible_chunks.as_ref().map_or(false, |visible| !visible.contains(&chunk_pos)) {
+                    continue;
+                }
+                chunks_in_frustum.push(chunk_pos);
+                if occlusion_culling && self.occlusion_culler.is_occluded(chunk_pos) {
+                    continue;
+                }
+                drawn_chunks.push(chunk_pos);
+            }
+        }
+        let (chunk_index_buffers, chunk_vertex_buffers) = (&self.chunk_index_buffers, &self.chunk_vertex_buffers);
+        self.chunk_draws.update(device, encoder, &drawn_chunks, |chunk_pos| {
+            let (index_pos, index_len) = chunk_index_buffers.get_pos_len(&chunk_pos).unwrap();
+            let (vertex_pos, _) = chunk_vertex_buffers.get_pos_len(&chunk_pos).unwrap();
+            (index_pos, index_len, vertex_pos)
+        });
         {
             let mut rpass = super::render::create_default_render_pass(encoder, buffers);
+            viewport.apply(&mut rpass);
             rpass.set_pipeline(&self.chunk_pipeline);
             rpass.set_bind_group(0, &self.chunk_bind_group, &[]);
             rpass.set_vertex_buffer(0, self.chunk_vertex_buffers.get_buffer().slice(..));
             rpass.set_index_buffer(self.chunk_index_buffers.get_buffer().slice(..));
-            let mut count = 0;
-            for chunk_pos in self.chunk_index_buffers.keys() {
-                if !enable_culling || Frustum::contains_chunk(&planes, &view_mat, chunk_pos) {
-                    count += 1;
-                    let (index_pos, index_len) =
-                        self.chunk_index_buffers.get_pos_len(&chunk_pos).unwrap();
-                    let (vertex_pos, _) =
-                        self.chunk_vertex_buffers.get_pos_len(&chunk_pos).unwrap();
-                    rpass.draw_indexed(
-                        (index_pos as u32)..((index_pos + index_len) as u32),
-                        vertex_pos as i32,
-                        0..1,
-                    );
-                }
-            }
+            self.chunk_draws.draw(&mut rpass);
             send_debug_info(
                 "Render",
                 "renderedchunks",
-                format!("{} chunks were rendered", count),
+                format!("{} chunks were rendered", self.chunk_draws.num_drawn_chunks()),
+            );
+        }
+        if occlusion_culling {
+            send_debug_info(
+                "Render",
+                "occludedchunks",
+                format!("{} chunks were occluded", self.occlusion_culler.num_occluded_chunks()),
+            );
+            self.occlusion_culler.test_chunks(
+                device,
+                encoder,
+                buffers,
+                viewport,
+                camera_chunk,
+                chunks_in_frustum.into_iter(),
             );
         }
+        gpu_timer.end_pass(device, queue, encoder, "Chunks");
 
         // Draw the skybox
         {
@@ -321,13 +654,25 @@ impl WorldRenderer {
                 ])
             );
             encoder.copy_buffer_to_buffer(&src_buffer, 0, &self.uniform_model, 0, 64);
+            // Update sky buffer
+            let [x, y, z] = self.sun_direction;
+            let [r, g, b] = self.dimension.sky_color;
+            let has_sun = if self.dimension.sunlight { 1.0 } else { 0.0 };
+            let src_buffer = buffer_from_slice(
+                device,
+                wgpu::BufferUsage::COPY_SRC,
+                to_u8_slice(&[x, y, z, 0.0, r, g, b, has_sun])
+            );
+            encoder.copy_buffer_to_buffer(&src_buffer, 0, &self.uniform_sky, 0, 32);
             let mut rpass = super::render::create_default_render_pass(encoder, buffers);
+            viewport.apply(&mut rpass);
             rpass.set_pipeline(&self.skybox_pipeline);
             rpass.set_bind_group(0, &self.vpm_bind_group, &[]);
             rpass.set_vertex_buffer(0, self.skybox_vertex_buffer.slice(..));
             rpass.set_index_buffer(self.skybox_index_buffer.slice(..));
             rpass.draw_indexed(0..36, 0, 0..1);
         }
+        gpu_timer.end_pass(device, queue, encoder, "Skybox");
 
         // Draw the target if necessary
         if let Some((target_pos, target_face)) = pointed_block {
@@ -370,6 +715,7 @@ impl WorldRenderer {
             );
             encoder.copy_buffer_to_buffer(&src_buffer, 0, &self.uniform_model, 0, 64);
             let mut rpass = super::render::create_default_render_pass(encoder, buffers);
+            viewport.apply(&mut rpass);
             rpass.set_pipeline(&self.target_pipeline);
             rpass.set_bind_group(0, &self.vpm_bind_group, &[]);
             rpass.set_vertex_buffer(0, self.target_vertex_buffer.slice(..));
@@ -377,12 +723,16 @@ impl WorldRenderer {
         }
 
         // Draw the models
-        for model in models {
+        for (model, &light_level) in models.iter().zip(model_light_levels) {
             // Compute model matrix
             let mut transform = Similarity3::identity();
             transform.append_scaling_mut(model.scale);
             let offset_translation = Translation3::from(-Vector3::from(model.rot_offset));
             transform.append_translation_mut(&offset_translation);
+            transform.append_rotation_mut(&UnitQuaternion::from_axis_angle(
+                &Vector3::z_axis(),
+                model.rot_z,
+            ));
             transform.append_rotation_mut(&UnitQuaternion::from_axis_angle(
                 &Vector3::y_axis(),
                 model.rot_y,
@@ -392,15 +742,18 @@ impl WorldRenderer {
                     + &Vector3::from(model.rot_offset),
             ));
             let transformation_matrix: Matrix4<f32> = nalgebra::convert(transform);
-            // Update model buffer
+            // Update model buffer, the light level of the model follows the matrix
+            let mut model_data = transformation_matrix.as_slice().to_vec();
+            model_data.extend_from_slice(&[light_level as f32, 0.0, 0.0, 0.0]);
             let src_buffer = buffer_from_slice(
                 device,
                 wgpu::BufferUsage::COPY_SRC,
-                to_u8_slice(transformation_matrix.as_ref())
+                to_u8_slice(&model_data)
             );
-            encoder.copy_buffer_to_buffer(&src_buffer, 0, &self.uniform_model, 0, 64);
+            encoder.copy_buffer_to_buffer(&src_buffer, 0, &self.uniform_model, 0, MODEL_UNIFORM_SIZE);
             // Draw model
             let mut rpass = super::render::create_default_render_pass(encoder, buffers);
+            viewport.apply(&mut rpass);
             rpass.set_pipeline(&self.model_pipeline);
             rpass.set_bind_group(0, &self.vpm_bind_group, &[]);
             rpass.set_vertex_buffer(0, self.model_vertex_buffers.get_buffer().slice(..));
@@ -419,6 +772,39 @@ impl WorldRenderer {
                 0..1,
             );
         }
+
+        // Draw the particles
+        if !self.particles.is_empty() {
+            let vertex_buffer = buffer_from_slice(
+                device,
+                wgpu::BufferUsage::VERTEX,
+                to_u8_slice(&self.particles.vertices)
+            );
+            let mut rpass = super::render::create_default_render_pass(encoder, buffers);
+            viewport.apply(&mut rpass);
+            rpass.set_pipeline(&self.debug_lines_pipeline);
+            rpass.set_bind_group(0, &self.vpm_bind_group, &[]);
+            rpass.set_vertex_buffer(0, vertex_buffer.slice(..));
+            rpass.draw(0..(self.particles.vertices.len() as u32), 0..1);
+        }
+
+        // Draw the debug lines on top of everything else
+        if !self.debug_lines.is_empty() {
+            super::render::clear_depth(encoder, buffers);
+            let vertex_buffer = buffer_from_slice(
+                device,
+                wgpu::BufferUsage::VERTEX,
+                to_u8_slice(&self.debug_lines.vertices)
+            );
+            let mut rpass = super::render::create_default_render_pass(encoder, buffers);
+            viewport.apply(&mut rpass);
+            rpass.set_pipeline(&self.debug_lines_pipeline);
+            rpass.set_bind_group(0, &self.vpm_bind_group, &[]);
+            rpass.set_vertex_buffer(0, vertex_buffer.slice(..));
+            rpass.draw(0..(self.debug_lines.vertices.len() as u32), 0..1);
+        }
+        // Also includes the target, the particles and the debug lines
+        gpu_timer.end_pass(device, queue, encoder, "Models");
     }
 
     pub fn update_chunk_mesh(
@@ -427,18 +813,50 @@ impl WorldRenderer {
         encoder: &mut wgpu::CommandEncoder,
         chunk_mesh: ChunkMesh,
     ) {
-        let (pos, vertices, indices) = chunk_mesh;
+        let (pos, vertices, indices, visibility) = chunk_mesh;
+        self.chunk_visibilities.insert(pos, visibility);
+        self.chunk_draws.invalidate();
         if vertices.len() > 0 && indices.len() > 0 {
             self.chunk_vertex_buffers
                 .update(device, encoder, pos, &vertices[..]);
             self.chunk_index_buffers
                 .update(device, encoder, pos, &indices[..]);
+        } else {
+            // The chunk became empty, don't keep its old mesh around
+            self.chunk_vertex_buffers.remove(&pos);
+            self.chunk_index_buffers.remove(&pos);
+        }
+    }
+
+    /// Defragment the chunk buffers, and shrink them after many chunks were removed
+    pub fn compact_chunk_buffers(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        let vertices_moved = self.chunk_vertex_buffers.compact_if_needed(device, encoder);
+        let indices_moved = self.chunk_index_buffers.compact_if_needed(device, encoder);
+        if vertices_moved || indices_moved {
+            self.chunk_draws.invalidate();
         }
     }
 
+    /// Size of the chunk buffers in bytes, and the number of bytes that are actually used
+    pub fn chunk_buffers_memory(&self) -> (usize, usize) {
+        let vertex_size = std::mem::size_of::<ChunkVertex>();
+        let index_size = std::mem::size_of::<u32>();
+        (
+            self.chunk_vertex_buffers.capacity() * vertex_size + self.chunk_index_buffers.capacity() * index_size,
+            self.chunk_vertex_buffers.used_len() * vertex_size + self.chunk_index_buffers.used_len() * index_size,
+        )
+    }
+
     pub fn remove_chunk_mesh(&mut self, pos: ChunkPos) {
+        self.chunk_draws.invalidate();
         self.chunk_vertex_buffers.remove(&pos);
         self.chunk_index_buffers.remove(&pos);
+        self.occlusion_culler.remove_chunk(pos);
+        self.chunk_visibilities.remove(&pos);
     }
 }
 
@@ -452,10 +870,12 @@ pub struct ChunkVertex {
     pub texture_max_uv: [f32; 2],
     pub texture_uv: [f32; 2],
     pub occl_and_face: u32,
+    /// Number of frames and frame time of the texture
+    pub texture_animation: [f32; 2],
 }
 
 /// Chunk vertex attributes
-const CHUNK_VERTEX_ATTRIBUTES: [wgpu::VertexAttributeDescriptor; 6] = [
+const CHUNK_VERTEX_ATTRIBUTES: [wgpu::VertexAttributeDescriptor; 7] = [
     wgpu::VertexAttributeDescriptor {
         shader_location: 0,
         format: wgpu::VertexFormat::Float3,
@@ -486,6 +906,11 @@ const CHUNK_VERTEX_ATTRIBUTES: [wgpu::VertexAttributeDescriptor; 6] = [
         format: wgpu::VertexFormat::Uint,
         offset: 4 * (3 + 2 + 2 + 2 + 2),
     },
+    wgpu::VertexAttributeDescriptor {
+        shader_location: 6,
+        format: wgpu::VertexFormat::Float2,
+        offset: 4 * (3 + 2 + 2 + 2 + 2 + 1),
+    },
 ];
 
 const CHUNK_BIND_GROUP_LAYOUT: wgpu::BindGroupLayoutDescriptor<'static> =
@@ -514,6 +939,68 @@ const CHUNK_BIND_GROUP_LAYOUT: wgpu::BindGroupLayoutDescriptor<'static> =
                 },
                 count: None
             },
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStage::VERTEX,
+                ty: wgpu::BindingType::UniformBuffer { dynamic: false, min_binding_size: None },
+                count: None
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 4,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::SampledTexture {
+                    component_type: wgpu::TextureComponentType::Uint,
+                    multisampled: false,
+                    dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 5,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::UniformBuffer { dynamic: false, min_binding_size: None },
+                count: None
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 6,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::UniformBuffer { dynamic: false, min_binding_size: None },
+                count: None
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 7,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::UniformBuffer { dynamic: false, min_binding_size: None },
+                count: None
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 8,
+                visibility: wgpu::ShaderStage::VERTEX,
+                ty: wgpu::BindingType::UniformBuffer { dynamic: false, min_binding_size: None },
+                count: None
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 9,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::SampledTexture {
+                    component_type: wgpu::TextureComponentType::Float,
+                    multisampled: false,
+                    dimension: wgpu::TextureViewDimension::D2Array,
+                },
+                count: None
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 10,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::Sampler { comparison: true },
+                count: None
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 11,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::UniformBuffer { dynamic: false, min_binding_size: None },
+                count: None
+            },
         ],
     };
 
@@ -522,7 +1009,14 @@ fn create_chunk_bind_group(
     device: &wgpu::Device,
     layout: &wgpu::BindGroupLayout,
     texture_atlas_view: &wgpu::TextureView,
+    tile_cache_view: &wgpu::TextureView,
     uniform_view_proj: &wgpu::Buffer,
+    uniform_animation_time: &wgpu::Buffer,
+    uniform_page_table: &wgpu::Buffer,
+    uniform_dynamic_lights: &wgpu::Buffer,
+    uniform_weather: &wgpu::Buffer,
+    uniform_season: &wgpu::Buffer,
+    shadow_maps: &ShadowMaps,
 ) -> wgpu::BindGroup {
     // Create texture sampler
     let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
@@ -557,6 +1051,46 @@ fn create_chunk_bind_group(
                 binding: 2,
                 resource: wgpu::BindingResource::TextureView(texture_atlas_view),
             },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::Buffer(
+                    uniform_animation_time.slice(0..16)
+                ),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::TextureView(tile_cache_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: wgpu::BindingResource::Buffer(uniform_page_table.slice(..)),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: wgpu::BindingResource::Buffer(uniform_dynamic_lights.slice(..)),
+            },
+            wgpu::BindGroupEntry {
+                binding: 7,
+                resource: wgpu::BindingResource::Buffer(uniform_weather.slice(0..WEATHER_UNIFORM_SIZE)),
+            },
+            wgpu::BindGroupEntry {
+                binding: 8,
+                resource: wgpu::BindingResource::Buffer(uniform_season.slice(0..16)),
+            },
+            wgpu::BindGroupEntry {
+                binding: 9,
+                resource: wgpu::BindingResource::TextureView(shadow_maps.texture_view()),
+            },
+            wgpu::BindGroupEntry {
+                binding: 10,
+                resource: wgpu::BindingResource::Sampler(shadow_maps.sampler()),
+            },
+            wgpu::BindGroupEntry {
+                binding: 11,
+                resource: wgpu::BindingResource::Buffer(
+                    shadow_maps.uniform_shadows().slice(0..SHADOWS_UNIFORM_SIZE)
+                ),
+            },
         ],
     })
 }
@@ -576,6 +1110,12 @@ const SKYBOX_VERTEX_ATTRIBUTES: [wgpu::VertexAttributeDescriptor; 1] =
         offset: 0,
     }];
 
+/// Size of the model uniform: the model matrix, then the light level of the model in a vec4
+const MODEL_UNIFORM_SIZE: u64 = 64 + 16;
+/// Size of the weather uniform of the chunk shader: the wetness, the ambient light and the distance fog,
+/// then the position of the camera, the fog and the color of the horizon
+const WEATHER_UNIFORM_SIZE: u64 = 4 * 16;
+
 const SKYBOX_BIND_GROUP_LAYOUT: wgpu::BindGroupLayoutDescriptor<'static> =
     wgpu::BindGroupLayoutDescriptor {
         label: None,
@@ -590,7 +1130,14 @@ const SKYBOX_BIND_GROUP_LAYOUT: wgpu::BindGroupLayoutDescriptor<'static> =
             wgpu::BindGroupLayoutEntry {
                 // model
                 binding: 1,
-                visibility: wgpu::ShaderStage::VERTEX,
+                visibility: wgpu::ShaderStage::from_bits_truncate(wgpu::ShaderStage::VERTEX.bits() | wgpu::ShaderStage::FRAGMENT.bits()),
+                ty: wgpu::BindingType::UniformBuffer { dynamic: false, min_binding_size: None },
+                count: None
+            },
+            wgpu::BindGroupLayoutEntry {
+                // sky
+                binding: 2,
+                visibility: wgpu::ShaderStage::FRAGMENT,
                 ty: wgpu::BindingType::UniformBuffer { dynamic: false, min_binding_size: None },
                 count: None
             },
@@ -603,6 +1150,7 @@ fn create_vpm_bind_group(
     layout: &wgpu::BindGroupLayout,
     uniform_view_proj: &wgpu::Buffer,
     uniform_model: &wgpu::Buffer,
+    uniform_sky: &wgpu::Buffer,
 ) -> wgpu::BindGroup {
     device.create_bind_group(&wgpu::BindGroupDescriptor {
         label: None,
@@ -617,7 +1165,13 @@ fn create_vpm_bind_group(
             wgpu::BindGroupEntry {
                 binding: 1,
                 resource: wgpu::BindingResource::Buffer(
-                    uniform_model.slice(0..64)
+                    uniform_model.slice(0..MODEL_UNIFORM_SIZE)
+                ),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Buffer(
+                    uniform_sky.slice(0..32)
                 ),
             },
         ],
@@ -1,5 +1,6 @@
 use super::RgbVertex;
 use voxel_rs_common::data::vox::VoxelModel;
+use voxel_rs_common::world::{BlockPos, Direction};
 
 /// Data structure used to draw a pre-loaded model
 /// Contains the position, scale and its id in the model registry
@@ -11,20 +12,24 @@ pub struct Model {
     pub pos_z: f32,
     /// Model scaling
     pub scale: f32,
-    /// Model rotation (after scaling)
+    /// Model rotation around the y axis (after scaling)
     pub rot_y: f32,
+    /// Model rotation around the z axis, applied before `rot_y`
+    pub rot_z: f32,
     /// Offset to apply before rotating the model
     pub rot_offset: [f32; 3],
 }
 
-const D: [[i32; 3]; 6] = [
-    [1, 0, 0],
-    [-1, 0, 0],
-    [0, 1, 0],
-    [0, -1, 0],
-    [0, 0, 1],
-    [0, 0, -1],
-];
+impl Model {
+    /// The block containing the rotation center of the model, whose light is used to light the whole model
+    pub fn light_sample_pos(&self) -> BlockPos {
+        BlockPos::from((
+            (self.pos_x + self.rot_offset[0]) as f64,
+            (self.pos_y + self.rot_offset[1]) as f64,
+            (self.pos_z + self.rot_offset[2]) as f64,
+        ))
+    }
+}
 
 #[derive(Clone, Copy, Default)]
 pub struct Quad {
@@ -49,23 +54,6 @@ fn ambiant_occl(corners: u32, edge: u32) -> u32 {
     }
 }
 
-const D_DELTA1: [[i32; 3]; 6] = [
-    [0, 1, 0],
-    [0, 1, 0],
-    [1, 0, 0],
-    [1, 0, 0],
-    [1, 0, 0],
-    [1, 0, 0],
-];
-const D_DELTA2: [[i32; 3]; 6] = [
-    [0, 0, 1],
-    [0, 0, 1],
-    [0, 0, 1],
-    [0, 0, 1],
-    [0, 1, 0],
-    [0, 1, 0],
-];
-
 pub fn mesh_model(model: &VoxelModel) -> (Vec<RgbVertex>, Vec<u32>) {
     let mut res_vertex: Vec<RgbVertex> = Vec::new();
     let mut res_index: Vec<usize> = Vec::new();
@@ -110,27 +98,30 @@ pub fn mesh_model(model: &VoxelModel) -> (Vec<RgbVertex>, Vec<u32>) {
 
     for s in 0..6 {
         // each direction
+        let (dx, dy, dz) = Direction::ALL[s].offset();
+        let d = [dx as i32, dy as i32, dz as i32];
         for i in 0..size_x {
             for j in 0..size_y {
                 for k in 0..size_z {
                     if occl[ind(i + 1, j + 1, k + 1)] {
                         //checking if not void
                         if !occl[ind(
-                            i + (1 + D[s][0]) as usize,
-                            j + (1 + D[s][1]) as usize,
-                            k + (1 + D[s][2]) as usize,
+                            i + (1 + d[0]) as usize,
+                            j + (1 + d[1]) as usize,
+                            k + (1 + d[2]) as usize,
                         )] {
                             let mut coins = [0; 4];
                             let mut edge = [0; 4];
+                            let [t1, t2] = Direction::TANGENTS[s];
 
                             for i2 in -1..=1 {
                                 for j2 in -1..=1 {
                                     let dx =
-                                        1 + D[s][0] + D_DELTA1[s][0] * i2 + D_DELTA2[s][0] * j2;
+                                        1 + d[0] + t1[0] * i2 + t2[0] * j2;
                                     let dy =
-                                        1 + D[s][1] + D_DELTA1[s][1] * i2 + D_DELTA2[s][1] * j2;
+                                        1 + d[1] + t1[1] * i2 + t2[1] * j2;
                                     let dz =
-                                        1 + D[s][2] + D_DELTA1[s][2] * i2 + D_DELTA2[s][2] * j2;
+                                        1 + d[2] + t1[2] * i2 + t2[2] * j2;
 
                                     let xx = ((i as i32) + dx) as usize;
                                     let yy = ((j as i32) + dy) as usize;
@@ -0,0 +1,294 @@
+//! GPU occlusion culling of the chunks.
+//!
+//! Once the visible chunks are drawn, the bounding boxes of the chunks in the frustum are drawn against
+//! the depth buffer without writing to it. The fragment shader marks every chunk that has a visible fragment
+//! in a storage buffer, which is read back a few frames later. The chunks that had no visible fragment are
+//! skipped until the next test, so a chunk that becomes visible may appear a few frames late.
+
+use super::super::init::{load_glsl_shader, ShaderStage, RASTERIZER_NO_CULLING};
+use super::super::{buffer_from_slice, to_u8_slice, Viewport};
+use crate::window::WindowBuffers;
+use futures::FutureExt;
+use std::collections::HashSet;
+use std::future::Future;
+use std::pin::Pin;
+use voxel_rs_common::world::{ChunkPos, CHUNK_SIZE};
+
+/// Maximum number of chunks that are tested at once. The other chunks are always drawn.
+const MAX_TESTED_CHUNKS: usize = 16384;
+/// The bounding boxes are slightly larger than the chunks, so that the chunks don't hide themselves
+const BOX_MARGIN: f32 = 0.05;
+
+type MapFuture = Pin<Box<dyn Future<Output = Result<(), wgpu::BufferAsyncError>> + Send>>;
+
+/// Where the last occlusion test is
+enum TestState {
+    /// The buffers can be used for a new test
+    Idle,
+    /// A test was encoded, the readback buffer can be mapped once the commands are submitted
+    Encoded(Vec<ChunkPos>),
+    /// Waiting for the readback buffer to be mapped
+    Mapping(Vec<ChunkPos>, MapFuture),
+}
+
+/// Per-instance data of the bounding box of a chunk
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct ChunkBoxInstance {
+    min: [f32; 3],
+    size: f32,
+    index: u32,
+}
+
+const CHUNK_BOX_INSTANCE_ATTRIBUTES: [wgpu::VertexAttributeDescriptor; 3] = [
+    wgpu::VertexAttributeDescriptor {
+        shader_location: 0,
+        format: wgpu::VertexFormat::Float3,
+        offset: 0,
+    },
+    wgpu::VertexAttributeDescriptor {
+        shader_location: 1,
+        format: wgpu::VertexFormat::Float,
+        offset: 4 * 3,
+    },
+    wgpu::VertexAttributeDescriptor {
+        shader_location: 2,
+        format: wgpu::VertexFormat::Uint,
+        offset: 4 * 4,
+    },
+];
+
+const OCCLUSION_BIND_GROUP_LAYOUT: wgpu::BindGroupLayoutDescriptor<'static> =
+    wgpu::BindGroupLayoutDescriptor {
+        label: None,
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStage::VERTEX,
+                ty: wgpu::BindingType::UniformBuffer { dynamic: false, min_binding_size: None },
+                count: None
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::StorageBuffer {
+                    dynamic: false,
+                    min_binding_size: None,
+                    readonly: false,
+                },
+                count: None
+            },
+        ],
+    };
+
+pub struct OcclusionCuller {
+    pipeline: wgpu::RenderPipeline,
+    bind_group: wgpu::BindGroup,
+    /// One `u32` per tested chunk, set to 1 by the GPU if the chunk is visible
+    visibility_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    state: TestState,
+    occluded_chunks: HashSet<ChunkPos>,
+}
+
+impl OcclusionCuller {
+    pub fn new(device: &wgpu::Device, uniform_view_proj: &wgpu::Buffer) -> Self {
+        let buffer_size = (MAX_TESTED_CHUNKS * std::mem::size_of::<u32>()) as u64;
+        let visibility_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            mapped_at_creation: false,
+            label: None,
+            size: buffer_size,
+            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST | wgpu::BufferUsage::COPY_SRC,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            mapped_at_creation: false,
+            label: None,
+            size: buffer_size,
+            usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&OCCLUSION_BIND_GROUP_LAYOUT);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(uniform_view_proj.slice(0..64)),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Buffer(visibility_buffer.slice(..)),
+                },
+            ],
+        });
+
+        // The boxes are only depth-tested: they don't write any color or depth
+        let vertex_shader_bytes = load_glsl_shader(ShaderStage::Vertex, "assets/shaders/occlusion.vert");
+        let vertex_shader = device.create_shader_module(wgpu::util::make_spirv(&vertex_shader_bytes));
+        let fragment_shader_bytes = load_glsl_shader(ShaderStage::Fragment, "assets/shaders/occlusion.frag");
+        let fragment_shader = device.create_shader_module(wgpu::util::make_spirv(&fragment_shader_bytes));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[]
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: None,
+            layout: Some(&pipeline_layout),
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vertex_shader,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &fragment_shader,
+                entry_point: "main",
+            }),
+            vertex_state: wgpu::VertexStateDescriptor {
+                index_format: wgpu::IndexFormat::Uint32,
+                vertex_buffers: &[wgpu::VertexBufferDescriptor {
+                    stride: std::mem::size_of::<ChunkBoxInstance>() as u64,
+                    step_mode: wgpu::InputStepMode::Instance,
+                    attributes: &CHUNK_BOX_INSTANCE_ATTRIBUTES,
+                }],
+            },
+            rasterization_state: Some(RASTERIZER_NO_CULLING),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            color_states: &[wgpu::ColorStateDescriptor {
+                format: crate::window::HDR_COLOR_FORMAT,
+                color_blend: wgpu::BlendDescriptor::REPLACE,
+                alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                write_mask: wgpu::ColorWrite::empty(),
+            }],
+            depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                ..super::super::init::DEFAULT_DEPTH_STENCIL_STATE_DESCRIPTOR
+            }),
+            sample_count: crate::window::sample_count(),
+            sample_mask: 0xFFFFFFFF,
+            alpha_to_coverage_enabled: false,
+        });
+
+        Self {
+            pipeline,
+            bind_group,
+            visibility_buffer,
+            readback_buffer,
+            state: TestState::Idle,
+            occluded_chunks: HashSet::new(),
+        }
+    }
+
+    /// Check whether the result of the last test is available.
+    /// Must be called once per frame, before the chunks are drawn.
+    pub fn update(&mut self, device: &wgpu::Device) {
+        self.state = match std::mem::replace(&mut self.state, TestState::Idle) {
+            TestState::Idle => TestState::Idle,
+            // The commands of the previous frame were submitted, the buffer can be mapped
+            TestState::Encoded(tested_chunks) => {
+                let future = self.readback_buffer.slice(..).map_async(wgpu::MapMode::Read);
+                TestState::Mapping(tested_chunks, Box::pin(future))
+            }
+            TestState::Mapping(tested_chunks, mut future) => {
+                device.poll(wgpu::Maintain::Poll);
+                match (&mut future).now_or_never() {
+                    None => TestState::Mapping(tested_chunks, future),
+                    Some(Ok(())) => {
+                        {
+                            let data = self.readback_buffer.slice(..).get_mapped_range();
+                            self.occluded_chunks = tested_chunks
+                                .into_iter()
+                                .enumerate()
+                                .filter(|(i, _)| data[4 * i..4 * i + 4] == [0, 0, 0, 0])
+                                .map(|(_, pos)| pos)
+                                .collect();
+                        }
+                        self.readback_buffer.unmap();
+                        TestState::Idle
+                    }
+                    Some(Err(e)) => {
+                        log::warn!("Failed to read the chunk occlusion results: {:?}", e);
+                        TestState::Idle
+                    }
+                }
+            }
+        };
+    }
+
+    /// Return `true` if the chunk was hidden during the last test
+    pub fn is_occluded(&self, pos: ChunkPos) -> bool {
+        self.occluded_chunks.contains(&pos)
+    }
+
+    /// Number of chunks that were hidden during the last test
+    pub fn num_occluded_chunks(&self) -> usize {
+        self.occluded_chunks.len()
+    }
+
+    /// Forget about a chunk that was unloaded
+    pub fn remove_chunk(&mut self, pos: ChunkPos) {
+        self.occluded_chunks.remove(&pos);
+    }
+
+    /// Test the bounding boxes of `chunks` against the current depth buffer, if the previous test is over.
+    /// The chunks close to `camera_chunk` are never tested because the camera could be inside their box.
+    pub fn test_chunks(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        buffers: WindowBuffers,
+        viewport: Viewport,
+        camera_chunk: ChunkPos,
+        chunks: impl Iterator<Item = ChunkPos>,
+    ) {
+        if !matches!(self.state, TestState::Idle) {
+            return;
+        }
+        let tested_chunks: Vec<ChunkPos> = chunks
+            .filter(|pos| {
+                (pos.px - camera_chunk.px).abs() > 1
+                    || (pos.py - camera_chunk.py).abs() > 1
+                    || (pos.pz - camera_chunk.pz).abs() > 1
+            })
+            .take(MAX_TESTED_CHUNKS)
+            .collect();
+        if tested_chunks.is_empty() {
+            self.occluded_chunks.clear();
+            return;
+        }
+        let instances: Vec<ChunkBoxInstance> = tested_chunks
+            .iter()
+            .enumerate()
+            .map(|(i, pos)| ChunkBoxInstance {
+                min: [
+                    (pos.px * CHUNK_SIZE as i64) as f32 - BOX_MARGIN,
+                    (pos.py * CHUNK_SIZE as i64) as f32 - BOX_MARGIN,
+                    (pos.pz * CHUNK_SIZE as i64) as f32 - BOX_MARGIN,
+                ],
+                size: CHUNK_SIZE as f32 + 2.0 * BOX_MARGIN,
+                index: i as u32,
+            })
+            .collect();
+        let tested_size = (tested_chunks.len() * std::mem::size_of::<u32>()) as u64;
+
+        // Reset the visibility of the tested chunks
+        let zeros = vec![0u32; tested_chunks.len()];
+        let src_buffer = buffer_from_slice(device, wgpu::BufferUsage::COPY_SRC, to_u8_slice(&zeros));
+        encoder.copy_buffer_to_buffer(&src_buffer, 0, &self.visibility_buffer, 0, tested_size);
+
+        // Draw the boxes
+        let instance_buffer = buffer_from_slice(device, wgpu::BufferUsage::VERTEX, to_u8_slice(&instances));
+        {
+            let mut rpass = super::super::render::create_default_render_pass(encoder, buffers);
+            viewport.apply(&mut rpass);
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_bind_group(0, &self.bind_group, &[]);
+            rpass.set_vertex_buffer(0, instance_buffer.slice(..));
+            rpass.draw(0..36, 0..(instances.len() as u32));
+        }
+
+        encoder.copy_buffer_to_buffer(&self.visibility_buffer, 0, &self.readback_buffer, 0, tested_size);
+        self.state = TestState::Encoded(tested_chunks);
+    }
+}
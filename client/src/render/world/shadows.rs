@@ -0,0 +1,356 @@
+//! Cascaded shadow maps of the sun.
+//!
+//! The chunks around the camera are rendered from the direction of the sun into one layer of a depth texture
+//! array per cascade. Every cascade covers a larger area than the previous one with the same resolution, so the
+//! shadows are sharp close to the camera. The chunk shader uses the first cascade that contains the fragment.
+
+use super::super::buffers::MultiBuffer;
+use super::super::frustum::opengl_to_wgpu_matrix;
+use super::super::init::{load_glsl_shader, ShaderStage, RASTERIZER_NO_CULLING};
+use super::super::{buffer_from_slice, to_u8_slice};
+use super::ChunkVertex;
+use nalgebra::{Matrix4, Point3, Vector3, Vector4};
+use std::num::NonZeroU32;
+use voxel_rs_common::world::{ChunkPos, CHUNK_SIZE};
+
+/// Maximum number of cascades, must match world.frag
+pub const MAX_SHADOW_CASCADES: usize = 3;
+/// Half of the width of the area covered by every cascade, in blocks
+const CASCADE_RADII: [f64; MAX_SHADOW_CASCADES] = [24.0, 64.0, 160.0];
+/// Distance between the center of a cascade and the sun camera, in blocks.
+/// The blocks up to this distance towards the sun still cast shadows into the cascade.
+const SUN_CAMERA_DISTANCE: f64 = 256.0;
+const SHADOW_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+/// Size of the shadow uniform of the chunk shader: the matrix of every cascade,
+/// then the direction of the sun and the number of cascades, then the size of a texel
+pub const SHADOWS_UNIFORM_SIZE: u64 = 64 * MAX_SHADOW_CASCADES as u64 + 32;
+
+/// Only the position of the chunk vertices is used
+const SHADOW_VERTEX_ATTRIBUTES: [wgpu::VertexAttributeDescriptor; 1] = [wgpu::VertexAttributeDescriptor {
+    shader_location: 0,
+    format: wgpu::VertexFormat::Float3,
+    offset: 0,
+}];
+
+const CASCADE_BIND_GROUP_LAYOUT: wgpu::BindGroupLayoutDescriptor<'static> =
+    wgpu::BindGroupLayoutDescriptor {
+        label: None,
+        entries: &[wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStage::VERTEX,
+            ty: wgpu::BindingType::UniformBuffer { dynamic: false, min_binding_size: None },
+            count: None
+        }],
+    };
+
+pub struct ShadowMaps {
+    resolution: u32,
+    /// Number of cascades that are rendered, 0 if the shadows are disabled
+    cascades: usize,
+    _texture: wgpu::Texture,
+    /// All the cascades, sampled by the chunk shader
+    texture_view: wgpu::TextureView,
+    /// One layer per cascade, rendered to by the shadow pass
+    cascade_views: Vec<wgpu::TextureView>,
+    sampler: wgpu::Sampler,
+    /// The uniform of the chunk shader
+    uniform_shadows: wgpu::Buffer,
+    /// The view-projection matrix of every cascade
+    uniform_cascades: Vec<wgpu::Buffer>,
+    cascade_bind_groups: Vec<wgpu::BindGroup>,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl ShadowMaps {
+    /// Create the shadow maps, `resolution` is the width and the height of every cascade
+    pub fn new(device: &wgpu::Device, resolution: u32) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size: wgpu::Extent3d {
+                width: resolution,
+                height: resolution,
+                depth: MAX_SHADOW_CASCADES as u32,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: SHADOW_FORMAT,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+        });
+        let texture_view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let cascade_views = (0..MAX_SHADOW_CASCADES)
+            .map(|cascade| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: cascade as u32,
+                    array_layer_count: NonZeroU32::new(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        // Linear filtering of a comparison sampler blends the results of the 4 closest texels
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: None,
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            lod_min_clamp: 0.0,
+            lod_max_clamp: 0.0,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            anisotropy_clamp: None
+        });
+
+        let uniform_shadows = device.create_buffer(&wgpu::BufferDescriptor {
+            mapped_at_creation: false,
+            label: None,
+            size: SHADOWS_UNIFORM_SIZE,
+            usage: (wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST),
+        });
+        let bind_group_layout = device.create_bind_group_layout(&CASCADE_BIND_GROUP_LAYOUT);
+        let uniform_cascades: Vec<_> = (0..MAX_SHADOW_CASCADES)
+            .map(|_| {
+                device.create_buffer(&wgpu::BufferDescriptor {
+                    mapped_at_creation: false,
+                    label: None,
+                    size: 64,
+                    usage: (wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST),
+                })
+            })
+            .collect();
+        let cascade_bind_groups = uniform_cascades
+            .iter()
+            .map(|uniform_cascade| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: None,
+                    layout: &bind_group_layout,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::Buffer(uniform_cascade.slice(0..64)),
+                    }],
+                })
+            })
+            .collect();
+
+        // The shadow pass only writes depth, biased to avoid shadow acne
+        let vertex_shader_bytes = load_glsl_shader(ShaderStage::Vertex, "assets/shaders/shadow.vert");
+        let vertex_shader = device.create_shader_module(wgpu::util::make_spirv(&vertex_shader_bytes));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[]
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: None,
+            layout: Some(&pipeline_layout),
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vertex_shader,
+                entry_point: "main",
+            },
+            fragment_stage: None,
+            vertex_state: wgpu::VertexStateDescriptor {
+                index_format: wgpu::IndexFormat::Uint32,
+                vertex_buffers: &[wgpu::VertexBufferDescriptor {
+                    stride: std::mem::size_of::<ChunkVertex>() as u64,
+                    step_mode: wgpu::InputStepMode::Vertex,
+                    attributes: &SHADOW_VERTEX_ATTRIBUTES,
+                }],
+            },
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                depth_bias: 2,
+                depth_bias_slope_scale: 2.0,
+                ..RASTERIZER_NO_CULLING
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            color_states: &[],
+            depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+                format: SHADOW_FORMAT,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                ..super::super::init::DEFAULT_DEPTH_STENCIL_STATE_DESCRIPTOR
+            }),
+            sample_count: 1,
+            sample_mask: 0xFFFFFFFF,
+            alpha_to_coverage_enabled: false,
+        });
+
+        Self {
+            resolution,
+            cascades: MAX_SHADOW_CASCADES,
+            _texture: texture,
+            texture_view,
+            cascade_views,
+            sampler,
+            uniform_shadows,
+            uniform_cascades,
+            cascade_bind_groups,
+            pipeline,
+        }
+    }
+
+    /// Set the number of rendered cascades, 0 to disable the shadows
+    pub fn set_cascades(&mut self, cascades: usize) {
+        self.cascades = cascades.min(MAX_SHADOW_CASCADES);
+    }
+
+    pub fn texture_view(&self) -> &wgpu::TextureView {
+        &self.texture_view
+    }
+
+    pub fn sampler(&self) -> &wgpu::Sampler {
+        &self.sampler
+    }
+
+    pub fn uniform_shadows(&self) -> &wgpu::Buffer {
+        &self.uniform_shadows
+    }
+
+    /// Render the chunks into the cascades around `center`, and update the uniform of the chunk shader.
+    /// Nothing is rendered if there is no sun above the horizon.
+    pub fn render(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        sun_direction: Option<Vector3<f64>>,
+        center: Vector3<f64>,
+        chunk_index_buffers: &MultiBuffer<ChunkPos, u32>,
+        chunk_vertex_buffers: &MultiBuffer<ChunkPos, ChunkVertex>,
+    ) {
+        let sun_direction = sun_direction.filter(|direction| direction.y > 0.0);
+        let matrices = match sun_direction {
+            Some(direction) => cascade_matrices(direction, center, self.resolution, self.cascades),
+            None => Vec::new(),
+        };
+
+        // Update the uniform of the chunk shader
+        let opengl_to_wgpu = opengl_to_wgpu_matrix();
+        let mut uniform_data = Vec::with_capacity(SHADOWS_UNIFORM_SIZE as usize / 4);
+        for cascade in 0..MAX_SHADOW_CASCADES {
+            let matrix = matrices.get(cascade).map_or_else(Matrix4::zeros, |matrix| opengl_to_wgpu * matrix);
+            let matrix: Matrix4<f32> = nalgebra::convert(matrix);
+            uniform_data.extend_from_slice(matrix.as_slice());
+        }
+        let direction = sun_direction.unwrap_or_else(Vector3::y);
+        uniform_data.extend_from_slice(&[
+            direction.x as f32,
+            direction.y as f32,
+            direction.z as f32,
+            matrices.len() as f32,
+            1.0 / self.resolution as f32,
+            0.0,
+            0.0,
+            0.0,
+        ]);
+        let src_buffer = buffer_from_slice(device, wgpu::BufferUsage::COPY_SRC, to_u8_slice(&uniform_data));
+        encoder.copy_buffer_to_buffer(&src_buffer, 0, &self.uniform_shadows, 0, SHADOWS_UNIFORM_SIZE);
+
+        // Render every cascade
+        for (cascade, matrix) in matrices.iter().enumerate() {
+            let view_proj: Matrix4<f32> = nalgebra::convert(opengl_to_wgpu * matrix);
+            let src_buffer = buffer_from_slice(device, wgpu::BufferUsage::COPY_SRC, to_u8_slice(view_proj.as_slice()));
+            encoder.copy_buffer_to_buffer(&src_buffer, 0, &self.uniform_cascades[cascade], 0, 64);
+
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                    attachment: &self.cascade_views[cascade],
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_bind_group(0, &self.cascade_bind_groups[cascade], &[]);
+            rpass.set_vertex_buffer(0, chunk_vertex_buffers.get_buffer().slice(..));
+            rpass.set_index_buffer(chunk_index_buffers.get_buffer().slice(..));
+            for chunk_pos in chunk_index_buffers.keys() {
+                if !cascade_contains_chunk(matrix, CASCADE_RADII[cascade], chunk_pos) {
+                    continue;
+                }
+                let (index_pos, index_len) = chunk_index_buffers.get_pos_len(&chunk_pos).unwrap();
+                let (vertex_pos, _) = chunk_vertex_buffers.get_pos_len(&chunk_pos).unwrap();
+                rpass.draw_indexed(
+                    (index_pos as u32)..((index_pos + index_len) as u32),
+                    vertex_pos as i32,
+                    0..1,
+                );
+            }
+        }
+    }
+}
+
+/// The view-projection matrices of the cascades around `center`, in the OpenGL clip space.
+/// The cascades move by whole texels so that the edges of the shadows don't shimmer when the camera moves.
+fn cascade_matrices(sun_direction: Vector3<f64>, center: Vector3<f64>, resolution: u32, cascades: usize) -> Vec<Matrix4<f64>> {
+    // Any up vector works as long as it's not parallel to the sun
+    let up = if sun_direction.y.abs() < 0.99 { Vector3::y() } else { Vector3::z() };
+    // The sun camera looks towards -z, the sun is towards +z
+    let rotation = Matrix4::look_at_rh(&Point3::origin(), &Point3::from(-sun_direction), &up);
+    let rotated_center = rotation.transform_point(&Point3::from(center));
+    CASCADE_RADII
+        .iter()
+        .take(cascades)
+        .map(|&radius| {
+            let texel = 2.0 * radius / resolution as f64;
+            let eye = Vector3::new(
+                (rotated_center.x / texel).floor() * texel,
+                (rotated_center.y / texel).floor() * texel,
+                rotated_center.z + SUN_CAMERA_DISTANCE,
+            );
+            let view = Matrix4::new_translation(&-eye) * rotation;
+            let proj = Matrix4::new_orthographic(-radius, radius, -radius, radius, 0.0, 2.0 * SUN_CAMERA_DISTANCE);
+            proj * view
+        })
+        .collect()
+}
+
+/// Check whether a chunk may cast a shadow into the cascade with the given matrix and radius
+fn cascade_contains_chunk(matrix: &Matrix4<f64>, radius: f64, chunk_pos: ChunkPos) -> bool {
+    let half_size = CHUNK_SIZE as f64 / 2.0;
+    let chunk_center = Vector4::new(
+        chunk_pos.px as f64 * CHUNK_SIZE as f64 + half_size,
+        chunk_pos.py as f64 * CHUNK_SIZE as f64 + half_size,
+        chunk_pos.pz as f64 * CHUNK_SIZE as f64 + half_size,
+        1.0,
+    );
+    let clip = matrix * chunk_center;
+    // Half of the diagonal of the chunk, in clip space
+    let half_diagonal = half_size * 3.0f64.sqrt();
+    let margin_xy = 1.0 + half_diagonal / radius;
+    let margin_z = 1.0 + half_diagonal / SUN_CAMERA_DISTANCE;
+    clip.x.abs() <= margin_xy && clip.y.abs() <= margin_xy && clip.z.abs() <= margin_z
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cascade_matrices() {
+        let sun_direction = Vector3::new(1.0, 2.0, 0.5).normalize();
+        let center = Vector3::new(100.3, 64.7, -20.1);
+        let matrices = cascade_matrices(sun_direction, center, 1024, MAX_SHADOW_CASCADES);
+        assert_eq!(matrices.len(), MAX_SHADOW_CASCADES);
+        for (matrix, &radius) in matrices.iter().zip(CASCADE_RADII.iter()) {
+            // The center is at most one texel away from the middle of the cascade
+            let projected = matrix.transform_point(&Point3::from(center));
+            let texel = 2.0 / 1024.0;
+            assert!(projected.x.abs() <= texel && projected.y.abs() <= texel);
+            assert!(projected.z.abs() < 1e-6);
+            // The blocks towards the sun are closer to the sun camera
+            let towards_sun = matrix.transform_point(&Point3::from(center + sun_direction * 10.0));
+            assert!(towards_sun.z < projected.z);
+            // The edge of the cascade is at the radius
+            let side = sun_direction.cross(&Vector3::y()).normalize();
+            let edge = matrix.transform_point(&Point3::from(center + side * radius));
+            assert!((edge.x.hypot(edge.y) - 1.0).abs() < 2.0 * texel);
+        }
+        assert!(cascade_matrices(sun_direction, center, 1024, 0).is_empty());
+    }
+}
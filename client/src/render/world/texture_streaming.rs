@@ -0,0 +1,217 @@
+//! Streaming of the high resolution mipmaps of the texture atlas.
+//!
+//! Only the low resolution mipmaps of the atlas stay on the GPU. The atlas is split into square tiles,
+//! and the high resolution mipmaps of the tiles used by the blocks close to the player are uploaded
+//! to a smaller tile cache. The page table tells the shader where every tile is in the cache.
+
+use super::super::{buffer_from_slice, to_u8_slice};
+use crate::texture::{generate_mipmaps, load_mipmaps};
+use image::{ImageBuffer, Rgba};
+use voxel_rs_common::block::BlockMesh;
+use voxel_rs_common::data::MAX_TEXTURE_SIZE;
+
+/// Size of a tile in the first mipmap of the atlas, in pixels
+const TILE_SIZE: u32 = 256;
+/// Number of tiles on every side of the atlas
+const ATLAS_TILES: u32 = MAX_TEXTURE_SIZE / TILE_SIZE;
+/// Number of tiles on every side of the tile cache
+const CACHE_TILES: u32 = 4;
+/// The mipmaps before this level are streamed, the other ones are always on the GPU
+pub const RESIDENT_LEVEL: usize = 2;
+/// Size of the page table uniform, padded to a multiple of 16 bytes
+const PAGE_TABLE_SIZE: u64 = ((ATLAS_TILES * ATLAS_TILES * 4 + 15) / 16 * 16) as u64;
+
+/// The tiles of the atlas that contain the textures of a block
+pub fn tiles_of_mesh(mesh: &BlockMesh) -> Vec<u32> {
+    let mut tiles = Vec::new();
+    if let BlockMesh::FullCube { textures, .. } = mesh {
+        for rect in textures.iter() {
+            let tile = |coord: f32| ((coord * ATLAS_TILES as f32) as u32).min(ATLAS_TILES - 1);
+            let (min_x, max_x) = (tile(rect.x), tile(rect.x + rect.width - 1e-6));
+            let height = rect.height * rect.frames.max(1) as f32;
+            let (min_y, max_y) = (tile(rect.y), tile(rect.y + height - 1e-6));
+            for ty in min_y..=max_y {
+                for tx in min_x..=max_x {
+                    tiles.push(ty * ATLAS_TILES + tx);
+                }
+            }
+        }
+    }
+    tiles.sort_unstable();
+    tiles.dedup();
+    tiles
+}
+
+/// Keeps the resident mipmaps, the tile cache and the page table up to date
+pub struct TextureStreamer {
+    /// The streamed mipmaps of the atlas, kept in memory to upload the tiles
+    streamed_mipmaps: Vec<Vec<u8>>,
+    resident_texture: wgpu::Texture,
+    cache_texture: wgpu::Texture,
+    uniform_page_table: wgpu::Buffer,
+    /// For every tile, 0 if it's not in the cache or 1 + its slot in the cache
+    page_table: Vec<u32>,
+    /// The tile in every slot of the cache
+    slots: Vec<Option<u32>>,
+    /// The tiles that should be in the cache, most important first
+    requested_tiles: Vec<u32>,
+    page_table_changed: bool,
+    /// Maximum number of tiles uploaded every frame
+    uploads_per_frame: usize,
+}
+
+impl TextureStreamer {
+    pub fn new(
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture_atlas: ImageBuffer<Rgba<u8>, Vec<u8>>,
+        uploads_per_frame: usize,
+    ) -> Self {
+        assert_eq!(texture_atlas.width(), MAX_TEXTURE_SIZE);
+        assert_eq!(texture_atlas.height(), MAX_TEXTURE_SIZE);
+        let mut streamed_mipmaps = generate_mipmaps(&texture_atlas);
+        let resident_mipmaps = streamed_mipmaps.split_off(RESIDENT_LEVEL);
+        let resident_texture = load_mipmaps(
+            device,
+            encoder,
+            &resident_mipmaps,
+            MAX_TEXTURE_SIZE >> RESIDENT_LEVEL,
+        );
+
+        let cache_size = CACHE_TILES * TILE_SIZE;
+        let cache_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size: wgpu::Extent3d {
+                width: cache_size,
+                height: cache_size,
+                depth: 1,
+            },
+            mip_level_count: RESIDENT_LEVEL as u32,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsage::COPY_DST | wgpu::TextureUsage::SAMPLED,
+        });
+        let uniform_page_table = device.create_buffer(&wgpu::BufferDescriptor {
+            mapped_at_creation: false,
+            label: None,
+            size: PAGE_TABLE_SIZE,
+            usage: (wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST),
+        });
+
+        Self {
+            streamed_mipmaps,
+            resident_texture,
+            cache_texture,
+            uniform_page_table,
+            page_table: vec![0; (ATLAS_TILES * ATLAS_TILES) as usize],
+            slots: vec![None; (CACHE_TILES * CACHE_TILES) as usize],
+            requested_tiles: Vec::new(),
+            page_table_changed: true,
+            uploads_per_frame,
+        }
+    }
+
+    pub fn resident_texture(&self) -> &wgpu::Texture {
+        &self.resident_texture
+    }
+
+    pub fn cache_texture(&self) -> &wgpu::Texture {
+        &self.cache_texture
+    }
+
+    pub fn uniform_page_table(&self) -> &wgpu::Buffer {
+        &self.uniform_page_table
+    }
+
+    /// Set the tiles that should be in the cache, most important first.
+    /// The tiles that don't fit in the cache are ignored.
+    pub fn request_tiles(&mut self, mut tiles: Vec<u32>) {
+        tiles.truncate(self.slots.len());
+        self.requested_tiles = tiles;
+    }
+
+    /// Evict the tiles that are not requested anymore, upload a few requested tiles and update the page table
+    pub fn update(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
+        for slot in self.slots.iter_mut() {
+            if let Some(tile) = *slot {
+                if !self.requested_tiles.contains(&tile) {
+                    self.page_table[tile as usize] = 0;
+                    *slot = None;
+                    self.page_table_changed = true;
+                }
+            }
+        }
+
+        let missing_tiles = self
+            .requested_tiles
+            .iter()
+            .cloned()
+            .filter(|&tile| self.page_table[tile as usize] == 0)
+            .take(self.uploads_per_frame)
+            .collect::<Vec<_>>();
+        for tile in missing_tiles {
+            let slot = match self.slots.iter().position(Option::is_none) {
+                Some(slot) => slot,
+                None => break,
+            };
+            self.upload_tile(device, encoder, tile, slot as u32);
+            self.slots[slot] = Some(tile);
+            self.page_table[tile as usize] = slot as u32 + 1;
+            self.page_table_changed = true;
+        }
+
+        if self.page_table_changed {
+            self.page_table_changed = false;
+            let mut page_table = self.page_table.clone();
+            page_table.resize((PAGE_TABLE_SIZE / 4) as usize, 0);
+            let src_buffer = buffer_from_slice(
+                device,
+                wgpu::BufferUsage::COPY_SRC,
+                to_u8_slice(&page_table)
+            );
+            encoder.copy_buffer_to_buffer(&src_buffer, 0, &self.uniform_page_table, 0, PAGE_TABLE_SIZE);
+        }
+    }
+
+    /// Copy the streamed mipmaps of a tile to a slot of the cache
+    fn upload_tile(&self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, tile: u32, slot: u32) {
+        let (tile_x, tile_y) = (tile % ATLAS_TILES, tile / ATLAS_TILES);
+        let (slot_x, slot_y) = (slot % CACHE_TILES, slot / CACHE_TILES);
+        for (level, mipmap) in self.streamed_mipmaps.iter().enumerate() {
+            let atlas_size = (MAX_TEXTURE_SIZE >> level) as usize;
+            let tile_size = (TILE_SIZE >> level) as usize;
+            // Extract the rows of the tile
+            let mut pixels = Vec::with_capacity(tile_size * tile_size * 4);
+            for row in 0..tile_size {
+                let start = ((tile_y as usize * tile_size + row) * atlas_size + tile_x as usize * tile_size) * 4;
+                pixels.extend_from_slice(&mipmap[start..start + tile_size * 4]);
+            }
+            let src_buffer = buffer_from_slice(device, wgpu::BufferUsage::COPY_SRC, &pixels);
+            encoder.copy_buffer_to_texture(
+                wgpu::BufferCopyView {
+                    layout: wgpu::TextureDataLayout {
+                        offset: 0,
+                        rows_per_image: tile_size as u32,
+                        bytes_per_row: 4 * tile_size as u32,
+                    },
+                    buffer: &src_buffer,
+                },
+                wgpu::TextureCopyView {
+                    texture: &self.cache_texture,
+                    mip_level: level as u32,
+                    origin: wgpu::Origin3d {
+                        x: slot_x * tile_size as u32,
+                        y: slot_y * tile_size as u32,
+                        z: 0,
+                    },
+                },
+                wgpu::Extent3d {
+                    width: tile_size as u32,
+                    height: tile_size as u32,
+                    depth: 1,
+                },
+            );
+        }
+    }
+}
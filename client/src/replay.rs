@@ -0,0 +1,317 @@
+//! Recording and replaying of input events.
+//!
+//! A script is a RON file containing the events of every frame. It can be recorded from a real
+//! session by setting the `VOXEL_RS_RECORD_INPUT` environment variable to the output path, and
+//! replayed by setting `VOXEL_RS_REPLAY_INPUT` to the script path. This makes it possible to
+//! exercise menu flows without any user interaction.
+//!
+//! If `VOXEL_RS_HEADLESS` is also set, the script runs without opening a window: the states
+//! are rendered offscreen and the game exits at the end of the script.
+use crate::{input::InputState, settings::Settings, touch::TouchInput, window::State};
+use anyhow::{Context, Result};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use winit::dpi::{LogicalPosition, PhysicalPosition};
+use winit::event::{ElementState, MouseButton, TouchPhase};
+
+const RECORD_VAR: &str = "VOXEL_RS_RECORD_INPUT";
+const REPLAY_VAR: &str = "VOXEL_RS_REPLAY_INPUT";
+const HEADLESS_VAR: &str = "VOXEL_RS_HEADLESS";
+
+/// A synthetic input event, mirroring the winit events that the states care about.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ScriptEvent {
+    /// The cursor moved to the given logical position
+    CursorMoved(f64, f64),
+    /// The mouse moved by the given delta
+    MouseMotion(f64, f64),
+    /// A mouse button was pressed (`true`) or released (`false`)
+    MouseInput(ScriptMouseButton, bool),
+    /// The key with the given scancode was pressed (`true`) or released (`false`)
+    KeyboardInput(u32, bool),
+    /// The touch with the given id changed phase or moved at the given physical position
+    Touch(u64, ScriptTouchPhase, f64, f64),
+}
+
+impl From<TouchInput> for ScriptEvent {
+    fn from(touch: TouchInput) -> Self {
+        Self::Touch(touch.id, touch.phase.into(), touch.location.x, touch.location.y)
+    }
+}
+
+/// Serializable version of winit's `MouseButton`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ScriptMouseButton {
+    Left,
+    Right,
+    Middle,
+    Other(u16),
+}
+
+impl From<MouseButton> for ScriptMouseButton {
+    fn from(button: MouseButton) -> Self {
+        match button {
+            MouseButton::Left => Self::Left,
+            MouseButton::Right => Self::Right,
+            MouseButton::Middle => Self::Middle,
+            MouseButton::Other(x) => Self::Other(x),
+        }
+    }
+}
+
+impl From<ScriptMouseButton> for MouseButton {
+    fn from(button: ScriptMouseButton) -> Self {
+        match button {
+            ScriptMouseButton::Left => Self::Left,
+            ScriptMouseButton::Right => Self::Right,
+            ScriptMouseButton::Middle => Self::Middle,
+            ScriptMouseButton::Other(x) => Self::Other(x),
+        }
+    }
+}
+
+/// Serializable version of winit's `TouchPhase`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ScriptTouchPhase {
+    Started,
+    Moved,
+    Ended,
+    Cancelled,
+}
+
+impl From<TouchPhase> for ScriptTouchPhase {
+    fn from(phase: TouchPhase) -> Self {
+        match phase {
+            TouchPhase::Started => Self::Started,
+            TouchPhase::Moved => Self::Moved,
+            TouchPhase::Ended => Self::Ended,
+            TouchPhase::Cancelled => Self::Cancelled,
+        }
+    }
+}
+
+impl From<ScriptTouchPhase> for TouchPhase {
+    fn from(phase: ScriptTouchPhase) -> Self {
+        match phase {
+            ScriptTouchPhase::Started => Self::Started,
+            ScriptTouchPhase::Moved => Self::Moved,
+            ScriptTouchPhase::Ended => Self::Ended,
+            ScriptTouchPhase::Cancelled => Self::Cancelled,
+        }
+    }
+}
+
+fn element_state(pressed: bool) -> ElementState {
+    if pressed {
+        ElementState::Pressed
+    } else {
+        ElementState::Released
+    }
+}
+
+/// A list of input events, grouped by frame
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InputScript {
+    /// The events of every frame. An empty frame just waits for one frame.
+    pub frames: Vec<Vec<ScriptEvent>>,
+    /// `true` if the window should be closed once the script is over
+    pub close_window_at_end: bool,
+}
+
+impl InputScript {
+    /// Load a script from a RON file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let buf = std::fs::read_to_string(path)
+            .context(format!("Failed to read input script {}", path.display()))?;
+        ron::de::from_str(&buf).context(format!("Failed to parse input script {}", path.display()))
+    }
+
+    /// Write the script to a RON file
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let string = ron::ser::to_string_pretty(self, Default::default())
+            .context("Failed to serialize input script")?;
+        std::fs::write(path, string)
+            .context(format!("Failed to write input script {}", path.display()))?;
+        Ok(())
+    }
+}
+
+/// Records the input events of every frame
+pub struct InputRecorder {
+    path: PathBuf,
+    script: InputScript,
+    current_frame: Vec<ScriptEvent>,
+}
+
+impl InputRecorder {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            script: InputScript::default(),
+            current_frame: Vec::new(),
+        }
+    }
+
+    /// Record one event in the current frame
+    pub fn record(&mut self, event: ScriptEvent) {
+        self.current_frame.push(event);
+    }
+
+    /// Finish the current frame
+    pub fn end_frame(&mut self) {
+        let frame = std::mem::replace(&mut self.current_frame, Vec::new());
+        self.script.frames.push(frame);
+    }
+
+    /// Write the recorded script to its file
+    pub fn save(&mut self) -> Result<()> {
+        self.end_frame();
+        info!("Saving recorded input to {}", self.path.display());
+        self.script.save(&self.path)
+    }
+}
+
+/// Replays an `InputScript` frame by frame
+pub struct InputPlayer {
+    frames: std::vec::IntoIter<Vec<ScriptEvent>>,
+    close_window_at_end: bool,
+}
+
+impl InputPlayer {
+    pub fn new(script: InputScript) -> Self {
+        Self {
+            frames: script.frames.into_iter(),
+            close_window_at_end: script.close_window_at_end,
+        }
+    }
+
+    /// Get the events of the next frame, or `None` if the script is over
+    pub fn next_frame(&mut self) -> Option<Vec<ScriptEvent>> {
+        self.frames.next()
+    }
+
+    /// `true` if the window should be closed once the script is over
+    pub fn close_window_at_end(&self) -> bool {
+        self.close_window_at_end
+    }
+}
+
+/// Create the recorder and the player requested by the environment variables, if any
+pub fn from_env() -> Result<(Option<InputRecorder>, Option<InputPlayer>)> {
+    let recorder = std::env::var_os(RECORD_VAR).map(|path| {
+        info!("Recording input to {:?}", path);
+        InputRecorder::new(path.into())
+    });
+    let player = match std::env::var_os(REPLAY_VAR) {
+        Some(path) => {
+            info!("Replaying input from {:?}", path);
+            Some(InputPlayer::new(InputScript::load(path)?))
+        }
+        None => None,
+    };
+    Ok((recorder, player))
+}
+
+/// `true` if the environment asks to replay the input script without opening a window
+pub fn headless() -> bool {
+    std::env::var_os(HEADLESS_VAR).is_some()
+}
+
+/// Feed the events of one frame to the input state and to the current window state,
+/// the same way the window would with real events.
+/// The touches are returned, the window handles them with `window::handle_touch` because they depend on its state.
+pub fn feed_events(
+    events: Vec<ScriptEvent>,
+    settings: &Settings,
+    input_state: &mut InputState,
+    state: &mut dyn State,
+    mouse_state_changes: &mut Vec<(MouseButton, ElementState)>,
+    key_state_changes: &mut Vec<(u32, ElementState)>,
+) -> Vec<TouchInput> {
+    let mut touches = Vec::new();
+    for event in events.into_iter() {
+        match event {
+            ScriptEvent::CursorMoved(x, y) => {
+                state.handle_cursor_movement(LogicalPosition::new(x, y))
+            }
+            ScriptEvent::MouseMotion(dx, dy) => state.handle_mouse_motion(settings, (dx, dy)),
+            ScriptEvent::MouseInput(button, pressed) => {
+                let (button, element_state) = (button.into(), element_state(pressed));
+                if input_state.process_mouse_input(element_state, button) {
+                    mouse_state_changes.push((button, element_state));
+                }
+            }
+            ScriptEvent::KeyboardInput(scancode, pressed) => {
+                let element_state = element_state(pressed);
+                if input_state.process_key(scancode, element_state) {
+                    key_state_changes.push((scancode, element_state));
+                }
+            }
+            ScriptEvent::Touch(id, phase, x, y) => touches.push(TouchInput {
+                id,
+                phase: phase.into(),
+                location: PhysicalPosition::new(x, y),
+            }),
+        }
+    }
+    touches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+    use crate::ui::Ui;
+    use crate::window::WindowData;
+    use voxel_rs_common::debug::DebugInfo;
+    use winit::dpi::{LogicalSize, PhysicalSize};
+
+    #[test]
+    fn test_pause_menu_flow() {
+        let script: InputScript = ron::de::from_str(
+            "(frames: [
+                [KeyboardInput(1, true), KeyboardInput(1, false)],
+                [CursorMoved(800.0, 450.0), MouseInput(Left, true), MouseInput(Left, false)],
+                [CursorMoved(800.0, 170.0), MouseInput(Left, true), MouseInput(Left, false)],
+                [CursorMoved(800.0, 840.0), MouseInput(Left, true), MouseInput(Left, false)],
+                [CursorMoved(800.0, 150.0), MouseInput(Left, true), MouseInput(Left, false)],
+            ])",
+        )
+        .unwrap();
+        let data = WindowData {
+            logical_window_size: LogicalSize::new(1600.0, 900.0),
+            physical_window_size: PhysicalSize::new(1600, 900),
+            hidpi_factor: 1.0,
+            focused: true,
+        };
+        let mut debug_info = DebugInfo::new_current();
+        let mut settings = Settings::default();
+        let mut ui = Ui::new();
+        let mut player = InputPlayer::new(script);
+        let mut menu_shown = Vec::new();
+        while let Some(frame) = player.next_frame() {
+            for event in frame {
+                match event {
+                    ScriptEvent::CursorMoved(x, y) => ui.cursor_moved(LogicalPosition::new(x, y)),
+                    ScriptEvent::MouseInput(button, pressed) => ui
+                        .handle_mouse_state_changes(vec![(button.into(), element_state(pressed))]),
+                    ScriptEvent::KeyboardInput(key, pressed) => {
+                        ui.handle_key_state_changes(vec![(key, element_state(pressed))])
+                    }
+                    ScriptEvent::MouseMotion(_, _) | ScriptEvent::Touch(..) => (),
+                }
+            }
+            ui.update(&mut settings);
+            ui.rebuild(&settings, &mut debug_info, &data, &[]).unwrap();
+            menu_shown.push(!ui.should_capture_mouse());
+        }
+        // Escape opens the menu, then SETTINGS, the FOV slider, BACK and RESUME are clicked
+        assert_eq!(menu_shown, vec![true, true, true, true, false]);
+        assert_eq!(settings.fov, 75.0);
+        assert!(!ui.should_exit());
+    }
+}
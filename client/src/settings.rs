@@ -1,3 +1,5 @@
+use crate::input::{GamepadAction, GamepadBinding, GamepadButton};
+use crate::render::world::MAX_SHADOW_CASCADES;
 use anyhow::{Context, Result};
 use log::info;
 use serde::{Deserialize, Serialize};
@@ -6,6 +8,14 @@ use std::{
     io::{Read, Write},
     path::Path,
 };
+use voxel_rs_common::config::ClientTuning;
+use voxel_rs_common::player::RenderDistance;
+use voxel_rs_common::world::CHUNK_SIZE;
+
+/// Folder containing the settings file
+pub const SETTINGS_FOLDER: &str = "config";
+/// Path of the settings file
+pub const SETTINGS_FILE: &str = "config/settings.toml";
 
 pub fn load_settings(folder_path: &Path, file_path: &Path) -> Result<Settings> {
     info!(
@@ -36,16 +46,20 @@ pub fn load_settings(folder_path: &Path, file_path: &Path) -> Result<Settings> {
         ))?
     } else {
         std::fs::create_dir_all(folder_path)?;
-        let settings = Settings::default();
-        write_settings(file_path, &settings)?;
-        settings
+        Settings::default()
     };
 
-    // TODO: write settings
+    // Write the settings back to make sure that new options are added to the file
+    write_settings(file_path, &settings)?;
 
     Ok(settings)
 }
 
+/// Persist the settings to the settings file
+pub fn save_settings(settings: &Settings) -> Result<()> {
+    write_settings(SETTINGS_FILE, settings)
+}
+
 fn write_settings(path: impl AsRef<Path>, settings: &Settings) -> Result<()> {
     info!("Writing settings...");
     let path = path.as_ref();
@@ -69,7 +83,211 @@ fn write_settings(path: impl AsRef<Path>, settings: &Settings) -> Result<()> {
 pub struct Settings {
     pub window_size: (u32, u32),
     pub invert_mouse: bool,
+    /// Rotation of the camera in degrees per unit of mouse movement
+    pub mouse_sensitivity: f64,
+    /// `true` to use the raw mouse movement, `false` to use the movement of the cursor,
+    /// which includes the pointer acceleration of the platform
+    pub raw_mouse_input: bool,
+    /// Mouse smoothing factor between 0 (no smoothing) and 1 (excluded)
+    pub mouse_smoothing: f64,
     pub render_distance: (u64, u64, u64, u64, u64, u64),
+    /// Vertical field of view, in degrees
+    pub fov: f64,
+    /// Number of samples per pixel, must be 1, 2, 4 or 8. Only applied on restart.
+    pub msaa_samples: u32,
+    /// Antialiasing method. Only applied on restart.
+    pub antialiasing: Antialiasing,
+    /// Shadows of the sun
+    pub shadows: ShadowSettings,
+    /// Distance fog, and the effects in the water and below the world
+    pub fog: FogSettings,
+    /// Contrast of the final image, 1 to keep the colors unchanged
+    pub contrast: f32,
+    /// Path of a color lookup table applied to the final image, see `crate::render::Tonemapping`.
+    /// Only applied on restart.
+    pub color_lookup_table: Option<String>,
+    /// `true` to wait for the vertical blank before presenting a frame
+    pub vsync: bool,
+    /// `true` for borderless fullscreen
+    pub fullscreen: bool,
+    /// `true` to move and look around with touch controls
+    pub touch_controls: bool,
+    /// `true` to make the menus bigger, for touch screens
+    pub large_ui: bool,
+    /// Address of the last multiplayer server
+    pub server_address: String,
+    /// `true` to record local session statistics, see `crate::analytics`
+    pub session_analytics: bool,
+    /// Username sent to the servers, and displayed above the player
+    pub display_name: String,
+    /// Token sent with the username to the servers that reserve it
+    pub login_token: Option<String>,
+    /// Volume of the sounds, between 0 and 1
+    pub sound_volume: f32,
+    /// Gamepad controls, see `crate::gamepad::GamepadControls`
+    pub gamepad: GamepadSettings,
+    /// Performance tuning of the engine. Only applied on restart.
+    pub tuning: ClientTuning,
+}
+
+/// Settings of the gamepad controls
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct GamepadSettings {
+    /// `true` to listen to the gamepads. Only applied on restart.
+    pub enabled: bool,
+    /// Stick tilts below this fraction of the full tilt are ignored, for the left stick
+    pub move_deadzone: f64,
+    /// Stick tilts below this fraction of the full tilt are ignored, for the right stick
+    pub look_deadzone: f64,
+    /// Rotation of the camera in degrees per second when the right stick is fully tilted
+    pub look_speed: f64,
+    /// Speed of the cursor in the menus in logical pixels per second
+    pub cursor_speed: f64,
+    /// Scrolling speed in the menus in logical pixels per second
+    pub scroll_speed: f64,
+    /// Actions of the buttons in game. In the menus, `South` always clicks and `East` always goes back.
+    pub bindings: Vec<GamepadBinding>,
+}
+
+impl Default for GamepadSettings {
+    fn default() -> Self {
+        let binding = |button, action| GamepadBinding { button, action };
+        Self {
+            enabled: true,
+            move_deadzone: 0.2,
+            look_deadzone: 0.15,
+            look_speed: 180.0,
+            cursor_speed: 800.0,
+            scroll_speed: 600.0,
+            bindings: vec![
+                binding(GamepadButton::South, GamepadAction::MoveUp),
+                binding(GamepadButton::LeftStick, GamepadAction::MoveDown),
+                binding(GamepadButton::RightTrigger, GamepadAction::Break),
+                binding(GamepadButton::LeftTrigger, GamepadAction::Place),
+                binding(GamepadButton::Start, GamepadAction::OpenMenu),
+                binding(GamepadButton::North, GamepadAction::ToggleCrafting),
+                binding(GamepadButton::West, GamepadAction::ToggleFlight),
+                binding(GamepadButton::Select, GamepadAction::ToggleCameraMode),
+            ],
+        }
+    }
+}
+
+/// Settings of the shadows of the sun
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct ShadowSettings {
+    /// Width and height of the shadow map of every cascade, in texels. Only applied on restart.
+    pub resolution: u32,
+    /// Number of cascades, between 0 and 3, 0 to disable the shadows.
+    /// More cascades keep the shadows sharp further from the camera.
+    pub cascades: u32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            resolution: 2048,
+            cascades: 3,
+        }
+    }
+}
+
+impl ShadowSettings {
+    /// The resolutions that can be chosen in the settings menu
+    const RESOLUTIONS: [u32; 3] = [1024, 2048, 4096];
+
+    /// Get the number of rendered cascades, 0 if the shadows are disabled
+    pub fn get_cascades(&self) -> usize {
+        (self.cascades as usize).min(MAX_SHADOW_CASCADES)
+    }
+
+    /// Get the resolution of the shadow maps, falling back to 2048 if the setting is invalid
+    pub fn get_resolution(&self) -> u32 {
+        if self.resolution.is_power_of_two() && (256..=8192).contains(&self.resolution) {
+            self.resolution
+        } else {
+            2048
+        }
+    }
+
+    /// Use the next number of cascades, to cycle through them in the settings menu
+    pub fn cycle_cascades(&mut self) {
+        self.cascades = ((self.get_cascades() + 1) % (MAX_SHADOW_CASCADES + 1)) as u32;
+    }
+
+    /// Use the next resolution, to cycle through them in the settings menu
+    pub fn cycle_resolution(&mut self) {
+        let resolution = self.get_resolution();
+        self.resolution = Self::RESOLUTIONS
+            .iter()
+            .copied()
+            .find(|&r| r > resolution)
+            .unwrap_or(Self::RESOLUTIONS[0]);
+    }
+}
+
+/// Settings of the fog
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct FogSettings {
+    /// `true` to blend the chunks close to the render distance into the sky, to hide the chunks that pop in
+    pub distance_fog: bool,
+    /// Where the distance fog starts, as a fraction of the render distance
+    pub distance_fog_start: f64,
+    /// `true` to add a dense fog and to tint the screen when the camera is in water or below the world
+    pub environment_effects: bool,
+}
+
+impl Default for FogSettings {
+    fn default() -> Self {
+        Self {
+            distance_fog: true,
+            distance_fog_start: 0.7,
+            environment_effects: true,
+        }
+    }
+}
+
+impl FogSettings {
+    /// Get the distances where the distance fog starts and where it hides everything, in blocks.
+    /// The fog ends at the closest horizontal render distance, where the chunks that are not loaded yet begin.
+    pub fn distance_fog_range(&self, render_distance: &RenderDistance) -> Option<(f32, f32)> {
+        let chunks = render_distance
+            .x_max
+            .min(render_distance.x_min)
+            .min(render_distance.z_max)
+            .min(render_distance.z_min);
+        if !self.distance_fog || chunks == 0 {
+            return None;
+        }
+        let end = (chunks * CHUNK_SIZE as u64) as f32;
+        Some((end * self.distance_fog_start.max(0.0).min(1.0) as f32, end))
+    }
+}
+
+/// Antialiasing method
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Antialiasing {
+    /// Multisampling, with `msaa_samples` samples per pixel
+    Msaa,
+    /// Fast approximate antialiasing: a cheap filter that smooths the edges after the world is rendered
+    Fxaa,
+    /// Temporal antialiasing: the camera is jittered every frame and the frames are accumulated.
+    /// It also smooths the aliasing of the textures.
+    Taa,
+}
+
+impl Antialiasing {
+    /// The next method, to cycle through them in the settings menu
+    pub fn next(self) -> Self {
+        match self {
+            Antialiasing::Msaa => Antialiasing::Fxaa,
+            Antialiasing::Fxaa => Antialiasing::Taa,
+            Antialiasing::Taa => Antialiasing::Msaa,
+        }
+    }
 }
 
 impl Default for Settings {
@@ -77,7 +295,73 @@ impl Default for Settings {
         Self {
             window_size: (1600, 900),
             invert_mouse: false,
+            mouse_sensitivity: 0.2,
+            raw_mouse_input: true,
+            mouse_smoothing: 0.0,
             render_distance: (0, 0, 0, 0, 0, 0),
+            fov: 90.0,
+            msaa_samples: 4,
+            antialiasing: Antialiasing::Msaa,
+            shadows: ShadowSettings::default(),
+            fog: FogSettings::default(),
+            contrast: 1.0,
+            color_lookup_table: None,
+            vsync: false,
+            fullscreen: false,
+            touch_controls: false,
+            large_ui: false,
+            server_address: "127.0.0.1:42000".to_owned(),
+            session_analytics: false,
+            display_name: "Player".to_owned(),
+            login_token: None,
+            sound_volume: 1.0,
+            gamepad: GamepadSettings::default(),
+            tuning: ClientTuning::default(),
+        }
+    }
+}
+
+impl Settings {
+    /// Get the render distance of the player
+    pub fn get_render_distance(&self) -> RenderDistance {
+        let (x1, x2, y1, y2, z1, z2) = self.render_distance;
+        RenderDistance {
+            x_max: x1,
+            x_min: x2,
+            y_max: y1,
+            y_min: y2,
+            z_max: z1,
+            z_min: z2,
+        }
+    }
+
+    /// Get the number of MSAA samples, falling back to 4 if the setting is invalid.
+    /// The post-process antialiasing methods don't use multisampling.
+    pub fn get_msaa_samples(&self) -> u32 {
+        if self.antialiasing != Antialiasing::Msaa {
+            return 1;
+        }
+        match self.msaa_samples {
+            1 | 2 | 4 | 8 => self.msaa_samples,
+            _ => 4,
+        }
+    }
+
+    /// Get the scale factor of the menus
+    pub fn get_ui_scale(&self) -> f32 {
+        if self.large_ui {
+            1.5
+        } else {
+            1.0
+        }
+    }
+
+    /// Get the present mode of the swap chain
+    pub fn get_present_mode(&self) -> wgpu::PresentMode {
+        if self.vsync {
+            wgpu::PresentMode::Fifo
+        } else {
+            wgpu::PresentMode::Mailbox
         }
     }
 }
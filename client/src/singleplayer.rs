@@ -1,68 +1,238 @@
 use anyhow::Result;
-use log::info;
+use log::{error, info};
 
 use voxel_rs_common::{
-    block::Block,
-    network::{messages::ToClient, messages::ToServer, Client, ClientEvent},
-    player::RenderDistance,
+    block::{Block, BlockType},
+    config::VOID_HEIGHT,
+    data::Data,
+    dimension::FogProfile,
+    inventory::{InventoryItem, PredictedInventory, PLAYER_INVENTORY_SIZE},
+    network::{
+        dummy, messages::ToClient, messages::ToServer, Client, ClientEvent, DisconnectReason,
+        MessageDelivery,
+    },
+    player::{GameMode, InteractionConfig, InteractionRules, PlayerId, RenderDistance, MAX_HEALTH},
+    recipe::Recipe,
     registry::Registry,
-    world::BlockPos,
+    sound::{SoundEvent, SoundId},
+    world::{BlockPos, Direction},
 };
 
-use crate::input::YawPitch;
+use crate::audio::Audio;
+use crate::gui::debug::GPU_PERF_GRAPH;
+use crate::input::{
+    MouseFilter, YawPitch, CYCLE_WEATHER, TOGGLE_CAMERA_MODE, TOGGLE_COLLISION_DEBUG,
+    TOGGLE_GAMEMODE, TOGGLE_HELD_LIGHT, TOGGLE_PERF_GRAPHS,
+};
 //use crate::model::model::Model;
 //use crate::world::meshing::ChunkMeshData;
-use crate::render::{Frustum, UiRenderer, WorldRenderer};
+use crate::render::{
+    ColorGrading, DebugLines, Frustum, GpuTimer, PointLight, PostAntialiasing, Tonemapping, UiRenderer, Viewport,
+    WorldRenderer,
+};
 use crate::window::WindowBuffers;
 use crate::{
+    breaking::BlockBreakingEffects,
+    debug_render::DebugVisualizers,
     fps::FpsCounter,
     input::InputState,
     settings::Settings,
-    ui::Ui,
+    swing::SwingAnimation,
+    ui::{CraftingEntry, Ui},
+    weather::WeatherEffects,
     window::{State, StateTransition, WindowData, WindowFlags},
     world::World,
 };
-use nalgebra::Vector3;
-use std::time::Instant;
+use nalgebra::{Vector3, Vector4};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 use voxel_rs_common::data::vox::VoxelModel;
-use voxel_rs_common::debug::{send_debug_info, send_perf_breakdown, DebugInfo};
+use voxel_rs_common::debug::{send_debug_info, send_perf_breakdown, send_perf_sample, DebugInfo};
 use voxel_rs_common::item::{Item, ItemMesh};
+use voxel_rs_common::physics::raycast::RaycastHit;
+use voxel_rs_common::physics::{player::PhysicsPlayer, BlockContainer, RecordingBlockContainer};
 use voxel_rs_common::physics::simulation::{ClientPhysicsSimulation, PhysicsState, ServerState};
-use voxel_rs_common::time::BreakdownCounter;
+use voxel_rs_common::time::{BreakdownCounter, TimeOfDay};
+use voxel_rs_common::weather::{Precipitation, Weather};
 use winit::event::{ElementState, MouseButton};
+use crate::gui::inventory::{player_slot_action, render_inventory};
 use crate::gui::Gui;
+use crate::ui::{TextPart, UiLayer};
+use wgpu_glyph::ab_glyph::PxScale;
+use voxel_rs_server::{launch_server, save::WorldMetadata};
+
+/// Maximum distance between the player and the camera in third person
+const THIRD_PERSON_DISTANCE: f64 = 4.0;
+/// Precision of the third person camera collision
+const THIRD_PERSON_STEP: f64 = 0.1;
+/// Minimum distance between the third person camera and the blocks
+const THIRD_PERSON_MARGIN: f64 = 0.2;
+/// Folder containing the sound files
+const SOUNDS_FOLDER: &str = "data/sounds/files";
+/// Horizontal distance walked between two footstep sounds
+const FOOTSTEP_DISTANCE: f64 = 2.0;
+/// Offset between the eyes of the player and the light they hold
+const HELD_LIGHT_OFFSET: [f64; 3] = [0.0, -0.3, 0.0];
+/// Exposure when the sun is high
+const DAY_EXPOSURE: f32 = 1.0;
+/// Exposure during the night
+const NIGHT_EXPOSURE: f32 = 0.6;
+/// Exposure when the camera is in water
+const UNDERWATER_EXPOSURE: f32 = 0.8;
+/// Tint when the camera is in water
+const UNDERWATER_TINT: [f32; 3] = [0.55, 0.75, 1.0];
+/// Fog when the camera is in water
+const UNDERWATER_FOG: FogProfile = FogProfile {
+    color: [0.1, 0.25, 0.45],
+    density: 0.08,
+};
+/// Fog below the world, at its densest where the players die in the void
+const VOID_FOG: FogProfile = FogProfile {
+    color: [0.02, 0.0, 0.03],
+    density: 0.1,
+};
+/// The void fog and darkness start this far above the void height, in blocks
+const VOID_FOG_DEPTH: f64 = 64.0;
+/// Exposure where the players die in the void
+const VOID_EXPOSURE: f32 = 0.3;
+/// How fast the exposure reaches its target, in 1/seconds
+const EXPOSURE_ADAPTATION_SPEED: f64 = 2.0;
+
+/// `true` if at least `cooldown` seconds passed since `last`
+fn cooldown_elapsed(last: Option<Instant>, cooldown: f64) -> bool {
+    last.map_or(true, |last| last.elapsed().as_secs_f64() >= cooldown)
+}
+
+/// The name of an item or a block, with spaces instead of underscores
+fn item_name(items: &Registry<Item>, blocks: &Registry<Block>, item: InventoryItem) -> String {
+    let name = match item {
+        InventoryItem::Item(id) => items.get_name_by_id(id),
+        InventoryItem::Block(id) => blocks.get_name_by_id(id as u32),
+    };
+    name.map_or("<unknown>", String::as_str).replace('_', " ")
+}
+
+/// Where the camera is, relative to the player
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CameraMode {
+    FirstPerson,
+    /// Behind the player
+    ThirdPerson,
+}
 
 /// State of a singleplayer world
 pub struct SinglePlayer {
     fps_counter: FpsCounter,
     ui: Ui,
     ui_renderer: UiRenderer,
+    /// FXAA or TAA, if the world is not rendered with MSAA
+    post_antialiasing: Option<PostAntialiasing>,
+    tonemapping: Tonemapping,
+    /// Color grading of the world, driven by the time of day and the water around the camera
+    color_grading: ColorGrading,
     gui: Gui,
     world: World,
-    #[allow(dead_code)] // TODO: remove this
     block_registry: Registry<Block>,
     item_registry: Registry<Item>,
     item_meshes: Vec<ItemMesh>,
     model_registry: Registry<VoxelModel>,
+    sound_registry: Registry<SoundEvent>,
+    recipe_registry: Registry<Recipe>,
+    /// The inventory of the player, set by the server and changed right away by the slot actions
+    inventory: PredictedInventory,
+    audio: Audio,
+    /// Horizontal distance walked on the ground since the last footstep
+    footstep_distance: f64,
+    /// Position of the player during the previous frame
+    previous_player_position: Vector3<f64>,
     client: Box<dyn Client>,
+    /// The thread of the local server, stopped when the player exits so that it saves the world
+    local_server: Option<JoinHandle<()>>,
+    /// Why the server disconnected the client, the main menu is shown during the next frame
+    disconnect_reason: Option<DisconnectReason>,
+    /// Game data that was reloaded by the server, applied during the next frame
+    reloaded_game_data: Option<Data>,
     render_distance: RenderDistance,
+    display_names: HashMap<PlayerId, String>,
+    /// Time of the day, advanced locally and synchronized by the server
+    time_of_day: TimeOfDay,
+    /// Number of sleeping players and total number of players
+    sleeping_players: (usize, usize),
+    weather_effects: WeatherEffects,
+    breaking_effects: BlockBreakingEffects,
+    /// `Some` while the break button is held, with the block that the server was asked to break
+    breaking_target: Option<Option<BlockPos>>,
+    swing: SwingAnimation,
+    /// The gamemode of the player, set by the server
+    gamemode: GameMode,
+    /// The player whose camera is used in spectator mode
+    followed_player: Option<PlayerId>,
+    /// The reach and the cooldowns of every gamemode, set by the server
+    interaction: InteractionConfig,
+    /// When the player last asked to place a block, to respect the cooldown of the server
+    last_place: Option<Instant>,
+    /// When the player last asked to break a block instantly, to respect the cooldown of the server
+    last_break: Option<Instant>,
+    /// The health of the player in half hearts, set by the server
+    health: u32,
     // TODO: put this in the settigs
     physics_simulation: ClientPhysicsSimulation,
     yaw_pitch: YawPitch,
+    mouse_filter: MouseFilter,
+    camera_mode: CameraMode,
+    /// Second camera, frozen where it was enabled, rendered in the right half of the window
+    debug_camera: Option<Frustum>,
     debug_info: DebugInfo,
+    /// Which perf graphs are shown, toggled with `TOGGLE_PERF_GRAPHS`
+    shown_perf_graphs: [bool; 3],
+    /// `true` to draw the player hitbox, the tested blocks and the block picking ray
+    show_collisions: bool,
+    debug_visualizers: DebugVisualizers,
+    /// Blocks tested during the last physics step, and whether they are full
+    tested_blocks: HashMap<BlockPos, bool>,
+    /// `true` if the player holds a light, toggled with `TOGGLE_HELD_LIGHT`
+    // TODO: replace this by a torch item once there is one
+    holds_light: bool,
     start_time: Instant,
     client_timing: BreakdownCounter,
+    /// GPU time of the render passes, only measured while its perf graph is shown
+    gpu_timer: GpuTimer,
 }
 
 impl SinglePlayer {
     pub fn new_factory(client: Box<dyn Client>) -> crate::window::StateFactory {
-        Box::new(move |settings, device| Self::new(settings, device, client))
+        Box::new(move |settings, device| Self::new(settings, device, client, None))
+    }
+
+    /// Launch a local server for the given world, and create a factory that connects to it
+    pub fn new_local_factory(world: WorldMetadata) -> crate::window::StateFactory {
+        let (client, server) = dummy::new();
+
+        let local_server = std::thread::spawn(move || {
+            if let Err(e) = launch_server(Box::new(server), world) {
+                // TODO: rewrite this error reporting
+                error!(
+                    "Error happened in the server code: {}\nPrinting chain:\n{}",
+                    e,
+                    e.chain()
+                        .enumerate()
+                        .map(|(i, e)| format!("{}: {}", i, e))
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                );
+            }
+        });
+
+        Box::new(move |settings, device| Self::new(settings, device, Box::new(client), Some(local_server)))
     }
 
     pub fn new(
         settings: &mut Settings,
         device: &mut wgpu::Device,
         mut client: Box<dyn Client>,
+        local_server: Option<JoinHandle<()>>,
     ) -> Result<(Box<dyn State>, wgpu::CommandBuffer)> {
         info!("Launching singleplayer");
         // Wait for data and player_id from the server
@@ -75,52 +245,95 @@ impl SinglePlayer {
                 }
                 match client.receive_event() {
                     ClientEvent::ServerMessage(ToClient::GameData(game_data)) => {
-                        data = Some(game_data)
+                        data = Some(*game_data)
                     }
                     ClientEvent::ServerMessage(ToClient::CurrentId(id)) => player_id = Some(id),
+                    ClientEvent::Disconnected(reason) => {
+                        return crate::mainmenu::MainMenu::new_disconnected(device, &reason);
+                    }
+                    // A remote client sends its connection packets when it's polled, don't flood the server
+                    ClientEvent::NoEvent => std::thread::sleep(Duration::from_millis(5)),
                     _ => (),
                 }
             }
         };
         info!("Received game data from the server");
 
+        client.send(
+            ToServer::Login(settings.display_name.clone(), settings.login_token.clone()),
+            MessageDelivery::Ordered,
+        );
         // Set render distance
-        let (x1, x2, y1, y2, z1, z2) = settings.render_distance;
-        let render_distance = RenderDistance {
-            x_max: x1,
-            x_min: x2,
-            y_max: y1,
-            y_min: y2,
-            z_max: z1,
-            z_min: z2,
-        };
-        client.send(ToServer::SetRenderDistance(render_distance));
+        let render_distance = settings.get_render_distance();
+        client.send(ToServer::SetRenderDistance(render_distance), MessageDelivery::Ordered);
         // Create the renderers
         let ui_renderer = UiRenderer::new(device);
+        // The antialiasing setting is only applied on restart, like the number of MSAA samples
+        let post_antialiasing = if crate::window::sample_count() == 1 {
+            PostAntialiasing::new(device, settings.antialiasing)
+        } else {
+            None
+        };
 
         let mut encoder =
             device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
 
+        let tonemapping = Tonemapping::new(
+            device,
+            &mut encoder,
+            settings.color_lookup_table.as_deref().map(Path::new),
+        );
         let world_renderer = WorldRenderer::new(
             device,
             &mut encoder,
             data.texture_atlas,
             &data.models,
+            settings.shadows.get_resolution(),
+            &settings.tuning,
         );
 
+        // TODO: load the sound files sent by remote servers
+        let mut audio = Audio::new(&data.sounds, Path::new(SOUNDS_FOLDER));
+        audio.set_volume(settings.sound_volume);
+
         Ok((
             Box::new(Self {
                 fps_counter: FpsCounter::new(),
                 ui: Ui::new(),
                 ui_renderer,
+                post_antialiasing,
+                tonemapping,
+                color_grading: ColorGrading::default(),
                 gui: Gui::new(),
                 world: World::new(data.meshes.clone(), world_renderer),
                 block_registry: data.blocks,
                 model_registry: data.models,
                 item_registry: data.items,
                 item_meshes: data.item_meshes,
+                sound_registry: data.sounds,
+                recipe_registry: data.recipes,
+                inventory: PredictedInventory::new(PLAYER_INVENTORY_SIZE),
+                audio,
+                footstep_distance: 0.0,
+                previous_player_position: Vector3::zeros(),
                 client,
+                local_server,
+                disconnect_reason: None,
+                reloaded_game_data: None,
                 render_distance,
+                display_names: HashMap::new(),
+                time_of_day: TimeOfDay::default(),
+                sleeping_players: (0, 0),
+                weather_effects: WeatherEffects::new(),
+                breaking_effects: BlockBreakingEffects::new(),
+                breaking_target: None,
+                swing: SwingAnimation::default(),
+                gamemode: GameMode::default(),
+                followed_player: None,
+                interaction: InteractionConfig::default(),
+                last_place: None,
+                last_break: None,
+                health: MAX_HEALTH,
                 physics_simulation: ClientPhysicsSimulation::new(
                     ServerState {
                         physics_state: PhysicsState::default(),
@@ -128,11 +341,21 @@ impl SinglePlayer {
                         input: Default::default(),
                     },
                     player_id,
+                    data.physics,
                 ),
                 yaw_pitch: Default::default(),
+                mouse_filter: Default::default(),
+                camera_mode: CameraMode::FirstPerson,
+                debug_camera: None,
                 debug_info: DebugInfo::new_current(),
+                shown_perf_graphs: [false; 3],
+                show_collisions: false,
+                debug_visualizers: DebugVisualizers::default(),
+                tested_blocks: HashMap::new(),
+                holds_light: false,
                 start_time: Instant::now(),
                 client_timing: BreakdownCounter::new(),
+                gpu_timer: GpuTimer::default(),
             }),
             encoder.finish(),
         ))
@@ -143,30 +366,424 @@ impl SinglePlayer {
             match self.client.receive_event() {
                 ClientEvent::NoEvent => break,
                 ClientEvent::ServerMessage(message) => match message {
-                    ToClient::Chunk(chunk, light_chunk) => {
-                        self.world.add_chunk(chunk, light_chunk);
+                    ToClient::Chunk(chunk, light_chunk, block_entities) => {
+                        self.client.send(ToServer::ChunkReceived(chunk.pos), MessageDelivery::Ordered);
+                        self.world.add_chunk(chunk, light_chunk, block_entities);
+                    }
+                    ToClient::LightChunk(light_chunk) => {
+                        self.client.send(ToServer::ChunkReceived(light_chunk.pos), MessageDelivery::Ordered);
+                        self.world.set_light_chunk(light_chunk);
+                    }
+                    ToClient::Inventory(inventory, last_action) => {
+                        self.inventory.set_confirmed(inventory, last_action)
+                    }
+                    ToClient::UpdateHealth(health) => {
+                        self.health = health;
+                        self.ui.set_dead(health == 0);
                     }
                     ToClient::UpdatePhysics(server_state) => {
                         self.physics_simulation.receive_server_update(server_state);
                     }
-                    ToClient::GameData(_) => {}
+                    ToClient::GameData(game_data) => self.reloaded_game_data = Some(*game_data),
                     ToClient::CurrentId(_) => {}
+                    ToClient::DisplayName(id, display_name) => {
+                        self.display_names.insert(id, display_name);
+                    }
+                    ToClient::PlaySound(sound, pos) => self.audio.play_at(sound, pos),
+                    ToClient::BlockBreaking(player, block, progress) => {
+                        self.breaking_effects.set_progress(player, block, progress)
+                    }
+                    ToClient::TimeOfDay(time_of_day) => self.time_of_day = time_of_day,
+                    ToClient::Weather(weather) => self.weather_effects.set_weather(weather),
+                    ToClient::Dimension(dimension) => self.world.set_dimension(&dimension),
+                    ToClient::Season(season) => self.world.set_season(season),
+                    ToClient::SleepingPlayers(sleeping, total) => {
+                        self.sleeping_players = (sleeping, total)
+                    }
+                    // TODO: display it in a chat box, and add a way to type commands
+                    ToClient::CommandFeedback(feedback) => info!("{}", feedback),
+                    ToClient::ActionDenied(reason) => info!("{}", reason),
+                    ToClient::SetYawPitch(yaw, pitch) => self.yaw_pitch = YawPitch { yaw, pitch },
+                    ToClient::Teleport(position, teleports) => {
+                        self.physics_simulation.teleport(position, teleports)
+                    }
+                    ToClient::GameMode(gamemode) => {
+                        info!("Gamemode set to {:?}", gamemode);
+                        self.gamemode = gamemode;
+                        // The server stops the follow camera too
+                        if gamemode != GameMode::Spectator {
+                            self.followed_player = None;
+                        }
+                    }
+                    ToClient::InteractionConfig(interaction) => self.interaction = interaction,
                 },
-                ClientEvent::Disconnected => unimplemented!("server disconnected"),
+                ClientEvent::Disconnected(reason) => self.disconnect_reason = Some(reason),
                 ClientEvent::Connected => {}
             }
         }
     }
 }
 
+impl SinglePlayer {
+    /// The reach and the cooldowns of the current gamemode
+    fn interaction_rules(&self) -> InteractionRules {
+        self.interaction.rules(self.gamemode)
+    }
+
+    /// Describe the recipes of the crafting window, for example `3 wood + 3 leaves -> 1 bed`
+    fn crafting_entries(&self) -> Vec<CraftingEntry> {
+        let name = |item| item_name(&self.item_registry, &self.block_registry, item);
+        (0..self.recipe_registry.get_number_of_ids())
+            .filter_map(|id| self.recipe_registry.get_value_by_id(id).map(|recipe| (id, recipe)))
+            .map(|(id, recipe)| {
+                let ingredients = recipe
+                    .ingredients
+                    .iter()
+                    .map(|stack| format!("{} {}", stack.count, name(stack.item)))
+                    .collect::<Vec<_>>()
+                    .join(" + ");
+                CraftingEntry {
+                    recipe: id,
+                    description: format!("{} -> {} {}", ingredients, recipe.result.count, name(recipe.result.item)),
+                    craftable: recipe.can_craft(self.inventory.get()),
+                }
+            })
+            .collect()
+    }
+
+    /// Get the id of a sound event, if it exists
+    fn get_sound(&self, name: &str) -> Option<SoundId> {
+        self.sound_registry.get_id_by_name(&name.to_owned())
+    }
+
+    /// Swing and play the sound of an interaction at the center of `block` without waiting for the server,
+    /// so that the interactions feel instant even with a high ping. The server doesn't send this sound back,
+    /// and nothing needs to be undone if it refuses the interaction.
+    fn predict_interaction(&mut self, sound: Option<(&str, BlockPos)>) {
+        self.swing.start();
+        if let Some((name, block)) = sound {
+            if let Some(sound) = self.get_sound(name) {
+                let pos = Vector3::new(block.px as f64, block.py as f64, block.pz as f64)
+                    + Vector3::new(0.5, 0.5, 0.5);
+                self.audio.play_at(sound, pos);
+            }
+        }
+    }
+
+    /// Play a footstep sound every few blocks walked on the ground
+    fn update_footsteps(&mut self, flying: bool) {
+        let player = self.physics_simulation.get_player();
+        let pos = player.aabb.pos;
+        let on_the_ground = player.aabb.clone().is_on_the_ground(&self.world);
+        let movement = pos - self.previous_player_position;
+        self.previous_player_position = pos;
+        if flying || !on_the_ground {
+            return;
+        }
+        self.footstep_distance += (movement.x * movement.x + movement.z * movement.z).sqrt();
+        // Don't play a sound after a teleport
+        if self.footstep_distance > 2.0 * FOOTSTEP_DISTANCE {
+            self.footstep_distance = 0.0;
+        } else if self.footstep_distance >= FOOTSTEP_DISTANCE {
+            self.footstep_distance -= FOOTSTEP_DISTANCE;
+            if let Some(footstep) = self.get_sound("footstep") {
+                let feet = pos + Vector3::new(player.aabb.size_x / 2.0, 0.0, player.aabb.size_z / 2.0);
+                self.audio.play_at(footstep, feet);
+            }
+        }
+    }
+
+    /// Get the physics of the player followed by the camera, if it's still visible
+    fn get_followed_player(&self) -> Option<&PhysicsPlayer> {
+        let followed_player = self.followed_player?;
+        self.physics_simulation
+            .get_other_players()
+            .into_iter()
+            .find(|&(id, _, _)| id == followed_player)
+            .map(|(_, player, _)| player)
+    }
+
+    /// Follow the next visible player with the camera, or the previous one if `forward` is false.
+    /// The camera goes back to the spectator after the last player.
+    fn cycle_followed_player(&mut self, forward: bool) {
+        let mut players = self
+            .physics_simulation
+            .get_other_players()
+            .into_iter()
+            .map(|(id, _, _)| id)
+            .collect::<Vec<_>>();
+        players.sort();
+        if !forward {
+            players.reverse();
+        }
+        self.followed_player = match self.followed_player.and_then(|id| players.iter().position(|&p| p == id)) {
+            Some(index) => players.get(index + 1).copied(),
+            None => players.first().copied(),
+        };
+        self.client.send(ToServer::FollowPlayer(self.followed_player), MessageDelivery::Ordered);
+    }
+
+    /// Get the model of a player
+    fn player_model(&self, player: &PhysicsPlayer, yaw: f64) -> crate::render::Model {
+        // TODO: skins
+        let mesh_id = self
+            .model_registry
+            .get_id_by_name(&"knight".to_owned())
+            .unwrap();
+        let model = self.model_registry.get_value_by_id(mesh_id).unwrap();
+        let scale = player.aabb.size_y / model.size_y as f64;
+        let rot_offset = [
+            (model.size_x as f64 * scale / 2.0) as f32,
+            0.0,
+            (model.size_z as f64 * scale / 2.0) as f32,
+        ];
+        let pos = player.aabb.pos;
+        crate::render::Model {
+            mesh_id,
+            pos_x: (pos.x + player.aabb.size_x / 2.0) as f32 - rot_offset[0],
+            pos_y: pos.y as f32,
+            pos_z: (pos.z + player.aabb.size_z / 2.0) as f32 - rot_offset[2],
+            scale: scale as f32,
+            rot_offset,
+            rot_y: yaw.to_radians() as f32,
+            rot_z: 0.0,
+        }
+    }
+
+    /// Update the exposure and the tint of the world
+    fn update_color_grading(&mut self, settings: &Settings, seconds_delta: f64) {
+        // Fade the exposure when the sun is close to the horizon
+        let daylight = (self.time_of_day.sun_direction().y * 4.0).max(0.0).min(1.0) as f32;
+        let mut target_exposure = NIGHT_EXPOSURE + (DAY_EXPOSURE - NIGHT_EXPOSURE) * daylight;
+        let mut tint = [1.0; 3];
+        if settings.fog.environment_effects {
+            if self.is_camera_in_water() {
+                target_exposure *= UNDERWATER_EXPOSURE;
+                tint = UNDERWATER_TINT;
+            }
+            target_exposure *= 1.0 - (1.0 - VOID_EXPOSURE) * self.void_depth() as f32;
+        }
+
+        let adaptation = 1.0 - (-EXPOSURE_ADAPTATION_SPEED * seconds_delta).exp() as f32;
+        let exposure = &mut self.color_grading.exposure;
+        *exposure += (target_exposure - *exposure) * adaptation;
+        self.color_grading.contrast = settings.contrast;
+        self.color_grading.tint = tint;
+    }
+
+    /// Update the distance fog, and the fog of the environment of the camera
+    fn update_fog(&mut self, settings: &Settings) {
+        self.world.set_distance_fog(settings.fog.distance_fog_range(&self.render_distance));
+        let void_depth = self.void_depth();
+        let environment_fog = if !settings.fog.environment_effects {
+            None
+        } else if self.is_camera_in_water() {
+            Some(UNDERWATER_FOG)
+        } else if void_depth > 0.0 {
+            Some(FogProfile {
+                density: VOID_FOG.density * void_depth as f32,
+                ..VOID_FOG
+            })
+        } else {
+            None
+        };
+        self.world.set_environment_fog(environment_fog);
+    }
+
+    fn is_camera_in_water(&self) -> bool {
+        let camera_block = self.world.get_block(BlockPos::from(self.get_camera_position()));
+        Some(camera_block as u32) == self.block_registry.get_id_by_name(&"water".to_owned())
+    }
+
+    /// How deep below the world the camera is, from 0 above the void fog to 1 where the players die
+    fn void_depth(&self) -> f64 {
+        let height = self.get_camera_position().y;
+        ((VOID_HEIGHT + VOID_FOG_DEPTH - height) / VOID_FOG_DEPTH).max(0.0).min(1.0)
+    }
+
+    /// Get the position of the camera, depending on the camera mode
+    fn get_camera_position(&self) -> Vector3<f64> {
+        let eye = match self.get_followed_player() {
+            Some(player) => player.get_camera_position(),
+            None => self.physics_simulation.get_camera_position(),
+        };
+        match self.camera_mode {
+            CameraMode::FirstPerson => eye,
+            CameraMode::ThirdPerson => {
+                // Move the camera back until it would be inside a block
+                let y = self.yaw_pitch.yaw.to_radians();
+                let p = self.yaw_pitch.pitch.to_radians();
+                let dir = Vector3::new(-y.sin() * p.cos(), p.sin(), -y.cos() * p.cos());
+                let mut distance = 0.0;
+                while distance < THIRD_PERSON_DISTANCE {
+                    let next_distance = distance + THIRD_PERSON_STEP;
+                    if self
+                        .world
+                        .is_block_full(BlockPos::from(eye - dir * (next_distance + THIRD_PERSON_MARGIN)))
+                    {
+                        break;
+                    }
+                    distance = next_distance;
+                }
+                eye - dir * distance
+            }
+        }
+    }
+
+    /// Draw the player hitbox, the blocks tested during the last physics step and the block picking ray
+    fn collision_debug_lines(
+        &self,
+        dir: Vector3<f64>,
+        pointed_block: Option<RaycastHit>,
+    ) -> DebugLines {
+        const TESTED_FULL_COLOR: [f32; 3] = [1.0, 0.2, 0.2];
+        const TESTED_EMPTY_COLOR: [f32; 3] = [0.3, 0.3, 1.0];
+        const PLAYER_COLOR: [f32; 3] = [0.2, 1.0, 0.2];
+        const RAY_COLOR: [f32; 3] = [1.0, 1.0, 0.2];
+        const HIT_POINT_SIZE: f64 = 0.05;
+
+        let mut lines = DebugLines::default();
+        let player = self.physics_simulation.get_player();
+        let aabb = &player.aabb;
+        lines.add_box(
+            aabb.pos,
+            Vector3::new(aabb.size_x, aabb.size_y, aabb.size_z),
+            PLAYER_COLOR,
+        );
+        for (block, &full) in self.tested_blocks.iter() {
+            let color = if full { TESTED_FULL_COLOR } else { TESTED_EMPTY_COLOR };
+            lines.add_box(
+                Vector3::new(block.px as f64, block.py as f64, block.pz as f64),
+                Vector3::new(1.0, 1.0, 1.0),
+                color,
+            );
+        }
+        // The picking ray starts from the eyes of the player, not from the camera
+        let eye = player.get_camera_position();
+        let end = match pointed_block {
+            Some(hit) => hit.point,
+            None => eye + dir * self.interaction_rules().reach,
+        };
+        lines.add_line(eye, end, RAY_COLOR);
+        if pointed_block.is_some() {
+            let half_size = Vector3::new(HIT_POINT_SIZE, HIT_POINT_SIZE, HIT_POINT_SIZE);
+            lines.add_box(end - half_size, half_size * 2.0, RAY_COLOR);
+        }
+        lines
+    }
+
+    /// Show how many players are sleeping, if any
+    fn draw_sleeping_players(&mut self, data: &WindowData) {
+        let (sleeping, total) = self.sleeping_players;
+        if sleeping == 0 {
+            return;
+        }
+        let width = data.logical_window_size.width as f32;
+        self.gui.centered_text(
+            0.0,
+            40.0,
+            width,
+            30.0,
+            TextPart {
+                text: format!("{}/{} players sleeping", sleeping, total),
+                font_size: PxScale::from(24.0),
+                color: [1.0, 1.0, 1.0, 1.0],
+                font: None,
+            },
+        );
+    }
+
+    /// Draw the health of the player as hearts at the bottom of the window, in the gamemodes with damage
+    fn draw_health(&mut self, data: &WindowData) {
+        const HEART_SIZE: i32 = 16;
+        const HEART_SPACING: i32 = 4;
+        const FULL_COLOR: [f32; 4] = [0.85, 0.1, 0.1, 1.0];
+        const EMPTY_COLOR: [f32; 4] = [0.2, 0.2, 0.2, 0.8];
+
+        if !self.gamemode.takes_damage() || self.health == 0 {
+            return;
+        }
+        let num_hearts = (MAX_HEALTH as i32 + 1) / 2;
+        let total_width = num_hearts * (HEART_SIZE + HEART_SPACING) - HEART_SPACING;
+        let x0 = (data.logical_window_size.width as i32 - total_width) / 2;
+        let y = data.logical_window_size.height as i32 - 2 * HEART_SIZE;
+        for i in 0..num_hearts {
+            let x = x0 + i * (HEART_SIZE + HEART_SPACING);
+            self.gui.rect(x, y, HEART_SIZE, HEART_SIZE, EMPTY_COLOR);
+            // Every heart is two half hearts
+            let filled_halves = (self.health as i32 - 2 * i).max(0).min(2);
+            if filled_halves > 0 {
+                self.gui.rect(x, y, HEART_SIZE * filled_halves / 2, HEART_SIZE, FULL_COLOR);
+            }
+        }
+    }
+
+    /// Draw the pause button of the touch controls in the top right corner
+    fn draw_pause_button(&mut self, data: &WindowData) {
+        const BACKGROUND_COLOR: [f32; 4] = [0.2, 0.2, 0.2, 0.5];
+        const BAR_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 0.8];
+
+        // The touches are in physical pixels, and the gui in logical pixels
+        let size = (crate::touch::PAUSE_BUTTON_SIZE / data.hidpi_factor) as i32;
+        let x = data.logical_window_size.width as i32 - size;
+        self.gui.rect(x, 0, size, size, BACKGROUND_COLOR);
+        let (bar_width, bar_height) = (size / 6, size / 2);
+        self.gui.rect(x + size / 3 - bar_width / 2, size / 4, bar_width, bar_height, BAR_COLOR);
+        self.gui.rect(x + 2 * size / 3 - bar_width / 2, size / 4, bar_width, bar_height, BAR_COLOR);
+    }
+
+    /// Draw the names of the other players above their heads
+    fn draw_name_tags(&mut self, frustum: &Frustum, data: &WindowData) {
+        const MAX_NAME_TAG_DISTANCE: f64 = 64.0;
+        const NAME_TAG_WIDTH: f32 = 300.0;
+        const NAME_TAG_HEIGHT: f32 = 30.0;
+
+        let (width, height) = (
+            data.logical_window_size.width,
+            data.logical_window_size.height,
+        );
+        let view_proj = frustum.get_view_projection(width / height);
+        for (id, player, _) in self.physics_simulation.get_other_players() {
+            let display_name = match self.display_names.get(&id) {
+                Some(display_name) => display_name.clone(),
+                None => continue,
+            };
+            let head = player.aabb.pos
+                + Vector3::new(player.aabb.size_x / 2.0, player.aabb.size_y + 0.5, player.aabb.size_z / 2.0);
+            if (head - frustum.position).norm() > MAX_NAME_TAG_DISTANCE {
+                continue;
+            }
+            let clip = view_proj * Vector4::new(head.x, head.y, head.z, 1.0);
+            // Behind the camera
+            if clip.w <= 0.0 {
+                continue;
+            }
+            let x = (clip.x / clip.w + 1.0) / 2.0 * width;
+            let y = (1.0 - clip.y / clip.w) / 2.0 * height;
+            self.gui.centered_text(
+                x as f32 - NAME_TAG_WIDTH / 2.0,
+                y as f32 - NAME_TAG_HEIGHT / 2.0,
+                NAME_TAG_WIDTH,
+                NAME_TAG_HEIGHT,
+                TextPart {
+                    text: display_name,
+                    font_size: PxScale::from(20.0),
+                    color: [1.0, 1.0, 1.0, 1.0],
+                    font: None,
+                },
+            );
+        }
+    }
+}
+
 impl State for SinglePlayer {
     fn update(
         &mut self,
-        _settings: &mut Settings,
+        settings: &mut Settings,
         input_state: &InputState,
         _data: &WindowData,
         flags: &mut WindowFlags,
-        _seconds_delta: f64,
+        seconds_delta: f64,
         _device: &mut wgpu::Device,
     ) -> Result<StateTransition> {
         self.client_timing.start_frame();
@@ -174,19 +791,118 @@ impl State for SinglePlayer {
         self.handle_server_messages();
         self.client_timing.record_part("Network events");
 
+        // Update the sky
+        self.time_of_day.advance(seconds_delta);
+        self.world.set_sun_direction(self.time_of_day.sun_direction());
+        self.world.set_shadow_cascades(settings.shadows.get_cascades());
+
+        // Update render distance if it was changed
+        let render_distance = settings.get_render_distance();
+        if render_distance != self.render_distance {
+            self.render_distance = render_distance;
+            self.client.send(ToServer::SetRenderDistance(render_distance), MessageDelivery::Ordered);
+        }
+
+        // Process the Ui messages
+        if self.ui.update(settings) {
+            if let Some(click) = self.get_sound("ui_click") {
+                self.audio.play(click);
+            }
+        }
+        for recipe in self.ui.take_crafted_recipes() {
+            self.client.send(ToServer::CraftItem(recipe), MessageDelivery::Ordered);
+        }
+        // The slot actions are shown right away, and rolled back if the server refuses them
+        for action in self.gui.take_slot_interactions().into_iter().filter_map(player_slot_action) {
+            match self.inventory.act(action.clone()) {
+                Ok(id) => self.client.send(ToServer::SlotAction(id, action), MessageDelivery::Ordered),
+                Err(reason) => info!("{}", reason),
+            }
+        }
+        if self.ui.take_respawn_request() {
+            self.client.send(ToServer::Respawn, MessageDelivery::Ordered);
+        }
+        self.audio.set_volume(settings.sound_volume);
+
+        // Rotate the camera
+        let (dx, dy) = self.mouse_filter.extract_frame_rotation(settings);
+        if self.ui.should_update_camera() {
+            self.yaw_pitch.update_cursor(dx, dy);
+        }
+
         // Collect input
-        let frame_input =
-            input_state.get_physics_input(self.yaw_pitch, self.ui.should_update_camera());
-        // Send input to server
-        self.client.send(ToServer::UpdateInput(frame_input));
-        self.client_timing.record_part("Collect and send input");
+        // The spectator doesn't move while it follows another player
+        let allow_movement = self.ui.should_update_camera() && self.followed_player.is_none();
+        let mut frame_input = input_state.get_physics_input(self.yaw_pitch, allow_movement);
+        // The server doesn't let the player fly in every gamemode
+        frame_input.flying &= self.gamemode.can_fly();
+        frame_input.noclip = self.gamemode.has_noclip();
+        self.client_timing.record_part("Collect input");
 
         // Update physics
-        self.physics_simulation
-            .step_simulation(frame_input, Instant::now(), &self.world);
-        self.client_timing.record_part("Update physics");
+        let now = Instant::now();
+        let ticks = if self.show_collisions {
+            let world = RecordingBlockContainer::new(&self.world);
+            let ticks = self.physics_simulation
+                .step_simulation(frame_input, now, &world);
+            self.tested_blocks = world.into_tested_blocks();
+            ticks
+        } else {
+            self.physics_simulation
+                .step_simulation(frame_input, now, &self.world)
+        };
+        // Send input to server with the time of every local physics step, so that the server simulates the same durations
+        for tick in ticks {
+            self.client.send(ToServer::UpdateInput(frame_input, tick), MessageDelivery::UnreliableSequenced);
+        }
+        self.client_timing.record_part("Update physics and send input");
+
+        self.update_color_grading(settings, seconds_delta);
+        self.update_fog(settings);
+
+        // Update the dynamic lights
+        // TODO: add a short light for explosions once there are some
+        let mut dynamic_lights = Vec::new();
+        if self.holds_light {
+            let eye = self.physics_simulation.get_camera_position();
+            dynamic_lights.push(PointLight::held_torch(eye + Vector3::from(HELD_LIGHT_OFFSET)));
+        }
+        self.world.set_dynamic_lights(dynamic_lights);
+
+        // Update the weather effects
+        let camera_position = self.get_camera_position();
+        self.weather_effects.update(&self.world, camera_position, seconds_delta);
+        self.world.set_wetness(self.weather_effects.wetness());
+        self.client_timing.record_part("Update weather");
+
+        // Update the blocks broken by the players
+        let visible_players = self
+            .physics_simulation
+            .get_other_players()
+            .into_iter()
+            .map(|(id, _, _)| id)
+            .collect::<HashSet<_>>();
+        let own_id = self.physics_simulation.get_player_id();
+        self.breaking_effects.retain_players(|id| id == own_id || visible_players.contains(&id));
+        self.breaking_effects.update(seconds_delta);
+        self.swing.update(seconds_delta);
+        let mut particles = DebugLines::default();
+        self.weather_effects.add_particle_lines(&mut particles);
+        self.breaking_effects.add_lines(&mut particles);
+        self.world.set_particles(particles);
+
+        // Update the sounds
+        self.update_footsteps(frame_input.flying);
+        self.audio
+            .set_listener(self.get_camera_position(), self.yaw_pitch.yaw);
+        self.client_timing.record_part("Update audio");
 
         let p = self.physics_simulation.get_camera_position();
+        if !input_state.enable_debug_camera {
+            self.debug_camera = None;
+        } else if self.debug_camera.is_none() {
+            self.debug_camera = Some(Frustum::new(p, self.yaw_pitch, settings.fov));
+        }
         let player_chunk = BlockPos::from(p).containing_chunk_pos();
 
         // Debug current player position, yaw and pitch
@@ -215,13 +931,29 @@ impl State for SinglePlayer {
         self.world.enqueue_chunks_for_meshing(player_chunk, &self.render_distance);
         self.client_timing.record_part("Send chunks to meshing");
 
+        // Stream the high resolution textures of the close blocks
+        self.world.update_texture_streaming(player_chunk);
+        self.client_timing.record_part("Texture streaming");
+
         send_debug_info("Chunks", "clientloaded", format!("Client loaded {} chunks", self.world.num_loaded_chunks()));
+        send_debug_info("Network", "client", match self.client.network_stats() {
+            Some(stats) => format!("Server connection: {}", stats),
+            None => "Server connection: local".to_owned(),
+        });
+        let (meshes_capacity, meshes_used) = self.world.chunk_meshes_memory();
+        send_debug_info(
+            "Chunks",
+            "clientmeshes",
+            format!("Chunk meshes: {} KiB used / {} KiB allocated", meshes_used / 1024, meshes_capacity / 1024),
+        );
 
         flags.grab_cursor = self.ui.should_capture_mouse();
 
-        if self.ui.should_exit() {
-            //Ok(StateTransition::ReplaceCurrent(Box::new(crate::mainmenu::MainMenu::new)))
-            Ok(StateTransition::CloseWindow)
+        if let Some(reason) = self.disconnect_reason.take() {
+            info!("Disconnected from the server: {}", reason);
+            Ok(StateTransition::ReplaceCurrent(crate::mainmenu::MainMenu::new_disconnected_factory(reason)))
+        } else if self.ui.should_exit() {
+            Ok(StateTransition::ReplaceCurrent(crate::mainmenu::MainMenu::new_factory()))
         } else {
             Ok(StateTransition::KeepCurrent)
         }
@@ -229,9 +961,10 @@ impl State for SinglePlayer {
 
     fn render<'a>(
         &mut self,
-        _settings: &Settings,
+        settings: &Settings,
         buffers: WindowBuffers<'a>,
         device: &mut wgpu::Device,
+        queue: &wgpu::Queue,
         data: &WindowData,
         input_state: &InputState,
     ) -> Result<(StateTransition, wgpu::CommandBuffer)> {
@@ -239,38 +972,100 @@ impl State for SinglePlayer {
         self.fps_counter.add_frame();
         send_debug_info("Player", "fps", format!("fps = {}", self.fps_counter.fps()));
 
+        // The camera dips while the player swings
+        let camera_yaw_pitch = YawPitch {
+            yaw: self.yaw_pitch.yaw,
+            pitch: self.yaw_pitch.pitch - self.swing.angle(),
+        };
         let frustum = Frustum::new(
-            self.physics_simulation.get_camera_position(),
-            self.yaw_pitch,
+            self.get_camera_position(),
+            camera_yaw_pitch,
+            settings.fov,
         );
 
         // Try raytracing TODO: move this to update
         let pp = self.physics_simulation.get_player();
-        let pointed_block = {
-            let y = self.yaw_pitch.yaw.to_radians();
-            let p = self.yaw_pitch.pitch.to_radians();
-            let dir = Vector3::new(-y.sin() * p.cos(), p.sin(), -y.cos() * p.cos());
-            pp.get_pointed_at(dir, 10.0, &self.world)
+        let y = self.yaw_pitch.yaw.to_radians();
+        let p = self.yaw_pitch.pitch.to_radians();
+        let dir = Vector3::new(-y.sin() * p.cos(), p.sin(), -y.cos() * p.cos());
+        let pointed_block = pp.get_pointed_at(dir, self.interaction_rules().reach, &self.world);
+        let mut debug_lines = if self.show_collisions {
+            self.collision_debug_lines(dir, pointed_block)
+        } else {
+            DebugLines::default()
         };
-        if let Some((x, face)) = pointed_block {
+        let other_players = self
+            .physics_simulation
+            .get_other_players()
+            .into_iter()
+            .map(|(_, player, yaw)| (player, yaw))
+            .collect::<Vec<_>>();
+        self.debug_visualizers.add_lines(&mut debug_lines, pp, dir, &other_players);
+        self.world.set_debug_lines(debug_lines);
+        if let Some(RaycastHit { block: x, face, distance, .. }) = pointed_block {
             send_debug_info(
                 "Player",
                 "pointedat",
                 format!(
-                    "Pointed block: Some({}, {}, {}), face: {}",
-                    x.px, x.py, x.pz, face
+                    "Pointed block: Some({}, {}, {}), face: {}, distance: {:.2}",
+                    x.px, x.py, x.pz, face, distance
                 ),
             );
         } else {
             send_debug_info("Player", "pointedat", "Pointed block: None");
         }
+        // Break the pointed block while the button is held, starting again when another block is pointed
+        if let Some(target) = self.breaking_target {
+            let pointed = pointed_block.map(|hit| hit.block);
+            if pointed != target {
+                self.breaking_target = Some(pointed);
+                let message = match pointed {
+                    Some(_) => ToServer::StartBreaking(pp.aabb.pos, self.yaw_pitch.yaw, self.yaw_pitch.pitch),
+                    None => ToServer::StopBreaking,
+                };
+                self.client.send(message, MessageDelivery::Ordered);
+            }
+        }
         self.client_timing.record_part("Raytrace");
 
         // Begin rendering
+        self.gpu_timer.set_enabled(self.shown_perf_graphs[GPU_PERF_GRAPH]);
         let mut encoder =
             device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
 
-        crate::render::clear_color_and_depth(&mut encoder, buffers);
+        // The world is rendered to an HDR texture, then tonemapped
+        self.tonemapping.prepare(device, data);
+        let mut jitter = (0.0, 0.0);
+        if let Some(post_antialiasing) = self.post_antialiasing.as_mut() {
+            post_antialiasing.prepare(device, data);
+            jitter = post_antialiasing.jitter();
+        }
+        let world_buffers = self.tonemapping.scene_buffers(buffers);
+
+        crate::render::clear_color_and_depth(&mut encoder, world_buffers);
+
+        // Apply reloaded game data
+        if let Some(game_data) = self.reloaded_game_data.take() {
+            info!("Applying reloaded game data");
+            let world_renderer = WorldRenderer::new(
+                device,
+                &mut encoder,
+                game_data.texture_atlas,
+                &game_data.models,
+                settings.shadows.get_resolution(),
+                &settings.tuning,
+            );
+            self.world.reload_data(game_data.meshes, world_renderer);
+            self.block_registry = game_data.blocks;
+            self.model_registry = game_data.models;
+            self.item_registry = game_data.items;
+            self.item_meshes = game_data.item_meshes;
+            self.physics_simulation.set_config(game_data.physics);
+            self.audio
+                .load_sounds(&game_data.sounds, Path::new(SOUNDS_FOLDER));
+            self.sound_registry = game_data.sounds;
+            self.recipe_registry = game_data.recipes;
+        }
 
         let mut models_to_draw = Vec::new();
         models_to_draw.push(crate::render::Model {
@@ -284,7 +1079,20 @@ impl State for SinglePlayer {
             scale: 0.3,
             rot_offset: [0.0, 0.0, 0.0],
             rot_y: 0.0,
+            rot_z: 0.0,
         });
+        // Draw the players, including the current player in third person
+        for (id, player, yaw) in self.physics_simulation.get_other_players() {
+            // The followed player would hide the view in first person
+            if Some(id) != self.followed_player || self.camera_mode == CameraMode::ThirdPerson {
+                models_to_draw.push(self.player_model(player, yaw));
+            }
+        }
+        if self.camera_mode == CameraMode::ThirdPerson && self.followed_player.is_none() {
+            // Interpolated like the camera, so that the player doesn't jitter in front of it
+            let player = self.physics_simulation.get_interpolated_player();
+            models_to_draw.push(self.player_model(&player, self.yaw_pitch.yaw));
+        }
         let item_rotation = (Instant::now() - self.start_time).as_secs_f32(); // TODO: use f64
         models_to_draw.push(crate::render::Model {
             mesh_id: self
@@ -297,26 +1105,85 @@ impl State for SinglePlayer {
             scale: 1.0 / 32.0,
             rot_offset: [0.5, 0.5, 1.0 / 64.0],
             rot_y: item_rotation,
+            rot_z: 0.0,
         });
-        // Draw chunks
+        models_to_draw.extend(self.world.item_frame_models(&self.item_meshes));
+        // Draw chunks, splitting the screen if the debug camera is enabled
+        let viewport = Viewport::full(data);
+        let cameras = match self.debug_camera {
+            Some(debug_frustum) => {
+                let (left, right) = viewport.split_vertically();
+                vec![(left, frustum.with_jitter(jitter)), (right, debug_frustum.with_jitter(jitter))]
+            }
+            None => vec![(viewport, frustum.with_jitter(jitter))],
+        };
         self.world.render_chunks(
             device,
+            queue,
+            &mut self.gpu_timer,
             &mut encoder,
-            buffers,
-            data,
-            &frustum,
+            world_buffers,
+            &cameras,
             input_state.enable_culling,
-            pointed_block,
+            pointed_block.map(|hit| (hit.block, hit.face)),
             &models_to_draw,
         );
+        // Also includes the time spent waiting for the GPU while the GPU timer is enabled
         self.client_timing.record_part("Render chunks");
 
+        // With post-process antialiasing, the world is tonemapped to an intermediate texture
+        let tonemapping_output = match self.post_antialiasing.as_ref() {
+            Some(post_antialiasing) => post_antialiasing.scene_view(),
+            None => buffers.multisampled_texture_buffer,
+        };
+        self.tonemapping.apply(device, &mut encoder, buffers, tonemapping_output, &self.color_grading);
+        self.client_timing.record_part("Tonemapping");
+
+        if let Some(post_antialiasing) = self.post_antialiasing.as_mut() {
+            post_antialiasing.apply(device, &mut encoder, buffers, &frustum);
+            self.client_timing.record_part("Antialiasing");
+        }
+        self.gpu_timer.end_pass(device, queue, &mut encoder, "Post-processing");
+
         crate::render::clear_depth(&mut encoder, buffers);
 
         // Draw ui
-        self.ui.rebuild(&mut self.debug_info, data)?;
+        let crafting = if self.ui.is_crafting_open() {
+            self.crafting_entries()
+        } else {
+            Vec::new()
+        };
+        self.ui.rebuild(settings, &mut self.debug_info, data, &crafting)?;
+        self.gui.update_shift(input_state.get_modifiers_state().shift());
         self.gui.prepare();
-        crate::gui::experiments::render_debug_info(&mut self.gui, &mut self.debug_info);
+        self.gui.set_layer(UiLayer::World);
+        self.draw_name_tags(&frustum, data);
+        self.gui.set_layer(UiLayer::Hud);
+        self.draw_sleeping_players(data);
+        self.draw_health(data);
+        if settings.touch_controls && self.ui.should_capture_mouse() {
+            self.draw_pause_button(data);
+        }
+        if self.ui.is_inventory_open() {
+            self.gui.set_layer(UiLayer::Menu);
+            let (items, blocks) = (&self.item_registry, &self.block_registry);
+            render_inventory(
+                &mut self.gui,
+                self.inventory.get(),
+                &|item| item_name(items, blocks, item),
+                data.logical_window_size.width as i32,
+                data.logical_window_size.height as i32,
+            );
+        }
+        self.gui.set_layer(UiLayer::Debug);
+        crate::gui::debug::render_debug_info(&mut self.gui, &mut self.debug_info);
+        crate::gui::debug::render_perf_graphs(
+            &mut self.gui,
+            &mut self.debug_info,
+            &self.shown_perf_graphs,
+            data.logical_window_size.width as f32,
+            data.logical_window_size.height as f32,
+        );
         self.gui.finish();
         self.ui_renderer.render(
             buffers,
@@ -327,16 +1194,30 @@ impl State for SinglePlayer {
             &mut self.gui,
             self.ui.should_capture_mouse(),
         );
+        self.gpu_timer.end_pass(device, queue, &mut encoder, "UI");
         self.client_timing.record_part("Render UI");
 
+        if self.gpu_timer.is_enabled() {
+            let passes = self.gpu_timer.take_passes();
+            let text = passes.iter().map(|(pass, millis)| format!("{}: {:.2} ms", pass, millis)).collect::<Vec<_>>();
+            send_debug_info("Render", "gpupasses", format!("Approximate GPU time of the render passes\n{}", text.join("\n")));
+            send_perf_sample("Client performance", "gpu_graph", "GPU render passes (approximate)", passes);
+        } else {
+            send_debug_info("Render", "gpupasses", "GPU time of the render passes: not measured");
+        }
+
+        send_perf_sample("Client performance", "mainloop_graph", "Client main loop", self.client_timing.last_frame_millis());
         send_perf_breakdown("Client performance", "mainloop", "Client main loop", self.client_timing.extract_part_averages());
 
         Ok((StateTransition::KeepCurrent, encoder.finish()))
     }
 
-    fn handle_mouse_motion(&mut self, _settings: &Settings, delta: (f64, f64)) {
+    fn handle_mouse_motion(&mut self, settings: &Settings, delta: (f64, f64)) {
+        // The sensitivity and the inversion are applied by the mouse filter, so that they change live
         if self.ui.should_update_camera() {
-            self.yaw_pitch.update_cursor(delta.0, delta.1);
+            self.mouse_filter.add_motion(delta);
+        } else {
+            self.ui.preview_mouse_motion(settings, delta);
         }
     }
 
@@ -346,30 +1227,85 @@ impl State for SinglePlayer {
         self.gui.update_mouse_position(x, y);
     }
 
+    fn handle_mouse_wheel(&mut self, delta: f32) {
+        self.ui.mouse_wheel(delta);
+    }
+
     fn handle_mouse_state_changes(
         &mut self,
         changes: Vec<(winit::event::MouseButton, winit::event::ElementState)>,
     ) {
         for (button, state) in changes.iter() {
-            let pp = self.physics_simulation.get_player();
+            let pp = self.physics_simulation.get_player().clone();
             let y = self.yaw_pitch.yaw;
             let p = self.yaw_pitch.pitch;
+            let (yaw, pitch) = (y.to_radians(), p.to_radians());
+            let dir = Vector3::new(-yaw.sin() * pitch.cos(), pitch.sin(), -yaw.cos() * pitch.cos());
+            let pointed_block = pp.get_pointed_at(dir, self.interaction_rules().reach, &self.world);
+            // The clicks in the menus and in the crafting window don't act on the world
+            let in_world = self.ui.should_capture_mouse();
+            // The spectators cycle through the players to follow instead of interacting with the world
+            if !self.gamemode.can_interact() && in_world && *state == ElementState::Pressed {
+                match *button {
+                    MouseButton::Left => self.cycle_followed_player(true),
+                    MouseButton::Right => self.cycle_followed_player(false),
+                    _ => {}
+                }
+            }
+            let in_world = in_world && self.gamemode.can_interact();
             match *button {
                 MouseButton::Left => match *state {
+                    ElementState::Pressed if !in_world => {}
                     ElementState::Pressed => {
-                        self.client.send(ToServer::BreakBlock(pp.aabb.pos, y, p));
+                        if self.gamemode.breaks_instantly() {
+                            let cooldown = self.interaction_rules().break_cooldown;
+                            if cooldown_elapsed(self.last_break, cooldown) {
+                                self.last_break = Some(Instant::now());
+                                self.client.send(ToServer::StartBreaking(pp.aabb.pos, y, p), MessageDelivery::Ordered);
+                                if let Some(hit) = pointed_block {
+                                    self.predict_interaction(Some(("block_break", hit.block)));
+                                }
+                            }
+                        } else {
+                            // The pointed block is sent during the next frame
+                            self.breaking_target = Some(None);
+                            // The block breaks later, the server plays its sound
+                            if pointed_block.is_some() {
+                                self.predict_interaction(None);
+                            }
+                        }
+                    }
+                    ElementState::Released => {
+                        if self.breaking_target.take().flatten().is_some() {
+                            self.client.send(ToServer::StopBreaking, MessageDelivery::Ordered);
+                        }
                     }
-                    _ => {}
                 },
                 MouseButton::Right => match *state {
-                    ElementState::Pressed => {
-                        self.client.send(ToServer::PlaceBlock(pp.aabb.pos, y, p));
+                    ElementState::Pressed if in_world => {
+                        let cooldown = self.interaction_rules().place_cooldown;
+                        if cooldown_elapsed(self.last_place, cooldown) {
+                            self.last_place = Some(Instant::now());
+                            self.client.send(ToServer::PlaceBlock(pp.aabb.pos, y, p), MessageDelivery::Ordered);
+                            if let Some(RaycastHit { block, face, .. }) = pointed_block {
+                                // Using a bed or an item frame doesn't place a block
+                                let pointed_type = self
+                                    .block_registry
+                                    .get_value_by_id(self.world.get_block(block) as u32)
+                                    .map(|block| &block.block_type);
+                                let sound = match pointed_type {
+                                    Some(BlockType::Bed { .. }) | Some(BlockType::ItemFrame { .. }) => None,
+                                    _ => Some(("block_place", block.neighbor(Direction::ALL[face]))),
+                                };
+                                self.predict_interaction(sound);
+                            }
+                        }
                     }
                     _ => {}
                 },
                 MouseButton::Middle => match *state {
-                    ElementState::Pressed => {
-                        self.client.send(ToServer::SelectBlock(pp.aabb.pos, y, p));
+                    ElementState::Pressed if in_world => {
+                        self.client.send(ToServer::SelectBlock(pp.aabb.pos, y, p), MessageDelivery::Ordered);
                     }
                     _ => {}
                 },
@@ -384,6 +1320,7 @@ impl State for SinglePlayer {
                         self.gui.update_mouse_button(false);
                     }
                 },
+                MouseButton::Right => self.gui.update_right_mouse_button(*state == ElementState::Pressed),
                 _ => {}
             }
         }
@@ -391,6 +1328,60 @@ impl State for SinglePlayer {
     }
 
     fn handle_key_state_changes(&mut self, changes: Vec<(u32, winit::event::ElementState)>) {
+        for (key, state) in changes.iter() {
+            if *key == TOGGLE_CAMERA_MODE && *state == ElementState::Pressed {
+                self.camera_mode = match self.camera_mode {
+                    CameraMode::FirstPerson => CameraMode::ThirdPerson,
+                    CameraMode::ThirdPerson => CameraMode::FirstPerson,
+                };
+            }
+            if *key == TOGGLE_COLLISION_DEBUG && *state == ElementState::Pressed {
+                self.show_collisions = !self.show_collisions;
+            }
+            if *key == TOGGLE_HELD_LIGHT && *state == ElementState::Pressed {
+                self.holds_light = !self.holds_light;
+            }
+            if *key == TOGGLE_GAMEMODE && *state == ElementState::Pressed {
+                let gamemode = match self.gamemode {
+                    GameMode::Creative => GameMode::Survival,
+                    GameMode::Survival => GameMode::Spectator,
+                    GameMode::Spectator => GameMode::Creative,
+                };
+                self.client.send(ToServer::SetGameMode(gamemode), MessageDelivery::Ordered);
+            }
+            if *key == CYCLE_WEATHER && *state == ElementState::Pressed {
+                let precipitation = match self.weather_effects.weather().precipitation {
+                    None => Some(Precipitation::Rain),
+                    Some(Precipitation::Rain) => Some(Precipitation::Snow),
+                    Some(Precipitation::Snow) => None,
+                };
+                let intensity = if precipitation.is_some() { 1.0 } else { 0.0 };
+                self.weather_effects.set_weather(Weather { precipitation, intensity });
+            }
+            for (i, &toggle_key) in TOGGLE_PERF_GRAPHS.iter().enumerate() {
+                if *key == toggle_key && *state == ElementState::Pressed {
+                    self.shown_perf_graphs[i] = !self.shown_perf_graphs[i];
+                }
+            }
+        }
+        self.debug_visualizers.handle_key_state_changes(&changes);
+        if self.debug_visualizers.take_reload_chunks() {
+            info!("Remeshing {} chunks", self.world.num_loaded_chunks());
+            self.world.remesh_all_chunks();
+        }
         self.ui.handle_key_state_changes(changes);
     }
+
+    fn exit(&mut self) {
+        // Stop the local server and wait until it saved the world
+        if let Some(local_server) = self.local_server.take() {
+            if !local_server.is_finished() {
+                info!("Stopping the local server");
+                self.client.send(ToServer::StopServer, MessageDelivery::Ordered);
+            }
+            if local_server.join().is_err() {
+                error!("The local server panicked");
+            }
+        }
+    }
 }
@@ -0,0 +1,56 @@
+//! The swing of the player when it breaks or places a block.
+//!
+//! It starts as soon as the player clicks, without waiting for the server, so that the interactions feel instant.
+//! Nothing is undone if the server refuses the interaction: the swing only lasts a fraction of a second.
+
+/// Duration of a swing, in seconds
+const SWING_DURATION: f64 = 0.25;
+/// Maximum angle of the swing, in degrees
+const SWING_ANGLE: f64 = 3.0;
+
+#[derive(Debug, Default)]
+pub struct SwingAnimation {
+    /// Time since the swing started, `None` if the player isn't swinging
+    elapsed: Option<f64>,
+}
+
+impl SwingAnimation {
+    /// Start a new swing, even if the previous one didn't end
+    pub fn start(&mut self) {
+        self.elapsed = Some(0.0);
+    }
+
+    pub fn update(&mut self, seconds_delta: f64) {
+        self.elapsed = self
+            .elapsed
+            .map(|elapsed| elapsed + seconds_delta)
+            .filter(|&elapsed| elapsed < SWING_DURATION);
+    }
+
+    /// Current angle of the swing in degrees, going down quickly then coming back up
+    pub fn angle(&self) -> f64 {
+        match self.elapsed {
+            Some(elapsed) => {
+                let t = elapsed / SWING_DURATION;
+                SWING_ANGLE * (std::f64::consts::PI * t.sqrt()).sin()
+            }
+            None => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_swing() {
+        let mut swing = SwingAnimation::default();
+        assert_eq!(swing.angle(), 0.0);
+        swing.start();
+        swing.update(SWING_DURATION / 4.0);
+        assert!((swing.angle() - SWING_ANGLE).abs() < 1e-9);
+        swing.update(SWING_DURATION);
+        assert_eq!(swing.angle(), 0.0);
+    }
+}
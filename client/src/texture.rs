@@ -4,50 +4,14 @@ use log::info;
 
 const MIPMAP_LEVELS: u32 = 5;
 
-/// Load an image into a texture
-pub fn load_image(
+/// Load the mipmaps of a square image into a texture. The first mipmap is `image_size` pixels wide.
+pub fn load_mipmaps(
     device: &wgpu::Device,
     encoder: &mut wgpu::CommandEncoder,
-    image: ImageBuffer<Rgba<u8>, Vec<u8>>,
+    mipmaps: &[Vec<u8>],
+    image_size: u32,
 ) -> wgpu::Texture {
-    info!("Loading image...");
-    // Only squared images are allowed
-    // TODO: check for power of two
-    assert_eq!(image.width(), image.height());
-    let image_size = image.width();
-    // Generate mipmaps
-    let mut mipmaps = Vec::new();
-    mipmaps.push(Vec::from(&*image));
-    for level in 1..MIPMAP_LEVELS {
-        // 5 mip maps only
-        let current_size = (image_size >> level) as usize;
-        if current_size == 0 {
-            break;
-        }
-        let previous_size = (image_size >> (level - 1)) as usize;
-        let mut new_layer = Vec::with_capacity(current_size * current_size * 4);
-        let previous_layer = mipmaps.last().unwrap();
-        for row in 0..current_size {
-            for col in 0..current_size {
-                for color in 0..4 {
-                    new_layer.push(
-                        ((previous_layer[2 * row * previous_size * 4 + 2 * col * 4 + color] as u16
-                            + previous_layer
-                                [2 * row * previous_size * 4 + (2 * col + 1) * 4 + color]
-                                as u16
-                            + previous_layer
-                                [(2 * row + 1) * previous_size * 4 + 2 * col * 4 + color]
-                                as u16
-                            + previous_layer
-                                [(2 * row + 1) * previous_size * 4 + (2 * col + 1) * 4 + color]
-                                as u16)
-                            / 4) as u8,
-                    );
-                }
-            }
-        }
-        mipmaps.push(new_layer);
-    }
+    let mip_level_count = mipmaps.len() as u32;
     // Create texture
     info!("Creating texture");
     let texture_descriptor = wgpu::TextureDescriptor {
@@ -57,7 +21,7 @@ pub fn load_image(
             height: image_size,
             depth: 1,
         },
-        mip_level_count: MIPMAP_LEVELS,
+        mip_level_count,
         sample_count: 1,
         dimension: wgpu::TextureDimension::D2,
         format: wgpu::TextureFormat::Rgba8Unorm,
@@ -66,7 +30,7 @@ pub fn load_image(
     let texture = device.create_texture(&texture_descriptor);
     // Send texture to GPU
 
-    for level in 0..MIPMAP_LEVELS {
+    for level in 0..mip_level_count {
         info!("Copying mipmap level {mipmap_level}", mipmap_level = level);
         let current_size = image_size >> level;
         let src_buffer = device.create_buffer_init(&BufferInitDescriptor {
@@ -104,3 +68,41 @@ pub fn load_image(
     info!("Texture loading successful");
     texture
 }
+
+/// Generate the mipmaps of a square image by averaging every 2x2 block of the previous level.
+/// The first level is the image itself.
+pub fn generate_mipmaps(image: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> Vec<Vec<u8>> {
+    let image_size = image.width();
+    let mut mipmaps = Vec::new();
+    mipmaps.push(Vec::from(&**image));
+    for level in 1..MIPMAP_LEVELS {
+        let current_size = (image_size >> level) as usize;
+        if current_size == 0 {
+            break;
+        }
+        let previous_size = (image_size >> (level - 1)) as usize;
+        let mut new_layer = Vec::with_capacity(current_size * current_size * 4);
+        let previous_layer = mipmaps.last().unwrap();
+        for row in 0..current_size {
+            for col in 0..current_size {
+                for color in 0..4 {
+                    new_layer.push(
+                        ((previous_layer[2 * row * previous_size * 4 + 2 * col * 4 + color] as u16
+                            + previous_layer
+                                [2 * row * previous_size * 4 + (2 * col + 1) * 4 + color]
+                                as u16
+                            + previous_layer
+                                [(2 * row + 1) * previous_size * 4 + 2 * col * 4 + color]
+                                as u16
+                            + previous_layer
+                                [(2 * row + 1) * previous_size * 4 + (2 * col + 1) * 4 + color]
+                                as u16)
+                            / 4) as u8,
+                    );
+                }
+            }
+        }
+        mipmaps.push(new_layer);
+    }
+    mipmaps
+}
@@ -0,0 +1,113 @@
+//! Touch controls: a virtual joystick on the left half of the screen,
+//! dragging on the right half of the screen to look around, and a pause button in the top right corner.
+use winit::dpi::PhysicalPosition;
+use winit::event::{Touch, TouchPhase};
+
+/// Distance in pixels that the joystick must be moved before it registers
+const JOYSTICK_DEADZONE: f64 = 20.0;
+/// Size of the pause button in the top right corner of the screen, in pixels
+pub const PAUSE_BUTTON_SIZE: f64 = 96.0;
+
+/// A touch event, without the device of winit's `Touch` so that the replays can create it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TouchInput {
+    pub id: u64,
+    pub phase: TouchPhase,
+    pub location: PhysicalPosition<f64>,
+}
+
+impl From<Touch> for TouchInput {
+    fn from(touch: Touch) -> Self {
+        Self {
+            id: touch.id,
+            phase: touch.phase,
+            location: touch.location,
+        }
+    }
+}
+
+/// What a touch does in game
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TouchAction {
+    /// Rotate the camera by some pixels
+    Look(f64, f64),
+    /// Open the pause menu
+    Pause,
+}
+
+/// The state of the touch controls
+#[derive(Debug, Default)]
+pub struct TouchControls {
+    /// Id, start position and current position of the joystick touch
+    joystick: Option<(u64, PhysicalPosition<f64>, PhysicalPosition<f64>)>,
+    /// Id and last position of the look touch
+    look: Option<(u64, PhysicalPosition<f64>)>,
+}
+
+impl TouchControls {
+    /// Process a touch event, returning what it does, if anything
+    pub fn process_touch(&mut self, touch: TouchInput, window_width: u32) -> Option<TouchAction> {
+        let TouchInput { id, phase, location } = touch;
+        match phase {
+            TouchPhase::Started => {
+                if location.x >= window_width as f64 - PAUSE_BUTTON_SIZE && location.y < PAUSE_BUTTON_SIZE {
+                    return Some(TouchAction::Pause);
+                }
+                if location.x < window_width as f64 / 2.0 {
+                    if self.joystick.is_none() {
+                        self.joystick = Some((id, location, location));
+                    }
+                } else if self.look.is_none() {
+                    self.look = Some((id, location));
+                }
+                None
+            }
+            TouchPhase::Moved => {
+                if let Some((joystick_id, _, current)) = self.joystick.as_mut() {
+                    if *joystick_id == id {
+                        *current = location;
+                        return None;
+                    }
+                }
+                if let Some((look_id, last)) = self.look.as_mut() {
+                    if *look_id == id {
+                        let delta = (location.x - last.x, location.y - last.y);
+                        *last = location;
+                        return Some(TouchAction::Look(delta.0, delta.1));
+                    }
+                }
+                None
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                if self.joystick.map(|(joystick_id, _, _)| joystick_id) == Some(id) {
+                    self.joystick = None;
+                }
+                if self.look.map(|(look_id, _)| look_id) == Some(id) {
+                    self.look = None;
+                }
+                None
+            }
+        }
+    }
+
+    /// Get the offset of the joystick from its start position, in pixels, or `(0, 0)` if it's in the deadzone
+    pub fn get_joystick_offset(&self) -> (f64, f64) {
+        match self.joystick {
+            Some((_, start, current)) => {
+                let (dx, dy) = (current.x - start.x, current.y - start.y);
+                if dx * dx + dy * dy < JOYSTICK_DEADZONE * JOYSTICK_DEADZONE {
+                    (0.0, 0.0)
+                } else {
+                    (dx, dy)
+                }
+            }
+            None => (0.0, 0.0),
+        }
+    }
+
+    /// Release all the touches
+    pub fn clear(&mut self) {
+        self.joystick = None;
+        self.look = None;
+    }
+}
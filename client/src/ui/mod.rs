@@ -1,14 +1,19 @@
-use self::widgets::{Text, WithStyle};
+use self::tooltip::TooltipPrimitive;
+use self::widgets::{Label, Text, Toggle, WithStyle, WithTooltip};
+use crate::input::YawPitch;
+use crate::settings::Settings;
 use crate::ui::widgets::Button;
 use crate::window::WindowData;
 use anyhow::Result;
-use quint::{wt, Size, Style, WidgetTree};
+use quint::{wt, ScrollView, Size, Slider, Style, WidgetTree};
 use std::collections::BTreeMap;
 use voxel_rs_common::debug::DebugInfo;
+use voxel_rs_common::recipe::RecipeId;
 use wgpu_glyph::ab_glyph::PxScale;
 use winit::dpi::LogicalPosition;
 
 //pub mod rewrite;
+pub mod tooltip;
 pub mod widgets;
 
 // TODO: rewrite ui because it's very badly designed
@@ -17,13 +22,55 @@ pub mod widgets;
 pub enum Message {
     ExitMenu,
     ExitGame,
+    OpenSettings,
+    CloseSettings,
+    SetRenderDistance(f64),
+    SetFov(f64),
+    SetMouseSensitivity(f64),
+    ScrollSettings(f32),
+    ToggleInvertMouse,
+    ToggleVsync,
+    ToggleFullscreen,
+    ToggleTouchControls,
+    ToggleLargeUi,
+    CycleAntialiasing,
+    CycleShadowCascades,
+    ToggleDistanceFog,
+    ToggleEnvironmentEffects,
+    CycleShadowResolution,
+    Craft(RecipeId),
+    CloseCrafting,
+    Respawn,
+}
+
+/// A recipe listed in the crafting window
+#[derive(Debug, Clone)]
+pub struct CraftingEntry {
+    pub recipe: RecipeId,
+    pub description: String,
+    /// `true` if the inventory contains the ingredients
+    pub craftable: bool,
 }
 
 pub struct Ui {
     pub ui: quint::Ui<PrimitiveBuffer, Message>,
     messages: Vec<Message>,
     show_menu: bool,
+    show_settings: bool,
+    show_crafting: bool,
+    /// The inventory screen is drawn by the Gui, but it's opened and closed like the other windows
+    show_inventory: bool,
+    /// The recipes that the player clicked in the crafting window, sent to the server during the next update
+    crafted_recipes: Vec<RecipeId>,
+    /// `true` while the player is dead, until the server respawns it
+    show_death_screen: bool,
+    /// `true` if the player clicked the respawn button since the last update
+    respawn_requested: bool,
     should_exit: bool,
+    /// Camera rotation of the mouse movements made in the settings, to preview the mouse settings
+    mouse_preview: YawPitch,
+    /// Scroll offset of the settings list
+    settings_scroll: f32,
 }
 
 impl Ui {
@@ -32,25 +79,72 @@ impl Ui {
             ui: quint::Ui::new(),
             messages: Vec::new(),
             show_menu: false,
+            show_settings: false,
+            show_crafting: false,
+            show_inventory: false,
+            crafted_recipes: Vec::new(),
+            show_death_screen: false,
+            respawn_requested: false,
             should_exit: false,
+            mouse_preview: YawPitch { yaw: 0.0, pitch: 0.0 },
+            settings_scroll: 0.0,
         }
     }
 
     pub fn cursor_moved(&mut self, p: LogicalPosition<f64>) {
-        self.ui.set_cursor_position(quint::Position {
+        let position = quint::Position {
             x: p.x as f32,
             y: p.y as f32,
-        });
+        };
+        self.messages.extend(self.ui.update(vec![quint::Event::CursorMoved(position)]));
+    }
+
+    /// Scroll the widget under the cursor by `delta` logical pixels
+    pub fn mouse_wheel(&mut self, delta: f32) {
+        self.messages.extend(self.ui.update(vec![quint::Event::MouseWheel { delta }]));
     }
 
     pub fn should_update_camera(&self) -> bool {
-        !self.show_menu
+        !self.show_menu && !self.show_crafting && !self.show_inventory && !self.show_death_screen
     }
 
-    /// Rebuild the Ui if it changed
-    pub fn rebuild(&mut self, debug_info: &mut DebugInfo, data: &WindowData) -> Result<()> {
-        self.update();
+    /// `true` if the crafting window is open, so that the recipes must be passed to `rebuild`
+    pub fn is_crafting_open(&self) -> bool {
+        self.show_crafting
+    }
 
+    /// `true` if the inventory screen must be drawn
+    pub fn is_inventory_open(&self) -> bool {
+        self.show_inventory && !self.show_menu && !self.show_death_screen
+    }
+
+    /// The recipes that the player clicked since the last call
+    pub fn take_crafted_recipes(&mut self) -> Vec<RecipeId> {
+        std::mem::take(&mut self.crafted_recipes)
+    }
+
+    /// Show the death screen while the player is dead
+    pub fn set_dead(&mut self, dead: bool) {
+        self.show_death_screen = dead;
+        if dead {
+            self.show_crafting = false;
+            self.show_inventory = false;
+        }
+    }
+
+    /// `true` if the player clicked the respawn button since the last call
+    pub fn take_respawn_request(&mut self) -> bool {
+        std::mem::take(&mut self.respawn_requested)
+    }
+
+    /// Rebuild the Ui if it changed
+    pub fn rebuild(
+        &mut self,
+        settings: &Settings,
+        debug_info: &mut DebugInfo,
+        data: &WindowData,
+        crafting: &[CraftingEntry],
+    ) -> Result<()> {
         let mut layers = Vec::new();
 
         // Always draw debug info
@@ -60,7 +154,15 @@ impl Ui {
 
         // Draw menu
         if self.show_menu {
-            layers.push(self.draw_menu());
+            if self.show_settings {
+                layers.push(self.draw_settings(settings, data.logical_window_size.height as f32));
+            } else {
+                layers.push(self.draw_menu(settings.get_ui_scale()));
+            }
+        } else if self.show_death_screen {
+            layers.push(self.draw_death_screen(settings.get_ui_scale()));
+        } else if self.show_crafting {
+            layers.push(self.draw_crafting(settings.get_ui_scale(), crafting));
         }
 
         let (win_w, win_h) = (
@@ -132,20 +234,20 @@ impl Ui {
         }
     }
 
-    fn draw_menu(&self) -> WidgetTree<PrimitiveBuffer, Message> {
+    fn draw_menu(&self, scale: f32) -> WidgetTree<PrimitiveBuffer, Message> {
         let menu_button = |text: &'static str, message| {
             wt! {
                 Button {
                     text: vec![
                         TextPart {
                             text: text.to_owned(),
-                            font_size: PxScale::from(50.0),
+                            font_size: PxScale::from(50.0 * scale),
                             color: [1.0, 1.0, 1.0, 1.0],
                             font: Some("arcade".to_owned()),
                         },
                     ],
                     message,
-                    style: Style::default().absolute_size(400.0, 100.0),
+                    style: Style::default().absolute_size(400.0 * scale, 100.0 * scale),
                 },
             }
         };
@@ -160,12 +262,304 @@ impl Ui {
             }),
             vec![
                 menu_button("RESUME", Message::ExitMenu),
+                menu_button("SETTINGS", Message::OpenSettings),
                 menu_button("EXIT", Message::ExitGame),
             ],
         );
         buttons_container
     }
 
+    fn draw_crafting(&self, scale: f32, crafting: &[CraftingEntry]) -> WidgetTree<PrimitiveBuffer, Message> {
+        let label = |text: String, color: [f32; 4]| {
+            vec![TextPart {
+                text,
+                font_size: PxScale::from(30.0 * scale),
+                color,
+                font: Some("arcade".to_owned()),
+            }]
+        };
+        let white = [1.0, 1.0, 1.0, 1.0];
+        let gray = [0.5, 0.5, 0.5, 1.0];
+        let item_style = || Style::default().absolute_size(800.0 * scale, 60.0 * scale);
+
+        let mut rows = vec![wt! {
+            Label {
+                text: label("CRAFTING".to_owned(), white),
+                style: item_style(),
+            },
+        }];
+        if crafting.is_empty() {
+            rows.push(wt! {
+                Label {
+                    text: label("NO RECIPES".to_owned(), gray),
+                    style: item_style(),
+                },
+            });
+        }
+        for entry in crafting.iter() {
+            let text = entry.description.to_uppercase();
+            // The recipes that can't be crafted are displayed, but can't be clicked
+            rows.push(if entry.craftable {
+                wt! {
+                    Button {
+                        text: label(text, white),
+                        message: Message::Craft(entry.recipe),
+                        style: item_style(),
+                    },
+                }
+            } else {
+                wt! {
+                    Label {
+                        text: label(text, gray),
+                        style: item_style(),
+                    },
+                }
+            });
+        }
+        rows.push(wt! {
+            Button {
+                text: label("CLOSE".to_owned(), white),
+                message: Message::CloseCrafting,
+                style: item_style(),
+            },
+        });
+        WidgetTree::new(
+            Box::new(WithStyle {
+                style: Style::default()
+                    .percent_size(1.0, 1.0)
+                    .center_cross()
+                    .center_main()
+                    .vertical(),
+            }),
+            rows,
+        )
+    }
+
+    fn draw_death_screen(&self, scale: f32) -> WidgetTree<PrimitiveBuffer, Message> {
+        let label = |text: &str, font_size: f32| {
+            vec![TextPart {
+                text: text.to_owned(),
+                font_size: PxScale::from(font_size * scale),
+                color: [1.0, 1.0, 1.0, 1.0],
+                font: Some("arcade".to_owned()),
+            }]
+        };
+        WidgetTree::new(
+            Box::new(WithStyle {
+                style: Style::default()
+                    .percent_size(1.0, 1.0)
+                    .center_cross()
+                    .center_main()
+                    .vertical(),
+            }),
+            vec![
+                wt! {
+                    Label {
+                        text: label("YOU DIED", 80.0),
+                        style: Style::default().absolute_size(800.0 * scale, 150.0 * scale),
+                    },
+                },
+                wt! {
+                    Button {
+                        text: label("RESPAWN", 50.0),
+                        message: Message::Respawn,
+                        style: Style::default().absolute_size(400.0 * scale, 100.0 * scale),
+                    },
+                },
+            ],
+        )
+    }
+
+    fn draw_settings(&self, settings: &Settings, window_height: f32) -> WidgetTree<PrimitiveBuffer, Message> {
+        let scale = settings.get_ui_scale();
+        let label = |text: String| {
+            vec![TextPart {
+                text,
+                font_size: PxScale::from(30.0 * scale),
+                color: [1.0, 1.0, 1.0, 1.0],
+                font: Some("arcade".to_owned()),
+            }]
+        };
+        let item_height = 60.0 * scale;
+        let item_style = || Style::default().absolute_size(600.0 * scale, item_height).no_shrink();
+        let slider = |text: String, value: f64, min: f64, max: f64, on_change: fn(f64) -> Message| {
+            let slider: Slider<PrimitiveBuffer, Message> = Slider {
+                text: label(text),
+                value,
+                min,
+                max,
+                on_change,
+                style: item_style(),
+                background_color: [0.6, 0.1, 0.1, 1.0],
+                hover_color: [0.55, 0.12, 0.12, 1.0],
+                fill_color: [0.8, 0.2, 0.2, 1.0],
+            };
+            wt! { slider, }
+        };
+        let toggle = |text: &'static str, enabled: bool, message: Message| {
+            wt! {
+                Toggle {
+                    text: label(text.to_owned()),
+                    enabled,
+                    message,
+                    style: item_style(),
+                },
+            }
+        };
+
+        let render_distance = settings.render_distance.0;
+        let items = vec![
+            (
+                "Distance in chunks up to which the world is loaded and drawn.\nLower it if the game is slow.",
+                slider(
+                    format!("RENDER DISTANCE: {}", render_distance),
+                    render_distance as f64,
+                    MIN_RENDER_DISTANCE,
+                    MAX_RENDER_DISTANCE,
+                    Message::SetRenderDistance,
+                ),
+            ),
+            (
+                "Vertical field of view, in degrees",
+                slider(
+                    format!("FOV: {:.0}", settings.fov),
+                    settings.fov,
+                    MIN_FOV,
+                    MAX_FOV,
+                    Message::SetFov,
+                ),
+            ),
+            (
+                "How fast the camera turns when the mouse moves",
+                slider(
+                    format!("MOUSE SENSITIVITY: {:.2}", settings.mouse_sensitivity),
+                    settings.mouse_sensitivity,
+                    MIN_MOUSE_SENSITIVITY,
+                    MAX_MOUSE_SENSITIVITY,
+                    Message::SetMouseSensitivity,
+                ),
+            ),
+            (
+                "Look down when the mouse moves up",
+                toggle("INVERT MOUSE Y", settings.invert_mouse, Message::ToggleInvertMouse),
+            ),
+            (
+                "Move the mouse to try the mouse settings",
+                wt! {
+                    Label {
+                        text: label(format!(
+                            "MOVE THE MOUSE: YAW {:.0} PITCH {:.0}",
+                            self.mouse_preview.yaw, self.mouse_preview.pitch
+                        )),
+                        style: item_style(),
+                    },
+                },
+            ),
+            (
+                "Wait for the screen before drawing the next frame,\nto avoid tearing",
+                toggle("VSYNC", settings.vsync, Message::ToggleVsync),
+            ),
+            ("Play in fullscreen", toggle("FULLSCREEN", settings.fullscreen, Message::ToggleFullscreen)),
+            (
+                "Move and look around with touch controls",
+                toggle("TOUCH CONTROLS", settings.touch_controls, Message::ToggleTouchControls),
+            ),
+            ("Make the menus bigger, for touch screens", toggle("LARGE UI", settings.large_ui, Message::ToggleLargeUi)),
+            (
+                "Smooth the jagged edges of the blocks.\nThe game must be restarted to apply it.",
+                wt! {
+                    Button {
+                        text: label(format!(
+                            "ANTIALIASING: {} (RESTART)",
+                            format!("{:?}", settings.antialiasing).to_uppercase()
+                        )),
+                        message: Message::CycleAntialiasing,
+                        style: item_style(),
+                    },
+                },
+            ),
+            (
+                "Number of shadow maps: more cascades give sharper\nshadows far from the camera, but are slower",
+                wt! {
+                    Button {
+                        text: label(match settings.shadows.get_cascades() {
+                            0 => "SHADOWS: OFF".to_owned(),
+                            cascades => format!("SHADOWS: {} CASCADES", cascades),
+                        }),
+                        message: Message::CycleShadowCascades,
+                        style: item_style(),
+                    },
+                },
+            ),
+            (
+                "Size of the shadow maps: bigger maps give sharper shadows.\nThe game must be restarted to apply it.",
+                wt! {
+                    Button {
+                        text: label(format!("SHADOW RESOLUTION: {} (RESTART)", settings.shadows.get_resolution())),
+                        message: Message::CycleShadowResolution,
+                        style: item_style(),
+                    },
+                },
+            ),
+            (
+                "Fade the chunks into the sky near the render distance",
+                toggle("DISTANCE FOG", settings.fog.distance_fog, Message::ToggleDistanceFog),
+            ),
+            (
+                "Add a dense fog and tint the screen in water\nand below the world",
+                toggle(
+                    "UNDERWATER AND VOID EFFECTS",
+                    settings.fog.environment_effects,
+                    Message::ToggleEnvironmentEffects,
+                ),
+            ),
+        ];
+        let items = items
+            .into_iter()
+            .map(|(tooltip, item)| {
+                wt! {
+                    WithTooltip { tooltip: tooltip.to_owned(), style: item_style() },
+                    item,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // The settings are scrolled if they don't fit above the back button
+        let content_height = items.len() as f32 * item_height;
+        let list_height = content_height.min(window_height - 3.0 * item_height).max(item_height);
+        let list = WidgetTree::new(
+            Box::new(ScrollView {
+                offset: self.settings_scroll,
+                content_height,
+                on_scroll: Message::ScrollSettings,
+                style: Style::default()
+                    .absolute_size(600.0 * scale + SCROLLBAR_SPACE, list_height)
+                    .vertical(),
+                scrollbar_color: [1.0, 1.0, 1.0, 0.8],
+            }),
+            items,
+        );
+        WidgetTree::new(
+            Box::new(WithStyle {
+                style: Style::default()
+                    .percent_size(1.0, 1.0)
+                    .center_cross()
+                    .center_main()
+                    .vertical(),
+            }),
+            vec![
+                list,
+                wt! {
+                    Button {
+                        text: label("BACK".to_owned()),
+                        message: Message::CloseSettings,
+                        style: item_style(),
+                    },
+                },
+            ],
+        )
+    }
+
     pub fn handle_mouse_state_changes(
         &mut self,
         changes: Vec<(winit::event::MouseButton, winit::event::ElementState)>,
@@ -182,26 +576,98 @@ impl Ui {
 
     pub fn handle_key_state_changes(&mut self, changes: Vec<(u32, winit::event::ElementState)>) {
         for (key, state) in changes.into_iter() {
-            // Escape key
-            if key == 1 {
+            if key == crate::input::OPEN_MENU {
+                if let winit::event::ElementState::Pressed = state {
+                    if self.show_settings {
+                        self.show_settings = false;
+                    } else if self.show_crafting {
+                        self.show_crafting = false;
+                    } else if self.show_inventory {
+                        self.show_inventory = false;
+                    } else {
+                        self.show_menu = !self.show_menu;
+                    }
+                }
+            }
+            if key == crate::input::TOGGLE_CRAFTING && !self.show_menu && !self.show_death_screen {
+                if let winit::event::ElementState::Pressed = state {
+                    self.show_crafting = !self.show_crafting;
+                    self.show_inventory = false;
+                }
+            }
+            if key == crate::input::TOGGLE_INVENTORY && !self.show_menu && !self.show_death_screen {
                 if let winit::event::ElementState::Pressed = state {
-                    self.show_menu = !self.show_menu;
+                    self.show_inventory = !self.show_inventory;
+                    self.show_crafting = false;
                 }
             }
         }
     }
 
-    fn update(&mut self) {
+    /// Process the messages sent by the widgets, applying setting changes immediately
+    /// Process the messages of the widgets, returning `true` if a button was clicked
+    pub fn update(&mut self, settings: &mut Settings) -> bool {
+        let mut clicked = false;
         for message in self.messages.drain(..) {
+            match message {
+                Message::SetRenderDistance(_)
+                | Message::SetFov(_)
+                | Message::SetMouseSensitivity(_)
+                | Message::ScrollSettings(_) => {}
+                _ => clicked = true,
+            }
             match message {
                 Message::ExitMenu => self.show_menu = false,
                 Message::ExitGame => self.should_exit = true,
+                Message::OpenSettings => {
+                    self.show_settings = true;
+                    self.mouse_preview = YawPitch { yaw: 0.0, pitch: 0.0 };
+                    self.settings_scroll = 0.0;
+                }
+                Message::CloseSettings => self.show_settings = false,
+                Message::SetRenderDistance(distance) => {
+                    let distance = distance.round() as u64;
+                    let (_, _, y1, y2, _, _) = settings.render_distance;
+                    settings.render_distance = (distance, distance, y1, y2, distance, distance);
+                }
+                Message::SetFov(fov) => settings.fov = fov.round(),
+                Message::SetMouseSensitivity(sensitivity) => {
+                    settings.mouse_sensitivity = sensitivity
+                }
+                Message::ScrollSettings(offset) => self.settings_scroll = offset,
+                Message::ToggleInvertMouse => settings.invert_mouse = !settings.invert_mouse,
+                Message::ToggleVsync => settings.vsync = !settings.vsync,
+                Message::ToggleFullscreen => settings.fullscreen = !settings.fullscreen,
+                Message::ToggleTouchControls => {
+                    settings.touch_controls = !settings.touch_controls
+                }
+                Message::ToggleLargeUi => settings.large_ui = !settings.large_ui,
+                Message::CycleAntialiasing => settings.antialiasing = settings.antialiasing.next(),
+                Message::CycleShadowCascades => settings.shadows.cycle_cascades(),
+                Message::CycleShadowResolution => settings.shadows.cycle_resolution(),
+                Message::ToggleDistanceFog => settings.fog.distance_fog = !settings.fog.distance_fog,
+                Message::ToggleEnvironmentEffects => {
+                    settings.fog.environment_effects = !settings.fog.environment_effects
+                }
+                Message::Craft(recipe) => self.crafted_recipes.push(recipe),
+                Message::CloseCrafting => self.show_crafting = false,
+                Message::Respawn => self.respawn_requested = true,
             }
         }
+        clicked
+    }
+
+    /// Rotate the preview camera of the settings with the current mouse settings, like the real camera
+    pub fn preview_mouse_motion(&mut self, settings: &Settings, delta: (f64, f64)) {
+        if self.show_settings {
+            let dy = if settings.invert_mouse { -delta.1 } else { delta.1 };
+            self.mouse_preview
+                .update_cursor(delta.0 * settings.mouse_sensitivity, dy * settings.mouse_sensitivity);
+        }
     }
 
     pub fn should_capture_mouse(&self) -> bool {
-        !self.show_menu
+        !self.show_menu && !self.show_crafting && !self.show_inventory && !self.show_death_screen
     }
 
     pub fn should_exit(&self) -> bool {
@@ -209,6 +675,15 @@ impl Ui {
     }
 }
 
+const MIN_RENDER_DISTANCE: f64 = 1.0;
+const MAX_RENDER_DISTANCE: f64 = 32.0;
+const MIN_FOV: f64 = 30.0;
+const MAX_FOV: f64 = 120.0;
+const MIN_MOUSE_SENSITIVITY: f64 = 0.01;
+const MAX_MOUSE_SENSITIVITY: f64 = 1.0;
+/// Width added to the scrolled lists for their scrollbar
+pub const SCROLLBAR_SPACE: f32 = 16.0;
+
 pub fn quint_mouse_button(button: winit::event::MouseButton) -> quint::MouseButton {
     use winit::event::MouseButton::*;
     match button {
@@ -226,11 +701,59 @@ pub fn quint_element_state(state: winit::event::ElementState) -> quint::ButtonSt
     }
 }
 
+/// The text editing key of a virtual key code, if it's one
+pub fn quint_key(key: winit::event::VirtualKeyCode) -> Option<quint::Key> {
+    use winit::event::VirtualKeyCode::*;
+    Some(match key {
+        Left => quint::Key::Left,
+        Right => quint::Key::Right,
+        Home => quint::Key::Home,
+        End => quint::Key::End,
+        Back => quint::Key::Backspace,
+        Delete => quint::Key::Delete,
+        Return | NumpadEnter => quint::Key::Enter,
+        Escape => quint::Key::Escape,
+        A => quint::Key::A,
+        _ => return None,
+    })
+}
+
+pub fn quint_modifiers(modifiers: winit::event::ModifiersState) -> quint::Modifiers {
+    quint::Modifiers {
+        shift: modifiers.shift(),
+        ctrl: modifiers.ctrl() || modifiers.logo(),
+    }
+}
+
+/// The layers of the Ui, from bottom to top
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum UiLayer {
+    /// Elements attached to the world, such as the name tags
+    World,
+    /// The crosshair, the health and the other elements of the HUD
+    Hud,
+    /// The debug info and the performance graphs
+    Debug,
+    /// The menus and the windows
+    Menu,
+}
+
+/// Where a primitive is in the draw order: the primitives are drawn layer by layer, by increasing `z`,
+/// and in the order they were added if they have the same z-index
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ZIndex {
+    pub layer: UiLayer,
+    pub z: i32,
+    /// Index of the clipping area in `PrimitiveBuffer::clips`.
+    /// A new index is used every time the area changes, so sorting by index keeps the order of the primitives.
+    pub clip: usize,
+}
+
 #[derive(Debug, Clone)]
 pub struct RectanglePrimitive {
     pub layout: quint::Layout,
     pub color: [f32; 4],
-    pub z: f32,
+    pub z: ZIndex,
 }
 
 #[derive(Debug, Clone)]
@@ -240,16 +763,17 @@ pub struct TextPrimitive {
     pub w: Option<i32>,
     pub h: Option<i32>,
     pub parts: Vec<TextPart>,
-    pub z: f32,
+    pub z: ZIndex,
     pub center_horizontally: bool,
     pub center_vertically: bool,
 }
 
 #[derive(Debug, Clone)]
 pub struct TrianglesPrimitive {
-    pub vertices: Vec<[f32; 3]>,
+    pub vertices: Vec<[f32; 2]>,
     pub indices: Vec<u32>,
     pub color: [f32; 4],
+    pub z: ZIndex,
 }
 
 #[derive(Debug, Clone)]
@@ -260,19 +784,50 @@ pub struct TextPart {
     pub font: Option<String>,
 }
 
-#[derive(Default, Debug)]
+#[derive(Debug)]
 pub struct PrimitiveBuffer {
     pub rectangle: Vec<RectanglePrimitive>,
     pub text: Vec<TextPrimitive>,
     pub triangles: Vec<TrianglesPrimitive>,
+    /// The clipping areas of the primitives, `None` if they are not clipped
+    pub clips: Vec<Option<quint::Layout>>,
+    /// The tooltip of the hovered element, drawn over everything else
+    pub tooltip: Option<TooltipPrimitive>,
+    /// The z-index of the next primitives
+    z: ZIndex,
+}
+
+impl Default for PrimitiveBuffer {
+    fn default() -> Self {
+        Self {
+            rectangle: Vec::new(),
+            text: Vec::new(),
+            triangles: Vec::new(),
+            clips: vec![None],
+            tooltip: None,
+            z: ZIndex { layer: UiLayer::Hud, z: 0, clip: 0 },
+        }
+    }
 }
 
 impl PrimitiveBuffer {
-    pub fn draw_rectangle(&mut self, color: [f32; 4], layout: quint::Layout, z: f32) {
-        self.rectangle.push(RectanglePrimitive { color, layout, z });
+    /// Draw the next primitives in `layer`, with a z-index of 0
+    pub fn set_layer(&mut self, layer: UiLayer) {
+        self.z = ZIndex { layer, z: 0, ..self.z };
+    }
+
+    /// Sort the primitives in draw order. The order of the primitives with the same z-index is kept.
+    pub fn sort(&mut self) {
+        self.rectangle.sort_by_key(|r| r.z);
+        self.text.sort_by_key(|t| t.z);
+        self.triangles.sort_by_key(|t| t.z);
     }
 
-    pub fn draw_rect(&mut self, x: i32, y: i32, w: i32, h: i32, color: [f32; 4], z: f32) {
+    pub fn draw_rectangle(&mut self, color: [f32; 4], layout: quint::Layout) {
+        self.rectangle.push(RectanglePrimitive { color, layout, z: self.z });
+    }
+
+    pub fn draw_rect(&mut self, x: i32, y: i32, w: i32, h: i32, color: [f32; 4]) {
         self.rectangle.push(RectanglePrimitive {
             color,
             layout: quint::Layout {
@@ -281,26 +836,24 @@ impl PrimitiveBuffer {
                 width: w as f32,
                 height: h as f32,
             },
-            z,
+            z: self.z,
         });
     }
 
-    /*pub fn draw_text(
-        &mut self,
-        parts: Vec<TextPart>,
-        layout: quint::Layout,
-        z: f32,
-        centered: bool,
-    ) {
+    pub fn draw_text(&mut self, parts: Vec<TextPart>, layout: quint::Layout, centered: bool) {
         self.text.push(TextPrimitive {
-            layout,
+            x: layout.x as i32,
+            y: layout.y as i32,
+            w: Some(layout.width as i32),
+            h: Some(layout.height as i32),
             parts,
-            z,
-            centered,
+            z: self.z,
+            center_horizontally: centered,
+            center_vertically: centered,
         })
-    }*/
+    }
 
-    pub fn draw_text_simple(&mut self, x: i32, y: i32, h: i32, text: String, color: [f32; 4], z: f32) {
+    pub fn draw_text_simple(&mut self, x: i32, y: i32, h: i32, text: String, color: [f32; 4]) {
         self.text.push(TextPrimitive {
             x,
             y,
@@ -312,17 +865,47 @@ impl PrimitiveBuffer {
                 color,
                 font: None,
             }],
-            z,
+            z: self.z,
             center_horizontally: false,
             center_vertically: true,
         });
     }
 
-    pub fn draw_triangles(&mut self, vertices: Vec<[f32; 3]>, indices: Vec<u32>, color: [f32; 4]) {
+    pub fn draw_triangles(&mut self, vertices: Vec<[f32; 2]>, indices: Vec<u32>, color: [f32; 4]) {
         self.triangles.push(TrianglesPrimitive {
             vertices,
             indices,
             color,
+            z: self.z,
         });
     }
+
+    /// Show a tooltip next to the cursor at (`x`, `y`) if it stays on the element long enough.
+    /// Only the last tooltip of a frame is kept.
+    pub fn draw_tooltip(&mut self, x: f32, y: f32, text: String) {
+        self.tooltip = Some(TooltipPrimitive { x, y, text });
+    }
+}
+
+impl quint::Backend for PrimitiveBuffer {
+    type Text = Vec<TextPart>;
+
+    fn set_z_index(&mut self, z_index: i32) {
+        self.z.z = z_index;
+    }
+
+    fn set_clip_rect(&mut self, clip: Option<quint::Layout>) {
+        if self.clips[self.z.clip] != clip {
+            self.z.clip = self.clips.len();
+            self.clips.push(clip);
+        }
+    }
+
+    fn draw_rectangle(&mut self, color: [f32; 4], layout: quint::Layout) {
+        PrimitiveBuffer::draw_rectangle(self, color, layout);
+    }
+
+    fn draw_text(&mut self, text: Vec<TextPart>, layout: quint::Layout, centered: bool) {
+        PrimitiveBuffer::draw_text(self, text, layout, centered);
+    }
 }
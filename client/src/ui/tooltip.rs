@@ -0,0 +1,108 @@
+//! Tooltips: text boxes that follow the cursor to describe the hovered element.
+//!
+//! Every frame, the Gui and the widgets request the tooltip of the element under the cursor with
+//! `PrimitiveBuffer::draw_tooltip`. The renderer shows it once the cursor stayed on the element for `TOOLTIP_DELAY`,
+//! in a box sized to the text.
+use std::time::{Duration, Instant};
+
+/// How long the cursor must stay on an element before its tooltip is shown
+pub const TOOLTIP_DELAY: Duration = Duration::from_millis(500);
+/// Z-index of the tooltips in the menu layer, above the dragged stacks
+pub const TOOLTIP_Z_INDEX: i32 = 2_000_000;
+pub const TOOLTIP_FONT_SIZE: f32 = 20.0;
+/// Space between the border of a tooltip and its text
+pub const TOOLTIP_PADDING: f32 = 6.0;
+pub const TOOLTIP_COLOR: [f32; 4] = [0.05, 0.05, 0.2, 0.95];
+pub const TOOLTIP_TEXT_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+/// Distance between the cursor and the box of the tooltip
+const CURSOR_OFFSET: f32 = 12.0;
+
+/// The tooltip of the hovered element, with the position of the cursor. The text can have several lines.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TooltipPrimitive {
+    pub x: f32,
+    pub y: f32,
+    pub text: String,
+}
+
+/// Delays the tooltips until the cursor stays on the same element
+#[derive(Debug, Default)]
+pub struct TooltipDelay {
+    /// The text of the last requested tooltip, and since when it's requested
+    hovered: Option<(String, Instant)>,
+}
+
+impl TooltipDelay {
+    /// The tooltip to show this frame, given the one requested by the hovered element
+    pub fn update(&mut self, requested: Option<TooltipPrimitive>, now: Instant) -> Option<TooltipPrimitive> {
+        let requested = match requested {
+            Some(requested) => requested,
+            None => {
+                self.hovered = None;
+                return None;
+            }
+        };
+        let since = match &self.hovered {
+            Some((text, since)) if *text == requested.text => *since,
+            _ => now,
+        };
+        self.hovered = Some((requested.text.clone(), since));
+        if now.duration_since(since) >= TOOLTIP_DELAY {
+            Some(requested)
+        } else {
+            None
+        }
+    }
+}
+
+/// Top left corner of a tooltip box of size `w` x `h` for the cursor at (`x`, `y`). The box is below and right
+/// of the cursor, unless it would leave the window.
+pub fn tooltip_position(x: f32, y: f32, w: f32, h: f32, window_width: f32, window_height: f32) -> (f32, f32) {
+    let place = |cursor: f32, size: f32, window_size: f32| {
+        if cursor + CURSOR_OFFSET + size <= window_size {
+            cursor + CURSOR_OFFSET
+        } else {
+            (cursor - CURSOR_OFFSET - size).max(0.0)
+        }
+    };
+    (place(x, w, window_width), place(y, h, window_height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tooltip(text: &str) -> Option<TooltipPrimitive> {
+        Some(TooltipPrimitive {
+            x: 0.0,
+            y: 0.0,
+            text: text.to_owned(),
+        })
+    }
+
+    #[test]
+    fn test_tooltip_delay() {
+        let mut delay = TooltipDelay::default();
+        let start = Instant::now();
+        let later = |millis| start + Duration::from_millis(millis);
+
+        assert_eq!(delay.update(tooltip("stone"), start), None);
+        assert_eq!(delay.update(tooltip("stone"), later(400)), None);
+        assert_eq!(delay.update(tooltip("stone"), later(500)), tooltip("stone"));
+
+        // Hovering another element restarts the delay
+        assert_eq!(delay.update(tooltip("dirt"), later(600)), None);
+        assert_eq!(delay.update(tooltip("dirt"), later(1100)), tooltip("dirt"));
+        assert_eq!(delay.update(None, later(1200)), None);
+        assert_eq!(delay.update(tooltip("dirt"), later(1300)), None);
+    }
+
+    #[test]
+    fn test_tooltip_position() {
+        assert_eq!(tooltip_position(100.0, 100.0, 50.0, 20.0, 800.0, 600.0), (112.0, 112.0));
+        // Near the bottom right corner, the tooltip is above and left of the cursor
+        assert_eq!(tooltip_position(790.0, 590.0, 50.0, 20.0, 800.0, 600.0), (728.0, 558.0));
+        // A tooltip wider than the window stays at its left border
+        assert_eq!(tooltip_position(10.0, 10.0, 900.0, 20.0, 800.0, 600.0), (0.0, 22.0));
+    }
+}
@@ -1,14 +1,32 @@
 use super::{PrimitiveBuffer, TextPart};
-use quint::{Event, Layout, Position, Style, Widget};
+use quint::{Event, Layout, Position, Style, TextInputEvent, TextInputState, Widget};
+use wgpu_glyph::ab_glyph::PxScale;
+
+/// Space between the border of a text input and its text
+const TEXT_INPUT_PADDING: f32 = 8.0;
+/// Advance of the characters of the default font, which is monospace, relative to the font size
+const CHAR_WIDTH: f32 = 0.6;
 
 pub struct Text {
     pub text: Vec<TextPart>,
 }
 
+/// Some text with a custom style
+pub struct Label {
+    pub text: Vec<TextPart>,
+    pub style: Style,
+}
+
 pub struct WithStyle {
     pub style: Style,
 }
 
+/// A container showing a tooltip while the cursor is over it. Its style must fit its child.
+pub struct WithTooltip {
+    pub tooltip: String,
+    pub style: Style,
+}
+
 pub struct Button<Message>
 where
     Message: Clone,
@@ -18,13 +36,61 @@ where
     pub style: Style,
 }
 
+/// A checkbox with a label
+pub struct Toggle<Message>
+where
+    Message: Clone,
+{
+    pub message: Message,
+    pub text: Vec<TextPart>,
+    pub enabled: bool,
+    pub style: Style,
+}
+
+/// A single line text input.
+/// The application owns its state, and applies the events that the input sends with `on_edit`.
+pub struct TextInput<Message> {
+    pub state: TextInputState,
+    /// Text shown while the input is empty and not focused
+    pub placeholder: String,
+    pub font_size: f32,
+    pub on_edit: fn(TextInputEvent) -> Message,
+    pub style: Style,
+}
+
+impl<Message> TextInput<Message> {
+    fn char_width(&self) -> f32 {
+        self.font_size * CHAR_WIDTH
+    }
+
+    /// Number of characters that fit in the input
+    fn visible_chars(&self, layout: Layout) -> usize {
+        ((layout.width - 2.0 * TEXT_INPUT_PADDING) / self.char_width()).max(1.0) as usize
+    }
+
+    /// Index of the first visible character, scrolling the text so that the cursor stays visible
+    fn first_visible(&self, layout: Layout) -> usize {
+        (self.state.cursor() + 1).saturating_sub(self.visible_chars(layout))
+    }
+}
+
 impl<T> Widget<PrimitiveBuffer, T> for Text {
     fn style(&self) -> Style {
         Style::default().percent_size(1.0, 1.0)
     }
 
     fn render(&self, buffer: &mut PrimitiveBuffer, _cursor_position: Position, layout: Layout) {
-        //buffer.draw_text(self.text.clone(), layout, 0.0, false);
+        buffer.draw_text(self.text.clone(), layout, false);
+    }
+}
+
+impl<T> Widget<PrimitiveBuffer, T> for Label {
+    fn style(&self) -> Style {
+        self.style.clone()
+    }
+
+    fn render(&self, buffer: &mut PrimitiveBuffer, _cursor_position: Position, layout: Layout) {
+        buffer.draw_text(self.text.clone(), layout, true);
     }
 }
 
@@ -34,6 +100,18 @@ impl<T> Widget<PrimitiveBuffer, T> for WithStyle {
     }
 }
 
+impl<T> Widget<PrimitiveBuffer, T> for WithTooltip {
+    fn style(&self) -> Style {
+        self.style.clone()
+    }
+
+    fn render(&self, buffer: &mut PrimitiveBuffer, cursor_position: Position, layout: Layout) {
+        if layout.is_position_inside(cursor_position) {
+            buffer.draw_tooltip(cursor_position.x, cursor_position.y, self.tooltip.clone());
+        }
+    }
+}
+
 impl<T> Widget<PrimitiveBuffer, T> for Button<T>
 where
     T: Clone,
@@ -70,12 +148,12 @@ where
         // Top-left lighter shade
         buffer.draw_triangles(
             vec![
-                [l.x, l.y + l.height, 0.0],
-                [l.x, l.y, 0.0],
-                [l.x + l.width, l.y, 0.0],
-                [pl.x, pl.y + pl.height, 0.0],
-                [pl.x, pl.y, 0.0],
-                [pl.x + pl.width, pl.y, 0.0],
+                [l.x, l.y + l.height],
+                [l.x, l.y],
+                [l.x + l.width, l.y],
+                [pl.x, pl.y + pl.height],
+                [pl.x, pl.y],
+                [pl.x + pl.width, pl.y],
             ],
             vec![0, 3, 1, 1, 3, 4, 4, 5, 1, 1, 5, 2],
             light_shade,
@@ -83,22 +161,22 @@ where
         // Bottom-right darker shade
         buffer.draw_triangles(
             vec![
-                [l.x + l.width, l.y, 0.0],
-                [l.x + l.width, l.y + l.height, 0.0],
-                [l.x, l.y + l.height, 0.0],
-                [pl.x + pl.width, pl.y, 0.0],
-                [pl.x + pl.width, pl.y + pl.height, 0.0],
-                [pl.x, pl.y + pl.height, 0.0],
+                [l.x + l.width, l.y],
+                [l.x + l.width, l.y + l.height],
+                [l.x, l.y + l.height],
+                [pl.x + pl.width, pl.y],
+                [pl.x + pl.width, pl.y + pl.height],
+                [pl.x, pl.y + pl.height],
             ],
             vec![0, 3, 1, 1, 3, 4, 4, 5, 1, 1, 5, 2],
             dark_shade,
         );
-        buffer.draw_rectangle(main_color, pl, 0.0);
+        buffer.draw_rectangle(main_color, pl);
 
         if hovering {
             l.y += 2.0;
         }
-        //buffer.draw_text(self.text.clone(), l, 0.1, true);
+        buffer.draw_text(self.text.clone(), l, true);
     }
 
     fn on_event(
@@ -108,7 +186,10 @@ where
         cursor_position: Position,
         messages: &mut Vec<T>,
     ) {
-        let Event::MouseInput { button, state } = event;
+        let (button, state) = match event {
+            Event::MouseInput { button, state } => (button, state),
+            _ => return,
+        };
         if let quint::MouseButton::Left = button {
             if let quint::ButtonState::Pressed = state {
                 if layout.is_position_inside(cursor_position) {
@@ -118,3 +199,144 @@ where
         }
     }
 }
+
+impl<T> Widget<PrimitiveBuffer, T> for Toggle<T>
+where
+    T: Clone,
+{
+    fn style(&self) -> Style {
+        self.style.clone()
+    }
+
+    fn render(&self, buffer: &mut PrimitiveBuffer, cursor_position: Position, l: Layout) {
+        let hovering = l.is_position_inside(cursor_position);
+        let background_color = if hovering {
+            [0.55, 0.12, 0.12, 1.0]
+        } else {
+            [0.6, 0.1, 0.1, 1.0]
+        };
+        buffer.draw_rectangle(background_color, l);
+        // Checkbox on the left
+        let size = l.height - 16.0;
+        let checkbox = Layout {
+            x: l.x + 8.0,
+            y: l.y + 8.0,
+            width: size,
+            height: size,
+        };
+        buffer.draw_rectangle([1.0, 1.0, 1.0, 1.0], checkbox);
+        if self.enabled {
+            buffer.draw_rectangle([0.8, 0.2, 0.2, 1.0], checkbox.with_padding(4.0));
+        }
+        buffer.draw_text(self.text.clone(), l, true);
+    }
+
+    fn on_event(
+        &self,
+        event: Event,
+        layout: Layout,
+        cursor_position: Position,
+        messages: &mut Vec<T>,
+    ) {
+        let (button, state) = match event {
+            Event::MouseInput { button, state } => (button, state),
+            _ => return,
+        };
+        if let quint::MouseButton::Left = button {
+            if let quint::ButtonState::Pressed = state {
+                if layout.is_position_inside(cursor_position) {
+                    messages.push(self.message.clone());
+                }
+            }
+        }
+    }
+}
+
+impl<T> Widget<PrimitiveBuffer, T> for TextInput<T> {
+    fn style(&self) -> Style {
+        self.style.clone()
+    }
+
+    fn render(&self, buffer: &mut PrimitiveBuffer, cursor_position: Position, l: Layout) {
+        let hovering = l.is_position_inside(cursor_position);
+        let border_color = if self.state.is_focused() {
+            [1.0, 1.0, 1.0, 1.0]
+        } else if hovering {
+            [0.7, 0.7, 0.7, 1.0]
+        } else {
+            [0.5, 0.5, 0.5, 1.0]
+        };
+        buffer.draw_rectangle(border_color, l);
+        buffer.draw_rectangle([0.1, 0.1, 0.1, 0.9], l.with_padding(2.0));
+
+        let first = self.first_visible(l);
+        let visible = self.visible_chars(l);
+        let char_width = self.char_width();
+        let text_layout = Layout {
+            x: l.x + TEXT_INPUT_PADDING,
+            y: l.y + (l.height - self.font_size) / 2.0,
+            width: l.width - 2.0 * TEXT_INPUT_PADDING,
+            height: self.font_size,
+        };
+        // Screen position of the left side of a character
+        let char_x = |index: usize| text_layout.x + (index.max(first) - first).min(visible) as f32 * char_width;
+
+        if let Some((start, end)) = self.state.selection() {
+            let selection = Layout {
+                x: char_x(start),
+                width: char_x(end) - char_x(start),
+                ..text_layout
+            };
+            buffer.draw_rectangle([0.2, 0.4, 0.8, 1.0], selection);
+        }
+        let (text, color) = if self.state.text().is_empty() && !self.state.is_focused() {
+            (self.placeholder.clone(), [0.6, 0.6, 0.6, 1.0])
+        } else {
+            (self.state.text().chars().skip(first).take(visible).collect(), [1.0, 1.0, 1.0, 1.0])
+        };
+        let text = TextPart {
+            text,
+            font_size: PxScale::from(self.font_size),
+            color,
+            font: None,
+        };
+        buffer.draw_text(vec![text], text_layout, false);
+        if self.state.is_focused() {
+            let cursor = Layout {
+                x: char_x(self.state.cursor()) - 1.0,
+                width: 2.0,
+                ..text_layout
+            };
+            buffer.draw_rectangle([1.0, 1.0, 1.0, 1.0], cursor);
+        }
+    }
+
+    fn on_event(
+        &self,
+        event: Event,
+        layout: Layout,
+        cursor_position: Position,
+        messages: &mut Vec<T>,
+    ) {
+        let focused = self.state.is_focused();
+        match event {
+            Event::MouseInput {
+                button: quint::MouseButton::Left,
+                state: quint::ButtonState::Pressed,
+            } => {
+                if layout.is_position_inside(cursor_position) {
+                    let x = (cursor_position.x - layout.x - TEXT_INPUT_PADDING) / self.char_width();
+                    let index = self.first_visible(layout) + x.round().max(0.0) as usize;
+                    messages.push((self.on_edit)(TextInputEvent::Click(index)));
+                } else if focused {
+                    messages.push((self.on_edit)(TextInputEvent::Unfocus));
+                }
+            }
+            Event::KeyPressed { key, modifiers } if focused => {
+                messages.push((self.on_edit)(TextInputEvent::Key(key, modifiers)));
+            }
+            Event::Character(c) if focused => messages.push((self.on_edit)(TextInputEvent::Character(c))),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Client-side effects of the weather: wet surfaces and splash particles
+
+use crate::particles::{add_particle_lines, update_particles, Particle};
+use crate::render::DebugLines;
+use crate::world::World;
+use nalgebra::Vector3;
+use voxel_rs_common::physics::BlockContainer;
+use voxel_rs_common::weather::{Precipitation, Weather};
+use voxel_rs_common::world::{BlockPos, LightContainer};
+
+/// Horizontal distance between the camera and the splashes
+const SPLASH_RADIUS: f64 = 12.0;
+/// Vertical distance between the camera and the surfaces searched for splashes
+const SPLASH_HEIGHT: i64 = 16;
+/// Number of splashes per second at full intensity
+const SPLASHES_PER_SECOND: f64 = 300.0;
+/// Maximum number of particles alive at the same time
+const MAX_PARTICLES: usize = 2000;
+/// How fast the surfaces get wet when it rains, and dry when it stops, per second
+const WETTING_SPEED: f32 = 0.1;
+const DRYING_SPEED: f32 = 0.02;
+
+pub struct WeatherEffects {
+    weather: Weather,
+    wetness: f32,
+    /// Droplets bouncing off the surfaces
+    particles: Vec<Particle>,
+    /// Fractional number of splashes that were not spawned yet
+    pending_splashes: f64,
+}
+
+impl WeatherEffects {
+    pub fn new() -> Self {
+        Self {
+            weather: Weather::default(),
+            wetness: 0.0,
+            particles: Vec::new(),
+            pending_splashes: 0.0,
+        }
+    }
+
+    pub fn weather(&self) -> Weather {
+        self.weather
+    }
+
+    pub fn set_weather(&mut self, weather: Weather) {
+        self.weather = weather;
+    }
+
+    /// How wet the surfaces exposed to the sky are, between 0 and 1
+    pub fn wetness(&self) -> f32 {
+        self.wetness
+    }
+
+    /// Update the wetness, and move and spawn the particles around the camera
+    pub fn update(&mut self, world: &World, camera_position: Vector3<f64>, seconds_delta: f64) {
+        let rain = self.weather.rain_intensity();
+        let speed = if rain > self.wetness { WETTING_SPEED } else { DRYING_SPEED };
+        let max_step = speed * seconds_delta as f32;
+        self.wetness += (rain - self.wetness).max(-max_step).min(max_step);
+
+        update_particles(&mut self.particles, seconds_delta);
+
+        // Spawn the new splashes
+        let precipitation = match self.weather.precipitation {
+            Some(precipitation) => precipitation,
+            None => {
+                self.pending_splashes = 0.0;
+                return;
+            }
+        };
+        self.pending_splashes += SPLASHES_PER_SECOND * self.weather.intensity as f64 * seconds_delta;
+        while self.pending_splashes >= 1.0 {
+            self.pending_splashes -= 1.0;
+            if self.particles.len() >= MAX_PARTICLES {
+                continue;
+            }
+            let angle = rand::random::<f64>() * 2.0 * std::f64::consts::PI;
+            let distance = rand::random::<f64>().sqrt() * SPLASH_RADIUS;
+            let x = camera_position.x + angle.cos() * distance;
+            let z = camera_position.z + angle.sin() * distance;
+            if let Some(y) = find_exposed_surface(world, x, camera_position.y, z) {
+                self.spawn_splash(Vector3::new(x, y, z), precipitation);
+            }
+        }
+    }
+
+    fn spawn_splash(&mut self, position: Vector3<f64>, precipitation: Precipitation) {
+        let (droplets, speed, lifetime, color) = match precipitation {
+            Precipitation::Rain => (3, 1.5, 0.3, [0.5, 0.6, 0.8]),
+            Precipitation::Snow => (1, 0.3, 0.6, [0.95, 0.95, 1.0]),
+        };
+        for _ in 0..droplets {
+            let angle = rand::random::<f64>() * 2.0 * std::f64::consts::PI;
+            self.particles.push(Particle {
+                position,
+                velocity: Vector3::new(angle.cos() * speed * 0.5, speed, angle.sin() * speed * 0.5),
+                remaining_time: lifetime * (0.5 + 0.5 * rand::random::<f64>()),
+                color,
+            });
+        }
+    }
+
+    /// Add the lines of the particles to `lines`
+    pub fn add_particle_lines(&self, lines: &mut DebugLines) {
+        add_particle_lines(&self.particles, lines);
+    }
+}
+
+/// Find the top of the highest full block of the column around `y` that is exposed to the sky
+fn find_exposed_surface(world: &World, x: f64, y: f64, z: f64) -> Option<f64> {
+    let (x, y, z) = (x.floor() as i64, y.floor() as i64, z.floor() as i64);
+    for block_y in ((y - SPLASH_HEIGHT)..(y + SPLASH_HEIGHT)).rev() {
+        let pos = BlockPos::from((x, block_y, z));
+        if world.is_block_full(pos) {
+            let above = BlockPos::from((x, block_y + 1, z));
+            return if world.is_sky_exposed(above) {
+                Some(block_y as f64 + 1.0)
+            } else {
+                None
+            };
+        }
+    }
+    None
+}
@@ -1,13 +1,22 @@
-use crate::{input::InputState, settings::Settings};
-use anyhow::Result;
+use crate::{
+    analytics::SessionAnalytics,
+    gamepad::GamepadControls,
+    input::{InputState, OPEN_MENU, TAKE_SCREENSHOT},
+    render::ScreenshotCapture,
+    replay::{self, InputPlayer, InputRecorder, ScriptEvent},
+    settings::{self, Settings},
+    touch::{TouchAction, TouchInput},
+};
+use anyhow::{anyhow, Context, Result};
 use log::{info, warn};
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::time::Instant;
 use wgpu::Device;
 use futures::executor::block_on;
 use winit::dpi::{LogicalPosition, LogicalSize, PhysicalPosition, PhysicalSize};
-use winit::event::{ElementState, MouseButton};
+use winit::event::{ElementState, MouseButton, MouseScrollDelta, TouchPhase};
 use winit::event_loop::ControlFlow;
-use winit::window::Window;
+use winit::window::{Fullscreen, Window};
 
 /// A closure that creates a new instance of `State`.
 pub type StateFactory =
@@ -18,7 +27,6 @@ pub enum StateTransition {
     /// Don't transition, keep the current state.
     KeepCurrent,
     /// Transition to another state using its `StateFactory`.
-    #[allow(dead_code)] // TODO: remove when it will be used again
     ReplaceCurrent(StateFactory),
     /// Don't transition, close the current window.
     CloseWindow,
@@ -61,11 +69,13 @@ pub trait State {
     /// Render.
     ///
     /// Note: The state is responsible for swapping buffers.
+    /// The returned commands are submitted to `queue`, but the state may submit some commands before them.
     fn render<'a>(
         &mut self,
         settings: &Settings,
         buffers: WindowBuffers<'a>,
         device: &mut Device,
+        queue: &wgpu::Queue,
         data: &WindowData,
         input_state: &InputState,
     ) -> Result<(StateTransition, wgpu::CommandBuffer)>;
@@ -77,15 +87,76 @@ pub trait State {
     fn handle_mouse_state_changes(&mut self, changes: Vec<(MouseButton, ElementState)>);
     /// Key pressed
     fn handle_key_state_changes(&mut self, changes: Vec<(u32, ElementState)>);
+    /// Character typed or text editing key pressed, for the text inputs
+    fn handle_text_input(&mut self, _event: quint::Event) {}
+    /// Mouse wheel scrolled by some logical pixels, positive when scrolling up
+    fn handle_mouse_wheel(&mut self, _delta: f32) {}
+    /// Called before the state is replaced or the window is closed, for example to save the game
+    fn exit(&mut self) {}
+}
+
+/// Handle a touch if the touch controls are enabled.
+/// In game, it goes to the touch controls, and in the menus it acts like a left click.
+pub(crate) fn handle_touch(
+    touch: TouchInput,
+    in_game: bool,
+    window_data: &WindowData,
+    settings: &Settings,
+    input_state: &mut InputState,
+    state: &mut dyn State,
+    mouse_state_changes: &mut Vec<(MouseButton, ElementState)>,
+) {
+    if !settings.touch_controls {
+        return;
+    }
+    if in_game {
+        match input_state.process_touch(touch, window_data.physical_window_size.width) {
+            Some(TouchAction::Look(dx, dy)) => state.handle_mouse_motion(settings, (dx, dy)),
+            // Like pressing the key that opens the menu
+            Some(TouchAction::Pause) => state.handle_key_state_changes(vec![
+                (OPEN_MENU, ElementState::Pressed),
+                (OPEN_MENU, ElementState::Released),
+            ]),
+            None => (),
+        }
+    } else {
+        state.handle_cursor_movement(touch.location.to_logical(window_data.hidpi_factor));
+        let element_state = match touch.phase {
+            TouchPhase::Started => Some(ElementState::Pressed),
+            TouchPhase::Ended | TouchPhase::Cancelled => Some(ElementState::Released),
+            TouchPhase::Moved => None,
+        };
+        if let Some(element_state) = element_state {
+            if input_state.process_mouse_input(element_state, MouseButton::Left) {
+                mouse_state_changes.push((MouseButton::Left, element_state));
+            }
+        }
+    }
 }
 
+/// Logical pixels scrolled by one line of the mouse wheel
+const SCROLL_LINE_HEIGHT: f32 = 40.0;
+
 /// Color format of the window's color buffer
 pub const COLOR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Bgra8Unorm;
+/// Color format of the HDR buffer the world is rendered to, before it is tonemapped to the window's color buffer
+pub const HDR_COLOR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
 /// Format of the window's depth buffer
 pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
 
-/// Open a new window with the given settings and the given initial state
-pub fn open_window(mut settings: Settings, initial_state: StateFactory) -> ! {
+/// Size of the offscreen buffers that `run_headless` renders to
+const HEADLESS_WIDTH: u32 = 1600;
+const HEADLESS_HEIGHT: u32 = 900;
+
+/// Open a new window with the given settings and the given initial state.
+/// The input of the window is recorded to `input_recorder` and replaced by the script of `input_player`, if they are set.
+pub fn open_window(
+    mut settings: Settings,
+    analytics: Option<SessionAnalytics>,
+    mut input_recorder: Option<InputRecorder>,
+    mut input_player: Option<InputPlayer>,
+    initial_state: StateFactory,
+) -> ! {
     info!("Opening new window...");
     // Create the window
     let window_title = "voxel-rs".to_owned();
@@ -106,7 +177,8 @@ pub fn open_window(mut settings: Settings, initial_state: StateFactory) -> ! {
     .expect("Failed to create adapter");
     // TODO: device should be immutable
     let (mut device, queue) = block_on(adapter.request_device(&wgpu::DeviceDescriptor {
-        features: wgpu::Features::empty(),
+        // Chunks are drawn with a single call if the adapter supports it
+        features: adapter.features() & wgpu::Features::MULTI_DRAW_INDIRECT,
         limits: wgpu::Limits::default(),
         shader_validation: true
     }, None))
@@ -117,10 +189,11 @@ pub fn open_window(mut settings: Settings, initial_state: StateFactory) -> ! {
         format: COLOR_FORMAT,
         width: physical_window_size.width,
         height: physical_window_size.height,
-        present_mode: wgpu::PresentMode::Mailbox,
+        present_mode: settings.get_present_mode(),
     };
     let mut swap_chain = device.create_swap_chain(&surface, &sc_desc);
     info!("Creating the multisampled texture buffer");
+    SAMPLE_COUNT.store(settings.get_msaa_samples(), Ordering::Relaxed);
     let texture_view_descriptor = wgpu::TextureViewDescriptor::default();
     let mut msaa_texture_descriptor = wgpu::TextureDescriptor {
         label: None,
@@ -130,7 +203,7 @@ pub fn open_window(mut settings: Settings, initial_state: StateFactory) -> ! {
             depth: 1,
         },
         mip_level_count: 1,
-        sample_count: SAMPLE_COUNT,
+        sample_count: sample_count(),
         dimension: wgpu::TextureDimension::D2,
         format: sc_desc.format,
         usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
@@ -146,13 +219,15 @@ pub fn open_window(mut settings: Settings, initial_state: StateFactory) -> ! {
             depth: 1,
         },
         mip_level_count: 1,
-        sample_count: SAMPLE_COUNT,
+        sample_count: sample_count(),
         dimension: wgpu::TextureDimension::D2,
         format: DEPTH_FORMAT,
-        usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
+        // Sampled by the temporal antialiasing
+        usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
     };
     let mut depth_texture = device.create_texture(&depth_texture_descriptor);
     let mut depth_texture_view = depth_texture.create_view(&texture_view_descriptor);
+    let mut screenshots = ScreenshotCapture::new(&device);
 
     let mut window_data = {
         let physical_window_size = window.inner_size();
@@ -167,6 +242,11 @@ pub fn open_window(mut settings: Settings, initial_state: StateFactory) -> ! {
     };
 
     let mut input_state = InputState::new();
+    let mut gamepad = if settings.gamepad.enabled {
+        GamepadControls::new()
+    } else {
+        None
+    };
 
     let mut window_flags = WindowFlags {
         grab_cursor: false,
@@ -197,29 +277,88 @@ pub fn open_window(mut settings: Settings, initial_state: StateFactory) -> ! {
                     Moved(_) => (),
                     CloseRequested | Destroyed => *control_flow = ControlFlow::Exit,
                     DroppedFile(_) | HoveredFile(_) | HoveredFileCancelled => (),
-                    ReceivedCharacter(_) => (),
+                    // Pasted text is also received one character at a time
+                    // TODO: read the clipboard on ctrl+V, winit doesn't give access to it
+                    ReceivedCharacter(c) => state.handle_text_input(quint::Event::Character(c)),
                     Focused(focused) => {
                         window_data.focused = focused;
                         input_state.clear();
                     }
                     KeyboardInput { input, .. } => {
+                        if let Some(recorder) = input_recorder.as_mut() {
+                            let pressed = input.state == ElementState::Pressed;
+                            recorder.record(ScriptEvent::KeyboardInput(input.scancode, pressed));
+                        }
                         if input_state.process_keyboard_input(input) {
                             key_state_changes.push((input.scancode, input.state));
                         }
+                        // Repeated key presses also move the cursor of the text inputs
+                        if let (ElementState::Pressed, Some(key)) =
+                            (input.state, input.virtual_keycode.and_then(crate::ui::quint_key))
+                        {
+                            let modifiers = crate::ui::quint_modifiers(input_state.get_modifiers_state());
+                            state.handle_text_input(quint::Event::KeyPressed { key, modifiers });
+                        }
+                    }
+                    CursorMoved { position, .. } => {
+                        // Without raw input, the mouse motion is the movement of the cursor away from the center
+                        if !settings.raw_mouse_input && window_flags.grab_cursor && window_data.focused {
+                            let PhysicalSize { width, height } = window_data.physical_window_size;
+                            let delta = (position.x - (width / 2) as f64, position.y - (height / 2) as f64);
+                            if delta != (0.0, 0.0) {
+                                if let Some(recorder) = input_recorder.as_mut() {
+                                    recorder.record(ScriptEvent::MouseMotion(delta.0, delta.1));
+                                }
+                                state.handle_mouse_motion(&settings, delta);
+                            }
+                        }
+                        let position = position.to_logical(hidpi_factor);
+                        if let Some(gamepad) = gamepad.as_mut() {
+                            gamepad.set_cursor_position(position);
+                        }
+                        if let Some(recorder) = input_recorder.as_mut() {
+                            recorder.record(ScriptEvent::CursorMoved(position.x, position.y));
+                        }
+                        state.handle_cursor_movement(position)
+                    }
+                    CursorEntered { .. } | CursorLeft { .. } => (),
+                    MouseWheel { delta, .. } => {
+                        let delta = match delta {
+                            MouseScrollDelta::LineDelta(_, lines) => lines * SCROLL_LINE_HEIGHT,
+                            MouseScrollDelta::PixelDelta(pixels) => (pixels.y / hidpi_factor) as f32,
+                        };
+                        state.handle_mouse_wheel(delta);
                     }
-                    CursorMoved { position, .. } => state.handle_cursor_movement(position.to_logical(hidpi_factor)),
-                    CursorEntered { .. } | CursorLeft { .. } | MouseWheel { .. } => (),
                     MouseInput {
                         button,
                         state: element_state,
                         ..
                     } => {
+                        if let Some(recorder) = input_recorder.as_mut() {
+                            let pressed = element_state == ElementState::Pressed;
+                            recorder.record(ScriptEvent::MouseInput(button.into(), pressed));
+                        }
                         if input_state.process_mouse_input(element_state, button) {
                             mouse_state_changes.push((button, element_state));
                         }
                     }
+                    Touch(touch) => {
+                        let touch = TouchInput::from(touch);
+                        if let Some(recorder) = input_recorder.as_mut() {
+                            recorder.record(ScriptEvent::from(touch));
+                        }
+                        handle_touch(
+                            touch,
+                            window_flags.grab_cursor,
+                            &window_data,
+                            &settings,
+                            &mut input_state,
+                            &mut *state,
+                            &mut mouse_state_changes,
+                        );
+                    }
                     // weird events
-                    TouchpadPressure { .. } | AxisMotion { .. } | Touch(..) | ThemeChanged(_) => (),
+                    TouchpadPressure { .. } | AxisMotion { .. } | ThemeChanged(_) => (),
                     ModifiersChanged(modifiers_state) => input_state.set_modifiers_state(modifiers_state),
                 }
             },
@@ -229,12 +368,22 @@ pub fn open_window(mut settings: Settings, initial_state: StateFactory) -> ! {
                 }
                 use winit::event::DeviceEvent::*;
                 match event {
-                    MouseMotion { delta } => state.handle_mouse_motion(&settings, delta),
+                    MouseMotion { delta } if settings.raw_mouse_input => {
+                        if let Some(recorder) = input_recorder.as_mut() {
+                            recorder.record(ScriptEvent::MouseMotion(delta.0, delta.1));
+                        }
+                        state.handle_mouse_motion(&settings, delta)
+                    }
                     _ => (),
                 }
             }
             /* MAIN LOOP TICK */
             MainEventsCleared => {
+                // If vsync was toggled, recreate the SwapChain
+                if sc_desc.present_mode != settings.get_present_mode() {
+                    sc_desc.present_mode = settings.get_present_mode();
+                    swap_chain = device.create_swap_chain(&surface, &sc_desc);
+                }
                 // If the window was resized, update the SwapChain and the window data
                 if window_resized {
                     info!("The window was resized, adjusting buffers...");
@@ -260,6 +409,72 @@ pub fn open_window(mut settings: Settings, initial_state: StateFactory) -> ! {
                 }
                 window_resized = false;
 
+                // Replay scripted input
+                if let Some(player) = input_player.as_mut() {
+                    match player.next_frame() {
+                        Some(events) => {
+                            let touches = replay::feed_events(
+                                events,
+                                &settings,
+                                &mut input_state,
+                                &mut *state,
+                                &mut mouse_state_changes,
+                                &mut key_state_changes,
+                            );
+                            for touch in touches {
+                                handle_touch(
+                                    touch,
+                                    window_flags.grab_cursor,
+                                    &window_data,
+                                    &settings,
+                                    &mut input_state,
+                                    &mut *state,
+                                    &mut mouse_state_changes,
+                                );
+                            }
+                        }
+                        None => {
+                            info!("Done replaying input");
+                            if player.close_window_at_end() {
+                                *control_flow = ControlFlow::Exit;
+                            }
+                            input_player = None;
+                        }
+                    }
+                }
+                if let Some(recorder) = input_recorder.as_mut() {
+                    recorder.end_frame();
+                }
+
+                // The gamepad is updated once per frame, its sticks move continuously
+                if let Some(gamepad) = gamepad.as_mut() {
+                    let frame = gamepad.update(
+                        &settings,
+                        !window_flags.grab_cursor,
+                        window_data.logical_window_size,
+                        &mut input_state,
+                        &mut mouse_state_changes,
+                        &mut key_state_changes,
+                    );
+                    if let Some(delta) = frame.look {
+                        state.handle_mouse_motion(&settings, delta);
+                    }
+                    if let Some(position) = frame.cursor {
+                        if let Err(e) = window.set_cursor_position(position) {
+                            warn!("Failed to move the cursor with the gamepad: {}", e);
+                        }
+                        state.handle_cursor_movement(position);
+                    }
+                    if let Some(delta) = frame.scroll {
+                        state.handle_mouse_wheel(delta);
+                    }
+                }
+
+                // Screenshots work in every state
+                if key_state_changes.contains(&(TAKE_SCREENSHOT, ElementState::Pressed)) {
+                    screenshots.request();
+                }
+
                 // Update state
                 let (v1, v2) = (Vec::new(), Vec::new()); // TODO: clean up
                 state.handle_mouse_state_changes(std::mem::replace(&mut mouse_state_changes, v1));
@@ -283,6 +498,13 @@ pub fn open_window(mut settings: Settings, initial_state: StateFactory) -> ! {
 
                 // Update window flags
                 window.set_title(&window_flags.window_title);
+                if settings.fullscreen != window.fullscreen().is_some() {
+                    window.set_fullscreen(if settings.fullscreen {
+                        Some(Fullscreen::Borderless(window.current_monitor()))
+                    } else {
+                        None
+                    });
+                }
                 if window_flags.grab_cursor && window_data.focused {
                     window.set_cursor_visible(false);
                     let PhysicalSize { width, height } = window_data.physical_window_size;
@@ -308,6 +530,7 @@ pub fn open_window(mut settings: Settings, initial_state: StateFactory) -> ! {
                     StateTransition::KeepCurrent => (),
                     StateTransition::ReplaceCurrent(new_state) => {
                         info!("Transitioning to a new window state...");
+                        state.exit();
                         let (new_state, cmd) = new_state(&mut settings, &mut device)
                             .expect("Failed to create next window state");
                         state = new_state;
@@ -321,23 +544,45 @@ pub fn open_window(mut settings: Settings, initial_state: StateFactory) -> ! {
 
                 // Render frame
                 let swap_chain_output = swap_chain.get_current_frame().expect("Failed to unwrap swap chain output.");
+                // A captured frame is rendered to a texture that can be copied, and drawn to the swap chain afterwards
+                screenshots.update(&device);
+                let capture_frame = screenshots.prepare(&device, &window_data);
+                let texture_buffer = if capture_frame {
+                    screenshots.frame_view()
+                } else {
+                    &swap_chain_output.output.view
+                };
+                // Without multisampling, render directly to the swap chain
+                let multisampled_texture_buffer = if sample_count() > 1 {
+                    &msaa_texture_view
+                } else {
+                    texture_buffer
+                };
                 let (state_transition, commands) = state
                     .render(
                         &settings,
                         WindowBuffers {
-                            texture_buffer: &swap_chain_output.output.view,
-                            multisampled_texture_buffer: &msaa_texture_view,
+                            texture_buffer,
+                            multisampled_texture_buffer,
                             depth_buffer: &depth_texture_view,
                         },
                         &mut device,
+                        &queue,
                         &window_data,
                         &input_state,
                     )
                     .expect("Failed to `render` the current window state");
                 queue.submit(vec![commands]);
+                if capture_frame {
+                    queue.submit(vec![screenshots.capture(&device, &swap_chain_output.output.view)]);
+                }
+                if let Some(analytics) = analytics.as_ref() {
+                    analytics.add_frame(settings.render_distance);
+                }
                 match state_transition {
                     StateTransition::KeepCurrent => (),
                     StateTransition::ReplaceCurrent(new_state) => {
+                        state.exit();
                         let (new_state, cmd) = new_state(&mut settings, &mut device)
                             .expect("Failed to create next window state");
                         state = new_state;
@@ -351,12 +596,147 @@ pub fn open_window(mut settings: Settings, initial_state: StateFactory) -> ! {
             RedrawRequested(_) => (), // TODO: handle this
             LoopDestroyed => {
                 // TODO: cleanup relevant stuff
+                state.exit();
+                if let Err(e) = settings::save_settings(&settings) {
+                    warn!("Failed to save settings ({:?})", e);
+                }
+                if let Some(analytics) = analytics.as_ref() {
+                    analytics.finish(false);
+                }
+                if let Some(recorder) = input_recorder.as_mut() {
+                    if let Err(e) = recorder.save() {
+                        warn!("Failed to save recorded input ({:?})", e);
+                    }
+                }
             }
             _ => (),
         }
     });
 }
 
+/// Run the states without a window, feeding them the frames of an input script.
+/// Every frame is updated and rendered to offscreen buffers at a steady 60 FPS, whatever the speed of the replay.
+/// Return at the end of the script or when a state closes the window. The settings are not saved.
+pub fn run_headless(mut settings: Settings, initial_state: StateFactory, mut input_player: InputPlayer) -> Result<()> {
+    info!("Running headless...");
+    let instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY);
+    let adapter = block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::HighPerformance,
+        compatible_surface: None,
+    }))
+    .context("Failed to create adapter")?;
+    let (mut device, queue) = block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+        features: adapter.features() & wgpu::Features::MULTI_DRAW_INDIRECT,
+        limits: wgpu::Limits::default(),
+        shader_validation: true
+    }, None))
+    .map_err(|e| anyhow!("Failed to request device ({:?})", e))?;
+    SAMPLE_COUNT.store(settings.get_msaa_samples(), Ordering::Relaxed);
+    // The textures are kept alive with their views
+    let create_buffer = |sample_count: u32, format: wgpu::TextureFormat, usage: wgpu::TextureUsage| {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size: wgpu::Extent3d {
+                width: HEADLESS_WIDTH,
+                height: HEADLESS_HEIGHT,
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        (texture, view)
+    };
+    let (_color_texture, color_view) = create_buffer(1, COLOR_FORMAT, wgpu::TextureUsage::OUTPUT_ATTACHMENT);
+    let (_msaa_texture, msaa_view) =
+        create_buffer(sample_count(), COLOR_FORMAT, wgpu::TextureUsage::OUTPUT_ATTACHMENT);
+    let (_depth_texture, depth_view) = create_buffer(
+        sample_count(),
+        DEPTH_FORMAT,
+        wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+    );
+    let window_data = WindowData {
+        logical_window_size: LogicalSize::new(HEADLESS_WIDTH as f64, HEADLESS_HEIGHT as f64),
+        physical_window_size: PhysicalSize::new(HEADLESS_WIDTH, HEADLESS_HEIGHT),
+        hidpi_factor: 1.0,
+        focused: true,
+    };
+    let mut window_flags = WindowFlags {
+        grab_cursor: false,
+        window_title: "voxel-rs".to_owned(),
+    };
+    let mut input_state = InputState::new();
+    let mut mouse_state_changes = Vec::new();
+    let mut key_state_changes = Vec::new();
+
+    let (mut state, cmd) = initial_state(&mut settings, &mut device).context("Failed to create initial state")?;
+    queue.submit(vec![cmd]);
+    while let Some(events) = input_player.next_frame() {
+        let touches = replay::feed_events(
+            events,
+            &settings,
+            &mut input_state,
+            &mut *state,
+            &mut mouse_state_changes,
+            &mut key_state_changes,
+        );
+        for touch in touches {
+            handle_touch(
+                touch,
+                window_flags.grab_cursor,
+                &window_data,
+                &settings,
+                &mut input_state,
+                &mut *state,
+                &mut mouse_state_changes,
+            );
+        }
+        state.handle_mouse_state_changes(std::mem::take(&mut mouse_state_changes));
+        state.handle_key_state_changes(std::mem::take(&mut key_state_changes));
+        let state_transition = match state.update(
+            &mut settings,
+            &input_state,
+            &window_data,
+            &mut window_flags,
+            1.0 / 60.0,
+            &mut device,
+        )? {
+            StateTransition::KeepCurrent => {
+                let buffers = WindowBuffers {
+                    texture_buffer: &color_view,
+                    multisampled_texture_buffer: if sample_count() > 1 { &msaa_view } else { &color_view },
+                    depth_buffer: &depth_view,
+                };
+                let (state_transition, commands) =
+                    state.render(&settings, buffers, &mut device, &queue, &window_data, &input_state)?;
+                queue.submit(vec![commands]);
+                state_transition
+            }
+            state_transition => state_transition,
+        };
+        match state_transition {
+            StateTransition::KeepCurrent => (),
+            StateTransition::ReplaceCurrent(new_state) => {
+                info!("Transitioning to a new window state...");
+                state.exit();
+                let (new_state, cmd) =
+                    new_state(&mut settings, &mut device).context("Failed to create next window state")?;
+                state = new_state;
+                queue.submit(vec![cmd]);
+            }
+            StateTransition::CloseWindow => break,
+        }
+        // Don't let the frames pile up, like the swap chain would
+        device.poll(wgpu::Maintain::Wait);
+    }
+    info!("Done replaying input");
+    state.exit();
+    Ok(())
+}
+
 pub const CLEAR_COLOR: wgpu::Color = wgpu::Color {
     r: 0.2,
     g: 0.2,
@@ -364,7 +744,14 @@ pub const CLEAR_COLOR: wgpu::Color = wgpu::Color {
     a: 1.0,
 };
 pub const CLEAR_DEPTH: f32 = 1.0;
-pub const SAMPLE_COUNT: u32 = 4;
+
+/// Number of samples per pixel of the color and depth buffers
+static SAMPLE_COUNT: AtomicU32 = AtomicU32::new(4);
+
+/// Get the number of samples per pixel, chosen from the settings when the window was opened
+pub fn sample_count() -> u32 {
+    SAMPLE_COUNT.load(Ordering::Relaxed)
+}
 
 #[derive(Debug, Clone, Copy)]
 pub struct WindowBuffers<'a> {
@@ -1,13 +1,40 @@
 use std::collections::HashMap;
 use std::sync::Arc;
+use nalgebra::Vector3;
 use voxel_rs_common::{
-    block::BlockMesh,
+    block::{BlockId, BlockMesh, entity::{BlockEntity, ChunkBlockEntities, ITEM_FRAME_ROTATIONS}},
+    dimension::{Dimension, FogProfile},
+    item::ItemMesh,
     physics::BlockContainer,
     player::{CloseChunks, RenderDistance},
-    world::{BlockPos, ChunkPos, Chunk, LightChunk},
+    season::SeasonState,
+    world::{BlockPos, ChunkPos, Chunk, LightChunk, LightContainer, Neighborhood27, CHUNK_SIZE},
 };
-use crate::render::WorldRenderer;
-use crate::render::world::{ChunkMeshData, MeshingWorker, start_meshing_worker};
+use crate::render::{DebugLines, Model, PointLight, WorldRenderer};
+use crate::render::world::{tiles_of_mesh, ChunkMeshData, MeshingWorker, start_meshing_worker};
+
+/// Maximum distance between the player and the chunks whose textures are streamed in high resolution
+const STREAMING_DISTANCE: i64 = 2;
+/// Distance in chunks from which the chunks are meshed at a lower level of detail,
+/// with the side of the cubes of blocks that are merged into one
+const LOD_DISTANCES: [(f64, u32); 2] = [(8.0, 2), (16.0, 4)];
+/// The chunks only switch to another level of detail this many chunks past the LOD distances,
+/// so that the chunks at the border of two levels are not meshed again every time the player moves a bit
+const LOD_HYSTERESIS: f64 = 1.0;
+
+/// The level of detail of a chunk at some distance from the player
+fn lod_scale(distance: f64) -> u32 {
+    LOD_DISTANCES
+        .iter()
+        .rev()
+        .find(|&&(lod_distance, _)| distance >= lod_distance)
+        .map_or(1, |&(_, scale)| scale)
+}
+
+/// The new level of detail of a chunk, keeping the current one close to the LOD distances
+fn update_lod_scale(current: u32, distance: f64) -> u32 {
+    current.clamp(lod_scale(distance - LOD_HYSTERESIS), lod_scale(distance + LOD_HYSTERESIS))
+}
 
 /// Client-side world.
 /// It is currently responsible for:
@@ -22,6 +49,8 @@ pub struct World {
     close_chunks: CloseChunks,
     /// The renderer
     renderer: WorldRenderer,
+    /// The tiles of the texture atlas used by every block
+    block_tiles: Vec<Vec<u32>>,
 }
 
 impl World {
@@ -29,31 +58,118 @@ impl World {
     pub fn new(block_meshes: Vec<BlockMesh>, renderer: WorldRenderer) -> Self {
         Self {
             chunks: HashMap::new(),
+            block_tiles: block_meshes.iter().map(tiles_of_mesh).collect(),
             meshing_worker: start_meshing_worker(block_meshes),
             close_chunks: CloseChunks::new(&RenderDistance::default()),
             renderer,
         }
     }
 
+    /// Set the direction of the sun in the sky
+    pub fn set_sun_direction(&mut self, sun_direction: Vector3<f64>) {
+        self.renderer.set_sun_direction(sun_direction);
+    }
+
+    /// Set the debug lines drawn on top of the world
+    pub fn set_debug_lines(&mut self, debug_lines: DebugLines) {
+        self.renderer.set_debug_lines(debug_lines);
+    }
+
+    /// Set how wet the surfaces exposed to the rain are
+    pub fn set_wetness(&mut self, wetness: f32) {
+        self.renderer.set_wetness(wetness);
+    }
+
+    /// Set the sky, ambient light and fog of the dimension
+    pub fn set_dimension(&mut self, dimension: &Dimension) {
+        self.renderer.set_dimension(dimension);
+    }
+
+    /// Set the distances where the distance fog starts and where it hides everything, `None` to disable it
+    pub fn set_distance_fog(&mut self, distance_fog: Option<(f32, f32)>) {
+        self.renderer.set_distance_fog(distance_fog);
+    }
+
+    /// Set the fog of the environment of the camera, `None` to use the fog of the dimension
+    pub fn set_environment_fog(&mut self, environment_fog: Option<FogProfile>) {
+        self.renderer.set_environment_fog(environment_fog);
+    }
+
+    /// Set the tint and the texture variant of the seasonal blocks
+    pub fn set_season(&mut self, season: SeasonState) {
+        self.renderer.set_season(season);
+    }
+
+    /// Set the particles drawn in the world
+    pub fn set_particles(&mut self, particles: DebugLines) {
+        self.renderer.set_particles(particles);
+    }
+
+    /// Set the number of shadow cascades, 0 to disable the shadows
+    pub fn set_shadow_cascades(&mut self, cascades: usize) {
+        self.renderer.set_shadow_cascades(cascades);
+    }
+
+    /// Set the dynamic lights of the entities
+    pub fn set_dynamic_lights(&mut self, dynamic_lights: Vec<PointLight>) {
+        self.renderer.set_dynamic_lights(dynamic_lights);
+    }
+
+    /// Replace the block meshes and the renderer after the game data was reloaded, and remesh every chunk
+    pub fn reload_data(&mut self, block_meshes: Vec<BlockMesh>, renderer: WorldRenderer) {
+        self.block_tiles = block_meshes.iter().map(tiles_of_mesh).collect();
+        // The previous worker stops once it is dropped
+        self.meshing_worker = start_meshing_worker(block_meshes);
+        self.renderer = renderer;
+        for client_chunk in self.chunks.values_mut() {
+            client_chunk.is_in_meshing_queue = false;
+            client_chunk.needs_remesh = true;
+        }
+    }
+
+    /// Remove every chunk mesh and mesh the chunks again from their blocks, for example when stale geometry is drawn.
+    /// The meshes that are still in the meshing queue are kept, they are replaced when the new meshes are ready.
+    pub fn remesh_all_chunks(&mut self) {
+        for (&chunk_pos, client_chunk) in self.chunks.iter_mut() {
+            self.renderer.remove_chunk_mesh(chunk_pos);
+            client_chunk.needs_remesh = true;
+        }
+    }
+
     /// Receive a new chunk from the server
-    pub fn add_chunk(&mut self, chunk: Arc<Chunk>, light_chunk: Arc<LightChunk>) {
+    pub fn add_chunk(&mut self, chunk: Arc<Chunk>, light_chunk: Arc<LightChunk>, block_entities: Arc<ChunkBlockEntities>) {
         // TODO: make sure this only happens once
         let chunk_pos = chunk.pos;
+        let mut block_ids = chunk.palette().to_vec();
+        block_ids.sort_unstable();
+        block_ids.dedup();
         self.chunks.insert(chunk_pos, ClientChunk {
             chunk,
+            block_ids,
             light_chunk,
+            block_entities,
             is_in_meshing_queue: false,
             needs_remesh: true,
+            lod_scale: 1,
         });
-        // Queue adjacent chunks for meshing
-        for i in -1..=1 {
-            for j in -1..=1 {
-                for k in -1..=1 {
-                    let adjacent_chunk_pos = chunk_pos.offset(i, j, k);
-                    if let Some(client_chunk) = self.chunks.get_mut(&adjacent_chunk_pos) {
-                        client_chunk.needs_remesh = true;
-                    }
-                }
+        self.remesh_adjacent_chunks(chunk_pos);
+    }
+
+    /// Replace the light of a chunk. The light of a chunk that is not loaded anymore is ignored.
+    pub fn set_light_chunk(&mut self, light_chunk: Arc<LightChunk>) {
+        let chunk_pos = light_chunk.pos;
+        if let Some(client_chunk) = self.chunks.get_mut(&chunk_pos) {
+            client_chunk.light_chunk = light_chunk;
+            // The light of a chunk is used to mesh the adjacent chunks
+            self.remesh_adjacent_chunks(chunk_pos);
+        }
+    }
+
+    /// Queue a chunk and its adjacent chunks for meshing
+    fn remesh_adjacent_chunks(&mut self, chunk_pos: ChunkPos) {
+        for adjacent_chunk_pos in Neighborhood27::positions(chunk_pos) {
+            if let Some(client_chunk) = self.chunks.get_mut(&adjacent_chunk_pos) {
+                client_chunk.needs_remesh = true;
             }
         }
     }
@@ -70,9 +186,11 @@ impl World {
                 self.renderer.update_chunk_mesh(device, encoder, mesh);
             }
         }
+        self.renderer.compact_chunk_buffers(device, encoder);
     }
 
-    /// Remove chunks that are too far for the player
+    /// Remove chunks that are too far for the player, freeing their meshes.
+    /// The server stops tracking the same chunks, so it will send them again if the player comes back.
     pub fn remove_far_chunks(&mut self, player_chunk: ChunkPos, render_distance: &RenderDistance) {
         let Self { ref mut chunks, ref mut renderer, .. } = self;
         chunks.retain(|chunk_pos, _| {
@@ -88,8 +206,15 @@ impl World {
     /// Start the meshing of a few chunks
     pub fn enqueue_chunks_for_meshing(&mut self, player_chunk: ChunkPos, render_distance: &RenderDistance) {
         self.close_chunks.update(render_distance);
-        for pos in self.close_chunks.get_close_chunks() {
-            let pos = pos.offset_by_pos(player_chunk);
+        for pos in self.close_chunks.iter(player_chunk) {
+            if let Some(client_chunk) = self.chunks.get_mut(&pos) {
+                let distance = (pos.squared_euclidian_distance(player_chunk) as f64).sqrt();
+                let lod_scale = update_lod_scale(client_chunk.lod_scale, distance);
+                if lod_scale != client_chunk.lod_scale {
+                    client_chunk.lod_scale = lod_scale;
+                    client_chunk.needs_remesh = true;
+                }
+            }
             if let Some(client_chunk) = self.chunks.get(&pos) {
                 if client_chunk.needs_remesh && !client_chunk.is_in_meshing_queue {
                     let res = self.meshing_worker.enqueue(self.create_chunk_mesh_data(pos));
@@ -108,21 +233,41 @@ impl World {
         }
     }
 
+    /// Request the high resolution textures of the blocks close to the player, the closest chunks first
+    pub fn update_texture_streaming(&mut self, player_chunk: ChunkPos) {
+        let mut close_chunks = self
+            .chunks
+            .iter()
+            .filter(|(pos, _)| {
+                (pos.px - player_chunk.px).abs() <= STREAMING_DISTANCE
+                    && (pos.py - player_chunk.py).abs() <= STREAMING_DISTANCE
+                    && (pos.pz - player_chunk.pz).abs() <= STREAMING_DISTANCE
+            })
+            .collect::<Vec<_>>();
+        close_chunks.sort_by_key(|(pos, _)| pos.squared_euclidian_distance(player_chunk));
+
+        let mut tiles = Vec::new();
+        for (_, client_chunk) in close_chunks {
+            for &block in client_chunk.block_ids.iter() {
+                for &tile in self.block_tiles[block as usize].iter() {
+                    if !tiles.contains(&tile) {
+                        tiles.push(tile);
+                    }
+                }
+            }
+        }
+        self.renderer.request_texture_tiles(tiles);
+    }
+
     /// Create a `ChunkMeshData` for a loaded chunk
     fn create_chunk_mesh_data(&self, pos: ChunkPos) -> ChunkMeshData {
         let client_chunk = self.chunks.get(&pos).expect("no chunk at current position to create ChunkMeshData");
         let mut all_chunks: [Option<Arc<Chunk>>; 27] = Default::default();
         let mut all_light_chunks: [Option<Arc<LightChunk>>; 27] = Default::default();
-        for i in 0..3 {
-            for j in 0..3 {
-                for k in 0..3 {
-                    let np = pos.offset(i - 1, j - 1, k - 1);
-                    let idx = (i * 9 + j * 3 + k) as usize;
-                    let adj_client_chunk = self.chunks.get(&np);
-                    all_chunks[idx] = adj_client_chunk.map(|c| c.chunk.clone());
-                    all_light_chunks[idx] = adj_client_chunk.map(|c| c.light_chunk.clone());
-                }
-            }
+        for (idx, np) in Neighborhood27::positions(pos).enumerate() {
+            let adj_client_chunk = self.chunks.get(&np);
+            all_chunks[idx] = adj_client_chunk.map(|c| c.chunk.clone());
+            all_light_chunks[idx] = adj_client_chunk.map(|c| c.light_chunk.clone());
         }
 
         ChunkMeshData {
@@ -130,6 +275,7 @@ impl World {
             light_chunk: client_chunk.light_chunk.clone(),
             all_chunks,
             all_light_chunks,
+            lod_scale: client_chunk.lod_scale,
         }
     }
 
@@ -137,25 +283,117 @@ impl World {
     pub fn render_chunks(
         &mut self,
         device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        gpu_timer: &mut crate::render::GpuTimer,
         encoder: &mut wgpu::CommandEncoder,
         buffers: crate::window::WindowBuffers,
-        data: &crate::window::WindowData,
-        frustum: &crate::render::Frustum,
+        cameras: &[(crate::render::Viewport, crate::render::Frustum)],
         enable_culling: bool,
         pointed_block: Option<(BlockPos, usize)>,
         models: &[crate::render::world::Model],
     ) {
         // TODO: remove some of the parameters and calculate them here instead
         self.get_new_chunk_meshes(device, encoder);
-        self.renderer.render(device, encoder, buffers, data, frustum, enable_culling, pointed_block, models);
+        gpu_timer.end_pass(device, queue, encoder, "Uploads");
+        let model_light_levels: Vec<u8> = models
+            .iter()
+            .map(|model| self.get_light_level(model.light_sample_pos()))
+            .collect();
+        // Every camera shares the same chunk buffers, but only the main camera uses occlusion culling
+        for (i, (viewport, frustum)) in cameras.iter().enumerate() {
+            let occlusion_culling = enable_culling && i == 0;
+            self.renderer.render(
+                device,
+                queue,
+                gpu_timer,
+                encoder,
+                buffers,
+                *viewport,
+                frustum,
+                enable_culling,
+                occlusion_culling,
+                pointed_block,
+                models,
+                &model_light_levels,
+            );
+        }
+    }
+
+    /// Return block at position `pos` in the world. 0 is returned if the chunk is not loaded
+    pub fn get_block(&self, pos: BlockPos) -> BlockId {
+        match self.chunks.get(&pos.containing_chunk_pos()) {
+            None => 0,
+            Some(chunk) => chunk.chunk.get_block_at(pos.pos_in_containing_chunk()),
+        }
+    }
+
+    /// Return the light level at position `pos` in the world. 0 is returned if the chunk is not loaded
+    pub fn get_light_level(&self, pos: BlockPos) -> u8 {
+        match self.chunks.get(&pos.containing_chunk_pos()) {
+            None => 0,
+            Some(chunk) => chunk.light_chunk.get_light_at(pos.pos_in_containing_chunk()),
+        }
+    }
+
+    /// The models of the items displayed in the item frames
+    pub fn item_frame_models(&self, item_meshes: &[ItemMesh]) -> Vec<Model> {
+        let mut models = Vec::new();
+        for (chunk_pos, client_chunk) in self.chunks.iter() {
+            for (&(i, j, k), block_entity) in client_chunk.block_entities.iter() {
+                let BlockEntity::ItemFrame { item, face, rotation } = *block_entity;
+                let (mesh_id, scale, mesh_center) = match item_meshes.get(item as usize) {
+                    Some(&ItemMesh::SimpleMesh { mesh_id, scale, mesh_center }) => (mesh_id, scale, mesh_center),
+                    None => continue,
+                };
+                // The item is flat in the xy plane, turn it to face away from the frame
+                let (normal, rot_y) = match face {
+                    0 => ([1.0, 0.0, 0.0], std::f32::consts::FRAC_PI_2),
+                    1 => ([-1.0, 0.0, 0.0], -std::f32::consts::FRAC_PI_2),
+                    4 => ([0.0, 0.0, 1.0], 0.0),
+                    5 => ([0.0, 0.0, -1.0], std::f32::consts::PI),
+                    _ => continue,
+                };
+                let rot_offset = [mesh_center.0 * scale, mesh_center.1 * scale, mesh_center.2 * scale];
+                let distance = 0.5 + rot_offset[2];
+                let center = [
+                    (chunk_pos.px * CHUNK_SIZE as i64 + i as i64) as f32 + 0.5 + normal[0] * distance,
+                    (chunk_pos.py * CHUNK_SIZE as i64 + j as i64) as f32 + 0.5,
+                    (chunk_pos.pz * CHUNK_SIZE as i64 + k as i64) as f32 + 0.5 + normal[2] * distance,
+                ];
+                models.push(Model {
+                    mesh_id,
+                    pos_x: center[0] - rot_offset[0],
+                    pos_y: center[1] - rot_offset[1],
+                    pos_z: center[2] - rot_offset[2],
+                    scale,
+                    rot_y,
+                    rot_z: rotation as f32 * 2.0 * std::f32::consts::PI / ITEM_FRAME_ROTATIONS as f32,
+                    rot_offset,
+                });
+            }
+        }
+        models
     }
 
     /// Number of loaded chunks
+    /// Size of the chunk meshes in GPU memory in bytes, and the number of bytes that are actually used
+    pub fn chunk_meshes_memory(&self) -> (usize, usize) {
+        self.renderer.chunk_buffers_memory()
+    }
+
     pub fn num_loaded_chunks(&self) -> usize {
         self.chunks.len()
     }
 }
 
+impl LightContainer for World {
+    /// The light of the chunks that are not loaded is 0
+    fn get_light(&self, pos: BlockPos) -> (u8, u8) {
+        // TODO: blocks don't emit light yet, and the dynamic lights are only used for rendering
+        (self.get_light_level(pos), 0)
+    }
+}
+
 impl BlockContainer for World {
     fn is_block_full(&self, pos: BlockPos) -> bool {
         // TODO: use BlockRegistry
@@ -170,10 +408,34 @@ impl BlockContainer for World {
 struct ClientChunk {
     /// The chunk itself
     pub chunk: Arc<Chunk>,
+    /// The different blocks in the chunk
+    pub block_ids: Vec<BlockId>,
     /// The light chunk
     pub light_chunk: Arc<LightChunk>,
+    /// The block entities of the chunk
+    pub block_entities: Arc<ChunkBlockEntities>,
     /// True if the chunk is in the meshing queue
     pub is_in_meshing_queue: bool,
     /// True if the chunk needs to be meshed, for example before it never was meshed or because it changed.
     pub needs_remesh: bool,
+    /// The level of detail of the mesh of the chunk, see `ChunkMeshData::lod_scale`
+    pub lod_scale: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lod_hysteresis() {
+        assert_eq!(update_lod_scale(1, 3.0), 1);
+        // The chunk switches to a lower detail a bit past the LOD distance
+        assert_eq!(update_lod_scale(1, 8.5), 1);
+        assert_eq!(update_lod_scale(1, 9.5), 2);
+        // And back to a higher detail a bit before it
+        assert_eq!(update_lod_scale(2, 7.5), 2);
+        assert_eq!(update_lod_scale(2, 6.5), 1);
+        // A chunk that is far enough skips the intermediate levels
+        assert_eq!(update_lod_scale(1, 20.0), 4);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,86 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use voxel_rs_common::world::{Chunk, CHUNK_SIZE};
+
+const STONE: u16 = 1;
+const DIRT: u16 = 2;
+const GRASS: u16 = 3;
+const WATER: u16 = 4;
+
+/// A chunk that looks like generated terrain: stone, dirt and grass under a wavy surface, water in the holes
+fn terrain_chunk() -> Chunk {
+    let mut chunk = Chunk::new((0, 0, 0).into());
+    for i in 0..CHUNK_SIZE {
+        for k in 0..CHUNK_SIZE {
+            let height = 14 + ((i as f64 * 0.3).sin() * 4.0 + (k as f64 * 0.2).cos() * 4.0) as u32;
+            for j in 0..CHUNK_SIZE {
+                let block = if j + 4 < height {
+                    STONE
+                } else if j < height {
+                    DIRT
+                } else if j == height {
+                    GRASS
+                } else if j < 12 {
+                    WATER
+                } else {
+                    continue;
+                };
+                chunk.set_block_at((i, j, k), block);
+            }
+        }
+    }
+    chunk
+}
+
+fn chunk_benchmark(c: &mut Criterion) {
+    let chunk = terrain_chunk();
+    let flat_size = (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize * std::mem::size_of::<u16>();
+    println!(
+        "Terrain chunk: {} bytes with a palette of {} blocks, {} bytes for a flat array",
+        chunk.memory_usage(),
+        chunk.palette().len(),
+        flat_size,
+    );
+
+    c.bench_function("chunk get_block_at", |b| {
+        b.iter(|| {
+            let mut sum = 0u32;
+            for i in 0..CHUNK_SIZE {
+                for j in 0..CHUNK_SIZE {
+                    for k in 0..CHUNK_SIZE {
+                        sum += chunk.get_block_at(black_box((i, j, k))) as u32;
+                    }
+                }
+            }
+            sum
+        })
+    });
+    c.bench_function("chunk get_block_at_unsafe", |b| {
+        b.iter(|| {
+            let mut sum = 0u32;
+            for i in 0..CHUNK_SIZE {
+                for j in 0..CHUNK_SIZE {
+                    for k in 0..CHUNK_SIZE {
+                        sum += unsafe { chunk.get_block_at_unsafe(black_box((i, j, k))) } as u32;
+                    }
+                }
+            }
+            sum
+        })
+    });
+    c.bench_function("chunk set_block_at", |b| {
+        let mut chunk = chunk.clone();
+        b.iter(|| {
+            for i in 0..CHUNK_SIZE {
+                for j in 0..CHUNK_SIZE {
+                    for k in 0..CHUNK_SIZE {
+                        chunk.set_block_at(black_box((i, j, k)), (i + j + k) as u16 % 4);
+                    }
+                }
+            }
+        })
+    });
+    c.bench_function("generate terrain chunk", |b| b.iter(terrain_chunk));
+}
+
+criterion_group!(benches, chunk_benchmark);
+criterion_main!(benches);
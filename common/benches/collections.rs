@@ -0,0 +1,46 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use voxel_rs_common::collections::merge_sorted;
+use voxel_rs_common::world::ChunkPos;
+
+/// Render distance of the simulated players, in chunks
+const RENDER_DISTANCE: i64 = 6;
+
+/// A chunk and its squared distance to the player, sorted by distance first
+type CloseChunk = (u64, i64, i64, i64);
+
+/// The chunks around `center` sorted by distance, like the close chunks of a player
+fn close_chunks(center: ChunkPos) -> Vec<CloseChunk> {
+    let mut chunks = Vec::new();
+    for i in -RENDER_DISTANCE..=RENDER_DISTANCE {
+        for j in -RENDER_DISTANCE..=RENDER_DISTANCE {
+            for k in -RENDER_DISTANCE..=RENDER_DISTANCE {
+                let pos = center.offset(i, j, k);
+                chunks.push((pos.squared_euclidian_distance(center), pos.px, pos.py, pos.pz));
+            }
+        }
+    }
+    chunks.sort();
+    chunks
+}
+
+/// Players standing on a line, close enough to share most of their chunks
+fn players(count: i64) -> Vec<Vec<CloseChunk>> {
+    (0..count).map(|i| close_chunks(ChunkPos::from((i * 3, 0, i % 5)))).collect()
+}
+
+fn merge_benchmark(c: &mut Criterion) {
+    for &count in [1, 10, 100].iter() {
+        let input = players(count);
+        let mut output = Vec::new();
+        c.bench_function(&format!("merge_sorted {} players", count), |b| {
+            b.iter(|| {
+                let inputs = black_box(&input).iter().map(|chunks| chunks.iter().copied());
+                merge_sorted(&mut output, inputs, |&(_, px, py, pz)| (px, py, pz));
+                output.len()
+            })
+        });
+    }
+}
+
+criterion_group!(benches, merge_benchmark);
+criterion_main!(benches);
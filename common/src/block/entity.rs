@@ -0,0 +1,22 @@
+use crate::item::ItemId;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Number of rotation steps of an item in an item frame
+pub const ITEM_FRAME_ROTATIONS: u8 = 8;
+
+/// Data attached to a single block, in addition to its id
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum BlockEntity {
+    /// An item displayed on a face of an item frame
+    ItemFrame {
+        item: ItemId,
+        /// Face of the frame the item is attached to, it is never the top or the bottom face
+        face: usize,
+        /// Rotation of the item around the normal of the face, in steps of `1 / ITEM_FRAME_ROTATIONS` turn
+        rotation: u8,
+    },
+}
+
+/// The block entities of a chunk, by position in the chunk
+pub type ChunkBlockEntities = HashMap<(u32, u32, u32), BlockEntity>;
@@ -1,31 +1,109 @@
 use crate::data::TextureRect;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+
+pub mod entity;
 
 pub type BlockId = u16;
 
+/// Hardness of the blocks that don't specify one
+const DEFAULT_HARDNESS: f32 = 1.0;
+
 /// The type of a block. It contains the behavior and the mesh of the block.
 /// This is the data provided by the creator of the block.
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename = "Block")]
 pub enum BlockType {
     Air, // TODO: skip when deserializing
-    NormalCube { face_textures: Vec<String> },
+    NormalCube {
+        face_textures: Vec<String>,
+        /// Time in seconds that every frame of the animated face textures is displayed.
+        /// The face textures are only split into frames if it's set or if the block is seasonal.
+        #[serde(default)]
+        frame_time: Option<f32>,
+        /// The block is tinted by the seasons. Its face textures can have one frame per season instead of an animation.
+        #[serde(default)]
+        seasonal: bool,
+        /// Time in seconds that a player needs to break the block in survival
+        #[serde(default)]
+        hardness: Option<f32>,
+        /// The light goes through the block, losing this number of light levels on top of the usual one.
+        /// The block is opaque to light if this is not set.
+        #[serde(default)]
+        light_attenuation: Option<u8>,
+        /// The block falls when there is air below it, like sand
+        #[serde(default)]
+        falls: bool,
+        /// The random ticks turn the nearby blocks of this type that have air above them into this block,
+        /// like grass spreading on dirt. The block turns back into this type when it is covered.
+        #[serde(default)]
+        spreads_on: Option<String>,
+        /// Name of the structure that replaces the block during a random tick, like a sapling growing into a tree
+        #[serde(default)]
+        grows_into: Option<String>,
+    },
+    /// A full cube that sets the spawn point of the players that use it, and lets them sleep at night
+    Bed {
+        face_textures: Vec<String>,
+        #[serde(default)]
+        hardness: Option<f32>,
+    },
+    /// A full cube that displays an item on one of its side faces
+    ItemFrame {
+        face_textures: Vec<String>,
+        #[serde(default)]
+        hardness: Option<f32>,
+    },
+}
+
+impl BlockType {
+    /// Time in seconds that a player needs to break the block in survival
+    pub fn hardness(&self) -> f32 {
+        match self {
+            Self::Air => 0.0,
+            Self::NormalCube { hardness, .. } | Self::Bed { hardness, .. } | Self::ItemFrame { hardness, .. } => {
+                hardness.unwrap_or(DEFAULT_HARDNESS)
+            }
+        }
+    }
+
+    /// Whether the block stops the light completely
+    pub fn is_opaque_to_light(&self) -> bool {
+        match self {
+            Self::Air => false,
+            Self::NormalCube { light_attenuation, .. } => light_attenuation.is_none(),
+            Self::Bed { .. } | Self::ItemFrame { .. } => true,
+        }
+    }
+
+    /// Number of light levels lost by the light going through the block on top of the usual one,
+    /// if the block isn't opaque to light
+    pub fn light_attenuation(&self) -> u8 {
+        match self {
+            Self::NormalCube { light_attenuation: Some(attenuation), .. } => *attenuation,
+            _ => 0,
+        }
+    }
 }
 
 /// A general block in-memory representation.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Block {
     pub name: String,
     pub block_type: BlockType,
 }
 
 /// The mesh of a block.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum BlockMesh {
     /// No mesh
     Empty,
-    /// A usual full cube
-    FullCube { textures: [TextureRect; 6] },
+    /// A usual full cube, animated if `frame_time` is positive
+    FullCube {
+        textures: [TextureRect; 6],
+        frame_time: f32,
+        /// Tinted by the seasons, see `season`
+        seasonal: bool,
+    },
 }
 
 impl BlockMesh {
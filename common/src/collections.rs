@@ -1,4 +1,11 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
+use std::hash::Hash;
+
 /// Create a new zero-initialized vector
+///
+/// # Safety
+/// The all-zero bit pattern must be a valid value of `T`.
 pub unsafe fn zero_initialized_vec<T>(size: usize) -> Vec<T> {
     let mut v: Vec<T> = Vec::with_capacity(size);
     std::ptr::write_bytes(v.as_mut_ptr(), 0u8, size);
@@ -7,40 +14,51 @@ pub unsafe fn zero_initialized_vec<T>(size: usize) -> Vec<T> {
 }
 
 /// Fill a vector with zeroes
+///
+/// # Safety
+/// The all-zero bit pattern must be a valid value of `T`, and `T` must not need to be dropped.
 pub unsafe fn zero_vec<T>(v: &mut Vec<T>) {
     std::ptr::write_bytes(v.as_mut_ptr(), 0u8, v.len());
 }
 
-/// Merge sorted arrays
-pub fn merge_arrays<T: Ord + Copy>(output: &mut Vec<T>, input: &[Vec<T>]) {
+/// Merge sorted iterators, keeping only the first element of every key.
+///
+/// This is a k-way merge: the next element of every iterator is kept in a heap,
+/// so merging `n` elements from `k` iterators takes O(n log k).
+pub fn merge_sorted<T, K, I>(output: &mut Vec<T>, inputs: impl IntoIterator<Item = I>, key: impl Fn(&T) -> K)
+where
+    T: Ord + Copy,
+    K: Hash + Eq,
+    I: Iterator<Item = T>,
+{
     output.clear();
-    let n = input.len();
-    let mut indices = vec![0; n];
-    loop {
-        let mut lowest = None;
-        let mut j = n;
-        for i in 0..n {
-            if indices[i] < input[i].len() {
-                let el = input[i][indices[i]];
-                match &mut lowest {
-                    None => {
-                        lowest = Some(el);
-                        j = i;
-                    },
-                    Some(current) => {
-                        if *current < el {
-                            *current = el;
-                            j = i
-                        }
-                    }
-                }
-            }
+    let mut seen = HashSet::new();
+    let mut inputs = inputs.into_iter().collect::<Vec<_>>();
+    // The next element of every iterator, with the index of the iterator
+    let mut heap = inputs
+        .iter_mut()
+        .enumerate()
+        .filter_map(|(i, input)| input.next().map(|el| Reverse((el, i))))
+        .collect::<BinaryHeap<_>>();
+    while let Some(Reverse((el, i))) = heap.pop() {
+        if seen.insert(key(&el)) {
+            output.push(el);
         }
-        if j == n {
-            break
-        } else {
-            output.push(lowest.unwrap());
-            indices[j] += 1;
+        if let Some(next) = inputs[i].next() {
+            heap.push(Reverse((next, i)));
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_sorted() {
+        let mut output = vec![(42, 'z')];
+        let input = [vec![(1, 'a'), (4, 'b'), (9, 'c')], vec![], vec![(2, 'b'), (3, 'd'), (10, 'a')]];
+        merge_sorted(&mut output, input.iter().map(|array| array.iter().copied()), |&(_, name)| name);
+        assert_eq!(output, vec![(1, 'a'), (2, 'b'), (3, 'd'), (9, 'c')]);
+    }
+}
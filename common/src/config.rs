@@ -0,0 +1,61 @@
+//! Engine constants, and the tuning values that the configuration files can override.
+//!
+//! The constants are shared by the client and the server: changing them breaks the network protocol or the saves.
+//! The tuning values only trade performance for latency or memory. The client reads its tuning from the settings file
+//! and the server from the config of the world, and the missing values keep their defaults.
+use serde::{Deserialize, Serialize};
+
+/// Maximum light level. Only the blocks directly exposed to the sky have it,
+/// and a light level of 15 can reach blocks up to 14 blocks away.
+pub const MAX_LIGHT_LEVEL: u8 = 15;
+/// Reach of the players in creative mode when the server config doesn't change it, in blocks
+pub const DEFAULT_REACH: f64 = 10.0;
+/// Maximum reach that the server config can set, in blocks
+pub const MAX_REACH: f64 = 64.0;
+/// Maximum cooldown that the server config can set, in seconds
+pub const MAX_COOLDOWN: f64 = 10.0;
+/// Maximum render distance that a player can ask for, in chunks
+pub const MAX_RENDER_DISTANCE: u64 = 64;
+/// Maximum length of the display names, longer names are truncated
+pub const MAX_DISPLAY_NAME_LENGTH: usize = 32;
+/// Players below this height die in the void and respawn
+pub const VOID_HEIGHT: f64 = -256.0;
+
+/// Tuning of the client
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClientTuning {
+    /// Number of chunk meshes that the chunk buffers can hold when they are created, they grow when needed
+    pub chunk_buffer_capacity: usize,
+    /// Maximum number of texture tiles whose high resolution mipmaps are uploaded every frame
+    pub texture_uploads_per_frame: usize,
+}
+
+impl Default for ClientTuning {
+    fn default() -> Self {
+        Self {
+            chunk_buffer_capacity: 1000,
+            texture_uploads_per_frame: 2,
+        }
+    }
+}
+
+/// Tuning of the server
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerTuning {
+    /// Maximum number of block updates processed every tick, the others wait for the next tick.
+    /// This keeps long chains of updates from freezing the server.
+    pub block_updates_per_tick: usize,
+    /// Maximum number of chunks sent to every player every tick
+    pub chunks_sent_per_tick: usize,
+}
+
+impl Default for ServerTuning {
+    fn default() -> Self {
+        Self {
+            block_updates_per_tick: 4096,
+            chunks_sent_per_tick: 20,
+        }
+    }
+}
@@ -1,37 +1,53 @@
 pub mod vox;
 
 use crate::{
-    block::{Block, BlockMesh, BlockType},
+    block::{Block, BlockId, BlockMesh, BlockType},
     registry::Registry,
 };
 
 use crate::data::vox::{load_voxel_model, VoxelModel};
+use crate::dimension::Dimension;
 use crate::item::{Item, ItemMesh, ItemType};
-use anyhow::{Context, Result};
+use crate::physics::config::PhysicsConfig;
+use crate::recipe::{Recipe, RecipeData};
+use crate::sound::SoundEvent;
+use crate::worldgen::structure::{Structure, StructureData};
+use anyhow::{anyhow, ensure, Context, Result};
 use image::{ImageBuffer, Rgba};
 use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
 use std::io::Read;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use texture_packer::{TexturePacker, TexturePackerConfig};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Data {
     pub blocks: Registry<Block>,
     pub meshes: Vec<BlockMesh>,
+    #[serde(with = "crate::network::serialization::image_buffer")]
     pub texture_atlas: ImageBuffer<Rgba<u8>, Vec<u8>>,
     pub models: Registry<VoxelModel>,
     pub items: Registry<Item>,
     pub item_meshes: Vec<ItemMesh>,
+    pub sounds: Registry<SoundEvent>,
+    pub structures: Registry<Structure>,
+    pub recipes: Registry<Recipe>,
+    pub dimensions: Registry<Dimension>,
+    /// Physics constants of the world. The data packs use the default constants, the server replaces them by the constants of the world.
+    pub physics: PhysicsConfig,
 }
 
-// TODO: decent error handling
-pub fn load_data(data_directory: PathBuf) -> Result<Data> {
+/// Load the data from the given directory.
+/// `block_palette` contains the block names of a previous run, indexed by id: these blocks keep the same ids
+/// so that the saved chunks stay valid. Blocks that don't exist anymore are replaced by air.
+/// The data is reloaded while the game runs, so the mistakes of the data packs are errors instead of panics.
+pub fn load_data(data_directory: PathBuf, block_palette: &[String]) -> Result<Data> {
     info!("Loading data from directory {}", data_directory.display());
 
     // Load textures
     let mut textures: Vec<PathBuf> = Vec::new();
-    let mut texture_registry: Registry<()> = Default::default();
     let textures_directory = data_directory.join("textures");
     info!(
         "Loading textures from directory {}",
@@ -44,21 +60,24 @@ pub fn load_data(data_directory: PathBuf) -> Result<Data> {
             .context("failed to get file type")?
             .is_file()
         {
-            let file_path = dir_entry.path();
-
-            texture_registry.register(
-                file_path
-                    .file_stem()
-                    .context("failed to get file stem")?
-                    .to_str()
-                    .unwrap()
-                    .to_owned(),
-                (),
-            )?;
-            textures.push(file_path);
+            textures.push(dir_entry.path());
         }
     }
+    // Don't depend on the order of the directory entries
+    textures.sort();
 
+    let mut texture_registry: Registry<()> = Default::default();
+    for file_path in textures.iter() {
+        texture_registry.register(
+            file_path
+                .file_stem()
+                .context("failed to get file stem")?
+                .to_str()
+                .context("the texture name isn't valid UTF-8")?
+                .to_owned(),
+            (),
+        )?;
+    }
     let (texture_atlas, texture_rects) = load_textures(textures)?;
 
     //Load model
@@ -90,18 +109,16 @@ pub fn load_data(data_directory: PathBuf) -> Result<Data> {
     };*/
 
     // TODO : load every .vox in the model folder
-    let model_tree = load_voxel_model(
-        data_directory.join("model/tree.vox").to_str().unwrap()
-    ).unwrap();
-    models.register("tree".to_owned(), model_tree)?;
-    let model_knight = load_voxel_model(
-        data_directory.join("model/chr_knight.vox").to_str().unwrap()
-    ).unwrap();
-    models.register("knight".to_owned(), model_knight)?;
+    for (name, file) in [("tree", "tree.vox"), ("knight", "chr_knight.vox")].iter() {
+        let path = data_directory.join("model").join(file);
+        let model = load_voxel_model(&path.to_string_lossy())
+            .context(format!("Failed to load model {}", path.display()))?;
+        models.register((*name).to_owned(), model)?;
+    }
 
     // Load items
     let items_directory = data_directory.join("items");
-    let item_datas: Vec<(String, ItemType)> = load_files_from_folder(items_directory);
+    let item_datas: Vec<(String, ItemType)> = load_files_from_folder(items_directory)?;
     let mut items = Registry::default();
     let mut item_meshes = Vec::new();
 
@@ -109,8 +126,8 @@ pub fn load_data(data_directory: PathBuf) -> Result<Data> {
     for (name, ty) in item_datas.into_iter() {
         match &ty {
             ItemType::NormalItem { texture } => {
-                let texture_rect =
-                    texture_rects[texture_registry.get_id_by_name(texture).unwrap() as usize];
+                let texture_rect = texture_rect(texture, &texture_registry, &texture_rects)
+                    .context(format!("Failed to load item {}", name))?;
                 let model = self::vox::item::generate_item_model(texture_rect, &texture_atlas);
                 let mesh_center = (
                     model.size_x as f32 / 2.0,
@@ -118,12 +135,8 @@ pub fn load_data(data_directory: PathBuf) -> Result<Data> {
                     model.size_z as f32 / 2.0,
                 );
                 let scale = 1.0 / usize::max(model.size_x, model.size_y) as f32;
-                let mesh_id = models
-                    .register(format!("item:{}", name), model)
-                    .expect("Failed to register item model");
-                items
-                    .register(name.clone(), Item { name, ty })
-                    .expect("Failed to register item");
+                let mesh_id = models.register(format!("item:{}", name), model)?;
+                items.register(name.clone(), Item { name, ty })?;
                 item_meshes.push(ItemMesh::SimpleMesh {
                     mesh_id,
                     scale,
@@ -135,7 +148,7 @@ pub fn load_data(data_directory: PathBuf) -> Result<Data> {
 
     // Load blocks
     let blocks_directory = data_directory.join("blocks");
-    let block_datas: Vec<(String, BlockType)> = load_files_from_folder(blocks_directory);
+    let mut block_datas: Vec<(String, BlockType)> = load_files_from_folder(blocks_directory)?;
 
     info!("Processing collected block and texture data");
     let mut blocks = Registry::default();
@@ -149,34 +162,106 @@ pub fn load_data(data_directory: PathBuf) -> Result<Data> {
                 block_type: BlockType::Air,
             },
         )
-        .expect("Couldn't register air in the registry.");
+        .context("Couldn't register air in the registry.")?;
     meshes.push(BlockMesh::Empty);
 
-    for (name, block_type) in block_datas.into_iter() {
+    // Register the blocks of the palette first to keep their ids
+    let mut ordered_block_datas = Vec::with_capacity(block_datas.len());
+    for name in block_palette.iter().skip(1) {
+        match block_datas.iter().position(|(block_name, _)| block_name == name) {
+            Some(index) => ordered_block_datas.push(block_datas.remove(index)),
+            None => {
+                log::warn!("Block {} doesn't exist anymore, replacing it by air", name);
+                ordered_block_datas.push((name.clone(), BlockType::Air));
+            }
+        }
+    }
+    ordered_block_datas.extend(block_datas);
+
+    for (name, block_type) in ordered_block_datas.into_iter() {
         let block = Block {
             name: name.clone(),
             block_type: block_type.clone(),
         };
-        blocks.register(name, block)?;
+        let face_textures = |names: &[String]| {
+            face_textures(names, &texture_registry, &texture_rects).context(format!("Failed to load block {}", name))
+        };
         let mesh = match block_type {
             BlockType::Air => BlockMesh::Empty,
-            // TODO: make sure there are exactly 6 face textures
             BlockType::NormalCube {
                 face_textures: names,
+                frame_time,
+                seasonal,
+                ..
+            } => {
+                let mut textures = face_textures(&names)?;
+                // Only the blocks that declare an animation or the seasons have textures with several frames
+                if frame_time.is_some() || seasonal {
+                    for texture in textures.iter_mut() {
+                        *texture = texture.split_frames();
+                    }
+                }
+                BlockMesh::FullCube {
+                    textures,
+                    frame_time: frame_time.unwrap_or(0.0),
+                    seasonal,
+                }
+            }
+            BlockType::Bed {
+                face_textures: names,
+                ..
+            }
+            | BlockType::ItemFrame {
+                face_textures: names,
+                ..
             } => BlockMesh::FullCube {
-                textures: [
-                    texture_rects[texture_registry.get_id_by_name(&names[0]).unwrap() as usize],
-                    texture_rects[texture_registry.get_id_by_name(&names[1]).unwrap() as usize],
-                    texture_rects[texture_registry.get_id_by_name(&names[2]).unwrap() as usize],
-                    texture_rects[texture_registry.get_id_by_name(&names[3]).unwrap() as usize],
-                    texture_rects[texture_registry.get_id_by_name(&names[4]).unwrap() as usize],
-                    texture_rects[texture_registry.get_id_by_name(&names[5]).unwrap() as usize],
-                ],
+                textures: face_textures(&names)?,
+                frame_time: 0.0,
+                seasonal: false,
             },
         };
+        blocks.register(name, block)?;
         meshes.push(mesh);
     }
 
+    // Load sounds
+    let sounds_directory = data_directory.join("sounds");
+    let mut sounds = Registry::default();
+    for (name, sound) in load_files_from_folder::<SoundEvent>(sounds_directory)? {
+        sounds.register(name, sound)?;
+    }
+
+    // Load structures
+    let structures_directory = data_directory.join("structures");
+    let mut structures = Registry::default();
+    for (name, structure) in load_files_from_folder::<StructureData>(structures_directory)? {
+        match load_structure(&data_directory, &blocks, &mut models, &structure) {
+            Some(structure) => {
+                structures.register(name, structure)?;
+            }
+            None => log::error!("Failed to load structure {}, skipping...", name),
+        }
+    }
+
+    // Load recipes
+    let recipes_directory = data_directory.join("recipes");
+    let mut recipes = Registry::default();
+    for (name, recipe) in load_files_from_folder::<RecipeData>(recipes_directory)? {
+        match Recipe::from_data(&recipe, &items, &blocks) {
+            Ok(recipe) => {
+                recipes.register(name, recipe)?;
+            }
+            Err(e) => log::error!("Failed to load recipe {} ({}), skipping...", name, e),
+        }
+    }
+
+    // Load dimensions
+    let dimensions_directory = data_directory.join("dimensions");
+    let mut dimensions = Registry::default();
+    for (name, dimension) in load_files_from_folder::<Dimension>(dimensions_directory)? {
+        dimensions.register(name, dimension)?;
+    }
+
     info!("Data successfully loaded");
     Ok(Data {
         blocks,
@@ -185,25 +270,75 @@ pub fn load_data(data_directory: PathBuf) -> Result<Data> {
         models,
         items,
         item_meshes,
+        sounds,
+        structures,
+        recipes,
+        dimensions,
+        physics: PhysicsConfig::default(),
     })
 }
 
-#[derive(Clone, Copy, Debug, Default, PartialEq)]
+/// The position of the texture `name` in the atlas
+fn texture_rect(name: &str, texture_registry: &Registry<()>, texture_rects: &[TextureRect]) -> Result<TextureRect> {
+    let id = texture_registry
+        .get_id_by_name(&name.to_owned())
+        .ok_or_else(|| anyhow!("Unknown texture {}", name))?;
+    Ok(texture_rects[id as usize])
+}
+
+/// The positions of the 6 face textures of a block in the atlas
+fn face_textures(
+    names: &[String],
+    texture_registry: &Registry<()>,
+    texture_rects: &[TextureRect],
+) -> Result<[TextureRect; 6]> {
+    ensure!(names.len() == 6, "A block needs 6 face textures, not {}", names.len());
+    let mut textures = [TextureRect::default(); 6];
+    for (texture, name) in textures.iter_mut().zip(names.iter()) {
+        *texture = texture_rect(name, texture_registry, texture_rects)?;
+    }
+    Ok(textures)
+}
+
+/// The position of a texture in the atlas. The size is the size of a single frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct TextureRect {
     pub x: f32,
     pub y: f32,
     pub width: f32,
     pub height: f32,
+    /// Number of animation frames, stacked vertically below the first one
+    pub frames: u32,
+}
+
+impl TextureRect {
+    /// Split a texture that is a vertical strip of square frames into its frames.
+    /// The textures whose height isn't a multiple of their width are a single frame.
+    pub fn split_frames(self) -> Self {
+        let frames = (self.height / self.width).round();
+        if self.height > self.width && frames * self.width == self.height {
+            Self {
+                height: self.width,
+                frames: frames as u32,
+                ..self
+            }
+        } else {
+            self
+        }
+    }
 }
 
 pub const MAX_TEXTURE_SIZE: u32 = 2048;
+/// Number of pixels around every texture that are filled with the edge of the texture.
+/// This prevents the first mipmap levels from bleeding into the neighbouring textures.
+pub const TEXTURE_PADDING: u32 = 8;
 
 const TEXTURE_PACKER_CONFIG: TexturePackerConfig = TexturePackerConfig {
     max_width: MAX_TEXTURE_SIZE,
     max_height: MAX_TEXTURE_SIZE,
     allow_rotation: false,
-    border_padding: 0,
-    texture_padding: 0,
+    border_padding: TEXTURE_PADDING,
+    texture_padding: 2 * TEXTURE_PADDING,
     trim: false,
     texture_outlines: false,
 };
@@ -217,54 +352,112 @@ fn load_textures(
 
     let mut packer = TexturePacker::new_skyline(TEXTURE_PACKER_CONFIG);
     for (i, path) in textures.iter().enumerate() {
-        packer.pack_own(
-            format!("{}", i),
-            ImageImporter::import_from_file(path).expect("Failed to read texture to pack"),
-        ).expect("Failed to pack textures");
+        let texture = ImageImporter::import_from_file(path)
+            .map_err(|e| anyhow!("Failed to read texture {} ({})", path.display(), e))?;
+        packer
+            .pack_own(format!("{}", i), texture)
+            .map_err(|e| anyhow!("Failed to pack texture {} ({:?})", path.display(), e))?;
     }
 
     let mut texture_buffer: ImageBuffer<Rgba<u8>, Vec<u8>> =
         ImageBuffer::new(MAX_TEXTURE_SIZE, MAX_TEXTURE_SIZE);
-    texture_buffer.copy_from(
-        &ImageExporter::export(&packer).expect("Failed to export texture from packer"),
-        0,
-        0,
-    ).expect("Failed to copy texture atlas to buffer");
+    let atlas = ImageExporter::export(&packer).map_err(|e| anyhow!("Failed to export texture from packer ({})", e))?;
+    texture_buffer.copy_from(&atlas, 0, 0).context("Failed to copy texture atlas to buffer")?;
+    let frames: Vec<_> = (0..textures.len())
+        .map(|i| {
+            packer
+                .get_frame(&format!("{}", i))
+                .map(|frame| frame.frame)
+                .context("Texture packer frame key doesn't exist")
+        })
+        .collect::<Result<_>>()?;
+    for frame in frames.iter() {
+        extrude_texture(&mut texture_buffer, frame.x, frame.y, frame.w, frame.h);
+    }
     texture_buffer
         .save("atlas.png")
-        .expect("Failed to save texture atlas");
+        .context("Failed to save texture atlas")?;
     Ok((
         texture_buffer,
-        (0..textures.len())
-            .map(|i| {
-                let frame = packer
-                    .get_frame(&format!("{}", i))
-                    .expect("Texture packer frame key doesn't exist")
-                    .frame;
-                TextureRect {
-                    x: frame.x as f32 / MAX_TEXTURE_SIZE as f32,
-                    y: frame.y as f32 / MAX_TEXTURE_SIZE as f32,
-                    width: frame.w as f32 / MAX_TEXTURE_SIZE as f32,
-                    height: frame.h as f32 / MAX_TEXTURE_SIZE as f32,
-                }
+        frames
+            .into_iter()
+            .map(|frame| TextureRect {
+                x: frame.x as f32 / MAX_TEXTURE_SIZE as f32,
+                y: frame.y as f32 / MAX_TEXTURE_SIZE as f32,
+                width: frame.w as f32 / MAX_TEXTURE_SIZE as f32,
+                height: frame.h as f32 / MAX_TEXTURE_SIZE as f32,
+                frames: 1,
             })
             .collect(),
     ))
 }
 
+/// Copy the edge pixels of a texture into the padding around it
+fn extrude_texture(atlas: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, x: u32, y: u32, w: u32, h: u32) {
+    let min_x = x.saturating_sub(TEXTURE_PADDING);
+    let min_y = y.saturating_sub(TEXTURE_PADDING);
+    let max_x = (x + w + TEXTURE_PADDING).min(atlas.width());
+    let max_y = (y + h + TEXTURE_PADDING).min(atlas.height());
+    for py in min_y..max_y {
+        for px in min_x..max_x {
+            let sx = px.max(x).min(x + w - 1);
+            let sy = py.max(y).min(y + h - 1);
+            if (sx, sy) != (px, py) {
+                let pixel = *atlas.get_pixel(sx, sy);
+                atlas.put_pixel(px, py, pixel);
+            }
+        }
+    }
+}
+
+/// Load the model of a structure if it's not loaded yet, and resolve its block names
+fn load_structure(
+    data_directory: &Path,
+    blocks: &Registry<Block>,
+    models: &mut Registry<VoxelModel>,
+    structure: &StructureData,
+) -> Option<Structure> {
+    let model = match models.get_id_by_name(&structure.model) {
+        Some(id) => models.get_value_by_id(id).unwrap().clone(),
+        None => {
+            let path = data_directory.join("model").join(format!("{}.vox", structure.model));
+            let model = load_voxel_model(&path.to_string_lossy())?;
+            models.register(structure.model.clone(), model.clone()).ok()?;
+            model
+        }
+    };
+    let mut structure_blocks = HashMap::new();
+    for (color, block) in structure.blocks.iter() {
+        structure_blocks.insert(*color, blocks.get_id_by_name(block)? as BlockId);
+    }
+    let start_blocks = structure
+        .start_blocks
+        .iter()
+        .map(|block| blocks.get_id_by_name(block).map(|id| id as BlockId))
+        .collect::<Option<Vec<_>>>()?;
+    Some(Structure {
+        model,
+        blocks: structure_blocks,
+        tries: structure.tries,
+        start_blocks,
+    })
+}
+
 /// Load all <name>.ron files from a given folder and parse them into type `T`.
-fn load_files_from_folder<T: serde::de::DeserializeOwned>(directory: PathBuf) -> Vec<(String, T)> {
+/// The files that can't be parsed are skipped.
+fn load_files_from_folder<T: serde::de::DeserializeOwned>(directory: PathBuf) -> Result<Vec<(String, T)>> {
     let mut result = Vec::new();
     info!(
         "Loading objects of type {} from directory {}",
         std::any::type_name::<T>(),
         directory.display(),
     );
-    for dir_entry in fs::read_dir(directory).expect("Failed to read from directory") {
-        let dir_entry = dir_entry.expect("Failed to read directory entry");
+    let read_dir = fs::read_dir(&directory).context(format!("Failed to read directory {}", directory.display()))?;
+    for dir_entry in read_dir {
+        let dir_entry = dir_entry.context("Failed to read directory entry")?;
         if dir_entry
             .file_type()
-            .expect("Failed to get file type")
+            .context("Failed to get file type")?
             .is_file()
         {
             let file_path = dir_entry.path();
@@ -277,16 +470,15 @@ fn load_files_from_folder<T: serde::de::DeserializeOwned>(directory: PathBuf) ->
                 Some(ext) => {
                     if ext == "ron" {
                         log::info!("Attempting to read file {}", file_path.display());
-                        let mut file =
-                            fs::File::open(file_path.clone()).expect("Failed to open file");
+                        let mut file = fs::File::open(&file_path)
+                            .context(format!("Failed to open file {}", file_path.display()))?;
                         let mut buffer = String::new();
                         file.read_to_string(&mut buffer)
-                            .expect("Failed to read from file");
+                            .context(format!("Failed to read from file {}", file_path.display()))?;
                         let file_stem = file_path
                             .file_stem()
-                            .expect("Failed to get file stem")
-                            .to_str()
-                            .unwrap()
+                            .and_then(|stem| stem.to_str())
+                            .context(format!("Invalid file name {}", file_path.display()))?
                             .to_owned();
 
                         let parsed_file = {
@@ -311,5 +503,40 @@ fn load_files_from_folder<T: serde::de::DeserializeOwned>(directory: PathBuf) ->
             }
         }
     }
-    result
+    // Don't depend on the order of the directory entries
+    result.sort_by(|(name1, _), (name2, _)| name1.cmp(name2));
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_face_textures() {
+        let mut texture_registry = Registry::default();
+        texture_registry.register("stone".to_owned(), ()).unwrap();
+        let texture_rects = [TextureRect::default()];
+        let names = |names: &[&str]| names.iter().map(|&name| name.to_owned()).collect::<Vec<_>>();
+
+        assert!(face_textures(&names(&["stone"; 6]), &texture_registry, &texture_rects).is_ok());
+        assert!(face_textures(&names(&["stone"; 5]), &texture_registry, &texture_rects).is_err());
+        let unknown = names(&["stone", "stone", "stone", "stone", "stone", "dirt"]);
+        assert!(face_textures(&unknown, &texture_registry, &texture_rects).is_err());
+    }
+
+    #[test]
+    fn test_split_frames() {
+        let rect = |height: u32| TextureRect {
+            x: 0.0,
+            y: 0.0,
+            width: 16.0 / MAX_TEXTURE_SIZE as f32,
+            height: height as f32 / MAX_TEXTURE_SIZE as f32,
+            frames: 1,
+        };
+        let strip = rect(64).split_frames();
+        assert_eq!((strip.height, strip.frames), (rect(16).height, 4));
+        assert_eq!(rect(16).split_frames(), rect(16));
+        assert_eq!(rect(40).split_frames(), rect(40));
+    }
 }
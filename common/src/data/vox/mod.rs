@@ -1,3 +1,4 @@
+use serde::{Deserialize, Serialize};
 use std::fs::File;
 use std::io::Read;
 use std::str::from_utf8;
@@ -39,7 +40,7 @@ const DEFAULT_PALETTE: [u32; 256] = [
     0xffbbbbbb, 0xffaaaaaa, 0xff888888, 0xff777777, 0xff555555, 0xff444444, 0xff222222, 0xff111111,
 ];
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VoxelModel {
     pub size_x: usize,
     pub size_y: usize,
@@ -168,6 +169,75 @@ pub fn load_voxel_model(path: &str) -> Option<VoxelModel> {
     return None;
 }
 
+/// Encode the full voxels of a model in the .vox format of MagicaVoxel.
+/// The colors of the model become the palette, so the model can't have more than 255 different colors
+/// and its size can't exceed 256 along any axis.
+pub fn write_voxel_model(model: &VoxelModel) -> Result<Vec<u8>, String> {
+    if model.size_x > 256 || model.size_y > 256 || model.size_z > 256 {
+        return Err(format!(
+            "Model of size {}x{}x{} is too large for a .vox file",
+            model.size_x, model.size_y, model.size_z
+        ));
+    }
+    let mut palette: Vec<u32> = Vec::new();
+    let mut voxels = Vec::new();
+    for x in 0..model.size_x {
+        for y in 0..model.size_y {
+            for z in 0..model.size_z {
+                let s = x * model.size_z * model.size_y + y * model.size_z + z;
+                if !model.full[s] {
+                    continue;
+                }
+                let color = model.voxels[s] & 0x00ffffff;
+                let index = match palette.iter().position(|&c| c == color) {
+                    Some(index) => index,
+                    None => {
+                        if palette.len() == 255 {
+                            return Err("Model has more than 255 colors".to_owned());
+                        }
+                        palette.push(color);
+                        palette.len() - 1
+                    }
+                };
+                // The y axis of the model is the z axis of the file, and the color index 0 is empty
+                voxels.extend_from_slice(&[x as u8, z as u8, y as u8, index as u8 + 1]);
+            }
+        }
+    }
+
+    let mut chunks = Vec::new();
+    let mut size = Vec::with_capacity(12);
+    for &s in [model.size_x, model.size_z, model.size_y].iter() {
+        size.extend_from_slice(&(s as u32).to_le_bytes());
+    }
+    write_chunk(&mut chunks, b"SIZE", &size);
+    let mut xyzi = ((voxels.len() / 4) as u32).to_le_bytes().to_vec();
+    xyzi.extend_from_slice(&voxels);
+    write_chunk(&mut chunks, b"XYZI", &xyzi);
+    // Color index `i` is stored at position `i - 1` of the palette, with an opaque alpha
+    let mut rgba = Vec::with_capacity(4 * 256);
+    for i in 0..256 {
+        rgba.extend_from_slice(&(palette.get(i).copied().unwrap_or(0) | 0xff000000).to_le_bytes());
+    }
+    write_chunk(&mut chunks, b"RGBA", &rgba);
+
+    let mut buffer = b"VOX ".to_vec();
+    buffer.extend_from_slice(&150u32.to_le_bytes());
+    buffer.extend_from_slice(b"MAIN");
+    buffer.extend_from_slice(&0u32.to_le_bytes());
+    buffer.extend_from_slice(&(chunks.len() as u32).to_le_bytes());
+    buffer.extend_from_slice(&chunks);
+    Ok(buffer)
+}
+
+/// Append a chunk without children to `buffer`
+fn write_chunk(buffer: &mut Vec<u8>, id: &[u8; 4], content: &[u8]) {
+    buffer.extend_from_slice(id);
+    buffer.extend_from_slice(&(content.len() as u32).to_le_bytes());
+    buffer.extend_from_slice(&0u32.to_le_bytes());
+    buffer.extend_from_slice(content);
+}
+
 fn four_bytes_to_u32(bytes: &[u8], big_endian: bool) -> u32 {
     if big_endian {
         return ((bytes[0] as u32) << 24)
@@ -189,3 +259,41 @@ fn str_from_byte(bytes: &[u8]) -> &str {
         _ => "",
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_voxel_model() {
+        // A 2x3x1 model with two voxels of the same color and one of another color
+        let model = VoxelModel {
+            size_x: 2,
+            size_y: 3,
+            size_z: 1,
+            voxels: vec![0x00112233, 0, 0, 0x00445566, 0, 0x00112233],
+            full: vec![true, false, false, true, false, true],
+        };
+        let buffer = write_voxel_model(&model).unwrap();
+        assert_eq!(&buffer[0..4], b"VOX ");
+        assert_eq!(&buffer[8..12], b"MAIN");
+        assert_eq!(four_bytes_to_u32(&buffer[16..20], false) as usize, buffer.len() - 20);
+        // The y and z axes are swapped in the file
+        let size = &buffer[20..44];
+        assert_eq!(&size[0..4], b"SIZE");
+        assert_eq!(four_bytes_to_u32(&size[12..16], false), 2);
+        assert_eq!(four_bytes_to_u32(&size[16..20], false), 1);
+        assert_eq!(four_bytes_to_u32(&size[20..24], false), 3);
+        let xyzi = &buffer[44..];
+        assert_eq!(&xyzi[0..4], b"XYZI");
+        assert_eq!(four_bytes_to_u32(&xyzi[12..16], false), 3);
+        assert_eq!(&xyzi[16..28], &[0, 0, 0, 1, 1, 0, 0, 2, 1, 0, 2, 1]);
+        let rgba = &xyzi[28..];
+        assert_eq!(&rgba[0..4], b"RGBA");
+        assert_eq!(four_bytes_to_u32(&rgba[12..16], false), 0xff112233);
+        assert_eq!(four_bytes_to_u32(&rgba[16..20], false), 0xff445566);
+
+        let too_large = VoxelModel { size_x: 257, size_y: 1, size_z: 1, voxels: vec![0; 257], full: vec![false; 257] };
+        assert!(write_voxel_model(&too_large).is_err());
+    }
+}
@@ -1,6 +1,11 @@
 use crossbeam_channel::{unbounded, Receiver, Sender};
 use lazy_static::lazy_static;
-use std::{collections::BTreeMap, sync::Arc, sync::RwLock};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+    sync::RwLock,
+    time::{Duration, Instant},
+};
 lazy_static! {
     static ref DEBUG_INFO: Arc<RwLock<Option<Sender<DebugInfoUnit>>>> = Arc::new(RwLock::new(None));
 }
@@ -16,7 +21,67 @@ struct DebugInfoUnit {
 pub enum DebugInfoPart {
     Message(String),
     WorkerPerf(WorkerPerf),
-    PerfBreakdown(String, Vec<(String, f64)>)
+    PerfBreakdown(String, Vec<(String, f64)>),
+    PerfGraph(PerfGraph),
+}
+
+/// Duration of the history of the perf graphs
+pub const PERF_GRAPH_DURATION: Duration = Duration::from_secs(10);
+/// The samples received during the same interval are averaged
+const PERF_GRAPH_RESOLUTION: Duration = Duration::from_millis(50);
+
+/// History of the duration of every part of a loop
+#[derive(Debug, Clone)]
+pub struct PerfGraph {
+    pub name: String,
+    pub part_names: Vec<String>,
+    /// Start of every interval, with the average duration of every part in milliseconds
+    pub samples: VecDeque<(Instant, Vec<f64>)>,
+    /// Number of samples averaged in the last interval
+    last_sample_count: u32,
+}
+
+impl PerfGraph {
+    fn new(name: String, parts: Vec<(String, f64)>) -> Self {
+        let (part_names, millis) = parts.into_iter().unzip();
+        Self {
+            name,
+            part_names,
+            samples: vec![(Instant::now(), millis)].into(),
+            last_sample_count: 1,
+        }
+    }
+
+    /// Add the samples of `other` to the history
+    fn merge(&mut self, other: PerfGraph) {
+        if other.part_names != self.part_names {
+            *self = other;
+            return;
+        }
+        for (time, millis) in other.samples.into_iter() {
+            match self.samples.back_mut() {
+                Some((last_time, last_millis)) if time - *last_time < PERF_GRAPH_RESOLUTION => {
+                    // Running average
+                    self.last_sample_count += 1;
+                    let n = self.last_sample_count as f64;
+                    for (last, new) in last_millis.iter_mut().zip(millis.iter()) {
+                        *last += (new - *last) / n;
+                    }
+                }
+                _ => {
+                    self.samples.push_back((time, millis));
+                    self.last_sample_count = 1;
+                }
+            }
+        }
+        while let Some((time, _)) = self.samples.front() {
+            if time.elapsed() > PERF_GRAPH_DURATION {
+                self.samples.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
 }
 
 /// Helper struct allowing multiple threads to easily show debug info.
@@ -49,7 +114,15 @@ impl DebugInfo {
                     *next_id += 1;
                     (false, *next_id - 1, BTreeMap::new())
                 });
-            inner_map.insert(diu.id, diu.part);
+            match (inner_map.get_mut(&diu.id), diu.part) {
+                // Keep the history of the graphs
+                (Some(DebugInfoPart::PerfGraph(graph)), DebugInfoPart::PerfGraph(new_graph)) => {
+                    graph.merge(new_graph)
+                }
+                (_, part) => {
+                    inner_map.insert(diu.id, part);
+                }
+            }
         }
         &mut self.sections
     }
@@ -107,4 +180,16 @@ pub fn send_perf_breakdown(section: impl ToString, id: impl ToString, name: impl
             })
             .unwrap()
     });
-}
\ No newline at end of file
+}
+/// Send the duration in milliseconds of every part of the last iteration of a loop, to be shown in a graph
+pub fn send_perf_sample(section: impl ToString, id: impl ToString, name: impl ToString, parts: Vec<(String, f64)>) {
+    if let Some(sender) = DEBUG_INFO.read().unwrap().as_ref() {
+        sender
+            .send(DebugInfoUnit {
+                section: section.to_string(),
+                id: id.to_string(),
+                part: DebugInfoPart::PerfGraph(PerfGraph::new(name.to_string(), parts)),
+            })
+            .unwrap()
+    }
+}
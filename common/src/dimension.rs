@@ -0,0 +1,41 @@
+//! Dimensions: the sky, the lighting and the fog of a world, defined in the data packs.
+//!
+//! The server uses the sunlight of the dimension to light the chunks, and the client uses everything else to render.
+use serde::{Deserialize, Serialize};
+
+/// Name of the dimension used by the worlds that don't choose one
+pub const DEFAULT_DIMENSION: &str = "overworld";
+
+/// Distance fog of a dimension
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FogProfile {
+    pub color: [f32; 3],
+    /// Density of the exponential fog, per block. 0 disables the fog.
+    pub density: f32,
+}
+
+/// The sky and lighting profile of a dimension
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Dimension {
+    /// Color of the sky above the horizon
+    pub sky_color: [f32; 3],
+    /// `false` if the dimension has no sun, for example a cave dimension. The sky light is 0 everywhere.
+    pub sunlight: bool,
+    /// Minimum light level of the blocks when they are rendered, between 0 and 15
+    pub ambient_light: u8,
+    pub fog: FogProfile,
+}
+
+impl Default for Dimension {
+    fn default() -> Self {
+        Self {
+            sky_color: [0.2, 0.4, 0.8],
+            sunlight: true,
+            ambient_light: 0,
+            fog: FogProfile {
+                color: [0.7, 0.8, 1.0],
+                density: 0.0,
+            },
+        }
+    }
+}
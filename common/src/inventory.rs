@@ -0,0 +1,372 @@
+use crate::{block::BlockId, item::ItemId};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// Number of slots in the inventory of a player
+pub const PLAYER_INVENTORY_SIZE: usize = 36;
+/// Number of slots in a row of the inventory of a player.
+/// Shift-clicking a slot moves its stack between the first row and the other rows.
+pub const PLAYER_INVENTORY_ROW_SIZE: usize = 9;
+/// Maximum number of items in a single slot
+pub const MAX_STACK_SIZE: u32 = 64;
+
+/// Something that can be stored in an inventory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum InventoryItem {
+    Item(ItemId),
+    Block(BlockId),
+}
+
+/// Some number of the same item, stored in an inventory slot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ItemStack {
+    pub item: InventoryItem,
+    pub count: u32,
+}
+
+/// A fixed number of slots that can each contain an item stack
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Inventory {
+    slots: Vec<Option<ItemStack>>,
+}
+
+impl Inventory {
+    /// Create an empty inventory
+    pub fn new(size: usize) -> Self {
+        Self {
+            slots: vec![None; size],
+        }
+    }
+
+    pub fn slots(&self) -> &[Option<ItemStack>] {
+        &self.slots
+    }
+
+    /// Replace the content of a slot
+    pub fn set_slot(&mut self, slot: usize, stack: Option<ItemStack>) {
+        self.slots[slot] = stack;
+    }
+
+    /// Exchange the content of two slots
+    pub fn swap_slots(&mut self, a: usize, b: usize) {
+        self.slots.swap(a, b);
+    }
+
+    /// Add `count` items, filling the existing stacks first and then the empty slots.
+    /// Return the number of items that didn't fit.
+    pub fn add(&mut self, item: InventoryItem, count: u32) -> u32 {
+        self.add_to_slots(item, count, 0..self.slots.len())
+    }
+
+    /// Like `add`, but only in the slots of the range
+    fn add_to_slots(&mut self, item: InventoryItem, mut count: u32, slots: Range<usize>) -> u32 {
+        let slots = &mut self.slots[slots];
+        for stack in slots.iter_mut().flatten() {
+            if stack.item == item {
+                let added = count.min(MAX_STACK_SIZE - stack.count);
+                stack.count += added;
+                count -= added;
+            }
+        }
+        for slot in slots.iter_mut() {
+            if count == 0 {
+                break;
+            }
+            if slot.is_none() {
+                let added = count.min(MAX_STACK_SIZE);
+                *slot = Some(ItemStack { item, count: added });
+                count -= added;
+            }
+        }
+        count
+    }
+
+    /// Total number of `item` in the inventory
+    pub fn count(&self, item: InventoryItem) -> u32 {
+        self.slots
+            .iter()
+            .flatten()
+            .filter(|stack| stack.item == item)
+            .map(|stack| stack.count)
+            .sum()
+    }
+
+    /// Remove up to `count` items, emptying the last stacks first.
+    /// Return the number of items that were missing.
+    pub fn remove(&mut self, item: InventoryItem, mut count: u32) -> u32 {
+        for slot in self.slots.iter_mut().rev() {
+            if count == 0 {
+                break;
+            }
+            if let Some(stack) = slot {
+                if stack.item == item {
+                    let removed = count.min(stack.count);
+                    stack.count -= removed;
+                    count -= removed;
+                    if stack.count == 0 {
+                        *slot = None;
+                    }
+                }
+            }
+        }
+        count
+    }
+
+    /// Remove every item
+    pub fn clear(&mut self) {
+        for slot in self.slots.iter_mut() {
+            *slot = None;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.iter().all(Option::is_none)
+    }
+
+    /// Remove `count` items from the stack of a slot, emptying the slot if no item remains
+    fn remove_from_slot(&mut self, slot: usize, count: u32) {
+        if let Some(stack) = &mut self.slots[slot] {
+            stack.count -= count.min(stack.count);
+            if stack.count == 0 {
+                self.slots[slot] = None;
+            }
+        }
+    }
+}
+
+/// An action of the player on the slots of its inventory.
+/// The client applies it right away to show its result, and the server applies it again to the real inventory,
+/// refusing it if it's not valid anymore.
+// TODO: add the container of the slots once there are containers other than the inventory of the player
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SlotAction {
+    /// Drop the stack of `from` on `to`: it's merged into the stack of the same item, or the two stacks are swapped
+    Click { from: usize, to: usize },
+    /// Move the stack of a slot between the first row and the other rows, filling the existing stacks first.
+    /// The items that don't fit stay in the slot.
+    ShiftClick(usize),
+    /// Split the stack of `from` evenly among the slots of `to`, which must be empty or contain the same item.
+    /// The items that don't fit stay in `from`.
+    DragSplit { from: usize, to: Vec<usize> },
+}
+
+impl SlotAction {
+    /// Apply the action to the inventory, or return why it's not valid. The inventory doesn't change if it's not valid.
+    pub fn apply(&self, inventory: &mut Inventory) -> Result<(), String> {
+        let size = inventory.slots.len();
+        let check_slot = |slot: usize| {
+            if slot < size {
+                Ok(())
+            } else {
+                Err("Invalid inventory slot".to_owned())
+            }
+        };
+        let stack_at = |inventory: &Inventory, slot: usize| match inventory.slots[slot] {
+            Some(stack) => Ok(stack),
+            None => Err("The slot is empty".to_owned()),
+        };
+        match self {
+            &SlotAction::Click { from, to } => {
+                check_slot(from)?;
+                check_slot(to)?;
+                let stack = stack_at(inventory, from)?;
+                if from == to {
+                    return Err("The stack was dropped on its own slot".to_owned());
+                }
+                match &mut inventory.slots[to] {
+                    Some(target) if target.item == stack.item => {
+                        let moved = stack.count.min(MAX_STACK_SIZE.saturating_sub(target.count));
+                        target.count += moved;
+                        inventory.remove_from_slot(from, moved);
+                    }
+                    _ => inventory.swap_slots(from, to),
+                }
+            }
+            &SlotAction::ShiftClick(slot) => {
+                check_slot(slot)?;
+                let stack = stack_at(inventory, slot)?;
+                let row = PLAYER_INVENTORY_ROW_SIZE.min(size);
+                let targets = if slot < row { row..size } else { 0..row };
+                let mut moved_inventory = inventory.clone();
+                moved_inventory.slots[slot] = None;
+                let remaining = moved_inventory.add_to_slots(stack.item, stack.count, targets);
+                if remaining == stack.count {
+                    return Err("There is no room for the stack".to_owned());
+                }
+                if remaining > 0 {
+                    moved_inventory.slots[slot] = Some(ItemStack { count: remaining, ..stack });
+                }
+                *inventory = moved_inventory;
+            }
+            SlotAction::DragSplit { from, to } => {
+                let from = *from;
+                check_slot(from)?;
+                let stack = stack_at(inventory, from)?;
+                let mut targets = Vec::with_capacity(to.len());
+                for &slot in to.iter() {
+                    check_slot(slot)?;
+                    if slot == from || targets.contains(&slot) {
+                        return Err("The stack can't be split twice in the same slot".to_owned());
+                    }
+                    if matches!(inventory.slots[slot], Some(target) if target.item != stack.item) {
+                        return Err("The stack can only be split in empty slots or in the same item".to_owned());
+                    }
+                    targets.push(slot);
+                }
+                let per_slot = stack.count / targets.len().max(1) as u32;
+                if per_slot == 0 {
+                    return Err("There are not enough items to split the stack".to_owned());
+                }
+                let mut moved = 0;
+                for slot in targets {
+                    let target = inventory.slots[slot].get_or_insert(ItemStack { count: 0, ..stack });
+                    let added = per_slot.min(MAX_STACK_SIZE.saturating_sub(target.count));
+                    target.count += added;
+                    moved += added;
+                }
+                inventory.remove_from_slot(from, moved);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The inventory of the player as the client shows it: the last inventory sent by the server,
+/// with the slot actions that the server didn't process yet applied over it.
+#[derive(Debug, Clone)]
+pub struct PredictedInventory {
+    /// The actions sent to the server that it didn't process yet, with their id
+    pending: Vec<(u32, SlotAction)>,
+    predicted: Inventory,
+    next_id: u32,
+}
+
+impl PredictedInventory {
+    pub fn new(size: usize) -> Self {
+        Self {
+            pending: Vec::new(),
+            predicted: Inventory::new(size),
+            next_id: 1,
+        }
+    }
+
+    /// The inventory with the result of the pending actions
+    pub fn get(&self) -> &Inventory {
+        &self.predicted
+    }
+
+    /// Apply an action right away, returning the id to send it to the server with,
+    /// or why it's not valid. The invalid actions must not be sent.
+    pub fn act(&mut self, action: SlotAction) -> Result<u32, String> {
+        action.apply(&mut self.predicted)?;
+        let id = self.next_id;
+        self.next_id += 1;
+        self.pending.push((id, action));
+        Ok(id)
+    }
+
+    /// Set the inventory sent by the server after it processed the actions up to `last_action`.
+    /// The other actions are applied again, and the actions that the server refused are rolled back.
+    pub fn set_confirmed(&mut self, inventory: Inventory, last_action: u32) {
+        self.pending.retain(|(id, _)| *id > last_action);
+        self.predicted = inventory;
+        for (_, action) in self.pending.iter() {
+            // The action will be refused by the server if it's not valid anymore
+            let _ = action.apply(&mut self.predicted);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_fills_stacks_first() {
+        let mut inventory = Inventory::new(3);
+        let stone = InventoryItem::Block(1);
+        let ingot = InventoryItem::Item(0);
+        assert_eq!(inventory.add(stone, 10), 0);
+        assert_eq!(inventory.add(ingot, 1), 0);
+        assert_eq!(inventory.add(stone, 60), 0);
+        assert_eq!(inventory.slots()[0], Some(ItemStack { item: stone, count: 64 }));
+        assert_eq!(inventory.slots()[1], Some(ItemStack { item: ingot, count: 1 }));
+        assert_eq!(inventory.slots()[2], Some(ItemStack { item: stone, count: 6 }));
+
+        // The items that don't fit are returned
+        assert_eq!(inventory.add(stone, 100), 42);
+        assert_eq!(inventory.add(InventoryItem::Block(2), 1), 1);
+        assert_eq!(inventory.count(stone), 128);
+
+        inventory.clear();
+        assert!(inventory.is_empty());
+    }
+
+    fn stack(item: InventoryItem, count: u32) -> Option<ItemStack> {
+        Some(ItemStack { item, count })
+    }
+
+    #[test]
+    fn test_slot_actions() {
+        let stone = InventoryItem::Block(1);
+        let ingot = InventoryItem::Item(0);
+        let mut inventory = Inventory::new(PLAYER_INVENTORY_SIZE);
+        inventory.set_slot(0, stack(stone, 40));
+        inventory.set_slot(1, stack(ingot, 3));
+        inventory.set_slot(2, stack(stone, 50));
+
+        // Clicking merges the stacks of the same item, and swaps the other stacks
+        SlotAction::Click { from: 0, to: 2 }.apply(&mut inventory).unwrap();
+        assert_eq!(&inventory.slots()[..3], &[stack(stone, 26), stack(ingot, 3), stack(stone, 64)]);
+        SlotAction::Click { from: 0, to: 1 }.apply(&mut inventory).unwrap();
+        assert_eq!(&inventory.slots()[..2], &[stack(ingot, 3), stack(stone, 26)]);
+
+        // Shift-clicking moves the stack to the other rows
+        SlotAction::ShiftClick(1).apply(&mut inventory).unwrap();
+        assert_eq!(inventory.slots()[1], None);
+        assert_eq!(inventory.slots()[PLAYER_INVENTORY_ROW_SIZE], stack(stone, 26));
+        SlotAction::ShiftClick(PLAYER_INVENTORY_ROW_SIZE).apply(&mut inventory).unwrap();
+        assert_eq!(inventory.slots()[1], stack(stone, 26));
+
+        // Drag-splitting spreads the stack evenly, the remainder stays in the dragged slot
+        SlotAction::DragSplit { from: 1, to: vec![3, 4, 5] }.apply(&mut inventory).unwrap();
+        assert_eq!(&inventory.slots()[1..3], &[stack(stone, 2), stack(stone, 64)]);
+        assert_eq!(&inventory.slots()[3..6], &[stack(stone, 8); 3]);
+        assert_eq!(inventory.count(stone), 90);
+
+        // The invalid actions don't change the inventory
+        let before = inventory.clone();
+        assert!(SlotAction::Click { from: 6, to: 0 }.apply(&mut inventory).is_err());
+        assert!(SlotAction::Click { from: 0, to: PLAYER_INVENTORY_SIZE }.apply(&mut inventory).is_err());
+        assert!(SlotAction::DragSplit { from: 3, to: vec![0] }.apply(&mut inventory).is_err());
+        assert!(SlotAction::DragSplit { from: 3, to: vec![6, 6] }.apply(&mut inventory).is_err());
+        assert!(SlotAction::DragSplit { from: 1, to: vec![6, 7, 8] }.apply(&mut inventory).is_err());
+        assert_eq!(inventory, before);
+    }
+
+    #[test]
+    fn test_predicted_inventory() {
+        let stone = InventoryItem::Block(1);
+        let mut server = Inventory::new(PLAYER_INVENTORY_SIZE);
+        server.set_slot(0, stack(stone, 10));
+        let mut client = PredictedInventory::new(PLAYER_INVENTORY_SIZE);
+        client.set_confirmed(server.clone(), 0);
+
+        // The actions are shown right away
+        let first = client.act(SlotAction::Click { from: 0, to: 1 }).unwrap();
+        let second = client.act(SlotAction::Click { from: 1, to: 2 }).unwrap();
+        assert_eq!(client.get().slots()[2], stack(stone, 10));
+        assert!(client.act(SlotAction::ShiftClick(0)).is_err());
+
+        // The server processed the first action, but the stack changed before the second one
+        SlotAction::Click { from: 0, to: 1 }.apply(&mut server).unwrap();
+        client.set_confirmed(server.clone(), first);
+        assert_eq!(client.get().slots()[2], stack(stone, 10));
+        server.set_slot(1, None);
+        assert!(SlotAction::Click { from: 1, to: 2 }.apply(&mut server).is_err());
+
+        // The refused action is rolled back
+        client.set_confirmed(server.clone(), second);
+        assert_eq!(client.get(), &server);
+    }
+}
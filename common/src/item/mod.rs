@@ -1,17 +1,17 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 pub type ItemId = u32;
 
 /// The type of an item. It contains the behavior and the texture of the item.
 /// This is the data provided by the creator of the item.
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename = "Item")]
 pub enum ItemType {
     NormalItem { texture: String },
 }
 
 /// The mesh of an item
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ItemMesh {
     /// Simply a mesh
     SimpleMesh {
@@ -25,7 +25,7 @@ pub enum ItemMesh {
 }
 
 /// A general item in-memory representation
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Item {
     pub name: String,
     pub ty: ItemType,
@@ -1,13 +1,20 @@
 pub mod block;
 pub mod collections;
+pub mod config;
 pub mod data;
 pub mod debug;
+pub mod dimension;
+pub mod inventory;
 pub mod item;
 pub mod network;
 pub mod physics;
 pub mod player;
+pub mod recipe;
 pub mod registry;
+pub mod season;
+pub mod sound;
 pub mod time;
+pub mod weather;
 pub mod worker;
 pub mod world;
 pub mod worldgen;
@@ -1,19 +1,33 @@
 use super::messages::{ToClient, ToServer};
 use crate::{
-    network::{ClientEvent, ServerEvent},
+    network::{ClientEvent, DisconnectReason, MessageDelivery, NetworkStats, ServerEvent},
     player::PlayerId,
 };
 use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
 
+/// A message to the client, or the reason why the server disconnected it
+type ToClientOrDisconnect = Result<ToClient, DisconnectReason>;
+
 pub struct DummyClient {
     first_queried: bool,
+    disconnected: bool,
     pub(self) to_server: Sender<ToServer>,
-    pub(self) to_client: Receiver<ToClient>,
+    pub(self) to_client: Receiver<ToClientOrDisconnect>,
+}
+
+/// The state of the only client of the dummy server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DummyClientState {
+    Connecting,
+    Connected,
+    /// The client was disconnected, but the event was not received yet
+    Disconnecting,
+    Disconnected,
 }
 
 pub struct DummyServer {
-    first_queried: bool,
-    pub(self) to_client: Sender<ToClient>,
+    client_state: DummyClientState,
+    pub(self) to_client: Sender<ToClientOrDisconnect>,
     pub(self) to_server: Receiver<ToServer>,
 }
 
@@ -23,11 +37,12 @@ pub fn new() -> (DummyClient, DummyServer) {
     (
         DummyClient {
             first_queried: true,
+            disconnected: false,
             to_server: client_to_server.0,
             to_client: server_to_client.1,
         },
         DummyServer {
-            first_queried: true,
+            client_state: DummyClientState::Connecting,
             to_client: server_to_client.0,
             to_server: client_to_server.1,
         },
@@ -36,9 +51,17 @@ pub fn new() -> (DummyClient, DummyServer) {
 
 impl super::Server for DummyServer {
     fn receive_event(&mut self) -> ServerEvent {
-        if self.first_queried {
-            self.first_queried = false;
-            return ServerEvent::ClientConnected(PlayerId(0));
+        match self.client_state {
+            DummyClientState::Connecting => {
+                self.client_state = DummyClientState::Connected;
+                return ServerEvent::ClientConnected(PlayerId(0));
+            }
+            DummyClientState::Connected => {}
+            DummyClientState::Disconnecting => {
+                self.client_state = DummyClientState::Disconnected;
+                return ServerEvent::ClientDisconnected(PlayerId(0));
+            }
+            DummyClientState::Disconnected => return ServerEvent::NoEvent,
         }
         match self.to_server.try_recv() {
             Ok(m) => ServerEvent::ClientMessage(PlayerId(0), m),
@@ -47,8 +70,29 @@ impl super::Server for DummyServer {
         }
     }
 
-    fn send(&mut self, _: PlayerId, message: ToClient) {
-        self.to_client.send(message).unwrap();
+    // The channel delivers every message in order, which satisfies all the delivery modes
+    fn send(&mut self, _: PlayerId, message: ToClient, _: MessageDelivery) {
+        if self.client_state == DummyClientState::Connected {
+            self.to_client.send(Ok(message)).unwrap();
+        }
+    }
+
+    fn disconnect(&mut self, _: PlayerId, reason: String) {
+        if self.client_state == DummyClientState::Connected {
+            // The client may already be gone
+            let _ = self.to_client.send(Err(DisconnectReason::Kicked(reason)));
+            self.client_state = DummyClientState::Disconnecting;
+        }
+    }
+
+    // The dummy client is in the same process
+    fn network_stats(&self, _: PlayerId) -> Option<NetworkStats> {
+        None
+    }
+
+    // The only client is the singleplayer client that started the server
+    fn is_host(&self, _: PlayerId) -> bool {
+        true
     }
 }
 
@@ -58,14 +102,27 @@ impl super::Client for DummyClient {
             self.first_queried = false;
             return ClientEvent::Connected;
         }
+        if self.disconnected {
+            return ClientEvent::NoEvent;
+        }
         match self.to_client.try_recv() {
-            Ok(m) => ClientEvent::ServerMessage(m),
+            Ok(Ok(m)) => ClientEvent::ServerMessage(m),
+            Ok(Err(reason)) => {
+                self.disconnected = true;
+                ClientEvent::Disconnected(reason)
+            }
             Err(TryRecvError::Empty) => ClientEvent::NoEvent,
             Err(TryRecvError::Disconnected) => unreachable!(),
         }
     }
 
-    fn send(&mut self, message: ToServer) {
-        self.to_server.send(message).unwrap();
+    fn send(&mut self, message: ToServer, _: MessageDelivery) {
+        if !self.disconnected {
+            self.to_server.send(message).unwrap();
+        }
+    }
+
+    fn network_stats(&self) -> Option<NetworkStats> {
+        None
     }
 }
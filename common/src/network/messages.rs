@@ -1,38 +1,111 @@
 use crate::{
+    block::entity::ChunkBlockEntities,
     data::Data,
-    physics::simulation::ServerState,
+    dimension::Dimension,
+    physics::simulation::ServerStateUpdate,
+    inventory::{Inventory, SlotAction},
     player::PlayerId,
-    player::{PlayerInput, RenderDistance},
-    world::{Chunk, LightChunk},
+    player::{GameMode, InteractionConfig, PlayerInput, RenderDistance},
+    recipe::RecipeId,
+    season::SeasonState,
+    sound::SoundId,
+    time::TimeOfDay,
+    weather::Weather,
+    world::{BlockPos, Chunk, ChunkPos, LightChunk},
 };
 use nalgebra::Vector3;
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
+use std::time::Instant;
+
+/// Version of the messages, bump it whenever `ToServer`, `ToClient` or the types that they contain change.
+/// The clients can only connect to the servers with the same version.
+pub const MESSAGES_VERSION: u32 = 1;
 
 /// A message sent to the server by the client
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ToServer {
     /// Update player render distance
     SetRenderDistance(RenderDistance),
-    /// Update the player's input
-    UpdateInput(PlayerInput),
-    /// Break a block (player pos, yaw, pitch)
-    BreakBlock(Vector3<f64>, f64, f64),
+    /// Update the player's input, with the time when the client sampled it.
+    /// The input applies between the previous input and that time.
+    UpdateInput(PlayerInput, #[serde(with = "super::serialization::instant")] Instant),
+    /// Start breaking the pointed block (player pos, yaw, pitch). The block breaks once the player held
+    /// the button long enough, unless `StopBreaking` is sent before.
+    StartBreaking(Vector3<f64>, f64, f64),
+    /// Stop breaking the block
+    StopBreaking,
     /// Select a block
     SelectBlock(Vector3<f64>, f64, f64),
     /// Place a block
     PlaceBlock(Vector3<f64>, f64, f64),
+    /// Log in with a username and an optional token, see the accounts of the server config.
+    /// The server ignores the interactions of the player until it logged in, and disconnects it if the login is refused.
+    Login(String, Option<String>),
+    /// Execute a command, for example `/give Player stone 64`
+    Command(String),
+    /// Craft a recipe once with the items of the inventory
+    CraftItem(RecipeId),
+    /// Act on the slots of the inventory of the player. The id increases with every action,
+    /// and the server acknowledges the actions it processed, even the refused ones, with the next `Inventory` message.
+    SlotAction(u32, SlotAction),
+    /// Change the gamemode of the player. Needs the `gamemode` permission.
+    SetGameMode(GameMode),
+    /// Follow a player with the camera in spectator mode, or stop following with `None`
+    FollowPlayer(Option<PlayerId>),
+    /// Respawn after dying
+    Respawn,
+    /// Save the world and stop the server, for example when the player hosting a singleplayer world exits.
+    /// Needs the `stop` permission.
+    StopServer,
+    /// Acknowledge a `Chunk` or `LightChunk` message, so that the server can send the next version of the chunk
+    ChunkReceived(ChunkPos),
 }
 
 /// A message sent to the client by the server
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ToClient {
-    /// Send the game data
-    GameData(Data),
-    /// Send the chunk at some position
-    Chunk(Arc<Chunk>, Arc<LightChunk>),
-    /// Update the whole of the physics simulation
-    // TODO: only send part of the physics simulation
-    UpdatePhysics(ServerState),
+    /// Send the game data. It contains the registries, so the client always uses the ids of the server.
+    GameData(Box<Data>),
+    /// Send the chunk at some position, with its block entities
+    Chunk(Arc<Chunk>, Arc<LightChunk>, Arc<ChunkBlockEntities>),
+    /// Update the light of a chunk that was already sent, when only the light changed
+    LightChunk(Arc<LightChunk>),
+    /// Update the physics of the players close to the receiving player
+    UpdatePhysics(ServerStateUpdate),
     /// Set the id of a player
     CurrentId(PlayerId),
+    /// Set the name displayed above a player
+    DisplayName(PlayerId, String),
+    /// Play a sound at some position
+    PlaySound(SoundId, Vector3<f64>),
+    /// Progress of a player breaking a block, between 0 and 1. 1 means that the block broke, and `None` that the player stopped.
+    BlockBreaking(PlayerId, BlockPos, Option<f32>),
+    /// Synchronize the time of the day
+    TimeOfDay(TimeOfDay),
+    /// Set the weather
+    Weather(Weather),
+    /// Number of sleeping players and total number of players, sent when a player starts or stops sleeping
+    SleepingPlayers(usize, usize),
+    /// The result of a command sent by the player
+    CommandFeedback(String),
+    /// Why the server refused an action of the player, for example breaking a block in the spawn protection
+    ActionDenied(String),
+    /// Set the orientation of the camera (yaw, pitch), for example when the saved player is restored
+    SetYawPitch(f64, f64),
+    /// Set the gamemode of the player
+    GameMode(GameMode),
+    /// Set the reach and the cooldowns of every gamemode, so that the client doesn't send interactions that would be refused
+    InteractionConfig(InteractionConfig),
+    /// The player was teleported, for example when it respawned, with the number of times it was teleported
+    Teleport(Vector3<f64>, u32),
+    /// Set the content of the inventory of the player, sent when it changes or when slot actions were processed,
+    /// with the id of the last processed slot action
+    Inventory(Inventory, u32),
+    /// Set the health of the player in half hearts, sent when it changes. The player is dead if it's 0.
+    UpdateHealth(u32),
+    /// Set the sky, lighting and fog profile of the world
+    Dimension(Dimension),
+    /// Set the tint and the variant of the seasonal blocks
+    Season(SeasonState),
 }
@@ -1,6 +1,9 @@
 use crate::player::PlayerId;
 
 pub mod messages;
+pub mod remote;
+pub mod serialization;
+pub use voxel_rs_network::{DisconnectReason, MessageDelivery, NetworkStats};
 
 /// An event that the server received.
 #[derive(Debug, Clone)]
@@ -22,8 +25,8 @@ pub enum ClientEvent {
     NoEvent,
     /// Connected to the server.
     Connected,
-    /// Disconnected from the server.
-    Disconnected,
+    /// Disconnected from the server, or the server rejected the connection.
+    Disconnected(DisconnectReason),
     /// Server sent a message.
     ServerMessage(messages::ToClient),
 }
@@ -32,16 +35,24 @@ pub enum ClientEvent {
 pub trait Server {
     /// Receive the next event.
     fn receive_event(&mut self) -> ServerEvent;
-    /// Send a message to a client. The message will be dropped if it can't be sent.
-    fn send(&mut self, client: PlayerId, message: messages::ToClient);
+    /// Send a message to a client with the given delivery guarantees. The message will be dropped if it can't be sent.
+    fn send(&mut self, client: PlayerId, message: messages::ToClient, delivery: MessageDelivery);
+    /// Disconnect a client, sending it the reason. A `ClientDisconnected` event will be received for the client.
+    fn disconnect(&mut self, client: PlayerId, reason: String);
+    /// Statistics of the connection to a client, `None` if the connection is not over a network
+    fn network_stats(&self, client: PlayerId) -> Option<NetworkStats>;
+    /// `true` if the client hosts the server, because it runs the server in its own process
+    fn is_host(&self, client: PlayerId) -> bool;
 }
 
 /// An abstraction over a network client.
 pub trait Client {
     /// Receive the next event
     fn receive_event(&mut self) -> ClientEvent;
-    /// Send a message to the server. The message will be dropped if it can't be sent.
-    fn send(&mut self, message: messages::ToServer);
+    /// Send a message to the server with the given delivery guarantees. The message will be dropped if it can't be sent.
+    fn send(&mut self, message: messages::ToServer, delivery: MessageDelivery);
+    /// Statistics of the connection to the server, `None` if the connection is not over a network
+    fn network_stats(&self) -> Option<NetworkStats>;
 }
 
 /// Dummy client and server implementations for testing
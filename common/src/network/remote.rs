@@ -0,0 +1,283 @@
+//! Client and server that communicate over UDP, with the messages encoded by `serialization`
+use super::messages::{ToClient, ToServer, MESSAGES_VERSION};
+use super::serialization::{decode, encode};
+use crate::{
+    network::{ClientEvent, MessageDelivery, NetworkStats, ServerEvent},
+    player::PlayerId,
+};
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+use std::collections::{HashMap, VecDeque};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+/// Port of the servers when the address doesn't specify one
+pub const DEFAULT_PORT: u16 = 42000;
+/// Maximum size of a decoded message from the server, the game data is the largest one
+const MAX_TO_CLIENT_SIZE: usize = 64 * 1024 * 1024;
+/// Maximum size of a decoded message from a client
+const MAX_TO_SERVER_SIZE: usize = 64 * 1024;
+
+/// Resolve the address of a server, for example `127.0.0.1`, `example.com:1234` or `[::1]:1234`
+pub fn resolve_address(address: &str) -> Result<SocketAddr> {
+    let address = address.trim();
+    // Without a port, the address is a host name, an IPv4 address or an IPv6 address without brackets
+    let has_port = address.rsplit_once(':').is_some_and(|(host, port)| {
+        (!host.contains(':') || host.ends_with(']')) && port.parse::<u16>().is_ok()
+    });
+    let addresses = if has_port {
+        address.to_socket_addrs()
+    } else {
+        (address.trim_start_matches('[').trim_end_matches(']'), DEFAULT_PORT).to_socket_addrs()
+    };
+    addresses
+        .with_context(|| format!("couldn't resolve {}", address))?
+        .next()
+        .ok_or_else(|| anyhow!("{} doesn't have any address", address))
+}
+
+/// Bind a non-blocking UDP socket
+fn bind_socket(addr: SocketAddr) -> Result<UdpSocket> {
+    let socket = UdpSocket::bind(addr).with_context(|| format!("couldn't bind a socket to {}", addr))?;
+    socket.set_nonblocking(true).context("couldn't make the socket non-blocking")?;
+    Ok(socket)
+}
+
+/// A client connected to a server over the network
+pub struct RemoteClient {
+    client: voxel_rs_network::Client<UdpSocket>,
+    /// Events that were received but not returned yet
+    events: VecDeque<ClientEvent>,
+    connected: bool,
+    disconnected: bool,
+}
+
+impl RemoteClient {
+    /// Start connecting to the server at `server_addr`. The `Connected` event is received once the server accepted
+    /// the connection, and `Disconnected` if it refused it or didn't answer.
+    pub fn connect(server_addr: SocketAddr) -> Result<Self> {
+        let local_addr = if server_addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let mut client = voxel_rs_network::Client::new(bind_socket(local_addr.parse().unwrap())?, server_addr)
+            .with_game_version(MESSAGES_VERSION);
+        client.connect();
+        Ok(Self {
+            client,
+            events: VecDeque::new(),
+            connected: false,
+            disconnected: false,
+        })
+    }
+}
+
+impl super::Client for RemoteClient {
+    fn receive_event(&mut self) -> ClientEvent {
+        if self.events.is_empty() && !self.disconnected {
+            self.client.tick();
+            if !self.connected && self.client.is_connected() {
+                self.connected = true;
+                self.events.push_back(ClientEvent::Connected);
+            }
+            for (_, data) in self.client.get_messages() {
+                match decode(&data, MAX_TO_CLIENT_SIZE) {
+                    Ok(message) => self.events.push_back(ClientEvent::ServerMessage(message)),
+                    Err(e) => warn!("Dropped a message from the server that couldn't be decoded: {:?}", e),
+                }
+            }
+            if let Some(reason) = self.client.disconnect_reason() {
+                self.disconnected = true;
+                self.events.push_back(ClientEvent::Disconnected(reason.clone()));
+            }
+        }
+        self.events.pop_front().unwrap_or(ClientEvent::NoEvent)
+    }
+
+    // TODO: tell the server when the client leaves, for now it only notices the timeout
+    fn send(&mut self, message: ToServer, delivery: MessageDelivery) {
+        match encode(&message) {
+            Ok(data) => self.client.send_message(data, delivery),
+            Err(e) => warn!("Failed to encode a message to the server: {:?}", e),
+        }
+    }
+
+    fn network_stats(&self) -> Option<NetworkStats> {
+        self.client.stats()
+    }
+}
+
+/// A server that the clients connect to over the network
+pub struct RemoteServer {
+    server: voxel_rs_network::Server<UdpSocket>,
+    /// Events that were received but not returned yet
+    events: VecDeque<ServerEvent>,
+    players: HashMap<SocketAddr, PlayerId>,
+    addresses: HashMap<PlayerId, SocketAddr>,
+    next_id: u16,
+}
+
+impl RemoteServer {
+    /// Listen for the clients on `addr`
+    pub fn bind(addr: SocketAddr) -> Result<Self> {
+        Ok(Self {
+            server: voxel_rs_network::Server::new(bind_socket(addr)?).with_game_version(MESSAGES_VERSION),
+            events: VecDeque::new(),
+            players: HashMap::new(),
+            addresses: HashMap::new(),
+            next_id: 0,
+        })
+    }
+
+    /// An id that no connected client uses
+    fn new_id(&mut self) -> PlayerId {
+        loop {
+            let id = PlayerId(self.next_id);
+            self.next_id = self.next_id.wrapping_add(1);
+            if !self.addresses.contains_key(&id) {
+                return id;
+            }
+        }
+    }
+
+    /// Tick the network and convert the new events
+    fn receive_events(&mut self) {
+        self.server.tick();
+        let mut invalid_clients = Vec::new();
+        for event in self.server.get_events().collect::<Vec<_>>() {
+            match event {
+                voxel_rs_network::ServerEvent::Connected { id: addr } => {
+                    let id = self.new_id();
+                    self.players.insert(addr, id);
+                    self.addresses.insert(id, addr);
+                    self.events.push_back(ServerEvent::ClientConnected(id));
+                }
+                voxel_rs_network::ServerEvent::Disconnected { id: addr } => {
+                    if let Some(id) = self.players.remove(&addr) {
+                        self.addresses.remove(&id);
+                        self.events.push_back(ServerEvent::ClientDisconnected(id));
+                    }
+                }
+                voxel_rs_network::ServerEvent::Message { source_id: addr, data, .. } => {
+                    let id = match self.players.get(&addr) {
+                        Some(&id) => id,
+                        None => continue,
+                    };
+                    match decode(&data, MAX_TO_SERVER_SIZE) {
+                        Ok(message) => self.events.push_back(ServerEvent::ClientMessage(id, message)),
+                        Err(e) => {
+                            warn!("Received a message that couldn't be decoded from {} ({:?})", addr, e);
+                            invalid_clients.push(addr);
+                        }
+                    }
+                }
+            }
+        }
+        // The disconnection events are received with the next tick
+        for addr in invalid_clients {
+            self.server.kick(addr, "Invalid message".to_owned());
+        }
+    }
+}
+
+impl super::Server for RemoteServer {
+    fn receive_event(&mut self) -> ServerEvent {
+        if self.events.is_empty() {
+            self.receive_events();
+        }
+        self.events.pop_front().unwrap_or(ServerEvent::NoEvent)
+    }
+
+    fn send(&mut self, client: PlayerId, message: ToClient, delivery: MessageDelivery) {
+        if let Some(&addr) = self.addresses.get(&client) {
+            match encode(&message) {
+                Ok(data) => self.server.send_message(addr, data, delivery),
+                Err(e) => warn!("Failed to encode a message to {} ({:?})", addr, e),
+            }
+        }
+    }
+
+    fn disconnect(&mut self, client: PlayerId, reason: String) {
+        if let Some(&addr) = self.addresses.get(&client) {
+            self.server.kick(addr, reason);
+        }
+    }
+
+    fn network_stats(&self, client: PlayerId) -> Option<NetworkStats> {
+        self.addresses.get(&client).and_then(|&addr| self.server.stats(addr))
+    }
+
+    // The clients of a dedicated server never host it
+    fn is_host(&self, _: PlayerId) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::{Client, DisconnectReason, Server};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_resolve_address() {
+        let resolve = |address| resolve_address(address).unwrap().to_string();
+        assert_eq!(resolve("127.0.0.1"), format!("127.0.0.1:{}", DEFAULT_PORT));
+        assert_eq!(resolve(" 127.0.0.1:1234 "), "127.0.0.1:1234");
+        assert_eq!(resolve("::1"), format!("[::1]:{}", DEFAULT_PORT));
+        assert_eq!(resolve("[::1]"), format!("[::1]:{}", DEFAULT_PORT));
+        assert_eq!(resolve("[::1]:1234"), "[::1]:1234");
+        assert!(resolve_address("").is_err());
+        assert!(resolve_address("127.0.0.1:port").is_err());
+    }
+
+    #[test]
+    fn test_remote_connection() {
+        // Find a free port
+        let server_addr = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let mut server = RemoteServer::bind(server_addr).unwrap();
+        let mut client = RemoteClient::connect(server_addr).unwrap();
+
+        let start = Instant::now();
+        let (mut client_id, mut login, mut current_id) = (None, None, None);
+        while current_id.is_none() {
+            assert!(start.elapsed() < Duration::from_secs(10), "The client didn't connect");
+            match server.receive_event() {
+                ServerEvent::ClientConnected(id) => {
+                    client_id = Some(id);
+                    server.send(id, ToClient::CurrentId(id), MessageDelivery::Ordered);
+                }
+                ServerEvent::ClientMessage(id, ToServer::Login(name, token)) => login = Some((id, name, token)),
+                _ => {}
+            }
+            match client.receive_event() {
+                ClientEvent::Connected => {
+                    client.send(ToServer::Login("Player".to_owned(), None), MessageDelivery::Ordered)
+                }
+                ClientEvent::ServerMessage(ToClient::CurrentId(id)) => current_id = Some(id),
+                ClientEvent::Disconnected(reason) => panic!("The client was disconnected: {}", reason),
+                _ => {}
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(current_id, client_id);
+        while login.is_none() {
+            assert!(start.elapsed() < Duration::from_secs(10), "The server didn't receive the login");
+            client.receive_event();
+            if let ServerEvent::ClientMessage(id, ToServer::Login(name, token)) = server.receive_event() {
+                login = Some((id, name, token));
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(login, Some((client_id.unwrap(), "Player".to_owned(), None)));
+        assert!(server.network_stats(client_id.unwrap()).is_some());
+
+        // The kicked client receives the reason
+        server.disconnect(client_id.unwrap(), "Bye".to_owned());
+        loop {
+            assert!(start.elapsed() < Duration::from_secs(10), "The client wasn't disconnected");
+            server.receive_event();
+            if let ClientEvent::Disconnected(reason) = client.receive_event() {
+                assert_eq!(reason, DisconnectReason::Kicked("Bye".to_owned()));
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
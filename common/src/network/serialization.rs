@@ -0,0 +1,154 @@
+//! Serialization of the messages sent over the network
+use anyhow::{anyhow, Result};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Messages larger than this are compressed
+const COMPRESSION_THRESHOLD: usize = 512;
+const UNCOMPRESSED: u8 = 0;
+const COMPRESSED: u8 = 1;
+
+/// Serialize a message, and compress it if it's large. The first byte tells if the message is compressed.
+pub fn encode<T: Serialize>(message: &T) -> Result<Vec<u8>> {
+    let serialized = bincode::serialize(message)?;
+    let mut data = Vec::with_capacity(serialized.len() + 1);
+    if serialized.len() > COMPRESSION_THRESHOLD {
+        data.push(COMPRESSED);
+        data.extend(miniz_oxide::deflate::compress_to_vec(&serialized, 6));
+    } else {
+        data.push(UNCOMPRESSED);
+        data.extend(serialized);
+    }
+    Ok(data)
+}
+
+/// Decode a message encoded by `encode`. The compressed messages larger than `max_size` once decompressed are refused.
+pub fn decode<T: DeserializeOwned>(data: &[u8], max_size: usize) -> Result<T> {
+    match data.split_first() {
+        Some((&UNCOMPRESSED, serialized)) => Ok(bincode::deserialize(serialized)?),
+        Some((&COMPRESSED, compressed)) => {
+            let serialized = miniz_oxide::inflate::decompress_to_vec_with_limit(compressed, max_size)
+                .map_err(|status| anyhow!("failed to decompress the message: {:?}", status))?;
+            Ok(bincode::deserialize(&serialized)?)
+        }
+        Some((flag, _)) => Err(anyhow!("unknown message encoding {}", flag)),
+        None => Err(anyhow!("empty message")),
+    }
+}
+
+/// Serialize the instants as the time since an epoch chosen by each process. The instants of two processes can't be
+/// compared, only the durations between the instants of the same process are meaningful: the server measures its own
+/// time to limit the durations sent by a client. A time that the client sends and the server sends back,
+/// like the time of the last simulated input, keeps its value on the client.
+pub mod instant {
+    use lazy_static::lazy_static;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::time::{Duration, Instant};
+
+    lazy_static! {
+        static ref EPOCH: Instant = Instant::now();
+    }
+
+    pub fn serialize<S: Serializer>(instant: &Instant, serializer: S) -> Result<S::Ok, S::Error> {
+        to_nanos(*instant, *EPOCH).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Instant, D::Error> {
+        Ok(from_nanos(i64::deserialize(deserializer)?, *EPOCH))
+    }
+
+    /// Nanoseconds from `epoch` to `instant`
+    pub(super) fn to_nanos(instant: Instant, epoch: Instant) -> i64 {
+        match instant.checked_duration_since(epoch) {
+            Some(since) => since.as_nanos() as i64,
+            None => -(epoch.duration_since(instant).as_nanos() as i64),
+        }
+    }
+
+    /// The instant `nanos` nanoseconds after `epoch`
+    pub(super) fn from_nanos(nanos: i64, epoch: Instant) -> Instant {
+        let offset = Duration::from_nanos(nanos.unsigned_abs());
+        // Instants before the start of the process may not be representable
+        let instant = if nanos >= 0 { epoch.checked_add(offset) } else { epoch.checked_sub(offset) };
+        instant.unwrap_or(epoch)
+    }
+}
+
+/// Serialize the images as their size and their raw pixels, they are compressed with the rest of the message
+pub mod image_buffer {
+    use image::{ImageBuffer, Rgba};
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(image: &ImageBuffer<Rgba<u8>, Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error> {
+        (image.width(), image.height(), image.as_raw()).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let (width, height, pixels) = <(u32, u32, Vec<u8>)>::deserialize(deserializer)?;
+        ImageBuffer::from_raw(width, height, pixels).ok_or_else(|| D::Error::custom("wrong number of pixels"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::messages::{ToClient, ToServer};
+    use crate::world::{Chunk, LightChunk};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_encode_decode() {
+        // A small message is not compressed
+        let time = Instant::now() + Duration::from_millis(30);
+        let data = encode(&ToServer::UpdateInput(Default::default(), time)).unwrap();
+        assert_eq!(data[0], UNCOMPRESSED);
+        match decode(&data, 1024).unwrap() {
+            ToServer::UpdateInput(_, decoded_time) => assert_eq!(decoded_time, time),
+            message => panic!("Unexpected message {:?}", message),
+        }
+
+        // A chunk is compressed
+        let mut chunk = Chunk::new((1, -2, 3).into());
+        chunk.set_block_at((4, 5, 6), 7);
+        let light_chunk = LightChunk::new((1, -2, 3).into());
+        let message = ToClient::Chunk(Arc::new(chunk), Arc::new(light_chunk), Default::default());
+        let data = encode(&message).unwrap();
+        assert_eq!(data[0], COMPRESSED);
+        match decode(&data, 1024 * 1024).unwrap() {
+            ToClient::Chunk(chunk, light_chunk, _) => {
+                assert_eq!(chunk.pos, (1, -2, 3).into());
+                assert_eq!(chunk.get_block_at((4, 5, 6)), 7);
+                assert_eq!(chunk.get_block_at((6, 5, 4)), 0);
+                assert_eq!(light_chunk.pos, (1, -2, 3).into());
+            }
+            message => panic!("Unexpected message {:?}", message),
+        }
+        // Unless it's larger than the limit once decompressed
+        assert!(decode::<ToClient>(&data, 1024).is_err());
+        assert!(decode::<ToClient>(&[], 1024).is_err());
+    }
+
+    #[test]
+    fn test_instant_between_epochs() {
+        use instant::{from_nanos, to_nanos};
+
+        // The client and the server started at different times
+        let client_epoch = Instant::now();
+        let server_epoch = client_epoch + Duration::from_secs(3600);
+        for &time in [client_epoch + Duration::from_millis(30), client_epoch + Duration::from_secs(7200)].iter() {
+            // The server receives the time, and sends it back
+            let on_server = from_nanos(to_nanos(time, client_epoch), server_epoch);
+            assert_eq!(on_server - server_epoch, time - client_epoch);
+            assert_eq!(from_nanos(to_nanos(on_server, server_epoch), client_epoch), time);
+        }
+        // A time before the epoch of the client is sent as a negative time
+        if let Some(time) = client_epoch.checked_sub(Duration::from_secs(60)) {
+            let on_server = from_nanos(to_nanos(time, client_epoch), server_epoch);
+            assert_eq!(server_epoch - on_server, Duration::from_secs(60));
+            assert_eq!(from_nanos(to_nanos(on_server, server_epoch), client_epoch), time);
+        }
+    }
+}
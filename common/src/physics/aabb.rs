@@ -1,7 +1,8 @@
 use super::BlockContainer;
 use nalgebra::Vector3;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct AABB {
     pub pos: Vector3<f64>,
     pub size_x: f64,
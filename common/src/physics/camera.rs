@@ -3,19 +3,24 @@
 //! A `Camera` defines how a player's entity reacts to that player's inputs.
 
 use crate::{
-    debug::send_debug_info, physics::player::PhysicsPlayer, player::PlayerInput,
+    debug::send_debug_info,
+    physics::{config::PhysicsConfig, player::PhysicsPlayer},
+    player::PlayerInput,
 };
 use super::BlockContainer;
 use nalgebra::Vector3;
 
 /// The default camera. It doesn't let you go inside blocks unless you are already inside blocks.
+/// Return the downwards velocity of the player if it landed on the ground during this step.
 // TODO: use better integrator (RK4 ?)
 pub fn default_camera<BC: BlockContainer>(
     player: &mut PhysicsPlayer,
     input: PlayerInput,
     seconds_delta: f64,
     world: &BC,
-) {
+    config: &PhysicsConfig,
+) -> Option<f64> {
+    let mut landing_velocity = None;
     // Unit vector in the `angle` direction
     fn movement_direction(yaw: f64, angle: f64) -> Vector3<f64> {
         let yaw = yaw + angle;
@@ -30,7 +35,7 @@ pub fn default_camera<BC: BlockContainer>(
         }
     }
     // Compute the expected movement of the player, i.e. assuming there are no collisions.
-    if input.flying || player.aabb.intersect_world(world) {
+    if input.flying || input.noclip || player.aabb.intersect_world(world) {
         const ACCELERATION: f64 = 50.0;
         const MAX_SPEED: f64 = 30.0;
         player.velocity.y = 0.0;
@@ -63,12 +68,12 @@ pub fn default_camera<BC: BlockContainer>(
         if input.key_move_down {
             expected_movement.y -= (seconds_delta * MAX_SPEED) as f64;
         }
-        player.aabb.move_check_collision(world, expected_movement);
+        if input.noclip {
+            player.aabb.pos += expected_movement;
+        } else {
+            player.aabb.move_check_collision(world, expected_movement);
+        }
     } else {
-        const JUMP_SPEED: f64 = 8.0;
-        const GRAVITY_ACCELERATION: f64 = 25.0;
-        const MAX_DOWN_SPEED: f64 = 30.0;
-        const HORIZONTAL_SPEED: f64 = 7.0;
         player.velocity.x = 0.0;
         player.velocity.z = 0.0;
         let mut horizontal_velocity = Vector3::zeros();
@@ -84,19 +89,23 @@ pub fn default_camera<BC: BlockContainer>(
         if input.key_move_right {
             horizontal_velocity += movement_direction(input.yaw, 270.0);
         }
-        let horizontal_velocity = normalize_or_zero(horizontal_velocity) * HORIZONTAL_SPEED;
-        if player.aabb.is_on_the_ground(world) {
-            player.velocity.y = if input.key_move_up { JUMP_SPEED } else { 0.0 };
+        let horizontal_velocity = normalize_or_zero(horizontal_velocity) * config.walk_velocity;
+        let was_on_the_ground = player.aabb.is_on_the_ground(world);
+        if was_on_the_ground {
+            player.velocity.y = if input.key_move_up { config.jump_velocity } else { 0.0 };
         } else {
-            player.velocity.y -= GRAVITY_ACCELERATION * seconds_delta;
-            if player.velocity.y < -MAX_DOWN_SPEED {
-                player.velocity.y = -MAX_DOWN_SPEED;
+            player.velocity.y -= config.gravity * seconds_delta;
+            player.velocity.y *= (1.0 - config.air_friction * seconds_delta).max(0.0);
+            if player.velocity.y < -config.terminal_velocity {
+                player.velocity.y = -config.terminal_velocity;
             }
         };
         let expected_movement = (player.velocity + horizontal_velocity) * seconds_delta;
         player.aabb.move_check_collision(world, expected_movement);
+        if !was_on_the_ground && player.velocity.y < 0.0 && player.aabb.is_on_the_ground(world) {
+            landing_velocity = Some(-player.velocity.y);
+        }
     }
-    // TODO: add a noclip camera mode
     send_debug_info(
         "Physics",
         "ontheground",
@@ -111,4 +120,5 @@ pub fn default_camera<BC: BlockContainer>(
         "velocity",
         format!("velocity: {:.2} {:.2} {:.2}", vx, vy, vz),
     );
+    landing_velocity
 }
@@ -0,0 +1,50 @@
+//! Physics constants that can be changed for every world
+
+use serde::{Deserialize, Serialize};
+
+/// The physics constants of a world, sent to the clients so that their prediction matches the server
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PhysicsConfig {
+    /// Downwards acceleration, in blocks per second squared
+    pub gravity: f64,
+    /// Upwards velocity when jumping, in blocks per second
+    pub jump_velocity: f64,
+    /// Maximum falling velocity, in blocks per second
+    pub terminal_velocity: f64,
+    /// Fraction of the vertical velocity lost every second while in the air
+    pub air_friction: f64,
+    /// Horizontal velocity when walking, in blocks per second
+    pub walk_velocity: f64,
+    /// Maximum vertical velocity when landing without fall damage, in blocks per second
+    pub safe_landing_velocity: f64,
+    /// Damage for every block per second of landing velocity above `safe_landing_velocity`, in half hearts
+    pub fall_damage_per_velocity: f64,
+}
+
+impl Default for PhysicsConfig {
+    fn default() -> Self {
+        Self {
+            gravity: 25.0,
+            jump_velocity: 8.0,
+            terminal_velocity: 30.0,
+            air_friction: 0.0,
+            walk_velocity: 7.0,
+            // About 4 blocks with the default gravity
+            safe_landing_velocity: 14.0,
+            fall_damage_per_velocity: 1.0,
+        }
+    }
+}
+
+impl PhysicsConfig {
+    /// Damage of a landing with some vertical velocity, in half hearts
+    pub fn fall_damage(&self, landing_velocity: f64) -> u32 {
+        let excess = landing_velocity - self.safe_landing_velocity;
+        if excess > 0.0 {
+            (excess * self.fall_damage_per_velocity).ceil() as u32
+        } else {
+            0
+        }
+    }
+}
@@ -1,12 +1,53 @@
 use crate::world::BlockPos;
+use std::cell::RefCell;
+use std::collections::HashMap;
 
 pub mod aabb;
 pub mod camera;
+pub mod config;
 pub mod player;
+pub mod raycast;
 pub mod simulation;
 
 /// A "block container", i.e. either the client's World or the server's World.
 /// This trait allows the physics simulation to work transparently with both World structs.
 pub trait BlockContainer {
     fn is_block_full(&self, pos: BlockPos) -> bool;
+
+    /// Return `true` if rays can hit the block. Blocks that are not full, such as fluids or plants, can still be targeted.
+    fn is_block_targetable(&self, pos: BlockPos) -> bool {
+        self.is_block_full(pos)
+    }
+}
+
+/// A `BlockContainer` that remembers which blocks were tested, to visualize the collisions
+pub struct RecordingBlockContainer<'a, BC: BlockContainer> {
+    world: &'a BC,
+    tested_blocks: RefCell<HashMap<BlockPos, bool>>,
+}
+
+impl<'a, BC: BlockContainer> RecordingBlockContainer<'a, BC> {
+    pub fn new(world: &'a BC) -> Self {
+        Self {
+            world,
+            tested_blocks: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Get the tested blocks, and whether they are full
+    pub fn into_tested_blocks(self) -> HashMap<BlockPos, bool> {
+        self.tested_blocks.into_inner()
+    }
+}
+
+impl<'a, BC: BlockContainer> BlockContainer for RecordingBlockContainer<'a, BC> {
+    fn is_block_full(&self, pos: BlockPos) -> bool {
+        let full = self.world.is_block_full(pos);
+        self.tested_blocks.borrow_mut().insert(pos, full);
+        full
+    }
+
+    fn is_block_targetable(&self, pos: BlockPos) -> bool {
+        self.world.is_block_targetable(pos)
+    }
 }
@@ -0,0 +1,148 @@
+//! Ray casting through the blocks of the world
+use super::BlockContainer;
+use crate::world::BlockPos;
+use nalgebra::Vector3;
+
+/// The block hit by a ray
+#[derive(Debug, Clone, Copy)]
+pub struct RaycastHit {
+    /// Position of the block
+    pub block: BlockPos,
+    /// The face that was hit (x/-x/y/-y/z/-z)
+    pub face: usize,
+    /// Distance between the origin of the ray and the hit point
+    pub distance: f64,
+    /// The exact point where the ray hit the block
+    pub point: Vector3<f64>,
+}
+
+/// A ray going through the world, stopping at the first block it hits
+#[derive(Debug, Clone)]
+pub struct Raycast {
+    origin: Vector3<f64>,
+    dir: Vector3<f64>,
+    max_distance: f64,
+    ignore_non_solid: bool,
+}
+
+impl Raycast {
+    pub fn new(origin: Vector3<f64>, dir: Vector3<f64>, max_distance: f64) -> Self {
+        Self {
+            origin,
+            dir: dir.normalize(),
+            max_distance,
+            ignore_non_solid: false,
+        }
+    }
+
+    /// Go through the blocks that are not full, such as fluids or plants
+    pub fn ignore_non_solid(mut self, ignore_non_solid: bool) -> Self {
+        self.ignore_non_solid = ignore_non_solid;
+        self
+    }
+
+    /// Find the first block hit by the ray
+    pub fn cast<BC: BlockContainer>(&self, world: &BC) -> Option<RaycastHit> {
+        let mut block = [
+            self.origin.x.floor() as i64,
+            self.origin.y.floor() as i64,
+            self.origin.z.floor() as i64,
+        ];
+        // Distance to the next block boundary along every axis, and between two boundaries
+        let mut next_boundary = [f64::INFINITY; 3];
+        let mut boundary_step = [f64::INFINITY; 3];
+        let mut step = [0i64; 3];
+        for axis in 0..3 {
+            if self.dir[axis] > 0.0 {
+                step[axis] = 1;
+                next_boundary[axis] = (block[axis] as f64 + 1.0 - self.origin[axis]) / self.dir[axis];
+                boundary_step[axis] = 1.0 / self.dir[axis];
+            } else if self.dir[axis] < 0.0 {
+                step[axis] = -1;
+                next_boundary[axis] = (block[axis] as f64 - self.origin[axis]) / self.dir[axis];
+                boundary_step[axis] = -1.0 / self.dir[axis];
+            }
+        }
+        let next_axis = |next_boundary: &[f64; 3]| {
+            (0..3)
+                .min_by(|&a, &b| next_boundary[a].partial_cmp(&next_boundary[b]).unwrap())
+                .unwrap()
+        };
+
+        // Check current block first, returning the face the ray leaves through
+        if self.hits(world, block) {
+            let axis = next_axis(&next_boundary);
+            return Some(RaycastHit {
+                block: (block[0], block[1], block[2]).into(),
+                face: 2 * axis + if step[axis] > 0 { 0 } else { 1 },
+                distance: 0.0,
+                point: self.origin,
+            });
+        }
+        loop {
+            let axis = next_axis(&next_boundary);
+            let distance = next_boundary[axis];
+            if distance > self.max_distance {
+                return None;
+            }
+            block[axis] += step[axis];
+            next_boundary[axis] += boundary_step[axis];
+            if self.hits(world, block) {
+                return Some(RaycastHit {
+                    block: (block[0], block[1], block[2]).into(),
+                    face: 2 * axis + if step[axis] > 0 { 1 } else { 0 },
+                    distance,
+                    point: self.origin + self.dir * distance,
+                });
+            }
+        }
+    }
+
+    fn hits<BC: BlockContainer>(&self, world: &BC, block: [i64; 3]) -> bool {
+        let pos = (block[0], block[1], block[2]).into();
+        if self.ignore_non_solid {
+            world.is_block_full(pos)
+        } else {
+            world.is_block_targetable(pos)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A floor at y = 0, with water at y = 1
+    struct Floor;
+
+    impl BlockContainer for Floor {
+        fn is_block_full(&self, pos: BlockPos) -> bool {
+            pos.py == 0
+        }
+
+        fn is_block_targetable(&self, pos: BlockPos) -> bool {
+            pos.py <= 1
+        }
+    }
+
+    #[test]
+    fn test_raycast() {
+        let origin = Vector3::new(0.5, 3.5, 0.5);
+        let down = Vector3::new(0.0, -1.0, 0.0);
+
+        let hit = Raycast::new(origin, down, 10.0).cast(&Floor).unwrap();
+        assert_eq!(hit.block, (0, 1, 0).into());
+        assert_eq!(hit.face, 2);
+        assert!((hit.distance - 1.5).abs() < 1e-9);
+        assert!((hit.point - Vector3::new(0.5, 2.0, 0.5)).norm() < 1e-9);
+
+        let hit = Raycast::new(origin, down, 10.0)
+            .ignore_non_solid(true)
+            .cast(&Floor)
+            .unwrap();
+        assert_eq!(hit.block, (0, 0, 0).into());
+        assert!((hit.distance - 2.5).abs() < 1e-9);
+
+        assert!(Raycast::new(origin, down, 1.0).cast(&Floor).is_none());
+    }
+}
@@ -1,12 +1,15 @@
 use crate::{
     physics::camera::default_camera,
+    physics::config::PhysicsConfig,
     physics::player::PhysicsPlayer,
     physics::BlockContainer,
-    player::{PlayerId, PlayerInput},
+    player::{PlayerId, PlayerInput, RenderDistance},
+    world::{BlockPos, ChunkPos},
 };
 use nalgebra::Vector3;
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     time::{Duration, Instant},
 };
 
@@ -22,18 +25,50 @@ pub struct PhysicsState {
     pub players: HashMap<PlayerId, PhysicsPlayer>,
 }
 
+/// What happened to the players during a step of the simulation
+#[derive(Debug, Clone, Default)]
+pub struct StepResults {
+    /// The players that landed on the ground, and their fall damage in half hearts
+    pub fall_damage: Vec<(PlayerId, u32)>,
+}
+
 impl PhysicsState {
     /// Step the full physics simulation.
     /// For now, it just moves all connected players.
-    pub fn step_simulation<BC: BlockContainer>(&mut self, input: &Input, dt: Duration, world: &BC) {
-        let seconds_delta = dt.as_secs_f64();
+    pub fn step_simulation<BC: BlockContainer>(
+        &mut self,
+        input: &Input,
+        dt: Duration,
+        world: &BC,
+        config: &PhysicsConfig,
+    ) -> StepResults {
+        let mut results = StepResults::default();
         for (&id, input) in input.player_inputs.iter() {
-            let player = self.players.entry(id).or_insert(Default::default());
-            default_camera(player, *input, seconds_delta, world);
+            self.step_player(id, *input, dt, world, config, &mut results);
         }
         // Remove players that don't exist anymore
         self.players
             .retain(|id, _| input.player_inputs.contains_key(id));
+        results
+    }
+
+    /// Move a single player according to its input during `dt`
+    fn step_player<BC: BlockContainer>(
+        &mut self,
+        id: PlayerId,
+        input: PlayerInput,
+        dt: Duration,
+        world: &BC,
+        config: &PhysicsConfig,
+        results: &mut StepResults,
+    ) {
+        let player = self.players.entry(id).or_default();
+        if let Some(landing_velocity) = default_camera(player, input, dt.as_secs_f64(), world, config) {
+            let damage = config.fall_damage(landing_velocity);
+            if damage > 0 {
+                results.fall_damage.push((id, damage));
+            }
+        }
     }
 }
 
@@ -45,6 +80,56 @@ pub struct ServerState {
     pub input: Input,
 }
 
+/// Number of physics updates between two full updates.
+/// The updates in between only contain the changes, the full updates repair the client's state if some were dropped.
+const FULL_UPDATE_INTERVAL: u32 = 20;
+
+/// A physics update sent by the server to a single player. It only contains the players close to that player.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerStateUpdate {
+    /// Time of the update. If the receiving player sends timestamped inputs, it's the time of its last simulated input.
+    #[serde(with = "crate::network::serialization::instant")]
+    pub server_time: Instant,
+    /// If `true`, the update contains all the players of interest and the other players must be forgotten.
+    /// Otherwise, it only contains the players that changed since the previous update.
+    pub full: bool,
+    /// Physics and input of the players
+    pub players: HashMap<PlayerId, (PhysicsPlayer, PlayerInput)>,
+    /// Players that left the area of interest since the previous update
+    pub removed_players: Vec<PlayerId>,
+    /// Number of times the receiving player was teleported. It's sent in every update so that a dropped update doesn't
+    /// lose a teleport, and the client ignores the updates that were sent before a teleport it already knows about.
+    pub teleports: u32,
+}
+
+/// What the server has already sent to a player, to only send the changes in the next update
+#[derive(Debug, Default)]
+pub struct PlayerInterest {
+    sent_players: HashMap<PlayerId, (PhysicsPlayer, PlayerInput)>,
+    updates_since_full: u32,
+    /// The player followed by a spectator. It is sent in every update, whatever its distance.
+    followed_player: Option<PlayerId>,
+    /// Number of teleports of the player at the last full update. After a teleport, the player is sent in every update
+    /// until the next full update, so that a dropped update doesn't leave it where it was before the teleport.
+    full_update_teleports: u32,
+}
+
+impl PlayerInterest {
+    pub fn followed_player(&self) -> Option<PlayerId> {
+        self.followed_player
+    }
+
+    pub fn set_followed_player(&mut self, followed_player: Option<PlayerId>) {
+        self.followed_player = followed_player;
+    }
+}
+
+/// Duration of a step of the client's physics.
+/// The physics run at this fixed rate whatever the frame rate, and the camera is interpolated between the steps.
+pub const CLIENT_PHYSICS_TICK: Duration = Duration::from_nanos(1_000_000_000 / 60);
+/// Maximum number of steps during a single frame. After a longer frame, the first step covers the missed time.
+const MAX_TICKS_PER_FRAME: u32 = 10;
+
 /// The client's physics simulation
 pub struct ClientPhysicsSimulation {
     /// Previous client inputs
@@ -53,28 +138,71 @@ pub struct ClientPhysicsSimulation {
     last_server_state: ServerState,
     /// Current simulation state
     current_state: PhysicsState,
+    /// Simulation state one step before `current_state`
+    previous_state: PhysicsState,
+    /// Time of the last step
+    simulated_until: Instant,
+    /// Time of the last call to `step_simulation`, where the player is interpolated
+    frame_time: Instant,
     /// Dirty flag: whether the physics need to be computed again starting from the last server state.
     needs_recomputing: bool,
     /// Id of the current player
     player_id: PlayerId,
+    /// Physics constants of the world, they must be the same as the server's
+    config: PhysicsConfig,
+    /// Number of times the server teleported the player, as far as the client knows
+    teleports: u32,
 }
 
 impl ClientPhysicsSimulation {
     /// Create a new simulation from some `ServerState` and the client's id
-    pub fn new(server_state: ServerState, player_id: PlayerId) -> Self {
+    pub fn new(server_state: ServerState, player_id: PlayerId, config: PhysicsConfig) -> Self {
         Self {
             client_inputs: Vec::new(),
             last_server_state: server_state.clone(),
-            current_state: server_state.physics_state,
+            current_state: server_state.physics_state.clone(),
+            previous_state: server_state.physics_state,
+            simulated_until: server_state.server_time,
+            frame_time: server_state.server_time,
             needs_recomputing: false,
             player_id,
+            config,
+            teleports: 0,
         }
     }
 
+    /// Change the physics constants, for example after the game data was reloaded
+    pub fn set_config(&mut self, config: PhysicsConfig) {
+        self.config = config;
+        self.needs_recomputing = true;
+    }
+
     /// Process a server update
-    pub fn receive_server_update(&mut self, state: ServerState) {
-        // Save state
-        self.last_server_state = state;
+    pub fn receive_server_update(&mut self, update: ServerStateUpdate) {
+        // The update was sent before a teleport, the player would go back to where it was
+        if update.teleports < self.teleports {
+            return;
+        }
+        // Inputs sent before a teleport would move the player away from the teleport destination
+        if update.teleports > self.teleports {
+            self.teleports = update.teleports;
+            self.client_inputs.clear();
+        }
+        // Merge the update into the saved state
+        let state = &mut self.last_server_state;
+        if update.full {
+            state.physics_state.players.clear();
+            state.input.player_inputs.clear();
+        }
+        for id in update.removed_players.iter() {
+            state.physics_state.players.remove(id);
+            state.input.player_inputs.remove(id);
+        }
+        for (id, (player, input)) in update.players.into_iter() {
+            state.physics_state.players.insert(id, player);
+            state.input.player_inputs.insert(id, input);
+        }
+        state.server_time = update.server_time;
         // Drop inputs anterior to this server state
         let last_server_time = self.last_server_state.server_time;
         self.client_inputs
@@ -83,26 +211,82 @@ impl ClientPhysicsSimulation {
         self.needs_recomputing = true;
     }
 
-    /// Get the camera position of the client
+    /// Move the client player where the server teleported it, dropping its momentum and the inputs sent before.
+    /// `teleports` is the number of teleports of the player, the teleport is ignored if an update already applied it.
+    pub fn teleport(&mut self, position: Vector3<f64>, teleports: u32) {
+        if teleports <= self.teleports {
+            return;
+        }
+        self.teleports = teleports;
+        self.client_inputs.clear();
+        let mut states = [
+            &mut self.last_server_state.physics_state,
+            &mut self.current_state,
+            &mut self.previous_state,
+        ];
+        for state in states.iter_mut() {
+            if let Some(player) = state.players.get_mut(&self.player_id) {
+                player.aabb.pos = position;
+                player.velocity = Vector3::zeros();
+            }
+        }
+        self.needs_recomputing = true;
+    }
+
+    /// Get the camera position of the client, interpolated like `get_interpolated_player`
     pub fn get_camera_position(&self) -> Vector3<f64> {
-        self.current_state
-            .players
-            .get(&self.player_id)
-            .unwrap()
-            .get_camera_position()
+        self.get_interpolated_player().get_camera_position()
     }
 
-    /// Get the client player
+    /// Get the client player after the last step
     pub fn get_player(&self) -> &PhysicsPlayer {
         self.current_state.players.get(&self.player_id).unwrap()
     }
 
-    /// Step the simulation according to the current input and time
-    pub fn step_simulation<BC: BlockContainer>(&mut self, input: PlayerInput, time: Instant, world: &BC) {
+    /// Get the client player at the time of the last frame, interpolated between the last two steps.
+    /// It lags one step behind the simulation, but it moves smoothly at any frame rate.
+    pub fn get_interpolated_player(&self) -> PhysicsPlayer {
+        let current = self.get_player();
+        let mut player = current.clone();
+        if let Some(previous) = self.previous_state.players.get(&self.player_id) {
+            let elapsed = self.frame_time.saturating_duration_since(self.simulated_until);
+            let alpha = (elapsed.as_secs_f64() / CLIENT_PHYSICS_TICK.as_secs_f64()).min(1.0);
+            player.aabb.pos = previous.aabb.pos.lerp(&current.aabb.pos, alpha);
+        }
+        player
+    }
+
+    /// Get the id of the player
+    pub fn get_player_id(&self) -> PlayerId {
+        self.player_id
+    }
+
+    /// Get the id, the physics and the yaw of every other player
+    pub fn get_other_players(&self) -> Vec<(PlayerId, &PhysicsPlayer, f64)> {
+        self.current_state
+            .players
+            .iter()
+            .filter(|(&id, _)| id != self.player_id)
+            .map(|(&id, player)| {
+                let yaw = self
+                    .last_server_state
+                    .input
+                    .player_inputs
+                    .get(&id)
+                    .map_or(0.0, |input| input.yaw);
+                (id, player, yaw)
+            })
+            .collect()
+    }
+
+    /// Step the simulation with fixed steps until `time`, using the current input.
+    /// Return the times of the steps: the input must be sent to the server with each of them.
+    pub fn step_simulation<BC: BlockContainer>(&mut self, input: PlayerInput, time: Instant, world: &BC) -> Vec<Instant> {
         // Recompute simulation if necessary
         if self.needs_recomputing {
             self.needs_recomputing = false;
             self.current_state = self.last_server_state.physics_state.clone();
+            self.previous_state = self.current_state.clone();
 
             let mut previous_time = self.last_server_state.server_time;
             for &(time, player_input) in self.client_inputs.iter() {
@@ -112,54 +296,102 @@ impl ClientPhysicsSimulation {
                     .player_inputs
                     .insert(self.player_id, player_input);
                 // Only then can we step the simulation
+                self.previous_state = self.current_state.clone();
                 self.current_state.step_simulation(
                     &self.last_server_state.input,
                     time - previous_time,
                     world,
+                    &self.config,
                 );
                 previous_time = time;
             }
         }
 
-        let previous_instant = match self.client_inputs.last() {
-            Some((time, _)) => *time,
-            None => self.last_server_state.server_time,
-        };
+        // Skip the steps that don't fit in the frame, the next step covers their duration
+        let max_behind = CLIENT_PHYSICS_TICK * MAX_TICKS_PER_FRAME;
+        if time.saturating_duration_since(self.simulated_until) > max_behind {
+            self.simulated_until = time - max_behind;
+        }
 
-        // Store input for future processing
-        self.client_inputs.push((time, input));
-        self.last_server_state
-            .input
-            .player_inputs
-            .insert(self.player_id, input);
+        let mut ticks = Vec::new();
+        while self.simulated_until + CLIENT_PHYSICS_TICK <= time {
+            let tick = self.simulated_until + CLIENT_PHYSICS_TICK;
+            let previous_instant = match self.client_inputs.last() {
+                Some((time, _)) => *time,
+                None => self.last_server_state.server_time,
+            };
 
-        // Step local simulation
-        self.current_state.step_simulation(
-            &self.last_server_state.input,
-            time - previous_instant,
-            world,
-        );
+            // Store input for future processing
+            self.client_inputs.push((tick, input));
+            self.last_server_state
+                .input
+                .player_inputs
+                .insert(self.player_id, input);
+
+            // Step local simulation
+            self.previous_state = self.current_state.clone();
+            self.current_state.step_simulation(
+                &self.last_server_state.input,
+                tick - previous_instant,
+                world,
+                &self.config,
+            );
+            self.simulated_until = tick;
+            ticks.push(tick);
+        }
+        self.frame_time = time;
+        ticks
     }
 }
 
+/// The timestamped inputs of a player that the server didn't simulate yet
+#[derive(Debug)]
+struct InputBuffer {
+    /// Client time until which the player was simulated
+    simulated_until: Instant,
+    inputs: Vec<(Instant, PlayerInput)>,
+    /// Client time of the first input and server time when it was received.
+    /// The clocks of the client and of the server can't be compared, this gives the offset between them.
+    first_input: (Instant, Instant),
+}
+
 /// The server's physics simulation
 pub struct ServerPhysicsSimulation {
     /// The current state of the simulation
     server_state: ServerState,
+    /// The inputs of the players that sent timestamped inputs.
+    /// These players are simulated over the durations of their inputs instead of the duration of the server steps,
+    /// so that their movement doesn't depend on when the server receives the inputs.
+    input_buffers: HashMap<PlayerId, InputBuffer>,
+    /// Physics constants of the world
+    config: PhysicsConfig,
+    /// The players that are only sent to themselves and to the other hidden players
+    hidden_players: HashSet<PlayerId>,
+    /// Number of times each player was teleported
+    teleports: HashMap<PlayerId, u32>,
 }
 
 impl ServerPhysicsSimulation {
     /// Create a new simulation with no connected players starting at the current time
-    pub fn new() -> Self {
+    pub fn new(config: PhysicsConfig) -> Self {
         Self {
             server_state: ServerState {
                 physics_state: PhysicsState::default(),
                 server_time: Instant::now(),
                 input: Default::default(),
             },
+            input_buffers: HashMap::new(),
+            config,
+            hidden_players: HashSet::new(),
+            teleports: HashMap::new(),
         }
     }
 
+    /// Change the physics constants
+    pub fn set_config(&mut self, config: PhysicsConfig) {
+        self.config = config;
+    }
+
     /// Update the input of a player
     pub fn set_player_input(&mut self, player_id: PlayerId, input: PlayerInput) {
         self.server_state
@@ -168,23 +400,404 @@ impl ServerPhysicsSimulation {
             .insert(player_id, input);
     }
 
+    /// Add an input that the player sent at client time `time` and that the server received at `received`.
+    /// It applies between the previous input and `time`, like in the client's simulation.
+    /// Inputs that are older than the previous input are ignored. The client time can't advance more than the server
+    /// time since the first input, so that a client can't move for longer than the time that passed.
+    pub fn buffer_player_input(&mut self, player_id: PlayerId, time: Instant, received: Instant, input: PlayerInput) {
+        let buffer = self.input_buffers.entry(player_id).or_insert_with(|| InputBuffer {
+            simulated_until: time,
+            inputs: Vec::new(),
+            first_input: (time, received),
+        });
+        let (first_time, first_received) = buffer.first_input;
+        let time = time.min(first_time + received.saturating_duration_since(first_received));
+        let last_time = buffer.inputs.last().map_or(buffer.simulated_until, |&(time, _)| time);
+        if time > last_time {
+            buffer.inputs.push((time, input));
+        }
+        self.set_player_input(player_id, input);
+    }
+
+    /// Get the last input of a player
+    pub fn get_player_input(&self, player_id: PlayerId) -> Option<&PlayerInput> {
+        self.server_state.input.player_inputs.get(&player_id)
+    }
+
     /// Remove a player from the simulation
     pub fn remove(&mut self, player_id: PlayerId) {
         self.server_state.input.player_inputs.remove(&player_id);
+        self.input_buffers.remove(&player_id);
+        self.hidden_players.remove(&player_id);
+        self.teleports.remove(&player_id);
+    }
+
+    /// Hide a player from the players that are not hidden, or show it again
+    pub fn set_player_hidden(&mut self, player_id: PlayerId, hidden: bool) {
+        if hidden {
+            self.hidden_players.insert(player_id);
+        } else {
+            self.hidden_players.remove(&player_id);
+        }
     }
 
     /// Step the simulation according to the current input and time
-    pub fn step_simulation<BC: BlockContainer>(&mut self, time: Instant, world: &BC) {
-        self.server_state.physics_state.step_simulation(
-            &self.server_state.input,
-            time - self.server_state.server_time,
-            world,
-        );
-        self.server_state.server_time = time;
+    pub fn step_simulation<BC: BlockContainer>(&mut self, time: Instant, world: &BC) -> StepResults {
+        let ServerState { physics_state, input, server_time, .. } = &mut self.server_state;
+        let mut results = StepResults::default();
+        for (&id, &player_input) in input.player_inputs.iter() {
+            match self.input_buffers.get_mut(&id) {
+                // Apply every buffered input over its own duration
+                Some(buffer) => {
+                    for (input_time, buffered_input) in buffer.inputs.drain(..) {
+                        let dt = input_time - buffer.simulated_until;
+                        physics_state.step_player(id, buffered_input, dt, world, &self.config, &mut results);
+                        buffer.simulated_until = input_time;
+                    }
+                }
+                None => {
+                    physics_state.step_player(id, player_input, time - *server_time, world, &self.config, &mut results)
+                }
+            }
+        }
+        // Remove players that don't exist anymore
+        physics_state.players.retain(|id, _| input.player_inputs.contains_key(id));
+        *server_time = time;
+        results
+    }
+
+    /// Move a player to some position, without any movement in between.
+    /// It also works for the players that were just added and were never simulated.
+    pub fn teleport_player(&mut self, player_id: PlayerId, position: Vector3<f64>) {
+        if self.server_state.input.player_inputs.contains_key(&player_id) {
+            let player = self.server_state.physics_state.players.entry(player_id).or_default();
+            player.aabb.pos = position;
+            player.velocity = Vector3::zeros();
+            *self.teleports.entry(player_id).or_insert(0) += 1;
+            // The inputs sent before the client knows about the teleport would move the player away from the destination
+            if let Some(buffer) = self.input_buffers.get_mut(&player_id) {
+                if let Some(&(time, _)) = buffer.inputs.last() {
+                    buffer.simulated_until = time;
+                }
+                buffer.inputs.clear();
+            }
+        }
+    }
+
+    /// Set the velocity of a player, for example after teleporting it
+    pub fn set_player_velocity(&mut self, player_id: PlayerId, velocity: Vector3<f64>) {
+        if let Some(player) = self.server_state.physics_state.players.get_mut(&player_id) {
+            player.velocity = velocity;
+        }
+    }
+
+    /// Number of times a player was teleported
+    pub fn get_teleports(&self, player_id: PlayerId) -> u32 {
+        self.teleports.get(&player_id).copied().unwrap_or(0)
     }
 
     /// Get a reference to the current state of the simulation
     pub fn get_state(&self) -> &ServerState {
         &self.server_state
     }
+
+    /// Compute the next update of a player, only containing the players in its render distance
+    pub fn get_update_for_player(
+        &self,
+        player_id: PlayerId,
+        render_distance: RenderDistance,
+        interest: &mut PlayerInterest,
+    ) -> ServerStateUpdate {
+        let state = &self.server_state;
+        let player_chunk = |player: &PhysicsPlayer| -> ChunkPos {
+            BlockPos::from(player.get_camera_position()).containing_chunk_pos()
+        };
+        let center = state.physics_state.players.get(&player_id).map(player_chunk);
+        let sees_hidden = self.hidden_players.contains(&player_id);
+        let interesting: HashMap<PlayerId, (PhysicsPlayer, PlayerInput)> = state
+            .physics_state
+            .players
+            .iter()
+            .filter(|(&id, _)| id == player_id || sees_hidden || !self.hidden_players.contains(&id))
+            .filter(|(&id, player)| {
+                id == player_id
+                    || Some(id) == interest.followed_player
+                    || center.is_some_and(|center| {
+                        render_distance.is_chunk_visible(center, player_chunk(player))
+                    })
+            })
+            .map(|(&id, player)| {
+                let input = state.input.player_inputs.get(&id).copied().unwrap_or_default();
+                (id, (player.clone(), input))
+            })
+            .collect();
+
+        let full = interest.updates_since_full == 0;
+        interest.updates_since_full = (interest.updates_since_full + 1) % FULL_UPDATE_INTERVAL;
+        let teleports = self.get_teleports(player_id);
+        if full {
+            interest.full_update_teleports = teleports;
+        }
+        let teleported = teleports != interest.full_update_teleports;
+        let (players, removed_players) = if full {
+            (interesting.clone(), Vec::new())
+        } else {
+            // The followed player is always sent, so that a dropped update doesn't freeze the camera of the spectator
+            let changed = interesting
+                .iter()
+                .filter(|(&id, player)| {
+                    Some(id) == interest.followed_player
+                        || id == player_id && teleported
+                        || interest.sent_players.get(&id) != Some(player)
+                })
+                .map(|(&id, player)| (id, player.clone()))
+                .collect();
+            let removed = interest
+                .sent_players
+                .keys()
+                .filter(|id| !interesting.contains_key(id))
+                .copied()
+                .collect();
+            (changed, removed)
+        };
+        interest.sent_players = interesting;
+
+        // The client replays the inputs that the server didn't simulate yet
+        let server_time = self
+            .input_buffers
+            .get(&player_id)
+            .map_or(state.server_time, |buffer| buffer.simulated_until);
+        ServerStateUpdate {
+            server_time,
+            full,
+            players,
+            removed_players,
+            teleports,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct EmptyWorld;
+
+    impl BlockContainer for EmptyWorld {
+        fn is_block_full(&self, _pos: crate::world::BlockPos) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn test_update_only_contains_nearby_changes() {
+        let (near, far, me) = (PlayerId(0), PlayerId(1), PlayerId(2));
+        let mut simulation = ServerPhysicsSimulation::new(PhysicsConfig::default());
+        for &id in [near, far, me].iter() {
+            simulation.set_player_input(id, Default::default());
+        }
+        simulation.step_simulation(Instant::now(), &EmptyWorld);
+        simulation.teleport_player(far, Vector3::new(1000.0, 50.0, 0.0));
+
+        let mut interest = PlayerInterest::default();
+        let update = simulation.get_update_for_player(me, RenderDistance::default(), &mut interest);
+        assert!(update.full);
+        assert!(update.players.contains_key(&me) && update.players.contains_key(&near));
+        assert!(!update.players.contains_key(&far));
+
+        // Only the player that moved is sent again, and players that went away are removed
+        simulation.teleport_player(near, Vector3::new(2.0, 52.6, 2.0));
+        let update = simulation.get_update_for_player(me, RenderDistance::default(), &mut interest);
+        assert!(!update.full);
+        assert_eq!(update.players.keys().collect::<Vec<_>>(), vec![&near]);
+
+        simulation.teleport_player(near, Vector3::new(-1000.0, 50.0, 0.0));
+        let update = simulation.get_update_for_player(me, RenderDistance::default(), &mut interest);
+        assert!(update.players.is_empty());
+        assert_eq!(update.removed_players, vec![near]);
+    }
+
+    #[test]
+    fn test_hidden_players() {
+        let (me, spectator, other_spectator) = (PlayerId(0), PlayerId(1), PlayerId(2));
+        let mut simulation = ServerPhysicsSimulation::new(PhysicsConfig::default());
+        for &id in [me, spectator, other_spectator].iter() {
+            simulation.set_player_input(id, Default::default());
+            simulation.teleport_player(id, Vector3::new(0.0, 50.0, 0.0));
+        }
+        simulation.set_player_hidden(spectator, true);
+        simulation.set_player_hidden(other_spectator, true);
+
+        // The hidden players only see each other
+        let update = simulation.get_update_for_player(me, RenderDistance::default(), &mut PlayerInterest::default());
+        assert_eq!(update.players.keys().collect::<Vec<_>>(), vec![&me]);
+        let update =
+            simulation.get_update_for_player(spectator, RenderDistance::default(), &mut PlayerInterest::default());
+        assert_eq!(update.players.len(), 3);
+
+        // A player that is shown again is sent again
+        let mut interest = PlayerInterest::default();
+        simulation.get_update_for_player(me, RenderDistance::default(), &mut interest);
+        simulation.set_player_hidden(spectator, false);
+        let update = simulation.get_update_for_player(me, RenderDistance::default(), &mut interest);
+        assert_eq!(update.players.keys().collect::<Vec<_>>(), vec![&spectator]);
+    }
+
+    #[test]
+    fn test_followed_player_is_always_sent() {
+        let (followed, me) = (PlayerId(0), PlayerId(1));
+        let mut simulation = ServerPhysicsSimulation::new(PhysicsConfig::default());
+        for &id in [followed, me].iter() {
+            simulation.set_player_input(id, Default::default());
+        }
+        simulation.teleport_player(followed, Vector3::new(1000.0, 50.0, 0.0));
+
+        // The followed player is sent even if it's far and didn't move
+        let mut interest = PlayerInterest::default();
+        interest.set_followed_player(Some(followed));
+        let update = simulation.get_update_for_player(me, RenderDistance::default(), &mut interest);
+        assert!(update.players.contains_key(&followed));
+        let update = simulation.get_update_for_player(me, RenderDistance::default(), &mut interest);
+        assert!(!update.full);
+        assert_eq!(update.players.keys().collect::<Vec<_>>(), vec![&followed]);
+
+        interest.set_followed_player(None);
+        let update = simulation.get_update_for_player(me, RenderDistance::default(), &mut interest);
+        assert_eq!(update.removed_players, vec![followed]);
+    }
+
+    #[test]
+    fn test_buffered_inputs_ignore_batching() {
+        let player = PlayerId(0);
+        let forward = PlayerInput { key_move_forward: true, flying: false, ..Default::default() };
+        let idle = PlayerInput { flying: false, ..Default::default() };
+        // Simulate the same inputs, stepping the server after every `batch` inputs
+        let simulate = |batch: usize| {
+            let mut simulation = ServerPhysicsSimulation::new(PhysicsConfig::default());
+            simulation.set_player_input(player, idle);
+            simulation.teleport_player(player, Vector3::new(0.5, 0.0, 0.5));
+            let start = simulation.get_state().server_time;
+            for i in 0..60u64 {
+                let input = if i % 20 < 12 { forward } else { idle };
+                let time = start + Duration::from_millis(i * 17);
+                simulation.buffer_player_input(player, time, time, input);
+                if i as usize % batch == batch - 1 {
+                    simulation.step_simulation(start + Duration::from_millis(i * 17 + 200), &Floor);
+                }
+            }
+            simulation.step_simulation(start + Duration::from_secs(5), &Floor);
+            simulation.get_state().physics_state.players[&player].aabb.pos
+        };
+        let expected = simulate(1);
+        assert!(expected.z < 0.0);
+        for &batch in [3, 7, 60].iter() {
+            assert_eq!(simulate(batch), expected);
+        }
+    }
+
+    #[test]
+    fn test_buffered_inputs_limited_by_server_time() {
+        let player = PlayerId(0);
+        let forward = PlayerInput { key_move_forward: true, flying: false, ..Default::default() };
+        // The client time is unrelated to the server time, and the second input claims that an hour passed
+        let simulate = |client_dt: Duration| {
+            let mut simulation = ServerPhysicsSimulation::new(PhysicsConfig::default());
+            simulation.set_player_input(player, forward);
+            simulation.teleport_player(player, Vector3::new(0.5, 0.0, 0.5));
+            let start = simulation.get_state().server_time;
+            let client_start = start + Duration::from_secs(1000);
+            simulation.buffer_player_input(player, client_start, start, forward);
+            simulation.buffer_player_input(player, client_start + client_dt, start + Duration::from_millis(100), forward);
+            simulation.step_simulation(start + Duration::from_millis(100), &Floor);
+            simulation.get_state().physics_state.players[&player].aabb.pos
+        };
+        assert_eq!(simulate(Duration::from_secs(3600)), simulate(Duration::from_millis(100)));
+        assert!(simulate(Duration::from_millis(100)).z < 0.0);
+    }
+
+    #[test]
+    fn test_client_camera_interpolation() {
+        let player = PlayerId(0);
+        let start = Instant::now();
+        let mut physics_state = PhysicsState::default();
+        physics_state.players.insert(player, PhysicsPlayer::default());
+        let server_state = ServerState {
+            physics_state,
+            server_time: start,
+            input: Default::default(),
+        };
+        let mut simulation = ClientPhysicsSimulation::new(server_state, player, PhysicsConfig::default());
+        let falling = PlayerInput { flying: false, ..Default::default() };
+
+        // The player falls at a fixed rate, and the camera is between the last two steps
+        let ticks = simulation.step_simulation(falling, start + CLIENT_PHYSICS_TICK * 5 / 2, &EmptyWorld);
+        assert_eq!(ticks, vec![start + CLIENT_PHYSICS_TICK, start + CLIENT_PHYSICS_TICK * 2]);
+        let camera_y = simulation.get_camera_position().y;
+        let previous_y = simulation.previous_state.players[&player].get_camera_position().y;
+        let current_y = simulation.get_player().get_camera_position().y;
+        assert!(current_y < camera_y && camera_y < previous_y);
+        assert!(simulation.step_simulation(falling, start + CLIENT_PHYSICS_TICK * 11 / 4, &EmptyWorld).is_empty());
+        assert!(simulation.get_camera_position().y < camera_y);
+
+        // After a long frame, the number of steps is limited
+        let ticks = simulation.step_simulation(falling, start + CLIENT_PHYSICS_TICK * 100, &EmptyWorld);
+        assert_eq!(ticks.len(), MAX_TICKS_PER_FRAME as usize);
+        assert_eq!(ticks.last(), Some(&(start + CLIENT_PHYSICS_TICK * 100)));
+    }
+
+    #[test]
+    fn test_teleports_survive_dropped_updates() {
+        let player = PlayerId(0);
+        let mut server = ServerPhysicsSimulation::new(PhysicsConfig::default());
+        server.set_player_input(player, Default::default());
+        server.teleport_player(player, Vector3::new(0.0, 50.0, 0.0));
+        let mut interest = PlayerInterest::default();
+        let stale = server.get_update_for_player(player, RenderDistance::default(), &mut interest);
+        let mut client = ClientPhysicsSimulation::new(server.get_state().clone(), player, PhysicsConfig::default());
+
+        // The update after the teleport is dropped, the next one still carries the teleport
+        server.teleport_player(player, Vector3::new(100.0, 50.0, 0.0));
+        server.get_update_for_player(player, RenderDistance::default(), &mut interest);
+        let update = server.get_update_for_player(player, RenderDistance::default(), &mut interest);
+        assert_eq!(update.teleports, 2);
+        client.client_inputs.push((Instant::now(), Default::default()));
+        client.receive_server_update(update);
+        assert!(client.client_inputs.is_empty());
+        assert_eq!(client.last_server_state.physics_state.players[&player].aabb.pos.x, 100.0);
+
+        // An update sent before the teleport doesn't move the player back, and the teleport message is already applied
+        client.receive_server_update(stale);
+        assert_eq!(client.last_server_state.physics_state.players[&player].aabb.pos.x, 100.0);
+        client.teleport(Vector3::new(0.0, 50.0, 0.0), 2);
+        assert_eq!(client.last_server_state.physics_state.players[&player].aabb.pos.x, 100.0);
+        assert_eq!(client.last_server_state.physics_state.players[&player].aabb.pos.x, 100.0);
+    }
+
+    struct Floor;
+
+    impl BlockContainer for Floor {
+        fn is_block_full(&self, pos: crate::world::BlockPos) -> bool {
+            pos.py < 0
+        }
+    }
+
+    #[test]
+    fn test_fall_damage() {
+        let fall = |height: f64| {
+            let player = PlayerId(0);
+            let mut simulation = ServerPhysicsSimulation::new(PhysicsConfig::default());
+            simulation.set_player_input(player, PlayerInput { flying: false, ..Default::default() });
+            simulation.teleport_player(player, Vector3::new(0.5, height, 0.5));
+            let start = simulation.get_state().server_time;
+            let mut damage = 0;
+            for i in 1..=300 {
+                let results = simulation.step_simulation(start + Duration::from_millis(i * 10), &Floor);
+                damage += results.fall_damage.iter().map(|&(_, damage)| damage).sum::<u32>();
+            }
+            damage
+        };
+        assert_eq!(fall(2.0), 0);
+        assert!(fall(20.0) > 0);
+        assert!(fall(40.0) >= fall(20.0));
+    }
 }
@@ -1,8 +1,12 @@
+use crate::config::{DEFAULT_REACH, MAX_COOLDOWN, MAX_REACH};
 use crate::world::ChunkPos;
-use serde::Deserialize;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
 
 /// The input of a player
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct PlayerInput {
     pub key_move_forward: bool,
     pub key_move_left: bool,
@@ -13,6 +17,8 @@ pub struct PlayerInput {
     pub yaw: f64,
     pub pitch: f64,
     pub flying: bool,
+    /// The player flies through the blocks. Set from the gamemode, like `flying`.
+    pub noclip: bool,
 }
 
 impl Default for PlayerInput {
@@ -27,16 +33,140 @@ impl Default for PlayerInput {
             yaw: 0.0,
             pitch: 0.0,
             flying: true,
+            noclip: false,
+        }
+    }
+}
+
+/// How a player can move and interact with the world
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum GameMode {
+    /// The player can fly and break blocks instantly from far away
+    #[default]
+    Creative,
+    /// The player walks, and must be close to the blocks and hold the button to break them
+    Survival,
+    /// The player flies through the blocks and can follow the other players with its camera.
+    /// It doesn't interact with the world, and only the other spectators see it.
+    Spectator,
+}
+
+/// Health of a player when it spawns, in half hearts
+pub const MAX_HEALTH: u32 = 20;
+
+impl GameMode {
+    pub fn can_fly(self) -> bool {
+        self == GameMode::Creative || self == GameMode::Spectator
+    }
+
+    /// No collisions and no gravity
+    pub fn has_noclip(self) -> bool {
+        self == GameMode::Spectator
+    }
+
+    /// Only the players with the same gamemode see the hidden players
+    pub fn is_hidden(self) -> bool {
+        self == GameMode::Spectator
+    }
+
+    pub fn can_interact(self) -> bool {
+        self != GameMode::Spectator
+    }
+
+    pub fn takes_damage(self) -> bool {
+        self == GameMode::Survival
+    }
+
+    pub fn breaks_instantly(self) -> bool {
+        self == GameMode::Creative
+    }
+
+    /// Time needed to break a block with the given hardness, in seconds
+    pub fn break_time(self, hardness: f32) -> f64 {
+        if self.breaks_instantly() {
+            0.0
+        } else {
+            hardness as f64
+        }
+    }
+}
+
+/// How far and how often a player can interact with the blocks
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct InteractionRules {
+    /// Maximum distance at which the player can interact with blocks
+    pub reach: f64,
+    /// Minimum time between two placed blocks, in seconds
+    pub place_cooldown: f64,
+    /// Minimum time between two broken blocks, in seconds
+    pub break_cooldown: f64,
+}
+
+impl InteractionRules {
+    /// Check that the rules are in the allowed ranges
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.reach > 0.0 && self.reach <= MAX_REACH) {
+            return Err(format!("The reach must be between 0 and {} blocks, got {}", MAX_REACH, self.reach));
+        }
+        for &(name, cooldown) in [("place", self.place_cooldown), ("break", self.break_cooldown)].iter() {
+            if !(0.0..=MAX_COOLDOWN).contains(&cooldown) {
+                return Err(format!(
+                    "The {} cooldown must be between 0 and {} seconds, got {}",
+                    name, MAX_COOLDOWN, cooldown
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The interaction rules of every gamemode, set by the server
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InteractionConfig {
+    pub creative: InteractionRules,
+    pub survival: InteractionRules,
+}
+
+impl InteractionConfig {
+    pub fn rules(&self, gamemode: GameMode) -> InteractionRules {
+        match gamemode {
+            // The spectators don't interact with the blocks, but they can point at them from as far as in creative
+            GameMode::Creative | GameMode::Spectator => self.creative,
+            GameMode::Survival => self.survival,
+        }
+    }
+
+    /// Check that the rules of every gamemode are in the allowed ranges
+    pub fn validate(&self) -> Result<(), String> {
+        self.creative.validate().map_err(|e| format!("Creative: {}", e))?;
+        self.survival.validate().map_err(|e| format!("Survival: {}", e))
+    }
+}
+
+impl Default for InteractionConfig {
+    fn default() -> Self {
+        Self {
+            creative: InteractionRules {
+                reach: DEFAULT_REACH,
+                place_cooldown: 0.1,
+                break_cooldown: 0.1,
+            },
+            survival: InteractionRules {
+                reach: 5.0,
+                place_cooldown: 0.2,
+                break_cooldown: 0.25,
+            },
         }
     }
 }
 
 /// Some unique player id.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct PlayerId(pub(crate) u16);
 
 /// The render distance of a player
-#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct RenderDistance {
     pub x_max: u64,
     pub x_min: u64,
@@ -124,10 +254,18 @@ impl Default for RenderDistance {
     }
 }
 
+/// The offsets of the close chunks with their squared distance, sorted by distance
+type CloseChunkOffsets = Vec<(u64, ChunkPos)>;
+
+lazy_static! {
+    /// The offsets of the render distances in use, shared by the players with the same render distance
+    static ref CLOSE_CHUNK_OFFSETS: Mutex<HashMap<RenderDistance, Weak<CloseChunkOffsets>>> = Mutex::new(HashMap::new());
+}
+
 /// All the visible chunks for a given `RenderDistance` sorted by distance
 pub struct CloseChunks {
-    /// The chunks
-    close_chunks: Vec<ChunkPos>,
+    /// The offsets of the chunks from the player
+    offsets: Arc<CloseChunkOffsets>,
     /// The `RenderDistance` for which the chunks are valid
     render_distance: RenderDistance,
 }
@@ -135,26 +273,47 @@ pub struct CloseChunks {
 impl CloseChunks {
     pub fn new(render_distance: &RenderDistance) -> Self {
         Self {
-            close_chunks: get_close_chunks(render_distance),
+            offsets: get_close_chunk_offsets(render_distance),
             render_distance: *render_distance,
         }
     }
 
     pub fn update(&mut self, render_distance: &RenderDistance) {
         if *render_distance != self.render_distance {
-            self.close_chunks = get_close_chunks(render_distance);
+            self.offsets = get_close_chunk_offsets(render_distance);
             self.render_distance = *render_distance;
         }
     }
 
-    pub fn get_close_chunks(&self) -> &Vec<ChunkPos> {
-        &self.close_chunks
+    /// Iterate over the close chunks of a player in `player_chunk`, closest first
+    pub fn iter(&self, player_chunk: ChunkPos) -> impl Iterator<Item = ChunkPos> + '_ {
+        self.iter_with_distance(player_chunk).map(|(_, pos)| pos)
+    }
+
+    /// Iterate over the close chunks of a player in `player_chunk` with their squared distance to the player,
+    /// closest first
+    pub fn iter_with_distance(&self, player_chunk: ChunkPos) -> impl Iterator<Item = (u64, ChunkPos)> + '_ {
+        self.offsets
+            .iter()
+            .map(move |&(square_dist, offset)| (square_dist, offset.offset_by_pos(player_chunk)))
     }
 }
 
-fn get_close_chunks(render_distance: &RenderDistance) -> Vec<ChunkPos> {
+/// Get the sorted offsets of a render distance, computing them if no player uses them yet
+fn get_close_chunk_offsets(render_distance: &RenderDistance) -> Arc<CloseChunkOffsets> {
+    let mut cache = CLOSE_CHUNK_OFFSETS.lock().unwrap();
+    if let Some(offsets) = cache.get(render_distance).and_then(Weak::upgrade) {
+        return offsets;
+    }
     let origin = ChunkPos::from([0, 0, 0]);
-    let mut adjacent_positions: Vec<_> = render_distance.iterate_around_player(origin).collect();
-    adjacent_positions.sort_by_key(|pos| origin.squared_euclidian_distance(*pos));
-    adjacent_positions
+    let mut offsets: Vec<_> = render_distance
+        .iterate_around_player(origin)
+        .map(|pos| (origin.squared_euclidian_distance(pos), pos))
+        .collect();
+    offsets.sort_by_key(|&(square_dist, _)| square_dist);
+    let offsets = Arc::new(offsets);
+    // Forget the render distances that nobody uses anymore
+    cache.retain(|_, offsets| offsets.strong_count() > 0);
+    cache.insert(*render_distance, Arc::downgrade(&offsets));
+    offsets
 }
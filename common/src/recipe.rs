@@ -0,0 +1,108 @@
+use crate::{
+    block::Block,
+    inventory::{Inventory, InventoryItem, ItemStack},
+    item::Item,
+    registry::Registry,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+pub type RecipeId = u32;
+
+/// A crafting recipe, as written in the data files. The ingredients and the result are
+/// `(name, count)` pairs, where the name is the name of an item or of a block.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename = "Recipe")]
+pub struct RecipeData {
+    pub ingredients: Vec<(String, u32)>,
+    pub result: (String, u32),
+}
+
+/// A crafting recipe that turns some ingredients of an inventory into a result
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Recipe {
+    pub ingredients: Vec<ItemStack>,
+    pub result: ItemStack,
+}
+
+impl Recipe {
+    /// Resolve the names of the recipe, looking at the items first and then at the blocks
+    pub fn from_data(data: &RecipeData, items: &Registry<Item>, blocks: &Registry<Block>) -> Result<Self> {
+        let stack = |(name, count): &(String, u32)| -> Result<ItemStack> {
+            let item = if let Some(id) = items.get_id_by_name(name) {
+                InventoryItem::Item(id)
+            } else if let Some(id) = blocks.get_id_by_name(name).filter(|&id| id != 0) {
+                InventoryItem::Block(id as u16)
+            } else {
+                anyhow::bail!("Unknown item or block: {}", name);
+            };
+            if *count == 0 {
+                anyhow::bail!("The count of {} must be positive", name);
+            }
+            Ok(ItemStack { item, count: *count })
+        };
+        Ok(Self {
+            ingredients: data.ingredients.iter().map(stack).collect::<Result<_>>()?,
+            result: stack(&data.result)?,
+        })
+    }
+
+    /// Whether the inventory contains all the ingredients
+    pub fn can_craft(&self, inventory: &Inventory) -> bool {
+        self.ingredients
+            .iter()
+            .all(|ingredient| inventory.count(ingredient.item) >= ingredient.count)
+    }
+
+    /// Replace the ingredients by the result in the inventory.
+    /// The inventory is not modified if an ingredient is missing or if the result doesn't fit.
+    pub fn craft(&self, inventory: &mut Inventory) -> Result<(), String> {
+        if !self.can_craft(inventory) {
+            return Err("Some ingredients are missing".to_owned());
+        }
+        let mut crafted = inventory.clone();
+        for ingredient in self.ingredients.iter() {
+            crafted.remove(ingredient.item, ingredient.count);
+        }
+        if crafted.add(self.result.item, self.result.count) > 0 {
+            return Err("The inventory is full".to_owned());
+        }
+        *inventory = crafted;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_craft() {
+        let wood = InventoryItem::Block(1);
+        let leaves = InventoryItem::Block(2);
+        let bed = InventoryItem::Block(3);
+        let recipe = Recipe {
+            ingredients: vec![ItemStack { item: wood, count: 3 }, ItemStack { item: leaves, count: 3 }],
+            result: ItemStack { item: bed, count: 1 },
+        };
+        let mut inventory = Inventory::new(2);
+        inventory.add(wood, 5);
+        assert!(!recipe.can_craft(&inventory));
+        assert!(recipe.craft(&mut inventory).is_err());
+        assert_eq!(inventory.count(wood), 5);
+
+        inventory.add(leaves, 3);
+        assert!(recipe.can_craft(&inventory));
+        assert_eq!(recipe.craft(&mut inventory), Ok(()));
+        assert_eq!(inventory.count(wood), 2);
+        assert_eq!(inventory.count(leaves), 0);
+        assert_eq!(inventory.count(bed), 1);
+
+        // The result doesn't fit if the ingredients don't free a slot
+        let mut inventory = Inventory::new(2);
+        inventory.add(wood, 10);
+        inventory.add(leaves, 10);
+        assert_eq!(recipe.craft(&mut inventory), Err("The inventory is full".to_owned()));
+        assert_eq!(inventory.count(wood), 10);
+    }
+}
@@ -1,3 +1,4 @@
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 #[derive(Debug)]
@@ -18,7 +19,7 @@ impl std::fmt::Display for RegistryError {
 impl std::error::Error for RegistryError {}
 
 /// A way to store elements by name or by id
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Registry<T> {
     name_to_id: HashMap<String, u32>,
     id_to_name: Vec<String>,
@@ -42,6 +43,15 @@ impl<T> Registry<T> {
         self.name_to_id.get(name).cloned()
     }
 
+    pub fn get_name_by_id(&self, id: u32) -> Option<&String> {
+        self.id_to_name.get(id as usize)
+    }
+
+    /// The names of the registered values, indexed by id
+    pub fn get_names(&self) -> &[String] {
+        &self.id_to_name
+    }
+
     pub fn get_number_of_ids(&self) -> u32 {
         return self.id_to_name.len() as u32;
     }
@@ -0,0 +1,114 @@
+//! Seasons: an optional long cycle of the world time that changes the colors of the plants.
+//!
+//! The server advances the time of the year and sends the current season state to the clients. The seasonal blocks
+//! are tinted and use the texture frame of the current season in the shaders, so the chunks don't need to be meshed
+//! again when the season changes.
+use crate::time::DAY_DURATION;
+use serde::{Deserialize, Serialize};
+
+/// Number of seasons in a year: spring, summer, autumn and winter
+pub const SEASON_COUNT: usize = 4;
+
+/// Settings of the seasonal cycle
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SeasonCycle {
+    /// Number of days of every season
+    pub days_per_season: f64,
+    /// Color multiplied with the seasonal blocks in the middle of spring, summer, autumn and winter.
+    /// The tint blends smoothly from one season to the next.
+    pub tints: [[f32; 3]; SEASON_COUNT],
+}
+
+impl Default for SeasonCycle {
+    fn default() -> Self {
+        Self {
+            days_per_season: 7.0,
+            tints: [
+                [0.95, 1.05, 0.9],
+                [1.0, 1.0, 1.0],
+                [1.15, 0.85, 0.6],
+                [0.85, 0.9, 0.95],
+            ],
+        }
+    }
+}
+
+impl SeasonCycle {
+    /// The tint and the variant of the seasonal blocks at some time of the year
+    pub fn state(&self, time_of_year: TimeOfYear) -> SeasonState {
+        // Position relative to the middles of the seasons
+        let position = time_of_year.0 * SEASON_COUNT as f64 - 0.5;
+        let blend = (position - position.floor()) as f32;
+        let previous = (position.floor() as i64).rem_euclid(SEASON_COUNT as i64) as usize;
+        let next = (previous + 1) % SEASON_COUNT;
+        let channel = |i: usize| self.tints[previous][i] * (1.0 - blend) + self.tints[next][i] * blend;
+        SeasonState {
+            tint: [channel(0), channel(1), channel(2)],
+            variant: time_of_year.season() as u32,
+        }
+    }
+}
+
+/// Time of the year, between 0 and 1. The year starts at the beginning of spring.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct TimeOfYear(pub f64);
+
+impl TimeOfYear {
+    /// Advance the time by some number of seconds
+    pub fn advance(&mut self, seconds: f64, cycle: &SeasonCycle) {
+        let year_duration = DAY_DURATION * cycle.days_per_season.max(1e-3) * SEASON_COUNT as f64;
+        self.0 = (self.0 + seconds / year_duration).rem_euclid(1.0);
+    }
+
+    /// Index of the current season, 0 for spring
+    pub fn season(self) -> usize {
+        ((self.0 * SEASON_COUNT as f64) as usize).min(SEASON_COUNT - 1)
+    }
+}
+
+/// How the clients render the seasonal blocks
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SeasonState {
+    /// Color multiplied with the seasonal blocks
+    pub tint: [f32; 3],
+    /// Frame of the seasonal textures that have one frame per season
+    pub variant: u32,
+}
+
+impl Default for SeasonState {
+    /// Without seasons, the seasonal blocks look like in spring without any tint
+    fn default() -> Self {
+        Self {
+            tint: [1.0; 3],
+            variant: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_season_state() {
+        let cycle = SeasonCycle::default();
+        // The middle of every season has the tint of the season
+        for season in 0..SEASON_COUNT {
+            let state = cycle.state(TimeOfYear((season as f64 + 0.5) / SEASON_COUNT as f64));
+            assert_eq!(state.variant, season as u32);
+            for (channel, expected) in state.tint.iter().zip(cycle.tints[season].iter()) {
+                assert!((channel - expected).abs() < 1e-5);
+            }
+        }
+        // The start of the year is halfway between winter and spring
+        let state = cycle.state(TimeOfYear(0.0));
+        assert_eq!(state.variant, 0);
+        assert!((state.tint[2] - (cycle.tints[3][2] + cycle.tints[0][2]) / 2.0).abs() < 1e-5);
+
+        let mut time = TimeOfYear(0.9);
+        time.advance(DAY_DURATION * cycle.days_per_season * 2.0, &cycle);
+        assert!((time.0 - 0.4).abs() < 1e-9);
+        assert_eq!(time.season(), 1);
+    }
+}
@@ -0,0 +1,19 @@
+use serde::{Deserialize, Serialize};
+
+pub type SoundId = u32;
+
+/// A sound that is played when something happens in the game.
+/// This is the data provided by the creator of the sound.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename = "Sound")]
+pub struct SoundEvent {
+    /// Sound files in `data/sounds/files`. One of them is chosen every time the sound is played.
+    pub files: Vec<String>,
+    /// Volume between 0 and 1
+    #[serde(default = "default_volume")]
+    pub volume: f32,
+}
+
+fn default_volume() -> f32 {
+    1.0
+}
@@ -1,3 +1,5 @@
+use nalgebra::Vector3;
+use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
 use std::time::{Duration, Instant};
 
@@ -117,4 +119,52 @@ impl BreakdownCounter {
         let total_micros = self.total_micros.iter().sum::<u128>() as f64;
         self.part_names.drain(..).zip(self.total_micros.iter()).map(|(s, m)| (s, *m as f64 / total_micros)).collect()
     }
-}
\ No newline at end of file
+
+    /// Get the duration of every part of the current frame in milliseconds.
+    /// Must be called before `extract_part_averages`.
+    pub fn last_frame_millis(&self) -> Vec<(String, f64)> {
+        let durations = self.times.back().map(|(_, durations)| &durations[..]).unwrap_or(&[]);
+        self.part_names
+            .iter()
+            .zip(durations.iter())
+            .map(|(s, d)| (s.clone(), d.as_secs_f64() * 1000.0))
+            .collect()
+    }
+}
+
+/// Duration of a full day, in seconds
+pub const DAY_DURATION: f64 = 1200.0;
+
+/// Time of the day, as a fraction of the day in `[0, 1)`.
+/// The sun rises at 0, is at its highest at 0.25, and sets at 0.5. Then the night lasts until 1.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TimeOfDay(pub f64);
+
+impl TimeOfDay {
+    /// Advance the time by some number of seconds
+    pub fn advance(&mut self, seconds: f64) {
+        self.0 = (self.0 + seconds / DAY_DURATION).rem_euclid(1.0);
+    }
+
+    pub fn is_night(self) -> bool {
+        self.0 >= 0.5
+    }
+
+    /// The time at which the next day starts
+    pub fn next_morning() -> Self {
+        Self(0.0)
+    }
+
+    /// Normalized direction of the sun. It is below the horizon at night.
+    pub fn sun_direction(self) -> Vector3<f64> {
+        let angle = self.0 * 2.0 * std::f64::consts::PI;
+        Vector3::new(angle.cos(), angle.sin(), 0.5).normalize()
+    }
+}
+
+impl Default for TimeOfDay {
+    fn default() -> Self {
+        // Start in the morning
+        Self(0.1)
+    }
+}
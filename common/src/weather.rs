@@ -0,0 +1,40 @@
+use serde::{Deserialize, Serialize};
+
+/// What falls from the sky
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Precipitation {
+    Rain,
+    Snow,
+}
+
+/// Weather of the world, decided by the server
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Weather {
+    /// `None` if the sky is clear
+    pub precipitation: Option<Precipitation>,
+    /// How strong the precipitation is, in `[0, 1]`
+    pub intensity: f32,
+}
+
+impl Weather {
+    pub fn clear() -> Self {
+        Self {
+            precipitation: None,
+            intensity: 0.0,
+        }
+    }
+
+    /// Intensity of the rain, 0 if it's not raining
+    pub fn rain_intensity(self) -> f32 {
+        match self.precipitation {
+            Some(Precipitation::Rain) => self.intensity,
+            _ => 0.0,
+        }
+    }
+}
+
+impl Default for Weather {
+    fn default() -> Self {
+        Self::clear()
+    }
+}
@@ -27,27 +27,26 @@ impl<Input: Send + 'static, Output: Send + 'static, State: WorkerState<Input, Ou
     pub fn new(state: State, channel_size: usize, name: String) -> Self {
         let (in_sender, in_receiver) = bounded::<Input>(channel_size);
         let (out_sender, out_receiver) = bounded::<Output>(channel_size);
+        spawn_worker_thread(state, in_receiver, out_sender, name);
 
-        std::thread::spawn(move || { // TODO: debug timing
-            let mut state = state;
-            let mut timing = AverageTimeCounter::new();
-            while let Ok(input) = in_receiver.recv() {
-                // Compute
-                let t1 = Instant::now();
-                let output = state.compute(input);
-                let t2 = Instant::now();
-                timing.add_time(t2 - t1);
-
-                // Send debug info
-                send_worker_perf("Workers", &name, &name, timing.average_time_micros() as f32, timing.average_iter_per_sec(), 0);
+        Self {
+            to_worker: in_sender,
+            from_worker: out_receiver,
+            _phantom: PhantomData,
+        }
+    }
 
-                // Send result
-                match out_sender.send(output) {
-                    Ok(()) => (),
-                    Err(_) => break,
-                }
-            }
-        });
+    /// Start `threads` workers sharing the same queues, each with its own copy of the state.
+    /// The outputs can be produced in a different order than the inputs.
+    pub fn new_pool(state: State, threads: usize, channel_size: usize, name: String) -> Self
+    where
+        State: Clone,
+    {
+        let (in_sender, in_receiver) = bounded::<Input>(channel_size);
+        let (out_sender, out_receiver) = bounded::<Output>(channel_size);
+        for i in 0..threads.max(1) {
+            spawn_worker_thread(state.clone(), in_receiver.clone(), out_sender.clone(), format!("{} {}", name, i));
+        }
 
         Self {
             to_worker: in_sender,
@@ -68,4 +67,32 @@ impl<Input: Send + 'static, Output: Send + 'static, State: WorkerState<Input, Ou
     pub fn get_result(&self) -> Option<Output> {
        self.from_worker.try_recv().ok()
     }
-}
\ No newline at end of file
+}
+
+fn spawn_worker_thread<Input: Send + 'static, Output: Send + 'static, State: WorkerState<Input, Output> + Send + 'static>(
+    state: State,
+    in_receiver: Receiver<Input>,
+    out_sender: Sender<Output>,
+    name: String,
+) {
+    std::thread::spawn(move || { // TODO: debug timing
+        let mut state = state;
+        let mut timing = AverageTimeCounter::new();
+        while let Ok(input) = in_receiver.recv() {
+            // Compute
+            let t1 = Instant::now();
+            let output = state.compute(input);
+            let t2 = Instant::now();
+            timing.add_time(t2 - t1);
+
+            // Send debug info
+            send_worker_perf("Workers", &name, &name, timing.average_time_micros() as f32, timing.average_iter_per_sec(), 0);
+
+            // Send result
+            match out_sender.send(output) {
+                Ok(()) => (),
+                Err(_) => break,
+            }
+        }
+    });
+}
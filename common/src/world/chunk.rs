@@ -0,0 +1,366 @@
+use super::{ChunkPos, CHUNK_SIZE};
+use crate::block::BlockId;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+/// Number of blocks in a chunk
+const CHUNK_VOLUME: usize = (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize;
+
+#[inline(always)]
+fn block_index((px, py, pz): (u32, u32, u32)) -> usize {
+    (px * CHUNK_SIZE * CHUNK_SIZE + py * CHUNK_SIZE + pz) as usize
+}
+
+/// Smallest power of two number of bits that can index a palette of `palette_len` blocks
+fn bits_for_palette(palette_len: usize) -> u32 {
+    let needed = usize::BITS - (palette_len - 1).leading_zeros();
+    if needed == 0 {
+        0
+    } else {
+        needed.next_power_of_two()
+    }
+}
+
+/// One palette index per block, packed in `u64` words.
+/// The number of bits per index is a power of two so that an index never straddles two words,
+/// and 0 bits means that all the indices are 0.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PackedIndices {
+    bits: u32,
+    words: Vec<u64>,
+}
+
+impl PackedIndices {
+    fn new(bits: u32) -> Self {
+        Self {
+            bits,
+            words: vec![0; CHUNK_VOLUME * bits as usize / 64],
+        }
+    }
+
+    /// Word containing index `i`, and the shift of the index in that word
+    #[inline(always)]
+    fn locate(&self, i: usize) -> (usize, u32) {
+        let indices_per_word_log2 = 6 - self.bits.trailing_zeros();
+        let word = i >> indices_per_word_log2;
+        let shift = (i & ((1 << indices_per_word_log2) - 1)) as u32 * self.bits;
+        (word, shift)
+    }
+
+    #[inline(always)]
+    fn mask(&self) -> u64 {
+        (1u64 << self.bits) - 1
+    }
+
+    #[inline(always)]
+    fn get(&self, i: usize) -> usize {
+        if self.bits == 0 {
+            return 0;
+        }
+        let (word, shift) = self.locate(i);
+        ((self.words[word] >> shift) & self.mask()) as usize
+    }
+
+    #[inline(always)]
+    unsafe fn get_unchecked(&self, i: usize) -> usize {
+        if self.bits == 0 {
+            return 0;
+        }
+        let (word, shift) = self.locate(i);
+        ((*self.words.get_unchecked(word) >> shift) & self.mask()) as usize
+    }
+
+    #[inline(always)]
+    fn set(&mut self, i: usize, value: usize) {
+        if self.bits == 0 {
+            return;
+        }
+        let (word, shift) = self.locate(i);
+        let mask = self.mask();
+        let word = &mut self.words[word];
+        *word = (*word & !(mask << shift)) | ((value as u64 & mask) << shift);
+    }
+
+    #[inline(always)]
+    unsafe fn set_unchecked(&mut self, i: usize, value: usize) {
+        if self.bits == 0 {
+            return;
+        }
+        let (word, shift) = self.locate(i);
+        let mask = self.mask();
+        let word = self.words.get_unchecked_mut(word);
+        *word = (*word & !(mask << shift)) | ((value as u64 & mask) << shift);
+    }
+
+    /// Copy the indices, mapping them with `f` and storing them with `bits` bits
+    fn repack(&self, bits: u32, f: impl Fn(usize) -> usize) -> Self {
+        let mut repacked = Self::new(bits);
+        if bits > 0 {
+            for i in 0..CHUNK_VOLUME {
+                repacked.set(i, f(self.get(i)));
+            }
+        }
+        repacked
+    }
+}
+
+/// A chunk. The blocks are stored as indices into a palette of the blocks that the chunk contains,
+/// using as few bits per block as possible.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "UncheckedChunk")]
+pub struct Chunk {
+    pub pos: ChunkPos,
+    /// The different blocks of the chunk. It can contain blocks that were replaced, see `compact`.
+    palette: Vec<BlockId>,
+    indices: PackedIndices,
+}
+
+/// A chunk read from the disk or the network, that is only used once it is validated
+#[derive(Deserialize)]
+struct UncheckedChunk {
+    pos: ChunkPos,
+    palette: Vec<BlockId>,
+    indices: PackedIndices,
+}
+
+impl TryFrom<UncheckedChunk> for Chunk {
+    type Error = String;
+
+    /// Check that every block index is in the palette, so that the accessors can't read out of bounds
+    fn try_from(UncheckedChunk { pos, palette, indices }: UncheckedChunk) -> Result<Self, Self::Error> {
+        // Without duplicates, the palette can't contain more blocks than there are block ids
+        if palette.is_empty() || palette.len() > BlockId::MAX as usize + 1 {
+            return Err(format!("Invalid palette length {}", palette.len()));
+        }
+        let bits = bits_for_palette(palette.len());
+        if indices.bits != bits {
+            return Err(format!("{} bits per block for a palette of {} blocks", indices.bits, palette.len()));
+        }
+        if indices.words.len() != CHUNK_VOLUME * bits as usize / 64 {
+            return Err(format!("{} words for {} bits per block", indices.words.len(), bits));
+        }
+        if (0..CHUNK_VOLUME).any(|i| indices.get(i) >= palette.len()) {
+            return Err("Block index outside of the palette".to_owned());
+        }
+        Ok(Self { pos, palette, indices })
+    }
+}
+
+impl Chunk {
+    /// Create a new empty chunk
+    pub fn new(pos: ChunkPos) -> Self {
+        Self {
+            pos,
+            palette: vec![0],
+            indices: PackedIndices::new(0),
+        }
+    }
+
+    /// Get block at some position
+    #[inline(always)]
+    pub fn get_block_at(&self, pos: (u32, u32, u32)) -> BlockId {
+        self.palette[self.indices.get(block_index(pos))]
+    }
+
+    /// Set block at some position
+    #[inline(always)]
+    pub fn set_block_at(&mut self, pos: (u32, u32, u32), block: BlockId) {
+        let palette_index = self.palette_index(block);
+        self.indices.set(block_index(pos), palette_index);
+    }
+
+    /// Get block at some position, without bounds checking
+    ///
+    /// # Safety
+    /// Every coordinate of `pos` must be less than `CHUNK_SIZE`.
+    #[inline(always)]
+    pub unsafe fn get_block_at_unsafe(&self, pos: (u32, u32, u32)) -> BlockId {
+        *self.palette.get_unchecked(self.indices.get_unchecked(block_index(pos)))
+    }
+
+    /// Set block at some position, without bounds checking
+    ///
+    /// # Safety
+    /// Every coordinate of `pos` must be less than `CHUNK_SIZE`.
+    #[inline(always)]
+    pub unsafe fn set_block_at_unsafe(&mut self, pos: (u32, u32, u32), block: BlockId) {
+        let palette_index = self.palette_index(block);
+        self.indices.set_unchecked(block_index(pos), palette_index);
+    }
+
+    /// Replace every block of the chunk
+    pub fn fill(&mut self, block: BlockId) {
+        self.palette = vec![block];
+        self.indices = PackedIndices::new(0);
+    }
+
+    /// The blocks of the palette. It can contain blocks that are not in the chunk anymore.
+    pub fn palette(&self) -> &[BlockId] {
+        &self.palette
+    }
+
+    /// Remove the blocks that are not used anymore from the palette, and use fewer bits per block if possible
+    pub fn compact(&mut self) {
+        let mut used = vec![false; self.palette.len()];
+        for i in 0..CHUNK_VOLUME {
+            used[self.indices.get(i)] = true;
+        }
+        let mut new_indices = vec![0; self.palette.len()];
+        let mut new_palette = Vec::new();
+        for (i, &block) in self.palette.iter().enumerate() {
+            if used[i] {
+                new_indices[i] = new_palette.len();
+                new_palette.push(block);
+            }
+        }
+        if new_palette.len() < self.palette.len() {
+            self.indices = self
+                .indices
+                .repack(bits_for_palette(new_palette.len()), |i| new_indices[i]);
+            self.palette = new_palette;
+        }
+    }
+
+    /// Number of bytes used by the chunk, including its heap allocations
+    pub fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.palette.capacity() * std::mem::size_of::<BlockId>()
+            + self.indices.words.capacity() * std::mem::size_of::<u64>()
+    }
+
+    /// Get the index of `block` in the palette, adding it to the palette if necessary
+    #[inline(always)]
+    fn palette_index(&mut self, block: BlockId) -> usize {
+        // TODO: use a HashMap if chunks with a lot of different blocks become common
+        match self.palette.iter().position(|&b| b == block) {
+            Some(index) => index,
+            None => {
+                self.palette.push(block);
+                let bits = bits_for_palette(self.palette.len());
+                if bits != self.indices.bits {
+                    self.indices = self.indices.repack(bits, |i| i);
+                }
+                self.palette.len() - 1
+            }
+        }
+    }
+}
+
+/// An RLE-compressed chunk
+#[derive(Debug, Clone)]
+pub struct CompressedChunk {
+    pub pos: ChunkPos,
+    pub data: Vec<(u16, BlockId)>,
+}
+
+impl CompressedChunk {
+    /// Compress `chunk` using RLE
+    pub fn from_chunk(chunk: &Chunk) -> Self {
+        let mut compressed_data = Vec::new();
+        let mut current_index = chunk.indices.get(0);
+        let mut current_block_count = 0;
+        for i in 0..CHUNK_VOLUME {
+            let index = chunk.indices.get(i);
+            if index != current_index {
+                compressed_data.push((current_block_count, chunk.palette[current_index]));
+                current_index = index;
+                current_block_count = 0;
+            }
+            current_block_count += 1;
+        }
+
+        compressed_data.push((current_block_count, chunk.palette[current_index]));
+
+        Self {
+            pos: chunk.pos,
+            data: compressed_data,
+        }
+    }
+
+    /// Recover original chunk
+    pub fn to_chunk(&self) -> Chunk {
+        let mut chunk = Chunk::new(self.pos);
+        if let [(_, block)] = self.data[..] {
+            chunk.fill(block);
+            return chunk;
+        }
+
+        let mut i = 0;
+        for &(len, block) in self.data.iter() {
+            let palette_index = chunk.palette_index(block);
+            for j in (i as usize)..((i + len) as usize) {
+                chunk.indices.set(j, palette_index);
+            }
+            i += len;
+        }
+        chunk
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_palette_chunk() {
+        let mut chunk = Chunk::new((0, 0, 0).into());
+        assert_eq!(chunk.get_block_at((3, 4, 5)), 0);
+
+        // Grow the palette past a few bit widths
+        for block in 1..20 {
+            chunk.set_block_at((block as u32, 1, 2), block);
+        }
+        for block in 1..20 {
+            assert_eq!(chunk.get_block_at((block as u32, 1, 2)), block);
+        }
+        assert_eq!(chunk.get_block_at((0, 1, 2)), 0);
+        assert_eq!(chunk.indices.bits, 8);
+
+        let compressed = CompressedChunk::from_chunk(&chunk);
+        let decompressed = compressed.to_chunk();
+        for block in 0..20 {
+            assert_eq!(decompressed.get_block_at((block as u32, 1, 2)), block);
+        }
+
+        // Removing the blocks shrinks the palette back
+        for block in 2..20 {
+            chunk.set_block_at((block as u32, 1, 2), 0);
+        }
+        chunk.compact();
+        assert_eq!(chunk.palette(), &[0, 1]);
+        assert_eq!(chunk.indices.bits, 1);
+        assert_eq!(chunk.get_block_at((1, 1, 2)), 1);
+        assert_eq!(chunk.get_block_at((2, 1, 2)), 0);
+    }
+
+    #[test]
+    fn test_chunk_validation() {
+        let mut chunk = Chunk::new((0, 0, 0).into());
+        for block in 0..4 {
+            chunk.set_block_at((block as u32, 0, 0), block);
+        }
+        let unchecked = |chunk: &Chunk| UncheckedChunk {
+            pos: chunk.pos,
+            palette: chunk.palette.clone(),
+            indices: chunk.indices.clone(),
+        };
+        assert!(Chunk::try_from(unchecked(&chunk)).is_ok());
+
+        // An index past the end of the palette
+        let mut invalid = unchecked(&chunk);
+        invalid.palette.pop();
+        assert!(Chunk::try_from(invalid).is_err());
+        // Bits that don't match the palette
+        let mut invalid = unchecked(&chunk);
+        invalid.indices.bits = 64;
+        assert!(Chunk::try_from(invalid).is_err());
+        // Missing words
+        let mut invalid = unchecked(&chunk);
+        invalid.indices.words.pop();
+        assert!(Chunk::try_from(invalid).is_err());
+        // Empty palette
+        let mut invalid = unchecked(&chunk);
+        invalid.palette.clear();
+        assert!(Chunk::try_from(invalid).is_err());
+    }
+}
@@ -1,11 +1,18 @@
 use crate::{
-    block::{Block, BlockId},
+    block::Block,
+    config::MAX_LIGHT_LEVEL,
     registry::Registry,
 };
 use nalgebra::Vector3;
+use serde::{Deserialize, Serialize};
+
+mod chunk;
+mod neighborhood;
+pub use self::chunk::{Chunk, CompressedChunk};
+pub use self::neighborhood::{Direction, Neighborhood27};
 
 /// The position of a block in the world.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct BlockPos {
     pub px: i64,
     pub py: i64,
@@ -58,18 +65,20 @@ impl From<Vector3<f64>> for BlockPos {
     }
 }
 
-/// A world generator
-pub trait WorldGenerator {
+/// A world generator. It is shared by the worldgen threads.
+pub trait WorldGenerator: Send + Sync {
     /// Generate the chunk at position `pos`. The result must always be the same,
-    /// independently of the previous calls to this function!
-    fn generate_chunk(&mut self, pos: ChunkPos, block_registry: &Registry<Block>) -> Chunk;
+    /// independently of the previous calls to this function and of the calls made by other threads at the same time!
+    fn generate_chunk(&self, pos: ChunkPos, block_registry: &Registry<Block>) -> Chunk;
+    /// Find the block where the players spawn when they join the world for the first time, at the surface
+    fn spawn_point(&self) -> BlockPos;
 }
 
 /// Number of blocks along an axis of the chunk
 pub const CHUNK_SIZE: u32 = 32;
 
 /// Position of a chunk in the world
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ChunkPos {
     pub px: i64,
     pub py: i64,
@@ -157,118 +166,19 @@ impl From<ChunkPos> for ChunkPosXZ {
     }
 }
 
+/// Read access to the light of a world, i.e. either the client's World or the server's World.
+/// This is used by the game rules that depend on the light, such as the weather effects.
+pub trait LightContainer {
+    /// The light at some position: the light that comes from the sky and the light emitted by the blocks
+    fn get_light(&self, pos: BlockPos) -> (u8, u8);
 
-/// An RLE-compressed chunk
-#[derive(Debug, Clone)]
-pub struct CompressedChunk {
-    pub pos: ChunkPos,
-    pub data: Vec<(u16, BlockId)>,
-}
-
-impl CompressedChunk {
-    /// Compress `chunk` using RLE
-    pub fn from_chunk(chunk: &Chunk) -> Self {
-        let mut compressed_data = Vec::new();
-        let mut current_block = chunk.data[0];
-        let mut current_block_count = 0;
-        for i in 0..(CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize {
-            if chunk.data[i] != current_block {
-                compressed_data.push((current_block_count, current_block));
-                current_block = chunk.data[i];
-                current_block_count = 0;
-            }
-            current_block_count += 1;
-        }
-
-        compressed_data.push((current_block_count, current_block));
-
-        Self {
-            pos: chunk.pos,
-            data: compressed_data,
-        }
-    }
-
-    /// Recover original chunk
-    pub fn to_chunk(&self) -> Chunk {
-        let mut data = unsafe { crate::collections::zero_initialized_vec((CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize) };
-
-        let mut i = 0;
-        for &(len, block) in self.data.iter() {
-            for el in &mut data[(i as usize)..((i+len) as usize)] {
-                *el = block;
-            }
-            i += len;
-        }
-
-        Chunk {
-            pos: self.pos,
-            data,
-        }
-    }
-}
-
-/// A chunk
-#[derive(Debug, Clone)]
-pub struct Chunk {
-    pub pos: ChunkPos,
-    pub data: Vec<BlockId>,
-}
-
-impl Chunk {
-    /// Create a new empty chunk
-    pub fn new(pos: ChunkPos) -> Self {
-        let data: Vec<BlockId> = unsafe {
-            crate::collections::zero_initialized_vec(
-                (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize,
-            )
-        };
-        Self { pos, data }
-    }
-
-    /// Get block at some position
-    #[inline(always)]
-    pub fn get_block_at(&self, (px, py, pz): (u32, u32, u32)) -> BlockId {
-        self.data[(px * CHUNK_SIZE * CHUNK_SIZE + py * CHUNK_SIZE + pz) as usize]
-    }
-
-    /// Set block at some position
-    #[inline(always)]
-    pub fn set_block_at(&mut self, (px, py, pz): (u32, u32, u32), block: BlockId) {
-        self.data[(px * CHUNK_SIZE * CHUNK_SIZE + py * CHUNK_SIZE + pz) as usize] = block;
-    }
-
-    #[inline(always)]
-    pub unsafe fn get_block_at_unsafe(&self, (px, py, pz): (u32, u32, u32)) -> BlockId {
-        *self
-            .data
-            .get_unchecked((px * CHUNK_SIZE * CHUNK_SIZE + py * CHUNK_SIZE + pz) as usize)
-    }
-
-    /// Set block at some position
-    #[inline(always)]
-    pub unsafe fn set_block_at_unsafe(&mut self, (px, py, pz): (u32, u32, u32), block: BlockId) {
-        *self
-            .data
-            .get_unchecked_mut((px * CHUNK_SIZE * CHUNK_SIZE + py * CHUNK_SIZE + pz) as usize) =
-            block;
-    }
-
-    #[inline(always)]
-    pub unsafe fn fill_unsafe(&mut self, block: BlockId) {
-        for i in 0..(CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize {
-            *self.data.get_unchecked_mut(i) = block;
-        }
-    }
-
-    #[inline(always)]
-    pub fn fill(&mut self, block: BlockId) {
-        for i in 0..(CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize {
-            self.data[i] = block;
-        }
+    /// Return `true` if no block above the position stops the sunlight
+    fn is_sky_exposed(&self, pos: BlockPos) -> bool {
+        self.get_light(pos).0 == MAX_LIGHT_LEVEL
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct LightChunk {
     pub light: Vec<u8>,
     pub pos: ChunkPos,
@@ -277,7 +187,7 @@ pub struct LightChunk {
 impl LightChunk {
     pub fn new(pos: ChunkPos) -> Self {
         let mut light = Vec::new();
-        light.resize((CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize, 15);
+        light.resize((CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize, MAX_LIGHT_LEVEL);
         Self { light, pos }
     }
 
@@ -0,0 +1,164 @@
+//! Helpers to iterate over the neighbors of a block or a chunk.
+use super::{BlockPos, ChunkPos};
+
+/// One of the 6 directions along the axes. The discriminant is the index of the face of a block in that direction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    PosX = 0,
+    NegX = 1,
+    PosY = 2,
+    NegY = 3,
+    PosZ = 4,
+    NegZ = 5,
+}
+
+impl Direction {
+    /// All the directions, ordered by face index
+    pub const ALL: [Direction; 6] = [
+        Direction::PosX,
+        Direction::NegX,
+        Direction::PosY,
+        Direction::NegY,
+        Direction::PosZ,
+        Direction::NegZ,
+    ];
+
+    /// Unit vector along the axis of every direction, indexed by face. It's positive for the negative directions too.
+    pub const AXES: [[i32; 3]; 6] = [
+        [1, 0, 0],
+        [1, 0, 0],
+        [0, 1, 0],
+        [0, 1, 0],
+        [0, 0, 1],
+        [0, 0, 1],
+    ];
+
+    /// The 2 unit vectors along the faces of every direction, indexed by face
+    pub const TANGENTS: [[[i32; 3]; 2]; 6] = [
+        [[0, 1, 0], [0, 0, 1]],
+        [[0, 1, 0], [0, 0, 1]],
+        [[1, 0, 0], [0, 0, 1]],
+        [[1, 0, 0], [0, 0, 1]],
+        [[1, 0, 0], [0, 1, 0]],
+        [[1, 0, 0], [0, 1, 0]],
+    ];
+
+    /// Index of the face of a block in this direction, between 0 and 5
+    #[inline(always)]
+    pub fn face(self) -> usize {
+        self as usize
+    }
+
+    /// The direction of the face with index `face`, or `None` if the index is invalid
+    pub fn from_face(face: usize) -> Option<Self> {
+        Self::ALL.get(face).copied()
+    }
+
+    /// Unit offset along the axis of the direction
+    #[inline(always)]
+    pub fn offset(self) -> (i64, i64, i64) {
+        match self {
+            Direction::PosX => (1, 0, 0),
+            Direction::NegX => (-1, 0, 0),
+            Direction::PosY => (0, 1, 0),
+            Direction::NegY => (0, -1, 0),
+            Direction::PosZ => (0, 0, 1),
+            Direction::NegZ => (0, 0, -1),
+        }
+    }
+
+    pub fn opposite(self) -> Self {
+        Self::ALL[self.face() ^ 1]
+    }
+
+    /// Iterate over the directions, ordered by face index
+    pub fn iter() -> impl Iterator<Item = Direction> {
+        Self::ALL.iter().copied()
+    }
+}
+
+impl BlockPos {
+    /// The adjacent block in direction `dir`
+    pub fn neighbor(self, dir: Direction) -> Self {
+        let (dx, dy, dz) = dir.offset();
+        Self {
+            px: self.px + dx,
+            py: self.py + dy,
+            pz: self.pz + dz,
+        }
+    }
+}
+
+impl ChunkPos {
+    /// The adjacent chunk in direction `dir`
+    pub fn neighbor(self, dir: Direction) -> Self {
+        let (dx, dy, dz) = dir.offset();
+        self.offset(dx, dy, dz)
+    }
+}
+
+/// Indexing of a 3x3x3 neighborhood of chunks, stored in x, y, z order.
+/// Positions in the neighborhood go from 0 to 2 along each axis, and offsets from the center go from -1 to 1.
+pub struct Neighborhood27;
+
+impl Neighborhood27 {
+    /// Number of chunks in the neighborhood
+    pub const SIZE: usize = 27;
+    /// Index of the center chunk
+    pub const CENTER: usize = 13;
+
+    /// Index of the chunk at position `(i, j, k)` in the neighborhood
+    #[inline(always)]
+    pub fn index(i: usize, j: usize, k: usize) -> usize {
+        debug_assert!(i < 3 && j < 3 && k < 3);
+        i * 9 + j * 3 + k
+    }
+
+    /// Index of the chunk at offset `(dx, dy, dz)` from the center
+    #[inline(always)]
+    pub fn offset_index(dx: i64, dy: i64, dz: i64) -> usize {
+        Self::index((dx + 1) as usize, (dy + 1) as usize, (dz + 1) as usize)
+    }
+
+    /// Offset from the center of the chunk at index `index`
+    pub fn offset(index: usize) -> (i64, i64, i64) {
+        debug_assert!(index < Self::SIZE);
+        ((index / 9) as i64 - 1, (index / 3 % 3) as i64 - 1, (index % 3) as i64 - 1)
+    }
+
+    /// Iterate over the offsets from the center, in index order
+    pub fn offsets() -> impl Iterator<Item = (i64, i64, i64)> {
+        (0..Self::SIZE).map(Self::offset)
+    }
+
+    /// Iterate over the positions of the chunks around `center`, in index order
+    pub fn positions(center: ChunkPos) -> impl Iterator<Item = ChunkPos> {
+        Self::offsets().map(move |(dx, dy, dz)| center.offset(dx, dy, dz))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_neighborhood_indexing() {
+        for (index, (dx, dy, dz)) in Neighborhood27::offsets().enumerate() {
+            assert_eq!(Neighborhood27::offset_index(dx, dy, dz), index);
+            assert_eq!(Neighborhood27::index((dx + 1) as usize, (dy + 1) as usize, (dz + 1) as usize), index);
+        }
+        assert_eq!(Neighborhood27::offset(Neighborhood27::CENTER), (0, 0, 0));
+        for dir in Direction::iter() {
+            let (dx, dy, dz) = dir.offset();
+            assert_eq!(dir.opposite().offset(), (-dx, -dy, -dz));
+            assert_eq!(Direction::from_face(dir.face()), Some(dir));
+            // The axis and the tangents form a basis
+            let [t1, t2] = Direction::TANGENTS[dir.face()];
+            let axis = Direction::AXES[dir.face()];
+            assert_eq!((axis[0].abs() as i64, axis[1].abs() as i64, axis[2].abs() as i64), (dx.abs(), dy.abs(), dz.abs()));
+            for a in 0..3 {
+                assert_eq!(axis[a] + t1[a] + t2[a], 1);
+            }
+        }
+    }
+}
@@ -1,5 +1,6 @@
+use crate::data::vox::VoxelModel;
 use crate::world::BlockPos;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 // TODO : Create a procedural decorator
 /// Struct used to generate pre-defined groups of block in the world
@@ -9,6 +10,55 @@ pub(crate) struct Decorator {
     pub pass: Vec<DecoratorPass>, // the pass of each block for the decorator
 }
 
+impl Decorator {
+    /// Create a decorator from the full voxels of a model, replacing every color by a block.
+    /// The model is centered horizontally on the start block and placed just above it.
+    pub fn from_model(
+        model: &VoxelModel,
+        blocks: &HashMap<u32, u16>,
+        number_of_try: u32,
+        block_start_whitelist: HashSet<u16>,
+    ) -> Self {
+        // Sort the passes by block to keep the generation deterministic
+        let mut passes: BTreeMap<u16, DecoratorPass> = BTreeMap::new();
+        for i in 0..model.size_x {
+            for j in 0..model.size_y {
+                for k in 0..model.size_z {
+                    let index = i * model.size_y * model.size_z + j * model.size_z + k;
+                    if !model.full[index] {
+                        continue;
+                    }
+                    if let Some(&block_type) = blocks.get(&model.voxels[index]) {
+                        passes
+                            .entry(block_type)
+                            .or_insert_with(|| DecoratorPass::new(block_type))
+                            .block_pos
+                            .push(BlockPos::from((
+                                i as i64 - model.size_x as i64 / 2,
+                                j as i64 + 1,
+                                k as i64 - model.size_z as i64 / 2,
+                            )));
+                    }
+                }
+            }
+        }
+        // The blocks of the structure can replace each other
+        let structure_blocks: HashSet<u16> = passes.keys().cloned().collect();
+        let pass = passes
+            .into_values()
+            .map(|mut pass| {
+                pass.block_whitelist.extend(structure_blocks.iter().cloned());
+                pass
+            })
+            .collect();
+        Decorator {
+            number_of_try,
+            block_start_whitelist,
+            pass,
+        }
+    }
+}
+
 pub struct DecoratorPass {
     pub block_type: u16,                  // the block type
     pub block_non_blocking: HashSet<u16>, // list of the block that will no be replaced but will not block the strucutre to spawn
@@ -1,30 +1,41 @@
 use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
 
 use crate::world::BlockPos;
 use crate::worldgen::perlin::rand_pos_int;
 use crate::{
     block::Block,
     registry::Registry,
-    world::{Chunk, ChunkPos, CHUNK_SIZE, WorldGenerator},
+    world::{Chunk, ChunkPos, Neighborhood27, CHUNK_SIZE, WorldGenerator},
 };
 
 use crate::debug::send_debug_info;
 use crate::worldgen::decorator::Decorator;
 use crate::worldgen::decorator::DecoratorPass;
+use crate::worldgen::structure::Structure;
 use crate::worldgen::topology::{generate_chunk_topology, HeightMap};
 
 pub mod perlin;
 #[macro_use]
 pub mod decorator;
+pub mod structure;
 pub mod topology;
 
 pub struct DefaultWorldGenerator {
-    pregenerated_chunks: HashMap<ChunkPos, Chunk>,
-    pregenerated_chunks_decorator_count: HashMap<ChunkPos, u32>,
-    tree_decorator: Decorator,
+    /// The topology of the chunks around the recently generated chunks, with the number of chunks that used it.
+    /// It is dropped once the 27 chunks around it were generated.
+    pregenerated_chunks: Mutex<HashMap<ChunkPos, (Chunk, u32)>>,
+    /// The tree, then the structures of the data packs
+    decorators: Vec<Decorator>,
     height_map: HeightMap,
+    seed: i32,
 }
 
+/// Distance between the columns that are tried when looking for a spawn point on the ground, in blocks
+const SPAWN_SEARCH_STEP: i64 = 8;
+/// Number of rings of columns around the origin that are tried when looking for a spawn point
+const SPAWN_SEARCH_RINGS: i64 = 32;
+
 struct BlockToPlace {
     pub pos: BlockPos,
     pub id: u16,
@@ -40,7 +51,7 @@ impl BlockToPlace {
 }
 
 impl DefaultWorldGenerator {
-    pub fn new(block_registry: &Registry<Block>) -> Self {
+    pub fn new(block_registry: &Registry<Block>, structures: &Registry<Structure>, seed: i32) -> Self {
         let grass_block = block_registry.get_id_by_name(&"grass".to_owned()).unwrap() as u16;
         let leaves_block = block_registry.get_id_by_name(&"leaves".to_owned()).unwrap() as u16;
         let wood_block = block_registry.get_id_by_name(&"wood".to_owned()).unwrap() as u16;
@@ -79,23 +90,35 @@ impl DefaultWorldGenerator {
             block_start_whitelist: set![grass_block],
             pass: vec![pass_leaves, pass_wood],
         };
+        let mut decorators = vec![tree_decorator];
+        for id in 0..structures.get_number_of_ids() {
+            let structure = structures.get_value_by_id(id).unwrap();
+            decorators.push(Decorator::from_model(
+                &structure.model,
+                &structure.blocks,
+                structure.tries,
+                structure.start_blocks.iter().cloned().collect(),
+            ));
+        }
         Self {
-            tree_decorator,
-            pregenerated_chunks_decorator_count: HashMap::new(),
-            pregenerated_chunks: HashMap::new(),
-            height_map: HeightMap::new(),
+            decorators,
+            pregenerated_chunks: Mutex::new(HashMap::new()),
+            height_map: HeightMap::new(seed),
+            seed,
         }
     }
 
     fn pregenerate_chunk(
         chunk: &mut Chunk,
         block_registry: &Registry<Block>,
-        height_map: &mut HeightMap,
+        height_map: &HeightMap,
     ) {
         generate_chunk_topology(chunk, block_registry, height_map);
     }
 
-    fn decorate_chunk(chunks: &mut Vec<Chunk>, decorator: &Decorator) {
+    fn decorate_chunk(chunks: &mut [Chunk], decorator: &Decorator, seed: i32) {
+        // Leave room for the 3 random coordinates of every try
+        let seed = seed.rem_euclid(1 << 20) * 3 * decorator.number_of_try as i32;
         let min_x = chunks[0].pos.px * CHUNK_SIZE as i64;
         let max_x = (chunks[0].pos.px + 3) * CHUNK_SIZE as i64;
         let min_y = chunks[0].pos.py * CHUNK_SIZE as i64;
@@ -114,7 +137,7 @@ impl DefaultWorldGenerator {
             for j in -1..=1 {
                 for k in -1..=1 {
                     for l in 0..decorator.number_of_try as i32 {
-                        let current_chunk = &chunks[((i + 1) * 9 + (j + 1) * 3 + (k + 1)) as usize];
+                        let current_chunk = &chunks[Neighborhood27::offset_index(i, j, k)];
                         let cc_pos = current_chunk.pos;
                         let cbx = cc_pos.px * chunk_size_64;
                         let cby = cc_pos.py * chunk_size_64;
@@ -124,19 +147,19 @@ impl DefaultWorldGenerator {
                             cc_pos.px as i32,
                             cc_pos.py as i32,
                             cc_pos.pz as i32,
-                            3 * l,
+                            seed + 3 * l,
                         ) as i64;
                         let mut ty = rand_pos_int(
                             cc_pos.px as i32,
                             cc_pos.py as i32,
                             cc_pos.pz as i32,
-                            3 * l + 1,
+                            seed + 3 * l + 1,
                         ) as i64;
                         let mut tz = rand_pos_int(
                             cc_pos.px as i32,
                             cc_pos.py as i32,
                             cc_pos.pz as i32,
-                            3 * l + 2,
+                            seed + 3 * l + 2,
                         ) as i64;
 
                         tx = (tx % chunk_size_64 + chunk_size_64) % chunk_size_64;
@@ -177,7 +200,7 @@ impl DefaultWorldGenerator {
                                             cblock_pos.py - chunks[0].pos.py,
                                             cblock_pos.pz - chunks[0].pos.pz,
                                         );
-                                        let chunk = &chunks[(x * 9 + y * 3 + z) as usize];
+                                        let chunk = &chunks[Neighborhood27::index(x as usize, y as usize, z as usize)];
                                         let (ux, uy, uz) = pos.pos_in_containing_chunk();
                                         if decorator_pass
                                             .block_whitelist
@@ -244,47 +267,46 @@ impl DefaultWorldGenerator {
 }
 
 impl WorldGenerator for DefaultWorldGenerator {
-    fn generate_chunk(&mut self, pos: ChunkPos, block_registry: &Registry<Block>) -> Chunk {
+    fn generate_chunk(&self, pos: ChunkPos, block_registry: &Registry<Block>) -> Chunk {
         let mut chunks_vec = Vec::new();
-        for i in -1..=1 {
-            for j in -1..=1 {
-                for k in -1..=1 {
-                    chunks_vec.push(
-                        match self.pregenerated_chunks.remove(&pos.offset(i, j, k)) {
-                            Some(chunk) => chunk,
-                            None => {
-                                let mut chunk = Chunk::new(pos.offset(i, j, k));
-                                DefaultWorldGenerator::pregenerate_chunk(
-                                    &mut chunk,
-                                    &block_registry,
-                                    &mut self.height_map,
-                                );
-                                chunk
-                            }
-                        },
+        for chunk_pos in Neighborhood27::positions(pos) {
+            let pregenerated_chunk = self
+                .pregenerated_chunks
+                .lock()
+                .unwrap()
+                .get(&chunk_pos)
+                .map(|(chunk, _)| chunk.clone());
+            chunks_vec.push(match pregenerated_chunk {
+                Some(chunk) => chunk,
+                None => {
+                    let mut chunk = Chunk::new(chunk_pos);
+                    DefaultWorldGenerator::pregenerate_chunk(
+                        &mut chunk,
+                        block_registry,
+                        &self.height_map,
                     );
+                    chunk
                 }
-            }
+            });
         }
 
-        let decorator = &self.tree_decorator;
-        let chunk_center = chunks_vec[13].clone();
-
-        DefaultWorldGenerator::decorate_chunk(&mut chunks_vec, decorator);
+        // Only the topology is cached: the chunks must not depend on the decoration of the chunks generated before them
+        let topology = chunks_vec.clone();
 
-        let chunk_res = std::mem::replace(&mut chunks_vec[13], chunk_center);
+        for (i, decorator) in self.decorators.iter().enumerate() {
+            // Every decorator needs different random positions
+            DefaultWorldGenerator::decorate_chunk(&mut chunks_vec, decorator, self.seed.wrapping_add(i as i32));
+        }
 
-        for chunk in chunks_vec.drain(..) {
-            let pos = chunk.pos.clone();
+        let chunk_res = chunks_vec.swap_remove(Neighborhood27::CENTER);
 
-            let u = self.pregenerated_chunks_decorator_count.get(&pos);
-            let k = match u {
-                None => 1,
-                Some(i) => *i + 1,
-            };
-            if k < 27 {
-                self.pregenerated_chunks.insert(chunk.pos, chunk);
-                self.pregenerated_chunks_decorator_count.insert(pos, k);
+        let mut pregenerated_chunks = self.pregenerated_chunks.lock().unwrap();
+        for chunk in topology {
+            let pos = chunk.pos;
+            let (_, count) = pregenerated_chunks.entry(pos).or_insert((chunk, 0));
+            *count += 1;
+            if *count >= 27 {
+                pregenerated_chunks.remove(&pos);
             }
         }
 
@@ -293,18 +315,38 @@ impl WorldGenerator for DefaultWorldGenerator {
             "worldgenstruct",
             format!(
                 "Stored pregenerated chunks = {}",
-                self.pregenerated_chunks.len()
+                pregenerated_chunks.len()
             ),
         );
 
         chunk_res
     }
+
+    fn spawn_point(&self) -> BlockPos {
+        // Find the closest column to the origin that is above the water
+        for ring in 0..=SPAWN_SEARCH_RINGS {
+            for i in -ring..=ring {
+                for k in -ring..=ring {
+                    if i.abs().max(k.abs()) != ring {
+                        continue;
+                    }
+                    let (x, z) = (i * SPAWN_SEARCH_STEP, k * SPAWN_SEARCH_STEP);
+                    let height = self.height_map.get_height(x, z);
+                    if height >= 1 {
+                        return BlockPos::from((x, height as i64 + 1, z));
+                    }
+                }
+            }
+        }
+        // Spawn above the water
+        BlockPos::from((0, self.height_map.get_height(0, 0).max(0) as i64, 0))
+    }
 }
 
 pub struct DebugWorldGenerator;
 
 impl WorldGenerator for DebugWorldGenerator {
-    fn generate_chunk(&mut self, pos: ChunkPos, block_registry: &Registry<Block>) -> Chunk {
+    fn generate_chunk(&self, pos: ChunkPos, block_registry: &Registry<Block>) -> Chunk {
         let stone = block_registry.get_id_by_name(&"stone".to_owned()).unwrap() as u16;
         let mut c = Chunk::new(pos);
         for i in 0..CHUNK_SIZE {
@@ -318,4 +360,63 @@ impl WorldGenerator for DebugWorldGenerator {
         }
         c
     }
+
+    fn spawn_point(&self) -> BlockPos {
+        BlockPos::from((0, 0, 0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::BlockType;
+    use std::sync::Arc;
+
+    fn block_registry() -> Registry<Block> {
+        let mut blocks = Registry::default();
+        for name in ["air", "stone", "grass", "dirt", "dirt_grass", "water", "sand", "leaves", "wood"].iter() {
+            let block_type = BlockType::NormalCube { face_textures: Vec::new(), frame_time: None, seasonal: false, hardness: None, light_attenuation: None, falls: false, spreads_on: None, grows_into: None };
+            blocks
+                .register(name.to_string(), Block { name: name.to_string(), block_type })
+                .unwrap();
+        }
+        blocks
+    }
+
+    fn same_blocks(a: &Chunk, b: &Chunk) -> bool {
+        (0..CHUNK_SIZE).all(|i| {
+            (0..CHUNK_SIZE).all(|j| (0..CHUNK_SIZE).all(|k| a.get_block_at((i, j, k)) == b.get_block_at((i, j, k))))
+        })
+    }
+
+    // The chunks don't depend on the order in which they are generated, on the number of threads, or on the cache
+    #[test]
+    fn test_deterministic_generation() {
+        let blocks = Arc::new(block_registry());
+        let positions: Vec<ChunkPos> = (-1..=1).flat_map(|x| (-1..=0).map(move |y| ChunkPos::from((x, y, 0)))).collect();
+        let generator = DefaultWorldGenerator::new(&blocks, &Registry::default(), 42);
+        let expected: Vec<Chunk> = positions.iter().map(|&pos| generator.generate_chunk(pos, &blocks)).collect();
+
+        // A new generator for every chunk doesn't have anything cached
+        for (&pos, expected) in positions.iter().zip(expected.iter()) {
+            let chunk = DefaultWorldGenerator::new(&blocks, &Registry::default(), 42).generate_chunk(pos, &blocks);
+            assert!(same_blocks(&chunk, expected), "Chunk {:?} depends on the cache", pos);
+        }
+
+        let generator = Arc::new(DefaultWorldGenerator::new(&blocks, &Registry::default(), 42));
+        let threads: Vec<_> = positions
+            .iter()
+            .rev()
+            .map(|&pos| {
+                let (generator, blocks) = (generator.clone(), blocks.clone());
+                std::thread::spawn(move || generator.generate_chunk(pos, &blocks))
+            })
+            .collect();
+        let mut chunks: Vec<Chunk> = threads.into_iter().map(|thread| thread.join().unwrap()).collect();
+        chunks.reverse();
+        for (chunk, expected) in chunks.iter().zip(expected.iter()) {
+            assert_eq!(chunk.pos, expected.pos);
+            assert!(same_blocks(chunk, expected), "Chunk {:?} is different", chunk.pos);
+        }
+    }
 }
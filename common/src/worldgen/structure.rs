@@ -0,0 +1,33 @@
+use crate::block::BlockId;
+use crate::data::vox::VoxelModel;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// A structure built from a .vox model, placed in the world by the world generator.
+/// This is the data provided by the creator of the structure.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename = "Structure")]
+pub struct StructureData {
+    /// Name of the .vox file in the model directory, without the extension
+    pub model: String,
+    /// The block that replaces every color of the model. Voxels with other colors are skipped.
+    pub blocks: HashMap<u32, String>,
+    /// Number of times the generator tries to place the structure in every chunk
+    #[serde(default = "default_tries")]
+    pub tries: u32,
+    /// The blocks the structure can be placed on
+    pub start_blocks: Vec<String>,
+}
+
+fn default_tries() -> u32 {
+    1
+}
+
+/// A structure with its model loaded and its block names resolved
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Structure {
+    pub model: VoxelModel,
+    pub blocks: HashMap<u32, BlockId>,
+    pub tries: u32,
+    pub start_blocks: Vec<BlockId>,
+}
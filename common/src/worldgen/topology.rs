@@ -3,36 +3,51 @@ use crate::registry::Registry;
 use crate::world::{Chunk, CHUNK_SIZE, ChunkPosXZ};
 use crate::worldgen::perlin;
 use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
 
+/// Cache of the ground level of the chunk columns, shared by the worldgen threads
 pub struct HeightMap {
-    height_map: HashMap<ChunkPosXZ, Vec<i32>>,
+    height_map: RwLock<HashMap<ChunkPosXZ, Arc<Vec<i32>>>>,
+    seed: i32,
 }
 
 impl  HeightMap {
 
-    pub fn new() ->Self{
+    pub fn new(seed: i32) ->Self{
         return Self{
-            height_map: HashMap::new(),
+            height_map: RwLock::new(HashMap::new()),
+            seed,
         };
     }
 
-    pub fn get_chunk_height_map(&mut self, pos : ChunkPosXZ) -> &Vec<i32> {
-         if !self.height_map.contains_key(&pos){
-             let mut res = vec![-1; (CHUNK_SIZE*CHUNK_SIZE) as usize];
-             let c = CHUNK_SIZE as f32;
-             let s = generate_ground_level((pos.px as f32)*c, (pos.pz as f32)*c);
-             for i in 0..(CHUNK_SIZE*CHUNK_SIZE)  as usize {
-                 res[i]  = s[i] as i32;
-             }
-             self.height_map.insert(pos, res);
-         }
-        return self.height_map.get(&pos).unwrap();
+    pub fn get_chunk_height_map(&self, pos : ChunkPosXZ) -> Arc<Vec<i32>> {
+        if let Some(h) = self.height_map.read().unwrap().get(&pos) {
+            return h.clone();
+        }
+        // Two threads can compute the same column at the same time, they get the same result anyway
+        let mut res = vec![-1; (CHUNK_SIZE*CHUNK_SIZE) as usize];
+        let c = CHUNK_SIZE as f32;
+        let s = generate_ground_level((pos.px as f32)*c, (pos.pz as f32)*c, self.seed);
+        for i in 0..(CHUNK_SIZE*CHUNK_SIZE)  as usize {
+            res[i]  = s[i] as i32;
+        }
+        self.height_map.write().unwrap().entry(pos).or_insert_with(|| Arc::new(res)).clone()
+    }
+
+    /// Get the height of the highest ground block of the column at `(x, z)`
+    pub fn get_height(&self, x: i64, z: i64) -> i32 {
+        let c = CHUNK_SIZE as i64;
+        let column = self.get_chunk_height_map(ChunkPosXZ { px: x.div_euclid(c), pz: z.div_euclid(c) });
+        column[(x.rem_euclid(c) * c + z.rem_euclid(c)) as usize]
     }
 
 }
 
-pub fn generate_ground_level(px: f32, pz: f32) -> Vec<f32> {
+/// Generate the ground level of a chunk column. Different world seeds give different terrains.
+pub fn generate_ground_level(px: f32, pz: f32, seed: i32) -> Vec<f32> {
     let mut res = vec![0.0; (CHUNK_SIZE * CHUNK_SIZE) as usize];
+    // Leave room for the seeds of the octaves of every noise
+    let seed = seed.rem_euclid(1 << 24) * 16;
 
     let dx1 = perlin::perlin2d(
         px,
@@ -42,7 +57,7 @@ pub fn generate_ground_level(px: f32, pz: f32) -> Vec<f32> {
         1.0 / 64.0,
         5,
         0.5,
-        0,
+        seed,
     );
     let dy1 = perlin::perlin2d(
         px,
@@ -52,7 +67,7 @@ pub fn generate_ground_level(px: f32, pz: f32) -> Vec<f32> {
         1.0 / 64.0,
         5,
         0.5,
-        1,
+        seed + 1,
     );
 
     let noise1 = perlin::perlin2d_with_displacement(
@@ -66,7 +81,7 @@ pub fn generate_ground_level(px: f32, pz: f32) -> Vec<f32> {
         1.0 / 128.0,
         5,
         0.4,
-        2,
+        seed + 2,
     );
     let noise2 = perlin::perlin2d(
         px,
@@ -76,7 +91,7 @@ pub fn generate_ground_level(px: f32, pz: f32) -> Vec<f32> {
         1.0 / 256.0,
         5,
         0.3,
-        3,
+        seed + 3,
     );
 
     for i in 0..(CHUNK_SIZE * CHUNK_SIZE) as usize {
@@ -92,7 +107,7 @@ pub fn generate_ground_level(px: f32, pz: f32) -> Vec<f32> {
 }
 
 /// Generate the topology of the chunk
-pub fn generate_chunk_topology(chunk: &mut Chunk, block_registry: &Registry<Block>,height_map :  &mut HeightMap) {
+pub fn generate_chunk_topology(chunk: &mut Chunk, block_registry: &Registry<Block>,height_map :  &HeightMap) {
     let stone_block = block_registry.get_id_by_name(&"stone".to_owned()).unwrap() as u16;
     let grass_block = block_registry.get_id_by_name(&"grass".to_owned()).unwrap() as u16;
     let dirt_block = block_registry.get_id_by_name(&"dirt".to_owned()).unwrap() as u16;
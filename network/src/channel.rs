@@ -1,11 +1,12 @@
 use std::{
     collections::VecDeque,
-    time::Instant,
+    time::{Duration, Instant},
 };
 use super::types::*;
 
 struct QueuedPacket {
     pub sequence: Sequence,
+    pub ordered: bool,
     pub data: Vec<u8>,
     pub first_send: Option<Instant>,
     pub last_send: Instant,
@@ -18,13 +19,18 @@ pub struct Sender {
     next_sequence: Sequence,
     /// Earliest sequence number that the receiver hasn't acked yet
     earliest_unacked_sequence: Sequence,
+    /// Sequence for the next unreliable sequenced message
+    next_unreliable_sequence: Sequence,
 }
 
 /// First receive, then get_message, then get_acks
 pub struct Receiver {
-    received: Vec<Option<Vec<u8>>>,
+    /// The received reliable messages. The data is `None` if the message was unordered and already delivered.
+    received: Vec<Option<Option<Vec<u8>>>>,
     received_sequences: [Sequence; RELIABLE_BUFFER_SIZE],
     next_sequence: Sequence,
+    /// Sequence of the most recent unreliable sequenced message
+    last_unreliable_sequence: Sequence,
 }
 
 impl Sender {
@@ -33,12 +39,14 @@ impl Sender {
             reliable_packets: VecDeque::new(),
             next_sequence: 1,
             earliest_unacked_sequence: 1,
+            next_unreliable_sequence: 1,
         }
     }
 
-    pub fn send(&mut self, data: Vec<u8>) {
+    pub fn send(&mut self, data: Vec<u8>, ordered: bool) {
         self.reliable_packets.push_back(QueuedPacket {
             sequence: { (self.next_sequence, self.next_sequence += 1).0 },
+            ordered,
             data,
             first_send: None,
             last_send: Instant::now() - RESEND_DELAY,
@@ -58,6 +66,7 @@ impl Sender {
             if now - packet.last_send > RESEND_DELAY {
                 if send_message(Message::Reliable {
                     sequence: packet.sequence,
+                    ordered: packet.ordered,
                     data: packet.data.clone(),
                 }) {
                     packet.last_send = now;
@@ -73,13 +82,13 @@ impl Sender {
     }
 
     pub fn receive_acks(&mut self, first_sequence: Sequence, acks: BitSet) {
-        // TODO: process time to estimate RTT and packet loss
         self.reliable_packets.retain(|packet| {
             if packet.sequence < first_sequence { false }
             else {
                 let idx = packet.sequence - first_sequence;
+                // Only the acknowledged packets are removed
                 if idx as usize >= acks.len() { true }
-                else { acks[idx as usize] }
+                else { !acks[idx as usize] }
             }
         });
         self.earliest_unacked_sequence = match self.reliable_packets.front() {
@@ -87,6 +96,13 @@ impl Sender {
             None => self.next_sequence,
         };
     }
+
+    pub fn send_unreliable_sequenced(&mut self, data: Vec<u8>) -> Message {
+        Message::UnreliableSequenced {
+            sequence: { (self.next_unreliable_sequence, self.next_unreliable_sequence += 1).0 },
+            data,
+        }
+    }
 }
 
 impl Receiver {
@@ -95,25 +111,52 @@ impl Receiver {
             received: vec![None; RELIABLE_BUFFER_SIZE],
             received_sequences: [0; RELIABLE_BUFFER_SIZE],
             next_sequence: 1,
+            last_unreliable_sequence: 0,
         }
     }
 
+    /// Get the next ordered message
     pub fn get_message(&mut self) -> Option<Vec<u8>> {
-        let next_idx = self.next_sequence as usize % RELIABLE_BUFFER_SIZE;
-        if self.received[next_idx].is_some() && self.received_sequences[next_idx] == self.next_sequence {
-            self.next_sequence += 1;
-            self.received[next_idx].take()
-        } else {
-            None
+        loop {
+            let next_idx = self.next_sequence as usize % RELIABLE_BUFFER_SIZE;
+            if self.received[next_idx].is_some() && self.received_sequences[next_idx] == self.next_sequence {
+                self.next_sequence += 1;
+                // Skip the unordered messages, they were already delivered
+                if let Some(data) = self.received[next_idx].take().unwrap() {
+                    return Some(data);
+                }
+            } else {
+                return None;
+            }
         }
     }
 
-    pub fn receive(&mut self, sequence: Sequence, data: Vec<u8>) {
+    /// Receive a reliable message. Unordered messages are returned immediately if they were not received before.
+    /// Return an error if the sender didn't respect the size of the receive buffer.
+    pub fn receive(&mut self, sequence: Sequence, data: Vec<u8>, ordered: bool) -> Result<Option<Vec<u8>>, String> {
         let idx = sequence as usize % RELIABLE_BUFFER_SIZE;
         if sequence > self.received_sequences[idx] {
-            assert!(sequence - self.received_sequences[idx] <= RELIABLE_BUFFER_SIZE as u32, "sequence number too high received");
+            if sequence - self.received_sequences[idx] > RELIABLE_BUFFER_SIZE as u32 {
+                return Err(format!("reliable sequence number {} is too high", sequence));
+            }
             self.received_sequences[idx] = sequence;
-            self.received[idx] = Some(data);
+            if ordered {
+                self.received[idx] = Some(Some(data));
+            } else {
+                self.received[idx] = Some(None);
+                return Ok(Some(data));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Return true if the unreliable sequenced message is more recent than the previous ones
+    pub fn receive_unreliable_sequenced(&mut self, sequence: Sequence) -> bool {
+        if sequence > self.last_unreliable_sequence {
+            self.last_unreliable_sequence = sequence;
+            true
+        } else {
+            false
         }
     }
 
@@ -125,11 +168,166 @@ impl Receiver {
             set.push(self.received_sequences[idx] >= seq && self.received[idx].is_some());
         }
         // Remove final 0s
-        while let Some(last_bit) = set.iter().by_val().last() {
-            if !last_bit {
-                set.pop().unwrap();
+        let len = (0..set.len()).rev().find(|&i| set[i]).map_or(0, |i| i + 1);
+        set.truncate(len);
+        (seq, set)
+    }
+}
+
+/// Weight of the newest sample in the smoothed round trip time and packet loss
+const SMOOTHING: f64 = 0.125;
+
+/// Sends the pings and measures the round trip time, the packet loss and the bandwidth of a connection
+pub struct ConnectionMonitor {
+    next_ping_id: u32,
+    last_ping: Instant,
+    /// The pings that were not answered yet, with their send time
+    pending_pings: VecDeque<(u32, Instant)>,
+    rtt: Option<Duration>,
+    packet_loss: f64,
+    /// Size and time of the recently sent and received packets
+    sent: VecDeque<(Instant, usize)>,
+    received: VecDeque<(Instant, usize)>,
+}
+
+impl ConnectionMonitor {
+    pub fn new() -> Self {
+        Self {
+            next_ping_id: 0,
+            last_ping: Instant::now() - PING_INTERVAL,
+            pending_pings: VecDeque::new(),
+            rtt: None,
+            packet_loss: 0.0,
+            sent: VecDeque::new(),
+            received: VecDeque::new(),
+        }
+    }
+
+    /// Return the ping to send if it's time to send one, and count the expired pings as lost
+    pub fn tick(&mut self, now: Instant) -> Option<Message> {
+        while let Some(&(_, time)) = self.pending_pings.front() {
+            if now - time <= PING_TIMEOUT {
+                break;
             }
+            self.pending_pings.pop_front();
+            self.packet_loss += (1.0 - self.packet_loss) * SMOOTHING;
+        }
+        if now - self.last_ping < PING_INTERVAL {
+            return None;
+        }
+        self.last_ping = now;
+        let id = { (self.next_ping_id, self.next_ping_id = self.next_ping_id.wrapping_add(1)).0 };
+        self.pending_pings.push_back((id, now));
+        Some(Message::Ping(id))
+    }
+
+    /// Receive the answer to a ping
+    pub fn receive_pong(&mut self, id: u32, now: Instant) {
+        if let Some(i) = self.pending_pings.iter().position(|&(ping_id, _)| ping_id == id) {
+            let (_, time) = self.pending_pings.remove(i).unwrap();
+            let sample = now - time;
+            self.rtt = Some(match self.rtt {
+                Some(rtt) => rtt.mul_f64(1.0 - SMOOTHING) + sample.mul_f64(SMOOTHING),
+                None => sample,
+            });
+            self.packet_loss *= 1.0 - SMOOTHING;
         }
-        (seq, set)
     }
+
+    pub fn record_sent(&mut self, bytes: usize, now: Instant) {
+        record_bytes(&mut self.sent, bytes, now);
+    }
+
+    pub fn record_received(&mut self, bytes: usize, now: Instant) {
+        record_bytes(&mut self.received, bytes, now);
+    }
+
+    pub fn stats(&self) -> NetworkStats {
+        let bandwidth = |packets: &VecDeque<(Instant, usize)>| {
+            packets.iter().map(|&(_, bytes)| bytes).sum::<usize>() as f64 / BANDWIDTH_WINDOW.as_secs_f64()
+        };
+        NetworkStats {
+            rtt: self.rtt,
+            packet_loss: self.packet_loss as f32,
+            upload: bandwidth(&self.sent),
+            download: bandwidth(&self.received),
+        }
+    }
+}
+
+/// Record a packet and forget the packets older than `BANDWIDTH_WINDOW`
+fn record_bytes(packets: &mut VecDeque<(Instant, usize)>, bytes: usize, now: Instant) {
+    packets.push_back((now, bytes));
+    while let Some(&(time, _)) = packets.front() {
+        if now - time <= BANDWIDTH_WINDOW {
+            break;
+        }
+        packets.pop_front();
+    }
+}
+
+#[test]
+fn test_connection_monitor() {
+    let mut monitor = ConnectionMonitor::new();
+    let start = Instant::now();
+    // The first ping is sent immediately, the next one after the interval
+    let first = match monitor.tick(start) {
+        Some(Message::Ping(id)) => id,
+        _ => panic!("no ping was sent"),
+    };
+    assert_eq!(monitor.tick(start + PING_INTERVAL / 2), None);
+    monitor.receive_pong(first, start + Duration::from_millis(40));
+    assert_eq!(monitor.stats().rtt, Some(Duration::from_millis(40)));
+    // An unknown pong is ignored
+    monitor.receive_pong(first, start + Duration::from_millis(400));
+    assert_eq!(monitor.stats().rtt, Some(Duration::from_millis(40)));
+    // An unanswered ping is lost
+    assert!(monitor.tick(start + PING_INTERVAL).is_some());
+    monitor.tick(start + PING_INTERVAL + PING_TIMEOUT * 2);
+    assert!(monitor.stats().packet_loss > 0.0);
+
+    monitor.record_sent(1000, start);
+    monitor.record_sent(500, start + BANDWIDTH_WINDOW * 2);
+    assert_eq!(monitor.stats().upload, 500.0 / BANDWIDTH_WINDOW.as_secs_f64());
+}
+
+#[test]
+fn test_receiver_delivery() {
+    let mut receiver = Receiver::new();
+    // An unordered message is delivered before the previous ordered message
+    assert_eq!(receiver.receive(2, vec![2], false), Ok(Some(vec![2])));
+    assert_eq!(receiver.get_message(), None);
+    assert_eq!(receiver.receive(2, vec![2], false), Ok(None));
+    assert_eq!(receiver.receive(1, vec![1], true), Ok(None));
+    assert_eq!(receiver.receive(3, vec![3], true), Ok(None));
+    assert_eq!(receiver.get_message(), Some(vec![1]));
+    assert_eq!(receiver.get_message(), Some(vec![3]));
+    assert_eq!(receiver.get_message(), None);
+    // Older sequenced messages are dropped
+    assert!(receiver.receive_unreliable_sequenced(2));
+    assert!(!receiver.receive_unreliable_sequenced(1));
+    assert!(receiver.receive_unreliable_sequenced(3));
+    // A sequence number beyond the receive buffer is an error, not a panic
+    assert!(receiver.receive(3 + 2 * RELIABLE_BUFFER_SIZE as u32, vec![], true).is_err());
+}
+
+#[test]
+fn test_sender_acks() {
+    let mut sender = Sender::new();
+    for i in 1..=3 {
+        sender.send(vec![i], true);
+    }
+    // The second message is acknowledged before the first one
+    let mut receiver = Receiver::new();
+    assert_eq!(receiver.receive(2, vec![2], true), Ok(None));
+    let (first_sequence, acks) = receiver.get_acks();
+    sender.receive_acks(first_sequence, acks);
+    let mut resent = Vec::new();
+    sender.tick(|message| {
+        if let Message::Reliable { sequence, .. } = message {
+            resent.push(sequence);
+        }
+        true
+    });
+    assert_eq!(resent, vec![1, 3]);
 }
@@ -1,6 +1,6 @@
 use std::time::Instant;
-use super::channel::{Sender, Receiver};
-use super::packet::{serialize_packet, deserialize_packet};
+use super::channel::{ConnectionMonitor, Sender, Receiver};
+use super::packet::{serialize_packet, deserialize_packet, Fragmenter, Reassembler};
 use super::socket::{Socket, SocketAddr};
 use super::types::*;
 
@@ -17,11 +17,15 @@ enum Status {
         salts_xor: Salt,
         last_server_packet: Instant,
         sender: Sender,
-        receiver: Receiver,
-        pending_unreliable: Vec<Vec<u8>>,
+        receiver: Box<Receiver>,
+        pending_unreliable: Vec<Message>,
+        fragmenter: Fragmenter,
+        reassembler: Reassembler,
+        monitor: Box<ConnectionMonitor>,
     },
     Disconnected {
-        message: String,
+        /// `None` if the client never connected
+        reason: Option<DisconnectReason>,
     },
 }
 
@@ -31,6 +35,7 @@ pub struct Client<S: Socket> {
     status: Status,
     buf: Vec<u8>,
     messages: Vec<(MessageDelivery, Vec<u8>)>,
+    game_version: u32,
 }
 
 impl<S: Socket> Client<S> {
@@ -38,12 +43,20 @@ impl<S: Socket> Client<S> {
         Self {
             server_addr,
             socket,
-            status: Status::Disconnected { message: "Not yet connected".to_owned() },
+            status: Status::Disconnected { reason: None },
             buf: Vec::with_capacity(MAX_PACKET_SIZE),
             messages: Vec::new(),
+            game_version: 0,
         }
     }
 
+    /// Set the version of the messages that the application sends over the connection.
+    /// The server rejects the clients whose version is not its own.
+    pub fn with_game_version(mut self, game_version: u32) -> Self {
+        self.game_version = game_version;
+        self
+    }
+
     pub fn connect(&mut self) {
         match &self.status {
             Status::Disconnected { .. } => {
@@ -62,12 +75,29 @@ impl<S: Socket> Client<S> {
         }
     }
 
+    /// Statistics of the connection to the server, `None` if the client is not connected
+    pub fn stats(&self) -> Option<NetworkStats> {
+        match &self.status {
+            Status::Connected { monitor, .. } => Some(monitor.stats()),
+            _ => None,
+        }
+    }
+
+    /// Why the client was disconnected, `None` if it is connected or never connected
+    pub fn disconnect_reason(&self) -> Option<&DisconnectReason> {
+        match &self.status {
+            Status::Disconnected { reason } => reason.as_ref(),
+            _ => None,
+        }
+    }
+
     pub fn read(&mut self) {
         while let Some((packet_size, src)) = {
             self.buf.resize(MAX_PACKET_SIZE, 0);
             self.socket.receive(&mut self.buf)
         } {
             if src != self.server_addr { continue; }
+            let mut protocol_error = None;
             if let Ok(packet) = deserialize_packet(&mut self.buf[0..packet_size]) {
                 match &mut self.status {
                     Status::ConnectSent { client_salt, .. } => {
@@ -81,9 +111,9 @@ impl<S: Socket> Client<S> {
                                     };
                                 }
                             }
-                            ToClientPacket::Disconnect { salts_xor, message } => {
+                            ToClientPacket::Disconnect { salts_xor, reason } => {
                                 if *client_salt == salts_xor {
-                                    self.status = Status::Disconnected { message };
+                                    self.status = Status::Disconnected { reason: Some(reason) };
                                 }
                             }
                             _ => {}
@@ -100,18 +130,47 @@ impl<S: Socket> Client<S> {
                                                 salts_xor,
                                                 last_server_packet: Instant::now(),
                                                 sender: Sender::new(),
-                                                receiver: Receiver::new(),
+                                                receiver: Box::new(Receiver::new()),
                                                 pending_unreliable: Vec::new(),
+                                                fragmenter: Fragmenter::new(),
+                                                reassembler: Reassembler::new(),
+                                                monitor: Box::new(ConnectionMonitor::new()),
                                             };
                                         }
                                         _ => {}
                                     }
-                                    if let Status::Connected { sender, receiver, .. } = &mut self.status {
+                                    if let Status::Connected { sender, receiver, reassembler, last_server_packet, pending_unreliable, monitor, .. } = &mut self.status {
+                                        *last_server_packet = Instant::now();
+                                        monitor.record_received(packet_size, *last_server_packet);
                                         for msg in messages {
+                                            // Wait until all the fragments of a message are received
+                                            let msg = match msg {
+                                                Message::Fragment { id, index, count, data } => match reassembler.receive(id, index, count, data) {
+                                                    Some(msg) => msg,
+                                                    None => continue,
+                                                },
+                                                msg => msg,
+                                            };
                                             match msg {
                                                 Message::Unreliable(data) => self.messages.push((MessageDelivery::Unreliable, data)),
-                                                Message::Reliable { sequence, data } => receiver.receive(sequence, data),
+                                                Message::UnreliableSequenced { sequence, data } => {
+                                                    if receiver.receive_unreliable_sequenced(sequence) {
+                                                        self.messages.push((MessageDelivery::UnreliableSequenced, data));
+                                                    }
+                                                }
+                                                Message::Reliable { sequence, ordered, data } => match receiver.receive(sequence, data, ordered) {
+                                                    Ok(Some(data)) => self.messages.push((MessageDelivery::ReliableUnordered, data)),
+                                                    Ok(None) => {}
+                                                    Err(e) => {
+                                                        protocol_error = Some(e);
+                                                        break;
+                                                    }
+                                                },
                                                 Message::ReliableAcks { first_sequence, acks } => sender.receive_acks(first_sequence, acks.into()),
+                                                Message::Ping(id) => pending_unreliable.push(Message::Pong(id)),
+                                                Message::Pong(id) => monitor.receive_pong(id, Instant::now()),
+                                                // Reassembled messages are never fragments
+                                                Message::Fragment { .. } => {}
                                             }
                                         }
                                         while let Some(data) = receiver.get_message() {
@@ -120,9 +179,9 @@ impl<S: Socket> Client<S> {
                                     }
                                 }
                             }
-                            ToClientPacket::Disconnect { salts_xor: message_salts_xor, message } => {
+                            ToClientPacket::Disconnect { salts_xor: message_salts_xor, reason } => {
                                 if *salts_xor == message_salts_xor {
-                                    self.status = Status::Disconnected { message };
+                                    self.status = Status::Disconnected { reason: Some(reason) };
                                 }
                             }
                             _ => {}
@@ -131,6 +190,10 @@ impl<S: Socket> Client<S> {
                     Status::Disconnected { .. } => {}
                 }
             }
+            if let Some(e) = protocol_error {
+                log::warn!("Disconnecting from the server: protocol error ({})", e);
+                self.status = Status::Disconnected { reason: Some(DisconnectReason::ProtocolError(e)) };
+            }
         }
     }
 
@@ -144,11 +207,16 @@ impl<S: Socket> Client<S> {
             } => {
                 // Timeout
                 if Instant::now() - *time > DISCONNECT_TIMEOUT {
-                    self.status = Status::Disconnected { message: TIMEOUT_MESSAGE.to_owned() };
+                    self.status = Status::Disconnected { reason: Some(DisconnectReason::TimedOut) };
                     return;
                 }
                 // Send connect packet
-                let connect_packet = ToServerPacket::TryConnect { client_salt: *client_salt, padding: Default::default() };
+                let connect_packet = ToServerPacket::TryConnect {
+                    client_salt: *client_salt,
+                    protocol_version: PROTOCOL_VERSION,
+                    game_version: self.game_version,
+                    padding: Default::default(),
+                };
                 serialize_packet(&mut self.buf, &connect_packet).expect("Failed to serialize TryConnect packet");
                 self.socket.send(&mut self.buf, self.server_addr);
             }
@@ -158,7 +226,7 @@ impl<S: Socket> Client<S> {
             } => {
                 // Timeout
                 if Instant::now() - *time > DISCONNECT_TIMEOUT {
-                    self.status = Status::Disconnected { message: TIMEOUT_MESSAGE.to_owned() };
+                    self.status = Status::Disconnected { reason: Some(DisconnectReason::TimedOut) };
                     return;
                 }
                 // Send challenge response packet
@@ -166,44 +234,60 @@ impl<S: Socket> Client<S> {
                 serialize_packet(&mut self.buf, &connect_packet).expect("Failed to serialize ChallengeResponse packet");
                 self.socket.send(&mut self.buf, self.server_addr);
             }
-            Status::Connected { last_server_packet, salts_xor, pending_unreliable, sender, receiver, .. } => {
+            Status::Connected { last_server_packet, salts_xor, pending_unreliable, sender, receiver, fragmenter, reassembler, monitor } => {
                 // Timeout
                 if Instant::now() - *last_server_packet > DISCONNECT_TIMEOUT {
-                    self.status = Status::Disconnected { message: TIMEOUT_MESSAGE.to_owned() };
+                    self.status = Status::Disconnected { reason: Some(DisconnectReason::TimedOut) };
                     return;
                 }
+                reassembler.remove_expired();
+                // Send a ping from time to time, it also keeps the connection alive
+                if let Some(ping) = monitor.tick(Instant::now()) {
+                    pending_unreliable.push(ping);
+                }
                 let Self { buf, socket, server_addr, .. } = self;
                 let mut packet_body: Vec<Message> = Vec::new();
                 let mut send_message = |message| {
-                    packet_body.push(message);
-                    let mut packet = ToServerPacket::Message {
-                        salts_xor: *salts_xor,
-                        messages: std::mem::replace(&mut packet_body, Vec::new()),
+                    // Split the messages that don't fit in a packet
+                    let messages = match fragmenter.split(message) {
+                        Some(messages) => messages,
+                        None => {
+                            log::warn!("Dropped a message to the server that is too large to be sent");
+                            return true;
+                        }
                     };
-                    // If the new message can't fit in the packet, then send the packet without the new message
-                    // TODO: maybe optimize ?
-                    if serialize_packet(buf, &packet).is_err() {
-                        // Extract last message
-                        let message = match &mut packet {
-                            ToServerPacket::Message { messages, .. } => messages,
-                            _ => unreachable!(),
-                        }.pop().unwrap();
-                        // Send packet
-                        serialize_packet(buf, &packet).expect("Failed to serialize packet to server");
-                        socket.send(buf, *server_addr);
-                        // Prepare next packet
+                    for message in messages {
                         packet_body.push(message);
-                    } else {
-                        match packet {
-                            ToServerPacket::Message { messages, .. } => packet_body = messages,
-                            _ => unreachable!(),
+                        let mut packet = ToServerPacket::Message {
+                            salts_xor: *salts_xor,
+                            messages: std::mem::take(&mut packet_body),
+                        };
+                        // If the new message can't fit in the packet, then send the packet without the new message
+                        // TODO: maybe optimize ?
+                        if serialize_packet(buf, &packet).is_err() {
+                            // Extract last message
+                            let message = match &mut packet {
+                                ToServerPacket::Message { messages, .. } => messages,
+                                _ => unreachable!(),
+                            }.pop().unwrap();
+                            // Send packet
+                            serialize_packet(buf, &packet).expect("Failed to serialize packet to server");
+                            socket.send(buf, *server_addr);
+                            monitor.record_sent(buf.len(), Instant::now());
+                            // Prepare next packet
+                            packet_body.push(message);
+                        } else {
+                            match packet {
+                                ToServerPacket::Message { messages, .. } => packet_body = messages,
+                                _ => unreachable!(),
+                            }
                         }
                     }
                     // TODO: implement rate control
                     true
                 };
                 for message in pending_unreliable.drain(..) {
-                    send_message(Message::Unreliable(message));
+                    send_message(message);
                 }
                 // Send acks
                 let (first_sequence, acks) = receiver.get_acks();
@@ -218,6 +302,7 @@ impl<S: Socket> Client<S> {
                     };
                     serialize_packet(&mut self.buf, &packet).expect("Failed to serialize packet to server");
                     self.socket.send(&mut self.buf, *server_addr);
+                    monitor.record_sent(self.buf.len(), Instant::now());
                 }
             }
             Status::Disconnected {..} => {}
@@ -227,8 +312,10 @@ impl<S: Socket> Client<S> {
     pub fn send_message(&mut self, data: Vec<u8>, delivery: MessageDelivery) {
         if let Status::Connected { sender, pending_unreliable, .. } = &mut self.status {
             match delivery {
-                MessageDelivery::Unreliable => pending_unreliable.push(data),
-                MessageDelivery::Ordered => sender.send(data),
+                MessageDelivery::Unreliable => pending_unreliable.push(Message::Unreliable(data)),
+                MessageDelivery::UnreliableSequenced => pending_unreliable.push(sender.send_unreliable_sequenced(data)),
+                MessageDelivery::ReliableUnordered => sender.send(data, false),
+                MessageDelivery::Ordered => sender.send(data, true),
             }
         }
     }
@@ -8,4 +8,4 @@ mod types;
 pub use client::Client;
 pub use server::{Server, ServerEvent};
 pub use socket::{Socket, SocketAddr};
-pub use types::MessageDelivery;
\ No newline at end of file
+pub use types::{DisconnectReason, MessageDelivery, NetworkStats, PROTOCOL_VERSION};
\ No newline at end of file
@@ -1,12 +1,17 @@
 use bincode::{DefaultOptions, Options};
 use crc::crc32;
 use serde::{Serialize, de::DeserializeOwned};
+use std::collections::HashMap;
+use std::time::Instant;
 use super::types::*;
 
 lazy_static::lazy_static! {
     static ref BINCODE_OPTIONS: bincode::config::WithOtherLimit<DefaultOptions, bincode::config::Bounded> = {
         DefaultOptions::default().with_limit(MAX_PACKET_CONTENT as u64)
     };
+    static ref FRAGMENTED_BINCODE_OPTIONS: bincode::config::WithOtherLimit<DefaultOptions, bincode::config::Bounded> = {
+        DefaultOptions::default().with_limit((MAX_FRAGMENTS * FRAGMENT_SIZE) as u64)
+    };
 }
 
 pub fn serialize_packet<P: Serialize>(target: &mut Vec<u8>, packet: &P) -> bincode::Result<()> {
@@ -58,6 +63,127 @@ pub fn deserialize_packet<P: DeserializeOwned>(source: &mut [u8]) -> bincode::Re
     BINCODE_OPTIONS.deserialize_from(&source[HEADER_SIZE..])
 }
 
+/// Splits the messages that don't fit in a packet into fragments
+pub struct Fragmenter {
+    next_unreliable_id: u32,
+}
+
+impl Fragmenter {
+    pub fn new() -> Self {
+        Self {
+            next_unreliable_id: 0,
+        }
+    }
+
+    /// Split the message if it's too large to be sent in a single packet.
+    /// Return `None` if the message is too large to be fragmented.
+    pub fn split(&mut self, message: Message) -> Option<Vec<Message>> {
+        let size = FRAGMENTED_BINCODE_OPTIONS.serialized_size(&message).ok()? as usize;
+        if size <= MAX_UNFRAGMENTED_MESSAGE {
+            return Some(vec![message]);
+        }
+        let id = match &message {
+            Message::Reliable { sequence, .. } => FragmentId::Reliable(*sequence),
+            _ => {
+                self.next_unreliable_id = self.next_unreliable_id.wrapping_add(1);
+                FragmentId::Unreliable(self.next_unreliable_id)
+            }
+        };
+        let serialized = FRAGMENTED_BINCODE_OPTIONS.serialize(&message).ok()?;
+        let count = serialized.len().div_ceil(FRAGMENT_SIZE);
+        Some(serialized
+            .chunks(FRAGMENT_SIZE)
+            .enumerate()
+            .map(|(index, data)| Message::Fragment {
+                id,
+                index: index as u16,
+                count: count as u16,
+                data: data.to_vec(),
+            })
+            .collect())
+    }
+}
+
+/// The fragments of a message that were received so far
+struct PartialMessage {
+    fragments: Vec<Option<Vec<u8>>>,
+    missing_fragments: usize,
+    last_fragment: Instant,
+}
+
+/// Reassembles the fragmented messages, the fragments can be received in any order
+pub struct Reassembler {
+    partial_messages: HashMap<FragmentId, PartialMessage>,
+    /// Total size of the data of the fragments in `partial_messages`
+    partial_bytes: usize,
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Self {
+            partial_messages: HashMap::new(),
+            partial_bytes: 0,
+        }
+    }
+
+    /// Receive a fragment, and return the message if it was the last missing fragment.
+    /// Invalid fragments are ignored.
+    pub fn receive(&mut self, id: FragmentId, index: u16, count: u16, data: Vec<u8>) -> Option<Message> {
+        let (index, count) = (index as usize, count as usize);
+        if count == 0 || count > MAX_FRAGMENTS || index >= count || data.len() > FRAGMENT_SIZE {
+            return None;
+        }
+        if !self.partial_messages.contains_key(&id) && self.partial_messages.len() >= MAX_PARTIAL_MESSAGES {
+            return None;
+        }
+        let partial = self.partial_messages.entry(id).or_insert_with(|| PartialMessage {
+            fragments: vec![None; count],
+            missing_fragments: count,
+            last_fragment: Instant::now(),
+        });
+        if partial.fragments.len() != count {
+            return None;
+        }
+        partial.last_fragment = Instant::now();
+        if partial.fragments[index].is_none() {
+            if self.partial_bytes + data.len() > MAX_PARTIAL_BYTES {
+                return None;
+            }
+            self.partial_bytes += data.len();
+            partial.fragments[index] = Some(data);
+            partial.missing_fragments -= 1;
+        }
+        if partial.missing_fragments > 0 {
+            return None;
+        }
+        let partial = self.partial_messages.remove(&id).unwrap();
+        self.partial_bytes -= partial_size(&partial);
+        let serialized: Vec<u8> = partial.fragments.into_iter().flat_map(Option::unwrap).collect();
+        match FRAGMENTED_BINCODE_OPTIONS.deserialize(&serialized) {
+            // A fragment can't contain another fragment
+            Ok(Message::Fragment { .. }) | Err(_) => None,
+            Ok(message) => Some(message),
+        }
+    }
+
+    /// Drop the incomplete messages that didn't receive a fragment for `FRAGMENT_TIMEOUT`
+    pub fn remove_expired(&mut self) {
+        let now = Instant::now();
+        let partial_bytes = &mut self.partial_bytes;
+        self.partial_messages.retain(|_, partial| {
+            let expired = now - partial.last_fragment > FRAGMENT_TIMEOUT;
+            if expired {
+                *partial_bytes -= partial_size(partial);
+            }
+            !expired
+        });
+    }
+}
+
+/// Size of the data of the fragments received so far
+fn partial_size(partial: &PartialMessage) -> usize {
+    partial.fragments.iter().flatten().map(Vec::len).sum()
+}
 
 #[test]
 fn test_ser_de() {
@@ -75,3 +201,59 @@ fn test_ser_de() {
     let msg2 = deserialize_packet(&mut v[..]).unwrap();
     assert_eq!(msg1, msg2);
 }
+
+#[test]
+fn test_fragments_out_of_order() {
+    let message = Message::Reliable {
+        sequence: 42,
+        ordered: true,
+        data: (0..5000).map(|i| i as u8).collect(),
+    };
+    let mut fragments = Fragmenter::new().split(message).unwrap();
+    assert!(fragments.len() > 1);
+    // Every fragment must fit in a packet
+    for fragment in fragments.iter() {
+        let packet = ToClientPacket::Message { salts_xor: Salt::MAX, messages: vec![fragment.clone()] };
+        serialize_packet(&mut Vec::new(), &packet).unwrap();
+    }
+    // Receive the fragments in reverse order, with a duplicate
+    fragments.reverse();
+    fragments.insert(1, fragments[2].clone());
+    let mut reassembler = Reassembler::new();
+    let last = fragments.pop().unwrap();
+    for fragment in fragments {
+        if let Message::Fragment { id, index, count, data } = fragment {
+            assert_eq!(reassembler.receive(id, index, count, data), None);
+        }
+    }
+    if let Message::Fragment { id, index, count, data } = last {
+        let reassembled = reassembler.receive(id, index, count, data).unwrap();
+        match reassembled {
+            Message::Reliable { sequence, ordered, data } => {
+                assert_eq!((sequence, ordered), (42, true));
+                assert_eq!(data, (0..5000).map(|i| i as u8).collect::<Vec<_>>());
+            }
+            _ => panic!("wrong reassembled message"),
+        }
+    }
+}
+
+#[test]
+fn test_reassembler_byte_budget() {
+    // Incomplete messages of the maximum size can't use more than the budget
+    let mut reassembler = Reassembler::new();
+    for id in 0..3 {
+        for index in 0..MAX_FRAGMENTS - 1 {
+            let data = vec![0; FRAGMENT_SIZE];
+            let id = FragmentId::Unreliable(id);
+            assert_eq!(reassembler.receive(id, index as u16, MAX_FRAGMENTS as u16, data), None);
+        }
+    }
+    assert!(reassembler.partial_bytes <= MAX_PARTIAL_BYTES);
+    assert_eq!(reassembler.partial_bytes, partial_bytes_sum(&reassembler));
+}
+
+#[cfg(test)]
+fn partial_bytes_sum(reassembler: &Reassembler) -> usize {
+    reassembler.partial_messages.values().map(partial_size).sum()
+}
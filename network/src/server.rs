@@ -1,6 +1,6 @@
 use std::time::Instant;
-use super::channel::{Sender, Receiver};
-use super::packet::{serialize_packet, deserialize_packet};
+use super::channel::{ConnectionMonitor, Sender, Receiver};
+use super::packet::{serialize_packet, deserialize_packet, Fragmenter, Reassembler};
 use super::socket::{Socket, SocketAddr};
 use super::types::*;
 
@@ -19,8 +19,11 @@ enum ClientSlot {
         last_client_packet: Instant,
         remote: SocketAddr,
         sender: Sender,
-        receiver: Receiver,
-        pending_unreliable: Vec<Vec<u8>>,
+        receiver: Box<Receiver>,
+        pending_unreliable: Vec<Message>,
+        fragmenter: Fragmenter,
+        reassembler: Reassembler,
+        monitor: Box<ConnectionMonitor>,
     },
 }
 
@@ -42,6 +45,7 @@ pub struct Server<S: Socket> {
     players: [ClientSlot; MAX_PLAYERS],
     buf: Vec<u8>,
     events: Vec<ServerEvent>,
+    game_version: u32,
 }
 
 impl<S: Socket> Server<S> {
@@ -51,21 +55,27 @@ impl<S: Socket> Server<S> {
             players: Default::default(),
             buf: Vec::with_capacity(MAX_PACKET_SIZE),
             events: Vec::new(),
+            game_version: 0,
         }
     }
 
+    /// Set the version of the messages that the application sends over the connections.
+    /// The clients with another version are rejected.
+    pub fn with_game_version(mut self, game_version: u32) -> Self {
+        self.game_version = game_version;
+        self
+    }
+
     pub fn read(&mut self) {
         while let Some((packet_size, src)) = {
             self.buf.resize(MAX_PACKET_SIZE, 0);
             self.socket.receive(&mut self.buf)
         } {
+            // Corrupted packets are dropped: anyone can send them with the address of a client, so they can't disconnect it.
+            // Only the protocol errors in the packets that carry the salts of a client disconnect it.
             let packet = match deserialize_packet(&mut self.buf[0..packet_size]) {
                 Ok(packet) => packet,
-                Err(e) => {
-                    dbg!(&mut self.buf[0..packet_size]);
-                    dbg!(e);
-                    continue
-                },
+                Err(_) => continue,
             };
             if let Some(i) = self.find_client_slot(src) {
                 match &mut self.players[i] {
@@ -79,8 +89,11 @@ impl<S: Socket> Server<S> {
                                         last_client_packet: Instant::now(),
                                         remote: src,
                                         sender: Sender::new(),
-                                        receiver: Receiver::new(),
+                                        receiver: Box::new(Receiver::new()),
                                         pending_unreliable: Vec::new(),
+                                        fragmenter: Fragmenter::new(),
+                                        reassembler: Reassembler::new(),
+                                        monitor: Box::new(ConnectionMonitor::new()),
                                     };
                                     self.events.push(ServerEvent::Connected { id: src });
                                 }
@@ -88,19 +101,63 @@ impl<S: Socket> Server<S> {
                             _ => {}
                         }
                     }
-                    &mut ClientSlot::Connected { salts_xor, ref mut sender, ref mut receiver, .. } => {
+                    &mut ClientSlot::Connected {
+                        salts_xor,
+                        ref mut sender,
+                        ref mut receiver,
+                        ref mut reassembler,
+                        ref mut last_client_packet,
+                        ref mut pending_unreliable,
+                        ref mut monitor,
+                        ..
+                    } => {
+                        let mut protocol_error = None;
                         match packet {
                             ToServerPacket::Message { salts_xor: packet_salts_xor, messages } => {
                                 if salts_xor == packet_salts_xor {
+                                    *last_client_packet = Instant::now();
+                                    monitor.record_received(packet_size, *last_client_packet);
                                     for message in messages {
+                                        // Wait until all the fragments of a message are received
+                                        let message = match message {
+                                            Message::Fragment { id, index, count, data } => match reassembler.receive(id, index, count, data) {
+                                                Some(message) => message,
+                                                None => continue,
+                                            },
+                                            message => message,
+                                        };
                                         match message {
                                             Message::Unreliable(data) => self.events.push(ServerEvent::Message {
                                                 source_id: src,
                                                 kind: MessageDelivery::Unreliable,
                                                 data,
                                             }),
-                                            Message::Reliable { sequence, data } => receiver.receive(sequence, data),
+                                            Message::UnreliableSequenced { sequence, data } => {
+                                                if receiver.receive_unreliable_sequenced(sequence) {
+                                                    self.events.push(ServerEvent::Message {
+                                                        source_id: src,
+                                                        kind: MessageDelivery::UnreliableSequenced,
+                                                        data,
+                                                    });
+                                                }
+                                            }
+                                            Message::Reliable { sequence, ordered, data } => match receiver.receive(sequence, data, ordered) {
+                                                Ok(Some(data)) => self.events.push(ServerEvent::Message {
+                                                    source_id: src,
+                                                    kind: MessageDelivery::ReliableUnordered,
+                                                    data,
+                                                }),
+                                                Ok(None) => {}
+                                                Err(e) => {
+                                                    protocol_error = Some(e);
+                                                    break;
+                                                }
+                                            },
                                             Message::ReliableAcks { first_sequence, acks } => sender.receive_acks(first_sequence, acks.into()),
+                                            Message::Ping(id) => pending_unreliable.push(Message::Pong(id)),
+                                            Message::Pong(id) => monitor.receive_pong(id, Instant::now()),
+                                            // Reassembled messages are never fragments
+                                            Message::Fragment { .. } => {}
                                         }
                                     }
                                     while let Some(data) = receiver.get_message() {
@@ -120,25 +177,78 @@ impl<S: Socket> Server<S> {
                             }
                             _ => {}
                         }
+                        if let Some(e) = protocol_error {
+                            self.disconnect_slot(i, DisconnectReason::ProtocolError(e));
+                        }
                     }
                 }
-            } else if let Some(i) = self.find_free_slot() {
+            } else {
                 match packet {
-                    ToServerPacket::TryConnect { client_salt, .. } => {
-                        let server_salt: Salt = rand::random();
-                        self.players[i] = ClientSlot::ConnectReceived {
-                            client_salt,
-                            server_salt,
-                            time: Instant::now(),
-                            remote: src,
-                        }
+                    ToServerPacket::TryConnect { client_salt, protocol_version, game_version, .. } => {
+                        let free_slot = self.find_free_slot();
+                        // The client sends TryConnect until it receives a response, so the rejection is sent once per packet
+                        let rejection = if protocol_version != PROTOCOL_VERSION {
+                            DisconnectReason::VersionMismatch {
+                                client_version: protocol_version,
+                                server_version: PROTOCOL_VERSION,
+                            }
+                        } else if game_version != self.game_version {
+                            DisconnectReason::GameVersionMismatch {
+                                client_version: game_version,
+                                server_version: self.game_version,
+                            }
+                        } else if let Some(i) = free_slot {
+                            let server_salt: Salt = rand::random();
+                            self.players[i] = ClientSlot::ConnectReceived {
+                                client_salt,
+                                server_salt,
+                                time: Instant::now(),
+                                remote: src,
+                            };
+                            continue;
+                        } else {
+                            DisconnectReason::ServerFull
+                        };
+                        let disconnect_packet = ToClientPacket::Disconnect { salts_xor: client_salt, reason: rejection };
+                        serialize_packet(&mut self.buf, &disconnect_packet).expect("Failed to serialize Disconnect packet");
+                        self.socket.send(&self.buf, src);
                     }
                     _ => {}
                 }
+            }
+        }
+    }
+
+    /// Statistics of the connection to a client, `None` if the client is not connected
+    pub fn stats(&self, id: SocketAddr) -> Option<NetworkStats> {
+        match self.find_client_slot(id).map(|i| &self.players[i]) {
+            Some(ClientSlot::Connected { monitor, .. }) => Some(monitor.stats()),
+            _ => None,
+        }
+    }
+
+    /// Disconnect a client, sending it the message
+    pub fn kick(&mut self, id: SocketAddr, message: String) {
+        if let Some(i) = self.find_client_slot(id) {
+            self.disconnect_slot(i, DisconnectReason::Kicked(message));
+        }
+    }
+
+    /// Disconnect the client of a slot, sending it the reason if it was connected
+    fn disconnect_slot(&mut self, i: usize, reason: DisconnectReason) {
+        if let ClientSlot::Connected { salts_xor, remote, .. } = self.players[i] {
+            log::warn!("Disconnecting client {}: {}", remote, reason);
+            let disconnect_packet = ToClientPacket::Disconnect { salts_xor, reason };
+            if serialize_packet(&mut self.buf, &disconnect_packet).is_ok() {
+                for _ in 0..DISCONNECT_PACKET_COPIES {
+                    self.socket.send(&self.buf, remote);
+                }
             } else {
-                // TODO: send 10x server full message ?
+                log::warn!("Failed to serialize Disconnect packet, the disconnect reason may be too long");
             }
+            self.events.push(ServerEvent::Disconnected { id: remote });
         }
+        self.players[i] = ClientSlot::Empty;
     }
 
     fn find_client_slot(&self, addr: SocketAddr) -> Option<usize> {
@@ -181,45 +291,61 @@ impl<S: Socket> Server<S> {
                     serialize_packet(&mut self.buf, &challenge_packet).expect("Failed to serialize Challenge packet");
                     self.socket.send(&mut self.buf, *remote);
                 }
-                ClientSlot::Connected { last_client_packet, salts_xor, remote, pending_unreliable, sender, receiver, .. } => {
+                ClientSlot::Connected { last_client_packet, salts_xor, remote, pending_unreliable, sender, receiver, fragmenter, reassembler, monitor } => {
                     // Timeout
                     if Instant::now() - *last_client_packet > DISCONNECT_TIMEOUT {
                         self.events.push(ServerEvent::Disconnected { id: *remote });
                         *slot = ClientSlot::Empty {};
                         return;
                     }
+                    reassembler.remove_expired();
+                    // Send a ping from time to time, it also keeps the connection alive
+                    if let Some(ping) = monitor.tick(Instant::now()) {
+                        pending_unreliable.push(ping);
+                    }
                     let Self { buf, socket, .. } = self;
                     let mut packet_body: Vec<Message> = Vec::new();
                     let mut send_message = |message| {
-                        packet_body.push(message);
-                        let mut packet = ToClientPacket::Message {
-                            salts_xor: *salts_xor,
-                            messages: std::mem::replace(&mut packet_body, Vec::new()),
+                        // Split the messages that don't fit in a packet
+                        let messages = match fragmenter.split(message) {
+                            Some(messages) => messages,
+                            None => {
+                                log::warn!("Dropped a message to {} that is too large to be sent", remote);
+                                return true;
+                            }
                         };
-                        // If the new message can't fit in the packet, then send the packet without the new message
-                        // TODO: maybe optimize ?
-                        if serialize_packet(buf, &packet).is_err() {
-                            // Extract last message
-                            let message = match &mut packet {
-                                ToClientPacket::Message { messages, .. } => messages,
-                                _ => unreachable!(),
-                            }.pop().unwrap();
-                            // Send packet
-                            serialize_packet(buf, &packet).expect("Failed to serialize packet to client");
-                            socket.send(buf, *remote);
-                            // Prepare next packet
+                        for message in messages {
                             packet_body.push(message);
-                        } else {
-                            match packet {
-                                ToClientPacket::Message { messages, .. } => packet_body = messages,
-                                _ => unreachable!(),
+                            let mut packet = ToClientPacket::Message {
+                                salts_xor: *salts_xor,
+                                messages: std::mem::take(&mut packet_body),
+                            };
+                            // If the new message can't fit in the packet, then send the packet without the new message
+                            // TODO: maybe optimize ?
+                            if serialize_packet(buf, &packet).is_err() {
+                                // Extract last message
+                                let message = match &mut packet {
+                                    ToClientPacket::Message { messages, .. } => messages,
+                                    _ => unreachable!(),
+                                }.pop().unwrap();
+                                // Send packet
+                                serialize_packet(buf, &packet).expect("Failed to serialize packet to client");
+                                socket.send(buf, *remote);
+                                monitor.record_sent(buf.len(), Instant::now());
+                                // Prepare next packet
+                                packet_body.push(message);
+                            } else {
+                                match packet {
+                                    ToClientPacket::Message { messages, .. } => packet_body = messages,
+                                    _ => unreachable!(),
+                                }
                             }
                         }
                         // TODO: implement rate control
                         true
                     };
                     for message in pending_unreliable.drain(..) {
-                        send_message(Message::Unreliable(message));
+                        send_message(message);
                     }
                     // Send acks
                     let (first_sequence, acks) = receiver.get_acks();
@@ -234,6 +360,7 @@ impl<S: Socket> Server<S> {
                         };
                         serialize_packet(&mut self.buf, &packet).expect("Failed to serialize packet to client");
                         self.socket.send(&mut self.buf, *remote);
+                        monitor.record_sent(self.buf.len(), Instant::now());
                     }
                 }
             }
@@ -250,10 +377,16 @@ impl<S: Socket> Server<S> {
             } = &mut self.players[slot] {
                 match delivery {
                     MessageDelivery::Unreliable => {
-                        pending_unreliable.push(data);
+                        pending_unreliable.push(Message::Unreliable(data));
+                    }
+                    MessageDelivery::UnreliableSequenced => {
+                        pending_unreliable.push(sender.send_unreliable_sequenced(data));
+                    }
+                    MessageDelivery::ReliableUnordered => {
+                        sender.send(data, false);
                     }
                     MessageDelivery::Ordered => {
-                        sender.send(data);
+                        sender.send(data, true);
                     }
                 }
             }
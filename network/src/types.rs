@@ -11,32 +11,59 @@ pub const MAX_PACKET_SIZE: usize = 1200;
 pub const HEADER_SIZE: usize = 4; // only CRC32
 pub const MAX_PACKET_CONTENT: usize = MAX_PACKET_SIZE - HEADER_SIZE;
 pub const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(5);
-pub const TIMEOUT_MESSAGE: &'static str = "Timed out";
+/// Version of the network protocol, a client can only connect to a server with the same version
+pub const PROTOCOL_VERSION: u32 = 3;
+/// Number of times a disconnect packet is sent, since it is not acked
+pub const DISCONNECT_PACKET_COPIES: usize = 10;
 pub const RELIABLE_BUFFER_SIZE: usize = 1024;
 pub const RESEND_DELAY: Duration = Duration::from_millis(100);
+/// Maximum serialized size of a message sent in a single packet, larger messages are fragmented
+pub const MAX_UNFRAGMENTED_MESSAGE: usize = MAX_PACKET_CONTENT - 32;
+/// Size of the data of every fragment but the last one
+pub const FRAGMENT_SIZE: usize = MAX_PACKET_CONTENT - 64;
+/// Maximum number of fragments of a message
+pub const MAX_FRAGMENTS: usize = 1024;
+/// Maximum number of messages that can be reassembled at the same time
+pub const MAX_PARTIAL_MESSAGES: usize = 8;
+/// Maximum number of bytes of the fragments kept by the reassembler of a peer, so that a peer can't exhaust the memory
+pub const MAX_PARTIAL_BYTES: usize = 2 * MAX_FRAGMENTS * FRAGMENT_SIZE;
+/// Time after which the fragments of an incomplete message are dropped
+pub const FRAGMENT_TIMEOUT: Duration = Duration::from_secs(2);
+/// Time between two pings, that also keep the connection alive
+pub const PING_INTERVAL: Duration = Duration::from_millis(250);
+/// A ping that is not answered within this time is considered lost
+pub const PING_TIMEOUT: Duration = Duration::from_secs(2);
+/// Duration over which the bandwidth is averaged
+pub const BANDWIDTH_WINDOW: Duration = Duration::from_secs(1);
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub enum ToClientPacket {
     Challenge { client_salt: Salt, server_salt: Salt },
     Message { salts_xor: Salt, messages: Vec<Message> },
-    Disconnect { salts_xor: Salt, message: String }, // salts_xor is just the client salt if the server is full
+    Disconnect { salts_xor: Salt, reason: DisconnectReason }, // salts_xor is just the client salt if the connection was rejected
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub enum ToServerPacket {
-    TryConnect { client_salt: Salt, padding: [[u8; 32]; 32] },
+    TryConnect { client_salt: Salt, protocol_version: u32, game_version: u32, padding: [[u8; 32]; 32] },
     ChallengeResponse { salts_xor: Salt, padding: [[u8; 32]; 32] },
     Message { salts_xor: Salt, messages: Vec<Message> },
     Disconnect { salts_xor: Salt },
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum Message {
     /// Unreliable message
     Unreliable(Vec<u8>),
-    /// Reliable message
+    /// Unreliable message, dropped if a more recent one was already received
+    UnreliableSequenced {
+        sequence: Sequence,
+        data: Vec<u8>,
+    },
+    /// Reliable message, `ordered` is false if it can be delivered before the previous reliable messages
     Reliable {
         sequence: Sequence,
+        ordered: bool,
         data: Vec<u8>,
     },
     /// Acks for reliable messages
@@ -44,19 +71,109 @@ pub enum Message {
     ReliableAcks {
         first_sequence: Sequence,
         acks: SimpleBitSet,
+    },
+    /// Ask the other side to answer with a `Pong` with the same id, to measure the round trip time
+    Ping(u32),
+    /// Answer to a `Ping`
+    Pong(u32),
+    /// Part of a serialized message that doesn't fit in a packet
+    Fragment {
+        id: FragmentId,
+        index: u16,
+        count: u16,
+        data: Vec<u8>,
+    },
+}
+
+/// Id of a fragmented message.
+/// Reliable messages keep the same id when they are resent, so the fragments of several sends can be combined.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FragmentId {
+    Reliable(Sequence),
+    Unreliable(u32),
+}
+
+/// Why a client was disconnected from the server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DisconnectReason {
+    /// The client and the server don't use the same protocol version
+    VersionMismatch { client_version: u32, server_version: u32 },
+    /// The client and the server don't use the same version of the messages of the game
+    GameVersionMismatch { client_version: u32, server_version: u32 },
+    /// The server has no free slot for the client
+    ServerFull,
+    /// The server kicked the client with the given message
+    Kicked(String),
+    /// No packet was received for `DISCONNECT_TIMEOUT`
+    TimedOut,
+    /// The other side sent a packet or a message that doesn't follow the protocol
+    ProtocolError(String),
+}
+
+impl std::fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::VersionMismatch { client_version, server_version } => write!(
+                f,
+                "Protocol version mismatch (client: {}, server: {})",
+                client_version, server_version
+            ),
+            Self::GameVersionMismatch { client_version, server_version } => write!(
+                f,
+                "Game version mismatch (client: {}, server: {})",
+                client_version, server_version
+            ),
+            Self::ServerFull => write!(f, "Server is full"),
+            Self::Kicked(message) => write!(f, "Kicked: {}", message),
+            Self::TimedOut => write!(f, "Timed out"),
+            Self::ProtocolError(error) => write!(f, "Protocol error: {}", error),
+        }
+    }
+}
+
+/// Statistics of a connection
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct NetworkStats {
+    /// Smoothed round trip time, `None` until the first pong is received
+    pub rtt: Option<Duration>,
+    /// Fraction of the recent pings that were lost, between 0 and 1
+    pub packet_loss: f32,
+    /// Bytes sent per second
+    pub upload: f64,
+    /// Bytes received per second
+    pub download: f64,
+}
+
+impl std::fmt::Display for NetworkStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.rtt {
+            Some(rtt) => write!(f, "rtt = {} ms", rtt.as_millis())?,
+            None => write!(f, "rtt = ?")?,
+        }
+        write!(
+            f,
+            ", loss = {:.1}%, up = {:.1} kB/s, down = {:.1} kB/s",
+            self.packet_loss * 100.0,
+            self.upload / 1000.0,
+            self.download / 1000.0,
+        )
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum MessageDelivery {
     /// Message may not arrive.
     Unreliable,
+    /// Message may not arrive, and is dropped if a more recent UnreliableSequenced message already arrived.
+    UnreliableSequenced,
+    /// The message is guaranteed to arrive exactly once, but maybe before some previous messages.
+    ReliableUnordered,
     /// The message is guaranteed to arrive exactly once in order (with respect to the other Ordered messages).
     Ordered,
 }
 
 // For easier serialization
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct SimpleBitSet {
     last_byte_bits: u8,
     bytes: Vec<u8>,
@@ -0,0 +1,75 @@
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+use voxel_rs_network::{Client, Server, ServerEvent, SocketAddr, MessageDelivery};
+
+mod common;
+use self::common::{DummySocket, DummySocketConfig};
+
+fn large_message(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed)).collect()
+}
+
+// Server sends two large messages to client, client sends back a large message to server
+#[test]
+fn test_fragmentation_with_loss_and_jitter() {
+    let config = DummySocketConfig {
+        packet_loss: 0.2,
+        latency: Duration::from_millis(20),
+        max_jitter: Duration::from_millis(50),
+    };
+    let sleep_duration = Duration::from_millis(10);
+    let client_addr = SocketAddr::from_str("127.0.0.1:44").unwrap();
+    let server_addr = SocketAddr::from_str("127.0.0.1:45").unwrap();
+    thread::spawn(move || {
+        let client_socket = DummySocket::new(client_addr, config);
+        let mut client = Client::new(client_socket, server_addr);
+        client.connect();
+
+        let mut received = [false; 2];
+        loop {
+            client.tick();
+            for (_, data) in client.get_messages() {
+                if data == large_message(20_000, 1) {
+                    received[0] = true;
+                } else if data == large_message(30_000, 2) {
+                    received[1] = true;
+                }
+            }
+            if received == [true, true] {
+                client.send_message(large_message(10_000, 3), MessageDelivery::Ordered);
+                received = [false, false];
+            }
+            thread::sleep(sleep_duration);
+        }
+    });
+
+    let server_thread = thread::spawn(move || {
+        let server_socket = DummySocket::new(server_addr, config);
+        let mut server = Server::new(server_socket);
+
+        loop {
+            server.tick();
+            let mut send_id = None;
+            for event in server.get_events() {
+                match event {
+                    ServerEvent::Connected { id } => {
+                        send_id = Some(id);
+                    }
+                    ServerEvent::Message { data, .. } if data == large_message(10_000, 3) => {
+                        return true;
+                    }
+                    _ => {}
+                }
+            }
+            if let Some(id) = send_id {
+                server.send_message(id, large_message(20_000, 1), MessageDelivery::Ordered);
+                server.send_message(id, large_message(30_000, 2), MessageDelivery::ReliableUnordered);
+            }
+            thread::sleep(sleep_duration);
+        }
+    });
+
+    let join_result = server_thread.join();
+    assert!(join_result.unwrap(), "Server received the client's message");
+}
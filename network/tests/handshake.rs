@@ -0,0 +1,76 @@
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+use voxel_rs_network::{Client, DisconnectReason, Server, SocketAddr};
+
+mod common;
+use self::common::{DummySocket, NO_LOSS_CONFIG};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// Connect until the server is full, then kick a client
+#[test]
+fn test_server_full_and_kick() {
+    let server_addr = SocketAddr::from_str("127.0.0.1:50").unwrap();
+    let mut server = Server::new(DummySocket::new(server_addr, NO_LOSS_CONFIG));
+    let mut clients: Vec<_> = (0..11)
+        .map(|i| {
+            let client_addr = SocketAddr::from_str(&format!("127.0.0.1:{}", 51 + i)).unwrap();
+            Client::new(DummySocket::new(client_addr, NO_LOSS_CONFIG), server_addr)
+        })
+        .collect();
+    let tick = |server: &mut Server<DummySocket>, clients: &mut [Client<DummySocket>]| {
+        server.tick();
+        server.get_events().for_each(drop);
+        for client in clients.iter_mut() {
+            client.tick();
+            client.get_messages().for_each(drop);
+        }
+        thread::sleep(Duration::from_millis(5));
+    };
+
+    // The first 10 clients connect before the last one tries to
+    for client in clients[..10].iter_mut() {
+        client.connect();
+    }
+    let start = Instant::now();
+    while !clients[..10].iter().all(|client| client.is_connected()) {
+        assert!(start.elapsed() < TIMEOUT, "Clients failed to connect");
+        tick(&mut server, &mut clients[..10]);
+    }
+    clients[10].connect();
+    while clients[10].disconnect_reason() != Some(&DisconnectReason::ServerFull) {
+        assert!(start.elapsed() < TIMEOUT, "Client was not rejected");
+        tick(&mut server, &mut clients);
+    }
+
+    server.kick(SocketAddr::from_str("127.0.0.1:51").unwrap(), "Bye".to_owned());
+    let kicked = DisconnectReason::Kicked("Bye".to_owned());
+    while clients[0].disconnect_reason() != Some(&kicked) {
+        assert!(start.elapsed() < TIMEOUT, "Client was not kicked");
+        tick(&mut server, &mut clients[..10]);
+    }
+    assert!(clients[1..10].iter().all(|client| client.is_connected()));
+}
+
+// A client with another version of the game messages is rejected
+#[test]
+fn test_game_version_mismatch() {
+    let server_addr = SocketAddr::from_str("127.0.0.1:70").unwrap();
+    let mut server = Server::new(DummySocket::new(server_addr, NO_LOSS_CONFIG)).with_game_version(1);
+    let client_addr = SocketAddr::from_str("127.0.0.1:71").unwrap();
+    let mut client = Client::new(DummySocket::new(client_addr, NO_LOSS_CONFIG), server_addr).with_game_version(2);
+
+    client.connect();
+    let rejected = DisconnectReason::GameVersionMismatch { client_version: 2, server_version: 1 };
+    let start = Instant::now();
+    while client.disconnect_reason() != Some(&rejected) {
+        assert!(start.elapsed() < TIMEOUT, "Client was not rejected");
+        server.tick();
+        server.get_events().for_each(drop);
+        client.tick();
+        client.get_messages().for_each(drop);
+        thread::sleep(Duration::from_millis(5));
+    }
+    assert!(!client.is_connected());
+}
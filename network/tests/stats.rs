@@ -0,0 +1,36 @@
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+use voxel_rs_network::{Client, Server, SocketAddr};
+
+mod common;
+use self::common::{DummySocket, DummySocketConfig};
+
+// The pings measure the latency of the sockets in both directions
+#[test]
+fn test_connection_stats() {
+    let config = DummySocketConfig {
+        packet_loss: 0.0,
+        latency: Duration::from_millis(50),
+        max_jitter: Duration::from_millis(0),
+    };
+    let client_addr = SocketAddr::from_str("127.0.0.1:42").unwrap();
+    let server_addr = SocketAddr::from_str("127.0.0.1:43").unwrap();
+    let mut client = Client::new(DummySocket::new(client_addr, config), server_addr);
+    let mut server = Server::new(DummySocket::new(server_addr, config));
+    client.connect();
+
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(2) {
+        client.tick();
+        server.tick();
+        thread::sleep(Duration::from_millis(5));
+    }
+
+    for stats in [client.stats().unwrap(), server.stats(client_addr).unwrap()].iter() {
+        let rtt = stats.rtt.expect("No pong was received");
+        assert!(rtt >= Duration::from_millis(100) && rtt < Duration::from_millis(300), "{}", stats);
+        assert_eq!(stats.packet_loss, 0.0);
+        assert!(stats.upload > 0.0 && stats.download > 0.0, "{}", stats);
+    }
+}
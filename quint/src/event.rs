@@ -1,3 +1,5 @@
+use crate::Position;
+
 /// The state of a button.
 #[derive(Debug, Clone, Copy)]
 pub enum ButtonState {
@@ -14,12 +16,45 @@ pub enum MouseButton {
     Other(u16),
 }
 
+/// A key used to edit text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Left,
+    Right,
+    Home,
+    End,
+    Backspace,
+    Delete,
+    Enter,
+    Escape,
+    /// Selects all the text when pressed with ctrl.
+    A,
+}
+
+/// The modifiers held when a key is pressed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+}
+
 /// A Ui event.
 #[derive(Debug, Clone, Copy)]
 pub enum Event {
+    /// The cursor moved to a new position.
+    CursorMoved(Position),
+    /// The cursor moved while the left mouse button is held.
+    /// It is sent by the `Ui` after `CursorMoved`, with the position where the button was pressed.
+    MouseDrag { start: Position },
+    /// The mouse wheel was scrolled by some logical pixels, positive when scrolling up.
+    MouseWheel { delta: f32 },
     /// A change in the state of a mouse button.
     MouseInput {
         state: ButtonState,
         button: MouseButton,
     },
+    /// A key used to edit text was pressed.
+    KeyPressed { key: Key, modifiers: Modifiers },
+    /// A character was typed or pasted.
+    Character(char),
 }
@@ -1,5 +1,5 @@
 /// The computed layout of a `Widget`.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Layout {
     pub x: f32,
     pub y: f32,
@@ -31,4 +31,25 @@ impl Layout {
             height: self.height - 2.0 * padding_pixels,
         }
     }
+
+    /// Move this `Layout` by some logical pixels
+    pub fn translate(&self, offset: crate::geometry::Position) -> Self {
+        Self {
+            x: self.x + offset.x,
+            y: self.y + offset.y,
+            ..*self
+        }
+    }
+
+    /// The part of this `Layout` that is also inside `other`. The size is 0 if they don't overlap.
+    pub fn intersection(&self, other: &Layout) -> Self {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        Self {
+            x,
+            y,
+            width: ((self.x + self.width).min(other.x + other.width) - x).max(0.0),
+            height: ((self.y + self.height).min(other.y + other.height) - y).max(0.0),
+        }
+    }
 }
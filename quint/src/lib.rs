@@ -2,10 +2,14 @@ mod event;
 mod geometry;
 mod layout;
 mod style;
+mod text_input;
 mod ui;
+mod widgets;
 
-pub use event::{ButtonState, Event, MouseButton};
+pub use event::{ButtonState, Event, Key, Modifiers, MouseButton};
 pub use geometry::{Position, Size};
 pub use layout::Layout;
 pub use style::Style;
-pub use ui::{Ui, Widget, WidgetTree};
+pub use text_input::{TextInputEvent, TextInputState};
+pub use ui::{Backend, Ui, Widget, WidgetTree};
+pub use widgets::{ScrollView, Slider};
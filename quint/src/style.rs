@@ -60,6 +60,11 @@ impl Style {
     pub fn absolute_size(self, width: f32, height: f32) -> Self {
         self.absolute_width(width).absolute_height(height)
     }
+    /// Don't shrink when the parent is too small, for example for the content of a `ScrollView`
+    pub fn no_shrink(mut self) -> Self {
+        self.style.flex_shrink = 0.0;
+        self
+    }
 }
 
 impl Default for Style {
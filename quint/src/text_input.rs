@@ -0,0 +1,211 @@
+use crate::{Key, Modifiers};
+
+/// What a text input widget reports to the application, to be applied to its `TextInputState`.
+#[derive(Debug, Clone, Copy)]
+pub enum TextInputEvent {
+    /// The input was clicked, with the index of the character under the cursor.
+    Click(usize),
+    /// The mouse was pressed outside of the focused input.
+    Unfocus,
+    /// A character was typed or pasted in the focused input.
+    Character(char),
+    /// A key was pressed in the focused input.
+    Key(Key, Modifiers),
+}
+
+/// The text, the cursor and the selection of a text input.
+///
+/// The state is owned by the application: the widget gets a copy when the Ui is rebuilt,
+/// and reports the `TextInputEvent`s that the application applies to the state.
+/// Positions are in characters, not in bytes.
+#[derive(Debug, Clone, Default)]
+pub struct TextInputState {
+    text: String,
+    cursor: usize,
+    /// The other end of the selection, `None` if no text is selected.
+    anchor: Option<usize>,
+    focused: bool,
+}
+
+impl TextInputState {
+    /// Create a new unfocused input with the cursor at the end of `text`.
+    pub fn new(text: String) -> Self {
+        let cursor = text.chars().count();
+        Self {
+            text,
+            cursor,
+            anchor: None,
+            focused: false,
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Replace the text and move the cursor to its end.
+    pub fn set_text(&mut self, text: String) {
+        self.cursor = text.chars().count();
+        self.anchor = None;
+        self.text = text;
+    }
+
+    pub fn is_focused(&self) -> bool {
+        self.focused
+    }
+
+    pub fn set_focused(&mut self, focused: bool) {
+        self.focused = focused;
+        if !focused {
+            self.anchor = None;
+        }
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// The selected range of characters, if it's not empty.
+    pub fn selection(&self) -> Option<(usize, usize)> {
+        match self.anchor {
+            Some(anchor) if anchor < self.cursor => Some((anchor, self.cursor)),
+            Some(anchor) if anchor > self.cursor => Some((self.cursor, anchor)),
+            _ => None,
+        }
+    }
+
+    /// Apply an event reported by the widget. Return `true` if the text was submitted with enter.
+    pub fn apply(&mut self, event: TextInputEvent) -> bool {
+        match event {
+            TextInputEvent::Click(index) => {
+                self.focused = true;
+                self.cursor = index.min(self.len());
+                self.anchor = None;
+            }
+            TextInputEvent::Unfocus => self.set_focused(false),
+            // Control characters are also sent for the editing keys, which are handled separately
+            TextInputEvent::Character(c) if !c.is_control() => self.insert(c),
+            TextInputEvent::Character(_) => {}
+            TextInputEvent::Key(key, modifiers) => return self.press_key(key, modifiers),
+        }
+        false
+    }
+
+    fn press_key(&mut self, key: Key, modifiers: Modifiers) -> bool {
+        let len = self.len();
+        match key {
+            Key::Left => self.move_cursor(self.cursor.saturating_sub(1), modifiers.shift),
+            Key::Right => self.move_cursor((self.cursor + 1).min(len), modifiers.shift),
+            Key::Home => self.move_cursor(0, modifiers.shift),
+            Key::End => self.move_cursor(len, modifiers.shift),
+            Key::Backspace => {
+                if !self.delete_selection() && self.cursor > 0 {
+                    self.cursor -= 1;
+                    let index = self.byte_index(self.cursor);
+                    self.text.remove(index);
+                }
+            }
+            Key::Delete => {
+                if !self.delete_selection() && self.cursor < len {
+                    let index = self.byte_index(self.cursor);
+                    self.text.remove(index);
+                }
+            }
+            Key::Enter => return true,
+            Key::Escape => self.set_focused(false),
+            Key::A if modifiers.ctrl => {
+                self.anchor = Some(0);
+                self.cursor = len;
+            }
+            Key::A => {}
+        }
+        false
+    }
+
+    /// Move the cursor, extending the selection if `select` is `true`.
+    fn move_cursor(&mut self, cursor: usize, select: bool) {
+        if select {
+            if self.anchor.is_none() {
+                self.anchor = Some(self.cursor);
+            }
+        } else {
+            self.anchor = None;
+        }
+        self.cursor = cursor;
+    }
+
+    /// Insert a character at the cursor, replacing the selection.
+    fn insert(&mut self, c: char) {
+        self.delete_selection();
+        let index = self.byte_index(self.cursor);
+        self.text.insert(index, c);
+        self.cursor += 1;
+    }
+
+    /// Delete the selected text, returning `false` if there was no selection.
+    fn delete_selection(&mut self) -> bool {
+        let selection = self.selection();
+        self.anchor = None;
+        match selection {
+            Some((start, end)) => {
+                let range = self.byte_index(start)..self.byte_index(end);
+                self.text.replace_range(range, "");
+                self.cursor = start;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.text.chars().count()
+    }
+
+    fn byte_index(&self, index: usize) -> usize {
+        self.text
+            .char_indices()
+            .nth(index)
+            .map_or(self.text.len(), |(i, _)| i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(state: &mut TextInputState, key: Key, shift: bool) -> bool {
+        state.apply(TextInputEvent::Key(key, Modifiers { shift, ctrl: false }))
+    }
+
+    #[test]
+    fn test_text_input_editing() {
+        let mut state = TextInputState::new("héllo".to_owned());
+        assert_eq!(state.cursor(), 5);
+        for c in " wörld\u{8}".chars() {
+            state.apply(TextInputEvent::Character(c));
+        }
+        assert_eq!(state.text(), "héllo wörld");
+
+        // Select "wörld" and replace it
+        press(&mut state, Key::End, false);
+        for _ in 0..5 {
+            press(&mut state, Key::Left, true);
+        }
+        assert_eq!(state.selection(), Some((6, 11)));
+        state.apply(TextInputEvent::Character('x'));
+        assert_eq!(state.text(), "héllo x");
+        assert_eq!(state.selection(), None);
+
+        state.apply(TextInputEvent::Click(1));
+        assert!(state.is_focused());
+        press(&mut state, Key::Delete, false);
+        press(&mut state, Key::Backspace, false);
+        assert_eq!(state.text(), "llo x");
+        assert_eq!(state.cursor(), 0);
+
+        state.apply(TextInputEvent::Key(Key::A, Modifiers { shift: false, ctrl: true }));
+        press(&mut state, Key::Backspace, false);
+        assert_eq!(state.text(), "");
+        assert!(press(&mut state, Key::Enter, false));
+    }
+}
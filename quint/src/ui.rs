@@ -1,4 +1,4 @@
-use crate::{Event, Layout, Position, Size, Style};
+use crate::{ButtonState, Event, Layout, MouseButton, Position, Size, Style};
 use std::collections::HashMap;
 use stretch::{node::Node, Stretch};
 
@@ -11,21 +11,34 @@ struct UiLayer<Renderer, Message> {
 /// User interface rendered using a `Renderer` and with widgets sending messages of type `Message`.
 ///
 /// Every frame, you should first update and then you rebuild the Ui.
-/// Don't forget to set the cursor position whenever it changes.
+/// Don't forget to send `Event::CursorMoved` whenever the cursor moves, so that the widgets can be dragged.
 pub struct Ui<Renderer, Message> {
     cursor_position: Position,
+    /// Where the left mouse button was pressed, if it is held
+    drag_start: Option<Position>,
     layers: Vec<UiLayer<Renderer, Message>>,
 }
 
+/// A widget with its computed position, as visited by `Ui::visit_layer`
+struct VisitedWidget<'a, Renderer, Message> {
+    widget: &'a dyn Widget<Renderer, Message>,
+    /// The absolute layout of the widget
+    layout: Layout,
+    z_index: i32,
+    /// The area the widget is clipped to, if any
+    clip: Option<Layout>,
+}
+
 impl<Renderer, Message> Ui<Renderer, Message> {
     pub fn new() -> Self {
         Self {
             cursor_position: Position::default(),
+            drag_start: None,
             layers: Vec::new(),
         }
     }
 
-    /// Set the cursor position
+    /// Set the cursor position without sending `Event::CursorMoved`
     pub fn set_cursor_position(&mut self, position: Position) {
         self.cursor_position = position;
     }
@@ -34,38 +47,98 @@ impl<Renderer, Message> Ui<Renderer, Message> {
     pub fn update(&mut self, events: Vec<Event>) -> Vec<Message> {
         let mut messages = Vec::new();
         for event in events.into_iter() {
-            self.propagate_event(event, &mut messages);
+            match event {
+                Event::CursorMoved(position) => {
+                    self.cursor_position = position;
+                    self.propagate_event(event, &mut messages);
+                    if let Some(start) = self.drag_start {
+                        self.propagate_event(Event::MouseDrag { start }, &mut messages);
+                    }
+                }
+                Event::MouseInput {
+                    button: MouseButton::Left,
+                    state,
+                } => {
+                    self.drag_start = match state {
+                        ButtonState::Pressed => Some(self.cursor_position),
+                        ButtonState::Released => None,
+                    };
+                    self.propagate_event(event, &mut messages);
+                }
+                _ => self.propagate_event(event, &mut messages),
+            }
         }
         messages
     }
 
     fn propagate_event(&self, event: Event, messages: &mut Vec<Message>) {
         for layer in self.layers.iter() {
-            let mut node_stack = vec![layer.root_node];
-            while let Some(current_node) = node_stack.pop() {
-                // Update widget if it exists
-                if let Some(widget) = layer.widgets.get(&current_node) {
-                    let layout = layer
-                        .stretch
-                        .layout(current_node)
-                        .expect("Couldn't get Node layout");
-                    widget.on_event(
-                        event,
-                        Layout::from_stretch(*layout),
+            Self::visit_layer(layer, |visited| {
+                // The widgets don't see the cursor where they are clipped
+                let (event, cursor_position) = match event {
+                    Event::MouseDrag { start } => (
+                        Event::MouseDrag {
+                            start: clip_position(start, visited.clip),
+                        },
                         self.cursor_position,
-                        messages,
-                    );
-                }
+                    ),
+                    _ => (event, clip_position(self.cursor_position, visited.clip)),
+                };
+                visited
+                    .widget
+                    .on_event(event, visited.layout, cursor_position, messages);
+            });
+        }
+    }
 
-                // Push child widgets onto the stack
-                node_stack.extend(
-                    layer
-                        .stretch
-                        .children(current_node)
-                        .expect("Couldn't get Node children")
-                        .into_iter(),
-                );
+    /// Visit every widget of a layer, parents before children.
+    fn visit_layer<'a>(
+        layer: &'a UiLayer<Renderer, Message>,
+        mut f: impl FnMut(VisitedWidget<'a, Renderer, Message>),
+    ) {
+        // The stretch layouts are relative to the parent, so the absolute position of the parent is kept
+        let mut node_stack = vec![(layer.root_node, 0, Position::default(), None)];
+        while let Some((current_node, parent_z_index, origin, parent_clip)) = node_stack.pop() {
+            let layout = layer
+                .stretch
+                .layout(current_node)
+                .expect("Couldn't get Node layout");
+            let layout = Layout::from_stretch(*layout).translate(origin);
+            let mut z_index = parent_z_index;
+            let mut children_origin = Position {
+                x: layout.x,
+                y: layout.y,
+            };
+            let mut clip = parent_clip;
+            if let Some(widget) = layer.widgets.get(&current_node) {
+                z_index += widget.z_index();
+                f(VisitedWidget {
+                    widget: widget.as_ref(),
+                    layout,
+                    z_index,
+                    clip: parent_clip,
+                });
+                let offset = widget.children_offset(layout);
+                children_origin.x += offset.x;
+                children_origin.y += offset.y;
+                if widget.clips_children() {
+                    clip = Some(match parent_clip {
+                        Some(parent_clip) => layout.intersection(&parent_clip),
+                        None => layout,
+                    });
+                }
             }
+
+            // Push child widgets onto the stack
+            let children = layer
+                .stretch
+                .children(current_node)
+                .expect("Couldn't get Node children");
+            node_stack.extend(
+                children
+                    .into_iter()
+                    .map(|child| (child, z_index, children_origin, clip)),
+            );
         }
     }
 
@@ -107,37 +180,55 @@ impl<Renderer, Message> Ui<Renderer, Message> {
             })
             .collect();
     }
+}
 
+impl<Renderer: Backend, Message> Ui<Renderer, Message> {
     /// Render the Ui using the provided `Renderer`.
     pub fn render(&self, renderer: &mut Renderer) {
-        // Recursively render every widget of every layer, the last layer being rendered first
+        // Render every widget of every layer, the last layer being rendered first
         for layer in self.layers.iter().rev() {
-            let mut render_stack = vec![layer.root_node];
-            while let Some(current_node) = render_stack.pop() {
-                // Draw widget if it exists
-                if let Some(widget) = layer.widgets.get(&current_node) {
-                    let layout = layer
-                        .stretch
-                        .layout(current_node)
-                        .expect("Couldn't get Node layout");
-                    widget.render(
-                        renderer,
-                        self.cursor_position,
-                        Layout::from_stretch(*layout),
-                    );
-                }
-
-                // Push child widgets onto the stack
-                let children = layer
-                    .stretch
-                    .children(current_node)
-                    .expect("Couldn't get Node children");
-                render_stack.extend(children.into_iter());
-            }
+            Self::visit_layer(layer, |visited| {
+                renderer.set_z_index(visited.z_index);
+                renderer.set_clip_rect(visited.clip);
+                visited.widget.render(
+                    renderer,
+                    clip_position(self.cursor_position, visited.clip),
+                    visited.layout,
+                );
+            });
         }
+        renderer.set_clip_rect(None);
+    }
+}
+
+/// A position that is outside of every layout
+const HIDDEN_POSITION: Position = Position {
+    x: f32::INFINITY,
+    y: f32::INFINITY,
+};
+
+/// Hide a position if it is outside of the clipping area of a widget
+fn clip_position(position: Position, clip: Option<Layout>) -> Position {
+    match clip {
+        Some(clip) if !clip.is_position_inside(position) => HIDDEN_POSITION,
+        _ => position,
     }
 }
 
+/// What the widgets of this crate draw with.
+pub trait Backend {
+    /// The text that is drawn
+    type Text: Clone;
+
+    /// Set the z-index of the next rendered widget. Widgets with a higher z-index must be drawn over the others,
+    /// and widgets with the same z-index in the order they are rendered.
+    fn set_z_index(&mut self, z_index: i32);
+    /// Set the area the next rendered widget is clipped to, `None` if it's not clipped
+    fn set_clip_rect(&mut self, clip: Option<Layout>);
+    fn draw_rectangle(&mut self, color: [f32; 4], layout: Layout);
+    fn draw_text(&mut self, text: Self::Text, layout: Layout, centered: bool);
+}
+
 /// A generic Widget.
 pub trait Widget<Renderer, Message> {
     // TODO: add screen size
@@ -145,6 +236,18 @@ pub trait Widget<Renderer, Message> {
     fn style(&self) -> Style;
     /// Render the widget using the renderer
     fn render(&self, _renderer: &mut Renderer, _cursor_position: Position, _layout: Layout) {}
+    /// The z-index of the widget and its children, relative to its parent
+    fn z_index(&self) -> i32 {
+        0
+    }
+    /// `true` if the children of the widget are clipped to its layout
+    fn clips_children(&self) -> bool {
+        false
+    }
+    /// How much the children of the widget are moved from where the layout puts them, used to scroll
+    fn children_offset(&self, _layout: Layout) -> Position {
+        Position::default()
+    }
     /// Process one event
     fn on_event(
         &self,
@@ -0,0 +1,304 @@
+use crate::{Backend, ButtonState, Event, Layout, MouseButton, Position, Style, Widget};
+
+/// Width of the scrollbar of a `ScrollView`, in logical pixels
+const SCROLLBAR_WIDTH: f32 = 12.0;
+/// Minimum height of the thumb of the scrollbar, so that it can still be dragged
+const MIN_THUMB_HEIGHT: f32 = 20.0;
+/// Space between the border of a slider and its filled part
+const SLIDER_PADDING: f32 = 4.0;
+
+/// A horizontal slider. Clicking or dragging it sends a message with the value under the cursor.
+pub struct Slider<B: Backend, Message> {
+    pub text: B::Text,
+    pub value: f64,
+    pub min: f64,
+    pub max: f64,
+    pub on_change: fn(f64) -> Message,
+    pub style: Style,
+    pub background_color: [f32; 4],
+    pub hover_color: [f32; 4],
+    pub fill_color: [f32; 4],
+}
+
+impl<B: Backend, Message> Slider<B, Message> {
+    /// The value under the cursor, clamped to the range of the slider
+    fn value_at(&self, layout: Layout, cursor_position: Position) -> f64 {
+        let fraction = ((cursor_position.x - layout.x) / layout.width).clamp(0.0, 1.0) as f64;
+        self.min + fraction * (self.max - self.min)
+    }
+}
+
+impl<B: Backend, Message> Widget<B, Message> for Slider<B, Message> {
+    fn style(&self) -> Style {
+        self.style.clone()
+    }
+
+    fn render(&self, renderer: &mut B, cursor_position: Position, layout: Layout) {
+        let background_color = if layout.is_position_inside(cursor_position) {
+            self.hover_color
+        } else {
+            self.background_color
+        };
+        renderer.draw_rectangle(background_color, layout);
+        // Filled part of the slider
+        let fraction = ((self.value - self.min) / (self.max - self.min)).clamp(0.0, 1.0) as f32;
+        let mut filled = layout.with_padding(SLIDER_PADDING);
+        filled.width *= fraction;
+        renderer.draw_rectangle(self.fill_color, filled);
+        renderer.draw_text(self.text.clone(), layout, true);
+    }
+
+    fn on_event(
+        &self,
+        event: Event,
+        layout: Layout,
+        cursor_position: Position,
+        messages: &mut Vec<Message>,
+    ) {
+        let grabbed = match event {
+            Event::MouseInput {
+                button: MouseButton::Left,
+                state: ButtonState::Pressed,
+            } => layout.is_position_inside(cursor_position),
+            // Keep following the cursor when it leaves the slider
+            Event::MouseDrag { start } => layout.is_position_inside(start),
+            _ => false,
+        };
+        if grabbed {
+            messages.push((self.on_change)(self.value_at(layout, cursor_position)));
+        }
+    }
+}
+
+/// A vertical list whose children are clipped to its layout and can be scrolled with the mouse wheel
+/// or by dragging the scrollbar.
+///
+/// The application owns the scroll offset and updates it with the messages sent by `on_scroll`.
+/// The children should use `Style::no_shrink` so that they keep their size when they don't fit.
+pub struct ScrollView<Message> {
+    /// How far the content is scrolled, in logical pixels
+    pub offset: f32,
+    /// Total height of the children, in logical pixels
+    pub content_height: f32,
+    pub on_scroll: fn(f32) -> Message,
+    pub style: Style,
+    pub scrollbar_color: [f32; 4],
+}
+
+impl<Message> ScrollView<Message> {
+    fn max_offset(&self, layout: Layout) -> f32 {
+        (self.content_height - layout.height).max(0.0)
+    }
+
+    /// The offset, clamped in case the content or the layout shrunk
+    fn clamped_offset(&self, layout: Layout) -> f32 {
+        self.offset.max(0.0).min(self.max_offset(layout))
+    }
+
+    fn scrollbar(&self, layout: Layout) -> Layout {
+        Layout {
+            x: layout.x + layout.width - SCROLLBAR_WIDTH,
+            width: SCROLLBAR_WIDTH,
+            ..layout
+        }
+    }
+
+    fn thumb_height(&self, layout: Layout) -> f32 {
+        (layout.height * layout.height / self.content_height)
+            .max(MIN_THUMB_HEIGHT)
+            .min(layout.height)
+    }
+
+    /// The offset that puts the center of the thumb under the cursor
+    fn offset_at(&self, layout: Layout, cursor_position: Position) -> f32 {
+        let thumb_height = self.thumb_height(layout);
+        let track = layout.height - thumb_height;
+        if track <= 0.0 {
+            return 0.0;
+        }
+        let fraction = ((cursor_position.y - layout.y - thumb_height / 2.0) / track).clamp(0.0, 1.0);
+        fraction * self.max_offset(layout)
+    }
+}
+
+impl<B: Backend, Message> Widget<B, Message> for ScrollView<Message> {
+    fn style(&self) -> Style {
+        self.style.clone()
+    }
+
+    fn render(&self, renderer: &mut B, _cursor_position: Position, layout: Layout) {
+        let max_offset = self.max_offset(layout);
+        if max_offset <= 0.0 {
+            return;
+        }
+        let scrollbar = self.scrollbar(layout);
+        let [r, g, b, a] = self.scrollbar_color;
+        renderer.draw_rectangle([r, g, b, a * 0.3], scrollbar);
+        let thumb_height = self.thumb_height(layout);
+        let thumb = Layout {
+            y: layout.y + self.clamped_offset(layout) / max_offset * (layout.height - thumb_height),
+            height: thumb_height,
+            ..scrollbar
+        };
+        renderer.draw_rectangle(self.scrollbar_color, thumb);
+    }
+
+    fn clips_children(&self) -> bool {
+        true
+    }
+
+    fn children_offset(&self, layout: Layout) -> Position {
+        Position {
+            x: 0.0,
+            y: -self.clamped_offset(layout),
+        }
+    }
+
+    fn on_event(
+        &self,
+        event: Event,
+        layout: Layout,
+        cursor_position: Position,
+        messages: &mut Vec<Message>,
+    ) {
+        if self.max_offset(layout) <= 0.0 {
+            return;
+        }
+        let scrollbar = self.scrollbar(layout);
+        let offset = match event {
+            Event::MouseWheel { delta } if layout.is_position_inside(cursor_position) => {
+                self.clamped_offset(layout) - delta
+            }
+            Event::MouseInput {
+                button: MouseButton::Left,
+                state: ButtonState::Pressed,
+            } if scrollbar.is_position_inside(cursor_position) => self.offset_at(layout, cursor_position),
+            Event::MouseDrag { start } if scrollbar.is_position_inside(start) => {
+                self.offset_at(layout, cursor_position)
+            }
+            _ => return,
+        };
+        messages.push((self.on_scroll)(offset.max(0.0).min(self.max_offset(layout))));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Size, Ui, WidgetTree};
+
+    /// A backend that records the clipping area of the rectangles
+    #[derive(Default)]
+    struct TestBackend {
+        clip: Option<Layout>,
+        rectangles: Vec<(Layout, Option<Layout>)>,
+    }
+
+    impl Backend for TestBackend {
+        type Text = ();
+
+        fn set_z_index(&mut self, _z_index: i32) {}
+        fn set_clip_rect(&mut self, clip: Option<Layout>) {
+            self.clip = clip;
+        }
+        fn draw_rectangle(&mut self, _color: [f32; 4], layout: Layout) {
+            self.rectangles.push((layout, self.clip));
+        }
+        fn draw_text(&mut self, _text: (), _layout: Layout, _centered: bool) {}
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Message {
+        Scroll(f32),
+        Slide(f64),
+    }
+
+    fn scrolled_slider(offset: f32) -> WidgetTree<TestBackend, Message> {
+        let slider = |height: f32| Slider {
+            text: (),
+            value: 0.0,
+            min: 0.0,
+            max: 10.0,
+            on_change: Message::Slide,
+            style: Style::default().absolute_size(100.0, height).no_shrink(),
+            background_color: [0.0; 4],
+            hover_color: [0.0; 4],
+            fill_color: [0.0; 4],
+        };
+        WidgetTree::new(
+            Box::new(ScrollView {
+                offset,
+                content_height: 200.0,
+                on_scroll: Message::Scroll,
+                style: Style::default().absolute_size(120.0, 100.0).vertical(),
+                scrollbar_color: [1.0; 4],
+            }),
+            vec![WidgetTree::new_leaf(Box::new(slider(150.0))), WidgetTree::new_leaf(Box::new(slider(50.0)))],
+        )
+    }
+
+    fn size() -> Size {
+        Size {
+            width: 500.0,
+            height: 500.0,
+        }
+    }
+
+    fn press() -> Event {
+        Event::MouseInput {
+            button: MouseButton::Left,
+            state: ButtonState::Pressed,
+        }
+    }
+
+    #[test]
+    fn test_scroll_view_clipping() {
+        let mut ui = Ui::new();
+        ui.rebuild(vec![scrolled_slider(0.0)], size());
+
+        // The slider at the bottom is hidden: it can't be clicked and it's clipped
+        ui.set_cursor_position(Position { x: 50.0, y: 175.0 });
+        assert_eq!(ui.update(vec![press()]), vec![]);
+        let mut backend = TestBackend::default();
+        ui.render(&mut backend);
+        let clip = backend.rectangles.last().unwrap().1.unwrap();
+        assert_eq!((clip.y, clip.height), (0.0, 100.0));
+        assert!(backend.clip.is_none());
+
+        // Scrolling to the bottom moves it into view
+        ui.set_cursor_position(Position { x: 50.0, y: 50.0 });
+        assert_eq!(
+            ui.update(vec![Event::MouseWheel { delta: -500.0 }]),
+            vec![Message::Scroll(100.0)]
+        );
+        ui.rebuild(vec![scrolled_slider(100.0)], size());
+        ui.set_cursor_position(Position { x: 25.0, y: 75.0 });
+        assert_eq!(ui.update(vec![press()]), vec![Message::Slide(2.5)]);
+    }
+
+    #[test]
+    fn test_drag() {
+        let mut ui = Ui::new();
+        ui.rebuild(vec![scrolled_slider(100.0)], size());
+
+        // Dragging the slider keeps changing it outside of its layout
+        ui.update(vec![Event::CursorMoved(Position { x: 50.0, y: 75.0 }), press()]);
+        assert_eq!(
+            ui.update(vec![Event::CursorMoved(Position { x: 400.0, y: 300.0 })]),
+            vec![Message::Slide(10.0)]
+        );
+        let release = Event::MouseInput {
+            button: MouseButton::Left,
+            state: ButtonState::Released,
+        };
+        ui.update(vec![release]);
+        assert_eq!(ui.update(vec![Event::CursorMoved(Position { x: 50.0, y: 75.0 })]), vec![]);
+
+        // Dragging the scrollbar scrolls
+        ui.update(vec![Event::CursorMoved(Position { x: 115.0, y: 90.0 }), press()]);
+        assert_eq!(
+            ui.update(vec![Event::CursorMoved(Position { x: 115.0, y: 0.0 })]),
+            vec![Message::Scroll(0.0)]
+        );
+    }
+}
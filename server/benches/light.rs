@@ -0,0 +1,75 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use voxel_rs_common::block::{Block, BlockType};
+use voxel_rs_common::registry::Registry;
+use voxel_rs_common::world::{Chunk, CHUNK_SIZE};
+use voxel_rs_server::light::{BlockLight, HighestOpaqueBlock};
+
+const STONE: u16 = 1;
+
+/// Air and an opaque block
+fn block_light() -> BlockLight {
+    let mut blocks = Registry::default();
+    blocks.register("air".to_owned(), Block { name: "air".to_owned(), block_type: BlockType::Air }).unwrap();
+    let stone = ron::de::from_str("NormalCube(face_textures: [])").unwrap();
+    blocks.register("stone".to_owned(), Block { name: "stone".to_owned(), block_type: stone }).unwrap();
+    BlockLight::new(&blocks)
+}
+
+/// A chunk with a wavy stone surface, and caves below it so that removing a block scans a few blocks down
+fn terrain_chunk() -> Chunk {
+    let mut chunk = Chunk::new((0, 0, 0).into());
+    for i in 0..CHUNK_SIZE {
+        for k in 0..CHUNK_SIZE {
+            let height = 16 + ((i as f64 * 0.3).sin() * 4.0 + (k as f64 * 0.2).cos() * 4.0) as u32;
+            for j in 0..height {
+                if j % 4 != 0 || j < 4 {
+                    chunk.set_block_at((i, j, k), STONE);
+                }
+            }
+        }
+    }
+    chunk
+}
+
+/// The highest block of a column of the terrain
+fn surface(chunk: &Chunk, i: u32, k: u32) -> u32 {
+    (0..CHUNK_SIZE).rev().find(|&j| chunk.get_block_at((i, j, k)) != 0).unwrap()
+}
+
+fn light_benchmark(c: &mut Criterion) {
+    let block_light = block_light();
+    let mut chunk = terrain_chunk();
+    let hob = HighestOpaqueBlock::from_chunk(&chunk, &block_light);
+    // Removing the highest block of a column is the slowest incremental update, the column is scanned again
+    let (i, k) = (7, 11);
+    let j = surface(&chunk, i, k);
+    chunk.set_block_at((i, j, k), 0);
+
+    c.bench_function("hob full rescan", |b| {
+        b.iter(|| HighestOpaqueBlock::from_chunk(black_box(&chunk), &block_light))
+    });
+    c.bench_function("hob incremental update, removed block", |b| {
+        b.iter(|| {
+            let mut hob = hob.clone();
+            hob.update_block(black_box(&chunk), &block_light, (i, j, k));
+            hob
+        })
+    });
+
+    // Placing a block above the surface only compares it with the current HOB
+    let mut chunk = terrain_chunk();
+    let hob = HighestOpaqueBlock::from_chunk(&chunk, &block_light);
+    let j = surface(&chunk, i, k) + 3;
+    chunk.set_block_at((i, j, k), STONE);
+    c.bench_function("hob incremental update, placed block", |b| {
+        b.iter(|| {
+            let mut hob = hob.clone();
+            hob.update_block(black_box(&chunk), &block_light, (i, j, k));
+            hob
+        })
+    });
+    c.bench_function("hob clone", |b| b.iter(|| black_box(&hob).clone()));
+}
+
+criterion_group!(benches, light_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,572 @@
+//! Import of the worlds saved in the Minecraft Anvil format (https://minecraft.gamepedia.com/Anvil_file_format).
+//!
+//! The blocks of every region file are read, converted to the blocks of the registry using a mapping table
+//! from the Minecraft block names, and saved as the modified chunks of a world. The imported chunks replace the
+//! generated chunks, the rest of the world is generated as usual.
+//! Only the block names are imported, the block states, the entities and the light are ignored.
+use crate::save::{self, WorldMetadata};
+use crate::DATA_FOLDER;
+use anyhow::{Context, Result};
+use log::{info, warn};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use voxel_rs_common::{
+    block::{entity::ChunkBlockEntities, Block, BlockId},
+    data::load_data,
+    item::Item,
+    registry::Registry,
+    world::{BlockPos, Chunk, ChunkPos},
+};
+
+/// Number of chunks in a region file
+const REGION_CHUNKS: usize = 1024;
+/// Size of a sector of a region file, in bytes
+const SECTOR_SIZE: usize = 4096;
+/// Size of a section along every axis, in blocks
+const SECTION_SIZE: usize = 16;
+/// First data version where the block states are not split across two longs (20w17a)
+const NON_SPANNING_DATA_VERSION: i32 = 2529;
+
+/// How the Minecraft blocks are converted to the blocks of the registry
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BlockMapping {
+    /// The block used for the Minecraft blocks that are not in `blocks`, or air if `None`
+    pub default_block: Option<String>,
+    /// The block names of the registry, by Minecraft name without the `minecraft:` prefix
+    pub blocks: HashMap<String, String>,
+}
+
+impl Default for BlockMapping {
+    fn default() -> Self {
+        let mut blocks = HashMap::new();
+        let table = [
+            ("stone", "stone"),
+            ("granite", "stone"),
+            ("diorite", "stone"),
+            ("andesite", "stone"),
+            ("cobblestone", "stone"),
+            ("stone_bricks", "stone"),
+            ("bedrock", "stone"),
+            ("gravel", "stone"),
+            ("grass_block", "dirt_grass"),
+            ("dirt", "dirt"),
+            ("coarse_dirt", "dirt"),
+            ("podzol", "dirt"),
+            ("sand", "sand"),
+            ("sandstone", "sand"),
+            ("water", "water"),
+            ("oak_log", "wood"),
+            ("spruce_log", "wood"),
+            ("birch_log", "wood"),
+            ("jungle_log", "wood"),
+            ("acacia_log", "wood"),
+            ("dark_oak_log", "wood"),
+            ("oak_planks", "wood"),
+            ("spruce_planks", "wood"),
+            ("birch_planks", "wood"),
+            ("oak_leaves", "leaves"),
+            ("spruce_leaves", "leaves"),
+            ("birch_leaves", "leaves"),
+            ("jungle_leaves", "leaves"),
+            ("acacia_leaves", "leaves"),
+            ("dark_oak_leaves", "leaves"),
+            ("red_bed", "bed"),
+            ("white_bed", "bed"),
+        ];
+        for &(minecraft_name, name) in table.iter() {
+            blocks.insert(minecraft_name.to_owned(), name.to_owned());
+        }
+        Self {
+            default_block: None,
+            blocks,
+        }
+    }
+}
+
+impl BlockMapping {
+    /// Load a mapping table from a RON file
+    pub fn load(path: &Path) -> Result<Self> {
+        let buf = std::fs::read_to_string(path)
+            .context(format!("Failed to read block mapping {}", path.display()))?;
+        ron::de::from_str(&buf).context(format!("Failed to parse block mapping {}", path.display()))
+    }
+
+    /// Resolve the Minecraft block names to the block ids of the registry
+    fn resolve(&self, blocks: &Registry<Block>) -> Result<ResolvedMapping> {
+        let get_id = |name: &String| {
+            blocks
+                .get_id_by_name(name)
+                .map(|id| id as BlockId)
+                .ok_or_else(|| anyhow::anyhow!("Unknown block {} in the block mapping", name))
+        };
+        let mut ids = HashMap::new();
+        for (minecraft_name, name) in self.blocks.iter() {
+            ids.insert(minecraft_name.clone(), get_id(name)?);
+        }
+        let default_id = match &self.default_block {
+            Some(name) => get_id(name)?,
+            None => 0,
+        };
+        Ok(ResolvedMapping {
+            ids,
+            default_id,
+            unmapped: HashMap::new(),
+        })
+    }
+}
+
+/// A mapping table with block ids, counting the blocks that are not in the table
+struct ResolvedMapping {
+    ids: HashMap<String, BlockId>,
+    default_id: BlockId,
+    unmapped: HashMap<String, u64>,
+}
+
+impl ResolvedMapping {
+    fn get(&mut self, minecraft_name: &str) -> BlockId {
+        let name = minecraft_name
+            .strip_prefix("minecraft:")
+            .unwrap_or(minecraft_name);
+        if name.ends_with("air") {
+            return 0;
+        }
+        match self.ids.get(name) {
+            Some(&id) => id,
+            None => {
+                *self.unmapped.entry(name.to_owned()).or_insert(0) += 1;
+                self.default_id
+            }
+        }
+    }
+}
+
+/// Create the world `world_name` from the region files of `region_folder`.
+/// The spawn point is still chosen by the world generator when the world is first loaded.
+pub fn import_world(
+    region_folder: &Path,
+    world_name: String,
+    mapping: &BlockMapping,
+) -> Result<WorldMetadata> {
+    let world = save::create_world(world_name, 0)?;
+    let block_palette = save::load_block_palette(&world)?;
+    let game_data = load_data(DATA_FOLDER.into(), &block_palette)?;
+    save::save_block_palette(&world, game_data.blocks.get_names())?;
+    import_regions(
+        region_folder,
+        &world,
+        &game_data.blocks,
+        &game_data.items,
+        mapping,
+    )?;
+    info!("Imported world {}", world.name);
+    Ok(world)
+}
+
+/// Import all the region files (`r.<x>.<z>.mca`) of `region_folder` into `world`.
+/// `blocks` must use the block palette of the world.
+pub fn import_regions(
+    region_folder: &Path,
+    world: &WorldMetadata,
+    blocks: &Registry<Block>,
+    items: &Registry<Item>,
+    mapping: &BlockMapping,
+) -> Result<()> {
+    let mut mapping = mapping.resolve(blocks)?;
+    let mut region_files = std::fs::read_dir(region_folder)
+        .context(format!(
+            "Failed to read region folder {}",
+            region_folder.display()
+        ))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "mca"))
+        .collect::<Vec<_>>();
+    region_files.sort();
+    for (i, path) in region_files.iter().enumerate() {
+        info!(
+            "Importing region {} ({}/{})",
+            path.display(),
+            i + 1,
+            region_files.len()
+        );
+        let buf =
+            std::fs::read(path).context(format!("Failed to read region {}", path.display()))?;
+        let mut chunks = HashMap::new();
+        for chunk_nbt in read_region(&buf) {
+            let result = chunk_nbt.and_then(|nbt| import_chunk(&nbt, &mut mapping, &mut chunks));
+            if let Err(e) = result {
+                warn!("Skipping a chunk of region {} ({:?})", path.display(), e);
+            }
+        }
+        // Every chunk is saved in a single file, so the chunks shared by several regions are merged with the saved ones
+        for (pos, chunk) in chunks {
+            let chunk = merge_with_saved_chunk(world, chunk, items);
+            save::save_chunk(world, &chunk, &ChunkBlockEntities::default(), None, items)?;
+            info!("Saved chunk {:?}", pos);
+        }
+    }
+    for (name, count) in mapping.unmapped.iter() {
+        warn!(
+            "{} blocks of type {} were not in the block mapping",
+            count, name
+        );
+    }
+    Ok(())
+}
+
+/// Add the blocks of the chunk that was saved by a previous region to the air blocks of `chunk`
+fn merge_with_saved_chunk(
+    world: &WorldMetadata,
+    mut chunk: Chunk,
+    items: &Registry<Item>,
+) -> Chunk {
+    if let Ok((saved_chunk, _, _)) = save::load_chunk(world, chunk.pos, items) {
+        let size = voxel_rs_common::world::CHUNK_SIZE;
+        for x in 0..size {
+            for y in 0..size {
+                for z in 0..size {
+                    let saved_block = saved_chunk.get_block_at((x, y, z));
+                    if saved_block != 0 && chunk.get_block_at((x, y, z)) == 0 {
+                        chunk.set_block_at((x, y, z), saved_block);
+                    }
+                }
+            }
+        }
+    }
+    chunk
+}
+
+/// Read the NBT data of the chunks stored in a region file
+fn read_region(buf: &[u8]) -> Vec<Result<Nbt>> {
+    let mut chunks = Vec::new();
+    if buf.len() < 2 * SECTOR_SIZE {
+        chunks.push(Err(anyhow::anyhow!("Truncated region header")));
+        return chunks;
+    }
+    for i in 0..REGION_CHUNKS {
+        let location = &buf[4 * i..4 * i + 4];
+        let offset =
+            (u32::from_be_bytes([0, location[0], location[1], location[2]]) as usize) * SECTOR_SIZE;
+        if offset == 0 {
+            continue;
+        }
+        chunks.push(read_region_chunk(buf, offset));
+    }
+    chunks
+}
+
+fn read_region_chunk(buf: &[u8], offset: usize) -> Result<Nbt> {
+    let header = buf
+        .get(offset..offset + 5)
+        .context("Truncated chunk header")?;
+    let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let data = buf
+        .get(offset + 5..offset + 4 + length)
+        .context("Truncated chunk data")?;
+    let data = match header[4] {
+        2 => miniz_oxide::inflate::decompress_to_vec_zlib(data)
+            .map_err(|e| anyhow::anyhow!("Failed to decompress chunk ({:?})", e))?,
+        3 => data.to_vec(),
+        compression => anyhow::bail!("Unsupported chunk compression {}", compression),
+    };
+    Nbt::parse(&data)
+}
+
+/// Convert the sections of a Minecraft chunk, adding them to `chunks`
+fn import_chunk(
+    nbt: &Nbt,
+    mapping: &mut ResolvedMapping,
+    chunks: &mut HashMap<ChunkPos, Chunk>,
+) -> Result<()> {
+    let data_version = nbt.get("DataVersion").and_then(Nbt::as_int).unwrap_or(0);
+    // The chunks are stored in a `Level` compound before 1.18
+    let (level, sections) = match nbt.get("Level") {
+        Some(level) => (level, level.get("Sections")),
+        None => (nbt, nbt.get("sections")),
+    };
+    let chunk_x = level
+        .get("xPos")
+        .and_then(Nbt::as_int)
+        .context("Missing xPos")? as i64;
+    let chunk_z = level
+        .get("zPos")
+        .and_then(Nbt::as_int)
+        .context("Missing zPos")? as i64;
+    let sections = match sections {
+        Some(Nbt::List(sections)) => sections,
+        _ => return Ok(()),
+    };
+    for section in sections.iter() {
+        let section_y = section
+            .get("Y")
+            .and_then(Nbt::as_int)
+            .context("Missing section Y")? as i64;
+        // The block states are in a `block_states` compound since 1.18
+        let (palette, states) = match section.get("block_states") {
+            Some(block_states) => (block_states.get("palette"), block_states.get("data")),
+            None => (section.get("Palette"), section.get("BlockStates")),
+        };
+        let palette = match palette {
+            Some(Nbt::List(palette)) => palette
+                .iter()
+                .map(|entry| mapping.get(entry.get("Name").and_then(Nbt::as_str).unwrap_or("air")))
+                .collect::<Vec<_>>(),
+            _ => continue,
+        };
+        let indices = match states {
+            Some(Nbt::LongArray(states)) => unpack_block_states(
+                states,
+                palette.len(),
+                data_version >= NON_SPANNING_DATA_VERSION,
+            ),
+            // A section with a single block doesn't store the block states
+            _ => vec![0; SECTION_SIZE * SECTION_SIZE * SECTION_SIZE],
+        };
+        for (i, &index) in indices.iter().enumerate() {
+            let block = *palette.get(index).unwrap_or(&0);
+            if block == 0 {
+                continue;
+            }
+            let (x, y, z) = (
+                i % SECTION_SIZE,
+                i / (SECTION_SIZE * SECTION_SIZE),
+                (i / SECTION_SIZE) % SECTION_SIZE,
+            );
+            let pos = BlockPos::from((
+                chunk_x * SECTION_SIZE as i64 + x as i64,
+                section_y * SECTION_SIZE as i64 + y as i64,
+                chunk_z * SECTION_SIZE as i64 + z as i64,
+            ));
+            let chunk_pos = pos.containing_chunk_pos();
+            chunks
+                .entry(chunk_pos)
+                .or_insert_with(|| Chunk::new(chunk_pos))
+                .set_block_at(pos.pos_in_containing_chunk(), block);
+        }
+    }
+    Ok(())
+}
+
+/// Unpack the palette indices of the 4096 blocks of a section.
+/// Before 20w17a, an index can be split between two longs.
+fn unpack_block_states(states: &[i64], palette_len: usize, non_spanning: bool) -> Vec<usize> {
+    let bits = (usize::BITS - (palette_len.max(1) - 1).leading_zeros()).max(4) as usize;
+    let mask = (1u64 << bits) - 1;
+    let num_blocks = SECTION_SIZE * SECTION_SIZE * SECTION_SIZE;
+    let long = |i: usize| states.get(i).map_or(0, |&l| l as u64);
+    (0..num_blocks)
+        .map(|i| {
+            if non_spanning {
+                let per_long = 64 / bits;
+                ((long(i / per_long) >> ((i % per_long) * bits)) & mask) as usize
+            } else {
+                let bit = i * bits;
+                let (index, offset) = (bit / 64, bit % 64);
+                let mut value = long(index) >> offset;
+                if offset + bits > 64 {
+                    value |= long(index + 1) << (64 - offset);
+                }
+                (value & mask) as usize
+            }
+        })
+        .collect()
+}
+
+/// A NBT tag (https://minecraft.gamepedia.com/NBT_format)
+#[derive(Debug, Clone, PartialEq)]
+enum Nbt {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<i8>),
+    String(String),
+    List(Vec<Nbt>),
+    Compound(HashMap<String, Nbt>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+impl Nbt {
+    /// Parse a NBT file, made of a single named compound
+    fn parse(buf: &[u8]) -> Result<Self> {
+        let mut reader = NbtReader { buf, pos: 0 };
+        if reader.read_u8()? != 10 {
+            anyhow::bail!("The root tag is not a compound");
+        }
+        reader.read_string()?;
+        reader.read_payload(10)
+    }
+
+    fn get(&self, key: &str) -> Option<&Nbt> {
+        match self {
+            Nbt::Compound(tags) => tags.get(key),
+            _ => None,
+        }
+    }
+
+    fn as_int(&self) -> Option<i32> {
+        match *self {
+            Nbt::Byte(value) => Some(value as i32),
+            Nbt::Short(value) => Some(value as i32),
+            Nbt::Int(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Nbt::String(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+struct NbtReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> NbtReader<'a> {
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .buf
+            .get(self.pos..self.pos + len)
+            .context("Unexpected end of NBT data")?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_i16(&mut self) -> Result<i16> {
+        let b = self.read_bytes(2)?;
+        Ok(i16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn read_i32(&mut self) -> Result<i32> {
+        let b = self.read_bytes(4)?;
+        Ok(i32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn read_i64(&mut self) -> Result<i64> {
+        let b = self.read_bytes(8)?;
+        Ok(i64::from_be_bytes([
+            b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7],
+        ]))
+    }
+
+    fn read_len(&mut self) -> Result<usize> {
+        // Negative lengths are used for the empty lists
+        Ok(self.read_i32()?.max(0) as usize)
+    }
+
+    fn read_string(&mut self) -> Result<String> {
+        let len = self.read_i16()? as u16 as usize;
+        Ok(String::from_utf8_lossy(self.read_bytes(len)?).into_owned())
+    }
+
+    fn read_payload(&mut self, tag: u8) -> Result<Nbt> {
+        Ok(match tag {
+            1 => Nbt::Byte(self.read_u8()? as i8),
+            2 => Nbt::Short(self.read_i16()?),
+            3 => Nbt::Int(self.read_i32()?),
+            4 => Nbt::Long(self.read_i64()?),
+            5 => Nbt::Float(f32::from_bits(self.read_i32()? as u32)),
+            6 => Nbt::Double(f64::from_bits(self.read_i64()? as u64)),
+            7 => {
+                let len = self.read_len()?;
+                Nbt::ByteArray(self.read_bytes(len)?.iter().map(|&b| b as i8).collect())
+            }
+            8 => Nbt::String(self.read_string()?),
+            9 => {
+                let element_tag = self.read_u8()?;
+                let len = self.read_len()?;
+                let mut elements = Vec::new();
+                for _ in 0..len {
+                    elements.push(self.read_payload(element_tag)?);
+                }
+                Nbt::List(elements)
+            }
+            10 => {
+                let mut tags = HashMap::new();
+                loop {
+                    let tag = self.read_u8()?;
+                    if tag == 0 {
+                        break;
+                    }
+                    let name = self.read_string()?;
+                    tags.insert(name, self.read_payload(tag)?);
+                }
+                Nbt::Compound(tags)
+            }
+            11 => {
+                let len = self.read_len()?;
+                Nbt::IntArray((0..len).map(|_| self.read_i32()).collect::<Result<_>>()?)
+            }
+            12 => {
+                let len = self.read_len()?;
+                Nbt::LongArray((0..len).map(|_| self.read_i64()).collect::<Result<_>>()?)
+            }
+            _ => anyhow::bail!("Unknown NBT tag {}", tag),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unpack_block_states() {
+        // 5 bits per block: the 13th index is split between the first two longs before 20w17a
+        let mut states = vec![0i64; 4096 * 5 / 64];
+        let value = 0b10110u64;
+        states[0] |= (value << 60) as i64;
+        states[1] |= (value >> 4) as i64;
+        let indices = unpack_block_states(&states, 17, false);
+        assert_eq!(indices[12], 0b10110);
+        assert_eq!(indices[11], 0);
+        // Since 20w17a, 12 indices fit in every long and the 13th starts in the second long
+        let mut states = vec![0i64; 4096 / 12 + 1];
+        states[1] = 0b10110;
+        let indices = unpack_block_states(&states, 17, true);
+        assert_eq!(indices[12], 0b10110);
+    }
+
+    #[test]
+    fn test_parse_nbt() {
+        // {"": {"xPos": 3, "Name": "minecraft:stone", "Sections": [{"Y": 1b}]}}
+        let mut buf = vec![10, 0, 0];
+        buf.extend_from_slice(&[3, 0, 4]);
+        buf.extend_from_slice(b"xPos");
+        buf.extend_from_slice(&3i32.to_be_bytes());
+        buf.extend_from_slice(&[8, 0, 4]);
+        buf.extend_from_slice(b"Name");
+        buf.extend_from_slice(&[0, 15]);
+        buf.extend_from_slice(b"minecraft:stone");
+        buf.extend_from_slice(&[9, 0, 8]);
+        buf.extend_from_slice(b"Sections");
+        buf.extend_from_slice(&[10, 0, 0, 0, 1]);
+        buf.extend_from_slice(&[1, 0, 1, b'Y', 1, 0]);
+        buf.push(0);
+        let nbt = Nbt::parse(&buf).unwrap();
+        assert_eq!(nbt.get("xPos").and_then(Nbt::as_int), Some(3));
+        assert_eq!(
+            nbt.get("Name").and_then(Nbt::as_str),
+            Some("minecraft:stone")
+        );
+        match nbt.get("Sections") {
+            Some(Nbt::List(sections)) => {
+                assert_eq!(sections[0].get("Y").and_then(Nbt::as_int), Some(1))
+            }
+            _ => panic!("Sections is not a list"),
+        }
+        assert!(Nbt::parse(&buf[..buf.len() - 3]).is_err());
+    }
+}
@@ -0,0 +1,91 @@
+//! Authentication of the players: every client logs in with a username and an optional token before playing.
+//!
+//! The username identifies a player across sessions: the saves and the commands use it, so two connected players
+//! can't have the same username. The accounts of the server config reserve some usernames for the players
+//! that know their token.
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use voxel_rs_common::config::MAX_DISPLAY_NAME_LENGTH;
+
+/// The accounts of a server
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// The token of every reserved username
+    pub accounts: HashMap<String, String>,
+    /// Only the usernames of `accounts` can join the server
+    pub require_account: bool,
+}
+
+/// The key that identifies a username whatever its case, used to compare the usernames, to store the roles
+/// and to name the player saves
+pub fn username_key(username: &str) -> String {
+    username.to_lowercase()
+}
+
+impl AuthConfig {
+    /// Check the username and the token sent by a player. `connected` contains the usernames of the logged in players.
+    /// Return the username without the surrounding whitespace, spelled like its account if it has one,
+    /// or why the player can't log in. The usernames are compared without case, because they are also used as file names.
+    pub fn authenticate<'a>(
+        &self,
+        username: &str,
+        token: Option<&str>,
+        mut connected: impl Iterator<Item = &'a str>,
+    ) -> Result<String, String> {
+        let username = username.trim();
+        if username.is_empty() || username.chars().count() > MAX_DISPLAY_NAME_LENGTH {
+            return Err(format!("The username must have between 1 and {} characters", MAX_DISPLAY_NAME_LENGTH));
+        }
+        if !username.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') {
+            return Err("The username can only contain letters, digits, - and _".to_owned());
+        }
+        let key = username_key(username);
+        let account = self.accounts.iter().find(|(name, _)| username_key(name) == key);
+        let username = match account {
+            Some((_, expected_token)) if token != Some(expected_token.as_str()) => {
+                return Err(format!("Wrong token for {}", username));
+            }
+            Some((name, _)) => name.as_str(),
+            None if self.require_account => {
+                return Err(format!("{} doesn't have an account on this server", username));
+            }
+            None => username,
+        };
+        if connected.any(|name| username_key(name) == key) {
+            return Err(format!("{} is already connected", username));
+        }
+        Ok(username.to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_authenticate() {
+        let mut config = AuthConfig::default();
+        config.accounts.insert("Admin".to_owned(), "secret".to_owned());
+        let none = std::iter::empty::<&str>();
+
+        assert_eq!(config.authenticate(" Steve ", None, none.clone()), Ok("Steve".to_owned()));
+        assert!(config.authenticate("", None, none.clone()).is_err());
+        assert!(config.authenticate("../Steve", None, none.clone()).is_err());
+        // The reserved usernames need their token, whatever their case
+        assert_eq!(config.authenticate("admin", Some("secret"), none.clone()), Ok("Admin".to_owned()));
+        assert!(config.authenticate("admin", None, none.clone()).is_err());
+        assert!(config.authenticate("ADMIN", Some("wrong"), none.clone()).is_err());
+        // Also when the case differs outside of ASCII
+        config.accounts.insert("Ödön".to_owned(), "token".to_owned());
+        assert!(config.authenticate("öDÖN", None, none.clone()).is_err());
+        assert_eq!(config.authenticate("öDÖN", Some("token"), none.clone()), Ok("Ödön".to_owned()));
+        // Two players can't use the same username
+        assert!(config.authenticate("steve", None, ["Steve"].iter().copied()).is_err());
+        assert!(config.authenticate("élodie", None, ["ÉLODIE"].iter().copied()).is_err());
+
+        config.require_account = true;
+        assert!(config.authenticate("Steve", None, none.clone()).is_err());
+        assert!(config.authenticate("Admin", Some("secret"), none).is_ok());
+    }
+}
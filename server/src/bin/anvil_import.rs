@@ -0,0 +1,23 @@
+//! Import a Minecraft world into a new saved world.
+//!
+//! Usage: `anvil_import <region folder> <world name> [block mapping.ron]`
+use std::path::Path;
+use voxel_rs_server::anvil::{import_world, BlockMapping};
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    let args = std::env::args().collect::<Vec<_>>();
+    if args.len() < 3 || args.len() > 4 {
+        anyhow::bail!(
+            "Usage: {} <region folder> <world name> [block mapping.ron]",
+            args[0]
+        );
+    }
+    let mapping = match args.get(3) {
+        Some(path) => BlockMapping::load(Path::new(path))?,
+        None => BlockMapping::default(),
+    };
+    import_world(Path::new(&args[1]), args[2].clone(), &mapping)?;
+    Ok(())
+}
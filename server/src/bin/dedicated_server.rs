@@ -0,0 +1,27 @@
+//! Run a world as a dedicated server, that the players join from the Connect button of the main menu.
+//! The world is created with a random seed if it doesn't exist yet.
+//!
+//! Usage: `dedicated_server <world name> [address]`, the server listens on all the interfaces by default
+use std::net::{Ipv4Addr, SocketAddr};
+use voxel_rs_common::network::remote::{resolve_address, RemoteServer, DEFAULT_PORT};
+use voxel_rs_server::{launch_server, save};
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    let args = std::env::args().collect::<Vec<_>>();
+    if args.len() != 2 && args.len() != 3 {
+        anyhow::bail!("Usage: {} <world name> [address]", args[0]);
+    }
+    let world = match save::list_worlds()?.into_iter().find(|world| world.name == args[1]) {
+        Some(world) => world,
+        None => save::create_world(args[1].clone(), rand::random())?,
+    };
+    let address = match args.get(2) {
+        Some(address) => resolve_address(address)?,
+        None => SocketAddr::from((Ipv4Addr::UNSPECIFIED, DEFAULT_PORT)),
+    };
+    let server = RemoteServer::bind(address)?;
+    println!("World {} is listening on {}", world.name, address);
+    launch_server(Box::new(server), world)
+}
@@ -0,0 +1,23 @@
+//! Generate and light the chunks around the spawn of a world, so that the players don't wait for them.
+//!
+//! Usage: `pregen <world name> <radius in chunks>`
+use voxel_rs_server::{plugins::ServerPlugins, pregen::pregenerate_world, save};
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    let args = std::env::args().collect::<Vec<_>>();
+    if args.len() != 3 {
+        anyhow::bail!("Usage: {} <world name> <radius in chunks>", args[0]);
+    }
+    let mut world = save::list_worlds()?
+        .into_iter()
+        .find(|world| world.name == args[1])
+        .ok_or_else(|| anyhow::anyhow!("Unknown world: {}", args[1]))?;
+    let radius = args[2]
+        .parse::<i64>()
+        .map_err(|_| anyhow::anyhow!("The radius must be an integer"))?;
+    let chunks = pregenerate_world(&mut world, radius, &ServerPlugins::default())?;
+    println!("Pregenerated {} chunks of world {}", chunks, world.name);
+    Ok(())
+}
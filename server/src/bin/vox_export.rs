@@ -0,0 +1,28 @@
+//! Export a region of a saved world to .vox files.
+//!
+//! Usage: `vox_export <world name> <x1> <y1> <z1> <x2> <y2> <z2> <output>`
+use std::path::Path;
+use voxel_rs_common::world::BlockPos;
+use voxel_rs_server::{save, vox_export::export_region};
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    let args = std::env::args().collect::<Vec<_>>();
+    if args.len() != 9 {
+        anyhow::bail!("Usage: {} <world name> <x1> <y1> <z1> <x2> <y2> <z2> <output>", args[0]);
+    }
+    let world = save::list_worlds()?
+        .into_iter()
+        .find(|world| world.name == args[1])
+        .ok_or_else(|| anyhow::anyhow!("Unknown world: {}", args[1]))?;
+    let coords = args[2..8]
+        .iter()
+        .map(|arg| arg.parse::<i64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| anyhow::anyhow!("The coordinates must be integers"))?;
+    let min = BlockPos::from((coords[0], coords[1], coords[2]));
+    let max = BlockPos::from((coords[3], coords[4], coords[5]));
+    export_region(&world, min, max, Path::new(&args[8]))?;
+    Ok(())
+}
@@ -0,0 +1,327 @@
+//! Block updates: the blocks react to the changes of their neighbors, to delayed ticks and to random ticks
+use crate::scheduler::{Scheduler, TaskId};
+use crate::world::World;
+use log::warn;
+use rand::Rng;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use voxel_rs_common::{
+    block::{BlockId, BlockType},
+    data::Data,
+    world::{BlockPos, ChunkPos, Direction, CHUNK_SIZE},
+    worldgen::structure::Structure,
+};
+
+/// Delay before a falling block moves down by one block
+const FALL_DELAY: Duration = Duration::from_millis(50);
+
+/// How a block type reacts to the changes around it
+pub(crate) trait BlockBehavior {
+    /// Called when the block or one of its 6 neighbors changed. `changed` is `pos` if the block itself changed.
+    fn neighbor_changed(&self, _ctx: &mut BlockUpdateContext, _pos: BlockPos, _changed: BlockPos) {}
+
+    /// Called when a tick scheduled with `BlockUpdateContext::schedule_tick` is due
+    fn scheduled_tick(&self, _ctx: &mut BlockUpdateContext, _pos: BlockPos) {}
+
+    /// Whether the block receives random ticks. The chunks without such blocks are skipped.
+    fn ticks_randomly(&self) -> bool {
+        false
+    }
+
+    /// Called when the block is chosen by a random tick, see `BlockUpdates::random_ticks`
+    fn random_tick(&self, _ctx: &mut BlockUpdateContext, _pos: BlockPos) {}
+}
+
+/// Access to the world for the block behaviors.
+/// The blocks modified with `World::set_block` send updates to their neighbors too.
+pub(crate) struct BlockUpdateContext<'a> {
+    pub world: &'a mut World,
+    scheduled_ticks: &'a mut ScheduledTicks,
+    now: Instant,
+}
+
+impl BlockUpdateContext<'_> {
+    /// Tick the block at `pos` after `delay`, unless a tick is already scheduled for it
+    pub fn schedule_tick(&mut self, pos: BlockPos, delay: Duration) {
+        self.scheduled_ticks.schedule(self.now, pos, delay);
+    }
+}
+
+/// The behavior of every block type, indexed by block id
+pub(crate) struct BlockBehaviors {
+    behaviors: Vec<Option<Box<dyn BlockBehavior>>>,
+}
+
+impl BlockBehaviors {
+    /// Register the behaviors that the block types ask for
+    pub fn new(data: &Data) -> Self {
+        let blocks = &data.blocks;
+        let behaviors = (0..blocks.get_number_of_ids())
+            .map(|id| {
+                let block = blocks.get_value_by_id(id)?;
+                match &block.block_type {
+                    BlockType::NormalCube { falls: true, .. } => Some(Box::new(FallingBlock) as Box<dyn BlockBehavior>),
+                    BlockType::NormalCube { spreads_on: Some(target), .. } => {
+                        match blocks.get_id_by_name(target) {
+                            Some(target) => Some(Box::new(SpreadingBlock { target: target as BlockId }) as Box<dyn BlockBehavior>),
+                            None => {
+                                warn!("Block {} spreads on unknown block {}", block.name, target);
+                                None
+                            }
+                        }
+                    }
+                    BlockType::NormalCube { grows_into: Some(structure), .. } => {
+                        match data.structures.get_id_by_name(structure) {
+                            Some(id) => {
+                                let structure = data.structures.get_value_by_id(id).unwrap().clone();
+                                Some(Box::new(GrowingBlock { structure }) as Box<dyn BlockBehavior>)
+                            }
+                            None => {
+                                warn!("Block {} grows into unknown structure {}", block.name, structure);
+                                None
+                            }
+                        }
+                    }
+                    _ => None,
+                }
+            })
+            .collect();
+        Self { behaviors }
+    }
+
+    fn get(&self, block: BlockId) -> Option<&dyn BlockBehavior> {
+        self.behaviors.get(block as usize).and_then(|behavior| behavior.as_deref())
+    }
+
+    fn ticks_randomly(&self, block: BlockId) -> bool {
+        match self.get(block) {
+            Some(behavior) => behavior.ticks_randomly(),
+            None => false,
+        }
+    }
+}
+
+/// The scheduled block ticks, with at most one tick per position
+#[derive(Default)]
+struct ScheduledTicks {
+    scheduler: Scheduler<BlockPos>,
+    positions: HashMap<BlockPos, TaskId>,
+}
+
+impl ScheduledTicks {
+    fn schedule(&mut self, now: Instant, pos: BlockPos, delay: Duration) {
+        if !self.positions.contains_key(&pos) {
+            let id = self.scheduler.schedule_once(now, delay, pos);
+            self.positions.insert(pos, id);
+        }
+    }
+
+    fn poll(&mut self, now: Instant) -> Vec<BlockPos> {
+        let due_ticks = self.scheduler.poll(now);
+        for pos in due_ticks.iter() {
+            self.positions.remove(pos);
+        }
+        due_ticks
+    }
+}
+
+/// The block updates waiting to be processed
+#[derive(Default)]
+pub(crate) struct BlockUpdates {
+    /// The blocks to notify, with the position of the block that changed
+    pending: VecDeque<(BlockPos, BlockPos)>,
+    scheduled_ticks: ScheduledTicks,
+}
+
+impl BlockUpdates {
+    /// Notify the changed blocks and their neighbors, and run the ticks that are due.
+    /// At most `max_updates` neighbor updates are processed, the others wait for the next call.
+    /// Return the number of updates that were processed.
+    pub fn process(&mut self, world: &mut World, behaviors: &BlockBehaviors, now: Instant, max_updates: usize) -> usize {
+        let mut processed = 0;
+        let mut ctx = BlockUpdateContext {
+            world,
+            scheduled_ticks: &mut self.scheduled_ticks,
+            now,
+        };
+        for pos in ctx.scheduled_ticks.poll(now) {
+            if let Some(behavior) = behaviors.get(ctx.world.get_block(pos)) {
+                behavior.scheduled_tick(&mut ctx, pos);
+            }
+            processed += 1;
+        }
+        loop {
+            for changed in ctx.world.take_changed_blocks() {
+                self.pending.push_back((changed, changed));
+                for &direction in Direction::ALL.iter() {
+                    self.pending.push_back((changed.neighbor(direction), changed));
+                }
+            }
+            if processed >= max_updates {
+                break;
+            }
+            let (pos, changed) = match self.pending.pop_front() {
+                Some(update) => update,
+                None => break,
+            };
+            if let Some(behavior) = behaviors.get(ctx.world.get_block(pos)) {
+                behavior.neighbor_changed(&mut ctx, pos, changed);
+            }
+            processed += 1;
+        }
+        processed
+    }
+
+    /// Tick `count` random blocks of a chunk, with a uniform distribution.
+    /// Return the number of random ticks, 0 if the chunk doesn't contain blocks that receive random ticks.
+    pub fn random_ticks(
+        &mut self,
+        world: &mut World,
+        behaviors: &BlockBehaviors,
+        now: Instant,
+        chunk_pos: ChunkPos,
+        count: u64,
+    ) -> u64 {
+        let chunk = match world.get_chunk(chunk_pos) {
+            Some(chunk) => chunk,
+            None => return 0,
+        };
+        if !chunk.palette().iter().any(|&block| behaviors.ticks_randomly(block)) {
+            return 0;
+        }
+        let mut ctx = BlockUpdateContext {
+            world,
+            scheduled_ticks: &mut self.scheduled_ticks,
+            now,
+        };
+        let mut rng = rand::thread_rng();
+        let size = CHUNK_SIZE as i64;
+        for _ in 0..count {
+            let pos = BlockPos::from((
+                chunk_pos.px * size + rng.gen_range(0..size),
+                chunk_pos.py * size + rng.gen_range(0..size),
+                chunk_pos.pz * size + rng.gen_range(0..size),
+            ));
+            if let Some(behavior) = behaviors.get(ctx.world.get_block(pos)) {
+                behavior.random_tick(&mut ctx, pos);
+            }
+        }
+        count
+    }
+
+    /// Number of updates that are waiting for a later tick
+    pub fn num_waiting(&self) -> usize {
+        self.pending.len() + self.scheduled_ticks.positions.len()
+    }
+}
+
+/// A block that falls when there is air below it
+struct FallingBlock;
+
+impl BlockBehavior for FallingBlock {
+    fn neighbor_changed(&self, ctx: &mut BlockUpdateContext, pos: BlockPos, _changed: BlockPos) {
+        if ctx.world.get_block(pos.neighbor(Direction::NegY)) == 0 {
+            ctx.schedule_tick(pos, FALL_DELAY);
+        }
+    }
+
+    fn scheduled_tick(&self, ctx: &mut BlockUpdateContext, pos: BlockPos) {
+        let below = pos.neighbor(Direction::NegY);
+        if ctx.world.get_block(below) != 0 {
+            return;
+        }
+        // The block doesn't fall into a chunk that is not loaded
+        let block = ctx.world.get_block(pos);
+        if ctx.world.set_block(below, block) {
+            ctx.world.set_block(pos, 0);
+        }
+    }
+}
+
+/// A block that spreads on the nearby blocks of the `target` type that have air above them, like grass on dirt.
+/// It turns back into `target` when it is covered.
+struct SpreadingBlock {
+    target: BlockId,
+}
+
+impl BlockBehavior for SpreadingBlock {
+    fn ticks_randomly(&self) -> bool {
+        true
+    }
+
+    fn random_tick(&self, ctx: &mut BlockUpdateContext, pos: BlockPos) {
+        if ctx.world.get_block(pos.neighbor(Direction::PosY)) != 0 {
+            ctx.world.set_block(pos, self.target);
+            return;
+        }
+        let mut rng = rand::thread_rng();
+        let spread_pos = BlockPos::from((
+            pos.px + rng.gen_range(-1..=1),
+            pos.py + rng.gen_range(-3..=1),
+            pos.pz + rng.gen_range(-1..=1),
+        ));
+        if ctx.world.get_block(spread_pos) == self.target && ctx.world.get_block(spread_pos.neighbor(Direction::PosY)) == 0 {
+            let block = ctx.world.get_block(pos);
+            ctx.world.set_block(spread_pos, block);
+        }
+    }
+}
+
+/// A block that is replaced by a structure during a random tick, like a sapling growing into a tree.
+/// The structure only replaces air and the growing block.
+struct GrowingBlock {
+    structure: Structure,
+}
+
+impl BlockBehavior for GrowingBlock {
+    fn ticks_randomly(&self) -> bool {
+        true
+    }
+
+    fn random_tick(&self, ctx: &mut BlockUpdateContext, pos: BlockPos) {
+        let block = ctx.world.get_block(pos);
+        let model = &self.structure.model;
+        // The model is centered horizontally on the growing block, and its lowest layer replaces it
+        for i in 0..model.size_x {
+            for j in 0..model.size_y {
+                for k in 0..model.size_z {
+                    let index = i * model.size_y * model.size_z + j * model.size_z + k;
+                    if !model.full[index] {
+                        continue;
+                    }
+                    if let Some(&structure_block) = self.structure.blocks.get(&model.voxels[index]) {
+                        let block_pos = BlockPos::from((
+                            pos.px + i as i64 - model.size_x as i64 / 2,
+                            pos.py + j as i64,
+                            pos.pz + k as i64 - model.size_z as i64 / 2,
+                        ));
+                        let current_block = ctx.world.get_block(block_pos);
+                        if current_block == 0 || current_block == block {
+                            ctx.world.set_block(block_pos, structure_block);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_one_scheduled_tick_per_block() {
+        let now = Instant::now();
+        let pos = BlockPos::from((1, 2, 3));
+        let mut ticks = ScheduledTicks::default();
+        ticks.schedule(now, pos, Duration::from_secs(1));
+        ticks.schedule(now, pos, Duration::from_secs(2));
+        assert!(ticks.poll(now).is_empty());
+        assert_eq!(ticks.poll(now + Duration::from_secs(3)), vec![pos]);
+
+        // The block can be ticked again after its tick ran
+        ticks.schedule(now, pos, Duration::from_secs(2));
+        assert_eq!(ticks.poll(now + Duration::from_secs(3)), vec![pos]);
+    }
+}
@@ -0,0 +1,319 @@
+//! Commands sent by the players or by the server console, for example `/give Player stone 64`.
+use crate::auth::username_key;
+use crate::permissions::{PermissionsConfig, OPERATOR_ROLE};
+use crate::save::{self, WorldMetadata};
+use crate::scheduler::Scheduler;
+use crate::{teleport_player, PlayerData, ServerTask};
+use nalgebra::Vector3;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use voxel_rs_common::{
+    data::Data,
+    inventory::{InventoryItem, MAX_STACK_SIZE, PLAYER_INVENTORY_SIZE},
+    network::{messages::ToClient, MessageDelivery, Server},
+    physics::simulation::ServerPhysicsSimulation,
+    player::PlayerId,
+};
+
+/// Maximum number of items given by a single `/give`
+const MAX_GIVE_COUNT: u32 = MAX_STACK_SIZE * PLAYER_INVENTORY_SIZE as u32;
+/// Maximum delay of a `/schedule`, in seconds
+const MAX_SCHEDULE_DELAY: u64 = 24 * 60 * 60;
+
+/// Who sent a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandSender {
+    Player(PlayerId),
+    /// The server console or a remote admin connection, that can use every command
+    Console,
+}
+
+/// What the commands can read and modify
+pub struct CommandContext<'a> {
+    pub players: &'a mut HashMap<PlayerId, PlayerData>,
+    pub game_data: &'a Data,
+    pub server: &'a mut dyn Server,
+    pub physics_simulation: &'a mut ServerPhysicsSimulation,
+    pub world_spawn: Vector3<f64>,
+    pub world_metadata: &'a WorldMetadata,
+    pub permissions: &'a mut PermissionsConfig,
+    /// The tasks run later by the server loop
+    pub scheduler: &'a mut Scheduler<ServerTask>,
+}
+
+/// A parsed command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Add some items or blocks to the inventory of a player
+    Give {
+        player: String,
+        item: String,
+        count: u32,
+    },
+    /// Remove everything from the inventory of a player
+    Clear { player: String },
+    /// List the inventory of a player
+    InventorySee { player: String },
+    /// Teleport the sender to the world spawn
+    Spawn,
+    /// List the usernames of the logged in players
+    List,
+    /// Give the operator role to a player, even if it's not connected
+    Op { player: String },
+    /// Give the default role back to a player
+    Deop { player: String },
+    /// Execute a command line later, with the permissions of the sender
+    Schedule { delay: u64, line: String },
+}
+
+impl Command {
+    /// Parse a command line, starting with a `/`
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let line = line.trim();
+        let mut args = line
+            .strip_prefix('/')
+            .ok_or_else(|| "Commands must start with /".to_owned())?
+            .split_whitespace();
+        let name = args.next().unwrap_or("");
+        let mut next_arg = |usage: &str| args.next().map(str::to_owned).ok_or_else(|| format!("Usage: {}", usage));
+        let command = match name {
+            "give" => {
+                const USAGE: &str = "/give <player> <item> [count]";
+                let player = next_arg(USAGE)?;
+                let item = next_arg(USAGE)?;
+                let count = match next_arg(USAGE) {
+                    Ok(count) => count
+                        .parse::<u32>()
+                        .ok()
+                        .filter(|&count| count > 0 && count <= MAX_GIVE_COUNT)
+                        .ok_or_else(|| format!("The count must be between 1 and {}", MAX_GIVE_COUNT))?,
+                    Err(_) => 1,
+                };
+                Self::Give { player, item, count }
+            }
+            "clear" => Self::Clear {
+                player: next_arg("/clear <player>")?,
+            },
+            "invsee" => Self::InventorySee {
+                player: next_arg("/invsee <player>")?,
+            },
+            "spawn" => Self::Spawn,
+            "list" => Self::List,
+            "op" => Self::Op {
+                player: next_arg("/op <player>")?,
+            },
+            "deop" => Self::Deop {
+                player: next_arg("/deop <player>")?,
+            },
+            "schedule" => {
+                const USAGE: &str = "/schedule <seconds> <command>";
+                let delay = next_arg(USAGE)?
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|&delay| delay <= MAX_SCHEDULE_DELAY)
+                    .ok_or_else(|| format!("The delay must be between 0 and {} seconds", MAX_SCHEDULE_DELAY))?;
+                let line = format!("/{}", args.by_ref().collect::<Vec<_>>().join(" "));
+                // Report the errors of the command now rather than when it runs
+                Self::parse(&line)?;
+                Self::Schedule { delay, line }
+            }
+            _ => return Err(format!("Unknown command: /{}", name)),
+        };
+        if args.next().is_some() {
+            return Err(format!("Too many arguments for /{}", name));
+        }
+        Ok(command)
+    }
+
+    /// The permission needed to use this command: its name
+    pub fn permission(&self) -> &'static str {
+        match self {
+            Self::Give { .. } => "give",
+            Self::Clear { .. } => "clear",
+            Self::InventorySee { .. } => "invsee",
+            Self::Spawn => "spawn",
+            Self::List => "list",
+            Self::Op { .. } => "op",
+            Self::Deop { .. } => "deop",
+            Self::Schedule { .. } => "schedule",
+        }
+    }
+
+    /// Execute the command sent by `sender`. Return the message displayed to the sender.
+    pub fn execute(self, sender: CommandSender, ctx: CommandContext) -> Result<String, String> {
+        let CommandContext {
+            players,
+            game_data,
+            server,
+            physics_simulation,
+            world_spawn,
+            world_metadata,
+            permissions,
+            scheduler,
+        } = ctx;
+        let allowed = match sender {
+            CommandSender::Player(id) => match players.get(&id) {
+                Some(data) => permissions.allows(&data.display_name, self.permission()),
+                None => false,
+            },
+            CommandSender::Console => true,
+        };
+        if !allowed {
+            return Err("You don't have the permission to use this command".to_owned());
+        }
+        match self {
+            Self::Give { player, item, count } => {
+                let inventory_item = find_item(game_data, &item)?;
+                let player_data = find_player(players, &player)?;
+                let not_given = player_data.inventory.add(inventory_item, count);
+                match not_given {
+                    0 => Ok(format!("Gave {} {} to {}", count, item, player)),
+                    _ => Ok(format!(
+                        "Gave {} {} to {}, {} didn't fit in the inventory",
+                        count - not_given,
+                        item,
+                        player,
+                        not_given
+                    )),
+                }
+            }
+            Self::Clear { player } => {
+                find_player(players, &player)?.inventory.clear();
+                Ok(format!("Cleared the inventory of {}", player))
+            }
+            Self::InventorySee { player } => {
+                let inventory = &find_player(players, &player)?.inventory;
+                if inventory.is_empty() {
+                    return Ok(format!("The inventory of {} is empty", player));
+                }
+                let mut lines = vec![format!("Inventory of {}:", player)];
+                for (slot, stack) in inventory.slots().iter().enumerate() {
+                    if let Some(stack) = stack {
+                        let name = match stack.item {
+                            InventoryItem::Item(id) => game_data.items.get_name_by_id(id),
+                            InventoryItem::Block(id) => game_data.blocks.get_name_by_id(id as u32),
+                        };
+                        let name = name.map_or("<unknown>", String::as_str);
+                        lines.push(format!("  {}: {} x{}", slot, name, stack.count));
+                    }
+                }
+                Ok(lines.join("\n"))
+            }
+            Self::Spawn => match sender {
+                CommandSender::Player(id) => {
+                    teleport_player(server, physics_simulation, id, world_spawn);
+                    Ok("Teleported to the world spawn".to_owned())
+                }
+                CommandSender::Console => Err("Only the players can use /spawn".to_owned()),
+            },
+            Self::List => {
+                let mut usernames = players
+                    .values()
+                    .filter(|data| data.logged_in)
+                    .map(|data| data.display_name.as_str())
+                    .collect::<Vec<_>>();
+                usernames.sort_unstable();
+                Ok(format!("{} players: {}", usernames.len(), usernames.join(", ")))
+            }
+            Self::Op { player } => {
+                set_role(server, players, world_metadata, permissions, &player, Some(OPERATOR_ROLE))?;
+                Ok(format!("{} is now an operator", player))
+            }
+            Self::Deop { player } => {
+                set_role(server, players, world_metadata, permissions, &player, None)?;
+                Ok(format!("{} is no longer an operator", player))
+            }
+            Self::Schedule { delay, line } => {
+                let message = format!("{} will run in {} seconds", line, delay);
+                scheduler.schedule_once(Instant::now(), Duration::from_secs(delay), ServerTask::Command(sender, line));
+                Ok(message)
+            }
+        }
+    }
+}
+
+/// Find an item by name, looking at the items first and then at the blocks
+fn find_item(game_data: &Data, name: &str) -> Result<InventoryItem, String> {
+    let name = name.to_owned();
+    if let Some(id) = game_data.items.get_id_by_name(&name) {
+        Ok(InventoryItem::Item(id))
+    } else if let Some(id) = game_data.blocks.get_id_by_name(&name).filter(|&id| id != 0) {
+        Ok(InventoryItem::Block(id as u16))
+    } else {
+        Err(format!("Unknown item or block: {}", name))
+    }
+}
+
+/// Change the role of a player and save the permissions. The player is told if it's connected.
+fn set_role(
+    server: &mut dyn Server,
+    players: &HashMap<PlayerId, PlayerData>,
+    world_metadata: &WorldMetadata,
+    permissions: &mut PermissionsConfig,
+    username: &str,
+    role: Option<&str>,
+) -> Result<(), String> {
+    permissions.set_role(username, role);
+    save::save_permissions(world_metadata, permissions).map_err(|e| format!("Failed to save the permissions: {:?}", e))?;
+    let connected = players
+        .iter()
+        .find(|(_, data)| data.logged_in && username_key(&data.display_name) == username_key(username));
+    if let Some((&id, _)) = connected {
+        let message = format!("Your role is now {}", permissions.role(username));
+        server.send(id, ToClient::CommandFeedback(message), MessageDelivery::Ordered);
+    }
+    Ok(())
+}
+
+/// Find a logged in player by username, without case
+fn find_player<'a>(players: &'a mut HashMap<PlayerId, PlayerData>, name: &str) -> Result<&'a mut PlayerData, String> {
+    players
+        .values_mut()
+        .find(|data| data.logged_in && username_key(&data.display_name) == username_key(name))
+        .ok_or_else(|| format!("Unknown player: {}", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(
+            Command::parse("/give Player stone 64"),
+            Ok(Command::Give {
+                player: "Player".to_owned(),
+                item: "stone".to_owned(),
+                count: 64
+            })
+        );
+        assert_eq!(
+            Command::parse(" /give Player ingot_iron "),
+            Ok(Command::Give {
+                player: "Player".to_owned(),
+                item: "ingot_iron".to_owned(),
+                count: 1
+            })
+        );
+        assert_eq!(Command::parse("/clear Player"), Ok(Command::Clear { player: "Player".to_owned() }));
+        assert_eq!(Command::parse("/spawn"), Ok(Command::Spawn));
+        assert_eq!(Command::parse("/list"), Ok(Command::List));
+        assert_eq!(Command::parse("/op Player"), Ok(Command::Op { player: "Player".to_owned() }));
+        assert_eq!(Command::parse("/deop Player"), Ok(Command::Deop { player: "Player".to_owned() }));
+        assert_eq!(
+            Command::parse("/schedule 60 give Player stone 64"),
+            Ok(Command::Schedule {
+                delay: 60,
+                line: "/give Player stone 64".to_owned()
+            })
+        );
+        assert!(Command::parse("/schedule 60 give Player").is_err());
+        assert!(Command::parse("/schedule soon list").is_err());
+        assert!(Command::parse("/op").is_err());
+        assert!(Command::parse("/give Player stone 0").is_err());
+        assert!(Command::parse("/give Player").is_err());
+        assert!(Command::parse("/clear Player now").is_err());
+        assert!(Command::parse("/unknown").is_err());
+        assert!(Command::parse("give Player stone").is_err());
+    }
+}
@@ -0,0 +1,89 @@
+//! Settings of the server that can be changed for every world
+
+use crate::auth::AuthConfig;
+use crate::console::RemoteAdminConfig;
+use crate::tick_regions::{default_tick_regions, TickRegion};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use voxel_rs_common::{
+    config::ServerTuning,
+    data::Data,
+    dimension::{Dimension, DEFAULT_DIMENSION},
+    player::{GameMode, InteractionConfig},
+    season::{SeasonCycle, SeasonState, TimeOfYear},
+};
+
+/// The settings of the server, stored in the world folder
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// Show the blocks that a player is breaking to the other players
+    pub broadcast_block_breaking: bool,
+    /// Maximum distance between a player and the blocks broken by the other players that it sees, in blocks
+    pub block_breaking_view_distance: f64,
+    /// The players without the `build_at_spawn` permission can't break or place blocks closer than this
+    /// horizontal distance to the world spawn, in blocks. 0 disables the protection.
+    pub spawn_protection_radius: f64,
+    /// The gamemode of the players that join the world for the first time
+    pub default_gamemode: GameMode,
+    /// The reach and the block cooldowns of every gamemode
+    pub interaction: InteractionConfig,
+    /// How often the chunks tick depending on their distance to the players. The chunks farther than every region are frozen.
+    pub tick_regions: Vec<TickRegion>,
+    /// Number of random blocks ticked in every chunk at every tick, for example to spread the grass or grow the saplings.
+    /// The regions decide which chunks tick, and 0 disables the random ticks.
+    pub random_ticks_per_chunk: u64,
+    /// Name of the dimension of the world in the data packs, that defines its sky and lighting
+    pub dimension: String,
+    /// Execute the lines typed in the server console as commands. Only changes when the server is restarted.
+    pub console: bool,
+    /// The usernames reserved for the players that know their token
+    pub auth: AuthConfig,
+    /// Accept commands from the admin tools over TCP. `None` disables the remote admin.
+    /// Only changes when the server is restarted.
+    pub remote_admin: Option<RemoteAdminConfig>,
+    /// The seasonal cycle of the world. `None` disables the seasons.
+    pub seasons: Option<SeasonCycle>,
+    /// Performance tuning of the server
+    pub tuning: ServerTuning,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            broadcast_block_breaking: true,
+            block_breaking_view_distance: 64.0,
+            spawn_protection_radius: 16.0,
+            default_gamemode: GameMode::default(),
+            interaction: InteractionConfig::default(),
+            tick_regions: default_tick_regions(),
+            random_ticks_per_chunk: 3,
+            dimension: DEFAULT_DIMENSION.to_owned(),
+            console: false,
+            auth: AuthConfig::default(),
+            remote_admin: None,
+            seasons: None,
+            tuning: ServerTuning::default(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// The dimension of the world, or the default dimension if it doesn't exist
+    pub fn dimension(&self, data: &Data) -> Dimension {
+        match data.dimensions.get_id_by_name(&self.dimension) {
+            Some(id) => *data.dimensions.get_value_by_id(id).unwrap(),
+            None => {
+                warn!("Unknown dimension {}, using the default dimension", self.dimension);
+                Dimension::default()
+            }
+        }
+    }
+
+    /// How the clients render the seasonal blocks at some time of the year
+    pub fn season_state(&self, time_of_year: TimeOfYear) -> SeasonState {
+        self.seasons
+            .as_ref()
+            .map_or_else(SeasonState::default, |cycle| cycle.state(time_of_year))
+    }
+}
@@ -0,0 +1,239 @@
+//! Administration of the server without joining it: commands typed in the server console, and commands sent by
+//! tools over TCP.
+//!
+//! The console and the admin connections run in their own threads and send the command lines to the server loop,
+//! which executes them like the chat commands of an operator and sends the output back.
+//!
+//! The TCP admin protocol is line-based: the first line sent by the tool must be the password from the server config.
+//! The server answers `OK` and then executes every line as a command. The output of every command is sent back,
+//! followed by an empty line. A wrong password closes the connection after a delay, and so do the lines longer than
+//! `MAX_LINE_LENGTH` and the connections that stay silent for too long.
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Maximum length of a line sent by an admin connection, in bytes
+const MAX_LINE_LENGTH: usize = 4096;
+/// Maximum number of admin connections at the same time
+const MAX_CONNECTIONS: usize = 4;
+/// Time given to a new connection to send the password
+const PASSWORD_TIMEOUT: Duration = Duration::from_secs(10);
+/// An authenticated connection that doesn't send any command for this long is closed
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+/// Delay before closing a connection that sent a wrong password. The connection keeps its slot during the delay,
+/// so that guessing the password is limited to `MAX_CONNECTIONS` attempts per delay.
+const WRONG_PASSWORD_DELAY: Duration = Duration::from_secs(1);
+
+/// Settings of the TCP admin protocol
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteAdminConfig {
+    /// Address the server listens on, for example `127.0.0.1:25575`
+    pub address: String,
+    /// Password that the tools must send before any command. The remote admin is disabled if it is empty.
+    pub password: String,
+}
+
+/// A command line sent by the console or by an admin connection
+pub struct AdminRequest {
+    pub line: String,
+    /// Where the output of the command is sent
+    pub reply: Sender<String>,
+}
+
+/// The command lines sent by the console and the admin connections
+pub struct AdminConsole {
+    receiver: Receiver<AdminRequest>,
+}
+
+impl AdminConsole {
+    /// Start reading the console if `console` is `true`, and start listening for admin connections if `remote` is set
+    pub fn start(console: bool, remote: Option<&RemoteAdminConfig>) -> Self {
+        let (sender, receiver) = channel();
+        if console {
+            let sender = sender.clone();
+            std::thread::spawn(move || read_console(sender));
+        }
+        if let Some(remote) = remote {
+            if remote.password.is_empty() {
+                warn!("The remote admin password is empty, the remote admin is disabled");
+            } else {
+                match TcpListener::bind(&remote.address) {
+                    Ok(listener) => {
+                        info!("Listening for remote admin connections on {}", remote.address);
+                        let password = remote.password.clone();
+                        std::thread::spawn(move || accept_connections(listener, password, sender));
+                    }
+                    Err(e) => warn!("Failed to listen for remote admin connections on {} ({:?})", remote.address, e),
+                }
+            }
+        }
+        Self { receiver }
+    }
+
+    /// The next command line that is waiting, if any
+    pub fn try_recv(&self) -> Option<AdminRequest> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+/// Send the lines of the standard input to the server, and print the output of the commands
+fn read_console(sender: Sender<AdminRequest>) {
+    let stdin = std::io::stdin();
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        match execute(&sender, line) {
+            Some(output) => println!("{}", output),
+            None => break,
+        }
+    }
+}
+
+/// Handle every admin connection in its own thread, refusing the connections above `MAX_CONNECTIONS`
+fn accept_connections(listener: TcpListener, password: String, sender: Sender<AdminRequest>) {
+    let connections = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let peer = stream.peer_addr().map(|addr| addr.to_string()).unwrap_or_default();
+                if connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+                    connections.fetch_sub(1, Ordering::SeqCst);
+                    warn!("Refused remote admin connection {}, there are too many connections", peer);
+                    continue;
+                }
+                let password = password.clone();
+                let sender = sender.clone();
+                let connections = connections.clone();
+                std::thread::spawn(move || {
+                    if let Err(e) = handle_connection(stream, &password, &sender) {
+                        warn!("Remote admin connection {} failed ({:?})", peer, e);
+                    }
+                    connections.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+            Err(e) => warn!("Failed to accept a remote admin connection ({:?})", e),
+        }
+    }
+}
+
+fn handle_connection(stream: TcpStream, password: &str, sender: &Sender<AdminRequest>) -> std::io::Result<()> {
+    let peer = stream.peer_addr()?;
+    stream.set_read_timeout(Some(PASSWORD_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    match read_line(&mut reader)? {
+        Some(line) if constant_time_eq(line.trim_end().as_bytes(), password.as_bytes()) => writeln!(writer, "OK")?,
+        _ => {
+            warn!("Remote admin connection {} sent a wrong password", peer);
+            std::thread::sleep(WRONG_PASSWORD_DELAY);
+            return Ok(());
+        }
+    }
+    info!("Remote admin connection {} authenticated", peer);
+    writer.set_read_timeout(Some(IDLE_TIMEOUT))?;
+    while let Some(line) = read_line(&mut reader)? {
+        let output = match execute(sender, line) {
+            Some(output) => output,
+            // The server stopped
+            None => break,
+        };
+        writeln!(writer, "{}\n", output)?;
+    }
+    Ok(())
+}
+
+/// Read a line of at most `MAX_LINE_LENGTH` bytes, without buffering the longer lines. Return `None` at the end of
+/// the stream.
+fn read_line(reader: &mut impl BufRead) -> std::io::Result<Option<String>> {
+    let mut line = String::new();
+    reader.take(MAX_LINE_LENGTH as u64 + 1).read_line(&mut line)?;
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if line.len() > MAX_LINE_LENGTH {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "the line is too long"));
+    }
+    Ok(Some(line.trim_end_matches(&['\r', '\n'][..]).to_owned()))
+}
+
+/// Compare two byte strings in a time that doesn't depend on where they differ
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let length_difference = (a.len() != b.len()) as u8;
+    let length = a.len().max(b.len());
+    let byte = |s: &[u8], i: usize| s.get(i).copied().unwrap_or(0);
+    (0..length).fold(length_difference, |difference, i| difference | (byte(a, i) ^ byte(b, i))) == 0
+}
+
+/// Send a command line to the server and wait for its output. Return `None` if the server stopped.
+fn execute(sender: &Sender<AdminRequest>, line: String) -> Option<String> {
+    // The console doesn't need the / of the chat commands
+    let line = if line.trim_start().starts_with('/') {
+        line
+    } else {
+        format!("/{}", line.trim_start())
+    };
+    let (reply, output) = channel();
+    sender.send(AdminRequest { line, reply }).ok()?;
+    output.recv().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remote_admin_protocol() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (sender, receiver) = channel();
+        std::thread::spawn(move || accept_connections(listener, "secret".to_owned(), sender));
+        // Answer the commands like the server loop
+        std::thread::spawn(move || {
+            for request in receiver {
+                let _ = request.reply.send(format!("executed {}", request.line));
+            }
+        });
+
+        let stream = TcpStream::connect(address).unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let mut lines = BufReader::new(stream).lines();
+        writeln!(writer, "secret\nlist\n/clear Player").unwrap();
+        assert_eq!(lines.next().unwrap().unwrap(), "OK");
+        assert_eq!(lines.next().unwrap().unwrap(), "executed /list");
+        assert_eq!(lines.next().unwrap().unwrap(), "");
+        assert_eq!(lines.next().unwrap().unwrap(), "executed /clear Player");
+
+        // A wrong password closes the connection
+        let stream = TcpStream::connect(address).unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        writeln!(writer, "wrong\nlist").unwrap();
+        assert!(!matches!(BufReader::new(stream).lines().next(), Some(Ok(_))));
+
+        // So does a line that is too long
+        let stream = TcpStream::connect(address).unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        writeln!(writer, "secret").unwrap();
+        let mut lines = BufReader::new(stream).lines();
+        assert_eq!(lines.next().unwrap().unwrap(), "OK");
+        let _ = writeln!(writer, "{}", "a".repeat(MAX_LINE_LENGTH + 1));
+        assert!(!matches!(lines.next(), Some(Ok(_))));
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret\0"));
+        assert!(!constant_time_eq(b"", b"secret"));
+    }
+}
@@ -0,0 +1,67 @@
+//! Detection of changes in the data directory, to reload the data packs without restarting
+use anyhow::{Context, Result};
+use log::warn;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Minimum time between two scans of the data directory
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Watches a directory by periodically looking at the modification times of its files
+pub struct DataWatcher {
+    directory: PathBuf,
+    last_modified: Option<SystemTime>,
+    last_poll: Instant,
+}
+
+impl DataWatcher {
+    pub fn new(directory: PathBuf) -> Self {
+        let last_modified = latest_modification(&directory).ok();
+        Self {
+            directory,
+            last_modified,
+            last_poll: Instant::now(),
+        }
+    }
+
+    /// Return `true` if the directory changed since the last call
+    pub fn poll(&mut self) -> bool {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.last_poll = Instant::now();
+        match latest_modification(&self.directory) {
+            Ok(modified) => {
+                let changed = self.last_modified.is_some_and(|last| modified > last);
+                self.last_modified = Some(modified);
+                changed
+            }
+            Err(e) => {
+                warn!("Failed to scan data directory ({:?})", e);
+                false
+            }
+        }
+    }
+}
+
+/// Most recent modification time of the directory, its subdirectories and their files.
+/// Directories are included so that removed files are detected too.
+fn latest_modification(directory: &Path) -> Result<SystemTime> {
+    let mut latest = std::fs::metadata(directory)
+        .and_then(|metadata| metadata.modified())
+        .context(format!("Failed to get modification time of {}", directory.display()))?;
+    for dir_entry in std::fs::read_dir(directory)
+        .context(format!("Failed to read directory {}", directory.display()))?
+    {
+        let path = dir_entry?.path();
+        let modified = if path.is_dir() {
+            latest_modification(&path)?
+        } else {
+            std::fs::metadata(&path)
+                .and_then(|metadata| metadata.modified())
+                .context(format!("Failed to get modification time of {}", path.display()))?
+        };
+        latest = latest.max(modified);
+    }
+    Ok(latest)
+}
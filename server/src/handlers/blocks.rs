@@ -0,0 +1,306 @@
+//! Breaking, selecting and placing the blocks
+use super::super::{
+    has_permission, remaining_cooldown, send_sleeping_players, standing_position, BlockBreaking, ServerState,
+};
+use crate::permissions::BUILD_AT_SPAWN_PERMISSION;
+use log::warn;
+use nalgebra::Vector3;
+use std::time::{Duration, Instant};
+use voxel_rs_common::block::{Block, BlockType};
+use voxel_rs_common::block::entity::{BlockEntity, ITEM_FRAME_ROTATIONS};
+use voxel_rs_common::network::{messages::ToClient, MessageDelivery};
+use voxel_rs_common::physics::aabb::AABB;
+use voxel_rs_common::physics::player::PhysicsPlayer;
+use voxel_rs_common::physics::raycast::RaycastHit;
+use voxel_rs_common::player::PlayerId;
+use voxel_rs_common::world::{BlockPos, Direction};
+
+/// Number of steps of the breaking progress sent to the players
+const BREAKING_STAGES: u32 = 10;
+/// Maximum distance between the position that a client sends with an interaction and its position on the server.
+/// The client predicts its movement, so it is a bit ahead of the server.
+const MAX_CLAIMED_POSITION_ERROR: f64 = 4.0;
+
+/// The block that a player at `player_pos` looking towards `yaw` and `pitch` points at, within `reach` blocks.
+/// Nothing is pointed at if `player_pos` is too far from the position of the player on the server.
+fn pointed_at(
+    state: &ServerState,
+    id: PlayerId,
+    player_pos: Vector3<f64>,
+    yaw: f64,
+    pitch: f64,
+    reach: f64,
+) -> Option<RaycastHit> {
+    let server_pos = state.physics_simulation.get_state().physics_state.players.get(&id)?.aabb.pos;
+    if (player_pos - server_pos).norm() > MAX_CLAIMED_POSITION_ERROR {
+        warn!("{:?} claimed to be at {:?} while it is at {:?} on the server", id, player_pos, server_pos);
+        return None;
+    }
+    let physics_player = PhysicsPlayer {
+        aabb: AABB {
+            pos: player_pos,
+            size_x: 0.0,
+            size_y: 0.0,
+            size_z: 0.0,
+        },
+        velocity: Vector3::zeros(),
+    };
+    let y = yaw.to_radians();
+    let p = pitch.to_radians();
+    let dir = Vector3::new(-y.sin() * p.cos(), p.sin(), -y.cos() * p.cos());
+    physics_player.get_pointed_at(dir, reach, &state.world)
+}
+
+/// Send a sound played at the center of `block` to every player, if the sound exists.
+/// `predicted_by` is the player that already played the sound when it clicked, it doesn't receive it again.
+fn play_sound(state: &mut ServerState, sound: &str, block: BlockPos, predicted_by: Option<PlayerId>) {
+    if let Some(sound_id) = state.game_data.sounds.get_id_by_name(&sound.to_owned()) {
+        let pos = Vector3::new(block.px as f64, block.py as f64, block.pz as f64)
+            + Vector3::new(0.5, 0.5, 0.5);
+        for (&player, _) in state.players.iter().filter(|(&player, _)| Some(player) != predicted_by) {
+            state.server.send(player, ToClient::PlaySound(sound_id, pos), MessageDelivery::Ordered);
+        }
+    }
+}
+
+/// Send the progress of `breaker` breaking `block` to the players that are close enough to see it.
+/// The breaker always receives it, and the other players only if the server config allows it.
+fn broadcast_block_breaking(state: &mut ServerState, breaker: PlayerId, block: BlockPos, progress: Option<f32>) {
+    let block_center = Vector3::new(block.px as f64, block.py as f64, block.pz as f64)
+        + Vector3::new(0.5, 0.5, 0.5);
+    let config = &state.server_config;
+    let physics_players = &state.physics_simulation.get_state().physics_state.players;
+    for &player in state.players.keys() {
+        let sees_block = player == breaker
+            || config.broadcast_block_breaking
+                && physics_players.get(&player).is_some_and(|physics_player| {
+                    (physics_player.get_camera_position() - block_center).norm()
+                        <= config.block_breaking_view_distance
+                });
+        if sees_block {
+            state.server.send(player, ToClient::BlockBreaking(breaker, block, progress), MessageDelivery::Ordered);
+        }
+    }
+}
+
+/// Break `block` for `breaker`, and tell the players that it broke.
+/// `instant` is true if the block broke as soon as the breaker clicked, then the breaker already played the sound.
+fn break_block(state: &mut ServerState, breaker: PlayerId, block: BlockPos, instant: bool) {
+    if state.world.set_block(block, 0) {
+        let predicted_by = if instant { Some(breaker) } else { None };
+        play_sound(state, "block_break", block, predicted_by);
+        broadcast_block_breaking(state, breaker, block, Some(1.0));
+    }
+    state.players.get_mut(&breaker).unwrap().last_break = Some(Instant::now());
+}
+
+/// Check that a player can break or place `block`, and tell it why otherwise.
+/// Only the players with the permission can modify the blocks close to the world spawn.
+fn can_modify_block(state: &mut ServerState, player: PlayerId, block: BlockPos) -> bool {
+    let config = &state.server_config;
+    let dx = block.px as f64 + 0.5 - state.world_spawn.x;
+    let dz = block.pz as f64 + 0.5 - state.world_spawn.z;
+    let protected = dx.abs().max(dz.abs()) < config.spawn_protection_radius;
+    if protected && !has_permission(&state.players, &state.permissions, player, BUILD_AT_SPAWN_PERMISSION) {
+        let reason = format!(
+            "You can't modify the blocks within {} blocks of the spawn",
+            config.spawn_protection_radius
+        );
+        state.server.send(player, ToClient::ActionDenied(reason), MessageDelivery::Ordered);
+        return false;
+    }
+    true
+}
+
+/// Stop breaking the block that a player is breaking, if any
+pub(super) fn stop_breaking(state: &mut ServerState, id: PlayerId) {
+    if let Some(breaking) = state.players.get_mut(&id).unwrap().breaking.take() {
+        broadcast_block_breaking(state, id, breaking.block, None);
+    }
+}
+
+/// Start breaking the block that a player points at, or break it immediately if it breaks instantly
+pub(super) fn start_breaking(state: &mut ServerState, id: PlayerId, player_pos: Vector3<f64>, yaw: f64, pitch: f64) {
+    stop_breaking(state, id);
+    let rules = state.server_config.interaction.rules(state.players[&id].gamemode);
+    let block = match pointed_at(state, id, player_pos, yaw, pitch, rules.reach) {
+        Some(RaycastHit { block, .. }) => block,
+        None => return,
+    };
+    if !can_modify_block(state, id, block) {
+        return;
+    }
+    let cooldown = remaining_cooldown(state.players[&id].last_break, rules.break_cooldown);
+    let block_id = state.world.get_block(block);
+    let hardness = state
+        .game_data
+        .blocks
+        .get_value_by_id(block_id as u32)
+        .map_or(0.0, |block| block.block_type.hardness());
+    let break_time = state.players[&id].gamemode.break_time(hardness);
+    if break_time <= 0.0 {
+        // The client doesn't break blocks during the cooldown
+        if cooldown <= 0.0 {
+            break_block(state, id, block, true);
+        }
+        return;
+    }
+    // Breaking starts when the cooldown ends
+    state.players.get_mut(&id).unwrap().breaking = Some(BlockBreaking {
+        block,
+        block_id,
+        start: Instant::now() + Duration::from_secs_f64(cooldown),
+        break_time,
+        sent_stage: 0,
+    });
+    broadcast_block_breaking(state, id, block, Some(0.0));
+}
+
+/// Advance the blocks that the players are breaking, and break those that are done
+pub(crate) fn update_block_breaking(state: &mut ServerState) {
+    let mut breaking_updates = Vec::new();
+    let mut broken_blocks = Vec::new();
+    for (&id, data) in state.players.iter_mut() {
+        if let Some(breaking) = data.breaking.as_mut() {
+            let progress = breaking.start.elapsed().as_secs_f64() / breaking.break_time;
+            // The block may have been broken or replaced by someone else
+            if state.world.get_block(breaking.block) != breaking.block_id {
+                breaking_updates.push((id, breaking.block, None));
+                data.breaking = None;
+            } else if progress >= 1.0 {
+                broken_blocks.push((id, breaking.block));
+                data.breaking = None;
+            } else {
+                let stage = (progress * BREAKING_STAGES as f64) as u32;
+                if stage != breaking.sent_stage {
+                    breaking.sent_stage = stage;
+                    breaking_updates.push((id, breaking.block, Some(progress as f32)));
+                }
+            }
+        }
+    }
+    for (id, block, progress) in breaking_updates {
+        broadcast_block_breaking(state, id, block, progress);
+    }
+    for (id, block) in broken_blocks {
+        break_block(state, id, block, false);
+    }
+}
+
+/// Select the block that a player points at as the block it places
+pub(super) fn select_block(state: &mut ServerState, id: PlayerId, player_pos: Vector3<f64>, yaw: f64, pitch: f64) {
+    let reach = state.server_config.interaction.rules(state.players[&id].gamemode).reach;
+    if let Some(RaycastHit { block, .. }) = pointed_at(state, id, player_pos, yaw, pitch, reach) {
+        // TODO: careful with more complicated blocks
+        state.players.get_mut(&id).unwrap().block_to_place = state.world.get_block(block);
+    }
+}
+
+/// Use the block that a player points at if it's a bed or an item frame, and otherwise place a block next to it
+pub(super) fn place_block(state: &mut ServerState, id: PlayerId, player_pos: Vector3<f64>, yaw: f64, pitch: f64) {
+    let rules = state.server_config.interaction.rules(state.players[&id].gamemode);
+    let (block, face) = match pointed_at(state, id, player_pos, yaw, pitch, rules.reach) {
+        Some(RaycastHit { block, face, .. }) => (block, face),
+        None => return,
+    };
+    let pointed_block = state.game_data.blocks.get_value_by_id(state.world.get_block(block) as u32);
+    match pointed_block {
+        // Use the bed instead of placing a block
+        Some(Block { block_type: BlockType::Bed { .. }, .. }) => {
+            let player_data = state.players.get_mut(&id).unwrap();
+            player_data.spawn_point = Some(standing_position(BlockPos::from((block.px, block.py + 1, block.pz))));
+            if state.time_of_day.is_night() && !player_data.sleeping {
+                player_data.sleeping = true;
+                send_sleeping_players(&mut *state.server, &state.players);
+            }
+        }
+        // Put an item in the item frame, or rotate the item that is already there
+        Some(Block { block_type: BlockType::ItemFrame { .. }, .. }) => {
+            if !can_modify_block(state, id, block) {
+                return;
+            }
+            let item = state.players[&id].item_to_place;
+            let block_entity = match state.world.get_block_entity(block) {
+                Some(&BlockEntity::ItemFrame { item, face, rotation }) => Some(BlockEntity::ItemFrame {
+                    item,
+                    face,
+                    rotation: (rotation + 1) % ITEM_FRAME_ROTATIONS,
+                }),
+                // Items can only be attached to the side faces
+                None if face != Direction::PosY.face()
+                    && face != Direction::NegY.face()
+                    && state.game_data.items.get_value_by_id(item).is_some() =>
+                {
+                    Some(BlockEntity::ItemFrame { item, face, rotation: 0 })
+                }
+                None => None,
+            };
+            if let Some(block_entity) = block_entity {
+                state.world.set_block_entity(block, Some(block_entity));
+            }
+        }
+        _ => {
+            let block = block.neighbor(Direction::ALL[face]);
+            if !can_modify_block(state, id, block) {
+                return;
+            }
+            // The client doesn't place blocks during the cooldown
+            if remaining_cooldown(state.players[&id].last_place, rules.place_cooldown) > 0.0 {
+                return;
+            }
+            if state.world.set_block(block, state.players[&id].block_to_place) {
+                state.players.get_mut(&id).unwrap().last_place = Some(Instant::now());
+                play_sound(state, "block_place", block, Some(id));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::save::{self, WorldMetadata};
+    use crate::PlayerData;
+    use std::sync::Arc;
+    use voxel_rs_common::network::{dummy, ServerEvent};
+    use voxel_rs_common::world::Chunk;
+
+    #[test]
+    fn test_far_claimed_position() {
+        let world_metadata = WorldMetadata {
+            name: format!("claimed position test {}", std::process::id()),
+            seed: 0,
+            version: 0,
+        };
+        let folder = world_metadata.folder();
+        let _ = std::fs::remove_dir_all(&folder);
+        let (_client, server) = dummy::new();
+        let mut state = ServerState::for_tests(Box::new(server), world_metadata);
+        let id = match state.server.receive_event() {
+            ServerEvent::ClientConnected(id) => id,
+            _ => unreachable!(),
+        };
+        state.players.insert(id, PlayerData::default());
+        state.physics_simulation.set_player_input(id, Default::default());
+        // A block 3 blocks in front of the camera of a player standing at `claimed_pos`
+        let block = BlockPos::from((0, 11, -3));
+        let mut chunk = Chunk::new(block.containing_chunk_pos());
+        chunk.set_block_at(block.pos_in_containing_chunk(), 1);
+        state.world.set_chunk(Arc::new(chunk));
+        let claimed_pos = Vector3::new(0.1, 9.5, 0.1);
+
+        // The player is far away on the server
+        state.physics_simulation.teleport_player(id, Vector3::new(100.1, 9.5, 0.1));
+        start_breaking(&mut state, id, claimed_pos, 0.0, 0.0);
+        assert_eq!(state.world.get_block(block), 1);
+
+        // The player really is there
+        state.physics_simulation.teleport_player(id, claimed_pos);
+        start_breaking(&mut state, id, claimed_pos, 0.0, 0.0);
+        assert_eq!(state.world.get_block(block), 0);
+
+        drop(state);
+        let _ = std::fs::remove_dir_all(&folder);
+        let _ = std::fs::remove_dir(save::SAVES_FOLDER);
+    }
+}
@@ -0,0 +1,329 @@
+//! The handlers of the events of the network and of the messages of the clients
+use super::{
+    dead_player_input, has_permission, restore_player, save_player, send_sleeping_players, teleport_player,
+    PlayerData, ServerState,
+};
+use crate::commands::{Command, CommandSender};
+use crate::permissions::{GAMEMODE_PERMISSION, OPERATOR_ROLE, STOP_PERMISSION};
+use crate::save;
+use crate::tickets::TicketSource;
+use log::{info, warn};
+use std::time::Instant;
+use voxel_rs_common::config::MAX_RENDER_DISTANCE;
+use voxel_rs_common::inventory::{SlotAction, PLAYER_INVENTORY_SIZE};
+use voxel_rs_common::network::{
+    messages::{ToClient, ToServer},
+    MessageDelivery, ServerEvent,
+};
+use voxel_rs_common::player::{GameMode, PlayerId, PlayerInput, RenderDistance, MAX_HEALTH};
+
+mod blocks;
+
+pub(crate) use blocks::update_block_breaking;
+
+/// Check that the values in a message sent by a client are valid, so that a single client can't make the server panic
+fn validate_message(message: &ToServer) -> Result<(), String> {
+    let is_finite = |values: &[f64]| values.iter().all(|value| value.is_finite());
+    match message {
+        ToServer::SetRenderDistance(render_distance) => {
+            let RenderDistance { x_max, x_min, y_max, y_min, z_max, z_min } = *render_distance;
+            if [x_max, x_min, y_max, y_min, z_max, z_min].iter().any(|&d| d > MAX_RENDER_DISTANCE) {
+                return Err(format!("The render distance can't be more than {} chunks", MAX_RENDER_DISTANCE));
+            }
+        }
+        ToServer::UpdateInput(input, _) => {
+            if !is_finite(&[input.yaw, input.pitch]) {
+                return Err("Invalid yaw or pitch".to_owned());
+            }
+        }
+        ToServer::StartBreaking(pos, yaw, pitch)
+        | ToServer::SelectBlock(pos, yaw, pitch)
+        | ToServer::PlaceBlock(pos, yaw, pitch) => {
+            if !is_finite(&[pos.x, pos.y, pos.z, *yaw, *pitch]) {
+                return Err("Invalid position, yaw or pitch".to_owned());
+            }
+        }
+        ToServer::SlotAction(_, action) => {
+            let slots = match action {
+                SlotAction::Click { from, to } => vec![*from, *to],
+                SlotAction::ShiftClick(slot) => vec![*slot],
+                SlotAction::DragSplit { from, to } => std::iter::once(*from).chain(to.iter().copied()).collect(),
+            };
+            if slots.len() > PLAYER_INVENTORY_SIZE || slots.iter().any(|&slot| slot >= PLAYER_INVENTORY_SIZE) {
+                return Err("Invalid inventory slot".to_owned());
+            }
+        }
+        ToServer::StopBreaking
+        | ToServer::Login(..)
+        | ToServer::Command(_)
+        | ToServer::CraftItem(_)
+        | ToServer::SetGameMode(_)
+        | ToServer::FollowPlayer(_)
+        | ToServer::Respawn
+        | ToServer::StopServer
+        | ToServer::ChunkReceived(_) => {}
+    }
+    Ok(())
+}
+
+/// Handle all the events received since the last tick
+pub(crate) fn handle_events(state: &mut ServerState) {
+    loop {
+        match state.server.receive_event() {
+            ServerEvent::NoEvent => break,
+            ServerEvent::ClientConnected(id) => client_connected(state, id),
+            ServerEvent::ClientDisconnected(id) => client_disconnected(state, id),
+            ServerEvent::ClientMessage(id, _) if !state.players.contains_key(&id) => {
+                warn!("Received a message from unknown client {:?}, disconnecting it", id);
+                state.server.disconnect(id, "Unknown client".to_owned());
+            }
+            ServerEvent::ClientMessage(id, message) => match validate_message(&message) {
+                Ok(()) => handle_message(state, id, message),
+                Err(reason) => {
+                    warn!("Disconnecting {:?} because it sent an invalid message: {}", id, reason);
+                    state.server.disconnect(id, reason);
+                }
+            },
+        }
+    }
+}
+
+fn client_connected(state: &mut ServerState, id: PlayerId) {
+    info!("Client connected to the server!");
+    state.physics_simulation.set_player_input(id, Default::default());
+    let host = state.server.is_host(id);
+    let gamemode = state.server_config.default_gamemode;
+    state.physics_simulation.set_player_hidden(id, gamemode.is_hidden());
+    state.players.insert(id, PlayerData { host, gamemode, ..PlayerData::default() });
+    let server = &mut *state.server;
+    server.send(id, ToClient::GameData(Box::new(state.game_data.clone())), MessageDelivery::Ordered);
+    server.send(id, ToClient::CurrentId(id), MessageDelivery::Ordered);
+    server.send(id, ToClient::TimeOfDay(state.time_of_day), MessageDelivery::Ordered);
+    let season_state = state.server_config.season_state(state.time_of_year);
+    server.send(id, ToClient::Season(season_state), MessageDelivery::Ordered);
+    server.send(id, ToClient::Weather(state.weather), MessageDelivery::Ordered);
+    server.send(id, ToClient::Dimension(state.dimension), MessageDelivery::Ordered);
+    server.send(id, ToClient::GameMode(gamemode), MessageDelivery::Ordered);
+    server.send(id, ToClient::InteractionConfig(state.server_config.interaction), MessageDelivery::Ordered);
+    teleport_player(server, &mut state.physics_simulation, id, state.world_spawn);
+    for (&other_id, other_data) in state.players.iter().filter(|(_, data)| data.logged_in) {
+        server.send(id, ToClient::DisplayName(other_id, other_data.display_name.clone()), MessageDelivery::Ordered);
+    }
+}
+
+fn client_disconnected(state: &mut ServerState, id: PlayerId) {
+    if let Some(data) = state.players.get(&id) {
+        save_player(&state.world_metadata, &state.game_data, &state.physics_simulation, id, data);
+    }
+    state.physics_simulation.remove(id);
+    state.players.remove(&id);
+    for data in state.players.values_mut() {
+        if data.physics_interest.followed_player() == Some(id) {
+            data.physics_interest.set_followed_player(None);
+        }
+    }
+    state.chunk_tickets.remove(TicketSource::Player(id));
+    send_sleeping_players(&mut *state.server, &state.players);
+}
+
+/// Handle a valid message of a connected client
+fn handle_message(state: &mut ServerState, id: PlayerId, message: ToServer) {
+    let player_data = match state.players.get(&id) {
+        Some(player_data) => player_data,
+        None => return,
+    };
+    // The players can only move and receive chunks until they log in
+    let allowed_before_login = matches!(
+        message,
+        ToServer::Login(..) | ToServer::UpdateInput(..) | ToServer::SetRenderDistance(_) | ToServer::ChunkReceived(_)
+    );
+    if !player_data.logged_in && !allowed_before_login {
+        return;
+    }
+    // The dead players can't interact with the world until they respawn
+    let interacts = matches!(
+        message,
+        ToServer::StartBreaking(..) | ToServer::SelectBlock(..) | ToServer::PlaceBlock(..)
+    );
+    if player_data.health == 0 && (interacts || matches!(message, ToServer::CraftItem(_))) {
+        return;
+    }
+    // The spectators can't modify the world
+    if !player_data.gamemode.can_interact() && interacts {
+        let reason = format!("You can't interact with the world in {:?} mode", player_data.gamemode);
+        state.server.send(id, ToClient::ActionDenied(reason), MessageDelivery::Ordered);
+        return;
+    }
+
+    match message {
+        ToServer::UpdateInput(mut input, time) => {
+            if let Some(player_data) = state.players.get_mut(&id) {
+                // Only some gamemodes allow flying, and the gamemode decides if the player goes through blocks
+                input.flying &= player_data.gamemode.can_fly();
+                input.noclip = player_data.gamemode.has_noclip();
+                if player_data.health == 0 {
+                    input = dead_player_input(input);
+                }
+                state.physics_simulation.buffer_player_input(id, time, Instant::now(), input);
+                // Moving wakes the player up
+                if is_moving(&input) && player_data.sleeping {
+                    player_data.sleeping = false;
+                    send_sleeping_players(&mut *state.server, &state.players);
+                }
+            }
+        }
+        ToServer::SetRenderDistance(render_distance) => {
+            if let Some(player_data) = state.players.get_mut(&id) {
+                player_data.render_distance = render_distance;
+            }
+        }
+        ToServer::Login(username, token) => login(state, id, username, token),
+        ToServer::Command(line) => {
+            let result = Command::parse(&line)
+                .and_then(|command| command.execute(CommandSender::Player(id), state.command_context()));
+            let feedback = match result {
+                Ok(feedback) => {
+                    info!("{} executed {}", state.players[&id].display_name, line.trim());
+                    feedback
+                }
+                Err(error) => error,
+            };
+            state.server.send(id, ToClient::CommandFeedback(feedback), MessageDelivery::Ordered);
+        }
+        ToServer::CraftItem(recipe_id) => {
+            if let Some(player_data) = state.players.get_mut(&id) {
+                let result = match state.game_data.recipes.get_value_by_id(recipe_id) {
+                    Some(recipe) => recipe.craft(&mut player_data.inventory),
+                    None => Err(format!("Unknown recipe {}", recipe_id)),
+                };
+                if let Err(reason) = result {
+                    state.server.send(id, ToClient::ActionDenied(reason), MessageDelivery::Ordered);
+                }
+            }
+        }
+        ToServer::SlotAction(action_id, action) => {
+            // The action is acknowledged even if it's refused, so that the client rolls it back
+            if let Some(player_data) = state.players.get_mut(&id) {
+                player_data.last_slot_action = action_id;
+                let result = if player_data.health == 0 {
+                    Err("Dead players can't use their inventory".to_owned())
+                } else {
+                    action.apply(&mut player_data.inventory)
+                };
+                if let Err(reason) = result {
+                    state.server.send(id, ToClient::ActionDenied(reason), MessageDelivery::Ordered);
+                }
+            }
+        }
+        ToServer::SetGameMode(gamemode) => set_gamemode(state, id, gamemode),
+        ToServer::FollowPlayer(target) => {
+            // Only the spectators can follow the other players
+            let spectator = state.players[&id].gamemode == GameMode::Spectator;
+            let target = target.filter(|target| spectator && *target != id && state.players.contains_key(target));
+            if let Some(player_data) = state.players.get_mut(&id) {
+                player_data.physics_interest.set_followed_player(target);
+            }
+        }
+        ToServer::Respawn => {
+            if let Some(player_data) = state.players.get_mut(&id) {
+                if player_data.health == 0 {
+                    info!("{} respawned", player_data.display_name);
+                    player_data.health = MAX_HEALTH;
+                    let spawn_point = player_data.spawn_point.unwrap_or(state.world_spawn);
+                    teleport_player(&mut *state.server, &mut state.physics_simulation, id, spawn_point);
+                }
+            }
+        }
+        ToServer::ChunkReceived(pos) => {
+            if let Some(player_data) = state.players.get_mut(&id) {
+                player_data.sent_chunks.acknowledge(pos);
+            }
+        }
+        ToServer::StopServer => {
+            if has_permission(&state.players, &state.permissions, id, STOP_PERMISSION) {
+                info!("{} stopped the server", state.players[&id].display_name);
+                state.stop_requested = true;
+            } else {
+                warn!("{} tried to stop the server without the permission", state.players[&id].display_name);
+            }
+        }
+        ToServer::StartBreaking(player_pos, yaw, pitch) => blocks::start_breaking(state, id, player_pos, yaw, pitch),
+        ToServer::StopBreaking => blocks::stop_breaking(state, id),
+        ToServer::SelectBlock(player_pos, yaw, pitch) => blocks::select_block(state, id, player_pos, yaw, pitch),
+        ToServer::PlaceBlock(player_pos, yaw, pitch) => blocks::place_block(state, id, player_pos, yaw, pitch),
+    }
+}
+
+/// `true` if the player presses a key that moves it
+fn is_moving(input: &PlayerInput) -> bool {
+    input.key_move_forward
+        || input.key_move_left
+        || input.key_move_backward
+        || input.key_move_right
+        || input.key_move_up
+        || input.key_move_down
+}
+
+fn login(state: &mut ServerState, id: PlayerId, username: String, token: Option<String>) {
+    if state.players[&id].logged_in {
+        let reason = "Already logged in".to_owned();
+        state.server.send(id, ToClient::ActionDenied(reason), MessageDelivery::Ordered);
+        return;
+    }
+    let connected = state.players.values().filter(|data| data.logged_in).map(|data| data.display_name.as_str());
+    let username = match state.server_config.auth.authenticate(&username, token.as_deref(), connected) {
+        Ok(username) => username,
+        Err(reason) => {
+            warn!("Refused the login of {:?}: {}", id, reason);
+            state.server.disconnect(id, reason);
+            return;
+        }
+    };
+    info!("{} logged in", username);
+    let player_data = match state.players.get_mut(&id) {
+        Some(player_data) => player_data,
+        None => return,
+    };
+    if player_data.host && state.permissions.players.is_empty() {
+        info!("{} hosts the world and is now an operator", username);
+        state.permissions.set_role(&username, Some(OPERATOR_ROLE));
+        if let Err(e) = save::save_permissions(&state.world_metadata, &state.permissions) {
+            warn!("Failed to save permissions ({:?})", e);
+        }
+    }
+    player_data.display_name = username.clone();
+    restore_player(
+        &mut *state.server,
+        &state.world_metadata,
+        &state.game_data,
+        &mut state.physics_simulation,
+        id,
+        player_data,
+    );
+    for (&player, _) in state.players.iter() {
+        state.server.send(player, ToClient::DisplayName(id, username.clone()), MessageDelivery::Ordered);
+    }
+}
+
+fn set_gamemode(state: &mut ServerState, id: PlayerId, gamemode: GameMode) {
+    if !has_permission(&state.players, &state.permissions, id, GAMEMODE_PERMISSION) {
+        let reason = "You don't have the permission to change your gamemode".to_owned();
+        state.server.send(id, ToClient::ActionDenied(reason), MessageDelivery::Ordered);
+        return;
+    }
+    let player_data = match state.players.get_mut(&id) {
+        Some(player_data) => player_data,
+        None => return,
+    };
+    info!("{} is now in {:?} mode", player_data.display_name, gamemode);
+    player_data.gamemode = gamemode;
+    if gamemode != GameMode::Spectator {
+        player_data.physics_interest.set_followed_player(None);
+    }
+    state.physics_simulation.set_player_hidden(id, gamemode.is_hidden());
+    state.server.send(id, ToClient::GameMode(gamemode), MessageDelivery::Ordered);
+    // Stop breaking the block if the new gamemode can't interact
+    if !gamemode.can_interact() {
+        blocks::stop_breaking(state, id);
+    }
+}
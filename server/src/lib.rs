@@ -1,50 +1,147 @@
-use crate::world::World;
+use crate::world::{ChunkUpdate, World};
 use anyhow::Result;
-use log::info;
+use log::{info, warn};
 use nalgebra::Vector3;
 use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use voxel_rs_common::block::BlockId;
-use voxel_rs_common::physics::aabb::AABB;
+use voxel_rs_common::inventory::{Inventory, PLAYER_INVENTORY_SIZE};
+use voxel_rs_common::item::ItemId;
 use voxel_rs_common::physics::player::PhysicsPlayer;
 use voxel_rs_common::{
-    data::load_data,
-    debug::{send_debug_info, send_perf_breakdown},
-    network::{
-        messages::{ToClient, ToServer},
-        Server, ServerEvent,
-    },
-    physics::simulation::ServerPhysicsSimulation,
-    player::{CloseChunks, RenderDistance},
+    config::VOID_HEIGHT,
+    data::{load_data, Data},
+    debug::{send_debug_info, send_perf_breakdown, send_perf_sample},
+    dimension::Dimension,
+    network::{messages::ToClient, MessageDelivery, Server},
+    physics::simulation::{PlayerInterest, ServerPhysicsSimulation},
+    player::{CloseChunks, GameMode, PlayerId, PlayerInput, RenderDistance, MAX_HEALTH},
     world::{
         ChunkPos,
         BlockPos,
+        WorldGenerator,
     },
     worldgen::DefaultWorldGenerator,
 };
-use voxel_rs_common::time::BreakdownCounter;
+use voxel_rs_common::season::TimeOfYear;
+use voxel_rs_common::time::{BreakdownCounter, TimeOfDay};
+use voxel_rs_common::weather::Weather;
 
-mod light;
+pub mod anvil;
+pub mod auth;
+mod block_updates;
+pub mod commands;
+pub mod config;
+pub mod console;
+mod data_watcher;
+mod handlers;
+pub mod light;
+pub mod permissions;
+pub mod plugins;
+mod sent_chunks;
+pub mod pregen;
+pub mod save;
+pub mod scheduler;
+pub mod tick_regions;
+pub mod tickets;
+pub mod vox_export;
 mod world;
+pub mod world_editor;
 mod worldgen;
 
-// TODO: refactor
-const D: [[i64; 3]; 6] = [
-    [1, 0, 0],
-    [-1, 0, 0],
-    [0, 1, 0],
-    [0, -1, 0],
-    [0, 0, 1],
-    [0, 0, -1],
-];
+use block_updates::{BlockBehaviors, BlockUpdates};
+use commands::{Command, CommandContext, CommandSender};
+use config::ServerConfig;
+use console::AdminConsole;
+use data_watcher::DataWatcher;
+use permissions::PermissionsConfig;
+use plugins::{PluginTask, ServerPlugins};
+use save::{SavedPlayer, WorldMetadata};
+use scheduler::Scheduler;
+use sent_chunks::SentChunks;
+use tick_regions::TickRegions;
+use tickets::{ChunkTicket, ChunkTickets, TicketSource};
+
+/// Folder containing the data packs
+pub(crate) const DATA_FOLDER: &str = "data";
+/// Number of chunks around the spawn chunk that always stay loaded
+const SPAWN_TICKET_RADIUS: u64 = 2;
+/// How much earlier than the end of their cooldown the interactions of the players are accepted, in seconds
+const COOLDOWN_TOLERANCE: f64 = 0.05;
+/// The spectators are teleported to the player they follow when they are farther than this distance from it, in blocks.
+/// This way, they receive the chunks around that player.
+const FOLLOW_TELEPORT_DISTANCE: f64 = 16.0;
+/// The players that didn't log in after this time are disconnected
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A task that the server runs at a scheduled time.
+/// The commands and the plugins schedule tasks with the scheduler of their `CommandContext`.
+#[derive(Debug, Clone)]
+pub enum ServerTask {
+    /// Log a summary of the server state
+    LogStatistics,
+    /// Send the time of the day and the season to the players, to correct the drift of their clocks
+    SyncTimeOfDay,
+    /// Execute a command line, scheduled by `/schedule`
+    Command(CommandSender, String),
+    /// Run a task of a plugin
+    Plugin(Arc<dyn PluginTask>),
+}
+
+/// A block that a player is breaking
+struct BlockBreaking {
+    block: BlockPos,
+    /// The block that was there when the player started breaking it
+    block_id: BlockId,
+    start: Instant,
+    /// Time needed to break the block, in seconds
+    break_time: f64,
+    /// Last progress sent to the players, in `BREAKING_STAGES` steps
+    sent_stage: u32,
+}
 
 /// The data that the server stores for every player.
 pub struct PlayerData {
-    loaded_chunks: HashMap<ChunkPos, u64>,
+    /// The chunks sent to the player
+    sent_chunks: SentChunks,
     render_distance: RenderDistance,
     close_chunks: CloseChunks,
     block_to_place: BlockId,
+    /// Item put in the item frames
+    // TODO: take it from the inventory
+    item_to_place: ItemId,
+    display_name: String,
+    /// Where the player respawns, set by using a bed. The world spawn is used if it's `None`.
+    spawn_point: Option<Vector3<f64>>,
+    /// `true` if the player is sleeping in a bed
+    sleeping: bool,
+    gamemode: GameMode,
+    /// The block that the player is breaking
+    breaking: Option<BlockBreaking>,
+    /// When the player last placed a block
+    last_place: Option<Instant>,
+    /// When the player last broke a block
+    last_break: Option<Instant>,
+    /// The physics state that was already sent to the player
+    physics_interest: PlayerInterest,
+    /// `true` if the player hosts the world. It becomes an operator if the world doesn't have any role yet.
+    host: bool,
+    inventory: Inventory,
+    /// The inventory that was last sent to the player
+    sent_inventory: Option<Inventory>,
+    /// The id of the last slot action of the player that was processed, and of the last one that was acknowledged
+    last_slot_action: u32,
+    sent_slot_action: u32,
+    /// Health in half hearts, the player is dead if it's 0
+    health: u32,
+    /// The health that was last sent to the player
+    sent_health: Option<u32>,
+    /// `true` once the player logged in, then `display_name` is its username and its saved state was restored.
+    /// The player is only saved after that, so that a fresh state never overwrites its save.
+    logged_in: bool,
+    /// When the player connected, to disconnect it if it doesn't log in
+    connected_at: Instant,
 }
 
 impl Default for PlayerData {
@@ -52,225 +149,763 @@ impl Default for PlayerData {
         let render_distance = Default::default();
         let close_chunks = CloseChunks::new(&render_distance);
         Self {
-            loaded_chunks: Default::default(),
+            sent_chunks: Default::default(),
             render_distance,
             close_chunks,
             block_to_place: 1,
+            item_to_place: 0,
+            display_name: "Player".to_owned(),
+            spawn_point: None,
+            sleeping: false,
+            gamemode: GameMode::default(),
+            breaking: None,
+            last_place: None,
+            last_break: None,
+            physics_interest: PlayerInterest::default(),
+            host: false,
+            inventory: Inventory::new(PLAYER_INVENTORY_SIZE),
+            sent_inventory: None,
+            last_slot_action: 0,
+            sent_slot_action: 0,
+            health: MAX_HEALTH,
+            sent_health: None,
+            logged_in: false,
+            connected_at: Instant::now(),
         }
     }
 }
 
-/// Start a new server instance.
-pub fn launch_server(mut server: Box<dyn Server>) -> Result<()> {
-    info!("Starting server");
+/// Time remaining before a cooldown of `cooldown` seconds since `last` ends, in seconds.
+/// The cooldown is shortened by `COOLDOWN_TOLERANCE`, so that the messages delayed by the network are accepted.
+fn remaining_cooldown(last: Option<Instant>, cooldown: f64) -> f64 {
+    last.map_or(0.0, |last| cooldown - COOLDOWN_TOLERANCE - last.elapsed().as_secs_f64())
+        .max(0.0)
+}
+
+/// Save the position, the orientation, the inventory and the selected block of a player, by display name
+fn save_player(
+    world_metadata: &WorldMetadata,
+    game_data: &Data,
+    physics_simulation: &ServerPhysicsSimulation,
+    id: PlayerId,
+    data: &PlayerData,
+) {
+    if !data.logged_in {
+        return;
+    }
+    let state = physics_simulation.get_state();
+    let (physics_player, input) = match (state.physics_state.players.get(&id), physics_simulation.get_player_input(id)) {
+        (Some(physics_player), Some(input)) => (physics_player, input),
+        _ => return,
+    };
+    let (position, velocity) = (physics_player.aabb.pos, physics_player.velocity);
+    let saved_player = SavedPlayer {
+        position: [position.x, position.y, position.z],
+        yaw: input.yaw,
+        pitch: input.pitch,
+        spawn_point: data.spawn_point.map(|pos| [pos.x, pos.y, pos.z]),
+        inventory: SavedPlayer::save_inventory(&data.inventory, game_data),
+        gamemode: Some(data.gamemode),
+        health: Some(data.health),
+        velocity: Some([velocity.x, velocity.y, velocity.z]),
+        selected_block: game_data.blocks.get_name_by_id(data.block_to_place as u32).cloned(),
+        selected_item: game_data.items.get_name_by_id(data.item_to_place).cloned(),
+    };
+    if let Err(e) = save::save_player(world_metadata, &data.display_name, &saved_player) {
+        warn!("Failed to save player {} ({:?})", data.display_name, e);
+    }
+}
+
+/// Restore the saved state of a player, if it has one
+fn restore_player(
+    server: &mut dyn Server,
+    world_metadata: &WorldMetadata,
+    game_data: &Data,
+    physics_simulation: &mut ServerPhysicsSimulation,
+    id: PlayerId,
+    data: &mut PlayerData,
+) {
+    data.logged_in = true;
+    match save::load_player(world_metadata, &data.display_name) {
+        Ok(Some(saved_player)) => {
+            info!("Restoring player {}", data.display_name);
+            teleport_player(server, physics_simulation, id, saved_player.position.into());
+            if let Some(velocity) = saved_player.velocity {
+                physics_simulation.set_player_velocity(id, velocity.into());
+            }
+            data.spawn_point = saved_player.spawn_point.map(Vector3::from);
+            data.inventory = saved_player.load_inventory(game_data);
+            server.send(id, ToClient::SetYawPitch(saved_player.yaw, saved_player.pitch), MessageDelivery::Ordered);
+            if let Some(gamemode) = saved_player.gamemode {
+                data.gamemode = gamemode;
+                physics_simulation.set_player_hidden(id, gamemode.is_hidden());
+                server.send(id, ToClient::GameMode(gamemode), MessageDelivery::Ordered);
+            }
+            if let Some(health) = saved_player.health {
+                data.health = health.min(MAX_HEALTH);
+            }
+            // The blocks and the items that don't exist anymore keep the default selection
+            if let Some(block) = saved_player.selected_block.and_then(|name| game_data.blocks.get_id_by_name(&name)) {
+                data.block_to_place = block as BlockId;
+            }
+            if let Some(item) = saved_player.selected_item.and_then(|name| game_data.items.get_id_by_name(&name)) {
+                data.item_to_place = item;
+            }
+        }
+        Ok(None) => {}
+        Err(e) => warn!("Failed to load player {} ({:?})", data.display_name, e),
+    }
+}
+
+/// The input of a dead player: it doesn't move and doesn't fall
+fn dead_player_input(input: PlayerInput) -> PlayerInput {
+    PlayerInput { yaw: input.yaw, pitch: input.pitch, ..PlayerInput::default() }
+}
+
+/// Remove some health from a player. It stops moving if it dies.
+fn damage_player(
+    physics_simulation: &mut ServerPhysicsSimulation,
+    id: PlayerId,
+    data: &mut PlayerData,
+    damage: u32,
+    cause: &str,
+) {
+    if data.health == 0 {
+        return;
+    }
+    data.health = data.health.saturating_sub(damage);
+    if data.health == 0 {
+        info!("{} {}", data.display_name, cause);
+        data.sleeping = false;
+        if let Some(&input) = physics_simulation.get_player_input(id) {
+            physics_simulation.set_player_input(id, dead_player_input(input));
+        }
+    }
+}
+
+/// Check if the role of a player allows `permission`
+fn has_permission(
+    players: &HashMap<PlayerId, PlayerData>,
+    permissions: &PermissionsConfig,
+    player: PlayerId,
+    permission: &str,
+) -> bool {
+    permissions.allows(&players[&player].display_name, permission)
+}
+
+/// Position of a player standing in `block`, centered horizontally
+fn standing_position(block: BlockPos) -> Vector3<f64> {
+    let player_size = PhysicsPlayer::default().aabb;
+    Vector3::new(
+        block.px as f64 + 0.5 - player_size.size_x / 2.0,
+        block.py as f64,
+        block.pz as f64 + 0.5 - player_size.size_z / 2.0,
+    )
+}
+
+/// Teleport a player, and tell its client so that it doesn't predict its movement across the teleport
+pub(crate) fn teleport_player(
+    server: &mut dyn Server,
+    physics_simulation: &mut ServerPhysicsSimulation,
+    id: PlayerId,
+    position: Vector3<f64>,
+) {
+    physics_simulation.teleport_player(id, position);
+    let teleports = physics_simulation.get_teleports(id);
+    server.send(id, ToClient::Teleport(position, teleports), MessageDelivery::Ordered);
+}
+
+/// Send the number of sleeping players to every player
+fn send_sleeping_players(server: &mut dyn Server, players: &HashMap<PlayerId, PlayerData>) {
+    let sleeping = players.values().filter(|player| player.sleeping).count();
+    for (&player, _) in players.iter() {
+        server.send(player, ToClient::SleepingPlayers(sleeping, players.len()), MessageDelivery::Ordered);
+    }
+}
+
+/// The state of a running server, shared by the main loop and the message handlers
+struct ServerState {
+    server: Box<dyn Server>,
+    world_metadata: WorldMetadata,
+    game_data: Data,
+    server_config: ServerConfig,
+    permissions: PermissionsConfig,
+    block_behaviors: BlockBehaviors,
+    world: World,
+    world_spawn: Vector3<f64>,
+    dimension: Dimension,
+    players: HashMap<PlayerId, PlayerData>,
+    physics_simulation: ServerPhysicsSimulation,
+    chunk_tickets: ChunkTickets,
+    time_of_day: TimeOfDay,
+    time_of_year: TimeOfYear,
+    weather: Weather,
+    scheduler: Scheduler<ServerTask>,
+    /// `true` once a player or the console asked to stop the server
+    stop_requested: bool,
+}
+
+impl ServerState {
+    /// The part of the state that the commands can access
+    fn command_context(&mut self) -> CommandContext<'_> {
+        CommandContext {
+            players: &mut self.players,
+            game_data: &self.game_data,
+            server: &mut *self.server,
+            physics_simulation: &mut self.physics_simulation,
+            world_spawn: self.world_spawn,
+            world_metadata: &self.world_metadata,
+            permissions: &mut self.permissions,
+            scheduler: &mut self.scheduler,
+        }
+    }
+
+    /// A state without any data and with an empty world, whose spawn is far away from the origin
+    #[cfg(test)]
+    fn for_tests(server: Box<dyn Server>, world_metadata: WorldMetadata) -> Self {
+        let game_data = Data {
+            blocks: Default::default(),
+            meshes: Vec::new(),
+            texture_atlas: image::ImageBuffer::new(1, 1),
+            models: Default::default(),
+            items: Default::default(),
+            item_meshes: Vec::new(),
+            sounds: Default::default(),
+            structures: Default::default(),
+            recipes: Default::default(),
+            dimensions: Default::default(),
+            physics: Default::default(),
+        };
+        let world = World::new(
+            Default::default(),
+            Default::default(),
+            Box::new(world::EmptyGenerator),
+            world_metadata.clone(),
+            true,
+        )
+        .unwrap();
+        Self {
+            server,
+            world_metadata,
+            block_behaviors: BlockBehaviors::new(&game_data),
+            physics_simulation: ServerPhysicsSimulation::new(game_data.physics),
+            game_data,
+            server_config: ServerConfig::default(),
+            permissions: PermissionsConfig::default(),
+            world,
+            world_spawn: Vector3::new(1000.0, 0.0, 1000.0),
+            dimension: Dimension::default(),
+            players: HashMap::new(),
+            chunk_tickets: ChunkTickets::default(),
+            time_of_day: TimeOfDay(0.0),
+            time_of_year: TimeOfYear(0.0),
+            weather: Weather::default(),
+            scheduler: Scheduler::new(),
+            stop_requested: false,
+        }
+    }
+
+    /// Reload the data and the config of the world, and send them to the players
+    fn reload_data(&mut self, plugins: &ServerPlugins) {
+        info!("Data directory changed, reloading data");
+        match load_data(DATA_FOLDER.into(), self.game_data.blocks.get_names()) {
+            Ok(new_game_data) => {
+                self.game_data = new_game_data;
+                // The physics constants of the world can be edited too
+                match save::load_physics_config(&self.world_metadata) {
+                    Ok(physics) => self.game_data.physics = physics,
+                    Err(e) => warn!("Failed to reload physics config ({:?})", e),
+                }
+                self.physics_simulation.set_config(self.game_data.physics);
+                self.world.set_block_light(&self.game_data.blocks);
+                // The new chunks must use the ids of the new block registry
+                let world_generator = create_world_generator(plugins, &self.game_data, self.world_metadata.seed);
+                self.world.set_world_generator(self.game_data.blocks.clone(), world_generator);
+                self.block_behaviors = BlockBehaviors::new(&self.game_data);
+                match save::load_server_config(&self.world_metadata) {
+                    Ok(mut config) => {
+                        // The console and the remote admin keep the settings they were started with
+                        if config.console != self.server_config.console || config.remote_admin != self.server_config.remote_admin {
+                            warn!("The console and remote admin settings only change when the server is restarted");
+                            config.console = self.server_config.console;
+                            config.remote_admin = self.server_config.remote_admin.clone();
+                        }
+                        if config.interaction != self.server_config.interaction {
+                            for &id in self.players.keys() {
+                                let message = ToClient::InteractionConfig(config.interaction);
+                                self.server.send(id, message, MessageDelivery::Ordered);
+                            }
+                        }
+                        self.server_config = config;
+                    }
+                    Err(e) => warn!("Failed to reload server config ({:?})", e),
+                }
+                match save::load_permissions(&self.world_metadata) {
+                    Ok(new_permissions) => self.permissions = new_permissions,
+                    Err(e) => warn!("Failed to reload permissions ({:?})", e),
+                }
+                if let Err(e) =
+                    save::save_block_palette(&self.world_metadata, self.game_data.blocks.get_names())
+                {
+                    warn!("Failed to save block palette ({:?})", e);
+                }
+                for (&player, _) in self.players.iter() {
+                    self.server.send(player, ToClient::GameData(Box::new(self.game_data.clone())), MessageDelivery::Ordered);
+                }
+                // The dimension of the world or its profile may have changed
+                let new_dimension = self.server_config.dimension(&self.game_data);
+                if new_dimension != self.dimension {
+                    self.dimension = new_dimension;
+                    self.world.set_sunlight(self.dimension.sunlight);
+                    for (&player, _) in self.players.iter() {
+                        self.server.send(player, ToClient::Dimension(self.dimension), MessageDelivery::Ordered);
+                    }
+                }
+            }
+            Err(e) => warn!("Failed to reload data ({:?})", e),
+        }
+    }
+}
+
+/// The world generator of the blocks of `game_data`, with the hooks of the plugins
+fn create_world_generator(plugins: &ServerPlugins, game_data: &Data, seed: i32) -> Box<dyn WorldGenerator> {
+    plugins.hook_world_generator(
+        Box::new(DefaultWorldGenerator::new(&game_data.blocks, &game_data.structures, seed)),
+        seed,
+    )
+}
+
+/// Start a new server instance for the given world.
+pub fn launch_server(server: Box<dyn Server>, world_metadata: WorldMetadata) -> Result<()> {
+    launch_server_with_plugins(server, world_metadata, ServerPlugins::default())
+}
+
+/// Start a new server instance for the given world, with the extensions of the plugins.
+pub fn launch_server_with_plugins(
+    server: Box<dyn Server>,
+    mut world_metadata: WorldMetadata,
+    plugins: ServerPlugins,
+) -> Result<()> {
+    info!("Starting server for world {}", world_metadata.name);
+    save::migrate_world(&mut world_metadata)?;
 
     let mut server_timing = BreakdownCounter::new();
 
     // Load data
-    let game_data = load_data("data".into())?;
+    // The block ids are stored with the world so that they don't change when the data changes
+    let block_palette = save::load_block_palette(&world_metadata)?;
+    let mut game_data = load_data(DATA_FOLDER.into(), &block_palette)?;
+    save::save_block_palette(&world_metadata, game_data.blocks.get_names())?;
+    game_data.physics = save::load_physics_config(&world_metadata)?;
+    let server_config = save::load_server_config(&world_metadata)?;
+    let permissions = save::load_permissions(&world_metadata)?;
+    let mut data_watcher = DataWatcher::new(DATA_FOLDER.into());
+    let admin_console = AdminConsole::start(server_config.console, server_config.remote_admin.as_ref());
 
-    let mut world = World::new(
+    let world_generator = create_world_generator(&plugins, &game_data, world_metadata.seed);
+    let mut world_state = save::load_world_state(&world_metadata)?;
+    let world_spawn = match world_state.spawn_point {
+        Some(spawn_point) => Vector3::from(spawn_point),
+        None => {
+            let spawn_point = standing_position(world_generator.spawn_point());
+            info!("World spawn set to {:?}", spawn_point);
+            world_state.spawn_point = Some([spawn_point.x, spawn_point.y, spawn_point.z]);
+            save::save_world_state(&world_metadata, &world_state)?;
+            spawn_point
+        }
+    };
+    let dimension = server_config.dimension(&game_data);
+    let world = World::new(
         game_data.blocks.clone(),
-        Box::new(DefaultWorldGenerator::new(&game_data.blocks.clone())),
+        game_data.items.clone(),
+        world_generator,
+        world_metadata.clone(),
+        dimension.sunlight,
+    )?;
+    let mut chunk_tickets = ChunkTickets::default();
+    chunk_tickets.set(
+        TicketSource::Spawn,
+        ChunkTicket::around(
+            BlockPos::from(world_spawn).containing_chunk_pos(),
+            SPAWN_TICKET_RADIUS,
+        ),
     );
-    let mut players = HashMap::new();
-    let mut physics_simulation = ServerPhysicsSimulation::new();
+    let mut state = ServerState {
+        server,
+        dimension,
+        block_behaviors: BlockBehaviors::new(&game_data),
+        physics_simulation: ServerPhysicsSimulation::new(game_data.physics),
+        world_metadata,
+        game_data,
+        server_config,
+        permissions,
+        world,
+        world_spawn,
+        players: HashMap::new(),
+        chunk_tickets,
+        time_of_day: TimeOfDay(world_state.time_of_day),
+        time_of_year: TimeOfYear(world_state.time_of_year),
+        // TODO: change the weather over time
+        weather: Weather::default(),
+        scheduler: Scheduler::new(),
+        stop_requested: false,
+    };
+    let mut block_updates = BlockUpdates::default();
     let mut close_chunks_merged = Vec::new();
+    state.scheduler.schedule_repeating(
+        Instant::now(),
+        Duration::from_secs(60),
+        Duration::from_secs(60),
+        ServerTask::LogStatistics,
+    );
+    state.scheduler.schedule_repeating(
+        Instant::now(),
+        Duration::from_secs(10),
+        Duration::from_secs(10),
+        ServerTask::SyncTimeOfDay,
+    );
+    plugins.schedule_tasks(&mut state.scheduler, Instant::now());
+    let mut last_time_update = Instant::now();
+
+    // Number of ticks since the server started
+    let mut tick: u64 = 0;
 
     info!("Server initialized successfully! Starting server loop");
     loop {
         server_timing.start_frame();
+        tick += 1;
 
-        // Handle messages
-        loop {
-            match server.receive_event() {
-                ServerEvent::NoEvent => break,
-                ServerEvent::ClientConnected(id) => {
-                    info!("Client connected to the server!");
-                    physics_simulation.set_player_input(id, Default::default());
-                    players.insert(id, PlayerData::default());
-                    server.send(id, ToClient::GameData(game_data.clone()));
-                    server.send(id, ToClient::CurrentId(id));
-                }
-                ServerEvent::ClientDisconnected(id) => {
-                    physics_simulation.remove(id);
-                    players.remove(&id);
+        handlers::handle_events(&mut state);
+        for (&id, data) in state.players.iter() {
+            if !data.logged_in && data.connected_at.elapsed() > LOGIN_TIMEOUT {
+                state.server.disconnect(id, "The client didn't log in".to_owned());
+            }
+        }
+        server_timing.record_part("Network events");
+
+        // Execute the commands of the console and the remote admins
+        while let Some(request) = admin_console.try_recv() {
+            let result = Command::parse(&request.line)
+                .and_then(|command| command.execute(CommandSender::Console, state.command_context()));
+            let output = match result {
+                Ok(output) => {
+                    info!("The console executed {}", request.line.trim());
+                    output
                 }
-                ServerEvent::ClientMessage(id, message) => match message {
-                    ToServer::UpdateInput(input) => {
-                        assert!(players.contains_key(&id));
-                        physics_simulation.set_player_input(id, input);
-                    }
-                    ToServer::SetRenderDistance(render_distance) => {
-                        assert!(players.contains_key(&id));
-                        players.entry(id).and_modify(move |player_data| {
-                            player_data.render_distance = render_distance
-                        });
-                    }
-                    ToServer::BreakBlock(player_pos, yaw, pitch) => {
-                        // TODO: check player pos and block
-                        let physics_player = PhysicsPlayer {
-                            aabb: AABB {
-                                pos: player_pos,
-                                size_x: 0.0,
-                                size_y: 0.0,
-                                size_z: 0.0,
-                            },
-                            velocity: Vector3::zeros(),
-                        };
-                        let y = yaw.to_radians();
-                        let p = pitch.to_radians();
-                        let dir = Vector3::new(-y.sin() * p.cos(), p.sin(), -y.cos() * p.cos());
-                        // TODO: don't hardcode max dist
-                        if let Some((block, _face)) =
-                            physics_player.get_pointed_at(dir, 10.0, &world)
-                        {
-                            let chunk_pos = block.containing_chunk_pos();
-                            if let Some(chunk) = world.get_chunk(chunk_pos) {
-                                let mut new_chunk = (*chunk).clone();
-                                new_chunk.set_block_at(block.pos_in_containing_chunk(), 0);
-                                world.set_chunk(Arc::new(new_chunk));
-                            }
-                        }
-                    }
-                    ToServer::SelectBlock(player_pos, yaw, pitch) => {
-                        // TODO: check player pos and block
-                        let physics_player = PhysicsPlayer {
-                            aabb: AABB {
-                                pos: player_pos,
-                                size_x: 0.0,
-                                size_y: 0.0,
-                                size_z: 0.0,
-                            },
-                            velocity: Vector3::zeros(),
-                        };
-                        let y = yaw.to_radians();
-                        let p = pitch.to_radians();
-                        let dir = Vector3::new(-y.sin() * p.cos(), p.sin(), -y.cos() * p.cos());
-                        // TODO: don't hardcode max dist
-                        if let Some((block, _face)) =
-                            physics_player.get_pointed_at(dir, 10.0, &world)
-                        {
-                            // TODO: careful with more complicated blocks
-                            players.get_mut(&id).unwrap().block_to_place = world.get_block(block);
-                        }
+                Err(error) => error,
+            };
+            // The console or the connection may be gone
+            let _ = request.reply.send(output);
+        }
+
+        // Save everything and stop
+        if state.stop_requested {
+            info!("Saving world {}", state.world_metadata.name);
+            for (&id, data) in state.players.iter() {
+                save_player(&state.world_metadata, &state.game_data, &state.physics_simulation, id, data);
+            }
+            state.world.save_modified_chunks();
+            world_state.time_of_day = state.time_of_day.0;
+            world_state.time_of_year = state.time_of_year.0;
+            save::save_world_state(&state.world_metadata, &world_state)?;
+            info!("Server stopped");
+            return Ok(());
+        }
+
+        // Reload the data if it was modified
+        if data_watcher.poll() {
+            state.reload_data(&plugins);
+        }
+        server_timing.record_part("Reload data");
+
+        // Run scheduled tasks
+        for task in state.scheduler.poll(Instant::now()) {
+            match task {
+                ServerTask::LogStatistics => info!(
+                    "{} players connected, {} chunks loaded",
+                    state.players.len(),
+                    state.world.num_loaded_chunks(),
+                ),
+                ServerTask::SyncTimeOfDay => {
+                    // The season changes slowly enough to be sent with the time of the day
+                    let season_state = state.server_config.season_state(state.time_of_year);
+                    for (&player, _) in state.players.iter() {
+                        state.server.send(player, ToClient::TimeOfDay(state.time_of_day), MessageDelivery::Ordered);
+                        state.server.send(player, ToClient::Season(season_state), MessageDelivery::Ordered);
                     }
-                    ToServer::PlaceBlock(player_pos, yaw, pitch) => {
-                        // TODO: check player pos and block
-                        let physics_player = PhysicsPlayer {
-                            aabb: AABB {
-                                pos: player_pos,
-                                size_x: 0.0,
-                                size_y: 0.0,
-                                size_z: 0.0,
-                            },
-                            velocity: Vector3::zeros(),
-                        };
-                        let y = yaw.to_radians();
-                        let p = pitch.to_radians();
-                        let dir = Vector3::new(-y.sin() * p.cos(), p.sin(), -y.cos() * p.cos());
-                        // TODO: don't hardcode max dist
-                        if let Some((mut block, face)) =
-                        physics_player.get_pointed_at(dir, 10.0, &world)
-                        {
-                            block.px += D[face][0];
-                            block.py += D[face][1];
-                            block.pz += D[face][2];
-                            let chunk_pos = block.containing_chunk_pos();
-                            if let Some(chunk) = world.get_chunk(chunk_pos) {
-                                let mut new_chunk = (*chunk).clone();
-                                new_chunk.set_block_at(block.pos_in_containing_chunk(), players.get(&id).unwrap().block_to_place);
-                                world.set_chunk(Arc::new(new_chunk));
-                            }
+                }
+                ServerTask::Command(sender, line) => {
+                    let result =
+                        Command::parse(&line).and_then(|command| command.execute(sender, state.command_context()));
+                    let feedback = result.unwrap_or_else(|error| error);
+                    info!("Scheduled command {} of {:?}: {}", line, sender, feedback);
+                    if let CommandSender::Player(id) = sender {
+                        if state.players.contains_key(&id) {
+                            state.server.send(id, ToClient::CommandFeedback(feedback), MessageDelivery::Ordered);
                         }
                     }
-                },
+                }
+                ServerTask::Plugin(task) => task.run(state.command_context()),
             }
         }
-        server_timing.record_part("Network events");
+        server_timing.record_part("Run scheduled tasks");
 
         // Receive generated chunks
-        world.get_new_generated_chunks();
+        state.world.get_new_generated_chunks();
         server_timing.record_part("Receive generated chunks");
 
         // Receive lighted chunks
-        world.get_new_light_chunks();
+        state.world.get_new_light_chunks();
         server_timing.record_part("Receive lighted chunks");
 
         // Tick game
-        physics_simulation.step_simulation(Instant::now(), &world);
+        let physics_results = state.physics_simulation.step_simulation(Instant::now(), &state.world);
+        for (id, damage) in physics_results.fall_damage {
+            if let Some(player_data) = state.players.get_mut(&id) {
+                if player_data.gamemode.takes_damage() {
+                    damage_player(&mut state.physics_simulation, id, player_data, damage, "hit the ground too hard");
+                }
+            }
+        }
+        // The players that fell out of the world die in the void
+        let fallen_players = state.physics_simulation
+            .get_state()
+            .physics_state
+            .players
+            .iter()
+            .filter(|(_, player)| player.aabb.pos.y < VOID_HEIGHT)
+            .map(|(&id, _)| id)
+            .collect::<Vec<_>>();
+        for id in fallen_players {
+            if let Some(player_data) = state.players.get_mut(&id) {
+                damage_player(&mut state.physics_simulation, id, player_data, MAX_HEALTH, "fell out of the world");
+            }
+        }
         server_timing.record_part("Update physics");
 
+        // Tick the chunks, less often far from the players
+        let player_chunks = state.physics_simulation
+            .get_state()
+            .physics_state
+            .players
+            .values()
+            .map(|player| BlockPos::from(player.aabb.pos).containing_chunk_pos())
+            .collect();
+        let tick_regions = TickRegions::new(&state.server_config.tick_regions, player_chunks);
+        let (mut ticked_chunks, mut frozen_chunks, mut random_ticks) = (0, 0, 0);
+        let now = Instant::now();
+        for pos in state.world.loaded_chunks().collect::<Vec<_>>() {
+            match tick_regions.ticks(pos, tick) {
+                // TODO: run `interval` ticks worth of fluids and mob AI in the chunk
+                Some(interval) => {
+                    ticked_chunks += 1;
+                    let count = state.server_config.random_ticks_per_chunk * interval;
+                    random_ticks +=
+                        block_updates.random_ticks(&mut state.world, &state.block_behaviors, now, pos, count);
+                }
+                None if tick_regions.interval(pos).is_none() => frozen_chunks += 1,
+                None => {}
+            }
+        }
+        send_debug_info(
+            "Chunks",
+            "tickregions",
+            format!(
+                "Ticked chunks = {}\nFrozen chunks = {}\nRandom ticks = {}",
+                ticked_chunks, frozen_chunks, random_ticks
+            ),
+        );
+        server_timing.record_part("Tick chunks");
+
+        // Advance the blocks that the players are breaking
+        handlers::update_block_breaking(&mut state);
+        server_timing.record_part("Update block breaking");
+
+        // Let the blocks react to the changes of the world
+        let processed_updates = block_updates.process(
+            &mut state.world,
+            &state.block_behaviors,
+            Instant::now(),
+            state.server_config.tuning.block_updates_per_tick,
+        );
+        send_debug_info(
+            "Chunks",
+            "blockupdates",
+            format!(
+                "Block updates = {}\nWaiting block updates = {}",
+                processed_updates,
+                block_updates.num_waiting(),
+            ),
+        );
+        server_timing.record_part("Update blocks");
+
+        // Update the time of the day and of the year, skipping the night if all the players are sleeping
+        let now = Instant::now();
+        state.time_of_day.advance((now - last_time_update).as_secs_f64());
+        if let Some(cycle) = &state.server_config.seasons {
+            state.time_of_year.advance((now - last_time_update).as_secs_f64(), cycle);
+        }
+        last_time_update = now;
+        let all_sleeping = !state.players.is_empty() && state.players.values().all(|player| player.sleeping);
+        if state.time_of_day.is_night() && all_sleeping {
+            info!("All players are sleeping, skipping the night");
+            state.time_of_day = TimeOfDay::next_morning();
+            for (&player, _) in state.players.iter() {
+                state.server.send(player, ToClient::TimeOfDay(state.time_of_day), MessageDelivery::Ordered);
+            }
+        }
+        // Wake everyone up in the morning
+        if !state.time_of_day.is_night() && state.players.values().any(|player| player.sleeping) {
+            for player_data in state.players.values_mut() {
+                player_data.sleeping = false;
+            }
+            send_sleeping_players(&mut *state.server, &state.players);
+        }
+        server_timing.record_part("Update time of day");
+
+        // Keep the spectators close to the players they follow
+        let physics_players = &state.physics_simulation.get_state().physics_state.players;
+        let follow_teleports = state.players
+            .iter()
+            .filter_map(|(&id, data)| {
+                let spectator = physics_players.get(&id)?;
+                let target = physics_players.get(&data.physics_interest.followed_player()?)?;
+                if (spectator.aabb.pos - target.aabb.pos).norm() > FOLLOW_TELEPORT_DISTANCE {
+                    Some((id, target.aabb.pos))
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        for (id, position) in follow_teleports {
+            teleport_player(&mut *state.server, &mut state.physics_simulation, id, position);
+        }
+
         // Send physics updates to players
-        for (&player, _) in players.iter() {
-            server.send(
+        for (&player, data) in state.players.iter_mut() {
+            let update = state.physics_simulation.get_update_for_player(
                 player,
-                ToClient::UpdatePhysics((*physics_simulation.get_state()).clone()),
+                data.render_distance,
+                &mut data.physics_interest,
             );
+            state.server.send(player, ToClient::UpdatePhysics(update), MessageDelivery::UnreliableSequenced);
         }
         server_timing.record_part("Send physics updates to players");
 
+        // Send the inventories that changed, and acknowledge the slot actions
+        for (&player, data) in state.players.iter_mut() {
+            if data.sent_inventory.as_ref() != Some(&data.inventory) || data.sent_slot_action != data.last_slot_action {
+                let message = ToClient::Inventory(data.inventory.clone(), data.last_slot_action);
+                state.server.send(player, message, MessageDelivery::Ordered);
+                data.sent_inventory = Some(data.inventory.clone());
+                data.sent_slot_action = data.last_slot_action;
+            }
+        }
+        server_timing.record_part("Send inventories");
+
+        // Send the health that changed
+        for (&player, data) in state.players.iter_mut() {
+            if data.sent_health != Some(data.health) {
+                state.server.send(player, ToClient::UpdateHealth(data.health), MessageDelivery::Ordered);
+                data.sent_health = Some(data.health);
+            }
+        }
+        server_timing.record_part("Send health");
+
         // Send chunks to players
-        let mut player_positions = Vec::new();
-        for (player, data) in players.iter_mut() {
-            let player_pos = BlockPos::from(physics_simulation
-                .get_state()
-                .physics_state
-                .players
-                .get(player)
-                .unwrap()
-                .get_camera_position()
-            );
+        for (player, data) in state.players.iter_mut() {
+            let player_pos = match state.physics_simulation.get_state().physics_state.players.get(player) {
+                Some(physics_player) => BlockPos::from(physics_player.get_camera_position()),
+                None => continue,
+            };
             let player_chunk = player_pos.containing_chunk_pos();
-            player_positions.push((player_chunk, data.render_distance));
+            state.chunk_tickets.set(
+                TicketSource::Player(*player),
+                ChunkTicket {
+                    center: player_chunk,
+                    range: data.render_distance,
+                },
+            );
             // Send new chunks
-            let updates = world.send_chunks_to_player(player_chunk, data);
-            for (chunk, light_chunk) in updates {
-                server.send(*player, ToClient::Chunk(chunk, light_chunk));
+            let chunks_per_tick = state.server_config.tuning.chunks_sent_per_tick;
+            let updates = state.world.send_chunks_to_player(player_chunk, data, chunks_per_tick);
+            for update in updates {
+                let message = match update {
+                    ChunkUpdate::Chunk(chunk, light_chunk, block_entities) => ToClient::Chunk(chunk, light_chunk, block_entities),
+                    ChunkUpdate::Light(light_chunk) => ToClient::LightChunk(light_chunk),
+                };
+                state.server.send(*player, message, MessageDelivery::ReliableUnordered);
             }
             // Drop chunks that are too far away
             let render_distance = data.render_distance;
-            data.loaded_chunks
-                .retain(|chunk_pos, _| render_distance.is_chunk_visible(player_chunk, *chunk_pos));
+            data.sent_chunks
+                .retain(|chunk_pos| render_distance.is_chunk_visible(player_chunk, chunk_pos));
         }
         server_timing.record_part("Send chunks to players");
 
         // Compute close chunks
-        for (_, data) in players.iter_mut() {
+        for (_, data) in state.players.iter_mut() {
             data.close_chunks.update(&data.render_distance);
         }
-        let all_close_chunks = players
+        let all_close_chunks = state.players
             .iter()
-            .map(|(id, data)| {
-                let player = physics_simulation.get_state().physics_state.players.get(id).unwrap();
+            .filter_map(|(id, data)| {
+                let player = state.physics_simulation.get_state().physics_state.players.get(id)?;
                 let player_chunk = BlockPos::from(player.aabb.pos).containing_chunk_pos(); // TODO: have this in the physics state?
-                data.close_chunks.get_close_chunks().iter().map(|chunk_pos| CloseChunkPos::new(*chunk_pos, player_chunk)).collect::<Vec<_>>()
-            })
-            .collect::<Vec<_>>();
-        voxel_rs_common::collections::merge_arrays(&mut close_chunks_merged, &all_close_chunks[..]);
-        let close_chunks = close_chunks_merged.iter().map(|&ccp| ccp.pos).collect::<Vec<_>>();
+                Some(data.close_chunks
+                    .iter_with_distance(player_chunk)
+                    .map(|(square_dist, pos)| CloseChunkPos { square_dist, pos }))
+            });
+        // The players close to each other share chunks, which are only kept once
+        voxel_rs_common::collections::merge_sorted(&mut close_chunks_merged, all_close_chunks, |ccp| ccp.pos);
+        let mut close_chunks = close_chunks_merged.iter().map(|&ccp| ccp.pos).collect::<Vec<_>>();
+        // The chunks of the other tickets come after the chunks close to the players
+        close_chunks.extend(state.chunk_tickets.non_player_chunks());
         server_timing.record_part("Compute close chunks");
         
         // Update light
-        world.enqueue_chunks_for_lighting(&close_chunks);
+        state.world.enqueue_chunks_for_lighting(&close_chunks);
         server_timing.record_part("Send chunks to light worker");
 
         // Update worldgen
-        world.enqueue_chunks_for_worldgen(&close_chunks);
+        state.world.enqueue_chunks_for_worldgen(&close_chunks);
         server_timing.record_part("Send chunks to worldgen worker");
 
-        // Drop chunks that are far from all players
-        world.drop_far_chunks(&player_positions);
+        // Drop chunks that no ticket keeps loaded
+        state.world.drop_unticketed_chunks(&state.chunk_tickets);
         server_timing.record_part("Drop far chunks");
 
         send_debug_info("Chunks", "server",
                         format!(
                             "Server loaded chunks = {}\nServer loaded chunk columns = {}\n",
-                            world.num_loaded_chunks(),
-                            world.num_loaded_chunk_columns(),
+                            state.world.num_loaded_chunks(),
+                            state.world.num_loaded_chunk_columns(),
                         ));
+        let sent_chunks: Vec<String> = state.players
+            .values()
+            .map(|data| {
+                format!(
+                    "{}: {} chunks sent, {} in flight",
+                    data.display_name,
+                    data.sent_chunks.len(),
+                    data.sent_chunks.num_in_flight(),
+                )
+            })
+            .collect();
+        send_debug_info("Chunks", "sentchunks", sent_chunks.join("\n"));
+        let network_stats: Vec<String> = state.players
+            .iter()
+            .filter_map(|(&id, data)| {
+                state.server.network_stats(id).map(|stats| format!("{}: {}", data.display_name, stats))
+            })
+            .collect();
+        send_debug_info("Network", "server", match network_stats.len() {
+            0 => "No player connected over the network".to_owned(),
+            _ => network_stats.join("\n"),
+        });
 
         // Nothing else to do for now :-)
+        send_perf_sample("Server", "mainloop_graph", "Server main loop", server_timing.last_frame_millis());
         send_perf_breakdown("Server", "mainloop", "Server main loop", server_timing.extract_part_averages());
     }
 }
@@ -281,16 +916,6 @@ struct CloseChunkPos {
     pub pos: ChunkPos,
 }
 
-impl CloseChunkPos {
-    pub fn new(relative_pos: ChunkPos, reference_chunk: ChunkPos) -> Self {
-        let absolute_pos = relative_pos.offset_by_pos(reference_chunk);
-        Self {
-            square_dist: absolute_pos.squared_euclidian_distance(reference_chunk),
-            pos: absolute_pos,
-        }
-    }
-}
-
 impl PartialEq for CloseChunkPos {
     fn eq(&self, other: &CloseChunkPos) -> bool {
         self.square_dist == other.square_dist
@@ -0,0 +1,296 @@
+use voxel_rs_common::config::MAX_LIGHT_LEVEL;
+use voxel_rs_common::world::{Chunk, Direction, LightChunk, Neighborhood27, CHUNK_SIZE};
+use super::{BlockLight, HighestOpaqueBlock};
+use super::sunlight::{bloc_index, column_index, FastBFSQueue, BLOC_SIZE, CSIZE};
+use voxel_rs_common::collections::zero_initialized_vec;
+use std::sync::Arc;
+
+/// The buffers used by `update_light`, kept between the updates to avoid reallocating them
+pub struct IncrementalLightBuffers {
+    pub removal_queue: FastBFSQueue,
+    pub queue: FastBFSQueue,
+    pub light_data: Vec<u8>,
+    pub attenuation: Vec<u8>,
+}
+
+impl IncrementalLightBuffers {
+    pub fn new() -> Self {
+        Self {
+            removal_queue: FastBFSQueue::new(),
+            queue: FastBFSQueue::new(),
+            light_data: unsafe { zero_initialized_vec((CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE * 27) as usize) },
+            attenuation: unsafe { zero_initialized_vec((CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE * 27) as usize) },
+        }
+    }
+}
+
+/// Update the light of a 3x3x3 chunks bloc after some blocks changed, using a BFS to remove the old light
+/// and another one to propagate the new light. Only the blocks whose light can change are visited.
+/// `chunks` contains the new blocks, `light_chunks` the light before the change, and `changed_blocks`
+/// the positions in the bloc of the modified blocks and of the blocks that gained or lost sunlight.
+/// The changed blocks must be at least `MAX_LIGHT_LEVEL` blocks away from the border of the bloc.
+/// Return the new light chunks, reusing the old ones if their light didn't change.
+pub fn update_light(
+    chunks: &[Arc<Chunk>],
+    light_chunks: &[Arc<LightChunk>],
+    highest_opaque_blocks: &[Arc<HighestOpaqueBlock>],
+    block_light: &BlockLight,
+    changed_blocks: &[(usize, usize, usize)],
+    buffers: &mut IncrementalLightBuffers,
+) -> Vec<Arc<LightChunk>> {
+    let IncrementalLightBuffers { removal_queue, queue, light_data, attenuation } = buffers;
+    assert_eq!(chunks.len(), 27);
+    assert_eq!(light_chunks.len(), 27);
+    assert!(light_data.len() >= (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE * 27) as usize);
+    assert!(attenuation.len() >= (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE * 27) as usize);
+    removal_queue.clear();
+    queue.clear();
+
+    let bloc_size = BLOC_SIZE as isize;
+    let neighbours = |x: usize, y: usize, z: usize| {
+        Direction::iter().filter_map(move |dir| {
+            let (dx, dy, dz) = dir.offset();
+            let (nx, ny, nz) = (x as isize + dx as isize, y as isize + dy as isize, z as isize + dz as isize);
+            if 0 <= nx && nx < bloc_size && 0 <= ny && ny < bloc_size && 0 <= nz && nz < bloc_size {
+                Some((nx as usize, ny as usize, nz as usize))
+            } else {
+                None
+            }
+        })
+    };
+
+    // Copy the blocks and the old light of the bloc
+    for cx in 0..3 {
+        for cy in 0..3 {
+            for cz in 0..3 {
+                let chunk = &chunks[Neighborhood27::index(cx, cy, cz)];
+                let light_chunk = &light_chunks[Neighborhood27::index(cx, cy, cz)];
+                for i in 0..CSIZE {
+                    for j in 0..CSIZE {
+                        for k in 0..CSIZE {
+                            let s = bloc_index(cx * CSIZE + i, cy * CSIZE + j, cz * CSIZE + k);
+                            let pos = (i as u32, j as u32, k as u32);
+                            attenuation[s] = block_light.attenuation(chunk.get_block_at(pos));
+                            light_data[s] = light_chunk.get_light_at(pos);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    unsafe {
+        // Remove the light of the changed blocks, and the light that came from them
+        for &(x, y, z) in changed_blocks {
+            let s = bloc_index(x, y, z);
+            if light_data[s] > 0 {
+                removal_queue.push((x, y, z, light_data[s]));
+                light_data[s] = 0;
+            }
+        }
+        while !removal_queue.is_empty() {
+            let (x, y, z, ll) = *removal_queue.pop();
+            for (nx, ny, nz) in neighbours(x, y, z) {
+                let s = bloc_index(nx, ny, nz);
+                let neighbour_light = light_data[s];
+                if neighbour_light != 0 && neighbour_light < ll {
+                    light_data[s] = 0;
+                    removal_queue.push((nx, ny, nz, neighbour_light));
+                } else if neighbour_light >= ll {
+                    // This block has another light source, it will light the removed blocks again
+                    queue.push((nx, ny, nz, neighbour_light));
+                }
+            }
+        }
+
+        // Add the new sunlight, and let the neighbours of the changed blocks light them
+        let y0 = chunks[Neighborhood27::CENTER].pos.py - 1;
+        for &(x, y, z) in changed_blocks {
+            let s = bloc_index(x, y, z);
+            let hob = &highest_opaque_blocks[column_index(x / CSIZE, z / CSIZE)];
+            let hob_y = hob.y[HighestOpaqueBlock::index((x % CSIZE) as u32, (z % CSIZE) as u32)];
+            let world_y = y0 * CHUNK_SIZE as i64 + y as i64;
+            if attenuation[s] < MAX_LIGHT_LEVEL && world_y > hob_y {
+                light_data[s] = MAX_LIGHT_LEVEL;
+                queue.push((x, y, z, MAX_LIGHT_LEVEL));
+            }
+            for (nx, ny, nz) in neighbours(x, y, z) {
+                let neighbour_light = light_data[bloc_index(nx, ny, nz)];
+                if neighbour_light > 1 {
+                    queue.push((nx, ny, nz, neighbour_light));
+                }
+            }
+        }
+
+        // Propagate the light
+        while !queue.is_empty() {
+            let (x, y, z, ll) = *queue.pop();
+            if ll <= 1 {
+                continue;
+            }
+            for (nx, ny, nz) in neighbours(x, y, z) {
+                let s = bloc_index(nx, ny, nz);
+                let new_light = ll.saturating_sub(1 + attenuation[s]);
+                if light_data[s] < new_light {
+                    light_data[s] = new_light;
+                    queue.push((nx, ny, nz, new_light));
+                }
+            }
+        }
+    }
+
+    // Extract the light of every chunk
+    let mut result = Vec::with_capacity(27);
+    for cx in 0..3 {
+        for cy in 0..3 {
+            for cz in 0..3 {
+                let old_light_chunk = &light_chunks[Neighborhood27::index(cx, cy, cz)];
+                let mut light = Vec::with_capacity(CSIZE * CSIZE * CSIZE);
+                for i in 0..CSIZE {
+                    for j in 0..CSIZE {
+                        for k in 0..CSIZE {
+                            light.push(light_data[bloc_index(cx * CSIZE + i, cy * CSIZE + j, cz * CSIZE + k)]);
+                        }
+                    }
+                }
+                if light == old_light_chunk.light {
+                    result.push(old_light_chunk.clone());
+                } else {
+                    result.push(Arc::new(LightChunk {
+                        light,
+                        pos: old_light_chunk.pos,
+                    }));
+                }
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::sunlight::compute_light;
+    use voxel_rs_common::world::{BlockPos, ChunkPos};
+
+    const BLOC_VOLUME: usize = (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE * 27) as usize;
+
+    /// A 3x3x3 bloc with a floor at y = 40 and caves below it
+    fn create_chunks() -> Vec<Chunk> {
+        let mut chunks = Vec::new();
+        for cx in 0..3 {
+            for cy in 0..3 {
+                for cz in 0..3 {
+                    let mut chunk = Chunk::new(ChunkPos { px: cx, py: cy, pz: cz });
+                    for i in 0..CHUNK_SIZE {
+                        for j in 0..CHUNK_SIZE {
+                            for k in 0..CHUNK_SIZE {
+                                let y = cy * CHUNK_SIZE as i64 + j as i64;
+                                let cave = 20 < y && y < 36 && i % 7 != 0 && k % 5 != 0;
+                                if y <= 40 && !cave {
+                                    chunk.set_block_at((i, j, k), 1);
+                                }
+                            }
+                        }
+                    }
+                    chunks.push(chunk);
+                }
+            }
+        }
+        chunks
+    }
+
+    fn column_hob(chunks: &[Arc<Chunk>], cx: usize, cz: usize) -> Arc<HighestOpaqueBlock> {
+        let mut hob = HighestOpaqueBlock::new();
+        for cy in 0..3 {
+            hob.merge(&HighestOpaqueBlock::from_chunk(&chunks[Neighborhood27::index(cx, cy, cz)], &BlockLight::for_tests()));
+        }
+        Arc::new(hob)
+    }
+
+    /// Compute the light of a chunk of the bloc from scratch, the chunks outside of the bloc are not loaded
+    fn full_light(chunks: &[Arc<Chunk>], (cx, cy, cz): (usize, usize, usize)) -> Vec<u8> {
+        let in_bloc = |c: usize, d: usize| (c + d >= 1 && c + d <= 3).then(|| c + d - 1);
+        let mut window = Vec::new();
+        let mut hobs = Vec::new();
+        for i in 0..3 {
+            for k in 0..3 {
+                hobs.push(match (in_bloc(cx, i), in_bloc(cz, k)) {
+                    (Some(x), Some(z)) => column_hob(chunks, x, z),
+                    _ => Arc::new(HighestOpaqueBlock::new()),
+                });
+            }
+        }
+        for i in 0..3 {
+            for j in 0..3 {
+                for k in 0..3 {
+                    window.push(match (in_bloc(cx, i), in_bloc(cy, j), in_bloc(cz, k)) {
+                        (Some(x), Some(y), Some(z)) => Some(chunks[Neighborhood27::index(x, y, z)].clone()),
+                        _ => None,
+                    });
+                }
+            }
+        }
+        let mut light_data = unsafe { zero_initialized_vec(BLOC_VOLUME) };
+        let mut attenuation = unsafe { zero_initialized_vec(BLOC_VOLUME) };
+        compute_light(window, hobs, &BlockLight::for_tests(), &mut FastBFSQueue::new(), &mut light_data, &mut attenuation)
+            .light_level
+            .to_vec()
+    }
+
+    /// Set a block in the middle chunk and check that the incremental update matches the full computation
+    fn check_update(chunks: &mut [Arc<Chunk>], light_chunks: &mut Vec<Arc<LightChunk>>, pos: BlockPos, block: u16) {
+        let hob_index = HighestOpaqueBlock::index(pos.px as u32 % CHUNK_SIZE, pos.pz as u32 % CHUNK_SIZE);
+        let old_hob = column_hob(chunks, 1, 1).y[hob_index];
+        let mut middle_chunk = (*chunks[13]).clone();
+        middle_chunk.set_block_at(pos.pos_in_containing_chunk(), block);
+        chunks[13] = Arc::new(middle_chunk);
+        let hobs = (0..9).map(|i| column_hob(chunks, i / 3, i % 3)).collect::<Vec<_>>();
+        let new_hob = hobs[4].y[hob_index];
+
+        let (x, z) = (pos.px as usize, pos.pz as usize);
+        let mut changed_blocks = vec![(x, pos.py as usize, z)];
+        for y in (old_hob.min(new_hob) + 1)..=(old_hob.max(new_hob)) {
+            changed_blocks.push((x, y as usize, z));
+        }
+        *light_chunks = update_light(
+            chunks,
+            light_chunks,
+            &hobs,
+            &BlockLight::for_tests(),
+            &changed_blocks,
+            &mut IncrementalLightBuffers::new(),
+        );
+        assert!(light_chunks[13].light == full_light(chunks, (1, 1, 1)));
+    }
+
+    #[test]
+    fn test_incremental_light() {
+        let mut chunks: Vec<Arc<Chunk>> = create_chunks().into_iter().map(Arc::new).collect();
+        let mut light_chunks: Vec<Arc<LightChunk>> = chunks
+            .iter()
+            .map(|chunk| {
+                let c = (chunk.pos.px as usize, chunk.pos.py as usize, chunk.pos.pz as usize);
+                Arc::new(LightChunk {
+                    light: full_light(&chunks, c),
+                    pos: chunk.pos,
+                })
+            })
+            .collect();
+
+        // Place a block above the floor, break it, then dig into the floor
+        check_update(&mut chunks, &mut light_chunks, (48, 50, 48).into(), 1);
+        check_update(&mut chunks, &mut light_chunks, (48, 50, 48).into(), 0);
+        check_update(&mut chunks, &mut light_chunks, (50, 40, 50).into(), 0);
+        check_update(&mut chunks, &mut light_chunks, (50, 39, 50).into(), 0);
+        check_update(&mut chunks, &mut light_chunks, (50, 40, 50).into(), 1);
+        // Dig down to a cave
+        for y in (36..=40).rev() {
+            check_update(&mut chunks, &mut light_chunks, (51, y, 51).into(), 0);
+        }
+        // Cover the hole with a block that lets the light through, then remove it
+        check_update(&mut chunks, &mut light_chunks, (51, 41, 51).into(), 2);
+        check_update(&mut chunks, &mut light_chunks, (51, 38, 51).into(), 2);
+        check_update(&mut chunks, &mut light_chunks, (51, 41, 51).into(), 0);
+    }
+}
@@ -1,15 +1,70 @@
-use voxel_rs_common::world::{Chunk, CHUNK_SIZE};
+use voxel_rs_common::{
+    block::{Block, BlockId},
+    config::MAX_LIGHT_LEVEL,
+    registry::Registry,
+    world::{Chunk, CHUNK_SIZE},
+};
 use std::sync::Arc;
 
+mod incremental;
 mod sunlight;
 pub mod worker;
 
-/// This data structure contains the y position of the highest opaque block
+/// How every block affects the light, built from the block registry and shared with the light worker
+#[derive(Clone)]
+pub struct BlockLight {
+    /// Light levels lost by the light entering a block, indexed by block id. `MAX_LIGHT_LEVEL` if the block is opaque to light.
+    attenuation: Arc<Vec<u8>>,
+}
+
+impl BlockLight {
+    pub fn new(blocks: &Registry<Block>) -> Self {
+        let attenuation = (0..blocks.get_number_of_ids())
+            .map(|id| match blocks.get_value_by_id(id) {
+                Some(block) if !block.block_type.is_opaque_to_light() => block.block_type.light_attenuation().min(MAX_LIGHT_LEVEL),
+                _ => MAX_LIGHT_LEVEL,
+            })
+            .collect();
+        Self { attenuation: Arc::new(attenuation) }
+    }
+
+    /// Light levels lost by the light entering a block on top of the usual one, `MAX_LIGHT_LEVEL` if the block is opaque
+    #[inline(always)]
+    pub fn attenuation(&self, block: BlockId) -> u8 {
+        self.attenuation.get(block as usize).copied().unwrap_or(MAX_LIGHT_LEVEL)
+    }
+
+    /// Whether the sunlight from the sky stops at the block: the blocks below it only get the light that goes through it
+    #[inline(always)]
+    pub fn blocks_sky(&self, block: BlockId) -> bool {
+        self.attenuation(block) > 0
+    }
+
+    /// Air, a block that is opaque to light, and a block that the light goes through with an attenuation of 1
+    #[cfg(test)]
+    pub fn for_tests() -> Self {
+        Self { attenuation: Arc::new(vec![0, MAX_LIGHT_LEVEL, 1]) }
+    }
+}
+
+impl PartialEq for BlockLight {
+    fn eq(&self, other: &Self) -> bool {
+        self.attenuation == other.attenuation
+    }
+}
+
+/// This data structure contains the y position of the highest block that stops the sunlight
 #[derive(Clone)]
 pub struct HighestOpaqueBlock {
     pub y: [i64; (CHUNK_SIZE * CHUNK_SIZE) as usize],
 }
 
+impl Default for HighestOpaqueBlock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl HighestOpaqueBlock {
     pub fn new() -> Self {
         Self {
@@ -17,31 +72,99 @@ impl HighestOpaqueBlock {
         }
     }
 
-    pub fn from_chunk(chunk: &Arc<Chunk>) -> Self {
-        let mut hob = Self {
-            y: [i64::MIN; (CHUNK_SIZE * CHUNK_SIZE) as usize],
-        };
+    pub fn from_chunk(chunk: &Chunk, block_light: &BlockLight) -> Self {
+        let mut hob = Self::new();
         for i in 0..CHUNK_SIZE {
             for k in 0..CHUNK_SIZE {
-                for j in (0..CHUNK_SIZE).rev() {
-                    // TODO: use BlockRegistry
-                    if chunk.get_block_at((i, j, k)) != 0 {
-                        hob.y[(i*CHUNK_SIZE + k) as usize] = j as i64 + chunk.pos.py * CHUNK_SIZE as i64;
-                        break;
-                    }
-                }
+                hob.y[Self::index(i, k)] = Self::scan_down(chunk, block_light, i, k, CHUNK_SIZE);
             }
         }
         hob
     }
 
+    fn index(i: u32, k: u32) -> usize {
+        (i * CHUNK_SIZE + k) as usize
+    }
+
+    /// The y position of the highest block that stops the sunlight in the chunk column `(i, k)` strictly below `below`,
+    /// or `i64::MIN` if there is none
+    fn scan_down(chunk: &Chunk, block_light: &BlockLight, i: u32, k: u32, below: u32) -> i64 {
+        for j in (0..below).rev() {
+            if block_light.blocks_sky(chunk.get_block_at((i, j, k))) {
+                return j as i64 + chunk.pos.py * CHUNK_SIZE as i64;
+            }
+        }
+        i64::MIN
+    }
+
+    /// Update the HOB of a single chunk after the block at `(i, j, k)` changed. `chunk` must already contain the new block.
+    /// Only the column of the block is scanned again, and only if its highest block was removed.
+    /// Return true if the HOB changed.
+    pub fn update_block(&mut self, chunk: &Chunk, block_light: &BlockLight, (i, j, k): (u32, u32, u32)) -> bool {
+        let idx = Self::index(i, k);
+        let y = j as i64 + chunk.pos.py * CHUNK_SIZE as i64;
+        if block_light.blocks_sky(chunk.get_block_at((i, j, k))) {
+            if y > self.y[idx] {
+                self.y[idx] = y;
+                return true;
+            }
+        } else if y == self.y[idx] {
+            self.y[idx] = Self::scan_down(chunk, block_light, i, k, j);
+            return true;
+        }
+        false
+    }
+
     /// Merge with other HighestOpaqueBlock
     pub fn merge(&mut self, other: &HighestOpaqueBlock) {
-        for i in 0..CHUNK_SIZE {
-            for k in 0..CHUNK_SIZE {
-                let idx = (i*CHUNK_SIZE + k) as usize;
-                self.y[idx] = Ord::max(self.y[idx], other.y[idx]);
-            }
+        for (y, &other_y) in self.y.iter_mut().zip(other.y.iter()) {
+            *y = Ord::max(*y, other_y);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use voxel_rs_common::world::ChunkPos;
+
+    #[test]
+    fn test_hob_update_block() {
+        let block_light = BlockLight::for_tests();
+        let mut chunk = Chunk::new(ChunkPos::from((0, 2, 0)));
+        let base = 2 * CHUNK_SIZE as i64;
+        chunk.set_block_at((1, 3, 2), 1);
+        chunk.set_block_at((1, 10, 2), 1);
+        let mut hob = HighestOpaqueBlock::from_chunk(&chunk, &block_light);
+        assert_eq!(hob.y[HighestOpaqueBlock::index(1, 2)], base + 10);
+        assert_eq!(hob.y[HighestOpaqueBlock::index(0, 0)], i64::MIN);
+
+        // Placing a block above the highest block raises the HOB, placing it below doesn't change it
+        chunk.set_block_at((1, 20, 2), 1);
+        assert!(hob.update_block(&chunk, &block_light, (1, 20, 2)));
+        assert_eq!(hob.y[HighestOpaqueBlock::index(1, 2)], base + 20);
+        chunk.set_block_at((1, 5, 2), 1);
+        assert!(!hob.update_block(&chunk, &block_light, (1, 5, 2)));
+
+        // Removing the highest block scans down to the next one
+        chunk.set_block_at((1, 20, 2), 0);
+        assert!(hob.update_block(&chunk, &block_light, (1, 20, 2)));
+        assert_eq!(hob.y[HighestOpaqueBlock::index(1, 2)], base + 10);
+        // Removing a lower block doesn't change it
+        chunk.set_block_at((1, 5, 2), 0);
+        assert!(!hob.update_block(&chunk, &block_light, (1, 5, 2)));
+        chunk.set_block_at((1, 10, 2), 0);
+        assert!(hob.update_block(&chunk, &block_light, (1, 10, 2)));
+        assert_eq!(hob.y[HighestOpaqueBlock::index(1, 2)], base + 3);
+        // Removing the last block empties the column
+        chunk.set_block_at((1, 3, 2), 0);
+        assert!(hob.update_block(&chunk, &block_light, (1, 3, 2)));
+        assert_eq!(hob.y[HighestOpaqueBlock::index(1, 2)], i64::MIN);
+        assert_eq!(hob.y, HighestOpaqueBlock::from_chunk(&chunk, &block_light).y);
+
+        // The blocks that let some light through stop the sunlight too
+        chunk.set_block_at((1, 8, 2), 2);
+        assert!(hob.update_block(&chunk, &block_light, (1, 8, 2)));
+        assert_eq!(hob.y[HighestOpaqueBlock::index(1, 2)], base + 8);
+    }
+}
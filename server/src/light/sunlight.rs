@@ -1,5 +1,7 @@
-use voxel_rs_common::world::{Chunk, CHUNK_SIZE};
-use super::HighestOpaqueBlock;
+use voxel_rs_common::config::MAX_LIGHT_LEVEL;
+use voxel_rs_common::world::{Chunk, Direction, Neighborhood27, CHUNK_SIZE};
+use super::{BlockLight, HighestOpaqueBlock};
+use std::ops::Range;
 use std::sync::Arc;
 
 // TODO : Add block that are source of light
@@ -16,118 +18,109 @@ impl LightData {
     }
 }
 
-/// Take a 3x3x3 chunks bloc and 3x3 HighestOpaqueBlock and compute the light by using a BFS
+/// Number of blocks along every axis of a 3x3x3 chunk bloc
+pub(super) const BLOC_SIZE: usize = 3 * CHUNK_SIZE as usize;
+/// Size of a chunk, as a bloc coordinate
+pub(super) const CSIZE: usize = CHUNK_SIZE as usize;
+/// A light source can only affect the blocks at most this far from it
+const LIGHT_RANGE: usize = MAX_LIGHT_LEVEL as usize - 1;
+/// The bloc coordinates that can affect the light of the center chunk are in `MIN_POS..MAX_POS` along every axis
+const MIN_POS: usize = CSIZE - LIGHT_RANGE;
+const MAX_POS: usize = 2 * CSIZE + LIGHT_RANGE;
+
+/// Index of a chunk column in the 3x3 columns of the bloc
+#[inline(always)]
+pub(super) fn column_index(cx: usize, cz: usize) -> usize {
+    cx * 3 + cz
+}
+
+/// Index of a block in the light and opacity buffers, from its position in the bloc
+#[inline(always)]
+pub(super) fn bloc_index(x: usize, y: usize, z: usize) -> usize {
+    (x * BLOC_SIZE + y) * BLOC_SIZE + z
+}
+
+/// Index of a block in the light of a single chunk, from its position in the chunk
+#[inline(always)]
+fn chunk_block_index(i: usize, j: usize, k: usize) -> usize {
+    (i * CSIZE + j) * CSIZE + k
+}
+
+/// The positions in chunk number `c` of the bloc along some axis that can affect the light of the center chunk
+fn light_range(c: usize) -> Range<u32> {
+    match c {
+        0 => MIN_POS as u32..CHUNK_SIZE,
+        1 => 0..CHUNK_SIZE,
+        _ => 0..(MAX_POS - 2 * CSIZE) as u32,
+    }
+}
+
+#[inline(always)]
+fn in_light_range(pos: usize) -> bool {
+    (MIN_POS..MAX_POS).contains(&pos)
+}
+
+#[inline(always)]
+fn in_center_chunk(x: usize, y: usize, z: usize) -> bool {
+    x / CSIZE == 1 && y / CSIZE == 1 && z / CSIZE == 1
+}
+
+/// Take a 3x3x3 chunks bloc and 3x3 HighestOpaqueBlock and compute the light by using a BFS.
+/// `light_data` and `attenuation` are buffers for the whole bloc, they don't need to be cleared.
 pub fn compute_light(
     chunks: Vec<Option<Arc<Chunk>>>,
     highest_opaque_blocks: Vec<Arc<HighestOpaqueBlock>>,
+    block_light: &BlockLight,
     queue: &mut FastBFSQueue,
     light_data: &mut [u8],
-    opaque: &mut [bool],
+    attenuation: &mut [u8],
 ) -> LightData {
-    assert!(light_data.len() >= (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE * 27) as usize);
-    assert!(opaque.len() >= (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE * 27) as usize);
+    assert!(light_data.len() >= BLOC_SIZE * BLOC_SIZE * BLOC_SIZE);
+    assert!(attenuation.len() >= BLOC_SIZE * BLOC_SIZE * BLOC_SIZE);
     let mut res = LightData::new();
     queue.clear();
 
-    const MAX_LIGHT: u32 = 15;
-
-    //let mut light_data = [0; (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE * 27) as usize];
-    //let mut opaque = [false; (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE * 27) as usize];
-    let csize = CHUNK_SIZE as usize;
-
-    let mut transparent_count = 0;
-    let c = chunks[9 + 3 + 1].clone().unwrap();
+    // Number of transparent blocks of the center chunk that are not lit yet. The BFS stops when all of them are lit.
+    let mut dark_count = 0;
+    // A block can be lit a second time with more light if the light goes through blocks that attenuate it.
+    // In that case, the BFS can't stop as soon as every block of the center chunk is lit.
+    let mut attenuated = false;
+    let y0 = chunks[Neighborhood27::CENTER].as_ref().expect("The center chunk must be loaded").pos.py;
     unsafe {
-        let y0 = c.pos.py; // Center chunk height
-
-        'triple_loop: for cx in [1, 0, 2].iter() {
-            for cy in [1, 0, 2].iter() {
-                for cz in [1, 0, 2].iter() {
-                    if *cx != 1 && *cy != 1 && *cz != 1 && transparent_count == 0 {
+        // The center chunk comes first: if it doesn't have any dark block, the other chunks don't matter
+        'triple_loop: for &cx in [1, 0, 2].iter() {
+            for &cy in [1, 0, 2].iter() {
+                for &cz in [1, 0, 2].iter() {
+                    if (cx, cy, cz) != (1, 1, 1) && dark_count == 0 {
                         break 'triple_loop;
                     }
 
-                    let chunk = chunks[*cx * 9 + *cy * 3 + *cz].clone();
-                    let highest_opaque_block = &highest_opaque_blocks[*cx * 3 + *cz];
-                    // First we compute the range of the blocks we have to check in the chunk.
-                    let mut i_range = 0..CHUNK_SIZE;
-                    let mut j_range = 0..CHUNK_SIZE;
-                    let mut k_range = 0..CHUNK_SIZE;
-                    if *cx == 0 {
-                        i_range = (CHUNK_SIZE - MAX_LIGHT + 1)..CHUNK_SIZE;
-                    } else if *cx == 2 {
-                        i_range = 0..(MAX_LIGHT - 1);
-                    }
-                    if *cy == 0 {
-                        j_range = (CHUNK_SIZE - MAX_LIGHT + 1)..CHUNK_SIZE;
-                    } else if *cy == 2 {
-                        j_range = 0..(MAX_LIGHT - 1);
-                    }
-                    if *cz == 0 {
-                        k_range = (CHUNK_SIZE - MAX_LIGHT + 1)..CHUNK_SIZE;
-                    } else if *cz == 2 {
-                        k_range = 0..(MAX_LIGHT - 1);
-                    }
-                    // Then we fill the BFS queue
-                    match chunk {
-                        None => {
-                            for i in i_range {
-                                for k in j_range.clone() {
-                                    for j in k_range.clone() {
-                                        let s = (*cx * csize + i as usize) * csize * csize * 9
-                                            + (*cy * csize + j as usize) * csize * 3
-                                            + (*cz * csize + k as usize);
-                                        *opaque.get_unchecked_mut(s) = false;
-                                        if (y0 + *cy as i64 - 1) * CHUNK_SIZE as i64 + j as i64
-                                            > *highest_opaque_block
-                                                .y
-                                                .get_unchecked((i * CHUNK_SIZE + k) as usize)
-                                        {
-                                            *light_data.get_unchecked_mut(s) = 15;
-                                            queue.push((
-                                                *cx * csize + i as usize,
-                                                *cy * csize + j as usize,
-                                                *cz * csize + k as usize,
-                                                15,
-                                            ));
-                                        } else {
-                                            *light_data.get_unchecked_mut(s) = 0;
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                        Some(c) => {
-                            for i in i_range {
-                                for j in j_range.clone() {
-                                    for k in k_range.clone() {
-                                        let s = (*cx * csize + i as usize) * csize * csize * 9
-                                            + (*cy * csize + j as usize) * csize * 3
-                                            + (*cz * csize + k as usize);
-                                        if c.get_block_at_unsafe((i, j, k)) != 0 {
-                                            // TODO : replace by is opaque
-                                            *opaque.get_unchecked_mut(s) = true;
-                                        } else {
-                                            *opaque.get_unchecked_mut(s) = false;
-                                            if c.pos.py * CHUNK_SIZE as i64 + j as i64
-                                                > *highest_opaque_block
-                                                    .y
-                                                    .get_unchecked((i * CHUNK_SIZE + k) as usize)
-                                            {
-                                                *light_data.get_unchecked_mut(s) = 15;
-                                                queue.push((
-                                                    *cx * csize + i as usize,
-                                                    *cy * csize + j as usize,
-                                                    *cz * csize + k as usize,
-                                                    15,
-                                                ));
-                                            } else {
-                                                *light_data.get_unchecked_mut(s) = 0;
-                                                if *cx == 1 && *cy == 1 && *cz == 1 {
-                                                    transparent_count += 1;
-                                                }
-                                            }
-                                        }
+                    let chunk = chunks[Neighborhood27::index(cx, cy, cz)].as_ref();
+                    let highest_opaque_block = &highest_opaque_blocks[column_index(cx, cz)];
+                    let chunk_y = (y0 + cy as i64 - 1) * CHUNK_SIZE as i64;
+                    // Fill the buffers and the BFS queue with the blocks that can affect the center chunk
+                    for i in light_range(cx) {
+                        for j in light_range(cy) {
+                            for k in light_range(cz) {
+                                let (x, y, z) = (cx * CSIZE + i as usize, cy * CSIZE + j as usize, cz * CSIZE + k as usize);
+                                let s = bloc_index(x, y, z);
+                                let block_attenuation = match chunk {
+                                    Some(c) => block_light.attenuation(c.get_block_at_unsafe((i, j, k))),
+                                    None => 0,
+                                };
+                                *attenuation.get_unchecked_mut(s) = block_attenuation;
+                                attenuated |= 0 < block_attenuation && block_attenuation < MAX_LIGHT_LEVEL;
+                                if block_attenuation >= MAX_LIGHT_LEVEL {
+                                    *light_data.get_unchecked_mut(s) = 0;
+                                } else if chunk_y + j as i64
+                                    > *highest_opaque_block.y.get_unchecked(HighestOpaqueBlock::index(i, k))
+                                {
+                                    *light_data.get_unchecked_mut(s) = MAX_LIGHT_LEVEL;
+                                    queue.push((x, y, z, MAX_LIGHT_LEVEL));
+                                } else {
+                                    *light_data.get_unchecked_mut(s) = 0;
+                                    if (cx, cy, cz) == (1, 1, 1) {
+                                        dark_count += 1;
                                     }
                                 }
                             }
@@ -137,59 +130,41 @@ pub fn compute_light(
             }
         }
 
-        const MIN_VAL: isize = CHUNK_SIZE as isize - MAX_LIGHT as isize + 1;
-        const MAX_VAL: isize = 2 * CHUNK_SIZE as isize + MAX_LIGHT as isize;
-        const DX: [isize; 6] = [1, -1, 0, 0, 0, 0];
-        const DY: [isize; 6] = [0, 0, 1, -1, 0, 0];
-        const DZ: [isize; 6] = [0, 0, 0, 0, 1, -1];
-
-        while !queue.is_empty() && transparent_count > 0 {
+        while !queue.is_empty() && (dark_count > 0 || attenuated) {
             let (x, y, z, ll) = *queue.pop();
-            for i in 0..6 {
-                let (nx, ny, nz) = (x as isize + DX[i], y as isize + DY[i], z as isize + DZ[i]);
-                if MIN_VAL <= nx
-                    && nx < MAX_VAL
-                    && MIN_VAL <= ny
-                    && ny < MAX_VAL
-                    && MIN_VAL <= nz
-                    && nz < MAX_VAL
-                {
-                    let s = (nx as usize) * csize * csize * 9 + (ny as usize) * csize * 3 + (nz as usize);
-                    if *opaque.get_unchecked(s as usize) { continue; }
-                    let ref_light = light_data.get_unchecked_mut(s as usize);
-                    if *ref_light < ll - 1 {
-                        *ref_light = ll - 1;
-                        if ll > 1 {
-                            queue.push((nx as usize, ny as usize, nz as usize, ll - 1));
-                        }
-                        if nx as usize / csize == 1
-                            && ny as usize / csize == 1
-                            && nz as usize / csize == 1
-                            && !*opaque.get_unchecked(
-                                nx as usize * csize * csize * 9
-                                    + ny as usize * csize * 3
-                                    + nz as usize,
-                            )
-                        {
-                            transparent_count -= 1;
-                        }
+            for dir in Direction::iter() {
+                let (dx, dy, dz) = dir.offset();
+                // The queued blocks are in light range, so they are never on the border of the bloc
+                let (nx, ny, nz) = ((x as i64 + dx) as usize, (y as i64 + dy) as usize, (z as i64 + dz) as usize);
+                if !(in_light_range(nx) && in_light_range(ny) && in_light_range(nz)) {
+                    continue;
+                }
+                let s = bloc_index(nx, ny, nz);
+                let new_light = ll.saturating_sub(1 + *attenuation.get_unchecked(s));
+                let light = light_data.get_unchecked_mut(s);
+                if *light < new_light {
+                    if *light == 0 && in_center_chunk(nx, ny, nz) {
+                        dark_count -= 1;
+                    }
+                    *light = new_light;
+                    if new_light > 1 {
+                        queue.push((nx, ny, nz, new_light));
                     }
                 }
             }
         }
 
-        for i in 0..csize {
-            for j in 0..csize {
-                for k in 0..csize {
-                    res.light_level[i * csize * csize + j * csize + k] = *light_data.get_unchecked(
-                        (i + csize) * csize * csize * 9 + (j + csize) * 3 * csize + (k + csize),
-                    );
+        for i in 0..CSIZE {
+            for j in 0..CSIZE {
+                for k in 0..CSIZE {
+                    res.light_level[chunk_block_index(i, j, k)] =
+                        *light_data.get_unchecked(bloc_index(i + CSIZE, j + CSIZE, k + CSIZE));
                 }
             }
         }
     }
 
-    return res;
+    res
 }
 
 /// A structure to fasten the light computation
@@ -224,7 +199,7 @@ impl FastBFSQueue {
         self.pop_index =
             (self.pop_index + 1) % (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE * 27) as usize;
         //assert_ne!(self.pop_index, self.push_index);
-        return res;
+        res
     }
 
     #[inline(always)]
@@ -240,3 +215,208 @@ impl FastBFSQueue {
         self.push_index = 0;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use voxel_rs_common::world::ChunkPos;
+
+    const BLOC_VOLUME: usize = BLOC_SIZE * BLOC_SIZE * BLOC_SIZE;
+
+    /// A xorshift generator, so that the random worlds are the same on every run
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn chance(&mut self, percent: u64) -> bool {
+            self.next() % 100 < percent
+        }
+
+        fn below(&mut self, max: usize) -> usize {
+            (self.next() % max as u64) as usize
+        }
+    }
+
+    /// A random bloc: dense at the bottom, sparse at the top, and with a few missing chunks.
+    /// A quarter of the blocks let the light through.
+    fn random_bloc(rng: &mut Rng) -> Vec<Option<Arc<Chunk>>> {
+        let mut chunks = Vec::new();
+        for cx in 0..3 {
+            for cy in 0..3 {
+                for cz in 0..3 {
+                    if (cx, cy, cz) != (1, 1, 1) && rng.chance(10) {
+                        chunks.push(None);
+                        continue;
+                    }
+                    let density = [60, 30, 3][cy];
+                    let mut chunk = Chunk::new(ChunkPos::from((cx as i64 - 1, cy as i64 + 4, cz as i64 - 1)));
+                    for i in 0..CHUNK_SIZE {
+                        for j in 0..CHUNK_SIZE {
+                            for k in 0..CHUNK_SIZE {
+                                if rng.chance(density) {
+                                    chunk.set_block_at((i, j, k), if rng.chance(25) { 2 } else { 1 });
+                                }
+                            }
+                        }
+                    }
+                    chunks.push(Some(Arc::new(chunk)));
+                }
+            }
+        }
+        chunks
+    }
+
+    /// The HOBs of the columns of the bloc, from its loaded chunks
+    fn bloc_hobs(chunks: &[Option<Arc<Chunk>>]) -> Vec<Arc<HighestOpaqueBlock>> {
+        let mut hobs = Vec::new();
+        for cx in 0..3 {
+            for cz in 0..3 {
+                let mut hob = HighestOpaqueBlock::new();
+                for cy in 0..3 {
+                    if let Some(chunk) = chunks[Neighborhood27::index(cx, cy, cz)].as_ref() {
+                        hob.merge(&HighestOpaqueBlock::from_chunk(chunk, &BlockLight::for_tests()));
+                    }
+                }
+                hobs.push(Arc::new(hob));
+            }
+        }
+        hobs
+    }
+
+    fn light(chunks: &[Option<Arc<Chunk>>], queue: &mut FastBFSQueue) -> Vec<u8> {
+        let mut light_data = vec![0; BLOC_VOLUME];
+        let mut attenuation = vec![0; BLOC_VOLUME];
+        let block_light = BlockLight::for_tests();
+        compute_light(chunks.to_vec(), bloc_hobs(chunks), &block_light, queue, &mut light_data, &mut attenuation)
+            .light_level
+            .to_vec()
+    }
+
+    /// Propagate the light in the whole bloc until nothing changes, and return the light of the center chunk
+    fn brute_force_light(chunks: &[Option<Arc<Chunk>>]) -> Vec<u8> {
+        let hobs = bloc_hobs(chunks);
+        let y0 = chunks[Neighborhood27::CENTER].as_ref().unwrap().pos.py - 1;
+        let block_light = BlockLight::for_tests();
+        let mut attenuation = vec![0; BLOC_VOLUME];
+        let mut light = vec![0u8; BLOC_VOLUME];
+        for x in 0..BLOC_SIZE {
+            for y in 0..BLOC_SIZE {
+                for z in 0..BLOC_SIZE {
+                    let (i, j, k) = ((x % CSIZE) as u32, (y % CSIZE) as u32, (z % CSIZE) as u32);
+                    let s = bloc_index(x, y, z);
+                    let chunk = chunks[Neighborhood27::index(x / CSIZE, y / CSIZE, z / CSIZE)].as_ref();
+                    attenuation[s] = chunk.map(|c| block_light.attenuation(c.get_block_at((i, j, k)))).unwrap_or(0);
+                    let hob = hobs[column_index(x / CSIZE, z / CSIZE)].y[HighestOpaqueBlock::index(i, k)];
+                    if attenuation[s] < MAX_LIGHT_LEVEL && y0 * CHUNK_SIZE as i64 + y as i64 > hob {
+                        light[s] = MAX_LIGHT_LEVEL;
+                    }
+                }
+            }
+        }
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for x in 0..BLOC_SIZE {
+                for y in 0..BLOC_SIZE {
+                    for z in 0..BLOC_SIZE {
+                        let s = bloc_index(x, y, z);
+                        if attenuation[s] >= MAX_LIGHT_LEVEL {
+                            continue;
+                        }
+                        let best = Direction::iter()
+                            .map(|dir| dir.offset())
+                            .map(|(dx, dy, dz)| (x as i64 + dx, y as i64 + dy, z as i64 + dz))
+                            .filter(|&(nx, ny, nz)| [nx, ny, nz].iter().all(|n| (0..BLOC_SIZE as i64).contains(n)))
+                            .map(|(nx, ny, nz)| light[bloc_index(nx as usize, ny as usize, nz as usize)].saturating_sub(1 + attenuation[s]))
+                            .max()
+                            .unwrap_or(0);
+                        if best > light[s] {
+                            light[s] = best;
+                            changed = true;
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut result = vec![0; CSIZE * CSIZE * CSIZE];
+        for i in 0..CSIZE {
+            for j in 0..CSIZE {
+                for k in 0..CSIZE {
+                    result[chunk_block_index(i, j, k)] = light[bloc_index(i + CSIZE, j + CSIZE, k + CSIZE)];
+                }
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn test_light_matches_brute_force() {
+        let mut rng = Rng(0x1063);
+        // The queue is reused like in the light worker
+        let mut queue = FastBFSQueue::new();
+        for _ in 0..2 {
+            let chunks = random_bloc(&mut rng);
+            assert!(light(&chunks, &mut queue) == brute_force_light(&chunks));
+        }
+    }
+
+    #[test]
+    fn test_light_monotonicity() {
+        let mut rng = Rng(0xdead_beef);
+        let mut queue = FastBFSQueue::new();
+        let mut chunks = random_bloc(&mut rng);
+        let mut previous = light(&chunks, &mut queue);
+        for _ in 0..20 {
+            // Change a random block in light range of the center chunk
+            let (x, y, z) = (
+                MIN_POS + rng.below(MAX_POS - MIN_POS),
+                MIN_POS + rng.below(MAX_POS - MIN_POS),
+                MIN_POS + rng.below(MAX_POS - MIN_POS),
+            );
+            let index = Neighborhood27::index(x / CSIZE, y / CSIZE, z / CSIZE);
+            let mut chunk = match chunks[index].as_ref() {
+                Some(chunk) => (**chunk).clone(),
+                None => continue,
+            };
+            let pos = ((x % CSIZE) as u32, (y % CSIZE) as u32, (z % CSIZE) as u32);
+            let placed = chunk.get_block_at(pos) == 0;
+            chunk.set_block_at(pos, if placed { 1 } else { 0 });
+            chunks[index] = Some(Arc::new(chunk));
+
+            let current = light(&chunks, &mut queue);
+            for (s, (&before, &after)) in previous.iter().zip(current.iter()).enumerate() {
+                if in_center_chunk(x, y, z) && s == chunk_block_index(x - CSIZE, y - CSIZE, z - CSIZE) {
+                    continue;
+                }
+                // Placing a block never lights another block, removing a block never darkens another block
+                if placed {
+                    assert!(after <= before);
+                } else {
+                    assert!(after >= before);
+                }
+            }
+            previous = current;
+        }
+
+        // Two neighboring air blocks differ by at most one light level
+        let center = chunks[Neighborhood27::CENTER].as_ref().unwrap();
+        for i in 0..CSIZE {
+            for j in 0..CSIZE {
+                for k in 0..CSIZE - 1 {
+                    let (a, b) = ((i as u32, j as u32, k as u32), (i as u32, j as u32, k as u32 + 1));
+                    if center.get_block_at(a) == 0 && center.get_block_at(b) == 0 {
+                        let (la, lb) = (previous[chunk_block_index(i, j, k)], previous[chunk_block_index(i, j, k + 1)]);
+                        assert!((la as i32 - lb as i32).abs() <= 1);
+                    }
+                }
+            }
+        }
+    }
+}
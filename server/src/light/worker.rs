@@ -1,10 +1,10 @@
 use voxel_rs_common::{
-    collections::zero_initialized_vec,
-    world::{Chunk, CHUNK_SIZE, LightChunk},
+    world::{Chunk, LightChunk, Neighborhood27},
     worker::{Worker, WorkerState},
 };
-use super::HighestOpaqueBlock;
-use super::sunlight::{FastBFSQueue, compute_light};
+use super::{BlockLight, HighestOpaqueBlock};
+use super::incremental::{update_light, IncrementalLightBuffers};
+use super::sunlight::compute_light;
 use std::sync::Arc;
 
 static LIGHTING_QUEUE_SIZE: usize = 20;
@@ -14,41 +14,66 @@ pub fn start_lighting_worker() -> ChunkLightingWorker {
 }
 
 /// The chunk-specific data that is needed to generate light for it.
-pub struct ChunkLightingData {
-    pub chunks: Vec<Option<Arc<Chunk>>>,
-    pub highest_opaque_blocks: Vec<Arc<HighestOpaqueBlock>>,
+pub enum ChunkLightingData {
+    /// Compute the light of the middle chunk of a 3x3x3 chunks bloc
+    Full {
+        chunks: Vec<Option<Arc<Chunk>>>,
+        highest_opaque_blocks: Vec<Arc<HighestOpaqueBlock>>,
+        block_light: BlockLight,
+    },
+    /// Update the light of a 3x3x3 chunks bloc after a few blocks changed
+    Incremental {
+        chunks: Vec<Arc<Chunk>>,
+        light_chunks: Vec<Arc<LightChunk>>,
+        highest_opaque_blocks: Vec<Arc<HighestOpaqueBlock>>,
+        block_light: BlockLight,
+        /// Positions in the bloc of the blocks whose light must be recomputed
+        changed_blocks: Vec<(usize, usize, usize)>,
+    },
 }
 
 pub struct ChunkLightingState {
-    queue_reuse: FastBFSQueue,
-    light_data_reuse: Vec<u8>,
-    opaque_reuse: Vec<bool>,
+    buffers: IncrementalLightBuffers,
 }
 
 impl ChunkLightingState {
     pub(self) fn new() -> Self {
         Self {
-            queue_reuse: FastBFSQueue::new(),
-            light_data_reuse: unsafe { zero_initialized_vec((CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE * 27) as usize) },
-            opaque_reuse: unsafe { zero_initialized_vec((CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE * 27) as usize) },
+            buffers: IncrementalLightBuffers::new(),
         }
     }
 }
 
-impl WorkerState<ChunkLightingData, Arc<LightChunk>> for ChunkLightingState {
-    fn compute(&mut self, data: ChunkLightingData) -> Arc<LightChunk> {
-        let pos = data.chunks[9+3+1].as_ref().expect("No middle chunk").pos;
-        Arc::new(LightChunk {
-            light: compute_light(
-                data.chunks,
-                data.highest_opaque_blocks,
-                &mut self.queue_reuse,
-                &mut self.light_data_reuse,
-                &mut self.opaque_reuse,
-            ).light_level.to_vec(),
-            pos,
-        })
+impl WorkerState<ChunkLightingData, Vec<Arc<LightChunk>>> for ChunkLightingState {
+    fn compute(&mut self, data: ChunkLightingData) -> Vec<Arc<LightChunk>> {
+        match data {
+            ChunkLightingData::Full { chunks, highest_opaque_blocks, block_light } => {
+                let pos = chunks[Neighborhood27::CENTER].as_ref().expect("No middle chunk").pos;
+                vec![Arc::new(LightChunk {
+                    light: compute_light(
+                        chunks,
+                        highest_opaque_blocks,
+                        &block_light,
+                        &mut self.buffers.queue,
+                        &mut self.buffers.light_data,
+                        &mut self.buffers.attenuation,
+                    ).light_level.to_vec(),
+                    pos,
+                })]
+            }
+            ChunkLightingData::Incremental { chunks, light_chunks, highest_opaque_blocks, block_light, changed_blocks } => {
+                update_light(
+                    &chunks,
+                    &light_chunks,
+                    &highest_opaque_blocks,
+                    &block_light,
+                    &changed_blocks,
+                    &mut self.buffers,
+                )
+            }
+        }
     }
 }
 
-pub type ChunkLightingWorker = Worker<ChunkLightingData, Arc<LightChunk>, ChunkLightingState>;
+/// The light worker returns the light chunks that were computed. Those that didn't change are the same `Arc`s as before.
+pub type ChunkLightingWorker = Worker<ChunkLightingData, Vec<Arc<LightChunk>>, ChunkLightingState>;
@@ -0,0 +1,93 @@
+//! Roles and permissions of the players, stored with the world.
+//!
+//! Every role allows some permissions: the names of the commands, like `give`, and the privileged actions below.
+//! The players have the default role unless they were given another one, for example with `/op`.
+use crate::auth::username_key;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// The role given by `/op`
+pub const OPERATOR_ROLE: &str = "operator";
+/// A permission that allows everything
+pub const ALL_PERMISSIONS: &str = "*";
+/// Change the gamemode
+pub const GAMEMODE_PERMISSION: &str = "gamemode";
+/// Stop the server
+pub const STOP_PERMISSION: &str = "stop";
+/// Break and place blocks in the spawn protection
+pub const BUILD_AT_SPAWN_PERMISSION: &str = "build_at_spawn";
+
+/// The roles of a world and of its players
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PermissionsConfig {
+    /// The permissions allowed by every role
+    pub roles: HashMap<String, Vec<String>>,
+    /// The role of the players that are not in `players`
+    pub default_role: String,
+    /// The role of every username, in lower case because the usernames are compared without case
+    pub players: HashMap<String, String>,
+}
+
+impl Default for PermissionsConfig {
+    fn default() -> Self {
+        let mut roles = HashMap::new();
+        roles.insert(OPERATOR_ROLE.to_owned(), vec![ALL_PERMISSIONS.to_owned()]);
+        roles.insert("player".to_owned(), vec!["spawn".to_owned(), "list".to_owned()]);
+        Self {
+            roles,
+            default_role: "player".to_owned(),
+            players: HashMap::new(),
+        }
+    }
+}
+
+impl PermissionsConfig {
+    pub fn role(&self, username: &str) -> &str {
+        match self.players.get(&username_key(username)) {
+            Some(role) => role,
+            None => &self.default_role,
+        }
+    }
+
+    /// Check if the role of a player allows `permission`. The unknown roles don't allow anything.
+    pub fn allows(&self, username: &str, permission: &str) -> bool {
+        match self.roles.get(self.role(username)) {
+            Some(permissions) => permissions.iter().any(|p| p == ALL_PERMISSIONS || p == permission),
+            None => false,
+        }
+    }
+
+    /// Give a role to a player, or the default role if `role` is `None`
+    pub fn set_role(&mut self, username: &str, role: Option<&str>) {
+        match role {
+            Some(role) => self.players.insert(username_key(username), role.to_owned()),
+            None => self.players.remove(&username_key(username)),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permissions() {
+        let mut permissions = PermissionsConfig::default();
+        assert!(permissions.allows("Steve", "spawn"));
+        assert!(!permissions.allows("Steve", "give"));
+        assert!(!permissions.allows("Steve", GAMEMODE_PERMISSION));
+
+        permissions.set_role("Steve", Some(OPERATOR_ROLE));
+        assert_eq!(permissions.role("STEVE"), OPERATOR_ROLE);
+        assert!(permissions.allows("steve", "give"));
+        assert!(permissions.allows("steve", GAMEMODE_PERMISSION));
+
+        permissions.set_role("steve", Some("unknown"));
+        assert!(!permissions.allows("Steve", "spawn"));
+
+        permissions.set_role("Steve", None);
+        assert!(permissions.allows("Steve", "spawn"));
+        assert!(permissions.players.is_empty());
+    }
+}
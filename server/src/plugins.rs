@@ -0,0 +1,277 @@
+//! Extension points of the server, registered by the plugins before the server starts.
+//!
+//! For now, the plugins can modify the generated chunks with a `ChunkGeneratedHook`, and run code in the server loop
+//! with a `PluginTask`.
+use crate::commands::CommandContext;
+use crate::scheduler::Scheduler;
+use crate::ServerTask;
+use log::error;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use voxel_rs_common::{
+    block::Block,
+    registry::Registry,
+    world::{BlockPos, Chunk, ChunkPos, WorldGenerator, CHUNK_SIZE},
+};
+
+/// Number of chunks that are generated twice to check that the hooks are deterministic
+const DETERMINISM_CHECKED_CHUNKS: usize = 16;
+
+/// What the hooks know about the generated world
+pub struct ChunkGenerationContext<'a> {
+    pub seed: i32,
+    pub blocks: &'a Registry<Block>,
+}
+
+/// A hook that modifies every chunk after the world generator created it, before the chunk is lit, saved or sent.
+///
+/// The guarantees and the rules of the hooks:
+/// - The hooks run by increasing `priority`, then by name, whatever the order in which they were registered.
+///   Every hook sees the changes of the previous hooks.
+/// - A hook only receives the chunk it modifies, so it can't depend on the order in which the chunks are generated.
+///   A structure that crosses the border of a chunk must be placed again by the hook of every chunk it crosses.
+/// - The chunks that were not modified by the players are not saved and are generated again when they are loaded,
+///   and several chunks are generated at the same time on different threads. So the result of a hook must only
+///   depend on the position of the chunk, on the seed and on the blocks of the chunk.
+///   The first chunks of every server are generated twice, and the hooks that don't give the same result are disabled.
+pub trait ChunkGeneratedHook: Send + Sync {
+    /// Name of the hook in the logs, and to order the hooks with the same priority
+    fn name(&self) -> &str;
+
+    /// The hooks with a lower priority run first
+    fn priority(&self) -> i32 {
+        0
+    }
+
+    fn chunk_generated(&self, chunk: &mut Chunk, ctx: &ChunkGenerationContext);
+}
+
+/// A task that runs in the server loop when it's due. It can do what the commands do,
+/// and schedule other tasks with the scheduler of the context.
+pub trait PluginTask: Send + Sync {
+    /// Name of the task in the logs
+    fn name(&self) -> &str;
+
+    fn run(&self, ctx: CommandContext);
+}
+
+impl std::fmt::Debug for dyn PluginTask {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PluginTask({})", self.name())
+    }
+}
+
+/// A task scheduled when the server starts
+#[derive(Clone)]
+struct StartupTask {
+    delay: Duration,
+    /// `Some(period)` if the task repeats
+    period: Option<Duration>,
+    task: Arc<dyn PluginTask>,
+}
+
+/// The extensions registered by the plugins
+#[derive(Default, Clone)]
+pub struct ServerPlugins {
+    chunk_generated_hooks: Vec<Arc<dyn ChunkGeneratedHook>>,
+    startup_tasks: Vec<StartupTask>,
+}
+
+impl ServerPlugins {
+    pub fn add_chunk_generated_hook(&mut self, hook: impl ChunkGeneratedHook + 'static) {
+        self.chunk_generated_hooks.push(Arc::new(hook));
+    }
+
+    /// Run `task` `delay` after the server starts, and then every `period` if it's set
+    pub fn schedule_task(&mut self, delay: Duration, period: Option<Duration>, task: impl PluginTask + 'static) {
+        self.startup_tasks.push(StartupTask { delay, period, task: Arc::new(task) });
+    }
+
+    /// Add the tasks of the plugins to the scheduler of the server
+    pub(crate) fn schedule_tasks(&self, scheduler: &mut Scheduler<ServerTask>, now: Instant) {
+        for StartupTask { delay, period, task } in self.startup_tasks.iter().cloned() {
+            let task = ServerTask::Plugin(task);
+            match period {
+                Some(period) => scheduler.schedule_repeating(now, delay, period, task),
+                None => scheduler.schedule_once(now, delay, task),
+            };
+        }
+    }
+
+    /// Run the chunk generated hooks after the world generator
+    pub fn hook_world_generator(
+        &self,
+        world_generator: Box<dyn WorldGenerator>,
+        seed: i32,
+    ) -> Box<dyn WorldGenerator> {
+        if self.chunk_generated_hooks.is_empty() {
+            return world_generator;
+        }
+        let mut hooks = self
+            .chunk_generated_hooks
+            .iter()
+            .map(|hook| (hook.clone(), AtomicBool::new(true)))
+            .collect::<Vec<_>>();
+        hooks.sort_by(|(a, _), (b, _)| a.priority().cmp(&b.priority()).then_with(|| a.name().cmp(b.name())));
+        Box::new(HookedWorldGenerator {
+            world_generator,
+            hooks,
+            seed,
+            checked_chunks: AtomicUsize::new(0),
+        })
+    }
+}
+
+/// A world generator followed by the chunk generated hooks
+struct HookedWorldGenerator {
+    world_generator: Box<dyn WorldGenerator>,
+    /// The sorted hooks, and whether they are still enabled
+    hooks: Vec<(Arc<dyn ChunkGeneratedHook>, AtomicBool)>,
+    seed: i32,
+    checked_chunks: AtomicUsize,
+}
+
+impl WorldGenerator for HookedWorldGenerator {
+    fn generate_chunk(&self, pos: ChunkPos, block_registry: &Registry<Block>) -> Chunk {
+        let mut chunk = self.world_generator.generate_chunk(pos, block_registry);
+        let ctx = ChunkGenerationContext {
+            seed: self.seed,
+            blocks: block_registry,
+        };
+        let check_determinism = self.checked_chunks.fetch_add(1, Ordering::Relaxed) < DETERMINISM_CHECKED_CHUNKS;
+        for (hook, enabled) in self.hooks.iter() {
+            if !enabled.load(Ordering::Relaxed) {
+                continue;
+            }
+            if check_determinism {
+                let mut other_chunk = chunk.clone();
+                hook.chunk_generated(&mut chunk, &ctx);
+                hook.chunk_generated(&mut other_chunk, &ctx);
+                if !same_blocks(&chunk, &other_chunk) {
+                    error!("The chunk generated hook {} is not deterministic, disabling it", hook.name());
+                    enabled.store(false, Ordering::Relaxed);
+                }
+            } else {
+                hook.chunk_generated(&mut chunk, &ctx);
+            }
+        }
+        chunk
+    }
+
+    fn spawn_point(&self) -> BlockPos {
+        self.world_generator.spawn_point()
+    }
+}
+
+fn same_blocks(a: &Chunk, b: &Chunk) -> bool {
+    (0..CHUNK_SIZE).all(|x| {
+        (0..CHUNK_SIZE).all(|y| (0..CHUNK_SIZE).all(|z| a.get_block_at((x, y, z)) == b.get_block_at((x, y, z))))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    struct FlatGenerator;
+
+    impl WorldGenerator for FlatGenerator {
+        fn generate_chunk(&self, pos: ChunkPos, _block_registry: &Registry<Block>) -> Chunk {
+            Chunk::new(pos)
+        }
+
+        fn spawn_point(&self) -> BlockPos {
+            BlockPos::from((0, 0, 0))
+        }
+    }
+
+    /// Set the block at the origin of the chunk, and remember the order of the calls
+    struct SetOrigin {
+        name: &'static str,
+        block: u16,
+        calls: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    impl ChunkGeneratedHook for SetOrigin {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn chunk_generated(&self, chunk: &mut Chunk, _ctx: &ChunkGenerationContext) {
+            self.calls.lock().unwrap().push(self.name);
+            chunk.set_block_at((0, 0, 0), self.block);
+        }
+    }
+
+    /// A hook that places a different block every time
+    struct Counter(AtomicUsize);
+
+    impl ChunkGeneratedHook for Counter {
+        fn name(&self) -> &str {
+            "counter"
+        }
+
+        fn priority(&self) -> i32 {
+            -1
+        }
+
+        fn chunk_generated(&self, chunk: &mut Chunk, _ctx: &ChunkGenerationContext) {
+            let block = self.0.fetch_add(1, Ordering::Relaxed) as u16;
+            chunk.set_block_at((1, 0, 0), block);
+        }
+    }
+
+    #[test]
+    fn test_hooks_order_and_determinism() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut plugins = ServerPlugins::default();
+        plugins.add_chunk_generated_hook(SetOrigin { name: "b", block: 2, calls: calls.clone() });
+        plugins.add_chunk_generated_hook(SetOrigin { name: "a", block: 1, calls: calls.clone() });
+        plugins.add_chunk_generated_hook(Counter(AtomicUsize::new(1)));
+        let generator = plugins.hook_world_generator(Box::new(FlatGenerator), 42);
+        let blocks = Registry::default();
+
+        // The hooks run by name, and the last one wins
+        let chunk = generator.generate_chunk(ChunkPos::from((0, 0, 0)), &blocks);
+        assert_eq!(*calls.lock().unwrap(), vec!["a", "a", "b", "b"]);
+        assert_eq!(chunk.get_block_at((0, 0, 0)), 2);
+
+        // The counter is not deterministic, so it was disabled
+        let chunk = generator.generate_chunk(ChunkPos::from((1, 0, 0)), &blocks);
+        assert_eq!(chunk.get_block_at((1, 0, 0)), 0);
+    }
+
+    struct Announce;
+
+    impl PluginTask for Announce {
+        fn name(&self) -> &str {
+            "announce"
+        }
+
+        fn run(&self, _ctx: CommandContext) {}
+    }
+
+    #[test]
+    fn test_scheduled_tasks() {
+        let mut plugins = ServerPlugins::default();
+        plugins.schedule_task(Duration::from_secs(5), Some(Duration::from_secs(10)), Announce);
+        let mut scheduler = Scheduler::new();
+        let start = Instant::now();
+        plugins.schedule_tasks(&mut scheduler, start);
+
+        let names = |tasks: Vec<ServerTask>| {
+            tasks
+                .into_iter()
+                .map(|task| match task {
+                    ServerTask::Plugin(task) => task.name().to_owned(),
+                    task => format!("{:?}", task),
+                })
+                .collect::<Vec<_>>()
+        };
+        assert!(scheduler.poll(start).is_empty());
+        assert_eq!(names(scheduler.poll(start + Duration::from_secs(5))), vec!["announce"]);
+        assert_eq!(names(scheduler.poll(start + Duration::from_secs(15))), vec!["announce"]);
+    }
+}
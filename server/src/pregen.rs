@@ -0,0 +1,107 @@
+//! Pregeneration of the chunks around the spawn, with their light.
+//!
+//! The chunks are generated and lit in batches of columns, without a server and without players, and saved with
+//! their light so that the first player visit doesn't need to light them. The light worker is driven directly
+//! with the chunks of the batch instead of the chunks close to the players.
+use crate::plugins::ServerPlugins;
+use crate::save::{self, WorldMetadata};
+use crate::tickets::ChunkTickets;
+use crate::world::World;
+use crate::DATA_FOLDER;
+use anyhow::Result;
+use log::info;
+use std::time::Duration;
+use voxel_rs_common::{
+    data::load_data,
+    world::{BlockPos, ChunkPos},
+    worldgen::DefaultWorldGenerator,
+};
+
+/// Number of columns along x and z in a batch
+const BATCH_SIZE: i64 = 8;
+/// Number of chunks generated below and above the spawn chunk
+const VERTICAL_RADIUS: i64 = 4;
+/// Wait between two polls of the workers
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Generate and light the chunks at most `radius` chunks away from the spawn along x and z, and save them.
+/// The chunk generated hooks of the plugins run like in the server. Return the number of pregenerated chunks.
+pub fn pregenerate_world(world_metadata: &mut WorldMetadata, radius: i64, plugins: &ServerPlugins) -> Result<usize> {
+    save::migrate_world(world_metadata)?;
+    let block_palette = save::load_block_palette(world_metadata)?;
+    let game_data = load_data(DATA_FOLDER.into(), &block_palette)?;
+    save::save_block_palette(world_metadata, game_data.blocks.get_names())?;
+
+    let world_generator = plugins.hook_world_generator(
+        Box::new(DefaultWorldGenerator::new(&game_data.blocks, &game_data.structures, world_metadata.seed)),
+        world_metadata.seed,
+    );
+    let spawn = match save::load_world_state(world_metadata)?.spawn_point {
+        Some([x, y, z]) => BlockPos::from((x.floor() as i64, y.floor() as i64, z.floor() as i64)),
+        None => world_generator.spawn_point(),
+    };
+    let center = spawn.containing_chunk_pos();
+    let dimension = save::load_server_config(world_metadata)?.dimension(&game_data);
+    let mut world = World::new(
+        game_data.blocks.clone(),
+        game_data.items.clone(),
+        world_generator,
+        world_metadata.clone(),
+        dimension.sunlight,
+    )?;
+
+    let mut pregenerated = 0;
+    let mut bx = -radius;
+    while bx <= radius {
+        let mut bz = -radius;
+        while bz <= radius {
+            let max_x = (bx + BATCH_SIZE - 1).min(radius);
+            let max_z = (bz + BATCH_SIZE - 1).min(radius);
+            let batch = chunks_between(center, (bx, -VERTICAL_RADIUS, bz), (max_x, VERTICAL_RADIUS, max_z));
+            // The chunks around the batch are loaded too, so that the light of the batch is correct
+            let loaded = chunks_between(
+                center,
+                (bx - 1, -VERTICAL_RADIUS - 1, bz - 1),
+                (max_x + 1, VERTICAL_RADIUS + 1, max_z + 1),
+            );
+            pregenerate_batch(&mut world, &batch, &loaded);
+            pregenerated += batch.len();
+            info!("Pregenerated {} chunks", pregenerated);
+            bz += BATCH_SIZE;
+        }
+        bx += BATCH_SIZE;
+    }
+    Ok(pregenerated)
+}
+
+/// The chunks between two offsets from `center`, inclusive
+fn chunks_between(center: ChunkPos, min: (i64, i64, i64), max: (i64, i64, i64)) -> Vec<ChunkPos> {
+    let mut chunks = Vec::new();
+    for i in min.0..=max.0 {
+        for j in min.1..=max.1 {
+            for k in min.2..=max.2 {
+                chunks.push(center.offset(i, j, k));
+            }
+        }
+    }
+    chunks
+}
+
+/// Load or generate the `loaded` chunks, light the `batch` chunks and save them, then unload all the chunks
+fn pregenerate_batch(world: &mut World, batch: &[ChunkPos], loaded: &[ChunkPos]) {
+    while !loaded.iter().all(|&pos| world.is_chunk_loaded(pos)) {
+        world.enqueue_chunks_for_worldgen(loaded);
+        world.get_new_generated_chunks();
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    while !batch.iter().all(|&pos| world.is_chunk_lit(pos)) {
+        world.enqueue_chunks_for_lighting(batch);
+        world.get_new_light_chunks();
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    for &pos in batch {
+        world.mark_modified(pos);
+    }
+    // No ticket keeps the chunks loaded, so the batch is saved and everything is unloaded
+    world.drop_unticketed_chunks(&ChunkTickets::default());
+}
@@ -0,0 +1,177 @@
+//! Upgrade of the worlds saved with an older save format.
+//!
+//! Every change to the save format increments `SAVE_FORMAT_VERSION` and adds a migration from the previous version
+//! to `MIGRATIONS`. The migrations of a world run in order when the server starts, and the version in the world
+//! metadata is updated after every migration, so that an interrupted upgrade resumes where it stopped.
+use super::{chunk_path, list_saved_chunks, save_metadata, write_atomically, SavedChunk, WorldMetadata};
+use anyhow::{Context, Result};
+use log::{info, warn};
+
+/// Version of the save format written by this version of the server
+pub const SAVE_FORMAT_VERSION: u32 = 1;
+
+/// An upgrade of the save format from version `from` to version `from + 1`
+struct Migration {
+    from: u32,
+    description: &'static str,
+    migrate: fn(&WorldMetadata, &mut Progress) -> Result<()>,
+}
+
+/// The migrations, sorted by version. The migration at index `i` upgrades from version `i`.
+const MIGRATIONS: &[Migration] = &[Migration {
+    from: 0,
+    description: "Add checksums to the saved chunks",
+    migrate: add_chunk_checksums,
+}];
+
+/// Logs the progress of a migration, every 10% of the work
+struct Progress {
+    description: &'static str,
+    total: usize,
+    done: usize,
+    logged_tenths: usize,
+}
+
+impl Progress {
+    fn new(description: &'static str) -> Self {
+        Self {
+            description,
+            total: 0,
+            done: 0,
+            logged_tenths: 0,
+        }
+    }
+
+    fn set_total(&mut self, total: usize) {
+        self.total = total;
+    }
+
+    fn advance(&mut self) {
+        self.done += 1;
+        let tenths = self.done * 10 / self.total.max(1);
+        if tenths > self.logged_tenths {
+            self.logged_tenths = tenths;
+            info!("{}: {}/{} ({}%)", self.description, self.done, self.total, tenths * 10);
+        }
+    }
+}
+
+/// Upgrade a world to the current save format
+pub fn migrate_world(world: &mut WorldMetadata) -> Result<()> {
+    if world.version > SAVE_FORMAT_VERSION {
+        anyhow::bail!(
+            "World {} was saved with version {} of the save format, but this server only supports up to version {}",
+            world.name,
+            world.version,
+            SAVE_FORMAT_VERSION,
+        );
+    }
+    let pending = &MIGRATIONS[world.version as usize..];
+    for (i, migration) in pending.iter().enumerate() {
+        info!(
+            "Upgrading world {} from version {} ({}/{}): {}",
+            world.name,
+            migration.from,
+            i + 1,
+            pending.len(),
+            migration.description,
+        );
+        (migration.migrate)(world, &mut Progress::new(migration.description))
+            .context(format!("Failed to upgrade world {} from version {}", world.name, migration.from))?;
+        world.version = migration.from + 1;
+        save_metadata(world)?;
+    }
+    if !pending.is_empty() {
+        info!("World {} upgraded to version {}", world.name, world.version);
+    }
+    Ok(())
+}
+
+/// Version 0 to 1: the chunks store a checksum to detect the corrupted chunks
+fn add_chunk_checksums(world: &WorldMetadata, progress: &mut Progress) -> Result<()> {
+    let chunks = list_saved_chunks(world)?;
+    progress.set_total(chunks.len());
+    for pos in chunks {
+        let path = chunk_path(world, pos);
+        let buf = std::fs::read_to_string(&path).context(format!("Failed to read chunk {}", path.display()))?;
+        match ron::de::from_str::<SavedChunk>(&buf) {
+            Ok(mut saved_chunk) => {
+                if saved_chunk.checksum.is_none() {
+                    saved_chunk.checksum = Some(saved_chunk.compute_checksum());
+                    let string = ron::ser::to_string(&saved_chunk).context("Failed to serialize chunk")?;
+                    write_atomically(&path, string).context(format!("Failed to write chunk {}", path.display()))?;
+                }
+            }
+            // The chunk will be generated again when it's loaded
+            Err(e) => warn!("Skipping corrupted chunk {} ({:?})", path.display(), e),
+        }
+        progress.advance();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrations_are_sorted() {
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.from, i as u32);
+        }
+        assert_eq!(MIGRATIONS.len() as u32, SAVE_FORMAT_VERSION);
+    }
+
+    #[test]
+    fn test_migrate_v0_world() {
+        use super::super::{read_metadata, METADATA_FILE};
+        use voxel_rs_common::world::{Chunk, ChunkPos, CompressedChunk};
+
+        let mut world = WorldMetadata {
+            name: format!("migration test {}", std::process::id()),
+            seed: 0,
+            version: 0,
+        };
+        let folder = world.folder();
+        let _ = std::fs::remove_dir_all(&folder);
+        std::fs::create_dir_all(&folder).unwrap();
+        save_metadata(&world).unwrap();
+
+        // Save chunks without checksums, like the version 0
+        let positions = [ChunkPos::from((0, 0, 0)), ChunkPos::from((-1, 2, 3))];
+        for &pos in positions.iter() {
+            let mut chunk = Chunk::new(pos);
+            chunk.set_block_at((1, 2, 3), 5);
+            let saved_chunk = SavedChunk {
+                blocks: CompressedChunk::from_chunk(&chunk).data,
+                block_entities: Vec::new(),
+                checksum: None,
+                light: None,
+            };
+            let path = chunk_path(&world, pos);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, ron::ser::to_string(&saved_chunk).unwrap()).unwrap();
+        }
+
+        let result = migrate_world(&mut world);
+        let check = || {
+            result?;
+            assert_eq!(world.version, SAVE_FORMAT_VERSION);
+            assert_eq!(read_metadata(&folder.join(METADATA_FILE))?.version, SAVE_FORMAT_VERSION);
+            for &pos in positions.iter() {
+                let saved_chunk: SavedChunk = ron::de::from_str(&std::fs::read_to_string(chunk_path(&world, pos))?)?;
+                assert_eq!(saved_chunk.checksum, Some(saved_chunk.compute_checksum()));
+                saved_chunk.verify()?;
+            }
+            // The temporary files were renamed over the chunks
+            assert_eq!(list_saved_chunks(&world)?.len(), positions.len());
+            assert_eq!(std::fs::read_dir(chunk_path(&world, positions[0]).parent().unwrap())?.count(), positions.len());
+            Ok::<(), anyhow::Error>(())
+        };
+        let checked = check();
+        std::fs::remove_dir_all(&folder).unwrap();
+        // Only removed if no other world is saved there
+        let _ = std::fs::remove_dir(super::super::SAVES_FOLDER);
+        checked.unwrap();
+    }
+}
@@ -0,0 +1,597 @@
+//! Saved worlds
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+use crate::auth::username_key;
+use crate::config::ServerConfig;
+use crate::permissions::PermissionsConfig;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use voxel_rs_common::{
+    block::{entity::{BlockEntity, ChunkBlockEntities}, BlockId},
+    config::MAX_LIGHT_LEVEL,
+    data::Data,
+    inventory::{Inventory, InventoryItem, ItemStack, PLAYER_INVENTORY_SIZE},
+    physics::config::PhysicsConfig,
+    registry::Registry,
+    item::Item,
+    player::GameMode,
+    world::{Chunk, ChunkPos, CompressedChunk, LightChunk, CHUNK_SIZE},
+};
+
+mod migrations;
+pub use self::migrations::{migrate_world, SAVE_FORMAT_VERSION};
+
+/// Folder containing one subfolder per saved world
+pub const SAVES_FOLDER: &str = "saves";
+/// Name of the metadata file of every world
+const METADATA_FILE: &str = "world.ron";
+/// Name of the file storing the block names, indexed by block id
+const BLOCK_PALETTE_FILE: &str = "block_palette.ron";
+/// Name of the file storing the physics constants of the world
+const PHYSICS_CONFIG_FILE: &str = "physics.ron";
+/// Name of the file storing the settings of the server
+const SERVER_CONFIG_FILE: &str = "server.ron";
+/// Name of the file storing the roles and the permissions of the players
+const PERMISSIONS_FILE: &str = "permissions.ron";
+/// Name of the file storing the state of the world that is not in the chunks, like the time of the day
+const WORLD_STATE_FILE: &str = "state.ron";
+/// Name of the folder containing the modified chunks
+const CHUNKS_FOLDER: &str = "chunks";
+/// Name of the folder containing the players, by display name
+const PLAYERS_FOLDER: &str = "players";
+
+/// The metadata of a saved world
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorldMetadata {
+    pub name: String,
+    pub seed: i32,
+    /// Version of the save format, the worlds saved before the format was versioned are version 0
+    #[serde(default)]
+    pub version: u32,
+}
+
+impl WorldMetadata {
+    /// Get the folder of the world. The name is a single path component, `create_world` and `list_worlds` check it.
+    pub fn folder(&self) -> PathBuf {
+        Path::new(SAVES_FOLDER).join(&self.name)
+    }
+}
+
+/// List the saved worlds, sorted by name
+pub fn list_worlds() -> Result<Vec<WorldMetadata>> {
+    let mut worlds = Vec::new();
+    let saves_folder = Path::new(SAVES_FOLDER);
+    if !saves_folder.is_dir() {
+        return Ok(worlds);
+    }
+    for entry in std::fs::read_dir(saves_folder).context("Failed to read saves folder")? {
+        let world_folder = entry?.path();
+        let metadata_path = world_folder.join(METADATA_FILE);
+        if metadata_path.is_file() {
+            let world = read_metadata(&metadata_path)?;
+            // The folder of the world is computed from its name, a modified name could point outside of the saves
+            if validate_world_name(&world.name).is_err() || world.folder() != world_folder {
+                warn!("Skipping world {:?}, its name doesn't match its folder {}", world.name, world_folder.display());
+                continue;
+            }
+            worlds.push(world);
+        }
+    }
+    worlds.sort_by(|w1, w2| w1.name.cmp(&w2.name));
+    Ok(worlds)
+}
+
+/// Check that a world name can be used as the name of the world folder: it can't contain separators or dots,
+/// so it can't be `..` or an absolute path
+fn validate_world_name(name: &str) -> Result<()> {
+    if name.trim().is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == ' ' || c == '-' || c == '_') {
+        anyhow::bail!("The world name can only contain letters, digits, spaces, '-' and '_'");
+    }
+    if name.trim() != name {
+        anyhow::bail!("The world name can't start or end with a space");
+    }
+    Ok(())
+}
+
+/// Create a new world with the given name and seed
+pub fn create_world(name: String, seed: i32) -> Result<WorldMetadata> {
+    validate_world_name(&name)?;
+    info!("Creating world {} with seed {}", name, seed);
+    let world = WorldMetadata { name, seed, version: SAVE_FORMAT_VERSION };
+    let folder = world.folder();
+    if folder.exists() {
+        anyhow::bail!("World folder {} already exists", folder.display());
+    }
+    std::fs::create_dir_all(&folder)
+        .context(format!("Failed to create world folder {}", folder.display()))?;
+    save_metadata(&world)?;
+    Ok(world)
+}
+
+/// The seed of a world created with the seed typed by the player: a number is used as is, and other texts are hashed.
+/// `None` if the text is empty, then the seed is random.
+pub fn seed_from_text(text: &str) -> Option<i32> {
+    let text = text.trim();
+    if text.is_empty() {
+        None
+    } else if let Ok(seed) = text.parse::<i32>() {
+        Some(seed)
+    } else {
+        // A hash that doesn't depend on the version of Rust, so that a text always gives the same world
+        Some(text.chars().fold(0i32, |hash, c| hash.wrapping_mul(31).wrapping_add(c as i32)))
+    }
+}
+
+fn save_metadata(world: &WorldMetadata) -> Result<()> {
+    write_ron(&world.folder().join(METADATA_FILE), world, "world metadata")
+}
+
+/// Load the block palette of the world, or an empty palette if the world doesn't have one yet
+pub fn load_block_palette(world: &WorldMetadata) -> Result<Vec<String>> {
+    let path = world.folder().join(BLOCK_PALETTE_FILE);
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    let buf = std::fs::read_to_string(&path)
+        .context(format!("Failed to read block palette {}", path.display()))?;
+    ron::de::from_str(&buf).context(format!("Failed to parse block palette {}", path.display()))
+}
+
+/// Save the block palette of the world
+pub fn save_block_palette(world: &WorldMetadata, palette: &[String]) -> Result<()> {
+    let path = world.folder().join(BLOCK_PALETTE_FILE);
+    let string = ron::ser::to_string_pretty(&palette, Default::default())
+        .context("Failed to serialize block palette")?;
+    std::fs::write(&path, string)
+        .context(format!("Failed to write block palette {}", path.display()))?;
+    Ok(())
+}
+
+/// Load the physics constants of the world.
+/// The default constants are written to the world folder if it doesn't have any, so that they can be edited.
+pub fn load_physics_config(world: &WorldMetadata) -> Result<PhysicsConfig> {
+    load_config(world, PHYSICS_CONFIG_FILE, "physics config")
+}
+
+/// Load the settings of the server, writing the default settings if the world doesn't have any
+pub fn load_server_config(world: &WorldMetadata) -> Result<ServerConfig> {
+    let config: ServerConfig = load_config(world, SERVER_CONFIG_FILE, "server config")?;
+    config
+        .interaction
+        .validate()
+        .map_err(|e| anyhow!("Invalid interaction rules in the server config: {}", e))?;
+    Ok(config)
+}
+
+/// Load the permissions of the world, writing the default permissions if the world doesn't have them yet
+pub fn load_permissions(world: &WorldMetadata) -> Result<PermissionsConfig> {
+    load_config(world, PERMISSIONS_FILE, "permissions")
+}
+
+pub fn save_permissions(world: &WorldMetadata, permissions: &PermissionsConfig) -> Result<()> {
+    write_ron(&world.folder().join(PERMISSIONS_FILE), permissions, "permissions")
+}
+
+fn load_config<T: Default + Serialize + DeserializeOwned>(world: &WorldMetadata, file: &str, what: &str) -> Result<T> {
+    let path = world.folder().join(file);
+    if !path.is_file() {
+        let config = T::default();
+        let string = ron::ser::to_string_pretty(&config, Default::default())
+            .context(format!("Failed to serialize {}", what))?;
+        std::fs::write(&path, string)
+            .context(format!("Failed to write {} {}", what, path.display()))?;
+        return Ok(config);
+    }
+    let buf = std::fs::read_to_string(&path)
+        .context(format!("Failed to read {} {}", what, path.display()))?;
+    ron::de::from_str(&buf).context(format!("Failed to parse {} {}", what, path.display()))
+}
+
+/// The state of the world that is not stored in the chunks
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WorldState {
+    pub time_of_day: f64,
+    /// Time of the year, only used if the seasons are enabled in the server config
+    pub time_of_year: f64,
+    /// Where the players spawn, chosen by the world generator when the world is created
+    pub spawn_point: Option<[f64; 3]>,
+}
+
+impl Default for WorldState {
+    fn default() -> Self {
+        Self {
+            time_of_day: voxel_rs_common::time::TimeOfDay::default().0,
+            time_of_year: 0.0,
+            spawn_point: None,
+        }
+    }
+}
+
+/// Load the state of the world, writing the default state if the world doesn't have one yet
+pub fn load_world_state(world: &WorldMetadata) -> Result<WorldState> {
+    load_config(world, WORLD_STATE_FILE, "world state")
+}
+
+pub fn save_world_state(world: &WorldMetadata, state: &WorldState) -> Result<()> {
+    write_ron(&world.folder().join(WORLD_STATE_FILE), state, "world state")
+}
+
+/// A block entity, storing the item names instead of the item ids because the item ids are not stored with the world
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum SavedBlockEntity {
+    ItemFrame { item: String, face: usize, rotation: u8 },
+}
+
+/// A chunk as stored on disk
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SavedChunk {
+    /// The RLE-compressed blocks
+    blocks: Vec<(u16, BlockId)>,
+    block_entities: Vec<((u32, u32, u32), SavedBlockEntity)>,
+    /// Checksum of the blocks and of the block entities, to detect the corrupted chunks.
+    /// Not saved by the older versions.
+    #[serde(default)]
+    checksum: Option<u64>,
+    /// The RLE-compressed light, only saved if it was up-to-date, for example by the pregeneration.
+    /// The chunks saved with their light are not lit again when they are loaded.
+    #[serde(default)]
+    light: Option<Vec<(u16, u8)>>,
+}
+
+impl SavedChunk {
+    /// FNV-1a hash of the blocks and of the block entities
+    fn compute_checksum(&self) -> u64 {
+        let mut bytes = Vec::new();
+        for &(len, block) in self.blocks.iter() {
+            bytes.extend_from_slice(&len.to_le_bytes());
+            bytes.extend_from_slice(&block.to_le_bytes());
+        }
+        for ((x, y, z), block_entity) in self.block_entities.iter() {
+            for coord in [x, y, z].iter() {
+                bytes.extend_from_slice(&coord.to_le_bytes());
+            }
+            match block_entity {
+                SavedBlockEntity::ItemFrame { item, face, rotation } => {
+                    bytes.extend_from_slice(item.as_bytes());
+                    bytes.push(0);
+                    bytes.extend_from_slice(&(*face as u64).to_le_bytes());
+                    bytes.push(*rotation);
+                }
+            }
+        }
+        // The chunks without light keep the checksum of the older versions
+        for &(len, light) in self.light.iter().flatten() {
+            bytes.extend_from_slice(&len.to_le_bytes());
+            bytes.push(light);
+        }
+        bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+    }
+
+    /// Check that the chunk is not corrupted, so that it can be decompressed safely
+    fn verify(&self) -> Result<()> {
+        if let Some(checksum) = self.checksum {
+            if checksum != self.compute_checksum() {
+                return Err(anyhow!("Checksum mismatch"));
+            }
+        }
+        let volume = (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize;
+        if self.blocks.iter().map(|&(len, _)| len as usize).sum::<usize>() != volume {
+            return Err(anyhow!("Wrong number of blocks"));
+        }
+        let in_chunk = |&((x, y, z), _): &((u32, u32, u32), SavedBlockEntity)| {
+            x < CHUNK_SIZE && y < CHUNK_SIZE && z < CHUNK_SIZE
+        };
+        if !self.block_entities.iter().all(in_chunk) {
+            return Err(anyhow!("Block entity outside of the chunk"));
+        }
+        if let Some(light) = &self.light {
+            if light.iter().map(|&(len, _)| len as usize).sum::<usize>() != volume {
+                return Err(anyhow!("Wrong number of light levels"));
+            }
+            if light.iter().any(|&(_, light)| light > MAX_LIGHT_LEVEL) {
+                return Err(anyhow!("Invalid light level"));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn chunk_path(world: &WorldMetadata, pos: ChunkPos) -> PathBuf {
+    world
+        .folder()
+        .join(CHUNKS_FOLDER)
+        .join(format!("{}_{}_{}.ron", pos.px, pos.py, pos.pz))
+}
+
+/// List the chunks that were saved in the world folder
+pub fn list_saved_chunks(world: &WorldMetadata) -> Result<HashSet<ChunkPos>> {
+    let mut chunks = HashSet::new();
+    let folder = world.folder().join(CHUNKS_FOLDER);
+    if !folder.is_dir() {
+        return Ok(chunks);
+    }
+    for entry in std::fs::read_dir(&folder).context(format!("Failed to read chunks folder {}", folder.display()))? {
+        let path = entry?.path();
+        let coords = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .map(|stem| stem.split('_').map(str::parse::<i64>).collect::<Vec<_>>());
+        // The backups of the corrupted chunks are skipped
+        if path.extension() != Some("ron".as_ref()) {
+            continue;
+        }
+        if let Some([Ok(px), Ok(py), Ok(pz)]) = coords.as_deref() {
+            chunks.insert(ChunkPos::from((*px, *py, *pz)));
+        }
+    }
+    Ok(chunks)
+}
+
+/// RLE-compress the light levels of a chunk
+fn compress_light(light_chunk: &LightChunk) -> Vec<(u16, u8)> {
+    let mut runs: Vec<(u16, u8)> = Vec::new();
+    for &light in light_chunk.light.iter() {
+        match runs.last_mut() {
+            Some((len, last)) if *last == light && *len < u16::MAX => *len += 1,
+            _ => runs.push((1, light)),
+        }
+    }
+    runs
+}
+
+/// Save a chunk and its block entities, and its light if it's up-to-date
+pub fn save_chunk(
+    world: &WorldMetadata,
+    chunk: &Chunk,
+    block_entities: &ChunkBlockEntities,
+    light_chunk: Option<&LightChunk>,
+    items: &Registry<Item>,
+) -> Result<()> {
+    let mut saved_chunk = SavedChunk {
+        blocks: CompressedChunk::from_chunk(chunk).data,
+        block_entities: block_entities
+            .iter()
+            .filter_map(|(&pos, block_entity)| match *block_entity {
+                BlockEntity::ItemFrame { item, face, rotation } => items
+                    .get_name_by_id(item)
+                    .map(|item| (pos, SavedBlockEntity::ItemFrame { item: item.clone(), face, rotation })),
+            })
+            .collect(),
+        checksum: None,
+        light: light_chunk.map(compress_light),
+    };
+    saved_chunk.checksum = Some(saved_chunk.compute_checksum());
+    let path = chunk_path(world, chunk.pos);
+    std::fs::create_dir_all(path.parent().unwrap())
+        .context(format!("Failed to create chunks folder in {}", world.folder().display()))?;
+    let string = ron::ser::to_string(&saved_chunk).context("Failed to serialize chunk")?;
+    write_atomically(&path, string).context(format!("Failed to write chunk {}", path.display()))?;
+    Ok(())
+}
+
+/// Load a saved chunk, its block entities and its light if it was saved. The block entities of unknown items are dropped.
+/// A truncated or corrupted chunk is renamed with a `.corrupt` extension, so that it is generated again.
+pub fn load_chunk(
+    world: &WorldMetadata,
+    pos: ChunkPos,
+    items: &Registry<Item>,
+) -> Result<(Chunk, ChunkBlockEntities, Option<LightChunk>)> {
+    let path = chunk_path(world, pos);
+    let buf = std::fs::read_to_string(&path).context(format!("Failed to read chunk {}", path.display()))?;
+    let saved_chunk = ron::de::from_str::<SavedChunk>(&buf)
+        .map_err(anyhow::Error::from)
+        .and_then(|saved_chunk| saved_chunk.verify().map(|()| saved_chunk));
+    let saved_chunk = match saved_chunk {
+        Ok(saved_chunk) => saved_chunk,
+        Err(e) => {
+            let backup_path = path.with_extension("ron.corrupt");
+            if let Err(e) = std::fs::rename(&path, &backup_path) {
+                warn!("Failed to keep a backup of corrupted chunk {} ({:?})", path.display(), e);
+            }
+            return Err(e.context(format!("Chunk {} is corrupted, it was moved to {}", path.display(), backup_path.display())));
+        }
+    };
+    let chunk = CompressedChunk { pos, data: saved_chunk.blocks }.to_chunk();
+    let block_entities = saved_chunk
+        .block_entities
+        .into_iter()
+        .filter_map(|(pos, block_entity)| match block_entity {
+            SavedBlockEntity::ItemFrame { item, face, rotation } => items
+                .get_id_by_name(&item)
+                .map(|item| (pos, BlockEntity::ItemFrame { item, face, rotation })),
+        })
+        .collect();
+    let light_chunk = saved_chunk.light.map(|runs| LightChunk {
+        light: runs
+            .into_iter()
+            .flat_map(|(len, light)| vec![light; len as usize])
+            .collect(),
+        pos,
+    });
+    Ok((chunk, block_entities, light_chunk))
+}
+
+/// An item of an inventory, by name
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SavedItem {
+    Item(String),
+    Block(String),
+}
+
+/// The state of a player, saved when the player disconnects
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedPlayer {
+    pub position: [f64; 3],
+    pub yaw: f64,
+    pub pitch: f64,
+    pub spawn_point: Option<[f64; 3]>,
+    pub inventory: Vec<Option<(SavedItem, u32)>>,
+    /// Not saved by the older versions
+    #[serde(default)]
+    pub gamemode: Option<GameMode>,
+    #[serde(default)]
+    pub health: Option<u32>,
+    #[serde(default)]
+    pub velocity: Option<[f64; 3]>,
+    /// Name of the block that the player places
+    #[serde(default)]
+    pub selected_block: Option<String>,
+    /// Name of the item that the player puts in the item frames
+    #[serde(default)]
+    pub selected_item: Option<String>,
+}
+
+impl SavedPlayer {
+    /// Store the items of `inventory` by name
+    pub fn save_inventory(inventory: &Inventory, game_data: &Data) -> Vec<Option<(SavedItem, u32)>> {
+        inventory
+            .slots()
+            .iter()
+            .map(|stack| {
+                let stack = (*stack)?;
+                let item = match stack.item {
+                    InventoryItem::Item(id) => SavedItem::Item(game_data.items.get_name_by_id(id)?.clone()),
+                    InventoryItem::Block(id) => SavedItem::Block(game_data.blocks.get_name_by_id(id as u32)?.clone()),
+                };
+                Some((item, stack.count))
+            })
+            .collect()
+    }
+
+    /// Recreate the inventory of the player. The items that don't exist anymore are dropped.
+    pub fn load_inventory(&self, game_data: &Data) -> Inventory {
+        let mut inventory = Inventory::new(PLAYER_INVENTORY_SIZE);
+        for (slot, saved_stack) in self.inventory.iter().enumerate().take(PLAYER_INVENTORY_SIZE) {
+            let stack = saved_stack.as_ref().and_then(|(item, count)| {
+                let item = match item {
+                    SavedItem::Item(name) => InventoryItem::Item(game_data.items.get_id_by_name(name)?),
+                    SavedItem::Block(name) => InventoryItem::Block(game_data.blocks.get_id_by_name(name)? as BlockId),
+                };
+                Some(ItemStack { item, count: *count })
+            });
+            inventory.set_slot(slot, stack);
+        }
+        inventory
+    }
+}
+
+/// Path of the file of a player. The characters that can't be in a file name are replaced, and the name is lowercased
+/// like the usernames are compared, so that every spelling of a username has the same file on every file system.
+fn player_path(world: &WorldMetadata, display_name: &str) -> PathBuf {
+    let file_name = display_name
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect::<String>();
+    let file_name = username_key(&file_name);
+    world.folder().join(PLAYERS_FOLDER).join(format!("{}.ron", file_name))
+}
+
+/// Load a player, if it was saved
+pub fn load_player(world: &WorldMetadata, display_name: &str) -> Result<Option<SavedPlayer>> {
+    let mut path = player_path(world, display_name);
+    if !path.is_file() {
+        // The players saved before the file names were lowercased
+        let file_name = display_name.replace(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_'), "_");
+        path = world.folder().join(PLAYERS_FOLDER).join(format!("{}.ron", file_name));
+    }
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let buf = std::fs::read_to_string(&path).context(format!("Failed to read player {}", path.display()))?;
+    ron::de::from_str(&buf)
+        .map(Some)
+        .context(format!("Failed to parse player {}", path.display()))
+}
+
+pub fn save_player(world: &WorldMetadata, display_name: &str, player: &SavedPlayer) -> Result<()> {
+    let path = player_path(world, display_name);
+    std::fs::create_dir_all(path.parent().unwrap())
+        .context(format!("Failed to create players folder in {}", world.folder().display()))?;
+    write_ron(&path, player, "player")
+}
+
+fn write_ron<T: Serialize>(path: &Path, value: &T, what: &str) -> Result<()> {
+    let string = ron::ser::to_string_pretty(value, Default::default())
+        .context(format!("Failed to serialize {}", what))?;
+    std::fs::write(path, string).context(format!("Failed to write {} {}", what, path.display()))?;
+    Ok(())
+}
+
+/// Write a file through a temporary file that is renamed over it, so that the file is never left half-written
+fn write_atomically(path: &Path, contents: String) -> Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    std::fs::write(&temp_path, contents)?;
+    std::fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+fn read_metadata(path: &Path) -> Result<WorldMetadata> {
+    let buf = std::fs::read_to_string(path)
+        .context(format!("Failed to read world metadata {}", path.display()))?;
+    ron::de::from_str(&buf).context(format!("Failed to parse world metadata {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_world_name() {
+        assert!(validate_world_name("My World-1_2").is_ok());
+        assert!(validate_world_name("Welt Über").is_ok());
+        for name in ["", " ", "..", ".", "../World", "a/b", "a\\b", "/World", "C:", "World.", " World", "World "] {
+            assert!(validate_world_name(name).is_err(), "{:?} is a valid name", name);
+        }
+    }
+
+    #[test]
+    fn test_seed_from_text() {
+        assert_eq!(seed_from_text(""), None);
+        assert_eq!(seed_from_text("  "), None);
+        assert_eq!(seed_from_text("42"), Some(42));
+        assert_eq!(seed_from_text(" -7 "), Some(-7));
+        assert_eq!(seed_from_text("ab"), Some(31 * 97 + 98));
+        assert_eq!(seed_from_text("voxel"), seed_from_text("voxel"));
+        assert_ne!(seed_from_text("voxel"), seed_from_text("voxels"));
+    }
+
+    #[test]
+    fn test_chunk_corruption_detection() {
+        let mut chunk = Chunk::new((0, 0, 0).into());
+        chunk.set_block_at((1, 2, 3), 5);
+        let mut saved_chunk = SavedChunk {
+            blocks: CompressedChunk::from_chunk(&chunk).data,
+            block_entities: Vec::new(),
+            checksum: None,
+            light: None,
+        };
+        // The chunks saved without a checksum are still loaded
+        assert!(saved_chunk.verify().is_ok());
+        saved_chunk.checksum = Some(saved_chunk.compute_checksum());
+        assert!(saved_chunk.verify().is_ok());
+        saved_chunk.blocks[1].1 = 6;
+        assert!(saved_chunk.verify().is_err());
+        // A truncated chunk doesn't have enough blocks
+        saved_chunk.blocks.pop();
+        saved_chunk.checksum = Some(saved_chunk.compute_checksum());
+        assert!(saved_chunk.verify().is_err());
+
+        // The saved light is checked too
+        let mut saved_chunk = SavedChunk {
+            blocks: CompressedChunk::from_chunk(&chunk).data,
+            block_entities: Vec::new(),
+            checksum: None,
+            light: Some(compress_light(&LightChunk::new(chunk.pos))),
+        };
+        saved_chunk.checksum = Some(saved_chunk.compute_checksum());
+        assert!(saved_chunk.verify().is_ok());
+        saved_chunk.light.as_mut().unwrap()[0].1 = MAX_LIGHT_LEVEL + 1;
+        saved_chunk.checksum = Some(saved_chunk.compute_checksum());
+        assert!(saved_chunk.verify().is_err());
+    }
+}
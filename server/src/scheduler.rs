@@ -0,0 +1,122 @@
+//! Delayed and repeating tasks, run by the server loop
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::time::{Duration, Instant};
+
+/// Identifier of a scheduled task, used to cancel it
+pub type TaskId = u64;
+
+struct ScheduledTask<T> {
+    task: T,
+    /// `Some(period)` if the task repeats
+    period: Option<Duration>,
+}
+
+/// Keeps track of tasks that must run at a later time.
+/// The scheduler doesn't run anything by itself: the server loop polls it every tick.
+pub struct Scheduler<T: Clone> {
+    next_id: TaskId,
+    /// Next run time of every task, soonest first
+    queue: BinaryHeap<Reverse<(Instant, TaskId)>>,
+    tasks: HashMap<TaskId, ScheduledTask<T>>,
+}
+
+impl<T: Clone> Default for Scheduler<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Clone> Scheduler<T> {
+    pub fn new() -> Self {
+        Self {
+            next_id: 0,
+            queue: BinaryHeap::new(),
+            tasks: HashMap::new(),
+        }
+    }
+
+    /// Run `task` once, after `delay`
+    pub fn schedule_once(&mut self, now: Instant, delay: Duration, task: T) -> TaskId {
+        self.schedule(now + delay, task, None)
+    }
+
+    /// Run `task` after `delay`, and then every `period`
+    pub fn schedule_repeating(
+        &mut self,
+        now: Instant,
+        delay: Duration,
+        period: Duration,
+        task: T,
+    ) -> TaskId {
+        self.schedule(now + delay, task, Some(period))
+    }
+
+    /// Cancel a task, returning `false` if it doesn't exist anymore
+    pub fn cancel(&mut self, id: TaskId) -> bool {
+        // The queue entry is skipped when it's polled
+        self.tasks.remove(&id).is_some()
+    }
+
+    /// Number of tasks that are waiting to run
+    pub fn num_tasks(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// Get the tasks that must run at `now`, in order. Repeating tasks are rescheduled.
+    pub fn poll(&mut self, now: Instant) -> Vec<T> {
+        let mut due_tasks = Vec::new();
+        while let Some(&Reverse((time, id))) = self.queue.peek() {
+            if time > now {
+                break;
+            }
+            self.queue.pop();
+            let period = match self.tasks.get(&id) {
+                Some(scheduled_task) => {
+                    due_tasks.push(scheduled_task.task.clone());
+                    scheduled_task.period
+                }
+                // Cancelled task
+                None => continue,
+            };
+            match period {
+                // Don't try to catch up if the server was late
+                Some(period) => self.queue.push(Reverse(((time + period).max(now), id))),
+                None => {
+                    self.tasks.remove(&id);
+                }
+            }
+        }
+        due_tasks
+    }
+
+    fn schedule(&mut self, time: Instant, task: T, period: Option<Duration>) -> TaskId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.queue.push(Reverse((time, id)));
+        self.tasks.insert(id, ScheduledTask { task, period });
+        id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scheduler() {
+        let start = Instant::now();
+        let second = Duration::from_secs(1);
+        let mut scheduler = Scheduler::new();
+        scheduler.schedule_repeating(start, second, 2 * second, "repeating");
+        scheduler.schedule_once(start, 2 * second, "once");
+        let cancelled = scheduler.schedule_once(start, 2 * second, "cancelled");
+        assert!(scheduler.cancel(cancelled));
+
+        assert!(scheduler.poll(start).is_empty());
+        assert_eq!(scheduler.poll(start + second), vec!["repeating"]);
+        assert_eq!(scheduler.poll(start + 2 * second), vec!["once"]);
+        assert_eq!(scheduler.poll(start + 3 * second), vec!["repeating"]);
+        assert_eq!(scheduler.num_tasks(), 1);
+    }
+}
@@ -0,0 +1,145 @@
+//! The chunks sent to a player, so that a chunk is only sent again when it changed
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use voxel_rs_common::world::ChunkPos;
+
+/// Time after which a send that the player didn't acknowledge is considered lost.
+/// This way, a player that never acknowledges the chunks can't make the server keep the forgotten chunks forever.
+const IN_FLIGHT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What must be sent to a player for a chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkSend {
+    /// The blocks and the light of the chunk
+    Chunk,
+    /// Only the light, the player has the current blocks
+    Light,
+}
+
+struct SentChunk {
+    /// The chunk and light versions that were last sent, or `None` if the chunk was forgotten while a send was in flight
+    versions: Option<(u64, u64)>,
+    /// Number of sends that the player didn't acknowledge yet
+    in_flight: u32,
+    /// When the chunk was last sent
+    sent_at: Instant,
+}
+
+/// The chunks sent to a player, with their versions.
+///
+/// A chunk is sent again when its version changes, but not while a previous send is in flight:
+/// the chunks are sent unordered, so the newer version could arrive first and be overwritten.
+/// The player acknowledges every chunk and light message, and the latest version is sent after that.
+/// This way, a chunk that is modified many times in a row is sent at most twice.
+/// The forgotten chunks are kept until their sends are acknowledged or lost, so that an old acknowledgement
+/// isn't counted for a new send.
+#[derive(Default)]
+pub struct SentChunks {
+    chunks: HashMap<ChunkPos, SentChunk>,
+}
+
+impl SentChunks {
+    /// Get what must be sent to the player for a chunk with the given versions, and remember that it was sent.
+    /// Return `None` if the player already has these versions or if a previous send is in flight.
+    pub fn send(&mut self, pos: ChunkPos, versions: (u64, u64)) -> Option<ChunkSend> {
+        let send = match self.chunks.get(&pos) {
+            Some(sent) if sent.in_flight > 0 && sent.sent_at.elapsed() < IN_FLIGHT_TIMEOUT => return None,
+            None | Some(SentChunk { versions: None, .. }) => ChunkSend::Chunk,
+            Some(SentChunk { versions: Some(sent), .. }) if sent.0 < versions.0 => ChunkSend::Chunk,
+            Some(SentChunk { versions: Some(sent), .. }) if sent.1 < versions.1 => ChunkSend::Light,
+            Some(_) => return None,
+        };
+        self.chunks.insert(pos, SentChunk { versions: Some(versions), in_flight: 1, sent_at: Instant::now() });
+        Some(send)
+    }
+
+    /// The player received a chunk or light message for this chunk
+    pub fn acknowledge(&mut self, pos: ChunkPos) {
+        if let Some(sent) = self.chunks.get_mut(&pos) {
+            sent.in_flight = sent.in_flight.saturating_sub(1);
+            if sent.in_flight == 0 && sent.versions.is_none() {
+                self.chunks.remove(&pos);
+            }
+        }
+    }
+
+    /// Forget the chunks for which `f` returns `false`, for example because they are too far from the player.
+    /// They are sent again if they are needed later.
+    pub fn retain(&mut self, mut f: impl FnMut(ChunkPos) -> bool) {
+        self.chunks.retain(|&pos, sent| {
+            if f(pos) {
+                return true;
+            }
+            sent.versions = None;
+            sent.in_flight > 0 && sent.sent_at.elapsed() < IN_FLIGHT_TIMEOUT
+        });
+    }
+
+    /// Number of chunks sent to the player
+    pub fn len(&self) -> usize {
+        self.chunks.values().filter(|sent| sent.versions.is_some()).count()
+    }
+
+    /// Number of chunks whose last send wasn't acknowledged yet
+    pub fn num_in_flight(&self) -> usize {
+        self.chunks.values().filter(|sent| sent.in_flight > 0).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rapid_edits() {
+        let pos = ChunkPos::from((0, 0, 0));
+        let mut sent_chunks = SentChunks::default();
+        assert_eq!(sent_chunks.send(pos, (0, 0)), Some(ChunkSend::Chunk));
+        assert_eq!(sent_chunks.send(pos, (0, 0)), None);
+
+        // The chunk is edited many times while the first send is in flight
+        for version in 1..=10 {
+            assert_eq!(sent_chunks.send(pos, (version, version)), None);
+        }
+        assert_eq!(sent_chunks.num_in_flight(), 1);
+
+        // Only the latest version is sent once the first one arrived
+        sent_chunks.acknowledge(pos);
+        assert_eq!(sent_chunks.send(pos, (10, 10)), Some(ChunkSend::Chunk));
+        sent_chunks.acknowledge(pos);
+        assert_eq!(sent_chunks.send(pos, (10, 10)), None);
+
+        // A light update only sends the light
+        assert_eq!(sent_chunks.send(pos, (10, 11)), Some(ChunkSend::Light));
+        sent_chunks.acknowledge(pos);
+        assert_eq!(sent_chunks.num_in_flight(), 0);
+    }
+
+    #[test]
+    fn test_forgotten_chunk() {
+        let pos = ChunkPos::from((1, 2, 3));
+        let mut sent_chunks = SentChunks::default();
+        assert_eq!(sent_chunks.send(pos, (5, 5)), Some(ChunkSend::Chunk));
+        sent_chunks.retain(|_| false);
+        assert_eq!(sent_chunks.len(), 0);
+
+        // The chunk comes back while the first send is in flight, it's sent again once that send arrived
+        assert_eq!(sent_chunks.send(pos, (5, 5)), None);
+        assert_eq!(sent_chunks.num_in_flight(), 1);
+        sent_chunks.acknowledge(pos);
+        assert_eq!(sent_chunks.send(pos, (5, 5)), Some(ChunkSend::Chunk));
+        sent_chunks.acknowledge(pos);
+        assert_eq!(sent_chunks.num_in_flight(), 0);
+
+        // A forgotten chunk that isn't in flight is removed
+        sent_chunks.retain(|_| false);
+        assert_eq!(sent_chunks.send(pos, (5, 5)), Some(ChunkSend::Chunk));
+
+        // A forgotten chunk whose send is never acknowledged is removed once the send is considered lost
+        sent_chunks.retain(|_| false);
+        assert_eq!(sent_chunks.num_in_flight(), 1);
+        sent_chunks.chunks.get_mut(&pos).unwrap().sent_at -= IN_FLIGHT_TIMEOUT;
+        sent_chunks.retain(|_| false);
+        assert_eq!(sent_chunks.num_in_flight(), 0);
+    }
+}
@@ -0,0 +1,95 @@
+//! Tick regions: the chunks close to the players tick at full rate, the farther chunks tick less often, and the
+//! chunks beyond the last region are frozen.
+//!
+//! The systems that tick the chunks (random ticks, fluids, mob AI...) ask the regions whether a chunk ticks during
+//! the current tick. A chunk that ticks once every `n` ticks must do `n` ticks worth of work, so that the far chunks
+//! progress at the same average speed as the close ones.
+use serde::{Deserialize, Serialize};
+use voxel_rs_common::world::ChunkPos;
+
+/// The chunks at most `distance` chunks away from the closest player along every axis tick once every `interval` ticks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TickRegion {
+    pub distance: u64,
+    pub interval: u64,
+}
+
+/// The default regions: full rate close to the players, a quarter of the rate a bit farther
+pub fn default_tick_regions() -> Vec<TickRegion> {
+    vec![
+        TickRegion { distance: 4, interval: 1 },
+        TickRegion { distance: 8, interval: 4 },
+    ]
+}
+
+/// The tick rate of every chunk, for the current positions of the players
+pub struct TickRegions {
+    /// Sorted by distance, the closest region first
+    regions: Vec<TickRegion>,
+    player_chunks: Vec<ChunkPos>,
+}
+
+impl TickRegions {
+    pub fn new(regions: &[TickRegion], player_chunks: Vec<ChunkPos>) -> Self {
+        let mut regions = regions.to_vec();
+        regions.sort_by_key(|region| region.distance);
+        Self { regions, player_chunks }
+    }
+
+    /// Distance between the chunk and the closest player along the farthest axis, `None` if there is no player
+    fn player_distance(&self, pos: ChunkPos) -> Option<u64> {
+        self.player_chunks
+            .iter()
+            .map(|player| {
+                let (dx, dy, dz) = (pos.px - player.px, pos.py - player.py, pos.pz - player.pz);
+                dx.abs().max(dy.abs()).max(dz.abs()) as u64
+            })
+            .min()
+    }
+
+    /// Number of ticks between two ticks of the chunk, or `None` if the chunk is frozen
+    pub fn interval(&self, pos: ChunkPos) -> Option<u64> {
+        let distance = self.player_distance(pos)?;
+        self.regions
+            .iter()
+            .find(|region| distance <= region.distance)
+            .map(|region| region.interval.max(1))
+    }
+
+    /// Return the interval of the chunk if it ticks during tick number `tick`, or `None` if it doesn't.
+    /// The chunks with the same interval are spread over the ticks, so that they don't all tick at once.
+    pub fn ticks(&self, pos: ChunkPos, tick: u64) -> Option<u64> {
+        let interval = self.interval(pos)?;
+        let offset = (pos.px + 3 * pos.py + 7 * pos.pz).rem_euclid(interval as i64) as u64;
+        let phase = (tick + offset) % interval;
+        if phase == 0 {
+            Some(interval)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tick_regions() {
+        let regions = TickRegions::new(&default_tick_regions(), vec![ChunkPos::from((0, 0, 0)), ChunkPos::from((100, 0, 0))]);
+        assert_eq!(regions.interval((2, -4, 1).into()), Some(1));
+        assert_eq!(regions.interval((0, 0, 6).into()), Some(4));
+        assert_eq!(regions.interval((50, 0, 0).into()), None);
+        // The closest player decides
+        assert_eq!(regions.interval((97, 0, 0).into()), Some(1));
+
+        // A far chunk ticks once every 4 ticks
+        let pos = ChunkPos::from((6, 0, 0));
+        let ticks = (0..16).filter(|&tick| regions.ticks(pos, tick).is_some()).count();
+        assert_eq!(ticks, 4);
+        assert!((0..16).all(|tick| regions.ticks((1, 1, 1).into(), tick) == Some(1)));
+
+        // Without players, everything is frozen
+        assert_eq!(TickRegions::new(&default_tick_regions(), Vec::new()).interval((0, 0, 0).into()), None);
+    }
+}
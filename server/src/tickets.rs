@@ -0,0 +1,87 @@
+//! Chunk tickets: every ticket keeps the chunks around some position loaded
+use std::collections::HashMap;
+use voxel_rs_common::{
+    player::{PlayerId, RenderDistance},
+    world::ChunkPos,
+};
+
+/// The reason why a ticket exists
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TicketSource {
+    /// The area around the world spawn
+    Spawn,
+    /// The chunks a player can see
+    Player(PlayerId),
+    /// Chunks that were forced to stay loaded, for example by a command or a plugin
+    Forced(u64),
+}
+
+/// Keeps the chunks in `range` around `center` loaded
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkTicket {
+    pub center: ChunkPos,
+    pub range: RenderDistance,
+}
+
+impl ChunkTicket {
+    /// A ticket for the chunks at most `radius` chunks away from `center` in every direction
+    pub fn around(center: ChunkPos, radius: u64) -> Self {
+        Self {
+            center,
+            range: RenderDistance {
+                x_max: radius,
+                x_min: radius,
+                y_max: radius,
+                y_min: radius,
+                z_max: radius,
+                z_min: radius,
+            },
+        }
+    }
+
+    pub fn contains(&self, pos: ChunkPos) -> bool {
+        self.range.is_chunk_visible(self.center, pos)
+    }
+}
+
+/// All the tickets of the world
+#[derive(Debug, Default)]
+pub struct ChunkTickets {
+    tickets: HashMap<TicketSource, ChunkTicket>,
+    next_forced_id: u64,
+}
+
+impl ChunkTickets {
+    /// Add a ticket, replacing the previous ticket with the same source
+    pub fn set(&mut self, source: TicketSource, ticket: ChunkTicket) {
+        self.tickets.insert(source, ticket);
+    }
+
+    /// Add a new forced ticket, returning its source so that it can be removed later
+    pub fn force_load(&mut self, ticket: ChunkTicket) -> TicketSource {
+        let source = TicketSource::Forced(self.next_forced_id);
+        self.next_forced_id += 1;
+        self.set(source, ticket);
+        source
+    }
+
+    /// Remove a ticket, returning `false` if it didn't exist
+    pub fn remove(&mut self, source: TicketSource) -> bool {
+        self.tickets.remove(&source).is_some()
+    }
+
+    /// Check whether some ticket keeps the chunk loaded
+    pub fn keeps_loaded(&self, pos: ChunkPos) -> bool {
+        self.tickets.values().any(|ticket| ticket.contains(pos))
+    }
+
+    /// The chunks of the tickets that don't belong to a player.
+    /// The chunks close to the players are generated in priority, so they are handled separately.
+    pub fn non_player_chunks(&self) -> Vec<ChunkPos> {
+        self.tickets
+            .iter()
+            .filter(|(source, _)| !matches!(source, TicketSource::Player(_)))
+            .flat_map(|(_, ticket)| ticket.range.iterate_around_player(ticket.center))
+            .collect()
+    }
+}
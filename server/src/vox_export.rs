@@ -0,0 +1,149 @@
+//! Export of a region of a saved world to .vox files, to render the builds in MagicaVoxel or other tools.
+//!
+//! The saved chunks are read from the disk and the other chunks are generated again, so the server doesn't need
+//! to run. Every block is exported with the average color of its top texture.
+use crate::save::{self, WorldMetadata};
+use crate::DATA_FOLDER;
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use voxel_rs_common::{
+    block::BlockMesh,
+    data::{
+        load_data,
+        vox::{write_voxel_model, VoxelModel},
+        Data, MAX_TEXTURE_SIZE,
+    },
+    world::{BlockPos, Chunk, ChunkPos, WorldGenerator},
+    worldgen::DefaultWorldGenerator,
+};
+
+/// Maximum size of a .vox model along every axis, larger regions are split into several files
+const MAX_VOX_SIZE: i64 = 256;
+
+/// The average color of the top face of every block, in the format of the voxel models,
+/// or `None` for the blocks that are not exported
+pub fn block_colors(data: &Data) -> Vec<Option<u32>> {
+    data.meshes
+        .iter()
+        .map(|mesh| match mesh {
+            BlockMesh::Empty => None,
+            BlockMesh::FullCube { textures, .. } => {
+                let rect = textures[2];
+                let x = (rect.x * MAX_TEXTURE_SIZE as f32).round() as u32;
+                let y = (rect.y * MAX_TEXTURE_SIZE as f32).round() as u32;
+                let width = (rect.width * MAX_TEXTURE_SIZE as f32).round() as u32;
+                let height = (rect.height * MAX_TEXTURE_SIZE as f32).round() as u32;
+                let mut sum = [0u64; 3];
+                for u in x..x + width {
+                    for v in y..y + height {
+                        let rgba = data.texture_atlas.get_pixel(u, v);
+                        for c in 0..3 {
+                            sum[c] += rgba[c] as u64;
+                        }
+                    }
+                }
+                let count = (width * height).max(1) as u64;
+                let [r, g, b] = [sum[0] / count, sum[1] / count, sum[2] / count];
+                Some(((b as u32) << 16) + ((g as u32) << 8) + r as u32)
+            }
+        })
+        .collect()
+}
+
+/// Export the blocks between `min` and `max` (inclusive) to `<output>.vox`, or to `<output>_<i>_<j>_<k>.vox`
+/// if the region is split into several models. Return the written files.
+pub fn export_region(world: &WorldMetadata, min: BlockPos, max: BlockPos, output: &Path) -> Result<Vec<PathBuf>> {
+    let block_palette = save::load_block_palette(world)?;
+    let data = load_data(DATA_FOLDER.into(), &block_palette)?;
+    let colors = block_colors(&data);
+    let world_generator = DefaultWorldGenerator::new(&data.blocks, &data.structures, world.seed);
+    let saved_chunks = save::list_saved_chunks(world)?;
+
+    let (min, max) = (
+        BlockPos::from((min.px.min(max.px), min.py.min(max.py), min.pz.min(max.pz))),
+        BlockPos::from((min.px.max(max.px), min.py.max(max.py), min.pz.max(max.pz))),
+    );
+    let parts = |min: i64, max: i64| (max - min) / MAX_VOX_SIZE + 1;
+    let (parts_x, parts_y, parts_z) = (parts(min.px, max.px), parts(min.py, max.py), parts(min.pz, max.pz));
+    let single_part = parts_x * parts_y * parts_z == 1;
+    // The last chunk that was read, the columns of blocks are read in the same chunk most of the time
+    let mut chunk: Option<Chunk> = None;
+    let mut files = Vec::new();
+    for i in 0..parts_x {
+        for j in 0..parts_y {
+            for k in 0..parts_z {
+                let part_min = BlockPos::from((
+                    min.px + i * MAX_VOX_SIZE,
+                    min.py + j * MAX_VOX_SIZE,
+                    min.pz + k * MAX_VOX_SIZE,
+                ));
+                let part_max = BlockPos::from((
+                    (part_min.px + MAX_VOX_SIZE - 1).min(max.px),
+                    (part_min.py + MAX_VOX_SIZE - 1).min(max.py),
+                    (part_min.pz + MAX_VOX_SIZE - 1).min(max.pz),
+                ));
+                let mut model = VoxelModel {
+                    size_x: (part_max.px - part_min.px + 1) as usize,
+                    size_y: (part_max.py - part_min.py + 1) as usize,
+                    size_z: (part_max.pz - part_min.pz + 1) as usize,
+                    voxels: Vec::new(),
+                    full: Vec::new(),
+                };
+                let num_voxels = model.size_x * model.size_y * model.size_z;
+                model.voxels.resize(num_voxels, 0);
+                model.full.resize(num_voxels, false);
+                for px in part_min.px..=part_max.px {
+                    for pz in part_min.pz..=part_max.pz {
+                        for py in part_min.py..=part_max.py {
+                            let pos = BlockPos::from((px, py, pz));
+                            let chunk_pos = pos.containing_chunk_pos();
+                            if chunk.as_ref().is_none_or(|chunk| chunk.pos != chunk_pos) {
+                                chunk = Some(load_or_generate_chunk(world, &saved_chunks, &world_generator, &data, chunk_pos));
+                            }
+                            let block = chunk.as_ref().unwrap().get_block_at(pos.pos_in_containing_chunk());
+                            if let Some(color) = colors[block as usize] {
+                                let (x, y, z) = (
+                                    (px - part_min.px) as usize,
+                                    (py - part_min.py) as usize,
+                                    (pz - part_min.pz) as usize,
+                                );
+                                let s = x * model.size_z * model.size_y + y * model.size_z + z;
+                                model.voxels[s] = color;
+                                model.full[s] = true;
+                            }
+                        }
+                    }
+                }
+                let path = if single_part {
+                    output.with_extension("vox")
+                } else {
+                    let file_name = output.file_name().context("Invalid output file")?.to_string_lossy();
+                    output.with_file_name(format!("{}_{}_{}_{}.vox", file_name, i, j, k))
+                };
+                let buffer = write_voxel_model(&model).map_err(anyhow::Error::msg)?;
+                std::fs::write(&path, buffer).context(format!("Failed to write {}", path.display()))?;
+                info!("Exported blocks {:?} to {:?} to {}", part_min, part_max, path.display());
+                files.push(path);
+            }
+        }
+    }
+    Ok(files)
+}
+
+fn load_or_generate_chunk(
+    world: &WorldMetadata,
+    saved_chunks: &HashSet<ChunkPos>,
+    world_generator: &DefaultWorldGenerator,
+    data: &Data,
+    pos: ChunkPos,
+) -> Chunk {
+    if saved_chunks.contains(&pos) {
+        match save::load_chunk(world, pos, &data.items) {
+            Ok((chunk, _, _)) => return chunk,
+            Err(e) => warn!("Failed to load chunk {:?}, generating it again ({:?})", pos, e),
+        }
+    }
+    world_generator.generate_chunk(pos, &data.blocks)
+}
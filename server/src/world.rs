@@ -2,21 +2,29 @@ use std::{
     collections::{HashMap, HashSet},
     sync::Arc,
 };
+use log::warn;
 use voxel_rs_common::{
-    block::{Block, BlockId},
-    player::RenderDistance,
+    block::{Block, BlockId, entity::{BlockEntity, ChunkBlockEntities}},
+    config::MAX_LIGHT_LEVEL,
+    item::Item,
     physics::BlockContainer,
     registry::Registry,
     world::{
         Chunk, ChunkPos, ChunkPosXZ,
         BlockPos,
         LightChunk,
+        LightContainer,
+        Neighborhood27,
+        CHUNK_SIZE,
         WorldGenerator,
     },
 };
 use crate::{
-    light::HighestOpaqueBlock,
+    light::{BlockLight, HighestOpaqueBlock},
     light::worker::{ChunkLightingData, ChunkLightingWorker, start_lighting_worker},
+    save::{self, WorldMetadata},
+    sent_chunks::ChunkSend,
+    tickets::ChunkTickets,
     worldgen::{WorldGenerationWorker, start_worldgen_worker},
 };
 use lazy_static::lazy_static;
@@ -25,12 +33,17 @@ lazy_static! {
     static ref EMPTY_HOB: Arc<HighestOpaqueBlock> = {
         Arc::new(HighestOpaqueBlock::new())
     };
+    /// Every block is below an opaque block, so no block receives sunlight
+    static ref NO_SKY_HOB: Arc<HighestOpaqueBlock> = {
+        Arc::new(HighestOpaqueBlock { y: [i64::MAX; (CHUNK_SIZE * CHUNK_SIZE) as usize] })
+    };
 }
 
 /// Server-side world
 /// It is responsible for
 /// * storing chunk data
 /// * generating the chunks
+/// * saving and loading the modified chunks
 /// * updating the lighting
 pub struct World {
     /// The chunks
@@ -45,21 +58,105 @@ pub struct World {
     worldgen_worker: WorldGenerationWorker,
     /// The light worker
     light_worker: ChunkLightingWorker,
+    /// The saved world
+    world_metadata: WorldMetadata,
+    /// The chunks that were saved to disk. They are loaded instead of being generated.
+    saved_chunks: HashSet<ChunkPos>,
+    /// The unloaded chunks whose saved light is outdated, because the light settings or a neighbouring chunk changed.
+    /// They are lit again when they are loaded.
+    stale_light_chunks: HashSet<ChunkPos>,
+    /// The item registry, to save the items of the block entities by name
+    item_registry: Registry<Item>,
+    /// `false` if the dimension of the world has no sunlight
+    sunlight: bool,
+    /// How the blocks affect the light
+    block_light: BlockLight,
+    /// The blocks modified by `set_block` since the last call to `take_changed_blocks`
+    changed_blocks: Vec<BlockPos>,
 }
 
 impl World {
     pub fn new(
         block_registry: Registry<Block>,
-        world_generator: Box<dyn WorldGenerator + Send>
-    ) -> Self {
-        Self {
+        item_registry: Registry<Item>,
+        world_generator: Box<dyn WorldGenerator>,
+        world_metadata: WorldMetadata,
+        sunlight: bool,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            block_light: BlockLight::new(&block_registry),
             chunks: HashMap::default(),
             chunk_columns: HashMap::default(),
             next_chunk_version: 0,
             worldgen_queue: HashSet::default(),
             worldgen_worker: start_worldgen_worker(block_registry, world_generator),
             light_worker: start_lighting_worker(),
+            saved_chunks: save::list_saved_chunks(&world_metadata)?,
+            stale_light_chunks: HashSet::default(),
+            world_metadata,
+            item_registry,
+            sunlight,
+            changed_blocks: Vec::new(),
+        })
+    }
+
+    /// Use the light properties of new blocks, lighting all the loaded chunks again if they changed
+    pub fn set_block_light(&mut self, blocks: &Registry<Block>) {
+        let block_light = BlockLight::new(blocks);
+        if self.block_light != block_light {
+            self.block_light = block_light;
+            self.invalidate_saved_light();
+            for (&pos, server_chunk) in self.chunks.iter_mut() {
+                server_chunk.needs_light_update = true;
+                let column = self.chunk_columns.get_mut(&pos.into()).expect("No chunk column");
+                column.set_chunk(&server_chunk.chunk, &self.block_light);
+            }
+        }
+    }
+
+    /// Generate the new chunks with another block registry and world generator, for example after the data was reloaded.
+    /// The chunks that were being generated are generated again.
+    pub fn set_world_generator(&mut self, block_registry: Registry<Block>, world_generator: Box<dyn WorldGenerator>) {
+        // Dropping the previous worker stops its threads and discards the chunks it didn't send yet
+        self.worldgen_worker = start_worldgen_worker(block_registry, world_generator);
+        self.worldgen_queue.clear();
+    }
+
+    /// Enable or disable the sunlight, lighting all the loaded chunks again if it changed
+    pub fn set_sunlight(&mut self, sunlight: bool) {
+        if self.sunlight != sunlight {
+            self.sunlight = sunlight;
+            self.invalidate_saved_light();
+            for server_chunk in self.chunks.values_mut() {
+                server_chunk.needs_light_update = true;
+            }
+        }
+    }
+
+    /// Light all the saved chunks again when they are loaded, after the light settings changed
+    fn invalidate_saved_light(&mut self) {
+        let chunks = &self.chunks;
+        self.stale_light_chunks.extend(self.saved_chunks.iter().filter(|pos| !chunks.contains_key(pos)));
+    }
+
+    /// Light the unloaded neighbours of a modified chunk again when they are loaded
+    fn invalidate_neighbour_light(&mut self, pos: ChunkPos) {
+        for neighbour in Neighborhood27::positions(pos) {
+            if self.saved_chunks.contains(&neighbour) && !self.chunks.contains_key(&neighbour) {
+                self.stale_light_chunks.insert(neighbour);
+            }
+        }
+    }
+
+    /// The highest opaque blocks of a column used by the lighting, that block the whole sky without sunlight
+    fn lighting_hob(&self, pos: ChunkPosXZ) -> Arc<HighestOpaqueBlock> {
+        if !self.sunlight {
+            return NO_SKY_HOB.clone();
         }
+        self.chunk_columns
+            .get(&pos)
+            .map(|column| column.highest_opaque_block.clone())
+            .unwrap_or_else(|| EMPTY_HOB.clone())
     }
 
     /// Return some chunk if is loaded
@@ -78,22 +175,9 @@ impl World {
     /// Update the highest opaque block in the column, and mark relevant chunks for a light update.
     /// To be called after every chunk loading or modification.
     fn update_chunk_column(&mut self, pos: ChunkPos) {
-        let column_pos = pos.into();
+        self.update_column_hob(pos);
 
-        // Update chunk HOB
-        let hob = HighestOpaqueBlock::from_chunk(&self.chunks.get(&pos).unwrap().chunk);
-        let column = self.chunk_columns.get_mut(&column_pos).unwrap();
-        column.highest_opaque_blocks.insert(pos.py, hob);
-
-        // TODO: don't update entire column, try to be more clever
-
-        // Update column HOB
-        let mut column_hob = HighestOpaqueBlock::new();
-        for (_, chunk_hob) in column.highest_opaque_blocks.iter() {
-            column_hob.merge(chunk_hob);
-        }
-        column.highest_opaque_block = Arc::new(column_hob);
-        
+        let column_pos: ChunkPosXZ = pos.into();
         for i in -1..=1 {
             for k in -1..=1 {
                 self.update_column_light(column_pos.offset(i, k));
@@ -101,6 +185,12 @@ impl World {
         }
     }
 
+    /// Update the highest opaque block of a chunk and of its column, after the chunk was loaded or replaced
+    fn update_column_hob(&mut self, pos: ChunkPos) {
+        let chunk = self.chunks.get(&pos).unwrap().chunk.clone();
+        self.chunk_columns.get_mut(&pos.into()).unwrap().set_chunk(&chunk, &self.block_light);
+    }
+
     /// Mark an entire chunk column for light updates
     fn update_column_light(&mut self, pos: ChunkPosXZ) {
         if let Some(chunk_column) = self.chunk_columns.get(&pos) {
@@ -111,34 +201,195 @@ impl World {
         }
     }
 
-    /// Set the chunk at some position
+    /// Update the highest opaque block in the column of a chunk that was loaded with its light.
+    /// The neighbouring chunks that were also saved with their light are not lit again: their light was computed together,
+    /// or they are in `stale_light_chunks` if a neighbour was modified since.
+    fn update_chunk_column_with_light(&mut self, pos: ChunkPos) {
+        self.update_column_hob(pos);
+
+        let column_pos: ChunkPosXZ = pos.into();
+        for i in -1..=1 {
+            for k in -1..=1 {
+                if let Some(chunk_column) = self.chunk_columns.get(&column_pos.offset(i, k)) {
+                    for chunk_pos in chunk_column.loaded_chunks.iter() {
+                        let server_chunk = self.chunks.get_mut(chunk_pos).expect("Column loaded chunk is not loaded in the world");
+                        if !server_chunk.light_saved {
+                            server_chunk.needs_light_update = true;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Set the chunk at some position. It will be saved when it is unloaded.
     pub fn set_chunk(&mut self, chunk: Arc<Chunk>) {
+        self.replace_chunk(chunk, true);
+    }
+
+    /// Set the chunk at some position, `modified` is false if it doesn't need to be saved
+    fn replace_chunk(&mut self, chunk: Arc<Chunk>, modified: bool) {
+        let pos = chunk.pos;
+        self.insert_chunk(chunk, modified);
+        self.update_chunk_column(pos);
+    }
+
+    /// Set the chunk at some position without updating its column
+    fn insert_chunk(&mut self, chunk: Arc<Chunk>, modified: bool) {
         let pos = chunk.pos;
         let server_chunk = self.chunks.entry(pos).or_insert_with(|| {
             ServerChunk { 
                 chunk: chunk.clone(),
                 light_chunk: Arc::new(LightChunk::new(pos)),
+                block_entities: Default::default(),
                 version: 0,
+                light_version: 0,
                 is_in_light_queue: false,
                 needs_light_update: true,
+                modified: false,
+                light_saved: false,
             }
         });
+        // Drop the block entities of the blocks that changed
+        let old_chunk = &server_chunk.chunk;
+        if server_chunk.block_entities.keys().any(|&pos| old_chunk.get_block_at(pos) != chunk.get_block_at(pos)) {
+            let mut block_entities = (*server_chunk.block_entities).clone();
+            block_entities.retain(|&pos, _| old_chunk.get_block_at(pos) == chunk.get_block_at(pos));
+            server_chunk.block_entities = Arc::new(block_entities);
+        }
         server_chunk.chunk = chunk;
         server_chunk.needs_light_update = true;
+        server_chunk.modified |= modified;
         server_chunk.version = self.next_chunk_version;
         self.next_chunk_version += 1;
+        if modified {
+            self.invalidate_neighbour_light(pos);
+        }
 
-        let chunk_column = self.chunk_columns.entry(pos.into()).or_insert_with(|| {
-            ServerChunkColumn {
-                highest_opaque_block: Arc::new(HighestOpaqueBlock::new()),
-                highest_opaque_blocks: HashMap::new(),
-                loaded_chunks: HashSet::new(),
-            }
-        });
+        let chunk_column = self.chunk_columns.entry(pos.into()).or_insert_with(ServerChunkColumn::new);
         chunk_column.loaded_chunks.insert(pos);
         // highest_opaque_block and highest_opaque_blocks will be updated in update_chunk_col
+    }
 
-        self.update_chunk_column(pos);
+    /// Set the block at some position. The light is updated incrementally if possible.
+    /// Return false if the chunk is not loaded.
+    pub fn set_block(&mut self, pos: BlockPos, block: BlockId) -> bool {
+        let chunk_pos = pos.containing_chunk_pos();
+        let server_chunk = match self.chunks.get_mut(&chunk_pos) {
+            Some(server_chunk) => server_chunk,
+            None => return false,
+        };
+        let mut new_chunk = (*server_chunk.chunk).clone();
+        new_chunk.set_block_at(pos.pos_in_containing_chunk(), block);
+        let new_chunk = Arc::new(new_chunk);
+        server_chunk.chunk = new_chunk.clone();
+        if server_chunk.block_entities.contains_key(&pos.pos_in_containing_chunk()) {
+            let mut block_entities = (*server_chunk.block_entities).clone();
+            block_entities.remove(&pos.pos_in_containing_chunk());
+            server_chunk.block_entities = Arc::new(block_entities);
+        }
+        server_chunk.version = self.next_chunk_version;
+        server_chunk.modified = true;
+        self.next_chunk_version += 1;
+        self.changed_blocks.push(pos);
+        self.invalidate_neighbour_light(chunk_pos);
+
+        let column_pos = chunk_pos.into();
+        let column = self.chunk_columns.get_mut(&column_pos).expect("No chunk column");
+        let (old_hob, new_hob) = column.update_block(&new_chunk, &self.block_light, pos.pos_in_containing_chunk());
+
+        if !self.enqueue_incremental_light_update(pos, old_hob, new_hob) {
+            for i in -1..=1 {
+                for k in -1..=1 {
+                    self.update_column_light(column_pos.offset(i, k));
+                }
+            }
+        }
+        true
+    }
+
+    /// Return the blocks modified by `set_block` since the last call, to send them block updates
+    pub fn take_changed_blocks(&mut self) -> Vec<BlockPos> {
+        std::mem::take(&mut self.changed_blocks)
+    }
+
+    /// Return the block entity at some position, if there is one
+    pub fn get_block_entity(&self, pos: BlockPos) -> Option<&BlockEntity> {
+        self.chunks
+            .get(&pos.containing_chunk_pos())
+            .and_then(|server_chunk| server_chunk.block_entities.get(&pos.pos_in_containing_chunk()))
+    }
+
+    /// Set or remove the block entity at some position. The entity is removed when the block changes.
+    /// Return false if the chunk is not loaded.
+    pub fn set_block_entity(&mut self, pos: BlockPos, block_entity: Option<BlockEntity>) -> bool {
+        let server_chunk = match self.chunks.get_mut(&pos.containing_chunk_pos()) {
+            Some(server_chunk) => server_chunk,
+            None => return false,
+        };
+        let mut block_entities = (*server_chunk.block_entities).clone();
+        match block_entity {
+            Some(block_entity) => block_entities.insert(pos.pos_in_containing_chunk(), block_entity),
+            None => block_entities.remove(&pos.pos_in_containing_chunk()),
+        };
+        server_chunk.block_entities = Arc::new(block_entities);
+        // The chunk is sent again with its block entities
+        server_chunk.version = self.next_chunk_version;
+        server_chunk.modified = true;
+        self.next_chunk_version += 1;
+        true
+    }
+
+    /// Try to update the light of the 3x3x3 chunks around a modified block incrementally.
+    /// `old_hob` and `new_hob` are the highest opaque block of its column before and after the modification.
+    /// Return false if the incremental update is not possible, and the chunks must be lit from scratch.
+    fn enqueue_incremental_light_update(&mut self, pos: BlockPos, old_hob: i64, new_hob: i64) -> bool {
+        let center = pos.containing_chunk_pos();
+        let csize = CHUNK_SIZE as i64;
+        let (min_x, min_y, min_z) = ((center.px - 1) * csize, (center.py - 1) * csize, (center.pz - 1) * csize);
+
+        // The blocks that gained or lost sunlight must be far enough from the bottom of the bloc
+        let (old_hob, new_hob) = if self.sunlight { (old_hob, new_hob) } else { (0, 0) };
+        let sky_range = (old_hob.min(new_hob) + 1)..=(old_hob.max(new_hob));
+        if old_hob != new_hob && *sky_range.start() < min_y + MAX_LIGHT_LEVEL as i64 {
+            return false;
+        }
+
+        let mut chunks = Vec::with_capacity(27);
+        let mut light_chunks = Vec::with_capacity(27);
+        for chunk_pos in Neighborhood27::positions(center) {
+            match self.chunks.get(&chunk_pos) {
+                // The previous light must be up-to-date
+                Some(server_chunk) if !server_chunk.needs_light_update && !server_chunk.is_in_light_queue => {
+                    chunks.push(server_chunk.chunk.clone());
+                    light_chunks.push(server_chunk.light_chunk.clone());
+                }
+                _ => return false,
+            }
+        }
+
+        let mut highest_opaque_blocks = Vec::with_capacity(9);
+        for i in -1..=1 {
+            for k in -1..=1 {
+                highest_opaque_blocks.push(self.lighting_hob(ChunkPosXZ::from(center).offset(i, k)));
+            }
+        }
+
+        let (x, z) = ((pos.px - min_x) as usize, (pos.pz - min_z) as usize);
+        let mut changed_blocks = vec![(x, (pos.py - min_y) as usize, z)];
+        for y in sky_range {
+            changed_blocks.push((x, (y - min_y) as usize, z));
+        }
+
+        let block_light = self.block_light.clone();
+        let data = ChunkLightingData::Incremental { chunks, light_chunks, highest_opaque_blocks, block_light, changed_blocks };
+        if self.light_worker.enqueue(data).is_err() {
+            return false;
+        }
+        for chunk_pos in Neighborhood27::positions(center) {
+            self.chunks.get_mut(&chunk_pos).expect("Logic error").is_in_light_queue = true;
+        }
+        true
     }
 
     /// Fetch the new chunk meshes from the worldgen worker
@@ -147,18 +398,26 @@ impl World {
         // TODO: if there are multiple chunks in the same column this may save time
         while let Some(chunk) = self.worldgen_worker.get_result() {
             self.worldgen_queue.remove(&chunk.pos);
-            self.set_chunk(Arc::new(chunk));
+            self.replace_chunk(Arc::new(chunk), false);
         }
     }
 
     /// Fetch the new light chunks from the light worker
     pub fn get_new_light_chunks(&mut self) {
-        while let Some(light_chunk) = self.light_worker.get_result() {
-            if let Some(mut server_chunk) = self.chunks.get_mut(&light_chunk.pos) {
-                server_chunk.light_chunk = light_chunk;
-                server_chunk.is_in_light_queue = false;
-                server_chunk.version = self.next_chunk_version;
-                self.next_chunk_version += 1;
+        while let Some(light_chunks) = self.light_worker.get_result() {
+            for light_chunk in light_chunks {
+                if let Some(server_chunk) = self.chunks.get_mut(&light_chunk.pos) {
+                    server_chunk.is_in_light_queue = false;
+                    // Incremental updates return the previous light chunk if the light didn't change.
+                    // The light is only sent to the players once per tick, so the updates of the same chunk are coalesced.
+                    if !Arc::ptr_eq(&server_chunk.light_chunk, &light_chunk) && *server_chunk.light_chunk != *light_chunk {
+                        server_chunk.light_chunk = light_chunk;
+                        server_chunk.light_version = self.next_chunk_version;
+                        self.next_chunk_version += 1;
+                        // Don't keep an outdated light on the disk
+                        server_chunk.modified |= server_chunk.light_saved;
+                    }
+                }
             }
         }
     }
@@ -166,13 +425,13 @@ impl World {
     /// Start the lighting of a few chunks
     pub fn enqueue_chunks_for_lighting(&mut self, player_close_chunks: &[ChunkPos]) {
         for pos in player_close_chunks {
-            if let Some(server_chunk) = self.chunks.get(&pos) {
+            if let Some(server_chunk) = self.chunks.get(pos) {
                 if server_chunk.needs_light_update && !server_chunk.is_in_light_queue {
                     let res = self.light_worker.enqueue(self.create_chunk_lighting_data(*pos));
                     match res {
                         // If the lighting queue is not full, update chunk status
                         Ok(()) => {
-                            let server_chunk = self.chunks.get_mut(&pos).expect("Logic error");
+                            let server_chunk = self.chunks.get_mut(pos).expect("Logic error");
                             server_chunk.needs_light_update = false;
                             server_chunk.is_in_light_queue = true;
                         },
@@ -186,107 +445,175 @@ impl World {
 
     /// Create a `ChunkLightingData` for a loaded chunk
     fn create_chunk_lighting_data(&self, pos: ChunkPos) -> ChunkLightingData {
-        let mut chunks = Vec::with_capacity(27);
         let mut highest_opaque_blocks = Vec::with_capacity(9);
-
         for i in -1..=1 {
             for k in -1..=1 {
-                let pos: ChunkPosXZ = pos.offset(i, 0, k).into();
-                highest_opaque_blocks.push(
-                    (*self.chunk_columns
-                        .get(&pos)
-                        .map(|server_chunk| &server_chunk.highest_opaque_block)
-                        .unwrap_or_else(|| &*EMPTY_HOB))
-                        .clone()
-                );
+                highest_opaque_blocks.push(self.lighting_hob(pos.offset(i, 0, k).into()));
             }
         }
 
-        for i in -1..=1 {
-            for j in -1..=1 {
-                for k in -1..=1 {
-                    let pos = pos.offset(i, j, k);
-                    chunks.push(self.get_chunk(pos));
-                }
-            }
-        }
+        let chunks = Neighborhood27::positions(pos).map(|pos| self.get_chunk(pos)).collect();
 
-        ChunkLightingData { chunks, highest_opaque_blocks }
+        ChunkLightingData::Full { chunks, highest_opaque_blocks, block_light: self.block_light.clone() }
     }
 
     /// Start the worldgen of a few chunks
     pub fn enqueue_chunks_for_worldgen(&mut self, player_close_chunks: &[ChunkPos]) {
         for pos in player_close_chunks {
             if !self.chunks.contains_key(pos) && !self.worldgen_queue.contains(pos) {
-                let res = self.worldgen_worker.enqueue(*pos);
-                match res {
-                    // If the worldgen queue is not full, update chunk status
-                    Ok(()) => {
-                        self.worldgen_queue.insert(*pos);
-                    },
-                    // If the worldgen queue is full, stop
-                    Err(_) => break,
+                // If the worldgen queue is full, stop
+                if !self.load_or_generate_chunk(*pos) {
+                    break;
                 }
             }
         }
     }
 
-    /// Drop far chunks
-    pub fn drop_far_chunks(&mut self, player_positions: &[(ChunkPos, RenderDistance)]) {
+    /// Drop the chunks that are not kept loaded by any ticket
+    pub fn drop_unticketed_chunks(&mut self, tickets: &ChunkTickets) {
         let loaded_chunks = self.chunks.keys().cloned().collect::<Vec<_>>();
-        'chunks: for chunk_pos in loaded_chunks {
-            for (player_chunk, render_distance) in player_positions {
-                if render_distance.is_chunk_visible(*player_chunk, chunk_pos) {
-                    continue 'chunks
+        for chunk_pos in loaded_chunks {
+            if !tickets.keeps_loaded(chunk_pos) {
+                self.unload_chunk(chunk_pos);
+            }
+        }
+    }
+
+    /// Load a saved chunk from the disk, or start generating it if it was never saved.
+    /// Return false if the worldgen queue is full.
+    fn load_or_generate_chunk(&mut self, pos: ChunkPos) -> bool {
+        if self.saved_chunks.contains(&pos) {
+            let stale_light = self.stale_light_chunks.remove(&pos);
+            match save::load_chunk(&self.world_metadata, pos, &self.item_registry) {
+                Ok((chunk, block_entities, light_chunk)) => {
+                    self.insert_chunk(Arc::new(chunk), false);
+                    let server_chunk = self.chunks.get_mut(&pos).expect("Logic error");
+                    server_chunk.block_entities = Arc::new(block_entities);
+                    match light_chunk {
+                        // The chunk was saved with its light, for example by the pregeneration: it doesn't need to be lit again
+                        Some(light_chunk) if !stale_light => {
+                            server_chunk.light_chunk = Arc::new(light_chunk);
+                            server_chunk.light_saved = true;
+                            self.update_chunk_column_with_light(pos);
+                            self.chunks.get_mut(&pos).expect("Logic error").needs_light_update = false;
+                        }
+                        // The outdated saved light is computed again, and replaced on the disk
+                        Some(_) => {
+                            server_chunk.modified = true;
+                            self.update_chunk_column(pos);
+                        }
+                        None => self.update_chunk_column(pos),
+                    }
+                    return true;
+                }
+                Err(e) => {
+                    warn!("Failed to load chunk {:?}, generating it again ({:?})", pos, e);
+                    self.saved_chunks.remove(&pos);
+                }
+            }
+        }
+        let res = self.worldgen_worker.enqueue(pos);
+        if res.is_ok() {
+            self.worldgen_queue.insert(pos);
+        }
+        res.is_ok()
+    }
+
+    /// Save a chunk if it was modified since it was loaded or generated
+    fn save_chunk_if_modified(&mut self, pos: ChunkPos) {
+        if let Some(server_chunk) = self.chunks.get_mut(&pos) {
+            // The light is only saved if it's up-to-date, an outdated saved light is removed
+            let light_up_to_date = !server_chunk.needs_light_update && !server_chunk.is_in_light_queue;
+            if server_chunk.modified || (server_chunk.light_saved && !light_up_to_date) {
+                // The palette only grows when blocks are set, so the unused blocks are removed before saving
+                let mut chunk = (*server_chunk.chunk).clone();
+                chunk.compact();
+                server_chunk.chunk = Arc::new(chunk);
+                let light_chunk = Some(&*server_chunk.light_chunk).filter(|_| light_up_to_date);
+                match save::save_chunk(&self.world_metadata, &server_chunk.chunk, &server_chunk.block_entities, light_chunk, &self.item_registry) {
+                    Ok(()) => {
+                        server_chunk.modified = false;
+                        server_chunk.light_saved = light_up_to_date;
+                        self.saved_chunks.insert(pos);
+                    }
+                    Err(e) => warn!("Failed to save chunk {:?} ({:?})", pos, e),
                 }
             }
-            self.unload_chunk(chunk_pos);
         }
     }
 
-    /// Unload chunk
-    // TODO: persist to disk
+    /// Save all the loaded chunks that were modified
+    pub fn save_modified_chunks(&mut self) {
+        let loaded_chunks = self.chunks.keys().cloned().collect::<Vec<_>>();
+        for chunk_pos in loaded_chunks {
+            self.save_chunk_if_modified(chunk_pos);
+        }
+    }
+
+    /// Unload chunk, saving it first if it was modified
     fn unload_chunk(&mut self, pos: ChunkPos) {
+        self.save_chunk_if_modified(pos);
         self.chunks.remove(&pos);
         let column_pos = ChunkPosXZ::from(pos);
         let col = self.chunk_columns.get_mut(&column_pos).expect("No chunk column");
-        col.loaded_chunks.remove(&pos);
-        col.highest_opaque_blocks.remove(&pos.py);
-        if col.loaded_chunks.len() == 0 {
+        col.remove_chunk(pos);
+        if col.loaded_chunks.is_empty() {
             self.chunk_columns.remove(&column_pos);
         }
     }
 
-    /// Get chunks to send to a player this frame, and update the `PlayerData` accordingly. Start generating some chunks if necessary
-    pub fn send_chunks_to_player(&mut self, player_chunk: ChunkPos, data: &mut super::PlayerData) -> Vec<(Arc<Chunk>, Arc<LightChunk>)>{
-        const MAX_CHUNKS: usize = 20;
+    /// Get at most `max_chunks` chunks to send to a player this frame, and update the `PlayerData` accordingly.
+    /// Start generating some chunks if necessary
+    pub fn send_chunks_to_player(&mut self, player_chunk: ChunkPos, data: &mut super::PlayerData, max_chunks: usize) -> Vec<ChunkUpdate> {
         let mut updates = Vec::new();
-        for pos in data.close_chunks.get_close_chunks() {
-            let pos = pos.offset_by_pos(player_chunk);
+        for pos in data.close_chunks.iter(player_chunk) {
             if let Some(server_chunk) = self.chunks.get(&pos) {
-                // Send the chunk to the player
-                let loaded = data.loaded_chunks.insert(pos, server_chunk.version);
-                if let Some(old_client_version) = loaded {
-                    if old_client_version < server_chunk.version {
-                        updates.push((server_chunk.chunk.clone(), server_chunk.light_chunk.clone()));
+                // Send the chunk to the player, or only its light if the blocks didn't change
+                let versions = (server_chunk.version, server_chunk.light_version);
+                match data.sent_chunks.send(pos, versions) {
+                    Some(ChunkSend::Chunk) => {
+                        updates.push(ChunkUpdate::Chunk(server_chunk.chunk.clone(), server_chunk.light_chunk.clone(), server_chunk.block_entities.clone()));
+                    }
+                    Some(ChunkSend::Light) => {
+                        updates.push(ChunkUpdate::Light(server_chunk.light_chunk.clone()));
                     }
-                } else {
-                    updates.push((server_chunk.chunk.clone(), server_chunk.light_chunk.clone()));
+                    None => {}
                 }
-                if updates.len() == MAX_CHUNKS {
+                if updates.len() >= max_chunks {
                     break
                 }
-            } else {
-                // Generate the chunk
-                let res = self.worldgen_worker.enqueue(pos);
-                if res.is_ok() {
-                    self.worldgen_queue.insert(pos);
-                }
+            } else if !self.worldgen_queue.contains(&pos) {
+                // Load or generate the chunk
+                self.load_or_generate_chunk(pos);
             }
         }
         updates
     }
 
+    /// Return true if the chunk is loaded
+    pub fn is_chunk_loaded(&self, pos: ChunkPos) -> bool {
+        self.chunks.contains_key(&pos)
+    }
+
+    /// Return true if the chunk is loaded and its light is up-to-date
+    pub fn is_chunk_lit(&self, pos: ChunkPos) -> bool {
+        self.chunks
+            .get(&pos)
+            .is_some_and(|server_chunk| !server_chunk.needs_light_update && !server_chunk.is_in_light_queue)
+    }
+
+    /// Mark a loaded chunk as modified, so that it is saved with its light when it is unloaded
+    pub fn mark_modified(&mut self, pos: ChunkPos) {
+        if let Some(server_chunk) = self.chunks.get_mut(&pos) {
+            server_chunk.modified = true;
+        }
+    }
+
+    /// The positions of the loaded chunks
+    pub fn loaded_chunks(&self) -> impl Iterator<Item = ChunkPos> + '_ {
+        self.chunks.keys().cloned()
+    }
+
     /// Number of loaded chunks
     pub fn num_loaded_chunks(&self) -> usize {
         self.chunks.len()
@@ -298,6 +625,29 @@ impl World {
     }
 }
 
+impl LightContainer for World {
+    /// The light of a chunk may be stale while it waits for a light update, and the light of unloaded chunks is 0
+    fn get_light(&self, pos: BlockPos) -> (u8, u8) {
+        match self.chunks.get(&pos.containing_chunk_pos()) {
+            None => (0, 0),
+            // TODO: blocks don't emit light yet
+            Some(server_chunk) => (server_chunk.light_chunk.get_light_at(pos.pos_in_containing_chunk()), 0),
+        }
+    }
+
+    /// Use the highest opaque blocks, that are always up-to-date. Only the loaded chunks are taken into account.
+    fn is_sky_exposed(&self, pos: BlockPos) -> bool {
+        if !self.sunlight || !self.chunks.contains_key(&pos.containing_chunk_pos()) {
+            return false;
+        }
+        let (i, _, k) = pos.pos_in_containing_chunk();
+        self.chunk_columns
+            .get(&pos.containing_chunk_pos().into())
+            .map(|column| pos.py > column.highest_opaque_block.y[(i * CHUNK_SIZE + k) as usize])
+            .unwrap_or(false)
+    }
+}
+
 impl BlockContainer for World {
     fn is_block_full(&self, pos: BlockPos) -> bool {
         // TODO: use BlockRegistry
@@ -308,18 +658,34 @@ impl BlockContainer for World {
     }
 }
 
+/// An update of a chunk that must be sent to a player
+pub enum ChunkUpdate {
+    /// The player doesn't have the current blocks of the chunk
+    Chunk(Arc<Chunk>, Arc<LightChunk>, Arc<ChunkBlockEntities>),
+    /// The player has the current blocks of the chunk, but not its current light
+    Light(Arc<LightChunk>),
+}
+
 /// The data for each chunk stored by the server
 struct ServerChunk {
     /// The chunk itself
     pub chunk: Arc<Chunk>,
     /// The light chunk
     pub light_chunk: Arc<LightChunk>,
+    /// The block entities of the chunk
+    pub block_entities: Arc<ChunkBlockEntities>,
     /// The current chunk version
     pub version: u64,
+    /// The version of the light chunk, it changes without the chunk version when only the light changed
+    pub light_version: u64,
     /// True if the chunk is in the light queue
     pub is_in_light_queue: bool,
     /// True if the chunk needs a light update, for example before it never had one or because it changed.
     pub needs_light_update: bool,
+    /// True if the chunk was modified since it was loaded or generated, and must be saved
+    pub modified: bool,
+    /// True if the chunk was loaded or saved with its light, so the saved light must be kept up-to-date
+    pub light_saved: bool,
 }
 
 /// The data for each chunk column stored by the server
@@ -330,4 +696,149 @@ struct ServerChunkColumn {
     pub highest_opaque_blocks: HashMap<i64, HighestOpaqueBlock>,
     /// The loaded chunks from this column
     pub loaded_chunks: HashSet<ChunkPos>,
-}
\ No newline at end of file
+}
+
+impl ServerChunkColumn {
+    fn new() -> Self {
+        Self {
+            highest_opaque_block: Arc::new(HighestOpaqueBlock::new()),
+            highest_opaque_blocks: HashMap::new(),
+            loaded_chunks: HashSet::new(),
+        }
+    }
+
+    /// Recompute the HOB of a chunk of the column after it was loaded or replaced
+    fn set_chunk(&mut self, chunk: &Chunk, block_light: &BlockLight) {
+        self.highest_opaque_blocks.insert(chunk.pos.py, HighestOpaqueBlock::from_chunk(chunk, block_light));
+        self.merge_chunk_hobs();
+    }
+
+    /// Forget an unloaded chunk, its blocks don't block the sunlight anymore
+    fn remove_chunk(&mut self, pos: ChunkPos) {
+        self.loaded_chunks.remove(&pos);
+        if self.highest_opaque_blocks.remove(&pos.py).is_some() {
+            self.merge_chunk_hobs();
+        }
+    }
+
+    fn merge_chunk_hobs(&mut self) {
+        let mut column_hob = HighestOpaqueBlock::new();
+        for chunk_hob in self.highest_opaque_blocks.values() {
+            column_hob.merge(chunk_hob);
+        }
+        self.highest_opaque_block = Arc::new(column_hob);
+    }
+
+    /// Update the HOBs after the block at `(i, j, k)` of `chunk` changed, `chunk` already containing the new block.
+    /// Only the HOB of the column of the block is updated. Return the column HOB of the block before and after the update.
+    fn update_block(&mut self, chunk: &Chunk, block_light: &BlockLight, (i, j, k): (u32, u32, u32)) -> (i64, i64) {
+        let idx = (i * CHUNK_SIZE + k) as usize;
+        let old_hob = self.highest_opaque_block.y[idx];
+        let chunk_hob = self
+            .highest_opaque_blocks
+            .entry(chunk.pos.py)
+            .or_insert_with(|| HighestOpaqueBlock::from_chunk(chunk, block_light));
+        if !chunk_hob.update_block(chunk, block_light, (i, j, k)) {
+            return (old_hob, old_hob);
+        }
+        let new_hob = self
+            .highest_opaque_blocks
+            .values()
+            .map(|chunk_hob| chunk_hob.y[idx])
+            .max()
+            .unwrap_or(i64::MIN);
+        if new_hob != old_hob {
+            // The light worker may still hold the previous HOB, in which case it is copied
+            Arc::make_mut(&mut self.highest_opaque_block).y[idx] = new_hob;
+        }
+        (old_hob, new_hob)
+    }
+}
+
+/// A world generator that only generates empty chunks, for the tests
+#[cfg(test)]
+pub(crate) struct EmptyGenerator;
+
+#[cfg(test)]
+impl WorldGenerator for EmptyGenerator {
+    fn generate_chunk(&self, pos: ChunkPos, _block_registry: &Registry<Block>) -> Chunk {
+        Chunk::new(pos)
+    }
+
+    fn spawn_point(&self) -> BlockPos {
+        BlockPos::from((0, 0, 0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_column_hob() {
+        let block_light = BlockLight::for_tests();
+        let mut column = ServerChunkColumn::new();
+        let mut top = Chunk::new(ChunkPos::from((0, 1, 0)));
+        let mut bottom = Chunk::new(ChunkPos::from((0, 0, 0)));
+        bottom.set_block_at((4, 7, 5), 1);
+        top.set_block_at((4, 2, 5), 1);
+        column.set_chunk(&bottom, &block_light);
+        column.set_chunk(&top, &block_light);
+        let idx = (4 * CHUNK_SIZE + 5) as usize;
+        let top_y = CHUNK_SIZE as i64 + 2;
+        assert_eq!(column.highest_opaque_block.y[idx], top_y);
+
+        // Removing the highest block of the column falls back to the chunk below
+        top.set_block_at((4, 2, 5), 0);
+        assert_eq!(column.update_block(&top, &block_light, (4, 2, 5)), (top_y, 7));
+        assert_eq!(column.highest_opaque_block.y[idx], 7);
+        // Removing a block that isn't the highest doesn't change anything
+        assert_eq!(column.update_block(&top, &block_light, (4, 0, 5)), (7, 7));
+        // Placing a block above raises the HOB again, even if the previous HOB is shared
+        let shared = column.highest_opaque_block.clone();
+        top.set_block_at((4, 2, 5), 1);
+        assert_eq!(column.update_block(&top, &block_light, (4, 2, 5)), (7, top_y));
+        assert_eq!(shared.y[idx], 7);
+
+        // Unloading the top chunk updates the column
+        column.remove_chunk(top.pos);
+        assert_eq!(column.highest_opaque_block.y[idx], 7);
+        column.remove_chunk(bottom.pos);
+        assert_eq!(column.highest_opaque_block.y[idx], i64::MIN);
+    }
+
+    #[test]
+    fn test_stale_saved_light() {
+        let world_metadata = WorldMetadata {
+            name: format!("stale light test {}", std::process::id()),
+            seed: 0,
+            version: 0,
+        };
+        let folder = world_metadata.folder();
+        let _ = std::fs::remove_dir_all(&folder);
+        let (first, second) = (ChunkPos::from((0, 0, 0)), ChunkPos::from((5, 0, 0)));
+        for &pos in [first, second].iter() {
+            let light_chunk = LightChunk::new(pos);
+            save::save_chunk(&world_metadata, &Chunk::new(pos), &Default::default(), Some(&light_chunk), &Registry::default()).unwrap();
+        }
+        let mut world = World::new(Registry::default(), Registry::default(), Box::new(EmptyGenerator), world_metadata, true).unwrap();
+
+        // The saved light is used
+        assert!(world.load_or_generate_chunk(first));
+        assert!(world.is_chunk_lit(first));
+        world.unload_chunk(first);
+        // Modifying a neighbour while the chunk is unloaded makes its saved light stale
+        world.set_chunk(Arc::new(Chunk::new(ChunkPos::from((1, 0, 0)))));
+        assert!(world.load_or_generate_chunk(first));
+        assert!(!world.is_chunk_lit(first));
+        assert!(world.chunks[&first].modified);
+        // Changing the sunlight makes the light of all the unloaded chunks stale
+        world.set_sunlight(false);
+        assert!(world.load_or_generate_chunk(second));
+        assert!(!world.is_chunk_lit(second));
+
+        drop(world);
+        std::fs::remove_dir_all(&folder).unwrap();
+        let _ = std::fs::remove_dir(save::SAVES_FOLDER);
+    }
+}
@@ -0,0 +1,199 @@
+//! Programmatic edition of the world, with undo.
+//!
+//! Every operation is applied chunk by chunk: each modified chunk is replaced only once,
+//! so the light of the modified chunks is recomputed once per operation.
+use crate::world::World;
+use std::collections::HashMap;
+use std::sync::Arc;
+use voxel_rs_common::{
+    block::BlockId,
+    data::vox::VoxelModel,
+    world::{BlockPos, ChunkPos},
+};
+
+/// Maximum number of operations that can be undone
+const MAX_UNDO_STEPS: usize = 32;
+
+/// The previous blocks of the positions modified by an operation
+type UndoStep = Vec<(BlockPos, BlockId)>;
+
+/// Edits the world and remembers the previous blocks to undo the last operations
+#[derive(Default)]
+pub struct WorldEditor {
+    undo_stack: Vec<UndoStep>,
+}
+
+impl WorldEditor {
+    pub fn new() -> Self {
+        Self {
+            undo_stack: Vec::new(),
+        }
+    }
+
+    /// Set a single block. Return the number of modified blocks.
+    pub fn set_block(&mut self, world: &mut World, pos: BlockPos, block: BlockId) -> usize {
+        self.apply(world, vec![(pos, block)])
+    }
+
+    /// Fill the cuboid between `min` and `max` (inclusive) with `block`. Return the number of modified blocks.
+    pub fn fill(&mut self, world: &mut World, min: BlockPos, max: BlockPos, block: BlockId) -> usize {
+        let mut changes = Vec::new();
+        for px in min.px.min(max.px)..=min.px.max(max.px) {
+            for py in min.py.min(max.py)..=min.py.max(max.py) {
+                for pz in min.pz.min(max.pz)..=min.pz.max(max.pz) {
+                    changes.push((BlockPos { px, py, pz }, block));
+                }
+            }
+        }
+        self.apply(world, changes)
+    }
+
+    /// Paste the full voxels of `model` with their lowest corner at `origin`.
+    /// Every color of the model is replaced by the block of the nearest color of `blocks`, the color to block
+    /// mapping of the structures. Return the number of modified blocks.
+    pub fn paste_model(
+        &mut self,
+        world: &mut World,
+        origin: BlockPos,
+        model: &VoxelModel,
+        blocks: &HashMap<u32, BlockId>,
+    ) -> usize {
+        self.apply(world, model_blocks(origin, model, blocks))
+    }
+
+    /// Undo the last operation, returning `false` if there is nothing to undo
+    pub fn undo(&mut self, world: &mut World) -> bool {
+        match self.undo_stack.pop() {
+            Some(step) => {
+                set_blocks(world, step);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Apply the changes as a single operation that can be undone
+    fn apply(&mut self, world: &mut World, changes: Vec<(BlockPos, BlockId)>) -> usize {
+        let step = set_blocks(world, changes);
+        let modified_blocks = step.len();
+        if modified_blocks > 0 {
+            if self.undo_stack.len() == MAX_UNDO_STEPS {
+                self.undo_stack.remove(0);
+            }
+            self.undo_stack.push(step);
+        }
+        modified_blocks
+    }
+}
+
+/// The squared distance between the RGB components of two colors of a model
+fn color_distance(a: u32, b: u32) -> u32 {
+    (0..3)
+        .map(|shift| {
+            let a = (a >> (8 * shift)) & 0xff;
+            let b = (b >> (8 * shift)) & 0xff;
+            (a as i32 - b as i32).pow(2) as u32
+        })
+        .sum()
+}
+
+/// The blocks of the full voxels of `model` with its lowest corner at `origin`, with the block of the nearest color
+/// of `blocks`. The model is skipped if `blocks` is empty.
+fn model_blocks(origin: BlockPos, model: &VoxelModel, blocks: &HashMap<u32, BlockId>) -> Vec<(BlockPos, BlockId)> {
+    // Sort the colors so that the ties are resolved in the same way every time
+    let mut colors = blocks.iter().map(|(&color, &block)| (color, block)).collect::<Vec<_>>();
+    colors.sort_unstable();
+    let mut nearest_blocks = HashMap::new();
+    let mut changes = Vec::new();
+    for i in 0..model.size_x {
+        for j in 0..model.size_y {
+            for k in 0..model.size_z {
+                let index = i * model.size_y * model.size_z + j * model.size_z + k;
+                if !model.full[index] {
+                    continue;
+                }
+                let color = model.voxels[index];
+                let nearest_block = *nearest_blocks.entry(color).or_insert_with(|| {
+                    colors
+                        .iter()
+                        .min_by_key(|&&(block_color, _)| color_distance(color, block_color))
+                        .map(|&(_, block)| block)
+                });
+                if let Some(block) = nearest_block {
+                    let pos = BlockPos {
+                        px: origin.px + i as i64,
+                        py: origin.py + j as i64,
+                        pz: origin.pz + k as i64,
+                    };
+                    changes.push((pos, block));
+                }
+            }
+        }
+    }
+    changes
+}
+
+/// Set the blocks, replacing every modified chunk once. The blocks in chunks that are not loaded are skipped.
+/// Return the previous blocks of the modified positions.
+fn set_blocks(world: &mut World, changes: Vec<(BlockPos, BlockId)>) -> UndoStep {
+    let mut changes_by_chunk: HashMap<ChunkPos, Vec<(BlockPos, BlockId)>> = HashMap::new();
+    for (pos, block) in changes {
+        changes_by_chunk
+            .entry(pos.containing_chunk_pos())
+            .or_default()
+            .push((pos, block));
+    }
+
+    let mut previous_blocks = Vec::new();
+    for (chunk_pos, chunk_changes) in changes_by_chunk {
+        if let Some(chunk) = world.get_chunk(chunk_pos) {
+            let mut new_chunk = (*chunk).clone();
+            let mut modified = false;
+            for (pos, block) in chunk_changes {
+                let pos_in_chunk = pos.pos_in_containing_chunk();
+                let previous_block = new_chunk.get_block_at(pos_in_chunk);
+                if previous_block != block {
+                    new_chunk.set_block_at(pos_in_chunk, block);
+                    previous_blocks.push((pos, previous_block));
+                    modified = true;
+                }
+            }
+            if modified {
+                new_chunk.compact();
+                world.set_chunk(Arc::new(new_chunk));
+            }
+        }
+    }
+    // Restore the blocks in the reverse order if the same position was modified twice
+    previous_blocks.reverse();
+    previous_blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paste_two_color_model() {
+        // A 2x1x2 model with a red and a green voxel, a voxel close to red and an empty voxel
+        let model = VoxelModel {
+            size_x: 2,
+            size_y: 1,
+            size_z: 2,
+            voxels: vec![0xff0000ff, 0xff00ff00, 0xff1010e0, 0],
+            full: vec![true, true, true, false],
+        };
+        let blocks = [(0xff0000ff, 1), (0xff00ff00, 2)].iter().copied().collect();
+        let origin = BlockPos { px: 10, py: 20, pz: 30 };
+        let changes = model_blocks(origin, &model, &blocks);
+        assert_eq!(
+            changes,
+            vec![
+                (BlockPos { px: 10, py: 20, pz: 30 }, 1),
+                (BlockPos { px: 10, py: 20, pz: 31 }, 2),
+                (BlockPos { px: 11, py: 20, pz: 30 }, 1),
+            ]
+        );
+        assert!(model_blocks(origin, &model, &HashMap::new()).is_empty());
+    }
+}
@@ -1,3 +1,4 @@
+use std::sync::Arc;
 use voxel_rs_common::{
     block::Block,
     registry::Registry,
@@ -7,23 +8,31 @@ use voxel_rs_common::worker::{WorkerState, Worker};
 
 static WORLDGEN_QUEUE_SIZE: usize = 20;
 
+/// Start one worldgen thread per CPU core
 pub fn start_worldgen_worker(
     block_registry: Registry<Block>,
-    world_generator: Box<dyn WorldGenerator + Send>
+    world_generator: Box<dyn WorldGenerator>
 ) -> WorldGenerationWorker {
-    Worker::new(WorldGenerationState::new(block_registry, world_generator), WORLDGEN_QUEUE_SIZE, "Worldgen".into())
+    let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+    Worker::new_pool(
+        WorldGenerationState::new(block_registry, world_generator),
+        threads,
+        WORLDGEN_QUEUE_SIZE,
+        "Worldgen".into(),
+    )
 }
 
+#[derive(Clone)]
 pub struct WorldGenerationState {
-    block_registry: Registry<Block>,
-    world_generator: Box<dyn WorldGenerator + Send>,
+    block_registry: Arc<Registry<Block>>,
+    world_generator: Arc<dyn WorldGenerator>,
 }
 
 impl WorldGenerationState {
-    pub(self) fn new(block_registry: Registry<Block>, world_generator: Box<dyn WorldGenerator + Send>) -> Self {
+    pub(self) fn new(block_registry: Registry<Block>, world_generator: Box<dyn WorldGenerator>) -> Self {
         Self {
-            block_registry,
-            world_generator,
+            block_registry: Arc::new(block_registry),
+            world_generator: world_generator.into(),
         }
     }
 }